@@ -0,0 +1,16 @@
+#!/bin/sh
+# Times each benchmark under every engine spruce run has. The JIT needs the
+# jit feature. It leaves lists to the interpreter, so lists.sp shows what
+# falling back costs:
+#
+#     cargo build --release --features jit && benches/compare.sh
+
+spruce=${SPRUCE:-target/release/spruce}
+for bench in benches/*.sp; do
+    for engine in tree vm jit; do
+        start=$(date +%s%N)
+        result=$("$spruce" run --engine=$engine "$bench" 2>&1)
+        end=$(date +%s%N)
+        printf '%-20s %-5s %8s ms  %s\n' "$(basename "$bench")" "$engine" $(( (end - start) / 1000000 )) "$result"
+    done
+done
@@ -0,0 +1,667 @@
+/*
+The backends that compile a program, and the passes that improve it first,
+work on an intermediate representation in A-normal form rather than each
+walking the typed AST themselves. The typed AST is lowered to it once:
+
+  - every operand is an atom, which is a variable, an Int, Unit or a function
+    as a value, so anything that computes something is bound to a variable
+    by a let, in the order the interpreter evaluates it
+  - calls are saturated, and name the function they call whenever it's known
+    statically, leaving function values to `Apply`
+  - cases switch on an atom, with one flat arm per constructor or number
+    whose body starts by loading the constructor's fields, and arms no value
+    could reach are already gone, leaving decision.rs to compile them into
+    the tests they need
+
+A block's value is its last value rather than an atom, so that calls and
+cases in tail position stay visible. Variables are numbered per function, and
+most are bound once, except for the ones standing for a `mut`, which each
+update binds again.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::decision;
+use crate::error::SpruceErr;
+use crate::error_codes::ErrorCode;
+use crate::eval;
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+use crate::registry::LangValue;
+use crate::tailcall;
+use crate::trace::Phase;
+use crate::typecheck::{self, BodyNode, CaseBody, CaseNode, Expr, ExprNode, StmtNode};
+
+pub type Var = u32;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Atom {
+    Var(Var),
+    Int(i64),
+    Unit,
+    // a function or builtin, as a value
+    Func(na::SymbolID)
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Prim {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Neg,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    Eq,
+    NotEq,
+    // the first function, then the second
+    Compose
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Atom(Atom),
+    // where the operation is, for the ones that can fail
+    Prim(Prim, Vec<Atom>, NodeInfo),
+    // a program-level definition, which a function may have updated since
+    Global(na::SymbolID),
+    // a function or builtin, with all its arguments
+    Call(na::SymbolID, Vec<Atom>),
+    // a function value
+    Apply(Atom, Vec<Atom>),
+    Construct(na::ADTValID, Vec<Atom>),
+    // a field of a value a case has found the constructor of
+    Field(Atom, usize),
+    Case(Box<Case>),
+    // the function it's in, called again from tail position, which starts
+    // it over with new arguments rather than calling it
+    Recur(Vec<Atom>)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Stmt {
+    Let(Var, Value),
+    SetGlobal(na::SymbolID, Atom)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub stmts: Vec<Stmt>,
+    pub result: Value
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Pattern {
+    Constructor(na::ADTValID),
+    Int(i64)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Arm {
+    pub pattern: Pattern,
+    pub body: Block
+}
+
+/// Without a default, a value no arm matches is an error, said to be at
+/// `info`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Case {
+    pub scrutinee: Atom,
+    pub arms: Vec<Arm>,
+    pub default: Option<Block>,
+    pub info: NodeInfo
+}
+
+/// A function, whose parameters are its first variables, or the program's
+/// definitions, which have no name
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: Option<na::SymbolID>,
+    pub params: Vec<Var>,
+    // the Spruce variable each variable stands for, if any
+    pub vars: Vec<Option<na::SymbolID>>,
+    pub body: Block,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Program {
+    pub functions: Vec<Function>,
+    pub init: Function,
+    pub globals: Vec<na::SymbolID>,
+    pub entry: na::SymbolID,
+    // the builtins that are used, and where each is first used
+    pub builtins: Vec<(na::SymbolID, NodeInfo)>,
+    // the Int literals too wide for a tagged Int, and where each is
+    pub wide_ints: Vec<(i64, NodeInfo)>
+}
+
+struct Lowering<'a> {
+    prog: &'a na::Prog,
+    globals: &'a [na::SymbolID],
+
+    // the function being lowered
+    vars: Vec<Option<na::SymbolID>>,
+    locals: HashMap<na::SymbolID, Var>,
+    stmts: Vec<Stmt>,
+
+    wide_ints: Vec<(i64, NodeInfo)>
+}
+
+/// Lowers the functions reachable from `entry` and the program's definitions
+pub fn lower(prog: &na::Prog, typed: &typecheck::Prog, entry: &str) -> Result<Program, SpruceErr> {
+    let main = eval::entry_point(prog, typed, entry)?;
+    let used = reachable(typed, main);
+    let reached: Vec<&typecheck::FuncNode> = typed.functions.iter().filter(|func| used.contains_key(&func.val.name)).collect();
+    let left_out: Vec<&str> = typed.functions.iter().filter(|func| !used.contains_key(&func.val.name)).map(|func| eval::symbol_name(prog, func.val.name)).collect();
+    trace!(Phase::Codegen, "left out {} functions main doesn't reach: {}", left_out.len(), left_out.join(", "));
+    let globals: Vec<na::SymbolID> = typed.definitions.iter().filter_map(|def| match &def.val {
+        typecheck::Stmt::Assign(tgt, _) => Some(tgt.val.id()),
+        _ => None
+    }).collect();
+    let mut lowering = Lowering {
+        prog: prog,
+        globals: &globals,
+        vars: Vec::new(),
+        locals: HashMap::new(),
+        stmts: Vec::new(),
+        wide_ints: Vec::new()
+    };
+    let mut lowered = Vec::new();
+    for func in &reached {
+        lowered.push(lowering.function(Some(func.val.name), &func.val.args, func.info.clone(), |lower| lower.block(&func.val.body))?);
+    }
+    let init = lowering.function(None, &[], main.info.clone(), |lower| {
+        for def in &typed.definitions {
+            let val = lower.stmt(def)?;
+            lower.discard(val);
+        }
+        Ok(Block { stmts: std::mem::take(&mut lower.stmts), result: Value::Atom(Atom::Unit) })
+    })?;
+    let wide_ints = lowering.wide_ints;
+
+    let mut program = Program {
+        functions: lowered,
+        init: init,
+        globals: globals,
+        entry: main.val.name,
+        builtins: used_signatures(prog, &used),
+        wide_ints: wide_ints
+    };
+    decision::compile(prog, &mut program);
+    tailcall::compile(&mut program);
+    Ok(program)
+}
+
+/// The targets that tag their Ints keep them in 63 bits, so that they can be
+/// told apart from pointers, which leaves them this range
+pub const TAGGED_MIN: i64 = -(1 << 62);
+pub const TAGGED_MAX: i64 = (1 << 62) - 1;
+
+/// Fails on the first Int literal too wide to be tagged, for targets whose
+/// Ints are. Arithmetic on these targets wraps at 63 bits instead of 64, but a
+/// literal that doesn't fit is refused rather than read as another number
+pub fn tagged_ints(program: &Program, target: &str) -> Result<(), SpruceErr> {
+    match program.wide_ints.first() {
+        Some((n, info)) => {
+            let message = format!("{} doesn't fit in an Int on the {} target, whose Ints go from {} to {}", n, target, TAGGED_MIN, TAGGED_MAX);
+            Err(SpruceErr::new(message, info.clone()).with_code(ErrorCode::LiteralOutOfRange))
+        }
+        None => Ok(())
+    }
+}
+
+/// The functions and builtins that `main` and the program's definitions use,
+/// directly or not, along with where each is first used
+pub fn reachable(typed: &typecheck::Prog, main: &typecheck::FuncNode) -> HashMap<na::SymbolID, NodeInfo> {
+    let by_id: HashMap<na::SymbolID, &typecheck::FuncNode> = typed.functions.iter().map(|func| (func.val.name, func)).collect();
+    let mut used = HashMap::new();
+    let mut work = vec![(main.val.name, main.info.clone())];
+    for def in &typed.definitions {
+        uses_stmt(def, &mut work);
+    }
+    while let Some((id, info)) = work.pop() {
+        if used.contains_key(&id) {
+            continue;
+        }
+        used.insert(id, info);
+        if let Some(func) = by_id.get(&id) {
+            uses_body(&func.val.body, &mut work);
+        }
+    }
+    used
+}
+
+/// The builtins among what's used, in the order they're declared, with where
+/// each is first used
+pub fn used_signatures(prog: &na::Prog, used: &HashMap<na::SymbolID, NodeInfo>) -> Vec<(na::SymbolID, NodeInfo)> {
+    prog.signatures.iter().filter(|sig| used.contains_key(&sig.val.name)).map(|sig| (sig.val.name, used[&sig.val.name].clone())).collect()
+}
+
+/// The builtins among `used`, with their names and the number of arguments
+/// they take, as long as `supported` has them all. Errors point at the first
+/// use of a builtin rather than its declaration
+pub fn used_builtins<'a>(prog: &'a na::Prog, used: &[(na::SymbolID, NodeInfo)], target: &str, supported: &[&str]) -> Result<Vec<(na::SymbolID, &'a str, usize)>, SpruceErr> {
+    let builtins = eval::builtins(prog);
+    let mut found = Vec::new();
+    for (id, info) in used {
+        let sig = prog.signatures.iter().find(|sig| sig.val.name == *id).expect("builtins are declared by signatures");
+        let builtin = match builtins.get(id) {
+            Some(builtin) => *builtin,
+            None => {
+                let message = format!("'{}' can't be built, since only an interface declares it", eval::symbol_name(prog, *id));
+                return Err(SpruceErr::new(message, info.clone()));
+            }
+        };
+        if !supported.contains(&builtin) {
+            return Err(SpruceErr::new(format!("the {} target has no builtin '{}'", target, builtin), info.clone()));
+        }
+        let arity = match &sig.val.ty {
+            na::TypeID::Func(args, _) => args.len(),
+            _ => 0
+        };
+        found.push((*id, builtin, arity));
+    }
+    Ok(found)
+}
+
+fn uses_body(body: &BodyNode, used: &mut Vec<(na::SymbolID, NodeInfo)>) {
+    for stmt in &body.val.stmts {
+        uses_stmt(stmt, used);
+    }
+    if let Some(expr) = &body.val.expr {
+        uses_expr(expr, used);
+    }
+}
+
+fn uses_stmt(stmt: &StmtNode, used: &mut Vec<(na::SymbolID, NodeInfo)>) {
+    match &stmt.val {
+        typecheck::Stmt::Assign(_, expr) => uses_expr(expr, used),
+        typecheck::Stmt::FnCall(id, args) => {
+            used.push((*id, stmt.info.clone()));
+            args.iter().for_each(|arg| uses_expr(arg, used));
+        }
+        typecheck::Stmt::Case(case) => {
+            uses_expr(&case.val.expr, used);
+            for opt in &case.val.options {
+                match &opt.val.body.val {
+                    CaseBody::Expr(expr) => uses_expr(expr, used),
+                    CaseBody::Body(body) => uses_body(body, used)
+                }
+            }
+        }
+    }
+}
+
+fn uses_expr(expr: &ExprNode, used: &mut Vec<(na::SymbolID, NodeInfo)>) {
+    match &expr.val {
+        Expr::Id(id) | Expr::FnCall(id, _) => used.push((*id, expr.info.clone())),
+        _ => ()
+    }
+    for child in expr.val.children() {
+        uses_expr(child, used);
+    }
+}
+
+impl<'a> Lowering<'a> {
+    fn function(&mut self, name: Option<na::SymbolID>, args: &[na::SymbolID], info: NodeInfo, body: impl FnOnce(&mut Self) -> Result<Block, SpruceErr>) -> Result<Function, SpruceErr> {
+        self.vars = Vec::new();
+        self.locals = HashMap::new();
+        self.stmts = Vec::new();
+        let params = args.iter().map(|arg| self.local(*arg)).collect();
+        let body = body(self)?;
+        Ok(Function {
+            name: name,
+            params: params,
+            vars: std::mem::take(&mut self.vars),
+            body: body,
+            info: info
+        })
+    }
+
+    fn local(&mut self, id: na::SymbolID) -> Var {
+        if let Some(var) = self.locals.get(&id) {
+            return *var;
+        }
+        self.vars.push(Some(id));
+        let var = (self.vars.len() - 1) as Var;
+        self.locals.insert(id, var);
+        var
+    }
+
+    fn temp(&mut self) -> Var {
+        self.vars.push(None);
+        (self.vars.len() - 1) as Var
+    }
+
+    /// Binds a value to a variable of its own, unless it's an atom already
+    fn bind(&mut self, val: Value) -> Atom {
+        match val {
+            Value::Atom(atom) => atom,
+            val => {
+                let temp = self.temp();
+                self.stmts.push(Stmt::Let(temp, val));
+                Atom::Var(temp)
+            }
+        }
+    }
+
+    /// Keeps a value that's only needed for what it does
+    fn discard(&mut self, val: Value) {
+        self.bind(val);
+    }
+
+    fn block(&mut self, body: &BodyNode) -> Result<Block, SpruceErr> {
+        let outer = std::mem::take(&mut self.stmts);
+        let mut last = None;
+        for stmt in &body.val.stmts {
+            if let Some(val) = last.take() {
+                self.discard(val);
+            }
+            last = Some(self.stmt(stmt)?);
+        }
+        if let Some(expr) = &body.val.expr {
+            if let Some(val) = last.take() {
+                self.discard(val);
+            }
+            last = Some(self.value(expr)?);
+        }
+        let result = last.unwrap_or(Value::Atom(Atom::Unit));
+        Ok(Block { stmts: std::mem::replace(&mut self.stmts, outer), result: result })
+    }
+
+    /// Lowers a statement, giving the value it has when it's the last in a
+    /// body
+    fn stmt(&mut self, stmt: &StmtNode) -> Result<Value, SpruceErr> {
+        match &stmt.val {
+            typecheck::Stmt::Assign(tgt, expr) => {
+                let val = self.value(expr)?;
+                let id = tgt.val.id();
+                // updates can reach program-level `mut` definitions, and
+                // definitions themselves are globals
+                if self.globals.contains(&id) && !self.locals.contains_key(&id) {
+                    let atom = self.bind(val);
+                    self.stmts.push(Stmt::SetGlobal(id, atom.clone()));
+                    return Ok(Value::Atom(atom));
+                }
+                let var = self.local(id);
+                self.stmts.push(Stmt::Let(var, val));
+                Ok(Value::Atom(Atom::Var(var)))
+            }
+            typecheck::Stmt::FnCall(id, args) => {
+                let args = args.iter().map(|arg| self.atom(arg)).collect::<Result<Vec<Atom>, SpruceErr>>()?;
+                Ok(self.call(*id, args))
+            }
+            typecheck::Stmt::Case(case) => self.case(case)
+        }
+    }
+
+    fn case(&mut self, case: &CaseNode) -> Result<Value, SpruceErr> {
+        let scrutinee = self.atom(&case.val.expr)?;
+        let mut arms: Vec<Arm> = Vec::new();
+        let mut default = None;
+        for opt in &case.val.options {
+            // the first option for a constructor or number wins, and nothing
+            // after a wildcard is tried
+            let (pattern, names) = match &opt.val.pattern.val {
+                na::CasePattern::ADT(id, names) => (Pattern::Constructor(*id), &names[..]),
+                na::CasePattern::Lit(lit) => (Pattern::Int(self.int(*lit, &opt.val.pattern.info)), &[][..]),
+                na::CasePattern::Any => {
+                    default = Some(self.option(&[], &opt.val.body.val)?);
+                    break;
+                }
+            };
+            if !arms.iter().any(|arm| arm.pattern == pattern) {
+                let fields: Vec<(Var, Value)> = names.iter().enumerate().map(|(i, name)| (self.local(*name), Value::Field(scrutinee.clone(), i))).collect();
+                let body = self.option(&fields, &opt.val.body.val)?;
+                arms.push(Arm { pattern: pattern, body: body });
+            }
+        }
+        Ok(Value::Case(Box::new(Case { scrutinee: scrutinee, arms: arms, default: default, info: case.val.expr.info.clone() })))
+    }
+
+    /// An option's body, after the lets binding its fields
+    fn option(&mut self, fields: &[(Var, Value)], body: &CaseBody) -> Result<Block, SpruceErr> {
+        let mut block = match body {
+            CaseBody::Body(body) => self.block(body)?,
+            CaseBody::Expr(expr) => {
+                let outer = std::mem::take(&mut self.stmts);
+                let result = self.value(expr)?;
+                Block { stmts: std::mem::replace(&mut self.stmts, outer), result: result }
+            }
+        };
+        block.stmts.splice(0..0, fields.iter().map(|(var, val)| Stmt::Let(*var, val.clone())));
+        Ok(block)
+    }
+
+    /// A call to what `id` names, with its arguments lowered already
+    fn call(&mut self, id: na::SymbolID, args: Vec<Atom>) -> Value {
+        match self.load(id) {
+            Atom::Func(id) => Value::Call(id, args),
+            func => Value::Apply(func, args)
+        }
+    }
+
+    /// A variable, or a function as a value
+    fn load(&mut self, id: na::SymbolID) -> Atom {
+        if let Some(var) = self.locals.get(&id) {
+            return Atom::Var(*var);
+        }
+        if self.globals.contains(&id) {
+            return self.bind(Value::Global(id));
+        }
+        Atom::Func(id)
+    }
+
+    /// An Int literal, noting it if it's too wide to be tagged
+    fn int(&mut self, n: i64, info: &NodeInfo) -> i64 {
+        if !(TAGGED_MIN..=TAGGED_MAX).contains(&n) {
+            self.wide_ints.push((n, info.clone()));
+        }
+        n
+    }
+
+    fn atom(&mut self, expr: &ExprNode) -> Result<Atom, SpruceErr> {
+        let val = self.value(expr)?;
+        Ok(self.bind(val))
+    }
+
+    fn prim(&mut self, prim: Prim, expr: &ExprNode, operands: &[&ExprNode]) -> Result<Value, SpruceErr> {
+        let atoms = operands.iter().map(|operand| self.atom(operand)).collect::<Result<Vec<Atom>, SpruceErr>>()?;
+        Ok(Value::Prim(prim, atoms, expr.info.clone()))
+    }
+
+    fn value(&mut self, expr: &ExprNode) -> Result<Value, SpruceErr> {
+        let val = match &expr.val {
+            Expr::Add(l, r) => self.prim(Prim::Add, expr, &[l, r])?,
+            Expr::Subt(l, r) => self.prim(Prim::Sub, expr, &[l, r])?,
+            Expr::Mult(l, r) => self.prim(Prim::Mul, expr, &[l, r])?,
+            Expr::Div(l, r) => self.prim(Prim::Div, expr, &[l, r])?,
+            Expr::Mod(l, r) => self.prim(Prim::Mod, expr, &[l, r])?,
+            Expr::Pow(l, r) => self.prim(Prim::Pow, expr, &[l, r])?,
+            Expr::BitAnd(l, r) => self.prim(Prim::BitAnd, expr, &[l, r])?,
+            Expr::BitOr(l, r) => self.prim(Prim::BitOr, expr, &[l, r])?,
+            Expr::BitXor(l, r) => self.prim(Prim::BitXor, expr, &[l, r])?,
+            Expr::Shl(l, r) => self.prim(Prim::Shl, expr, &[l, r])?,
+            Expr::Shr(l, r) => self.prim(Prim::Shr, expr, &[l, r])?,
+            Expr::Lt(l, r) => self.prim(Prim::Lt, expr, &[l, r])?,
+            Expr::Gt(l, r) => self.prim(Prim::Gt, expr, &[l, r])?,
+            Expr::LtEq(l, r) => self.prim(Prim::LtEq, expr, &[l, r])?,
+            Expr::GtEq(l, r) => self.prim(Prim::GtEq, expr, &[l, r])?,
+            Expr::Eq(l, r) => self.prim(Prim::Eq, expr, &[l, r])?,
+            Expr::NotEq(l, r) => self.prim(Prim::NotEq, expr, &[l, r])?,
+            Expr::Neg(inner) => self.prim(Prim::Neg, expr, &[inner])?,
+            Expr::ComposeR(first, second) | Expr::ComposeL(second, first) => self.prim(Prim::Compose, expr, &[first, second])?,
+            Expr::Lit(lit) => Value::Atom(Atom::Int(self.int(*lit, &expr.info))),
+            Expr::List(elements) => {
+                let atoms = elements.iter().map(|elem| self.atom(elem)).collect::<Result<Vec<Atom>, SpruceErr>>()?;
+                let (cons, nil) = (self.prog.registry.value_id(LangValue::Cons), self.prog.registry.value_id(LangValue::Nil));
+                let mut list = Value::Construct(nil, Vec::new());
+                for atom in atoms.into_iter().rev() {
+                    let rest = self.bind(list);
+                    list = Value::Construct(cons, vec![atom, rest]);
+                }
+                list
+            }
+            Expr::Id(id) => Value::Atom(self.load(*id)),
+            Expr::FnCall(id, args) => {
+                let args = args.iter().map(|arg| self.atom(arg)).collect::<Result<Vec<Atom>, SpruceErr>>()?;
+                self.call(*id, args)
+            }
+            Expr::ADTVal(id, args) => {
+                let args = args.iter().map(|arg| self.atom(arg)).collect::<Result<Vec<Atom>, SpruceErr>>()?;
+                Value::Construct(*id, args)
+            }
+            Expr::Error => return Err(SpruceErr::new(String::from("cannot build an expression that failed to typecheck"), expr.info.clone()))
+        };
+        Ok(val)
+    }
+}
+
+impl Block {
+    /// Calls `f` with every atom the block uses, including in its cases
+    pub fn each_atom(&self, f: &mut impl FnMut(&Atom)) {
+        for stmt in &self.stmts {
+            match stmt {
+                Stmt::Let(_, val) => val.each_atom(f),
+                Stmt::SetGlobal(_, atom) => f(atom)
+            }
+        }
+        self.result.each_atom(f);
+    }
+
+    /// Whether the block can start its function over, which it can only do
+    /// from its result
+    pub fn recurs(&self) -> bool {
+        match &self.result {
+            Value::Recur(_) => true,
+            Value::Case(case) => case.arms.iter().any(|arm| arm.body.recurs()) || case.default.as_ref().map_or(false, |default| default.recurs()),
+            _ => false
+        }
+    }
+}
+
+impl Value {
+    pub fn each_atom(&self, f: &mut impl FnMut(&Atom)) {
+        match self {
+            Value::Atom(atom) => f(atom),
+            Value::Prim(_, atoms, _) | Value::Call(_, atoms) | Value::Construct(_, atoms) | Value::Recur(atoms) => atoms.iter().for_each(f),
+            Value::Apply(func, atoms) => {
+                f(func);
+                atoms.iter().for_each(f);
+            }
+            Value::Field(atom, _) => f(atom),
+            Value::Global(_) => (),
+            Value::Case(case) => {
+                f(&case.scrutinee);
+                for arm in &case.arms {
+                    arm.body.each_atom(f);
+                }
+                if let Some(default) = &case.default {
+                    default.each_atom(f);
+                }
+            }
+        }
+    }
+}
+
+impl Function {
+    /// How many times each variable is used
+    pub fn uses(&self) -> Vec<usize> {
+        let mut uses = vec![0; self.vars.len()];
+        self.body.each_atom(&mut |atom| if let Atom::Var(var) = atom {
+            uses[*var as usize] += 1;
+        });
+        uses
+    }
+}
+
+impl Program {
+    /// The program written out as text, for tracing and tests
+    pub fn dump(&self, prog: &na::Prog) -> String {
+        let mut out = String::new();
+        for func in self.functions.iter().chain(std::iter::once(&self.init)) {
+            let name = func.name.map_or("<definitions>", |id| eval::symbol_name(prog, id));
+            let params: Vec<String> = func.params.iter().map(|param| var(func, *param)).collect();
+            let _ = writeln!(out, "{}({}) {{", name, params.join(", "));
+            dump_block(&mut out, prog, func, &func.body, 1);
+            out.push_str("}\n");
+        }
+        out
+    }
+}
+
+/// A variable is written with the name of what it stands for, if anything
+fn var(func: &Function, var: Var) -> String {
+    match func.vars[var as usize] {
+        Some(_) => format!("v{}", var),
+        None => format!("t{}", var)
+    }
+}
+
+fn dump_atom(prog: &na::Prog, func: &Function, atom: &Atom) -> String {
+    match atom {
+        Atom::Var(v) => var(func, *v),
+        Atom::Int(n) => n.to_string(),
+        Atom::Unit => String::from("()"),
+        Atom::Func(id) => format!("&{}", eval::symbol_name(prog, *id))
+    }
+}
+
+fn dump_atoms(prog: &na::Prog, func: &Function, atoms: &[Atom]) -> String {
+    atoms.iter().map(|atom| dump_atom(prog, func, atom)).collect::<Vec<String>>().join(", ")
+}
+
+fn dump_block(out: &mut String, prog: &na::Prog, func: &Function, block: &Block, indent: usize) {
+    let pad = "    ".repeat(indent);
+    for stmt in &block.stmts {
+        match stmt {
+            Stmt::Let(v, val) => {
+                let _ = write!(out, "{}let {} = ", pad, var(func, *v));
+                dump_value(out, prog, func, val, indent);
+            }
+            Stmt::SetGlobal(id, atom) => { let _ = writeln!(out, "{}global {} = {}", pad, eval::symbol_name(prog, *id), dump_atom(prog, func, atom)); }
+        }
+    }
+    out.push_str(&pad);
+    dump_value(out, prog, func, &block.result, indent);
+}
+
+fn dump_value(out: &mut String, prog: &na::Prog, func: &Function, val: &Value, indent: usize) {
+    let pad = "    ".repeat(indent);
+    match val {
+        Value::Atom(atom) => { let _ = writeln!(out, "{}", dump_atom(prog, func, atom)); }
+        Value::Prim(prim, atoms, _) => { let _ = writeln!(out, "{:?}({})", prim, dump_atoms(prog, func, atoms)); }
+        Value::Global(id) => { let _ = writeln!(out, "global {}", eval::symbol_name(prog, *id)); }
+        Value::Call(id, atoms) => { let _ = writeln!(out, "{}({})", eval::symbol_name(prog, *id), dump_atoms(prog, func, atoms)); }
+        Value::Apply(f, atoms) => { let _ = writeln!(out, "apply {}({})", dump_atom(prog, func, f), dump_atoms(prog, func, atoms)); }
+        Value::Construct(id, atoms) => { let _ = writeln!(out, "{}({})", prog.type_table.values[id].name, dump_atoms(prog, func, atoms)); }
+        Value::Field(atom, i) => { let _ = writeln!(out, "{}.{}", dump_atom(prog, func, atom), i); }
+        Value::Recur(atoms) => { let _ = writeln!(out, "recur({})", dump_atoms(prog, func, atoms)); }
+        Value::Case(case) => {
+            let _ = writeln!(out, "case {} {{", dump_atom(prog, func, &case.scrutinee));
+            for arm in &case.arms {
+                match &arm.pattern {
+                    Pattern::Constructor(id) => { let _ = writeln!(out, "{}    {} ->", pad, prog.type_table.values[id].name); }
+                    Pattern::Int(n) => { let _ = writeln!(out, "{}    {} ->", pad, n); }
+                }
+                dump_block(out, prog, func, &arm.body, indent + 2);
+            }
+            if let Some(default) = &case.default {
+                let _ = writeln!(out, "{}    _ ->", pad);
+                dump_block(out, prog, func, default, indent + 2);
+            }
+            let _ = writeln!(out, "{}}}", pad);
+        }
+    }
+}
@@ -0,0 +1,401 @@
+/*
+The C target writes a checked program out as a single C99 file, with the
+runtime in cgen_runtime.c at the top, so that it can be built anywhere there's
+a C compiler, linked with the math library, as in `cc main.c -lm`. Spruce
+functions become C functions of the same shape, taking their arguments as an
+array. They're written from the program's A-normal form, where every value is
+worked out into a variable of its own before it's used, which keeps side
+effects in the order the interpreter has them, since C doesn't fix the order
+of a call's arguments. Each variable becomes a C variable, named after the
+symbol it stands for if any.
+
+Calls in tail position don't call at all: a function starting itself over
+jumps back to its top, and any other call hands the function and its
+arguments back to the trampoline in `sp_run`, so that loops written as
+recursion run in constant stack. As with the native target, only the
+functions the program can reach are written, and only the builtins the
+runtime has C versions of can be used.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::anf::{self, Atom, Prim, Value};
+use crate::error::SpruceErr;
+use crate::name_analysis as na;
+use crate::native;
+use crate::parser::NodeInfo;
+use crate::registry::LangValue;
+use crate::source::SourceMap;
+
+pub const RUNTIME: &str = include_str!("cgen_runtime.c");
+
+/// The builtins the runtime has C versions of
+const C_BUILTINS: [&str; 16] = ["show", "compare", "print", "printLine", "debug", "abs", "min", "max", "toFloat", "sqrt", "floor", "ceil", "sin", "cos", "tan", "args"];
+
+/// Where a value goes once it's worked out
+#[derive(PartialEq, Clone)]
+enum Dest {
+    Return,
+    Assign(String),
+    Discard
+}
+
+struct Generator<'a> {
+    sources: &'a SourceMap,
+    functions: HashMap<na::SymbolID, String>,
+    builtins: HashMap<na::SymbolID, String>,
+
+    // the function being written
+    out: String,
+    indent: usize,
+    next_temp: u32,
+    vars: Vec<Option<na::SymbolID>>,
+    uses: Vec<usize>,
+    params: Vec<anf::Var>
+}
+
+/// Writes a program out as C
+pub fn generate(prog: &na::Prog, program: &anf::Program, sources: &SourceMap) -> Result<String, SpruceErr> {
+    let mut gen = Generator {
+        sources: sources,
+        functions: program.functions.iter().filter_map(|func| func.name.map(|name| (name, native::mangle(prog, name)))).collect(),
+        builtins: anf::used_builtins(prog, &program.builtins, "c", &C_BUILTINS)?.into_iter().map(|(id, builtin, _)| {
+            (id, format!("sp_builtin_{}", builtin))
+        }).collect(),
+        out: String::new(),
+        indent: 1,
+        next_temp: 0,
+        vars: Vec::new(),
+        uses: Vec::new(),
+        params: Vec::new()
+    };
+
+    let mut out = String::from("/* generated by spruce */\n\n");
+    let max_args = program.functions.iter().map(|func| func.params.len()).chain(std::iter::once(2)).max().unwrap_or(2);
+    let _ = writeln!(out, "#define SP_MAX_ARGS {}", max_args);
+    let ids = [
+        ("SP_TRUE", LangValue::True), ("SP_FALSE", LangValue::False), ("SP_CONS", LangValue::Cons), ("SP_NIL", LangValue::Nil),
+        ("SP_LT", LangValue::LT), ("SP_EQ", LangValue::EQ), ("SP_GT", LangValue::GT)
+    ];
+    for (name, value) in ids {
+        let _ = writeln!(out, "#define {} {}", name, prog.registry.value_id(value));
+    }
+    let names: Vec<String> = native::constructor_names(prog).iter().map(|name| string(name)).collect();
+    let _ = writeln!(out, "\nstatic const char *sp_names[] = {{ {} }};\n", names.join(", "));
+    out.push_str(RUNTIME);
+    out.push('\n');
+
+    let mut globals = program.globals.clone();
+    globals.sort();
+    for id in globals {
+        let _ = writeln!(out, "static Value g_{};", id);
+    }
+    for func in &program.functions {
+        let _ = writeln!(out, "static Value {}(Value *args, Tail *tail);", gen.code(func));
+    }
+    out.push('\n');
+
+    for func in &program.functions {
+        gen.start(func);
+        gen.block(&func.body, Dest::Return);
+        let _ = writeln!(out, "static Value {}(Value *args, Tail *tail) {{", gen.code(func));
+        out.push_str(&gen.declarations(func));
+        out.push_str("    (void)args;\n    (void)tail;\n");
+        if func.body.recurs() {
+            out.push_str("top: ;\n");
+        }
+        out.push_str(&gen.out);
+        out.push_str("}\n\n");
+    }
+
+    gen.start(&program.init);
+    gen.block(&program.init.body, Dest::Discard);
+    out.push_str("static void sp_init(void) {\n");
+    out.push_str(&gen.declarations(&program.init));
+    out.push_str(&gen.out);
+    out.push_str("}\n\n");
+
+    out.push_str("int main(int argc, char **argv) {\n");
+    out.push_str("    Value result;\n");
+    out.push_str("    sp_argc = argc;\n");
+    out.push_str("    sp_argv = argv;\n");
+    out.push_str("    sp_init();\n");
+    let _ = writeln!(out, "    result = sp_run({}, NULL);", gen.functions[&program.entry]);
+    out.push_str("    if (result.kind != UNIT) {\n");
+    out.push_str("        puts(sp_shown(result));\n");
+    out.push_str("    }\n");
+    out.push_str("    return 0;\n");
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// A C string literal
+fn string(text: &str) -> String {
+    let mut lit = String::from("\"");
+    for b in text.bytes() {
+        match b {
+            b'"' | b'\\' => { lit.push('\\'); lit.push(b as char); }
+            b if b.is_ascii_graphic() || b == b' ' => lit.push(b as char),
+            b => { let _ = write!(lit, "\\{:03o}", b); }
+        }
+    }
+    lit.push('"');
+    lit
+}
+
+fn int(n: i64) -> String {
+    match n {
+        i64::MIN => String::from("INT64_MIN"),
+        n => format!("INT64_C({})", n)
+    }
+}
+
+impl<'a> Generator<'a> {
+    fn code(&self, func: &anf::Function) -> String {
+        self.functions[&func.name.expect("functions are named")].clone()
+    }
+
+    fn start(&mut self, func: &anf::Function) {
+        self.out = String::new();
+        self.indent = 1;
+        self.next_temp = 0;
+        self.vars = func.vars.clone();
+        self.uses = func.uses();
+        self.params = func.params.clone();
+    }
+
+    /// The variables the function just written uses, with its parameters
+    /// taken from `args`
+    fn declarations(&self, func: &anf::Function) -> String {
+        let mut out = String::new();
+        for var in 0..func.vars.len() as anf::Var {
+            if self.uses[var as usize] == 0 {
+                continue;
+            }
+            match func.params.iter().position(|param| *param == var) {
+                Some(i) => { let _ = writeln!(out, "    Value {} = args[{}];", self.var(var), i); }
+                None => { let _ = writeln!(out, "    Value {} = sp_unit;", self.var(var)); }
+            }
+        }
+        out
+    }
+
+    /// Variables are named after the symbol they stand for, if any
+    fn var(&self, var: anf::Var) -> String {
+        match self.vars[var as usize] {
+            Some(id) => format!("v_{}", id),
+            None => format!("t{}", var)
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn location(&self, info: &NodeInfo) -> String {
+        string(&SpruceErr::new(String::new(), info.clone()).location(self.sources))
+    }
+
+    fn atom(&self, atom: &Atom) -> String {
+        match atom {
+            Atom::Var(var) => self.var(*var),
+            Atom::Int(n) => format!("sp_int({})", int(*n)),
+            Atom::Unit => String::from("sp_unit"),
+            Atom::Func(id) => {
+                let code = self.functions.get(id).or_else(|| self.builtins.get(id)).expect("generation only reaches what was checked to be callable");
+                format!("sp_func({})", code)
+            }
+        }
+    }
+
+    /// An atom that is an Int, as a C number
+    fn num(&self, atom: &Atom) -> String {
+        match atom {
+            Atom::Int(n) => int(*n),
+            atom => format!("{}.as.n", self.atom(atom))
+        }
+    }
+
+    /// The arguments of a call, as an array, or NULL when there are none
+    fn array(&mut self, args: &[Atom]) -> String {
+        if args.is_empty() {
+            return String::from("NULL");
+        }
+        let values: Vec<String> = args.iter().map(|arg| self.atom(arg)).collect();
+        let array = format!("a{}", self.next_temp);
+        self.next_temp += 1;
+        self.line(&format!("Value {}[] = {{ {} }};", array, values.join(", ")));
+        array
+    }
+
+    fn block(&mut self, block: &anf::Block, dest: Dest) {
+        for stmt in &block.stmts {
+            match stmt {
+                anf::Stmt::Let(var, val) => {
+                    let dest = match self.uses[*var as usize] {
+                        0 => Dest::Discard,
+                        _ => Dest::Assign(self.var(*var))
+                    };
+                    self.value(val, dest);
+                }
+                anf::Stmt::SetGlobal(id, atom) => {
+                    let atom = self.atom(atom);
+                    self.line(&format!("g_{} = {};", id, atom));
+                }
+            }
+        }
+        self.value(&block.result, dest);
+    }
+
+    fn value(&mut self, val: &Value, dest: Dest) {
+        let expr = match val {
+            Value::Case(case) => return self.case(case, dest),
+            Value::Recur(args) => return self.recur(args),
+            Value::Construct(id, args) => return self.construct(*id, args, dest),
+            // calls in tail position go through the trampoline
+            Value::Call(id, args) if dest == Dest::Return && self.functions.contains_key(id) => {
+                let values: Vec<String> = args.iter().map(|arg| self.atom(arg)).collect();
+                for (i, val) in values.iter().enumerate() {
+                    self.line(&format!("tail->args[{}] = {};", i, val));
+                }
+                self.line(&format!("tail->code = {};", self.functions[id]));
+                self.line("return sp_unit;");
+                return;
+            }
+            Value::Call(id, args) => {
+                let args = self.array(args);
+                match self.functions.get(id) {
+                    Some(func) => format!("sp_run({}, {})", func, args),
+                    None => format!("{}({}, NULL)", self.builtins[id], args)
+                }
+            }
+            Value::Apply(func, args) => {
+                let args = self.array(args);
+                format!("sp_apply({}, {})", self.atom(func), args)
+            }
+            Value::Atom(atom) if dest == Dest::Discard && !matches!(atom, Atom::Func(_)) => return,
+            Value::Atom(atom) => self.atom(atom),
+            Value::Global(id) => format!("g_{}", id),
+            Value::Field(atom, i) => format!("{}.as.obj->fields[{}]", self.atom(atom), i),
+            Value::Prim(prim, operands, info) => self.prim(*prim, operands, info)
+        };
+        match dest {
+            Dest::Return => self.line(&format!("return {};", expr)),
+            Dest::Assign(var) => self.line(&format!("{} = {};", var, expr)),
+            Dest::Discard if matches!(val, Value::Call(_, _) | Value::Apply(_, _)) => self.line(&format!("{};", expr)),
+            Dest::Discard => self.line(&format!("(void){};", expr))
+        }
+    }
+
+    /// Starts the function over with new arguments, which are all worked
+    /// out before any parameter changes
+    fn recur(&mut self, args: &[Atom]) {
+        let mut assigns = Vec::new();
+        for (param, arg) in self.params.clone().into_iter().zip(args) {
+            if self.uses[param as usize] == 0 {
+                continue;
+            }
+            let temp = format!("r{}", self.next_temp);
+            self.next_temp += 1;
+            self.line(&format!("Value {} = {};", temp, self.atom(arg)));
+            assigns.push(format!("{} = {};", self.var(param), temp));
+        }
+        for assign in assigns {
+            self.line(&assign);
+        }
+        self.line("goto top;");
+    }
+
+    fn prim(&self, prim: Prim, operands: &[Atom], info: &NodeInfo) -> String {
+        let num = |i: usize| self.num(&operands[i]);
+        match prim {
+            Prim::Add => format!("sp_int(sp_add({}, {}))", num(0), num(1)),
+            Prim::Sub => format!("sp_int(sp_sub({}, {}))", num(0), num(1)),
+            Prim::Mul => format!("sp_int(sp_mul({}, {}))", num(0), num(1)),
+            Prim::Shl => format!("sp_int(sp_shl({}, {}))", num(0), num(1)),
+            Prim::Shr => format!("sp_int(sp_shr({}, {}))", num(0), num(1)),
+            Prim::Div => format!("sp_int(sp_div({}, {}, {}))", num(0), num(1), self.location(info)),
+            Prim::Mod => format!("sp_int(sp_mod({}, {}, {}))", num(0), num(1), self.location(info)),
+            Prim::Pow => format!("sp_int(sp_pow({}, {}, {}))", num(0), num(1), self.location(info)),
+            Prim::BitAnd => format!("sp_int({} & {})", num(0), num(1)),
+            Prim::BitOr => format!("sp_int({} | {})", num(0), num(1)),
+            Prim::BitXor => format!("sp_int({} ^ {})", num(0), num(1)),
+            Prim::Lt => format!("sp_bool({} < {})", num(0), num(1)),
+            Prim::Gt => format!("sp_bool({} > {})", num(0), num(1)),
+            Prim::LtEq => format!("sp_bool({} <= {})", num(0), num(1)),
+            Prim::GtEq => format!("sp_bool({} >= {})", num(0), num(1)),
+            Prim::Eq => format!("sp_bool(sp_compare({}, {}) == 0)", self.atom(&operands[0]), self.atom(&operands[1])),
+            Prim::NotEq => format!("sp_bool(sp_compare({}, {}) != 0)", self.atom(&operands[0]), self.atom(&operands[1])),
+            Prim::Neg => format!("sp_int(sp_sub(0, {}))", num(0)),
+            Prim::Compose => format!("sp_compose({}, {})", self.atom(&operands[0]), self.atom(&operands[1]))
+        }
+    }
+
+    fn construct(&mut self, id: na::ADTValID, args: &[Atom], dest: Dest) {
+        if dest == Dest::Discard {
+            return;
+        }
+        // built apart from its variable, which may be one of its fields
+        let obj = format!("o{}", self.next_temp);
+        self.next_temp += 1;
+        self.line(&format!("Value {} = sp_adt({}, {});", obj, id, args.len()));
+        for (i, arg) in args.iter().enumerate() {
+            let arg = self.atom(arg);
+            self.line(&format!("{}.as.obj->fields[{}] = {};", obj, i, arg));
+        }
+        match dest {
+            Dest::Return => self.line(&format!("return {};", obj)),
+            Dest::Assign(var) => self.line(&format!("{} = {};", var, obj)),
+            Dest::Discard => ()
+        }
+    }
+
+    fn case(&mut self, case: &anf::Case, dest: Dest) {
+        let val = self.atom(&case.scrutinee);
+
+        // constructors are switched on by their id and numbers by their
+        // value
+        let is_adt = case.arms.iter().any(|arm| matches!(arm.pattern, anf::Pattern::Constructor(_)));
+        self.line(&format!("switch ({}) {{", if is_adt { format!("{}.as.obj->tag", val) } else { self.num(&case.scrutinee) }));
+        for arm in &case.arms {
+            match &arm.pattern {
+                anf::Pattern::Constructor(id) => self.line(&format!("case {}: {{", id)),
+                anf::Pattern::Int(n) => self.line(&format!("case {}: {{", int(*n)))
+            }
+            self.indent += 1;
+            self.option(&arm.body, dest.clone());
+        }
+        match &case.default {
+            Some(body) => {
+                self.line("default: {");
+                self.indent += 1;
+                self.option(body, dest.clone());
+            }
+            None => {
+                let location = self.location(&case.info);
+                self.line("default:");
+                self.indent += 1;
+                self.line(&format!("sp_no_match({}, {});", val, location));
+                self.line(if dest == Dest::Return { "return sp_unit;" } else { "break;" });
+                self.indent -= 1;
+            }
+        }
+        self.line("}");
+    }
+
+    /// The rest of an option, whose opening brace has been written
+    fn option(&mut self, body: &anf::Block, dest: Dest) {
+        let returns = dest == Dest::Return;
+        self.block(body, dest);
+        if !returns {
+            self.line("break;");
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+}
@@ -0,0 +1,469 @@
+/*
+The runtime written at the top of every C program Spruce generates, in plain
+C99 so that any C compiler can build it, linked with the math library. Every
+value is a tagged union of its kind and its contents, where Ints and Floats
+are held directly and anything else points to an object:
+
+    ADT      the constructor's id, and its arguments
+    FUNC     the code of the function
+    COMPOSE  the first and second functions, as its two fields
+    STR      a pointer to the characters
+
+Objects are allocated with malloc and never freed, since programs are short
+lived.
+
+Functions all take their arguments as an array, along with somewhere to put
+a tail call: rather than making one, a function stores what it would call in
+`tail` and returns, and `sp_run` makes the call from there. That way tail
+calls, which is how Spruce loops, don't use up the C stack.
+
+Before this file the generated code defines SP_MAX_ARGS, the constructor
+names in `sp_names` and the ids of the constructors the runtime makes itself.
+*/
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef enum { UNIT, INT, ADT, FUNC, COMPOSE, STR, FLOAT } Kind;
+
+typedef struct Obj Obj;
+typedef struct Tail Tail;
+
+typedef struct {
+    Kind kind;
+    union {
+        int64_t n;
+        double f;
+        Obj *obj;
+        const char *str;
+    } as;
+} Value;
+
+typedef Value Code(Value *args, Tail *tail);
+
+struct Obj {
+    int64_t tag;
+    int64_t count;
+    Code *code;
+    Value fields[];
+};
+
+struct Tail {
+    Code *code;
+    Value args[SP_MAX_ARGS];
+};
+
+const Value sp_unit = { UNIT, { 0 } };
+
+int sp_argc;
+char **sp_argv;
+
+Value sp_int(int64_t n) {
+    Value v;
+    v.kind = INT;
+    v.as.n = n;
+    return v;
+}
+
+Value sp_float(double f) {
+    Value v;
+    v.kind = FLOAT;
+    v.as.f = f;
+    return v;
+}
+
+Value sp_obj(Kind kind, int64_t tag, int64_t count) {
+    Value v;
+    v.kind = kind;
+    v.as.obj = malloc(sizeof(Obj) + count * sizeof(Value));
+    if (v.as.obj == NULL) {
+        fprintf(stderr, "error: out of memory\n");
+        exit(1);
+    }
+    v.as.obj->tag = tag;
+    v.as.obj->count = count;
+    v.as.obj->code = NULL;
+    return v;
+}
+
+Value sp_adt(int64_t tag, int64_t count) {
+    return sp_obj(ADT, tag, count);
+}
+
+Value sp_func(Code *code) {
+    Value v = sp_obj(FUNC, 0, 0);
+    v.as.obj->code = code;
+    return v;
+}
+
+Value sp_compose(Value first, Value second) {
+    Value v = sp_obj(COMPOSE, 0, 2);
+    v.as.obj->fields[0] = first;
+    v.as.obj->fields[1] = second;
+    return v;
+}
+
+Value sp_str(const char *text) {
+    Value v;
+    v.kind = STR;
+    v.as.str = text;
+    return v;
+}
+
+Value sp_bool(int b) {
+    return sp_adt(b ? SP_TRUE : SP_FALSE, 0);
+}
+
+Value sp_run(Code *code, Value *args) {
+    Tail tail;
+    for (;;) {
+        Value result;
+        tail.code = NULL;
+        result = code(args, &tail);
+        if (tail.code == NULL) {
+            return result;
+        }
+        code = tail.code;
+        args = tail.args;
+    }
+}
+
+Value sp_apply(Value f, Value *args) {
+    if (f.kind == COMPOSE) {
+        Value inner = sp_apply(f.as.obj->fields[0], args);
+        return sp_apply(f.as.obj->fields[1], &inner);
+    }
+    return sp_run(f.as.obj->code, args);
+}
+
+/* Ints wrap around as in the interpreter, which C's signed arithmetic
+   doesn't promise, so it's done unsigned */
+
+int64_t sp_wrap(uint64_t n) {
+    return n <= INT64_MAX ? (int64_t)n : -(int64_t)(UINT64_MAX - n) - 1;
+}
+
+int64_t sp_add(int64_t a, int64_t b) { return sp_wrap((uint64_t)a + (uint64_t)b); }
+int64_t sp_sub(int64_t a, int64_t b) { return sp_wrap((uint64_t)a - (uint64_t)b); }
+int64_t sp_mul(int64_t a, int64_t b) { return sp_wrap((uint64_t)a * (uint64_t)b); }
+int64_t sp_shl(int64_t a, int64_t b) { return sp_wrap((uint64_t)a << (b & 63)); }
+
+int64_t sp_shr(int64_t a, int64_t b) {
+    return a < 0 ? ~(int64_t)(~(uint64_t)a >> (b & 63)) : a >> (b & 63);
+}
+
+void sp_fail(const char *message, const char *where) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n --> %s\n", message, where);
+    exit(1);
+}
+
+int64_t sp_div(int64_t a, int64_t b, const char *where) {
+    if (b == 0) {
+        sp_fail("division by zero", where);
+    }
+    return b == -1 ? sp_sub(0, a) : a / b;
+}
+
+int64_t sp_mod(int64_t a, int64_t b, const char *where) {
+    if (b == 0) {
+        sp_fail("division by zero", where);
+    }
+    return b == -1 ? 0 : a % b;
+}
+
+int64_t sp_pow(int64_t base, int64_t exp, const char *where) {
+    uint64_t result = 1, b = (uint64_t)base;
+    if (exp < 0) {
+        char message[64];
+        sprintf(message, "cannot raise an Int to the negative power %" PRId64, exp);
+        sp_fail(message, where);
+    }
+    for (; exp > 0; exp >>= 1) {
+        if (exp & 1) {
+            result *= b;
+        }
+        b *= b;
+    }
+    return sp_wrap(result);
+}
+
+/* Text that grows as it's written to */
+typedef struct {
+    char *text;
+    size_t len, cap;
+} Buf;
+
+void sp_write(Buf *buf, const char *text) {
+    size_t len = strlen(text);
+    if (buf->len + len + 1 > buf->cap) {
+        buf->cap = (buf->len + len + 1) * 2;
+        buf->text = realloc(buf->text, buf->cap);
+        if (buf->text == NULL) {
+            fprintf(stderr, "error: out of memory\n");
+            exit(1);
+        }
+    }
+    memcpy(buf->text + buf->len, text, len + 1);
+    buf->len += len;
+}
+
+/* A Float as the interpreter writes one: with the fewest digits that read
+   back as the same number, written out in full rather than with an exponent */
+void sp_show_float(Buf *buf, double x) {
+    char digits[32], *at = digits;
+    int precision, exponent, count, i;
+    if (isnan(x)) {
+        sp_write(buf, "NaN");
+        return;
+    }
+    if (isinf(x)) {
+        sp_write(buf, x > 0 ? "Infinity" : "-Infinity");
+        return;
+    }
+    if (x == 0) {
+        sp_write(buf, signbit(x) ? "-0" : "0");
+        return;
+    }
+    for (precision = 1; precision < 17; precision++) {
+        sprintf(digits, "%.*e", precision - 1, x);
+        if (strtod(digits, NULL) == x) {
+            break;
+        }
+    }
+    sprintf(digits, "%.*e", precision - 1, x);
+    if (*at == '-') {
+        sp_write(buf, "-");
+        at++;
+    }
+    /* what's left is d.ddde+XX, which becomes the digits alone */
+    exponent = atoi(strchr(at, 'e') + 1);
+    *strchr(at, 'e') = '\0';
+    if (at[1] == '.') {
+        memmove(at + 1, at + 2, strlen(at + 2) + 1);
+    }
+    count = (int)strlen(at);
+    if (exponent < 0) {
+        sp_write(buf, "0.");
+        for (i = exponent; i < -1; i++) {
+            sp_write(buf, "0");
+        }
+        sp_write(buf, at);
+    }
+    else if (exponent + 1 >= count) {
+        sp_write(buf, at);
+        for (i = count; i <= exponent; i++) {
+            sp_write(buf, "0");
+        }
+    }
+    else {
+        char rest[32];
+        strcpy(rest, at + exponent + 1);
+        at[exponent + 1] = '\0';
+        sp_write(buf, at);
+        sp_write(buf, ".");
+        sp_write(buf, rest);
+    }
+}
+
+/* A value as it would be written in Spruce, as the show builtin gives it */
+void sp_show(Buf *buf, Value v) {
+    char number[32];
+    int64_t i;
+    switch (v.kind) {
+    case UNIT:
+        sp_write(buf, "()");
+        break;
+    case INT:
+        sprintf(number, "%" PRId64, v.as.n);
+        sp_write(buf, number);
+        break;
+    case FLOAT:
+        sp_show_float(buf, v.as.f);
+        break;
+    case STR:
+        sp_write(buf, v.as.str);
+        break;
+    case ADT:
+        sp_write(buf, sp_names[v.as.obj->tag]);
+        if (v.as.obj->count > 0) {
+            sp_write(buf, "(");
+            for (i = 0; i < v.as.obj->count; i++) {
+                if (i > 0) {
+                    sp_write(buf, ", ");
+                }
+                sp_show(buf, v.as.obj->fields[i]);
+            }
+            sp_write(buf, ")");
+        }
+        break;
+    default:
+        sp_write(buf, "<function>");
+    }
+}
+
+char *sp_shown(Value v) {
+    Buf buf = { NULL, 0, 0 };
+    sp_write(&buf, "");
+    sp_show(&buf, v);
+    return buf.text;
+}
+
+/* Floats are ordered by their bits, with those of negative ones but the sign
+   flipped, which puts -0 before 0 and NaN at the ends as the interpreter does */
+int64_t sp_float_order(double x) {
+    int64_t bits;
+    memcpy(&bits, &x, sizeof bits);
+    return bits < 0 ? bits ^ INT64_MAX : bits;
+}
+
+/* A Float cut to an Int the way the interpreter cuts one, saturating at the
+   ends of an Int, with NaN as 0 */
+int64_t sp_to_int(double x) {
+    if (isnan(x)) {
+        return 0;
+    }
+    if (x >= 9223372036854775808.0) {
+        return INT64_MAX;
+    }
+    if (x < -9223372036854775808.0) {
+        return INT64_MIN;
+    }
+    return (int64_t)x;
+}
+
+/* Orders values as the interpreter does: numbers by value, and values of
+   types by constructor name and then by argument */
+int sp_compare(Value a, Value b) {
+    int order;
+    int64_t i, x, y;
+    if (a.kind != b.kind) {
+        return (a.kind > b.kind) - (a.kind < b.kind);
+    }
+    switch (a.kind) {
+    case UNIT:
+        return 0;
+    case INT:
+        return (a.as.n > b.as.n) - (a.as.n < b.as.n);
+    case FLOAT:
+        x = sp_float_order(a.as.f);
+        y = sp_float_order(b.as.f);
+        return (x > y) - (x < y);
+    case STR:
+        return strcmp(a.as.str, b.as.str);
+    case ADT:
+        order = strcmp(sp_names[a.as.obj->tag], sp_names[b.as.obj->tag]);
+        for (i = 0; order == 0 && i < a.as.obj->count && i < b.as.obj->count; i++) {
+            order = sp_compare(a.as.obj->fields[i], b.as.obj->fields[i]);
+        }
+        return order != 0 ? order : (a.as.obj->count > b.as.obj->count) - (a.as.obj->count < b.as.obj->count);
+    default:
+        return (a.as.obj > b.as.obj) - (a.as.obj < b.as.obj);
+    }
+}
+
+void sp_no_match(Value v, const char *where) {
+    fflush(stdout);
+    fprintf(stderr, "error: no option matches %s\n --> %s\n", sp_shown(v), where);
+    exit(1);
+}
+
+/* the builtins there are C versions of */
+
+Value sp_builtin_show(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_str(sp_shown(args[0]));
+}
+
+Value sp_builtin_compare(Value *args, Tail *tail) {
+    int order = sp_compare(args[0], args[1]);
+    (void)tail;
+    return sp_adt(order < 0 ? SP_LT : order > 0 ? SP_GT : SP_EQ, 0);
+}
+
+Value sp_builtin_print(Value *args, Tail *tail) {
+    (void)tail;
+    fputs(args[0].as.str, stdout);
+    fflush(stdout);
+    return sp_unit;
+}
+
+Value sp_builtin_printLine(Value *args, Tail *tail) {
+    (void)tail;
+    puts(args[0].as.str);
+    return sp_unit;
+}
+
+Value sp_builtin_debug(Value *args, Tail *tail) {
+    (void)tail;
+    fprintf(stderr, "debug: %s\n", sp_shown(args[0]));
+    return args[0];
+}
+
+Value sp_builtin_abs(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_int(args[0].as.n < 0 ? sp_sub(0, args[0].as.n) : args[0].as.n);
+}
+
+Value sp_builtin_min(Value *args, Tail *tail) {
+    (void)tail;
+    return args[0].as.n < args[1].as.n ? args[0] : args[1];
+}
+
+Value sp_builtin_max(Value *args, Tail *tail) {
+    (void)tail;
+    return args[0].as.n > args[1].as.n ? args[0] : args[1];
+}
+
+Value sp_builtin_toFloat(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_float((double)args[0].as.n);
+}
+
+Value sp_builtin_sqrt(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_float(sqrt(args[0].as.f));
+}
+
+Value sp_builtin_floor(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_int(sp_to_int(floor(args[0].as.f)));
+}
+
+Value sp_builtin_ceil(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_int(sp_to_int(ceil(args[0].as.f)));
+}
+
+Value sp_builtin_sin(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_float(sin(args[0].as.f));
+}
+
+Value sp_builtin_cos(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_float(cos(args[0].as.f));
+}
+
+Value sp_builtin_tan(Value *args, Tail *tail) {
+    (void)tail;
+    return sp_float(tan(args[0].as.f));
+}
+
+Value sp_builtin_args(Value *args, Tail *tail) {
+    Value list = sp_adt(SP_NIL, 0);
+    int i;
+    (void)args;
+    (void)tail;
+    for (i = sp_argc - 1; i >= 1; i--) {
+        Value cons = sp_adt(SP_CONS, 2);
+        cons.as.obj->fields[0] = sp_str(sp_argv[i]);
+        cons.as.obj->fields[1] = list;
+        list = cons;
+    }
+    return list;
+}
@@ -0,0 +1,119 @@
+/*
+Everything spruce does is a subcommand, as in `spruce run file.sp`. The
+subcommands read their arguments the same way: flags they share, like
+`--color` or `--verbose`, are taken by `Common`, and each reads its own flags
+around them. They end the same way too, with one of a few exit codes:
+
+  - 0 when the command did what it was asked
+  - 1 when the program did not compile, failed while running, or something
+    the command reads or writes could not be
+  - 2 when the command line itself is wrong, along with how it should look
+*/
+
+use std::str::FromStr;
+
+use spruce::error::ColorChoice;
+use spruce::lint::{self, Lints};
+use spruce::trace;
+
+/// The exit code of a command that couldn't do what it was asked
+pub const EXIT_FAILURE: i32 = 1;
+
+/// The exit code of a command given arguments it doesn't understand
+pub const EXIT_USAGE: i32 = 2;
+
+/// Each subcommand, with what it does
+pub const COMMANDS: [(&str, &str); 10] = [
+    ("check", "check a program or package for errors"),
+    ("run", "run a program"),
+    ("build", "compile a program to javascript, C, WebAssembly or an executable"),
+    ("fmt", "format source files"),
+    ("repl", "work with definitions and expressions interactively"),
+    ("lsp", "serve an editor as a language server"),
+    ("doc", "write documentation for a program"),
+    ("graph", "draw a program's calls and types for graphviz"),
+    ("test", "run a program's tests"),
+    ("explain", "explain an error code")
+];
+
+/// What `spruce` on its own or `spruce help` shows
+pub fn usage() -> String {
+    let mut usage = String::from("usage: spruce <command> [arguments]\n\ncommands:\n");
+    for (name, summary) in &COMMANDS {
+        usage.push_str(&format!("    {:<10}{}\n", name, summary));
+    }
+    usage.push_str("\nflags every command takes:\n");
+    usage.push_str("    --color=always|never|auto\n");
+    usage.push_str("    --verbose <phase>\n");
+    usage.push_str("    -A/-W/-D <lint>\n");
+    usage
+}
+
+/// Ends the command with the usage error `message`
+pub fn usage_error(message: &str) -> ! {
+    eprintln!("{}", message);
+    std::process::exit(EXIT_USAGE);
+}
+
+/// Ends the command as having failed, after whatever it has already said
+pub fn fail() -> ! {
+    std::process::exit(EXIT_FAILURE);
+}
+
+/// The value of a flag like `--seed=<n>`, ending the command with a usage
+/// error if it isn't a whole number
+pub fn number<T: FromStr>(flag: &str, value: &str) -> T {
+    value.parse::<T>().unwrap_or_else(|_| usage_error(&format!("{} expects a whole number, not '{}'", flag, value)))
+}
+
+/// The flags every subcommand takes
+pub struct Common {
+    pub color: ColorChoice,
+    pub lints: Lints
+}
+
+impl Default for Common {
+    fn default() -> Self {
+        Common {
+            color: ColorChoice::Auto,
+            lints: Lints::new()
+        }
+    }
+}
+
+impl Common {
+    pub fn use_color(&self) -> bool {
+        self.color.use_color()
+    }
+
+    /// Takes `arg` if it's one of the flags every subcommand has, along with
+    /// the value after it in `rest` if it has one. Anything else is left for
+    /// the subcommand
+    pub fn parse<'a>(&mut self, arg: &str, rest: &mut impl Iterator<Item = &'a String>) -> Result<bool, String> {
+        if let Some(choice) = arg.strip_prefix("--color=") {
+            self.color = ColorChoice::from_arg(choice).ok_or_else(|| format!("--color expects always, never or auto, not '{}'", choice))?;
+            return Ok(true);
+        }
+
+        // tracing is turned on a phase at a time, as in --verbose typecheck
+        if arg == "--verbose" {
+            let phase = rest.next().and_then(|name| name.parse::<trace::Phase>().ok()).ok_or_else(|| {
+                let names: Vec<&str> = trace::ALL_PHASES.iter().map(|phase| phase.as_str()).collect();
+                format!("--verbose expects a phase, one of: {}", names.join(", "))
+            })?;
+            trace::enable(phase);
+            return Ok(true);
+        }
+
+        // lint levels are set with -A, -W or -D followed by the lint's name
+        if let Some(level) = lint::Level::from_flag(arg) {
+            let lint = rest.next().and_then(|name| name.parse::<lint::Lint>().ok()).ok_or_else(|| {
+                let names: Vec<&str> = lint::ALL_LINTS.iter().map(|lint| lint.as_str()).collect();
+                format!("{} expects a lint name, one of: {}", arg, names.join(", "))
+            })?;
+            self.lints.set_level(lint, level);
+            return Ok(true);
+        }
+        Ok(false)
+    }
+}
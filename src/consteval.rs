@@ -0,0 +1,77 @@
+/*
+Constant evaluation works out the program's definitions as it compiles, once,
+with the interpreter, the same way a run does before it starts `main`. That
+makes a definition something that can only compute a value:
+
+  - calling a builtin with effects, like printLine, readFile or randomInt, is
+    an error, since it would happen as the program compiles rather than each
+    time it runs
+  - anything that fails, like dividing by zero or using a definition before
+    the one that gives it its value, would fail every run, so it's an error
+    compiling instead
+
+Some definitions can't be worked out yet: those that need a host function or
+a module only an interface declares, which only a run has, and those that
+take more calls than the budget allows. These are left for the program to
+work out when it runs, along with the definitions after them, which may need
+them. What's worked out is traced under `--verbose eval`.
+*/
+
+use crate::error::SpruceErr;
+use crate::error_codes::ErrorCode;
+use crate::eval::{self, Runtime};
+use crate::name_analysis as na;
+use crate::trace::Phase;
+use crate::typecheck::{self, StmtNode};
+
+/// How many calls the definitions can make between them before they're left
+/// for the program to work out
+const BUDGET: usize = 10_000;
+
+/// Works out the program's definitions, failing if any of them has effects
+/// or fails
+pub fn check(prog: &na::Prog, typed: &typecheck::Prog) -> Result<(), SpruceErr> {
+    if typed.definitions.is_empty() {
+        return Ok(());
+    }
+
+    // the interpreter recurses as the program does, so it gets the stack a
+    // run would
+    std::thread::scope(|scope| {
+        std::thread::Builder::new().stack_size(1 << 30).spawn_scoped(scope, || work_out(prog, typed))
+            .expect("failed to start constant evaluation").join().expect("constant evaluation panicked")
+    })
+}
+
+fn work_out(prog: &na::Prog, typed: &typecheck::Prog) -> Result<(), SpruceErr> {
+    match eval::definitions(prog, typed, Runtime::constant(BUDGET)) {
+        Ok(values) => {
+            for (id, val) in values {
+                trace!(Phase::Eval, "worked out '{}' as {}", eval::symbol_name(prog, id), eval::show(prog, &val));
+            }
+            Ok(())
+        }
+        Err((def, e)) if !e.is_error() => {
+            trace!(Phase::Eval, "left '{}' and the definitions after it for the program to work out, since {}", name(prog, def), e.message);
+            Ok(())
+        }
+        Err((def, e)) => {
+            let e = if e.code.is_none() { e.with_code(ErrorCode::FailingDefinition) } else { e };
+            let within = e.info.file == def.info.file && def.info.span.start <= e.info.span.start && e.info.span.end <= def.info.span.end;
+            if within {
+                Err(e)
+            }
+            else {
+                let label = format!("while working out '{}'", name(prog, def));
+                Err(e.with_label(label, def.info.clone()))
+            }
+        }
+    }
+}
+
+fn name<'p>(prog: &'p na::Prog, def: &StmtNode) -> &'p str {
+    match &def.val {
+        typecheck::Stmt::Assign(tgt, _) => eval::symbol_name(prog, tgt.val.id()),
+        _ => ""
+    }
+}
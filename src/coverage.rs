@@ -0,0 +1,237 @@
+/*
+Coverage for `spruce test --coverage`, which finds which parts of a program
+its tests ran. As the interpreter runs the tests, it counts how many times
+each statement, each expression that gives a body its value, and each
+function was reached. Each option of a case ran as many times as what it
+runs first did.
+
+The counts are then laid over the program's files, other than the prelude,
+and given either as lcov's tracefile, which genhtml and editors read:
+
+    SF:list.sp
+    FN:3,length
+    FNDA:4,length
+    BRDA:4,0,0,1
+    DA:4,4
+    ...
+    end_of_record
+
+or as the files themselves, each line with how many times it ran, and those
+that have something to run but never did marked out:
+
+        4 |     case list {
+    ##### |         Nil -> 0
+
+The tests themselves aren't counted, as they all run unless filtered out.
+*/
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::error::SpruceErr;
+use crate::eval::{self, Frame, Observer, Value};
+use crate::name_analysis as na;
+use crate::outline;
+use crate::parser::NodeInfo;
+use crate::source::{FileId, SourceMap};
+use crate::typecheck::{self, BodyNode, CaseBody, Stmt, StmtNode};
+
+/// Counts what a run reaches
+pub struct Coverage {
+    // how many times what starts at each place was reached
+    hits: HashMap<(FileId, usize), usize>,
+    calls: HashMap<na::SymbolID, usize>
+}
+
+/// How much of one file ran
+#[derive(Debug, PartialEq)]
+pub struct FileCoverage {
+    pub file: FileId,
+    // each line with something to run on it, counting from 1, and the most
+    // times anything on it ran
+    pub lines: BTreeMap<usize, usize>,
+    pub functions: Vec<FunctionCoverage>,
+    pub options: Vec<OptionCoverage>
+}
+
+#[derive(Debug, PartialEq)]
+pub struct FunctionCoverage {
+    pub name: String,
+    pub line: usize,
+    pub calls: usize
+}
+
+/// An option of a case, by the case's line and number in the file, and its
+/// own number in the case
+#[derive(Debug, PartialEq)]
+pub struct OptionCoverage {
+    pub line: usize,
+    pub case: usize,
+    pub option: usize,
+    // nothing if the case never ran
+    pub taken: Option<usize>
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage { hits: HashMap::new(), calls: HashMap::new() }
+    }
+
+    fn hits(&self, info: &NodeInfo) -> usize {
+        self.hits.get(&(info.file, info.span.start)).copied().unwrap_or(0)
+    }
+
+    /// How much of each of `files` ran, in that order
+    pub fn files(&self, prog: &na::Prog, typed: &typecheck::Prog, sources: &SourceMap, files: &[FileId]) -> Vec<FileCoverage> {
+        files.iter().map(|file| {
+            let mut covered = FileCoverage { file: *file, lines: BTreeMap::new(), functions: Vec::new(), options: Vec::new() };
+            for def in typed.definitions.iter().filter(|def| def.info.file == *file) {
+                self.stmt(sources, def, &mut covered);
+            }
+            for func in typed.functions.iter().filter(|func| func.info.file == *file && prog.test_name(&func.val.name).is_none()) {
+                let start = sources.get(*file).map_or(func.info.span.start, |source| outline::after_docs(&source.text, &func.info.span));
+                covered.functions.push(FunctionCoverage {
+                    name: String::from(eval::symbol_name(prog, func.val.name)),
+                    line: sources.line_col(*file, start).line,
+                    calls: self.calls.get(&func.val.name).copied().unwrap_or(0)
+                });
+                self.body(sources, &func.val.body, &mut covered);
+            }
+            covered.functions.sort_by_key(|func| func.line);
+            covered
+        }).collect()
+    }
+
+    /// Counts what starts at `info` as run on its line
+    fn point(&self, sources: &SourceMap, info: &NodeInfo, covered: &mut FileCoverage) {
+        ran(sources, info, self.hits(info), covered);
+    }
+
+    fn body(&self, sources: &SourceMap, body: &BodyNode, covered: &mut FileCoverage) {
+        for stmt in &body.val.stmts {
+            self.stmt(sources, stmt, covered);
+        }
+        if let Some(expr) = &body.val.expr {
+            self.point(sources, &expr.info, covered);
+        }
+    }
+
+    fn stmt(&self, sources: &SourceMap, stmt: &StmtNode, covered: &mut FileCoverage) {
+        self.point(sources, &stmt.info, covered);
+        let case = match &stmt.val {
+            Stmt::Case(case) => case,
+            Stmt::Assign(_, _) | Stmt::FnCall(_, _) => return
+        };
+        // cases are numbered in the order they're found
+        let number = covered.options.iter().map(|option| option.case + 1).max().unwrap_or(0);
+        let line = sources.line_col(stmt.info.file, stmt.info.span.start).line;
+        let case_ran = self.hits(&stmt.info) > 0;
+        for (i, option) in case.val.options.iter().enumerate() {
+            let first = match &option.val.body.val {
+                CaseBody::Expr(expr) => Some(&expr.info),
+                CaseBody::Body(body) => body.val.stmts.first().map(|stmt| &stmt.info).or(body.val.expr.as_ref().map(|expr| &expr.info))
+            };
+            let taken = first.map_or(0, |info| self.hits(info));
+            // so an option that never ran is marked out from its pattern on
+            ran(sources, &option.info, taken, covered);
+            covered.options.push(OptionCoverage {
+                line: line,
+                case: number,
+                option: i,
+                taken: if case_ran { Some(taken) } else { None }
+            });
+        }
+        for option in &case.val.options {
+            match &option.val.body.val {
+                CaseBody::Expr(expr) => self.point(sources, &expr.info, covered),
+                CaseBody::Body(body) => self.body(sources, body, covered)
+            }
+        }
+    }
+}
+
+/// Counts the line `info` starts on as run `hits` times, unless something
+/// else on it ran more
+fn ran(sources: &SourceMap, info: &NodeInfo, hits: usize, covered: &mut FileCoverage) {
+    let line = sources.line_col(info.file, info.span.start).line;
+    let most = covered.lines.entry(line).or_insert(0);
+    *most = (*most).max(hits);
+}
+
+impl FileCoverage {
+    /// How many lines, functions and options there are, and how many of each ran
+    fn counts(&self) -> [(usize, usize); 3] {
+        [
+            (self.lines.len(), self.lines.values().filter(|hits| **hits > 0).count()),
+            (self.functions.len(), self.functions.iter().filter(|func| func.calls > 0).count()),
+            (self.options.len(), self.options.iter().filter(|option| option.taken.map_or(false, |taken| taken > 0)).count())
+        ]
+    }
+
+    /// A line on how much of the file ran
+    pub fn summary(&self, sources: &SourceMap) -> String {
+        let [lines, functions, options] = self.counts();
+        format!("{}: {} of {} lines, {} of {} functions, {} of {} case options",
+            sources.name(self.file), lines.1, lines.0, functions.1, functions.0, options.1, options.0)
+    }
+
+    /// The file, each line with how many times it ran
+    pub fn annotate(&self, sources: &SourceMap) -> String {
+        let text = sources.get(self.file).map_or("", |source| source.text.as_str());
+        let mut out = String::new();
+        for (i, line) in text.lines().enumerate() {
+            let hits = match self.lines.get(&(i + 1)) {
+                Some(0) => String::from("#####"),
+                Some(hits) => hits.to_string(),
+                None => String::from("-")
+            };
+            out.push_str(&format!("{:>9} | {}\n", hits, line));
+        }
+        out
+    }
+}
+
+/// The coverage of `files` as an lcov tracefile
+pub fn lcov(files: &[FileCoverage], sources: &SourceMap) -> String {
+    let mut out = String::new();
+    for file in files {
+        out.push_str(&format!("TN:\nSF:{}\n", sources.name(file.file)));
+        for func in &file.functions {
+            out.push_str(&format!("FN:{},{}\n", func.line, func.name));
+        }
+        for func in &file.functions {
+            out.push_str(&format!("FNDA:{},{}\n", func.calls, func.name));
+        }
+        let [lines, functions, options] = file.counts();
+        out.push_str(&format!("FNF:{}\nFNH:{}\n", functions.0, functions.1));
+        for option in &file.options {
+            let taken = option.taken.map_or(String::from("-"), |taken| taken.to_string());
+            out.push_str(&format!("BRDA:{},{},{},{}\n", option.line, option.case, option.option, taken));
+        }
+        out.push_str(&format!("BRF:{}\nBRH:{}\n", options.0, options.1));
+        for (line, hits) in &file.lines {
+            out.push_str(&format!("DA:{},{}\n", line, hits));
+        }
+        out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", lines.0, lines.1));
+    }
+    out
+}
+
+impl Observer for Coverage {
+    fn enter(&mut self, _: &na::Prog, id: na::SymbolID, _: &NodeInfo) -> Result<(), SpruceErr> {
+        *self.calls.entry(id).or_insert(0) += 1;
+        Ok(())
+    }
+
+    fn leave(&mut self, _: &na::Prog, _: na::SymbolID, _: Option<&Value>) {}
+
+    fn reach(&mut self, _: &na::Prog, _: &Frame, info: &NodeInfo) -> Result<(), SpruceErr> {
+        *self.hits.entry((info.file, info.span.start)).or_insert(0) += 1;
+        Ok(())
+    }
+}
@@ -0,0 +1,163 @@
+/*
+Dead code elimination strips what the program can't use from the IR once it's
+been folded, so that the compiled targets only write what runs:
+
+  - functions and builtins that neither `main` nor the program's definitions
+    reach, which folding can add to by deciding the cases that called them
+  - program-level definitions nothing reads, other than to compute them
+  - lets whose variable is never used, when working out their value can't
+    fail or do anything else, so calls and cases always stay
+
+Removing one of these can leave another unused, so the pass repeats until
+there's nothing left to remove. What's removed is traced under
+`--verbose codegen`.
+*/
+
+use std::collections::HashSet;
+
+use crate::anf::{Atom, Block, Function, Prim, Program, Stmt, Value};
+use crate::eval;
+use crate::name_analysis as na;
+use crate::trace::Phase;
+
+/// Removes what the program can't use
+pub fn compile(prog: &na::Prog, program: &mut Program) {
+    let mut bindings = 0;
+    loop {
+        let mut changed = false;
+
+        let used = reachable(program);
+        let before = program.functions.len();
+        program.functions.retain(|func| {
+            let name = func.name.expect("functions are named");
+            if !used.contains(&name) {
+                trace!(Phase::Codegen, "removed '{}', which main doesn't reach", eval::symbol_name(prog, name));
+            }
+            used.contains(&name)
+        });
+        program.builtins.retain(|(id, _)| used.contains(id));
+        changed |= program.functions.len() != before;
+
+        let read = globals_read(program);
+        for id in program.globals.iter().filter(|id| !read.contains(id)) {
+            trace!(Phase::Codegen, "removed '{}', which nothing reads", eval::symbol_name(prog, *id));
+            changed = true;
+        }
+        program.globals.retain(|id| read.contains(id));
+        for func in program.functions.iter_mut().chain(std::iter::once(&mut program.init)) {
+            let removed = unused_bindings(func, &read);
+            bindings += removed;
+            changed |= removed > 0;
+        }
+
+        if !changed {
+            break;
+        }
+    }
+    trace!(Phase::Codegen, "removed {} bindings nothing used", bindings);
+}
+
+/// The functions and builtins named from the entry point or the program's
+/// definitions, directly or not
+fn reachable(program: &Program) -> HashSet<na::SymbolID> {
+    let mut used = HashSet::new();
+    let mut work = vec![program.entry];
+    refs(&program.init.body, &mut work);
+    while let Some(id) = work.pop() {
+        if !used.insert(id) {
+            continue;
+        }
+        if let Some(func) = program.functions.iter().find(|func| func.name == Some(id)) {
+            refs(&func.body, &mut work);
+        }
+    }
+    used
+}
+
+/// The functions a block calls or uses as values
+fn refs(body: &Block, found: &mut Vec<na::SymbolID>) {
+    body.each_atom(&mut |atom| if let Atom::Func(id) = atom {
+        found.push(*id);
+    });
+    each_value(body, &mut |val| if let Value::Call(id, _) = val {
+        found.push(*id);
+    });
+}
+
+fn globals_read(program: &Program) -> HashSet<na::SymbolID> {
+    let mut read = HashSet::new();
+    for func in program.functions.iter().chain(std::iter::once(&program.init)) {
+        each_value(&func.body, &mut |val| if let Value::Global(id) = val {
+            read.insert(*id);
+        });
+    }
+    read
+}
+
+/// Calls `f` with every value in a block, including those in its cases
+fn each_value(body: &Block, f: &mut impl FnMut(&Value)) {
+    let vals = body.stmts.iter().filter_map(|stmt| match stmt {
+        Stmt::Let(_, val) => Some(val),
+        Stmt::SetGlobal(_, _) => None
+    });
+    for val in vals.chain(std::iter::once(&body.result)) {
+        f(val);
+        if let Value::Case(case) = val {
+            for arm in &case.arms {
+                each_value(&arm.body, f);
+            }
+            if let Some(default) = &case.default {
+                each_value(default, f);
+            }
+        }
+    }
+}
+
+/// Removes the lets nothing uses and updates to globals nothing reads,
+/// giving how many were removed
+fn unused_bindings(func: &mut Function, read: &HashSet<na::SymbolID>) -> usize {
+    let mut removed = 0;
+    loop {
+        let uses = func.uses();
+        let count = remove_unused(&mut func.body, &uses, read);
+        if count == 0 {
+            return removed;
+        }
+        removed += count;
+    }
+}
+
+fn remove_unused(body: &mut Block, uses: &[usize], read: &HashSet<na::SymbolID>) -> usize {
+    let before = body.stmts.len();
+    body.stmts.retain(|stmt| match stmt {
+        Stmt::Let(var, val) => uses[*var as usize] > 0 || !pure(val),
+        Stmt::SetGlobal(id, _) => read.contains(id)
+    });
+    let mut removed = before - body.stmts.len();
+    let vals = body.stmts.iter_mut().filter_map(|stmt| match stmt {
+        Stmt::Let(_, val) => Some(val),
+        Stmt::SetGlobal(_, _) => None
+    });
+    for val in vals.chain(std::iter::once(&mut body.result)) {
+        if let Value::Case(case) = val {
+            for arm in &mut case.arms {
+                removed += remove_unused(&mut arm.body, uses, read);
+            }
+            if let Some(default) = &mut case.default {
+                removed += remove_unused(default, uses, read);
+            }
+        }
+    }
+    removed
+}
+
+/// Whether working out a value only gives the value
+fn pure(val: &Value) -> bool {
+    match val {
+        Value::Atom(_) | Value::Global(_) | Value::Construct(_, _) | Value::Field(_, _) => true,
+        Value::Prim(Prim::Div, atoms, _) | Value::Prim(Prim::Mod, atoms, _) => matches!(atoms[1], Atom::Int(n) if n != 0),
+        Value::Prim(Prim::Pow, atoms, _) => matches!(atoms[1], Atom::Int(n) if n >= 0),
+        Value::Prim(_, _, _) => true,
+        Value::Call(_, _) | Value::Apply(_, _) | Value::Case(_) | Value::Recur(_) => false
+    }
+}
@@ -0,0 +1,322 @@
+/*
+The debugger behind `spruce run --debug`, which runs a program in the
+interpreter and stops it to look around. It reads commands before the
+program starts and whenever it stops:
+
+    break <function> | <file>:<line>    stop when the function is called, or
+                                        when the run gets to the line
+    delete <n>                          forget breakpoint n
+    info                                list the breakpoints
+    continue                            run until a breakpoint
+    step                                run until the next statement, going
+                                        into calls
+    next                                run until the next statement of this
+                                        call or one it returns to
+    finish                              run until this call returns, showing
+                                        what it gave
+    locals                              the call's variables with their types
+    print <name>                        one of them
+    backtrace                           the calls the run is in, innermost
+                                        first, with where each has got to
+    quit                                stop the program
+
+Each command can be given by its first letter, and `bt` is backtrace. The
+program's definitions run before `main`, outside of any call, and stop like
+the rest of it. A call in tail position takes the place of the call it's
+in, so it takes its place in the backtrace as well.
+*/
+
+use std::io::{BufRead, Write};
+
+use crate::error::SpruceErr;
+use crate::eval::{self, Frame, Observer, Value};
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+use crate::source::{FileId, SourceMap};
+use crate::typecheck::Environment;
+
+const HELP: &str = "commands: break <function>|<file>:<line>, delete <n>, breakpoints, continue, step, next, finish, locals, print <name>, backtrace, quit";
+
+#[derive(Debug, PartialEq)]
+enum Breakpoint {
+    Function(String),
+    // a file, by its name, and a line in it, counting from 1
+    Line(FileId, usize)
+}
+
+/// How far to let the program run before stopping it again, other than at a
+/// breakpoint
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Resume {
+    Continue,
+    Step,
+    // until a statement no more calls deep than this
+    Next(usize),
+    // until the call at this depth returns
+    Finish(usize)
+}
+
+/// A call the run is in
+struct Call {
+    id: na::SymbolID,
+    // where it was called from
+    site: NodeInfo
+}
+
+pub struct Debugger<'a, R: BufRead, W: Write> {
+    prog: &'a na::Prog,
+    env: &'a Environment,
+    sources: &'a SourceMap,
+    input: R,
+    output: W,
+    // deleted breakpoints leave a gap, so the others keep their numbers
+    breakpoints: Vec<Option<Breakpoint>>,
+    stack: Vec<Call>,
+    resume: Resume,
+    // the breakpoint of a function just called, which stops the run at what
+    // it runs first
+    entered: Option<usize>,
+    // how many calls deep and on which line the run last was, so a run only
+    // stops at a line's breakpoint as it gets to the line
+    last: Option<(usize, FileId, usize)>,
+    // the input ran out, so the program runs on without stopping
+    detached: bool,
+    pub quit: bool
+}
+
+impl<'a, R: BufRead, W: Write> Debugger<'a, R, W> {
+    /// A debugger reading commands from `input` and writing to `output`
+    pub fn new(prog: &'a na::Prog, env: &'a Environment, sources: &'a SourceMap, input: R, output: W) -> Self {
+        Debugger {
+            prog: prog,
+            env: env,
+            sources: sources,
+            input: input,
+            output: output,
+            breakpoints: Vec::new(),
+            stack: Vec::new(),
+            resume: Resume::Continue,
+            entered: None,
+            last: None,
+            detached: false,
+            quit: false
+        }
+    }
+
+    /// Adds a breakpoint on a function by its name, or on a line as
+    /// `file:line`, giving what was added
+    pub fn add_breakpoint(&mut self, spec: &str) -> Result<String, String> {
+        let breakpoint = match spec.rsplit_once(':') {
+            Some((file, line)) => {
+                let line: usize = line.parse().map_err(|_| format!("'{}' isn't a line number", line))?;
+                let named = |name: &str| name == file || name.ends_with(&format!("/{}", file));
+                let id = self.sources.files()
+                    .find(|(_, source)| named(&source.name))
+                    .map(|(id, _)| id)
+                    .ok_or_else(|| format!("there's no file '{}' in the program", file))?;
+                if line == 0 || line > self.sources.get(id).map_or(0, |source| source.lines.line_count()) {
+                    return Err(format!("{} has no line {}", file, line));
+                }
+                Breakpoint::Line(id, line)
+            }
+            None if self.prog.functions.iter().any(|func| eval::symbol_name(self.prog, func.val.name) == spec) => Breakpoint::Function(String::from(spec)),
+            None => return Err(format!("there's no function '{}'", spec))
+        };
+        self.breakpoints.push(Some(breakpoint));
+        Ok(format!("breakpoint {} at {}", self.breakpoints.len(), self.describe(self.breakpoints.len() - 1)))
+    }
+
+    fn describe(&self, n: usize) -> String {
+        match &self.breakpoints[n] {
+            Some(Breakpoint::Function(name)) => name.clone(),
+            Some(Breakpoint::Line(file, line)) => format!("{}:{}", self.sources.name(*file), line),
+            None => String::new()
+        }
+    }
+
+    /// Reads commands before the program starts, until one runs it. False
+    /// if the program shouldn't run at all
+    pub fn start(&mut self) -> bool {
+        self.prompt(None).is_ok()
+    }
+
+    fn say(&mut self, text: &str) {
+        // what the debugger says is only for whoever's debugging, so there's
+        // nothing to do if it can't be written
+        let _ = writeln!(self.output, "{}", text);
+    }
+
+    /// Where `info` is, as the function it's in, and file:line:col
+    fn location(&self, info: &NodeInfo) -> String {
+        let pos = self.sources.line_col(info.file, info.span.start);
+        format!("{}:{}:{}", self.sources.name(info.file), pos.line, pos.col)
+    }
+
+    fn function(&self, call: Option<&Call>) -> String {
+        match call {
+            Some(call) => String::from(eval::symbol_name(self.prog, call.id)),
+            None => String::from("the program's definitions")
+        }
+    }
+
+    /// Stops the run at `info`, for `reason`, and reads commands until one
+    /// lets it go on
+    fn stop(&mut self, frame: &Frame, info: &NodeInfo, reason: &str) -> Result<(), SpruceErr> {
+        let here = format!("{}in {} at {}", reason, self.function(self.stack.last()), self.location(info));
+        self.say(&here);
+        if let Some(source) = self.sources.get(info.file) {
+            let line = source.lines.line_of(info.span.start);
+            let (start, end) = source.lines.line_range(&source.text, line);
+            let text = format!("{:>5} | {}", line + 1, &source.text[start..end]);
+            self.say(&text);
+        }
+        self.prompt(Some((frame, info)))
+    }
+
+    fn prompt(&mut self, stopped: Option<(&Frame, &NodeInfo)>) -> Result<(), SpruceErr> {
+        loop {
+            let _ = write!(self.output, "(spruce) ");
+            let _ = self.output.flush();
+            let mut line = String::new();
+            if self.input.read_line(&mut line).map_or(true, |read| read == 0) {
+                self.detached = true;
+                return Ok(());
+            }
+            let mut words = line.split_whitespace();
+            let (command, arg) = (words.next().unwrap_or(""), words.next());
+            match (command, arg, stopped) {
+                ("", _, _) => (),
+                ("c" | "continue", _, _) => {
+                    self.resume = Resume::Continue;
+                    return Ok(());
+                }
+                ("s" | "step", _, _) => {
+                    self.resume = Resume::Step;
+                    return Ok(());
+                }
+                ("n" | "next", _, _) => {
+                    self.resume = Resume::Next(self.stack.len());
+                    return Ok(());
+                }
+                ("f" | "finish", _, Some(_)) if !self.stack.is_empty() => {
+                    self.resume = Resume::Finish(self.stack.len());
+                    return Ok(());
+                }
+                ("f" | "finish", _, Some(_)) => self.say("the definitions aren't in a call, so there's nothing to finish"),
+                ("b" | "break", Some(spec), _) => {
+                    let added = self.add_breakpoint(spec).unwrap_or_else(|message| message);
+                    self.say(&added);
+                }
+                ("d" | "delete", Some(n), _) => match n.parse::<usize>().ok().filter(|n| *n > 0 && self.breakpoints.get(n - 1).map_or(false, Option::is_some)) {
+                    Some(n) => {
+                        let deleted = format!("deleted breakpoint {} at {}", n, self.describe(n - 1));
+                        self.breakpoints[n - 1] = None;
+                        self.say(&deleted);
+                    }
+                    None => self.say(&format!("there's no breakpoint {}", n))
+                },
+                ("i" | "info" | "breakpoints", _, _) => {
+                    let listed: Vec<String> = (0..self.breakpoints.len())
+                        .filter(|n| self.breakpoints[*n].is_some())
+                        .map(|n| format!("{}: {}", n + 1, self.describe(n)))
+                        .collect();
+                    if listed.is_empty() {
+                        self.say("there are no breakpoints");
+                    }
+                    for line in listed {
+                        self.say(&line);
+                    }
+                }
+                ("l" | "locals", _, Some((frame, _))) => {
+                    let mut locals: Vec<(&str, String)> = frame.iter().map(|(id, value)| (eval::symbol_name(self.prog, *id), self.binding(*id, value))).collect();
+                    locals.sort();
+                    if locals.is_empty() {
+                        self.say("there are no locals here");
+                    }
+                    for (_, local) in locals {
+                        self.say(&local);
+                    }
+                }
+                ("p" | "print", Some(name), Some((frame, _))) => {
+                    let found = frame.iter().find(|(id, _)| eval::symbol_name(self.prog, **id) == name).map(|(id, value)| self.binding(*id, value));
+                    self.say(&found.unwrap_or_else(|| format!("there's no local '{}' here", name)));
+                }
+                ("bt" | "backtrace", _, Some((_, info))) => {
+                    let mut lines = vec![format!("#0 {} at {}", self.function(self.stack.last()), self.location(info))];
+                    // each call is at the place it called the one inside it from
+                    for (i, call) in self.stack.iter().enumerate().rev().skip(1) {
+                        let inner = &self.stack[i + 1];
+                        lines.push(format!("#{} {} at {}", lines.len(), self.function(Some(call)), self.location(&inner.site)));
+                    }
+                    for line in lines {
+                        self.say(&line);
+                    }
+                }
+                ("q" | "quit", _, _) => {
+                    self.quit = true;
+                    let info = stopped.map_or_else(eval::no_info, |(_, info)| info.clone());
+                    return Err(SpruceErr::new(String::from("the debugger stopped the program"), info));
+                }
+                ("h" | "help", _, _) => self.say(HELP),
+                ("f" | "finish" | "l" | "locals" | "p" | "print" | "bt" | "backtrace", _, None) => self.say("the program hasn't started yet"),
+                _ => self.say(&format!("I don't know '{}'. {}", line.trim(), HELP))
+            }
+        }
+    }
+
+    /// A variable as `name : type = value`
+    fn binding(&self, id: na::SymbolID, value: &Value) -> String {
+        let name = eval::symbol_name(self.prog, id);
+        match self.env.type_of_id(id) {
+            Some(ty) => format!("{} : {} = {}", name, ty, eval::show(self.prog, value)),
+            None => format!("{} = {}", name, eval::show(self.prog, value))
+        }
+    }
+}
+
+impl<'a, R: BufRead, W: Write> Observer for Debugger<'a, R, W> {
+    fn enter(&mut self, prog: &na::Prog, id: na::SymbolID, info: &NodeInfo) -> Result<(), SpruceErr> {
+        self.stack.push(Call { id: id, site: info.clone() });
+        let name = eval::symbol_name(prog, id);
+        if let Some(n) = self.breakpoints.iter().position(|breakpoint| breakpoint.as_ref() == Some(&Breakpoint::Function(String::from(name)))) {
+            self.entered = Some(n);
+        }
+        Ok(())
+    }
+
+    fn leave(&mut self, prog: &na::Prog, id: na::SymbolID, result: Option<&Value>) {
+        self.stack.pop();
+        if let (Resume::Finish(depth), Some(value), false) = (self.resume, result, self.detached) {
+            if self.stack.len() < depth {
+                let returned = format!("{} returned {}", eval::symbol_name(prog, id), eval::show(prog, value));
+                self.say(&returned);
+                self.resume = Resume::Step;
+            }
+        }
+    }
+
+    fn reach(&mut self, _: &na::Prog, frame: &Frame, info: &NodeInfo) -> Result<(), SpruceErr> {
+        let depth = self.stack.len();
+        let here = (depth, info.file, self.sources.line_col(info.file, info.span.start).line);
+        let arrived = self.last != Some(here);
+        self.last = Some(here);
+        if self.detached {
+            return Ok(());
+        }
+
+        let hit = self.entered.take().or_else(|| {
+            let line = Breakpoint::Line(here.1, here.2);
+            self.breakpoints.iter().position(|breakpoint| arrived && breakpoint.as_ref() == Some(&line))
+        });
+        let stepped = match self.resume {
+            Resume::Continue | Resume::Finish(_) => false,
+            Resume::Step => true,
+            Resume::Next(depth_then) => depth <= depth_then
+        };
+        match hit {
+            Some(n) => self.stop(frame, info, &format!("breakpoint {}, ", n + 1)),
+            None if stepped => self.stop(frame, info, ""),
+            None => Ok(())
+        }
+    }
+}
@@ -0,0 +1,98 @@
+/*
+Cases are compiled to decision trees once they're in A-normal form, so that
+the backends only have to write one switch per case. Spruce's patterns are
+flat, a constructor with names for its fields or a number, so every tree is a
+single switch on the scrutinee's tag or value, and what's left to decide is
+which tests it needs at all:
+
+  - when the arms cover every constructor of the type, the last one is
+    tested by elimination, becoming the default, and a default after them is
+    dropped since no value reaches it
+  - a case left with nothing to test is just its default, which takes the
+    case's place
+  - fields are only loaded for the arms that use them
+
+The arms stay in the order they were written, and the backends are free to
+order the tests however suits them.
+*/
+
+use std::collections::HashSet;
+
+use crate::anf::{Atom, Block, Case, Function, Pattern, Program, Stmt, Value};
+use crate::name_analysis as na;
+
+/// Compiles every case in the program
+pub fn compile(prog: &na::Prog, program: &mut Program) {
+    for func in program.functions.iter_mut().chain(std::iter::once(&mut program.init)) {
+        function(prog, func);
+    }
+}
+
+fn function(prog: &na::Prog, func: &mut Function) {
+    let uses = func.uses();
+    block(prog, &uses, &mut func.body);
+}
+
+fn block(prog: &na::Prog, uses: &[usize], body: &mut Block) {
+    let mut stmts = Vec::new();
+    for stmt in body.stmts.drain(..) {
+        match stmt {
+            // a field nothing uses isn't loaded
+            Stmt::Let(var, Value::Field(_, _)) if uses[var as usize] == 0 => (),
+            Stmt::Let(var, Value::Case(case)) => match switch(prog, uses, *case) {
+                Ok(case) => stmts.push(Stmt::Let(var, Value::Case(Box::new(case)))),
+                Err(inner) => {
+                    stmts.extend(inner.stmts);
+                    stmts.push(Stmt::Let(var, inner.result));
+                }
+            },
+            stmt => stmts.push(stmt)
+        }
+    }
+    body.stmts = stmts;
+
+    let result = std::mem::replace(&mut body.result, Value::Atom(Atom::Unit));
+    body.result = match result {
+        Value::Case(case) => match switch(prog, uses, *case) {
+            Ok(case) => Value::Case(Box::new(case)),
+            Err(inner) => {
+                body.stmts.extend(inner.stmts);
+                inner.result
+            }
+        },
+        result => result
+    };
+}
+
+/// The switch a case compiles to, or the block it is when it has nothing
+/// left to test
+fn switch(prog: &na::Prog, uses: &[usize], mut case: Case) -> Result<Case, Block> {
+    for arm in &mut case.arms {
+        block(prog, uses, &mut arm.body);
+    }
+    if let Some(default) = &mut case.default {
+        block(prog, uses, default);
+    }
+
+    if covers_type(prog, &case) {
+        let last = case.arms.pop().expect("a covered type has a constructor");
+        case.default = Some(last.body);
+    }
+    match case.default {
+        Some(default) if case.arms.is_empty() => Err(default),
+        _ => Ok(case)
+    }
+}
+
+/// Whether the arms test for every constructor of the scrutinee's type
+fn covers_type(prog: &na::Prog, case: &Case) -> bool {
+    let tested: HashSet<na::ADTValID> = case.arms.iter().filter_map(|arm| match &arm.pattern {
+        Pattern::Constructor(id) => Some(*id),
+        Pattern::Int(_) => None
+    }).collect();
+    let data_type = match tested.iter().next() {
+        Some(id) => prog.type_table.values[id].data_type,
+        None => return false
+    };
+    prog.type_table.values.values().filter(|value| value.data_type == data_type).all(|value| tested.contains(&value.id))
+}
@@ -0,0 +1,289 @@
+/*
+`spruce doc` writes documentation for a program: a page for each of its
+modules, with what the module exports, and an index of the modules. A
+function or constant is shown with the type inferred for it, a type with its
+constructors and what each takes, and either with the doc comment on it. Wherever a type declared in one of the modules appears
+in another's types, it links to where that type is documented.
+
+The pages are HTML or Markdown. Markdown can't link from inside code, so types
+there are plain text, with the names of the program's types as links.
+*/
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::name_analysis::{self as na, SymbolID};
+use crate::source::SourceMap;
+use crate::typecheck::{Environment, TypeRepr};
+
+/// What the pages are written in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Format {
+    Html,
+    Markdown
+}
+
+impl FromStr for Format {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        match name {
+            "html" => Ok(Format::Html),
+            "markdown" | "md" => Ok(Format::Markdown),
+            _ => Err(())
+        }
+    }
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Html => "html",
+            Format::Markdown => "md"
+        }
+    }
+}
+
+/// A page of documentation, and the name of the file it's written to
+#[derive(Debug, PartialEq)]
+pub struct Page {
+    pub file: String,
+    pub text: String
+}
+
+/// Part of a type as it's shown, which is either text or the name of a type
+/// documented in the named module
+#[derive(Debug, PartialEq, Clone)]
+enum Piece {
+    Text(String),
+    Link(String, String)
+}
+
+/// A declaration a module exports
+struct Entry {
+    name: String,
+    // `type` for a type, or nothing for a function or constant
+    keyword: &'static str,
+    ty: Vec<Piece>,
+    doc: Option<String>,
+    constructors: Vec<(String, Vec<Piece>)>
+}
+
+/// The pages documenting each module of `prog` other than the prelude and
+/// those read from interfaces, followed by the index
+pub fn document(prog: &na::Prog, env: &Environment, sources: &SourceMap, format: Format) -> Vec<Page> {
+    let prelude = sources.file_id(na::PRELUDE_FILE);
+    let modules: Vec<&na::Module> = prog.modules.iter().filter(|module| !module.interface && Some(module.file) != prelude).collect();
+
+    // each type that's documented, by name, with the module it's in
+    let mut links: HashMap<String, String> = HashMap::new();
+    for adt in prog.type_table.types.values() {
+        if let Some(module) = modules.iter().find(|module| module.name == adt.module && module.exports(&adt.name)) {
+            links.entry(adt.name.clone()).or_insert_with(|| module.name.clone());
+        }
+    }
+
+    let mut pages: Vec<Page> = modules.iter().map(|module| {
+        let entries = entries(prog, env, module, &links);
+        let text = match format {
+            Format::Html => html_page(&module.name, &entries),
+            Format::Markdown => markdown_page(&module.name, &entries)
+        };
+        Page { file: format!("{}.{}", module.name, format.extension()), text: text }
+    }).collect();
+
+    let names: Vec<&str> = modules.iter().map(|module| module.name.as_str()).collect();
+    let index = match format {
+        Format::Html => html_index(&names),
+        Format::Markdown => markdown_index(&names)
+    };
+    pages.push(Page { file: format!("index.{}", format.extension()), text: index });
+    pages
+}
+
+/// What `module` exports, in the order it's declared
+fn entries(prog: &na::Prog, env: &Environment, module: &na::Module, links: &HashMap<String, String>) -> Vec<Entry> {
+    let mut found: Vec<(usize, Entry)> = Vec::new();
+    let symbol_name = |id: SymbolID| prog.symbol_table.lookup_id(&id).map_or(String::new(), |sym| sym.name.clone());
+
+    let mut symbols: Vec<(usize, SymbolID, Option<&String>)> = prog.functions.iter()
+        .filter(|func| func.info.file == module.file && prog.test_name(&func.val.name).is_none())
+        .map(|func| (func.info.span.start, func.val.name, func.val.doc.as_ref()))
+        .collect();
+    symbols.extend(prog.definitions.iter().filter(|stmt| stmt.info.file == module.file).filter_map(|stmt| match &stmt.val {
+        na::Stmt::Assign(target, _) => Some((stmt.info.span.start, target.val.id(), None)),
+        _ => None
+    }));
+    for (start, id, doc) in symbols {
+        let name = symbol_name(id);
+        if let (true, Some(ty)) = (module.exports(&name), env.type_of_id(id)) {
+            found.push((start, Entry { name: name, keyword: "", ty: pieces(&ty, links), doc: doc.cloned(), constructors: Vec::new() }));
+        }
+    }
+
+    for t in prog.types.iter().filter(|t| t.info.file == module.file && module.exports(&t.val.name)) {
+        let adt = match prog.type_table.types.values().find(|adt| adt.name == t.val.name && adt.module == module.name) {
+            Some(adt) => adt,
+            None => continue
+        };
+        let params: Vec<String> = adt.type_params.iter().filter_map(|id| prog.type_table.type_params.get(id)).map(|param| param.name.clone()).collect();
+        let mut ty = vec![Piece::Text(adt.name.clone())];
+        if !params.is_empty() {
+            ty.push(Piece::Text(format!("({})", params.join(", "))));
+        }
+
+        let constructors = if module.exports_constructors(&adt.name) {
+            t.val.options.iter().filter_map(|option| {
+                let val = prog.type_table.values.values().find(|val| val.name == option.val.name && val.module == module.name)?;
+                Some((val.name.clone(), pieces(&env.type_of_value(val.id)?, links)))
+            }).collect()
+        }
+        else {
+            Vec::new()
+        };
+        found.push((t.info.span.start, Entry { name: adt.name.clone(), keyword: "type", ty: ty, doc: t.val.doc.clone(), constructors: constructors }));
+    }
+
+    found.sort_by_key(|(start, _)| *start);
+    found.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// A type as it's written, with the types that are documented as links
+fn pieces(ty: &TypeRepr, links: &HashMap<String, String>) -> Vec<Piece> {
+    let mut found = Vec::new();
+    write_pieces(ty, links, &mut found);
+
+    // neighbouring text is joined, so it's written as one
+    let mut joined: Vec<Piece> = Vec::new();
+    for piece in found {
+        match (joined.last_mut(), piece) {
+            (Some(Piece::Text(last)), Piece::Text(text)) => last.push_str(&text),
+            (_, piece) => joined.push(piece)
+        }
+    }
+    joined
+}
+
+fn write_pieces(ty: &TypeRepr, links: &HashMap<String, String>, found: &mut Vec<Piece>) {
+    let list = |types: &[TypeRepr], found: &mut Vec<Piece>| {
+        for (i, ty) in types.iter().enumerate() {
+            if i > 0 {
+                found.push(Piece::Text(String::from(", ")));
+            }
+            write_pieces(ty, links, found);
+        }
+    };
+    match ty {
+        TypeRepr::ADT(name, args) => {
+            match links.get(name) {
+                Some(module) => found.push(Piece::Link(name.clone(), module.clone())),
+                None => found.push(Piece::Text(name.clone()))
+            }
+            if !args.is_empty() {
+                found.push(Piece::Text(String::from("(")));
+                list(args, found);
+                found.push(Piece::Text(String::from(")")));
+            }
+        }
+        TypeRepr::Func(args, out) => {
+            found.push(Piece::Text(String::from("(")));
+            list(args, found);
+            found.push(Piece::Text(String::from(") -> ")));
+            write_pieces(out, links, found);
+        }
+        ty => found.push(Piece::Text(ty.to_string()))
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+fn html_type(ty: &[Piece]) -> String {
+    ty.iter().map(|piece| match piece {
+        Piece::Text(text) => escape(text),
+        Piece::Link(name, module) => format!("<a href=\"{}.html#{}\">{}</a>", escape(module), escape(name), escape(name))
+    }).collect()
+}
+
+/// A doc comment's paragraphs, which are separated by blank lines
+fn paragraphs(doc: &str) -> Vec<String> {
+    doc.split("\n\n").map(|paragraph| paragraph.trim().to_string()).filter(|paragraph| !paragraph.is_empty()).collect()
+}
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 50em; margin: 2em auto; } \
+code { font-family: monospace; } section { margin-bottom: 2em; } h2 { font-size: 1.1em; }";
+
+fn html_document(title: &str, body: &str) -> String {
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n", escape(title), STYLE, body)
+}
+
+fn html_page(module: &str, entries: &[Entry]) -> String {
+    let mut body = format!("<p><a href=\"index.html\">index</a></p>\n<h1>module {}</h1>\n", escape(module));
+    for entry in entries {
+        let header = match entry.keyword {
+            "" => format!("{} : {}", escape(&entry.name), html_type(&entry.ty)),
+            keyword => format!("{} {}", keyword, html_type(&entry.ty))
+        };
+        body.push_str(&format!("<section id=\"{}\">\n<h2><code>{}</code></h2>\n", escape(&entry.name), header));
+        for paragraph in entry.doc.iter().flat_map(|doc| paragraphs(doc)) {
+            body.push_str(&format!("<p>{}</p>\n", escape(&paragraph)));
+        }
+        if !entry.constructors.is_empty() {
+            body.push_str("<ul>\n");
+            for (name, ty) in &entry.constructors {
+                body.push_str(&format!("<li><code>{} : {}</code></li>\n", escape(name), html_type(ty)));
+            }
+            body.push_str("</ul>\n");
+        }
+        body.push_str("</section>\n");
+    }
+    html_document(&format!("module {}", module), &body)
+}
+
+fn html_index(modules: &[&str]) -> String {
+    let mut body = String::from("<h1>Modules</h1>\n<ul>\n");
+    for module in modules {
+        body.push_str(&format!("<li><a href=\"{}.html\">{}</a></li>\n", escape(module), escape(module)));
+    }
+    body.push_str("</ul>\n");
+    html_document("Modules", &body)
+}
+
+fn markdown_type(ty: &[Piece]) -> String {
+    ty.iter().map(|piece| match piece {
+        Piece::Text(text) => text.clone(),
+        Piece::Link(name, module) => format!("[{}]({}.md#{})", name, module, name)
+    }).collect()
+}
+
+fn markdown_page(module: &str, entries: &[Entry]) -> String {
+    let mut page = format!("[index](index.md)\n\n# module {}\n", module);
+    for entry in entries {
+        // the anchor is spelled out, since headings are given different
+        // ones by different renderers
+        page.push_str(&format!("\n<a id=\"{}\"></a>\n\n## {}\n\n", entry.name, entry.name));
+        match entry.keyword {
+            "" => page.push_str(&format!("{} : {}\n", entry.name, markdown_type(&entry.ty))),
+            keyword => page.push_str(&format!("{} {}\n", keyword, markdown_type(&entry.ty)))
+        }
+        for paragraph in entry.doc.iter().flat_map(|doc| paragraphs(doc)) {
+            page.push_str(&format!("\n{}\n", paragraph));
+        }
+        if !entry.constructors.is_empty() {
+            page.push('\n');
+            for (name, ty) in &entry.constructors {
+                page.push_str(&format!("- {} : {}\n", name, markdown_type(ty)));
+            }
+        }
+    }
+    page
+}
+
+fn markdown_index(modules: &[&str]) -> String {
+    let mut page = String::from("# Modules\n\n");
+    for module in modules {
+        page.push_str(&format!("- [{}]({}.md)\n", module, module));
+    }
+    page
+}
@@ -0,0 +1,360 @@
+/*
+The embedding API lets a Rust program compile and run Spruce programs with
+builtins of its own, its host functions, alongside the prelude's. A host
+function is registered on a Compiler by name, with its type and the closure
+that implements it:
+
+  - the Compiler declares it as a builtin at the end of the prelude, so name
+    analysis and type checking see an ordinary symbol that every module can
+    use, and a program that uses it wrongly fails to compile the way it would
+    for any other builtin
+  - the Runtime it gives runs the closure whenever the program calls the
+    builtin, with either the interpreter or the VM, and an error the closure
+    gives fails the run at the call
+
+The compiled targets know nothing of the host, so a program using a host
+function can only be run, not built.
+
+Values cross between Rust and Spruce through conversions. Ints, Floats,
+Strings and () are the same whatever the program, and convert with `From`
+and `TryFrom`. Anything else is a constructor, whose id depends on the
+program, so Bools, Options, Vecs and structs convert with ToValue and
+FromValue, given the program. A struct declared with `marshal_struct!` is
+the constructor of the same name, with its fields in order.
+*/
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::error::SpruceErr;
+use crate::eval::{self, Runtime};
+use crate::lint::Lints;
+use crate::heap;
+use crate::name_analysis::{self as na, PRELUDE, PRELUDE_FILE};
+use crate::registry::LangValue;
+use crate::source::SourceMap;
+use crate::typecheck;
+
+pub use crate::eval::Value;
+
+/// What a host function is implemented by, which gets the program calling it
+/// and the arguments it was called with, and gives its result, or why it
+/// failed
+pub type HostFn = dyn Fn(&na::Prog, &[Value]) -> Result<Value, String>;
+
+/// The type of a host function, as the prelude would write it
+#[derive(Debug, PartialEq, Clone)]
+pub enum Type {
+    Int,
+    Float,
+    String,
+    Bool,
+    Unit,
+    // a type variable, such as the `a` of `(a) -> a`
+    Var(String),
+    List(Box<Type>),
+    Maybe(Box<Type>),
+    Result(Box<Type>, Box<Type>),
+    // any other type the prelude declares, with its arguments
+    Named(String, Vec<Type>),
+    Func(Vec<Type>, Box<Type>)
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let list = |types: &[Type]| types.iter().map(|ty| ty.to_string()).collect::<Vec<String>>().join(", ");
+        match self {
+            Type::Int => write!(f, "Int"),
+            Type::Float => write!(f, "Float"),
+            Type::String => write!(f, "String"),
+            Type::Bool => write!(f, "Bool"),
+            Type::Unit => write!(f, "()"),
+            Type::Var(name) => write!(f, "{}", name),
+            Type::List(ty) => write!(f, "List({})", ty),
+            Type::Maybe(ty) => write!(f, "Maybe({})", ty),
+            Type::Result(ok, err) => write!(f, "Result({}, {})", ok, err),
+            Type::Named(name, args) if args.is_empty() => write!(f, "{}", name),
+            Type::Named(name, args) => write!(f, "{}({})", name, list(args)),
+            Type::Func(args, ret) => write!(f, "({}) -> {}", list(args), ret)
+        }
+    }
+}
+
+/// Compiles programs that can call the host functions registered with it
+#[derive(Default)]
+pub struct Compiler {
+    host: Vec<(String, Type, Rc<HostFn>)>
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler { host: Vec::new() }
+    }
+
+    /// Makes `func` a builtin the programs this compiles can call as `name`,
+    /// where `ty` is a function type
+    pub fn register_fn<F>(&mut self, name: &str, ty: Type, func: F) -> &mut Self
+    where F: Fn(&na::Prog, &[Value]) -> Result<Value, String> + 'static {
+        self.host.push((String::from(name), ty, Rc::new(func)));
+        self
+    }
+
+    /// The prelude with a builtin declared for each host function
+    pub fn prelude(&self) -> String {
+        let mut prelude = String::from(PRELUDE);
+        for (name, ty, _) in &self.host {
+            prelude.push_str(&format!("\nbuiltin {} : {}\n", name, ty));
+        }
+        prelude
+    }
+
+    /// Compiles files given as (text, name) pairs along with the prelude
+    pub fn compile(&self, files: Vec<(&str, String)>) -> Result<(na::Prog, typecheck::Prog, typecheck::Environment), Vec<SpruceErr>> {
+        let mut sources = SourceMap::new();
+        sources.add(PRELUDE_FILE, &self.prelude());
+        for (text, name) in &files {
+            sources.add(name, text);
+        }
+        crate::compile_with_lints(&sources, &mut Lints::new())
+    }
+
+    /// The runtime to run a program this compiled with, which calls the host
+    /// functions for their builtins
+    pub fn runtime(&self, args: Vec<String>, seed: Option<u32>) -> Runtime {
+        let host: HashMap<String, Rc<HostFn>> = self.host.iter().map(|(name, _, func)| (name.clone(), func.clone())).collect();
+        Runtime::new(args, seed).with_host(host)
+    }
+
+    /// Calls the function named `name` in a program this compiled, with the
+    /// interpreter
+    pub fn call(&self, prog: &na::Prog, typed: &typecheck::Prog, name: &str, args: Vec<Value>) -> Result<Value, SpruceErr> {
+        eval::call_fn(prog, typed, name, args, self.runtime(Vec::new(), None))
+    }
+}
+
+/// A Rust value a program can be given
+pub trait ToValue {
+    /// Fails if the program has no constructor for the value
+    fn to_value(&self, prog: &na::Prog) -> Result<Value, String>;
+}
+
+/// A Rust value a program's values can be read as
+pub trait FromValue: Sized {
+    /// Fails if the value is of another type
+    fn from_value(prog: &na::Prog, val: &Value) -> Result<Self, String>;
+}
+
+/// The constructor named `name` applied to `args`, if the program has one
+/// that takes that many
+pub fn construct(prog: &na::Prog, name: &str, args: Vec<Value>) -> Result<Value, String> {
+    match prog.type_table.values.values().find(|val| val.name == name) {
+        Some(val) if val.args.len() == args.len() => Ok(Value::ADT(val.id, heap::alloc(args))),
+        Some(val) => Err(format!("'{}' takes {} values, not {}", name, val.args.len(), args.len())),
+        None => Err(format!("the program has no constructor '{}'", name))
+    }
+}
+
+/// The arguments of a value made by the constructor named `name`
+pub fn fields<'v>(prog: &na::Prog, name: &str, val: &'v Value) -> Result<&'v [Value], String> {
+    match val {
+        Value::ADT(id, args) if prog.type_table.values.get(id).map_or(false, |val| val.name == name) => Ok(args),
+        _ => Err(mismatch(prog, name, val))
+    }
+}
+
+fn mismatch(prog: &na::Prog, expected: &str, val: &Value) -> String {
+    format!("expected {}, not {}", expected, eval::show(prog, val))
+}
+
+impl From<i64> for Value {
+    fn from(n: i64) -> Self {
+        Value::Int(n)
+    }
+}
+
+impl From<f64> for Value {
+    fn from(x: f64) -> Self {
+        Value::Float(x)
+    }
+}
+
+impl From<String> for Value {
+    fn from(text: String) -> Self {
+        Value::Str(Rc::from(text))
+    }
+}
+
+impl From<&str> for Value {
+    fn from(text: &str) -> Self {
+        Value::Str(Rc::from(text))
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Unit
+    }
+}
+
+impl TryFrom<Value> for i64 {
+    type Error = String;
+
+    fn try_from(val: Value) -> Result<Self, String> {
+        match val {
+            Value::Int(n) => Ok(n),
+            _ => Err(String::from("expected an Int"))
+        }
+    }
+}
+
+impl TryFrom<Value> for f64 {
+    type Error = String;
+
+    fn try_from(val: Value) -> Result<Self, String> {
+        match val {
+            Value::Float(x) => Ok(x),
+            _ => Err(String::from("expected a Float"))
+        }
+    }
+}
+
+impl TryFrom<Value> for String {
+    type Error = String;
+
+    fn try_from(val: Value) -> Result<Self, String> {
+        match val {
+            Value::Str(text) => Ok(text.to_string()),
+            _ => Err(String::from("expected a String"))
+        }
+    }
+}
+
+impl TryFrom<Value> for () {
+    type Error = String;
+
+    fn try_from(val: Value) -> Result<Self, String> {
+        match val {
+            Value::Unit => Ok(()),
+            _ => Err(String::from("expected ()"))
+        }
+    }
+}
+
+// the values that don't need the program convert as they do without it
+macro_rules! marshal_plain {
+    ($($ty:ty),*) => {
+        $(
+            impl ToValue for $ty {
+                fn to_value(&self, _: &na::Prog) -> Result<Value, String> {
+                    Ok(Value::from(self.clone()))
+                }
+            }
+
+            impl FromValue for $ty {
+                fn from_value(prog: &na::Prog, val: &Value) -> Result<Self, String> {
+                    <$ty>::try_from(val.clone()).map_err(|expected| format!("{}, not {}", expected, eval::show(prog, val)))
+                }
+            }
+        )*
+    };
+}
+
+marshal_plain!(i64, f64, String, ());
+
+impl ToValue for Value {
+    fn to_value(&self, _: &na::Prog) -> Result<Value, String> {
+        Ok(self.clone())
+    }
+}
+
+impl FromValue for Value {
+    fn from_value(_: &na::Prog, val: &Value) -> Result<Self, String> {
+        Ok(val.clone())
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self, prog: &na::Prog) -> Result<Value, String> {
+        Ok(eval::bool(prog, *self))
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(prog: &na::Prog, val: &Value) -> Result<Self, String> {
+        match val {
+            Value::ADT(id, _) if *id == prog.registry.value_id(LangValue::True) => Ok(true),
+            Value::ADT(id, _) if *id == prog.registry.value_id(LangValue::False) => Ok(false),
+            _ => Err(mismatch(prog, "a Bool", val))
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+    fn to_value(&self, prog: &na::Prog) -> Result<Value, String> {
+        Ok(match self {
+            Some(val) => eval::constructor(prog, LangValue::Just, vec![val.to_value(prog)?]),
+            None => eval::constructor(prog, LangValue::Nothing, Vec::new())
+        })
+    }
+}
+
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(prog: &na::Prog, val: &Value) -> Result<Self, String> {
+        match val {
+            Value::ADT(id, args) if *id == prog.registry.value_id(LangValue::Just) => Ok(Some(T::from_value(prog, &args[0])?)),
+            Value::ADT(id, _) if *id == prog.registry.value_id(LangValue::Nothing) => Ok(None),
+            _ => Err(mismatch(prog, "a Maybe", val))
+        }
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self, prog: &na::Prog) -> Result<Value, String> {
+        let items = self.iter().map(|item| item.to_value(prog)).collect::<Result<Vec<Value>, String>>()?;
+        Ok(eval::list(prog, items))
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(prog: &na::Prog, mut val: &Value) -> Result<Self, String> {
+        let (cons, nil) = (prog.registry.value_id(LangValue::Cons), prog.registry.value_id(LangValue::Nil));
+        let mut items = Vec::new();
+        loop {
+            match val {
+                Value::ADT(id, args) if *id == cons => {
+                    items.push(T::from_value(prog, &args[0])?);
+                    val = &args[1];
+                }
+                Value::ADT(id, _) if *id == nil => return Ok(items),
+                _ => return Err(mismatch(prog, "a List", val))
+            }
+        }
+    }
+}
+
+/// Converts a struct to and from the constructor of the same name, whose
+/// arguments are the struct's fields in the order they're listed, as in
+/// `marshal_struct!(Point { x, y })` for `Point(Int, Int)`
+#[macro_export]
+macro_rules! marshal_struct {
+    ($name:ident { $($field:ident),* }) => {
+        impl $crate::embed::ToValue for $name {
+            fn to_value(&self, prog: &$crate::name_analysis::Prog) -> Result<$crate::embed::Value, String> {
+                let args = vec![$($crate::embed::ToValue::to_value(&self.$field, prog)?),*];
+                $crate::embed::construct(prog, stringify!($name), args)
+            }
+        }
+
+        impl $crate::embed::FromValue for $name {
+            fn from_value(prog: &$crate::name_analysis::Prog, val: &$crate::embed::Value) -> Result<Self, String> {
+                let mut fields = $crate::embed::fields(prog, stringify!($name), val)?.iter();
+                let mut next = || fields.next().ok_or_else(|| format!("'{}' has too few values", stringify!($name)));
+                Ok($name {
+                    $($field: $crate::embed::FromValue::from_value(prog, next()?)?),*
+                })
+            }
+        }
+    };
+}
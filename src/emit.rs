@@ -0,0 +1,241 @@
+/*
+What each phase of the compiler makes of a program, written out as text for
+`spruce check --emit=<artifact>`, for looking into why the compiler did what
+it did. Only the program's own files are written out, not the prelude, and
+everything is in order of file and then of where it is, so the same program
+always gives the same text.
+
+  - tokens: the innermost rules the grammar matched, a line each, with where
+    they are and what they matched
+  - ast: the parsed declarations, as the parser's own types print them
+  - named-ast: the declarations once names are resolved, where names are
+    symbol ids
+  - typed-ast: a tree of each function and definition, with every statement
+    and expression on a line of its own along with its type
+  - ir: the A-normal form the code generators start from, for a program
+    with a `main`
+  - env: the type of every symbol, sorted by name, after the type of every
+    type and constructor
+*/
+
+use std::fmt::Debug;
+use std::str::FromStr;
+
+use crate::anf;
+use crate::eval;
+use crate::mono;
+use crate::name_analysis as na;
+use crate::parser::{self, NodeInfo};
+use crate::error::SpruceErr;
+use crate::source::{FileId, SourceMap};
+use crate::typecheck::{self, CaseBody, Environment, Expr, ExprNode, Stmt};
+
+/// Something the compiler can write out
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Artifact {
+    Tokens,
+    Ast,
+    NamedAst,
+    TypedAst,
+    Ir,
+    Env
+}
+
+pub const ALL_ARTIFACTS: [Artifact; 6] = [Artifact::Tokens, Artifact::Ast, Artifact::NamedAst, Artifact::TypedAst, Artifact::Ir, Artifact::Env];
+
+impl Artifact {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Artifact::Tokens => "tokens",
+            Artifact::Ast => "ast",
+            Artifact::NamedAst => "named-ast",
+            Artifact::TypedAst => "typed-ast",
+            Artifact::Ir => "ir",
+            Artifact::Env => "env"
+        }
+    }
+
+    /// Whether the artifact only needs the program to parse, rather than to
+    /// check
+    pub fn parsed(&self) -> bool {
+        matches!(self, Artifact::Tokens | Artifact::Ast)
+    }
+}
+
+impl FromStr for Artifact {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        ALL_ARTIFACTS.iter().find(|artifact| artifact.as_str() == name).copied().ok_or(())
+    }
+}
+
+/// Where `info` starts, as file:line:col
+fn location(sources: &SourceMap, info: &NodeInfo) -> String {
+    let pos = sources.line_col(info.file, info.span.start);
+    format!("{}:{}:{}", sources.name(info.file), pos.line, pos.col)
+}
+
+/// The tokens of each of `files`, or why one of them doesn't parse
+pub fn tokens(sources: &SourceMap, files: &[FileId]) -> Result<String, SpruceErr> {
+    let mut out = String::new();
+    for id in files {
+        let text = sources.get(*id).map_or("", |source| source.text.as_str());
+        for token in parser::tokens(sources, *id)? {
+            out.push_str(&format!("{} {} {:?}\n", location(sources, &token.info), token.rule, &text[token.info.span.start..token.info.span.end]));
+        }
+    }
+    Ok(out)
+}
+
+/// Declarations in `files`, each printed as its type prints it, in order of
+/// where they are
+fn declarations(found: Vec<(&NodeInfo, &dyn Debug)>, files: &[FileId]) -> String {
+    let mut found: Vec<(&NodeInfo, &dyn Debug)> = found.into_iter().filter(|(info, _)| files.contains(&info.file)).collect();
+    found.sort_by_key(|(info, _)| (info.file, info.span.start));
+    found.iter().map(|(_, node)| format!("{:#?}\n", node)).collect()
+}
+
+/// The parsed declarations of `files`
+pub fn ast(prog: &parser::Prog, files: &[FileId]) -> String {
+    let mut found: Vec<(&NodeInfo, &dyn Debug)> = Vec::new();
+    found.extend(prog.modules.iter().map(|module| (&module.info, module as &dyn Debug)));
+    found.extend(prog.types.iter().map(|t| (&t.info, t as &dyn Debug)));
+    found.extend(prog.signatures.iter().map(|sig| (&sig.info, sig as &dyn Debug)));
+    found.extend(prog.definitions.iter().map(|def| (&def.info, def as &dyn Debug)));
+    found.extend(prog.functions.iter().map(|func| (&func.info, func as &dyn Debug)));
+    declarations(found, files)
+}
+
+/// The declarations of `files` with their names resolved
+pub fn named_ast(prog: &na::Prog, files: &[FileId]) -> String {
+    let mut found: Vec<(&NodeInfo, &dyn Debug)> = Vec::new();
+    found.extend(prog.types.iter().map(|t| (&t.info, t as &dyn Debug)));
+    found.extend(prog.signatures.iter().map(|sig| (&sig.info, sig as &dyn Debug)));
+    found.extend(prog.definitions.iter().map(|def| (&def.info, def as &dyn Debug)));
+    found.extend(prog.functions.iter().map(|func| (&func.info, func as &dyn Debug)));
+    declarations(found, files)
+}
+
+/// The functions and definitions of `files`, with the type of each part
+pub fn typed_ast(prog: &na::Prog, typed: &typecheck::Prog, env: &Environment, sources: &SourceMap, files: &[FileId]) -> String {
+    let printer = TypedPrinter { prog: prog, env: env, sources: sources };
+    let mut found: Vec<(&NodeInfo, String)> = Vec::new();
+    for def in typed.definitions.iter().filter(|def| files.contains(&def.info.file)) {
+        let mut out = String::new();
+        printer.stmt(&mut out, def, 0);
+        found.push((&def.info, out));
+    }
+    for func in typed.functions.iter().filter(|func| files.contains(&func.info.file)) {
+        let args: Vec<&str> = func.val.args.iter().map(|arg| eval::symbol_name(prog, *arg)).collect();
+        let ty = env.type_of_id(func.val.name).map_or_else(|| env.type_str(func.ty), |ty| ty.to_string());
+        let mut out = format!("{}({}) : {} @ {}\n", eval::symbol_name(prog, func.val.name), args.join(", "), ty, location(sources, &func.info));
+        printer.body(&mut out, &func.val.body, 1);
+        found.push((&func.info, out));
+    }
+    found.sort_by_key(|(info, _)| (info.file, info.span.start));
+    found.into_iter().map(|(_, out)| out).collect()
+}
+
+struct TypedPrinter<'a> {
+    prog: &'a na::Prog,
+    env: &'a Environment,
+    sources: &'a SourceMap
+}
+
+impl<'a> TypedPrinter<'a> {
+    fn line(&self, out: &mut String, indent: usize, label: &str, ty: typecheck::TypeId, info: &NodeInfo) {
+        out.push_str(&format!("{}{} : {} @ {}\n", "    ".repeat(indent), label, self.env.type_str(ty), location(self.sources, info)));
+    }
+
+    fn body(&self, out: &mut String, body: &typecheck::BodyNode, indent: usize) {
+        for stmt in &body.val.stmts {
+            self.stmt(out, stmt, indent);
+        }
+        if let Some(expr) = &body.val.expr {
+            self.expr(out, expr, indent);
+        }
+    }
+
+    fn stmt(&self, out: &mut String, stmt: &typecheck::StmtNode, indent: usize) {
+        match &stmt.val {
+            Stmt::Assign(target, expr) => {
+                let keyword = match target.val {
+                    na::Target::Update(_) => "update",
+                    _ => "let"
+                };
+                let label = format!("{} {}", keyword, eval::symbol_name(self.prog, target.val.id()));
+                self.line(out, indent, &label, expr.ty, &stmt.info);
+                self.expr(out, expr, indent + 1);
+            }
+            Stmt::FnCall(id, args) => {
+                self.line(out, indent, &format!("call {}", eval::symbol_name(self.prog, *id)), stmt.ty, &stmt.info);
+                for arg in args {
+                    self.expr(out, arg, indent + 1);
+                }
+            }
+            Stmt::Case(case) => {
+                self.line(out, indent, "case", case.ty, &case.info);
+                self.expr(out, &case.val.expr, indent + 1);
+                for option in &case.val.options {
+                    let pattern = &option.val.pattern.info;
+                    let text = self.sources.get(pattern.file).map_or("", |source| &source.text[pattern.span.start..pattern.span.end]);
+                    out.push_str(&format!("{}{} -> @ {}\n", "    ".repeat(indent + 1), text, location(self.sources, &option.info)));
+                    match &option.val.body.val {
+                        CaseBody::Expr(expr) => self.expr(out, expr, indent + 2),
+                        CaseBody::Body(body) => self.body(out, body, indent + 2)
+                    }
+                }
+            }
+        }
+    }
+
+    fn expr(&self, out: &mut String, expr: &ExprNode, indent: usize) {
+        let name = |id: na::SymbolID| eval::symbol_name(self.prog, id).to_string();
+        let label = match &expr.val {
+            Expr::Id(id) => name(*id),
+            Expr::FnCall(id, _) => format!("call {}", name(*id)),
+            Expr::ADTVal(id, _) => self.prog.type_table.values.get(id).map_or(String::new(), |val| val.name.clone()),
+            Expr::Lit(n) => n.to_string(),
+            Expr::Neg(_) => String::from("-"),
+            Expr::List(_) => String::from("[]"),
+            Expr::Add(_, _) => String::from("+"),
+            Expr::Subt(_, _) => String::from("-"),
+            Expr::Mult(_, _) => String::from("*"),
+            Expr::Div(_, _) => String::from("/"),
+            Expr::Pow(_, _) => String::from("^"),
+            Expr::Mod(_, _) => String::from("%"),
+            Expr::Eq(_, _) => String::from("=="),
+            Expr::NotEq(_, _) => String::from("!="),
+            Expr::LtEq(_, _) => String::from("<="),
+            Expr::GtEq(_, _) => String::from(">="),
+            Expr::Lt(_, _) => String::from("<"),
+            Expr::Gt(_, _) => String::from(">"),
+            Expr::ComposeR(_, _) => String::from(">>"),
+            Expr::ComposeL(_, _) => String::from("<<"),
+            Expr::BitAnd(_, _) => String::from("&"),
+            Expr::BitOr(_, _) => String::from("|"),
+            Expr::BitXor(_, _) => String::from("^^^"),
+            Expr::Shl(_, _) => String::from("<<<"),
+            Expr::Shr(_, _) => String::from(">>>"),
+            Expr::Error => String::from("error")
+        };
+        self.line(out, indent, &label, expr.ty, &expr.info);
+        for child in expr.val.children() {
+            self.expr(out, child, indent + 1);
+        }
+    }
+}
+
+/// The program as the code generators get it, once it's made monomorphic and
+/// lowered from `main`
+pub fn ir(mut prog: na::Prog, mut typed: typecheck::Prog, env: &Environment) -> Result<String, SpruceErr> {
+    mono::compile(&mut prog, &mut typed, env, "main")?;
+    let program = anf::lower(&prog, &typed, "main")?;
+    Ok(program.dump(&prog))
+}
+
+/// The type of everything the program declares
+pub fn env(prog: &na::Prog, env: &Environment) -> String {
+    env.as_str(prog, true)
+}
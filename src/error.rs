@@ -1,29 +1,552 @@
-extern crate pest;
-use pest::Position;
+use std::fmt;
+use std::io::IsTerminal;
 
 use crate::parser::{NodeInfo};
+use crate::source::{LineCol, SourceMap};
+use crate::error_codes::ErrorCode;
+use crate::typecheck::TypeError;
+use crate::trace::Phase;
 
-#[derive(Debug)]
-pub struct SpruceErr {
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+            Severity::Note => "note"
+        }
+    }
+
+    /// ANSI style the severity is drawn in
+    fn style(&self) -> &'static str {
+        match self {
+            Severity::Error => "1;31",
+            Severity::Warning => "1;33",
+            Severity::Note => "1;36"
+        }
+    }
+}
+
+/// Whether diagnostics are drawn with ANSI colors, picked with `--color`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorChoice {
+    Always,
+    Never,
+    /// Color only when writing to a terminal
+    Auto
+}
+
+impl ColorChoice {
+    pub fn from_arg(arg: &str) -> Option<ColorChoice> {
+        match arg {
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            "auto" => Some(ColorChoice::Auto),
+            _ => None
+        }
+    }
+
+    pub fn use_color(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => std::io::stdout().is_terminal()
+        }
+    }
+}
+
+/// A secondary span in a diagnostic, pointing at something that explains the
+/// primary one, such as where an expected type came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
     pub message: String,
     pub info: NodeInfo
 }
 
-impl SpruceErr {
-    pub fn as_str(&self, files: &Vec<(&str, String)>) -> String {
-        let (file, file_name) = files.iter().filter(|(_, file_name)| {*file_name == self.info.file}).next().expect(format!("could not find file while reporting error: {}", self.info.file).as_str());
+/// An edit that fixes a diagnostic, replacing the source under `info` with
+/// `replacement`. Lines after the first in the replacement are indented to
+/// the column the replacement starts at
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suggestion {
+    pub message: String,
+    pub info: NodeInfo,
+    pub replacement: String,
+    // whether the edit can be applied without a person looking at it. Edits
+    // with placeholders in them can't be
+    pub machine_applicable: bool
+}
+
+/// Something the compiler has to say about the program, pointing at where in
+/// the source it applies
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub message: String,
+    pub info: NodeInfo,
+    pub severity: Severity,
+    pub code: Option<ErrorCode>,
+    pub labels: Vec<Label>,
+    // advice on fixing the problem, shown beneath the snippet
+    pub helps: Vec<String>,
+    // edits for tools to apply. These aren't rendered, so anything worth
+    // telling a person should also be a help
+    pub suggestions: Vec<Suggestion>,
+    // the failure behind a type error, for tools that present it themselves
+    pub type_error: Option<TypeError>,
+    // the calls a runtime error happened in, innermost first, each as the
+    // function and where it had got to
+    pub trace: Vec<(String, NodeInfo)>
+}
+
+/// Most diagnostics stop compilation, so that's what the passes deal in
+pub type SpruceErr = Diagnostic;
+
+impl Diagnostic {
+    /// An error at `info`
+    pub fn new(message: String, info: NodeInfo) -> Self {
+        Diagnostic {
+            message: message,
+            info: info,
+            severity: Severity::Error,
+            code: None,
+            labels: Vec::new(),
+            helps: Vec::new(),
+            suggestions: Vec::new(),
+            type_error: None,
+            trace: Vec::new()
+        }
+    }
+
+    /// Tags the diagnostic with the code `spruce explain` knows it by
+    pub fn with_code(self, code: ErrorCode) -> Self {
+        Diagnostic { code: Some(code), ..self }
+    }
+
+    /// Adds a secondary label at `info`
+    pub fn with_label(mut self, message: String, info: NodeInfo) -> Self {
+        self.labels.push(Label { message: message, info: info });
+        self
+    }
+
+    pub fn with_help(mut self, help: String) -> Self {
+        self.helps.push(help);
+        self
+    }
+
+    pub fn with_suggestion(mut self, suggestion: Suggestion) -> Self {
+        self.suggestions.push(suggestion);
+        self
+    }
+
+    pub fn with_trace(self, trace: Vec<(String, NodeInfo)>) -> Self {
+        Diagnostic { trace: trace, ..self }
+    }
+
+    pub fn warning(message: String, info: NodeInfo) -> Self {
+        Diagnostic { severity: Severity::Warning, ..Diagnostic::new(message, info) }
+    }
+
+    pub fn note(message: String, info: NodeInfo) -> Self {
+        Diagnostic { severity: Severity::Note, ..Diagnostic::new(message, info) }
+    }
 
-        let pos = pest::Position::new(file, self.info.span.start).expect("Failed to find position in error");
-        let (line, col) = pos.line_col();
-        let line_text = pos.line_of();
+    /// An internal compiler error, for when a pass finds the compiler itself
+    /// in a state that should be impossible. It's reported like any other
+    /// error instead of taking the caller down, unless SPRUCE_PANIC_ON_ICE is
+    /// set, which panics on the spot so the bug can be caught where it happens
+    pub fn ice(phase: Phase, message: String, info: NodeInfo) -> Self {
+        let message = format!("internal compiler error in {}: {}", phase.as_str(), message);
+        if std::env::var_os("SPRUCE_PANIC_ON_ICE").is_some() {
+            panic!("{}", message);
+        }
 
-        let mut output = format!("Error in {}: {}\n\n", self.info.file, self.message);
-        output = format!("{}{}| {}", output, line, line_text);
+        Diagnostic::new(message, info).with_help(String::from("this is a bug in the compiler rather than the program, please report it"))
+    }
 
-        let line_num_len = format!("{}", line).len();
-        let spaces = std::iter::repeat(" ").take(line_num_len + 1 + col).collect::<String>();
-        output = format!("{}{}^\n\n", output, spaces);
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    /// Line and column where the error starts, in the file it was found in
+    pub fn line_col(&self, sources: &SourceMap) -> LineCol {
+        line_col_at(sources, &self.info, self.info.span.start)
+    }
+
+    /// Where the error is, as file:line:col
+    pub fn location(&self, sources: &SourceMap) -> String {
+        match self.line_col(sources) {
+            LineCol { line: 0, .. } => String::from(sources.name(self.info.file)),
+            pos => format!("{}:{}:{}", sources.name(self.info.file), pos.line, pos.col)
+        }
+    }
+
+    /// Renders the diagnostic with the source it points at, underlining the
+    /// offending span:
+    ///
+    /// ```text
+    /// error: Parse error
+    ///  --> main:2:8
+    ///   |
+    /// 2 |     1 +
+    ///   |        ^
+    /// ```
+    pub fn as_str(&self, sources: &SourceMap) -> String {
+        self.render(sources, false)
+    }
+
+    /// Same as `as_str`, drawing the severity, gutter and underline in color
+    /// when `color` is set
+    pub fn render(&self, sources: &SourceMap, color: bool) -> String {
+        // labels in the same file are drawn alongside the primary span, the
+        // rest get a snippet of their own
+        let (local, foreign): (Vec<&Label>, Vec<&Label>) = self.labels.iter().partition(|label| label.info.file == self.info.file);
+
+        let mut annotations = vec![Annotation { info: &self.info, message: None }];
+        annotations.extend(local.iter().map(|label| Annotation { info: &label.info, message: Some(&label.message) }));
+
+        // the gutter is sized for the largest line number drawn anywhere
+        let mut last_line = line_col_at(sources, &self.info, self.info.span.end).line;
+        for label in &self.labels {
+            last_line = last_line.max(line_col_at(sources, &label.info, label.info.span.start).line);
+        }
+        let gutter = " ".repeat(format!("{}", last_line).len());
+        let bar = paint("|", GUTTER_STYLE, color);
+
+        let heading = self.heading();
+        let mut output = format!("{}{}\n", paint(&heading, self.severity.style(), color), paint(&format!(": {}", self.message), "1", color));
+        output = format!("{}{}{} {}\n", output, gutter, paint("-->", GUTTER_STYLE, color), self.location(sources));
+        output = format!("{}{} {}\n", output, gutter, bar);
+        output = format!("{}{}", output, self.snippet(sources, &annotations, &gutter, color));
+
+        for label in foreign {
+            let pos = line_col_at(sources, &label.info, label.info.span.start);
+            let annotation = Annotation { info: &label.info, message: Some(&label.message) };
+
+            output = format!("{}{}{} {}:{}:{}\n", output, gutter, paint(":::", GUTTER_STYLE, color), sources.name(label.info.file), pos.line, pos.col);
+            output = format!("{}{} {}\n", output, gutter, bar);
+            output = format!("{}{}", output, self.snippet(sources, &vec![annotation], &gutter, color));
+        }
+
+        for help in &self.helps {
+            output = format!("{}{} {} {}\n", output, gutter, paint("=", GUTTER_STYLE, color), paint(&format!("help: {}", help), "1", color));
+        }
+
+        // deep recursion makes for long traces, so only their ends are shown
+        let mut trace: Vec<String> = self.trace.iter().map(|(function, info)| {
+            let pos = line_col_at(sources, info, info.span.start);
+            format!("{} at {}:{}:{}", function, sources.name(info.file), pos.line, pos.col)
+        }).collect();
+        if trace.len() > TRACE_ENDS * 2 + 1 {
+            let hidden = trace.len() - TRACE_ENDS * 2;
+            trace.splice(TRACE_ENDS..trace.len() - TRACE_ENDS, vec![format!("... {} more calls", hidden)]);
+        }
+        for (i, call) in trace.iter().enumerate() {
+            let lead = if i == 0 { format!("{} {}", paint("=", GUTTER_STYLE, color), paint("in:", "1", color)) } else { " ".repeat("= in:".len()) };
+            output = format!("{}{} {} {}\n", output, gutter, lead, call);
+        }
 
         output
     }
+
+    /// The severity, with the code if there is one, as in `error[E0008]`
+    fn heading(&self) -> String {
+        match self.code {
+            Some(code) => format!("{}[{}]", self.severity.as_str(), code.as_str()),
+            None => String::from(self.severity.as_str())
+        }
+    }
+
+    /// Draws the lines of a single file touched by some annotations, each
+    /// followed by an underline for every annotation on it. The primary span
+    /// is marked with carets, labels with dashes and only on their first line
+    fn snippet(&self, sources: &SourceMap, annotations: &Vec<Annotation>, gutter: &String, color: bool) -> String {
+        // diagnostics pointing into a file that isn't in the map are still
+        // reported, just without a snippet
+        let (file, index) = match sources.get(annotations[0].info.file) {
+            Some(source) => (source.text.as_str(), &source.lines),
+            None => return String::new()
+        };
+        let bar = paint("|", GUTTER_STYLE, color);
+
+        let mut lines: Vec<usize> = Vec::new();
+        for annotation in annotations {
+            let (start, end) = index.span_to_line_col(file, &annotation.info.span);
+            if annotation.message.is_some() {
+                lines.push(start.line);
+            }
+            // long spans only show their first and last lines
+            else if end.line - start.line > 2 {
+                lines.extend(vec![start.line, end.line]);
+            }
+            else {
+                lines.extend(start.line..=end.line);
+            }
+        }
+        lines.sort();
+        lines.dedup();
+
+        let mut output = String::new();
+        for (i, line) in lines.iter().enumerate() {
+            if i > 0 && lines[i - 1] + 1 != *line {
+                output = format!("{}{}\n", output, paint("...", GUTTER_STYLE, color));
+            }
+
+            let (line_start, line_end) = index.line_range(file, line - 1);
+            let line_text = &file[line_start..line_end];
+            let line_len = line_text.chars().count();
+
+            let line_num = format!("{:>width$}", line, width = gutter.len());
+            output = format!("{}{} {} {}\n", output, paint(&line_num, GUTTER_STYLE, color), bar, line_text);
+
+            for annotation in annotations {
+                let (start, end) = index.span_to_line_col(file, &annotation.info.span);
+                let on_line = match annotation.message {
+                    Some(_) => *line == start.line,
+                    None => start.line <= *line && *line <= end.line
+                };
+                if !on_line {
+                    continue;
+                }
+
+                let underline_start = if *line == start.line { start.col } else { 1 };
+                let underline_end = if *line == end.line { end.col } else { line_len + 1 };
+                let width = if underline_end > underline_start { underline_end - underline_start } else { 1 };
+
+                let underline = match annotation.message {
+                    Some(message) => paint(&format!("{} {}", "-".repeat(width), message), GUTTER_STYLE, color),
+                    None => paint(&"^".repeat(width), self.severity.style(), color)
+                };
+                output = format!("{}{} {} {}{}\n", output, gutter, bar, " ".repeat(underline_start - 1), underline);
+            }
+        }
+
+        output
+    }
+}
+
+/// Without the source map to hand, a diagnostic can only say which bytes of
+/// which file it points at. `render` is the one to show people
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} (file {}, bytes {}..{})", self.heading(), self.message, self.info.file, self.info.span.start, self.info.span.end)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+/// Applies suggestions to the text of a file, skipping any that overlap one
+/// already applied
+pub fn apply_suggestions(text: &str, suggestions: Vec<&Suggestion>) -> String {
+    let mut sorted = suggestions;
+    sorted.sort_by_key(|suggestion| suggestion.info.span.start);
+
+    let mut output = String::new();
+    let mut copied_to = 0;
+    for suggestion in sorted {
+        let span = &suggestion.info.span;
+        if span.start < copied_to {
+            continue;
+        }
+
+        let line_start = text[..span.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+        let indent = " ".repeat(text[line_start..span.start].chars().count());
+
+        output.push_str(&text[copied_to..span.start]);
+        output.push_str(&suggestion.replacement.replace("\n", &format!("\n{}", indent)));
+        copied_to = span.end;
+    }
+    output.push_str(&text[copied_to..]);
+
+    output
+}
+
+/// A span to underline in a snippet, with the label's message if it isn't
+/// the primary span
+struct Annotation<'a> {
+    info: &'a NodeInfo,
+    message: Option<&'a String>
+}
+
+/// Line 0 stands for a position in a file that isn't in the map
+fn line_col_at(sources: &SourceMap, info: &NodeInfo, offset: usize) -> LineCol {
+    sources.line_col(info.file, offset)
+}
+
+const GUTTER_STYLE: &str = "1;34";
+
+/// How many calls are shown from each end of a long stack trace
+const TRACE_ENDS: usize = 10;
+
+/// Wraps text in an ANSI style, or leaves it alone when color is off
+fn paint(text: &str, style: &str, color: bool) -> String {
+    if color {
+        format!("\x1b[{}m{}\x1b[0m", style, text)
+    }
+    else {
+        String::from(text)
+    }
+}
+
+
+#[test]
+fn render_snippet() {
+    use crate::parser::Span;
+
+    let src = "f() {\n    x = 1 + True\n}\n";
+    let mut sources = SourceMap::new();
+    let main = sources.add("main", src);
+    let err = SpruceErr::new(
+        String::from("bad add"),
+        NodeInfo { span: Span { start: 14, end: 22 }, file: main }
+    );
+
+    let expected = "error: bad add
+ --> main:2:9
+  |
+2 |     x = 1 + True
+  |         ^^^^^^^^
+";
+    assert_eq!(err.as_str(&sources), expected);
+
+    // spans across many lines elide the middle
+    let err = SpruceErr::new(
+        String::from("bad function"),
+        NodeInfo { span: Span { start: 0, end: src.len() - 1 }, file: main }
+    );
+    let expected = "error: bad function
+ --> main:1:1
+  |
+1 | f() {
+  | ^^^^^
+2 |     x = 1 + True
+  | ^^^^^^^^^^^^^^^^
+3 | }
+  | ^
+";
+    assert_eq!(err.as_str(&sources), expected);
+}
+
+#[test]
+fn render_colored() {
+    use crate::parser::Span;
+
+    let src = "x = 1\n";
+    let mut sources = SourceMap::new();
+    let main = sources.add("main", src);
+    let warning = Diagnostic::warning(
+        String::from("unused"),
+        NodeInfo { span: Span { start: 0, end: 1 }, file: main }
+    );
+    assert_eq!(warning.is_error(), false);
+
+    let expected = "\x1b[1;33mwarning\x1b[0m\x1b[1m: unused\x1b[0m
+ \x1b[1;34m-->\x1b[0m main:1:1
+  \x1b[1;34m|\x1b[0m
+\x1b[1;34m1\x1b[0m \x1b[1;34m|\x1b[0m x = 1
+  \x1b[1;34m|\x1b[0m \x1b[1;33m^\x1b[0m
+";
+    assert_eq!(warning.render(&sources, true), expected);
+    assert_eq!(warning.render(&sources, false), warning.as_str(&sources));
+    assert!(warning.as_str(&sources).starts_with("warning: unused\n"));
+}
+
+#[test]
+fn render_labels() {
+    use crate::parser::Span;
+
+    let lib = "one() {\n    1\n}\n";
+    let src = "b = True\n\nx = b + 1\n";
+    let mut sources = SourceMap::new();
+    let lib_id = sources.add("lib", lib);
+    let main = sources.add("main", src);
+    let err = SpruceErr::new(
+        String::from("bad add"),
+        NodeInfo { span: Span { start: 14, end: 15 }, file: main }
+    ).with_label(
+        String::from("assigned here"),
+        NodeInfo { span: Span { start: 0, end: 8 }, file: main }
+    ).with_label(
+        String::from("declared here"),
+        NodeInfo { span: Span { start: 0, end: lib.len() - 1 }, file: lib_id }
+    );
+
+    let expected = "error: bad add
+ --> main:3:5
+  |
+1 | b = True
+  | -------- assigned here
+...
+3 | x = b + 1
+  |     ^
+ ::: lib:1:1
+  |
+1 | one() {
+  | ------- declared here
+";
+    assert_eq!(err.as_str(&sources), expected);
+}
+
+#[test]
+fn apply_fixes() {
+    use crate::parser::Span;
+
+    let src = "main {\n    x = lenght\n}\n";
+    let fix = |start, end, replacement: &str| Suggestion {
+        message: String::new(),
+        info: NodeInfo { span: Span { start: start, end: end }, file: 0 },
+        replacement: String::from(replacement),
+        machine_applicable: true
+    };
+
+    let parens = fix(4, 4, "()");
+    let rename = fix(15, 21, "length");
+    let overlapping = fix(16, 18, "xx");
+    let fixed = apply_suggestions(src, vec![&rename, &overlapping, &parens]);
+    assert_eq!(fixed, "main() {\n    x = length\n}\n");
+
+    // inserted lines line up with the first
+    let insert = fix(11, 11, "y = 1\n");
+    assert_eq!(apply_suggestions(src, vec![&insert]), "main {\n    y = 1\n    x = lenght\n}\n");
+}
+
+#[test]
+fn display_error() {
+    use crate::parser::Span;
+
+    let err = SpruceErr::new(
+        String::from("bad add"),
+        NodeInfo { span: Span { start: 14, end: 22 }, file: 0 }
+    ).with_code(ErrorCode::MismatchedTypes);
+    assert_eq!(format!("{}", err), "error[E0008]: bad add (file 0, bytes 14..22)");
+
+    // usable wherever a boxed error is expected
+    let boxed: Box<dyn std::error::Error> = Box::from(err);
+    assert_eq!(boxed.to_string(), "error[E0008]: bad add (file 0, bytes 14..22)");
+}
+
+#[test]
+fn render_without_source() {
+    use crate::parser::Span;
+    use crate::source::NO_FILE;
+
+    let err = SpruceErr::new(
+        String::from("bad add"),
+        NodeInfo { span: Span { start: 14, end: 22 }, file: NO_FILE }
+    );
+    assert_eq!(err.as_str(&SourceMap::new()), "error: bad add\n --> <unknown>\n  |\n");
+}
+
+#[test]
+fn ice_is_reported() {
+    use crate::parser::Span;
+
+    let err = Diagnostic::ice(Phase::Typecheck, String::from("lost a type"), NodeInfo { span: Span { start: 0, end: 0 }, file: 0 });
+    assert_eq!(err.message, "internal compiler error in typecheck: lost a type");
+    assert!(err.is_error());
+    assert_eq!(err.helps.len(), 1);
 }
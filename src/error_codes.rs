@@ -0,0 +1,323 @@
+/*
+Every class of error the compiler reports has a stable code, so it can be
+looked up with `spruce explain <code>` for a longer description than fits in a
+diagnostic. Codes are never reused: retired errors keep their number.
+*/
+
+use std::str::FromStr;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ErrorCode {
+    Syntax,
+    DuplicateName,
+    UnboundName,
+    UnknownType,
+    LowercaseType,
+    NotAConstructor,
+    InvalidUpdate,
+    MismatchedTypes,
+    ArityMismatch,
+    MixedPatterns,
+    UntypedCase,
+    NonExhaustiveCase,
+    InfiniteType,
+    UnknownModule,
+    ImportCycle,
+    PrivateName,
+    AmbiguousName,
+    MisplacedBuiltin,
+    UnshowableType,
+    MisplacedTailCall,
+    EffectInDefinition,
+    FailingDefinition,
+    LiteralOutOfRange,
+    IncomparableType,
+    FractionalLiteral
+}
+
+pub const ALL_CODES: [ErrorCode; 25] = [
+    ErrorCode::Syntax,
+    ErrorCode::DuplicateName,
+    ErrorCode::UnboundName,
+    ErrorCode::UnknownType,
+    ErrorCode::LowercaseType,
+    ErrorCode::NotAConstructor,
+    ErrorCode::InvalidUpdate,
+    ErrorCode::MismatchedTypes,
+    ErrorCode::ArityMismatch,
+    ErrorCode::MixedPatterns,
+    ErrorCode::UntypedCase,
+    ErrorCode::NonExhaustiveCase,
+    ErrorCode::InfiniteType,
+    ErrorCode::UnknownModule,
+    ErrorCode::ImportCycle,
+    ErrorCode::PrivateName,
+    ErrorCode::AmbiguousName,
+    ErrorCode::MisplacedBuiltin,
+    ErrorCode::UnshowableType,
+    ErrorCode::MisplacedTailCall,
+    ErrorCode::EffectInDefinition,
+    ErrorCode::FailingDefinition,
+    ErrorCode::LiteralOutOfRange,
+    ErrorCode::IncomparableType,
+    ErrorCode::FractionalLiteral
+];
+
+/// Looks a code up by name, ignoring case so `e0001` works too
+impl FromStr for ErrorCode {
+    type Err = ();
+
+    fn from_str(code: &str) -> Result<Self, ()> {
+        ALL_CODES.iter().find(|c| c.as_str().eq_ignore_ascii_case(code)).copied().ok_or(())
+    }
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "E0001",
+            ErrorCode::DuplicateName => "E0002",
+            ErrorCode::UnboundName => "E0003",
+            ErrorCode::UnknownType => "E0004",
+            ErrorCode::LowercaseType => "E0005",
+            ErrorCode::NotAConstructor => "E0006",
+            ErrorCode::InvalidUpdate => "E0007",
+            ErrorCode::MismatchedTypes => "E0008",
+            ErrorCode::ArityMismatch => "E0009",
+            ErrorCode::MixedPatterns => "E0010",
+            ErrorCode::UntypedCase => "E0011",
+            ErrorCode::NonExhaustiveCase => "E0012",
+            ErrorCode::InfiniteType => "E0013",
+            ErrorCode::UnknownModule => "E0014",
+            ErrorCode::ImportCycle => "E0015",
+            ErrorCode::PrivateName => "E0016",
+            ErrorCode::AmbiguousName => "E0017",
+            ErrorCode::MisplacedBuiltin => "E0018",
+            ErrorCode::UnshowableType => "E0019",
+            ErrorCode::MisplacedTailCall => "E0020",
+            ErrorCode::EffectInDefinition => "E0021",
+            ErrorCode::FailingDefinition => "E0022",
+            ErrorCode::LiteralOutOfRange => "E0023",
+            ErrorCode::IncomparableType => "E0024",
+            ErrorCode::FractionalLiteral => "E0025"
+        }
+    }
+
+    /// What the error means and how it's usually fixed
+    pub fn explanation(&self) -> &'static str {
+        match self {
+            ErrorCode::Syntax =>
+"The program could not be parsed.
+
+The error points at the first token the parser could not make sense of. The
+mistake is often just before it: a missing operand, an unclosed bracket, or a
+block that doesn't start with `{` at the end of the line.",
+            ErrorCode::DuplicateName =>
+"A name was declared twice in the same scope.
+
+Functions, types, constructors, arguments and variables must all have
+distinct names within their scope. Rename one of them, or use `mut` and `:=`
+if the intent was to change a variable.",
+            ErrorCode::UnboundName =>
+"A name was used that has not been declared.
+
+Variables must be assigned before they are used, and functions and
+constructors must be declared somewhere in the program or prelude. Check the
+spelling, and that the declaration comes first in the enclosing block.",
+            ErrorCode::UnknownType =>
+"A type was named that does not exist.
+
+Constructor arguments can only refer to the built-in types (such as Int) and
+types declared with `type`.",
+            ErrorCode::LowercaseType =>
+"A type was declared with a lowercase name.
+
+Type names start with an uppercase letter, which keeps them apart from
+functions and variables.",
+            ErrorCode::NotAConstructor =>
+"A case pattern named something that is not a constructor.
+
+Patterns match on the constructors of a type, such as `Some(x)` or `Nil`.
+Use `_` to match anything.",
+            ErrorCode::InvalidUpdate =>
+"A variable was updated with `:=` when it can't be.
+
+Only variables declared with `mut` inside a function can be updated.
+Program-level statements can never be updated.",
+            ErrorCode::MismatchedTypes =>
+"Two types that must be the same are not.
+
+This usually means a value of one type was passed or combined where another
+was expected, such as adding a Bool to an Int or calling a function with an
+argument of the wrong type.",
+            ErrorCode::ArityMismatch =>
+"A function was used with the wrong number of arguments.
+
+Every call must pass exactly as many arguments as the function declares.",
+            ErrorCode::MixedPatterns =>
+"A case statement matches on values of different types.
+
+All of the patterns in a case must be constructors of the same type, or all
+numeric literals.",
+            ErrorCode::UntypedCase =>
+"The branches of a case statement don't agree on a type.
+
+When blocks in a case produce values of different types, the case as a whole
+has no value. That is only allowed if none of the branches is a plain
+expression, since those only exist to give the case a value.",
+            ErrorCode::NonExhaustiveCase =>
+"A case statement doesn't handle every constructor of the type it matches.
+
+Add an option for each missing constructor, or a `_` option to handle all of
+them at once. A value no option matches stops the program when the case runs,
+so this is the `non-exhaustive` lint: a warning by default, and an error with
+`-D non-exhaustive`.",
+            ErrorCode::InfiniteType =>
+"A type would have to contain itself.
+
+This happens when a value is used both as something and as part of that same
+thing, such as building a list whose elements are the list itself. It is
+usually a mistake in a recursive definition, or arguments passed in the wrong
+order.",
+            ErrorCode::UnknownModule =>
+"An import named a module that is not part of the program.
+
+Every file compiled together is a module, named by the `module` declaration at
+its top or after the file itself. Check the spelling, and that the file
+declaring the module is being compiled.",
+            ErrorCode::ImportCycle =>
+"Modules import each other in a cycle.
+
+A module is checked after everything it imports, so imports can't loop back
+around. Move the declarations both modules need into a third module that they
+can each import.",
+            ErrorCode::PrivateName =>
+"A name was used that another module keeps to itself.
+
+A module with an export list only shows other modules what it lists. A type
+listed as `Type` keeps its constructors private, so values of it can only be
+made and taken apart by the module's own functions; list it as `Type(..)` to
+export the constructors too.",
+            ErrorCode::AmbiguousName =>
+"A name was used that more than one imported module exports.
+
+Qualify the name with the module it should come from, as in `List.map`. A
+module's own declarations are never ambiguous, since they take precedence over
+anything imported.",
+            ErrorCode::MisplacedBuiltin =>
+"A builtin was declared outside of the prelude.
+
+Builtins are implemented by the runtime that ships with the compiler, so only
+the prelude can declare them. Write the function in Spruce instead.",
+            ErrorCode::UnshowableType =>
+"A function was passed to `show`.
+
+Only values can be shown: numbers, and the constructors of types along with
+their arguments. Call the function and show its result instead.",
+            ErrorCode::MisplacedTailCall =>
+"A call marked `@tail` is not in tail position.
+
+A call is in tail position when its result is what the function gives back,
+as the last expression of the function's body or of an option of a case in
+that place. Only those calls can reuse the caller's frame, so marking one
+makes sure a loop written as recursion runs in constant stack. Move the call
+to the end, or remove the mark.",
+            ErrorCode::EffectInDefinition =>
+"A program-level definition does something besides compute its value.
+
+Definitions are worked out once, as the program compiles, so they can't
+print, read input or files, look at the program's arguments, or use random
+numbers, which would all happen at the wrong time. Do these in `main`, or in
+a function it calls, and pass what they give to whatever needs it.",
+            ErrorCode::FailingDefinition =>
+"A program-level definition fails when it's worked out.
+
+Definitions are worked out once, as the program compiles, so a failure that
+would stop every run, such as dividing by zero, a case that no option
+matches, or using a definition before the one that gives it a value, is
+reported then instead. Definitions are worked out in the order they're
+written.",
+            ErrorCode::LiteralOutOfRange =>
+"A number literal is too large or too small for an Int.
+
+Ints are 64 bits, so they go from -9223372036854775808 to
+9223372036854775807, and a literal outside of that, in any base, can't be
+written. The native and wasm targets keep Ints in 63 bits, so a program built
+for them can only use literals from -4611686018427387904 to
+4611686018427387903.",
+            ErrorCode::IncomparableType =>
+"A function was passed to `compare`.
+
+Only values have an order: numbers, text, and the constructors of types along
+with their arguments. Compare what the functions give back instead.",
+            ErrorCode::FractionalLiteral =>
+"A number literal has a fraction or an exponent.
+
+Number literals are Ints, and there's no literal for a Float, so a literal
+like `2.5` or `1e3` can't be written. Write the whole number for an Int, or
+make a Float from one with `toFloat`, as in `sqrt(toFloat(2))`, or from text
+with `parseFloat`."
+        }
+    }
+
+    /// A program that triggers the error
+    pub fn example(&self) -> &'static str {
+        match self {
+            ErrorCode::Syntax => "main() {\n    x = 1 +\n}\n",
+            ErrorCode::DuplicateName => "f() {\n}\nf() {\n}\n",
+            ErrorCode::UnboundName => "main() {\n    x = y + 1\n}\n",
+            ErrorCode::UnknownType => "type Box {\n    Box(Number)\n}\n",
+            ErrorCode::LowercaseType => "type color {\n    Red\n}\n",
+            ErrorCode::NotAConstructor => "f(x) {\n    case x {\n        Red -> 1\n    }\n}\n",
+            ErrorCode::InvalidUpdate => "main() {\n    x = 1\n    x := 2\n}\n",
+            ErrorCode::MismatchedTypes => "main() {\n    x = 1 + True\n}\n",
+            ErrorCode::ArityMismatch => "f(a, b) {\n    a + b\n}\nmain() {\n    x = f(1)\n}\n",
+            ErrorCode::MixedPatterns => "f(x) {\n    case x {\n        True -> 1\n        Nil -> 2\n    }\n}\n",
+            ErrorCode::UntypedCase => "f(x) {\n    case x {\n        True -> 1\n        False -> {\n            z = True\n        }\n    }\n}\n",
+            ErrorCode::NonExhaustiveCase => "f(x) {\n    case x {\n        Just(y) -> y\n    }\n}\n",
+            ErrorCode::InfiniteType => "f(x) {\n    y = Cons(x, x)\n    y\n}\n",
+            ErrorCode::UnknownModule => "module Main\nimport Lists\n",
+            ErrorCode::ImportCycle => "module Main\nimport Main\n",
+            ErrorCode::PrivateName => "// stack.sp\nmodule Stack (Stack, empty)\ntype Stack {\n    Empty\n}\nempty() {\n    Empty\n}\n// main.sp\nimport Stack\nmain() {\n    s = Empty\n}\n",
+            ErrorCode::AmbiguousName => "// a.sp\nmodule A\nf() {\n    1\n}\n// b.sp\nmodule B\nf() {\n    2\n}\n// main.sp\nimport A\nimport B\nmain() {\n    x = f()\n}\n",
+            ErrorCode::MisplacedBuiltin => "builtin shout : (Int) -> Int\n",
+            ErrorCode::UnshowableType => "f() {\n    1\n}\nmain() {\n    x = show(f)\n}\n",
+            ErrorCode::MisplacedTailCall => "count(n) {\n    1 + @tail count(n - 1)\n}\n",
+            ErrorCode::EffectInDefinition => "roll = randomInt(1, 6)\nmain() {\n    roll\n}\n",
+            ErrorCode::FailingDefinition => "half = 1 / 0\nmain() {\n    half\n}\n",
+            ErrorCode::LiteralOutOfRange => "mask = 0xFFFF_FFFF_FFFF_FFFF\n",
+            ErrorCode::IncomparableType => "f() {\n    1\n}\nmain() {\n    x = compare(f, f)\n}\n",
+            ErrorCode::FractionalLiteral => "half = 0.5\n"
+        }
+    }
+
+    /// The example split into its files, for examples that span several.
+    /// Each file after the first starts with a `// name.sp` line, and a file
+    /// without one is the main file
+    pub fn example_files(&self) -> Vec<(&'static str, String)> {
+        let example = self.example();
+        let mut starts: Vec<(usize, String)> = Vec::new();
+        let mut offset = 0;
+        for line in example.split_inclusive('\n') {
+            let header = line.trim_end().strip_prefix("// ").filter(|name| name.ends_with(".sp"));
+            if let Some(name) = header {
+                starts.push((offset, String::from(name)));
+            }
+            offset += line.len();
+        }
+
+        if starts.is_empty() {
+            return vec![(example, String::from("Main"))];
+        }
+        starts.iter().enumerate().map(|(i, (start, name))| {
+            let end = starts.get(i + 1).map_or(example.len(), |(next, _)| *next);
+            (&example[*start..end], name.clone())
+        }).collect()
+    }
+
+    /// The full text printed by `spruce explain`
+    pub fn explain(&self) -> String {
+        let example: Vec<String> = self.example().lines().map(|l| format!("    {}", l)).collect();
+        format!("{}: {}\n\nFor example:\n\n{}\n", self.as_str(), self.explanation(), example.join("\n"))
+    }
+}
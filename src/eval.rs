@@ -0,0 +1,884 @@
+/*
+The interpreter runs a checked program directly, rather than compiling it to
+JavaScript first. It walks the typed AST, giving each call a frame that holds
+the values of its arguments and variables by symbol, while the program-level
+definitions live in one more frame that every call can see. Spruce has no
+nested functions, so that's all the scope a call needs. A call in tail
+position doesn't recurse: the body hands it back to the call it's in, which
+makes it in that call's place, so loops written as recursion run in constant
+stack.
+
+The builtins the prelude declares are implemented here as well as in the js
+target's runtime, and behave the same way in both, down to the random numbers a seed
+gives and the order Dicts and Sets keep their keys in. Any other builtin is
+a host function, which the runtime calls.
+*/
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::embed::HostFn;
+use crate::error::SpruceErr;
+use crate::error_codes::ErrorCode;
+use crate::heap::{self, Obj};
+use crate::name_analysis as na;
+use crate::parser::{NodeInfo, Span};
+use crate::registry::LangValue;
+use crate::source::NO_FILE;
+use crate::trace::Phase;
+use crate::typecheck::{self, BodyNode, CaseBody, CaseNode, Expr, ExprNode, Stmt, StmtNode};
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Str(Rc<str>),
+    // a constructor and its arguments
+    ADT(na::ADTValID, Rc<Obj<Vec<Value>>>),
+    // a top-level function or a builtin
+    Func(na::SymbolID),
+    // `first >> second`, which applies first and then second to the result
+    Composed(Rc<Obj<(Value, Value)>>),
+    // entries and values are kept sorted, by `compare`
+    Dict(Rc<Vec<(Value, Value)>>),
+    Set(Rc<Vec<Value>>),
+    Unit
+}
+
+/// The values of a call's arguments and variables, by symbol
+pub type Frame = HashMap<na::SymbolID, Value>;
+
+/// What a body in tail position ends with: its value, or a call whose result
+/// is its value, which is left for the caller to make
+enum Tail {
+    Done(Option<Value>),
+    Call(na::SymbolID, Vec<Value>, NodeInfo)
+}
+
+/// The state a running program's builtins keep, whichever engine runs it
+pub struct Runtime {
+    args: Vec<String>,
+    random_state: u32,
+    // the host functions of an embedding program, by the builtin each is
+    host: HashMap<String, Rc<HostFn>>,
+    // how many more calls can be made while working out the program's
+    // definitions as it compiles, which is None for a run
+    budget: Option<usize>
+}
+
+/// The builtins that do something besides give a value, which can't be
+/// called while the program's definitions are worked out as it compiles
+pub const EFFECTS: [&str; 9] = ["print", "printLine", "debug", "readLine", "readFile", "writeFile", "args", "randomInt", "setSeed"];
+
+impl Runtime {
+    /// The program sees `args` as its command-line arguments, and its random
+    /// numbers are the same every run if there is a seed
+    pub fn new(args: Vec<String>, seed: Option<u32>) -> Self {
+        let default_seed = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_millis() as u32);
+        Runtime {
+            args: args,
+            random_state: seed.unwrap_or(default_seed),
+            host: HashMap::new(),
+            budget: None
+        }
+    }
+
+    /// A runtime for working out the program's definitions as it compiles,
+    /// which refuses effects and stops after `calls` calls
+    pub fn constant(calls: usize) -> Self {
+        Runtime {
+            args: Vec::new(),
+            random_state: 0,
+            host: HashMap::new(),
+            budget: Some(calls)
+        }
+    }
+
+    /// The runtime, calling the functions in `host` for the builtins they're
+    /// named after
+    pub fn with_host(mut self, host: HashMap<String, Rc<HostFn>>) -> Self {
+        self.host = host;
+        self
+    }
+
+    // mulberry32, just as the js runtime has it
+    fn random(&mut self) -> f64 {
+        self.random_state = self.random_state.wrapping_add(0x6D2B79F5);
+        let mut t = self.random_state;
+        t = (t ^ (t >> 15)).wrapping_mul(t | 1);
+        t ^= t.wrapping_add((t ^ (t >> 7)).wrapping_mul(t | 61));
+        (t ^ (t >> 14)) as f64 / 4294967296.0
+    }
+}
+
+/// What the builtins need of the engine running them, since some of them
+/// call back into the program
+pub trait Machine<'a> {
+    fn prog(&self) -> &'a na::Prog;
+    fn runtime(&mut self) -> &mut Runtime;
+    fn apply(&mut self, func: &Value, args: Vec<Value>, info: &NodeInfo) -> Result<Value, SpruceErr>;
+}
+
+/// Functions that have been compiled to something faster than walking them,
+/// which the interpreter calls instead when it can
+pub trait Natives {
+    /// None if `id` wasn't compiled. An error's trace has the calls made
+    /// from the compiled code, ending with `id`
+    fn call(&self, prog: &na::Prog, id: na::SymbolID, args: &[Value]) -> Option<Result<Value, SpruceErr>>;
+}
+
+/// Something watching the interpreter run a program, as a debugger does.
+/// It's told as each call of a function starts and ends, and before each
+/// statement runs, and each expression that gives a body its value, along
+/// with the variables of the call it's in. An error stops the run
+pub trait Observer {
+    fn enter(&mut self, prog: &na::Prog, id: na::SymbolID, info: &NodeInfo) -> Result<(), SpruceErr>;
+    /// `result` is nothing if the call failed, or ended in a call in tail
+    /// position, which takes its place
+    fn leave(&mut self, prog: &na::Prog, id: na::SymbolID, result: Option<&Value>);
+    fn reach(&mut self, prog: &na::Prog, frame: &Frame, info: &NodeInfo) -> Result<(), SpruceErr>;
+}
+
+struct Interpreter<'a> {
+    prog: &'a na::Prog,
+    functions: HashMap<na::SymbolID, &'a typecheck::FuncNode>,
+    builtins: HashMap<na::SymbolID, &'a str>,
+    globals: Frame,
+    runtime: Runtime,
+    natives: Option<&'a dyn Natives>,
+    observer: Option<&'a mut dyn Observer>,
+    // the calls being made, outermost first, each with where it was made,
+    // for the stack traces of errors
+    calls: Vec<(na::SymbolID, NodeInfo)>,
+    // where the stack was when the outermost call was made
+    stack_base: usize
+}
+
+/// How much of the stack a run's calls can take up. Runs are given 1 GiB,
+/// and what's left is for the innermost call, which can go on to recurse
+/// through a deep expression or a builtin
+const STACK_LIMIT: usize = 1 << 29;
+
+/// The name of each builtin, which is what it's implemented by. An
+/// interface's signatures only stand in for another module, and can't be
+/// run, but every other signature is a builtin
+pub fn builtins(prog: &na::Prog) -> HashMap<na::SymbolID, &str> {
+    let interface_files: Vec<_> = prog.modules.iter().filter(|module| module.interface).map(|module| module.file).collect();
+    prog.signatures.iter().filter(|sig| !interface_files.contains(&sig.info.file)).map(|sig| {
+        (sig.val.name, symbol_name(prog, sig.val.name))
+    }).collect()
+}
+
+/// Runs the function named `entry`, which takes no arguments, once the
+/// program's definitions have been evaluated
+pub fn run_prog(prog: &na::Prog, typed: &typecheck::Prog, entry: &str, runtime: Runtime) -> Result<Value, SpruceErr> {
+    run_with_natives(prog, typed, entry, runtime, None)
+}
+
+/// Runs the program as `run_prog` does, calling any of the functions that
+/// `natives` has compiled rather than interpreting them
+pub fn run_with_natives<'a>(prog: &'a na::Prog, typed: &'a typecheck::Prog, entry: &str, runtime: Runtime, natives: Option<&'a dyn Natives>) -> Result<Value, SpruceErr> {
+    let mut interp = Interpreter::start(prog, typed, runtime, natives, None)?;
+    let func = entry_point(prog, typed, entry)?;
+    interp.call(func.val.name, Vec::new(), &func.info)
+}
+
+/// Runs the program as `run_prog` does, telling `observer` what it does,
+/// from the program's definitions on
+pub fn run_observed<'a>(prog: &'a na::Prog, typed: &'a typecheck::Prog, entry: &str, runtime: Runtime, observer: &'a mut dyn Observer) -> Result<Value, SpruceErr> {
+    let mut interp = Interpreter::start(prog, typed, runtime, None, Some(observer))?;
+    let func = entry_point(prog, typed, entry)?;
+    interp.call(func.val.name, Vec::new(), &func.info)
+}
+
+/// Evaluates the program's definitions in order, as a run does before it
+/// starts, giving what each binds, or the definition that failed and why
+pub fn definitions<'t>(prog: &na::Prog, typed: &'t typecheck::Prog, runtime: Runtime) -> Result<Vec<(na::SymbolID, Value)>, (&'t StmtNode, SpruceErr)> {
+    let mut interp = Interpreter::new(prog, typed, runtime, None, None);
+    let mut values = Vec::new();
+    for def in &typed.definitions {
+        let mut frame = Frame::new();
+        interp.exec_stmt(&mut frame, def).map_err(|e| (def, e))?;
+        values.extend(frame.iter().map(|(id, val)| (*id, val.clone())));
+        interp.globals.extend(frame);
+    }
+    Ok(values)
+}
+
+/// What stops working out the program's definitions as it compiles, without
+/// anything being wrong with them, since the program could still run. It's
+/// a note rather than an error
+fn undecided(message: String, info: &NodeInfo) -> SpruceErr {
+    SpruceErr::note(message, info.clone())
+}
+
+/// Calls the function named `name` with `args`, once the program's
+/// definitions have been evaluated
+pub fn call_fn(prog: &na::Prog, typed: &typecheck::Prog, name: &str, args: Vec<Value>, runtime: Runtime) -> Result<Value, SpruceErr> {
+    call_observed(prog, typed, name, args, runtime, None)
+}
+
+/// Calls the function named `name` as `call_fn` does, telling `observer`, if
+/// there is one, what the call and the program's definitions do
+pub fn call_observed<'a>(prog: &'a na::Prog, typed: &'a typecheck::Prog, name: &str, args: Vec<Value>, runtime: Runtime, observer: Option<&'a mut dyn Observer>) -> Result<Value, SpruceErr> {
+    let mut interp = Interpreter::start(prog, typed, runtime, None, observer)?;
+    match typed.functions.iter().find(|func| symbol_name(prog, func.val.name) == name) {
+        Some(func) if func.val.args.len() == args.len() => interp.call(func.val.name, args, &func.info),
+        Some(func) => Err(SpruceErr::new(format!("'{}' takes {} arguments, not {}", name, func.val.args.len(), args.len()), func.info.clone())),
+        None => Err(SpruceErr::new(format!("there is no function '{}' to call", name), no_info()))
+    }
+}
+
+/// The function a run starts from, which can't take arguments
+pub fn entry_point<'t>(prog: &na::Prog, typed: &'t typecheck::Prog, entry: &str) -> Result<&'t typecheck::FuncNode, SpruceErr> {
+    match typed.functions.iter().find(|func| symbol_name(prog, func.val.name) == entry) {
+        Some(func) if func.val.args.is_empty() => Ok(func),
+        Some(func) => Err(SpruceErr::new(format!("'{}' can't be run, since it takes arguments", entry), func.info.clone())),
+        None => Err(SpruceErr::new(format!("there is no function '{}' to run", entry), no_info()))
+    }
+}
+
+/// Where errors that aren't from any part of the source say they are
+pub fn no_info() -> NodeInfo {
+    NodeInfo { span: Span { start: 0, end: 0 }, file: NO_FILE }
+}
+
+pub fn symbol_name(prog: &na::Prog, id: na::SymbolID) -> &str {
+    prog.symbol_table.lookup_id(&id).map_or("", |sym| sym.name.as_str())
+}
+
+/// The error for a symbol that has no value when it's needed
+pub fn missing(prog: &na::Prog, id: na::SymbolID, info: &NodeInfo) -> SpruceErr {
+    if prog.signatures.iter().any(|sig| sig.val.name == id) {
+        SpruceErr::new(format!("'{}' can't be run, since only an interface declares it", symbol_name(prog, id)), info.clone())
+    }
+    else {
+        SpruceErr::new(format!("'{}' is used before it has a value", symbol_name(prog, id)), info.clone())
+    }
+}
+
+/// A value as it would be written in Spruce, as the `show` builtin gives it
+pub fn show(prog: &na::Prog, value: &Value) -> String {
+    let join = |values: &mut dyn Iterator<Item=&Value>| values.map(|val| show(prog, val)).collect::<Vec<String>>().join(", ");
+    match value {
+        Value::Int(n) => n.to_string(),
+        Value::Float(x) if x.is_infinite() => String::from(if *x > 0.0 { "Infinity" } else { "-Infinity" }),
+        Value::Float(x) => x.to_string(),
+        Value::Str(text) => text.to_string(),
+        Value::ADT(id, args) if args.is_empty() => constructor_name(prog, *id).to_string(),
+        Value::ADT(id, args) => format!("{}({})", constructor_name(prog, *id), join(&mut args.iter())),
+        Value::Func(id) => format!("<function {}>", symbol_name(prog, *id)),
+        Value::Composed(_) => String::from("<function>"),
+        Value::Dict(entries) => {
+            let entries: Vec<String> = entries.iter().map(|(key, val)| format!("{}: {}", show(prog, key), show(prog, val))).collect();
+            format!("Dict({})", entries.join(", "))
+        }
+        Value::Set(values) => format!("Set({})", join(&mut values.iter())),
+        Value::Unit => String::from("()")
+    }
+}
+
+fn constructor_name(prog: &na::Prog, id: na::ADTValID) -> &str {
+    prog.type_table.values.get(&id).map_or("", |val| val.name.as_str())
+}
+
+/// Orders any two values of the same type: numbers and text by value, and
+/// values of types by constructor name and then by argument
+pub fn compare(prog: &na::Prog, a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Int(a), Value::Int(b)) => a.cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.total_cmp(b),
+        (Value::Str(a), Value::Str(b)) => a.cmp(b),
+        (Value::ADT(a, a_args), Value::ADT(b, b_args)) => {
+            constructor_name(prog, *a).cmp(constructor_name(prog, *b)).then_with(|| compare_all(prog, a_args, b_args))
+        }
+        (Value::Dict(a), Value::Dict(b)) => {
+            let keys = |entries: &Vec<(Value, Value)>| entries.iter().flat_map(|(key, val)| vec![key.clone(), val.clone()]).collect::<Vec<Value>>();
+            compare_all(prog, &keys(a), &keys(b))
+        }
+        (Value::Set(a), Value::Set(b)) => compare_all(prog, a, b),
+        (Value::Func(a), Value::Func(b)) => a.cmp(b),
+        // anything else has different types, which the typechecker never
+        // lets be compared
+        _ => Ordering::Equal
+    }
+}
+
+fn compare_all(prog: &na::Prog, a: &[Value], b: &[Value]) -> Ordering {
+    a.iter().zip(b.iter()).map(|(a, b)| compare(prog, a, b)).find(|order| *order != Ordering::Equal).unwrap_or(a.len().cmp(&b.len()))
+}
+
+pub fn constructor(prog: &na::Prog, item: LangValue, args: Vec<Value>) -> Value {
+    Value::ADT(prog.registry.value_id(item), heap::alloc(args))
+}
+
+pub fn bool(prog: &na::Prog, b: bool) -> Value {
+    constructor(prog, if b { LangValue::True } else { LangValue::False }, Vec::new())
+}
+
+fn maybe(prog: &na::Prog, val: Option<Value>) -> Value {
+    match val {
+        Some(val) => constructor(prog, LangValue::Just, vec![val]),
+        None => constructor(prog, LangValue::Nothing, Vec::new())
+    }
+}
+
+fn result(prog: &na::Prog, res: io::Result<Value>) -> Value {
+    match res {
+        Ok(val) => constructor(prog, LangValue::Ok, vec![val]),
+        Err(err) => constructor(prog, LangValue::Err, vec![Value::Str(Rc::from(err.to_string()))])
+    }
+}
+
+pub fn list(prog: &na::Prog, values: Vec<Value>) -> Value {
+    values.into_iter().rev().fold(constructor(prog, LangValue::Nil, Vec::new()), |rest, val| {
+        constructor(prog, LangValue::Cons, vec![val, rest])
+    })
+}
+
+fn list_items(prog: &na::Prog, mut list: &Value) -> Vec<Value> {
+    let cons = prog.registry.value_id(LangValue::Cons);
+    let mut items = Vec::new();
+    while let Value::ADT(id, args) = list {
+        if *id != cons {
+            break;
+        }
+        items.push(args[0].clone());
+        list = &args[1];
+    }
+    items
+}
+
+impl<'a> Machine<'a> for Interpreter<'a> {
+    fn prog(&self) -> &'a na::Prog {
+        self.prog
+    }
+
+    fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    fn apply(&mut self, func: &Value, args: Vec<Value>, info: &NodeInfo) -> Result<Value, SpruceErr> {
+        match func {
+            Value::Func(id) => self.call(*id, args, info),
+            Value::Composed(funcs) => {
+                let result = self.apply(&funcs.0, args, info)?;
+                self.apply(&funcs.1, vec![result], info)
+            }
+            _ => Err(SpruceErr::ice(Phase::Eval, format!("called {}, which isn't a function", show(self.prog, func)), info.clone()))
+        }
+    }
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(prog: &'a na::Prog, typed: &'a typecheck::Prog, runtime: Runtime, natives: Option<&'a dyn Natives>, observer: Option<&'a mut dyn Observer>) -> Self {
+        Interpreter {
+            prog: prog,
+            functions: typed.functions.iter().map(|func| (func.val.name, func)).collect(),
+            builtins: builtins(prog),
+            globals: Frame::new(),
+            runtime: runtime,
+            natives: natives,
+            observer: observer,
+            calls: Vec::new(),
+            stack_base: 0
+        }
+    }
+
+    /// An interpreter for the program, with its definitions evaluated
+    fn start(prog: &'a na::Prog, typed: &'a typecheck::Prog, runtime: Runtime, natives: Option<&'a dyn Natives>, observer: Option<&'a mut dyn Observer>) -> Result<Self, SpruceErr> {
+        let mut interp = Interpreter::new(prog, typed, runtime, natives, observer);
+        for def in &typed.definitions {
+            let mut frame = Frame::new();
+            interp.exec_stmt(&mut frame, def)?;
+            interp.globals.extend(frame);
+        }
+        Ok(interp)
+    }
+
+    fn lookup(&self, frame: &Frame, id: na::SymbolID, info: &NodeInfo) -> Result<Value, SpruceErr> {
+        if let Some(val) = frame.get(&id).or_else(|| self.globals.get(&id)) {
+            return Ok(val.clone());
+        }
+        if self.functions.contains_key(&id) || self.builtins.contains_key(&id) {
+            return Ok(Value::Func(id));
+        }
+        Err(self.missing(id, info))
+    }
+
+    /// The error for a symbol that has no value, unless the definitions are
+    /// being worked out and it's declared by an interface, which only the
+    /// run will have
+    fn missing(&self, id: na::SymbolID, info: &NodeInfo) -> SpruceErr {
+        if self.runtime.budget.is_some() && self.prog.signatures.iter().any(|sig| sig.val.name == id) {
+            return undecided(format!("'{}' is only declared by an interface", symbol_name(self.prog, id)), info);
+        }
+        missing(self.prog, id, info)
+    }
+
+    fn call(&mut self, id: na::SymbolID, args: Vec<Value>, info: &NodeInfo) -> Result<Value, SpruceErr> {
+        // a call in tail position takes the place of the one it's in, so
+        // the trace has it called from where that one was
+        let site = info.clone();
+        // the stack each call takes up depends on what its body does, so how
+        // deep the calls can go is measured in the stack they use, rather
+        // than in how many of them there are
+        let here = &site as *const NodeInfo as usize;
+        if self.calls.is_empty() {
+            self.stack_base = here;
+        }
+        else if self.stack_base.abs_diff(here) > STACK_LIMIT {
+            let err = SpruceErr::new(format!("stack overflow, {} calls deep", self.calls.len()), site.clone());
+            return Err(err.with_trace(self.trace(&site)));
+        }
+        let (mut id, mut args, mut info) = (id, args, info.clone());
+        loop {
+            match &mut self.runtime.budget {
+                Some(0) => return Err(undecided(String::from("working it out takes too many calls"), &info)),
+                Some(calls) => *calls -= 1,
+                None => ()
+            }
+            if let Some(result) = self.natives.and_then(|natives| natives.call(self.prog, id, &args)) {
+                // native code gives the calls it failed in, and the ones
+                // being interpreted are outside of them
+                return result.map_err(|mut err| {
+                    let mut trace = std::mem::take(&mut err.trace);
+                    trace.extend(self.trace(&site));
+                    err.with_trace(trace)
+                });
+            }
+            if let Some(func) = self.functions.get(&id).copied() {
+                trace!(Phase::Eval, "calling {}", symbol_name(self.prog, id));
+                if let Some(observer) = self.observer.as_mut() {
+                    observer.enter(self.prog, id, &info)?;
+                }
+                let mut frame: Frame = func.val.args.iter().copied().zip(args).collect();
+                self.calls.push((id, site.clone()));
+                let tail = self.eval_tail(&mut frame, &func.val.body).map_err(|err| {
+                    // only the innermost call sees the whole stack
+                    if err.is_error() && err.trace.is_empty() {
+                        let trace = self.trace(&err.info);
+                        err.with_trace(trace)
+                    }
+                    else {
+                        err
+                    }
+                });
+                self.calls.pop();
+                if let Some(observer) = self.observer.as_mut() {
+                    let result = match &tail {
+                        Ok(Tail::Done(val)) => Some(val.as_ref().unwrap_or(&Value::Unit)),
+                        _ => None
+                    };
+                    observer.leave(self.prog, id, result);
+                }
+                match tail? {
+                    Tail::Done(val) => return Ok(val.unwrap_or(Value::Unit)),
+                    Tail::Call(next, next_args, next_info) => {
+                        id = next;
+                        args = next_args;
+                        info = next_info;
+                        continue;
+                    }
+                }
+            }
+            if let Some(name) = self.builtins.get(&id).copied() {
+                return call_builtin(self, name, args, &info);
+            }
+            return Err(self.missing(id, &info));
+        }
+    }
+
+    /// Tells the observer, if there is one, that what's at `info` is about
+    /// to run
+    fn reach(&mut self, frame: &Frame, info: &NodeInfo) -> Result<(), SpruceErr> {
+        match self.observer.as_mut() {
+            Some(observer) => observer.reach(self.prog, frame, info),
+            None => Ok(())
+        }
+    }
+
+    /// Makes the call a body in tail position ends with, if any
+    fn finish(&mut self, tail: Tail) -> Result<Option<Value>, SpruceErr> {
+        match tail {
+            Tail::Done(val) => Ok(val),
+            Tail::Call(id, args, info) => Ok(Some(self.call(id, args, &info)?))
+        }
+    }
+
+    /// The calls being made, innermost first, as the function and where it
+    /// had got to, which for the innermost is `info`
+    fn trace(&self, info: &NodeInfo) -> Vec<(String, NodeInfo)> {
+        let mut at = info;
+        let mut trace = Vec::new();
+        for (id, site) in self.calls.iter().rev() {
+            trace.push((String::from(symbol_name(self.prog, *id)), at.clone()));
+            at = site;
+        }
+        trace
+    }
+
+    /// Runs a body in tail position, leaving a call it ends with unmade. The
+    /// value of a body is that of its final expression, or else that of its
+    /// last statement
+    fn eval_tail(&mut self, frame: &mut Frame, body: &BodyNode) -> Result<Tail, SpruceErr> {
+        let stmts = &body.val.stmts;
+        match &body.val.expr {
+            Some(expr) => {
+                for stmt in stmts {
+                    self.exec_stmt(frame, stmt)?;
+                }
+                self.reach(frame, &expr.info)?;
+                self.expr_tail(frame, expr)
+            }
+            None => {
+                let (last, rest) = match stmts.split_last() {
+                    Some(split) => split,
+                    None => return Ok(Tail::Done(None))
+                };
+                for stmt in rest {
+                    self.exec_stmt(frame, stmt)?;
+                }
+                if let Stmt::FnCall(_, _) | Stmt::Case(_) = last.val {
+                    self.reach(frame, &last.info)?;
+                }
+                match &last.val {
+                    Stmt::FnCall(id, args) => {
+                        let args = args.iter().map(|arg| self.eval(frame, arg)).collect::<Result<Vec<Value>, SpruceErr>>()?;
+                        self.call_tail(frame, *id, args, &last.info)
+                    }
+                    Stmt::Case(case) => self.case_tail(frame, case),
+                    Stmt::Assign(_, _) => Ok(Tail::Done(self.exec_stmt(frame, last)?))
+                }
+            }
+        }
+    }
+
+    fn expr_tail(&mut self, frame: &mut Frame, expr: &ExprNode) -> Result<Tail, SpruceErr> {
+        match &expr.val {
+            Expr::FnCall(id, args) => {
+                let args = args.iter().map(|arg| self.eval(frame, arg)).collect::<Result<Vec<Value>, SpruceErr>>()?;
+                self.call_tail(frame, *id, args, &expr.info)
+            }
+            _ => Ok(Tail::Done(Some(self.eval(frame, expr)?)))
+        }
+    }
+
+    fn exec_stmt(&mut self, frame: &mut Frame, stmt: &StmtNode) -> Result<Option<Value>, SpruceErr> {
+        self.reach(frame, &stmt.info)?;
+        match &stmt.val {
+            Stmt::Assign(tgt, expr) => {
+                let val = self.eval(frame, expr)?;
+                let id = tgt.val.id();
+                // updates can reach program-level `mut` definitions
+                match tgt.val {
+                    na::Target::Update(_) if !frame.contains_key(&id) && self.globals.contains_key(&id) => {
+                        self.globals.insert(id, val.clone());
+                    }
+                    _ => {
+                        frame.insert(id, val.clone());
+                    }
+                }
+                Ok(Some(val))
+            }
+            Stmt::FnCall(id, args) => {
+                let args = args.iter().map(|arg| self.eval(frame, arg)).collect::<Result<Vec<Value>, SpruceErr>>()?;
+                Ok(Some(self.call_value(frame, *id, args, &stmt.info)?))
+            }
+            Stmt::Case(case) => {
+                let tail = self.case_tail(frame, case)?;
+                self.finish(tail)
+            }
+        }
+    }
+
+    /// Runs the option of a case that matches, in tail position
+    fn case_tail(&mut self, frame: &mut Frame, case: &CaseNode) -> Result<Tail, SpruceErr> {
+        let val = self.eval(frame, &case.val.expr)?;
+        for opt in &case.val.options {
+            let matched = match (&opt.val.pattern.val, &val) {
+                (na::CasePattern::ADT(id, names), Value::ADT(val_id, args)) if id == val_id => {
+                    for (name, arg) in names.iter().zip(args.iter()) {
+                        frame.insert(*name, arg.clone());
+                    }
+                    true
+                }
+                (na::CasePattern::Lit(lit), Value::Int(n)) => *lit == *n,
+                (na::CasePattern::Any, _) => true,
+                _ => false
+            };
+
+            if matched {
+                return match &opt.val.body.val {
+                    CaseBody::Expr(expr) => {
+                        self.reach(frame, &expr.info)?;
+                        self.expr_tail(frame, expr)
+                    }
+                    CaseBody::Body(body) => self.eval_tail(frame, body)
+                };
+            }
+        }
+
+        Err(SpruceErr::new(format!("no option matches {}", show(self.prog, &val)), case.val.expr.info.clone()))
+    }
+
+    /// A call in tail position to what `id` names, left for the caller to
+    /// make when it's a function, since only a composition has to be applied
+    /// here
+    fn call_tail(&mut self, frame: &Frame, id: na::SymbolID, args: Vec<Value>, info: &NodeInfo) -> Result<Tail, SpruceErr> {
+        match frame.get(&id).or_else(|| self.globals.get(&id)).cloned() {
+            Some(Value::Func(func)) => Ok(Tail::Call(func, args, info.clone())),
+            Some(func) => Ok(Tail::Done(Some(self.apply(&func, args, info)?))),
+            None => Ok(Tail::Call(id, args, info.clone()))
+        }
+    }
+
+    /// Calls what `id` names, which is either a function or a variable
+    /// holding one
+    fn call_value(&mut self, frame: &Frame, id: na::SymbolID, args: Vec<Value>, info: &NodeInfo) -> Result<Value, SpruceErr> {
+        match frame.get(&id).or_else(|| self.globals.get(&id)).cloned() {
+            Some(func) => self.apply(&func, args, info),
+            None => self.call(id, args, info)
+        }
+    }
+
+    fn int(&mut self, frame: &mut Frame, expr: &ExprNode) -> Result<i64, SpruceErr> {
+        match self.eval(frame, expr)? {
+            Value::Int(n) => Ok(n),
+            val => Err(SpruceErr::ice(Phase::Eval, format!("expected an Int, found {}", show(self.prog, &val)), expr.info.clone()))
+        }
+    }
+
+    fn eval(&mut self, frame: &mut Frame, expr: &ExprNode) -> Result<Value, SpruceErr> {
+        let info = &expr.info;
+        let val = match &expr.val {
+            Expr::Add(l, r) => Value::Int(self.int(frame, l)?.wrapping_add(self.int(frame, r)?)),
+            Expr::Subt(l, r) => Value::Int(self.int(frame, l)?.wrapping_sub(self.int(frame, r)?)),
+            Expr::Mult(l, r) => Value::Int(self.int(frame, l)?.wrapping_mul(self.int(frame, r)?)),
+            Expr::Div(l, r) | Expr::Mod(l, r) => {
+                let (left, right) = (self.int(frame, l)?, self.int(frame, r)?);
+                if right == 0 {
+                    return Err(SpruceErr::new(String::from("division by zero"), info.clone()));
+                }
+                match expr.val {
+                    Expr::Div(_, _) => Value::Int(left.wrapping_div(right)),
+                    _ => Value::Int(left.wrapping_rem(right))
+                }
+            }
+            Expr::Pow(l, r) => {
+                let (base, exp) = (self.int(frame, l)?, self.int(frame, r)?);
+                if exp < 0 {
+                    return Err(SpruceErr::new(format!("cannot raise an Int to the negative power {}", exp), info.clone()));
+                }
+                Value::Int(base.wrapping_pow(exp.min(u32::MAX as i64) as u32))
+            }
+            Expr::BitAnd(l, r) => Value::Int(self.int(frame, l)? & self.int(frame, r)?),
+            Expr::BitOr(l, r) => Value::Int(self.int(frame, l)? | self.int(frame, r)?),
+            Expr::BitXor(l, r) => Value::Int(self.int(frame, l)? ^ self.int(frame, r)?),
+            Expr::Shl(l, r) => Value::Int(self.int(frame, l)?.wrapping_shl(self.int(frame, r)? as u32)),
+            Expr::Shr(l, r) => Value::Int(self.int(frame, l)?.wrapping_shr(self.int(frame, r)? as u32)),
+            Expr::Eq(l, r) | Expr::NotEq(l, r) => {
+                let (left, right) = (self.eval(frame, l)?, self.eval(frame, r)?);
+                let equal = compare(self.prog, &left, &right) == Ordering::Equal;
+                bool(self.prog, if let Expr::Eq(_, _) = expr.val { equal } else { !equal })
+            }
+            Expr::Lt(l, r) => { let b = self.int(frame, l)? < self.int(frame, r)?; bool(self.prog, b) }
+            Expr::Gt(l, r) => { let b = self.int(frame, l)? > self.int(frame, r)?; bool(self.prog, b) }
+            Expr::LtEq(l, r) => { let b = self.int(frame, l)? <= self.int(frame, r)?; bool(self.prog, b) }
+            Expr::GtEq(l, r) => { let b = self.int(frame, l)? >= self.int(frame, r)?; bool(self.prog, b) }
+            Expr::ComposeR(first, second) | Expr::ComposeL(second, first) => {
+                let (first, second) = (self.eval(frame, first)?, self.eval(frame, second)?);
+                let composed = Value::Composed(heap::alloc((first, second)));
+                heap::check(info)?;
+                composed
+            }
+            Expr::Lit(lit) => Value::Int(*lit),
+            Expr::Neg(inner) => Value::Int(self.int(frame, inner)?.wrapping_neg()),
+            Expr::List(elements) => {
+                let values = elements.iter().map(|elem| self.eval(frame, elem)).collect::<Result<Vec<Value>, SpruceErr>>()?;
+                let val = list(self.prog, values);
+                heap::check(info)?;
+                val
+            }
+            Expr::Id(id) => self.lookup(frame, *id, info)?,
+            Expr::FnCall(id, args) => {
+                let args = args.iter().map(|arg| self.eval(frame, arg)).collect::<Result<Vec<Value>, SpruceErr>>()?;
+                self.call_value(frame, *id, args, info)?
+            }
+            Expr::ADTVal(id, args) => {
+                let args = args.iter().map(|arg| self.eval(frame, arg)).collect::<Result<Vec<Value>, SpruceErr>>()?;
+                let val = Value::ADT(*id, heap::alloc(args));
+                heap::check(info)?;
+                val
+            }
+            Expr::Error => return Err(SpruceErr::ice(Phase::Eval, String::from("ran an expression that failed to typecheck"), info.clone()))
+        };
+        Ok(val)
+    }
+}
+
+/// Where `key` is among sorted `keys`, or where it would go if it isn't
+fn search<'v>(prog: &na::Prog, keys: impl Iterator<Item=&'v Value>, key: &Value) -> (usize, bool) {
+    // keys are few enough to search in order
+    let mut i = 0;
+    for k in keys {
+        match compare(prog, k, key) {
+            Ordering::Less => i += 1,
+            Ordering::Equal => return (i, true),
+            Ordering::Greater => break
+        }
+    }
+    (i, false)
+}
+
+/// Runs the builtin named `name`, which is the same whichever engine calls it
+pub fn call_builtin<'a, M: Machine<'a>>(machine: &mut M, name: &str, args: Vec<Value>, info: &NodeInfo) -> Result<Value, SpruceErr> {
+    trace!(Phase::Eval, "calling builtin {}", name);
+    if machine.runtime().budget.is_some() && EFFECTS.contains(&name) {
+        return Err(SpruceErr::new(format!("'{}' can't be called by a definition, which is worked out as the program compiles", name), info.clone())
+            .with_code(ErrorCode::EffectInDefinition));
+    }
+    let prog = machine.prog();
+    let val = match (name, args.as_slice()) {
+        ("show", [val]) => Value::Str(Rc::from(show(prog, val))),
+        ("compare", [a, b]) => {
+            let order = match compare(prog, a, b) {
+                Ordering::Less => LangValue::LT,
+                Ordering::Equal => LangValue::EQ,
+                Ordering::Greater => LangValue::GT
+            };
+            constructor(prog, order, Vec::new())
+        }
+        ("parseInt", [Value::Str(text)]) => {
+            let digits = text.strip_prefix('-').unwrap_or(text);
+            let valid = !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit());
+            maybe(prog, if valid { text.parse().ok().map(Value::Int) } else { None })
+        }
+        ("parseFloat", [Value::Str(text)]) => {
+            let valid = text.chars().next().map_or(false, |c| c.is_ascii_digit() || c == '-') && !text.ends_with('.');
+            maybe(prog, if valid { text.parse().ok().map(Value::Float) } else { None })
+        }
+        ("abs", [Value::Int(n)]) => Value::Int(n.wrapping_abs()),
+        ("min", [Value::Int(a), Value::Int(b)]) => Value::Int(*a.min(b)),
+        ("max", [Value::Int(a), Value::Int(b)]) => Value::Int(*a.max(b)),
+        ("toFloat", [Value::Int(n)]) => Value::Float(*n as f64),
+        ("sqrt", [Value::Float(x)]) => Value::Float(x.sqrt()),
+        ("floor", [Value::Float(x)]) => Value::Int(x.floor() as i64),
+        ("ceil", [Value::Float(x)]) => Value::Int(x.ceil() as i64),
+        ("sin", [Value::Float(x)]) => Value::Float(x.sin()),
+        ("cos", [Value::Float(x)]) => Value::Float(x.cos()),
+        ("tan", [Value::Float(x)]) => Value::Float(x.tan()),
+        ("emptyDict", []) => Value::Dict(Rc::new(Vec::new())),
+        ("dictInsert", [Value::Dict(entries), key, val]) => {
+            let (i, found) = search(prog, entries.iter().map(|(k, _)| k), key);
+            let mut entries = (**entries).clone();
+            if found {
+                entries[i] = (key.clone(), val.clone());
+            }
+            else {
+                entries.insert(i, (key.clone(), val.clone()));
+            }
+            Value::Dict(Rc::new(entries))
+        }
+        ("dictGet", [Value::Dict(entries), key]) => {
+            let (i, found) = search(prog, entries.iter().map(|(k, _)| k), key);
+            maybe(prog, if found { Some(entries[i].1.clone()) } else { None })
+        }
+        ("dictRemove", [Value::Dict(entries), key]) => {
+            let (i, found) = search(prog, entries.iter().map(|(k, _)| k), key);
+            let mut entries = (**entries).clone();
+            if found {
+                entries.remove(i);
+            }
+            Value::Dict(Rc::new(entries))
+        }
+        ("dictFold", [Value::Dict(entries), acc, func]) => {
+            let mut acc = acc.clone();
+            for (key, val) in entries.iter() {
+                acc = machine.apply(func, vec![acc, key.clone(), val.clone()], info)?;
+            }
+            acc
+        }
+        ("emptySet", []) => Value::Set(Rc::new(Vec::new())),
+        ("setInsert", [Value::Set(values), val]) => {
+            let (i, found) = search(prog, values.iter(), val);
+            let mut values = (**values).clone();
+            if !found {
+                values.insert(i, val.clone());
+            }
+            Value::Set(Rc::new(values))
+        }
+        ("setMember", [Value::Set(values), val]) => {
+            let (_, found) = search(prog, values.iter(), val);
+            bool(prog, found)
+        }
+        ("setUnion", [Value::Set(a), Value::Set(b)]) | ("setIntersection", [Value::Set(a), Value::Set(b)]) => {
+            let union = name == "setUnion";
+            let mut values: Vec<Value> = a.iter().chain(b.iter()).cloned().collect();
+            values.sort_by(|x, y| compare(prog, x, y));
+            let mut merged: Vec<Value> = Vec::new();
+            for (i, val) in values.iter().enumerate() {
+                let repeat = i > 0 && compare(prog, &values[i - 1], val) == Ordering::Equal;
+                let next_repeats = values.get(i + 1).map_or(false, |next| compare(prog, next, val) == Ordering::Equal);
+                if (union && !repeat) || (!union && next_repeats) {
+                    merged.push(val.clone());
+                }
+            }
+            Value::Set(Rc::new(merged))
+        }
+        ("setFromList", [list]) => {
+            let mut values = list_items(prog, list);
+            values.sort_by(|x, y| compare(prog, x, y));
+            values.dedup_by(|x, y| compare(prog, x, y) == Ordering::Equal);
+            Value::Set(Rc::new(values))
+        }
+        ("print", [Value::Str(text)]) => {
+            print!("{}", text);
+            io::stdout().flush().ok();
+            Value::Unit
+        }
+        ("printLine", [Value::Str(text)]) => {
+            println!("{}", text);
+            Value::Unit
+        }
+        ("debug", [val]) => {
+            eprintln!("debug: {}", show(prog, val));
+            val.clone()
+        }
+        ("readLine", []) => {
+            let mut line = String::new();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) => maybe(prog, None),
+                Ok(_) => {
+                    let line = line.strip_suffix('\n').unwrap_or(&line);
+                    let line = line.strip_suffix('\r').unwrap_or(line);
+                    maybe(prog, Some(Value::Str(Rc::from(line))))
+                }
+            }
+        }
+        ("readFile", [Value::Str(path)]) => result(prog, fs::read_to_string(&**path).map(|text| Value::Str(Rc::from(text)))),
+        ("writeFile", [Value::Str(path), Value::Str(text)]) => result(prog, fs::write(&**path, &**text).map(|_| Value::Unit)),
+        ("args", []) => {
+            let args = machine.runtime().args.iter().map(|arg| Value::Str(Rc::from(arg.as_str()))).collect();
+            list(prog, args)
+        }
+        ("randomInt", [Value::Int(low), Value::Int(high)]) => {
+            let (low, high) = (*low, *high);
+            Value::Int(low + (machine.runtime().random() * (high - low + 1) as f64).floor() as i64)
+        }
+        ("setSeed", [Value::Int(seed)]) => {
+            machine.runtime().random_state = *seed as u32;
+            Value::Unit
+        }
+        _ => match machine.runtime().host.get(name).cloned() {
+            Some(func) => func(prog, &args).map_err(|message| SpruceErr::new(message, info.clone()))?,
+            None if machine.runtime().budget.is_some() => return Err(undecided(format!("'{}' is the host's", name), info)),
+            None => return Err(SpruceErr::new(format!("the interpreter has no builtin '{}'", name), info.clone()))
+        }
+    };
+    Ok(val)
+}
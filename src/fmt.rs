@@ -0,0 +1,520 @@
+/*
+The formatter prints a file back out in the style Spruce is written in, so
+that how a program is laid out is never something to argue over:
+
+  - declarations and statements go one to a line, indented four spaces a
+    level, with a blank line around every function and type, and a blank
+    line kept wherever the source had one, though never more than one
+  - operators get a space either side and commas one after, and parentheses
+    are only kept where the operators' precedence needs them
+  - a call or a list too wide for its line has its arguments put one to a
+    line, and a case arm too wide for its line becomes a block
+  - comments stay next to what they were next to: those on lines of their
+    own before it, and those at the end of its line after it. A line with a
+    comment inside it is left as it was written
+
+Numbers and patterns are written as they were in the source, since the AST
+only keeps what they stand for.
+*/
+
+use std::collections::HashSet;
+
+use crate::error::SpruceErr;
+use crate::parser::{self, CaseBody, CaseOptionNode, CasePattern, CasePatternNode, CommentNode, Expr, ExprNode, FuncNode, SigType, Span, Stmt, StmtNode, Target, TypeIdentifier, TypeNode};
+use crate::source::SourceMap;
+
+/// How wide a line can be before what's on it is broken over several
+pub const WIDTH: usize = 80;
+
+const INDENT: usize = 4;
+
+/// The file formatted, or as it was if it doesn't parse
+pub fn format_source(text: &str) -> String {
+    let mut sources = SourceMap::new();
+    sources.add("main", text);
+    format_file(&sources).unwrap_or_else(|_| String::from(text))
+}
+
+/// The one file in `sources` formatted, or the errors parsing it found
+pub fn format_file(sources: &SourceMap) -> Result<String, Vec<SpruceErr>> {
+    let prog = parser::parse(sources)?;
+    let text = sources.files().next().map_or("", |(_, source)| source.text.as_str());
+
+    let mut items: Vec<(usize, Item)> = Vec::new();
+    if let Some(module) = prog.modules.first().filter(|module| module.info.span.end > 0) {
+        items.push((module.info.span.start, Item::Line(module.info.span.clone(), Line::Module)));
+    }
+    for import in prog.modules.iter().flat_map(|module| module.val.imports.iter()) {
+        items.push((import.info.span.start, Item::Line(import.info.span.clone(), Line::Import(&import.val.module))));
+    }
+    items.extend(prog.types.iter().map(|t| (t.info.span.start, Item::Type(t))));
+    items.extend(prog.functions.iter().map(|func| (func.info.span.start, Item::Func(func))));
+    items.extend(prog.definitions.iter().map(|def| (def.info.span.start, Item::Def(def))));
+    for sig in &prog.signatures {
+        let (start, keyword, doc) = builtin_start(text, sig.info.span.start);
+        let span = Span { start: keyword, end: sig.info.span.end };
+        items.push((start, Item::Builtin(doc, span, &sig.val.name, &sig.val.ty)));
+    }
+    items.sort_by_key(|(start, _)| *start);
+
+    // the scan for comments also finds the end of every doc comment, which
+    // is printed with what it documents instead
+    let mut comments: Vec<&CommentNode> = prog.comments.iter().filter(|comment| {
+        !text[..comment.info.span.start].ends_with('/')
+    }).collect();
+    comments.sort_by_key(|comment| comment.info.span.start);
+    let mut printer = Printer {
+        text: text,
+        comments: comments,
+        next: 0,
+        tails: prog.tail_calls.iter().map(|info| (info.span.start, info.span.end)).collect(),
+        out: String::new(),
+        pos: 0
+    };
+
+    let mut after_block = false;
+    for (start, item) in &items {
+        let block = matches!(item, Item::Type(t) if !t.val.builtin) || matches!(item, Item::Func(_));
+        let lead = printer.comments.get(printer.next).map_or(*start, |comment| comment.info.span.start.min(*start));
+        printer.gap(lead, block || after_block);
+        printer.comments_before(*start, 0);
+        printer.gap(*start, false);
+        match item {
+            Item::Line(span, line) => printer.leaf(0, span, |printer| match line {
+                Line::Module => printer.module(&prog.modules[0].val),
+                Line::Import(module) => format!("import {}", module)
+            }),
+            Item::Type(t) => printer.type_decl(t),
+            Item::Func(func) => printer.func(func),
+            Item::Def(def) => printer.stmt(def, 0),
+            Item::Builtin(doc, span, name, ty) => {
+                printer.docs(doc.as_ref(), 0);
+                printer.pos = span.start;
+                printer.leaf(0, span, |_| format!("builtin {} : {}", name, sig_type(ty)));
+            }
+        }
+        after_block = block;
+    }
+    printer.comments_before(text.len(), 0);
+    Ok(printer.out)
+}
+
+enum Line<'a> {
+    Module,
+    Import(&'a String)
+}
+
+enum Item<'a> {
+    // something that fits on one line
+    Line(Span, Line<'a>),
+    Type(&'a TypeNode),
+    Func(&'a FuncNode),
+    Def(&'a StmtNode),
+    // the doc comment, where the `builtin` starts and the signature
+    Builtin(Option<String>, Span, &'a String, &'a SigType)
+}
+
+/// Where a builtin's declaration starts, counting its doc comment, where
+/// its `builtin` keyword is, and the doc comment, since the AST only keeps
+/// its signature
+fn builtin_start(text: &str, sig: usize) -> (usize, usize, Option<String>) {
+    let keyword = text[..sig].rfind("builtin").unwrap_or(sig);
+    let mut start = text[..keyword].rfind('\n').map_or(0, |i| i + 1);
+    let mut lines = Vec::new();
+    while start > 0 {
+        let line_start = text[..start - 1].rfind('\n').map_or(0, |i| i + 1);
+        let line = text[line_start..start - 1].trim();
+        match line.strip_prefix("///") {
+            Some(doc) if !doc.starts_with('/') => lines.push(doc.strip_prefix(' ').unwrap_or(doc)),
+            _ => break
+        }
+        start = line_start;
+    }
+    lines.reverse();
+    let doc = if lines.is_empty() { None } else { Some(lines.join("\n")) };
+    (start, keyword, doc)
+}
+
+/// Whether there's a blank line between two places in the source
+fn blank_between(text: &str, from: usize, to: usize) -> bool {
+    let lines: Vec<&str> = text[from..to].split('\n').collect();
+    lines.len() > 2 && lines[1..lines.len() - 1].iter().any(|line| line.trim().is_empty())
+}
+
+/// A type as written in a type declaration
+pub fn type_id(ty: &TypeIdentifier) -> String {
+    if ty.args.is_empty() {
+        return ty.name.clone();
+    }
+    let args: Vec<String> = ty.args.iter().map(|arg| type_id(arg)).collect();
+    format!("{}({})", ty.name, args.join(", "))
+}
+
+pub fn sig_type(ty: &SigType) -> String {
+    match ty {
+        SigType::Named(name, args) if args.is_empty() => name.clone(),
+        SigType::Named(name, args) => format!("{}({})", name, args.iter().map(sig_type).collect::<Vec<String>>().join(", ")),
+        SigType::Func(args, out) => format!("({}) -> {}", args.iter().map(sig_type).collect::<Vec<String>>().join(", "), sig_type(out)),
+        SigType::Unit => String::from("()")
+    }
+}
+
+/// The operands and operator of a binary expression
+pub fn binary(expr: &Expr) -> Option<(&ExprNode, &'static str, &ExprNode)> {
+    let (left, op, right) = match expr {
+        Expr::Add(left, right) => (left, "+", right),
+        Expr::Subt(left, right) => (left, "-", right),
+        Expr::Mult(left, right) => (left, "*", right),
+        Expr::Div(left, right) => (left, "/", right),
+        Expr::Pow(left, right) => (left, "^", right),
+        Expr::Mod(left, right) => (left, "%", right),
+        Expr::Eq(left, right) => (left, "==", right),
+        Expr::NotEq(left, right) => (left, "!=", right),
+        Expr::LtEq(left, right) => (left, "<=", right),
+        Expr::GtEq(left, right) => (left, ">=", right),
+        Expr::Lt(left, right) => (left, "<", right),
+        Expr::Gt(left, right) => (left, ">", right),
+        Expr::ComposeR(left, right) => (left, ">>", right),
+        Expr::ComposeL(left, right) => (left, "<<", right),
+        Expr::Cons(left, right) => (left, "::", right),
+        Expr::BitAnd(left, right) => (left, "&", right),
+        Expr::BitOr(left, right) => (left, "|", right),
+        Expr::BitXor(left, right) => (left, "^^^", right),
+        Expr::Shl(left, right) => (left, "<<<", right),
+        Expr::Shr(left, right) => (left, ">>>", right),
+        _ => return None
+    };
+    Some((left, op, right))
+}
+
+/// How tightly an operator binds, as the parser climbs them, and whether it
+/// groups to the right
+pub fn precedence(op: &str) -> (u8, bool) {
+    match op {
+        ">>" => (0, false),
+        "<<" => (0, true),
+        "==" | "!=" => (1, false),
+        "<=" | ">=" | "<" | ">" => (2, false),
+        "::" => (3, true),
+        "|" => (4, false),
+        "^^^" => (5, false),
+        "&" => (6, false),
+        "<<<" | ">>>" => (7, false),
+        "%" => (8, false),
+        "+" | "-" => (9, false),
+        "*" | "/" => (10, false),
+        _ => (11, true)
+    }
+}
+
+/// Whether `operand` of the operator `op` needs parentheses to be parsed as
+/// its operand. Operators that bind as tightly as each other group as the
+/// one after the operand says: to the left unless it groups to the right
+pub fn needs_parens(op: &str, operand: &ExprNode, on_right: bool) -> bool {
+    let inner = match binary(&operand.val) {
+        Some((_, inner, _)) => inner,
+        None => return false
+    };
+    let (level, right_assoc) = precedence(op);
+    let (inner_level, inner_right_assoc) = precedence(inner);
+    inner_level < level || (inner_level == level && if on_right { !inner_right_assoc } else { right_assoc })
+}
+
+struct Printer<'a> {
+    text: &'a str,
+    // the file's comments in order, of which those from `next` on are still
+    // to be printed
+    comments: Vec<&'a CommentNode>,
+    next: usize,
+    // the spans of the calls marked `@tail`
+    tails: HashSet<(usize, usize)>,
+    out: String,
+    // where in the source what's been printed so far ends
+    pos: usize
+}
+
+impl<'a> Printer<'a> {
+    fn line(&mut self, indent: usize, line: &str) {
+        self.out.push_str(&" ".repeat(indent * INDENT));
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    /// Leaves a blank line before what starts at `start` if the source had
+    /// one, or if `force`, other than at the start of a block
+    fn gap(&mut self, start: usize, force: bool) {
+        let at_start = self.out.is_empty() || self.out.ends_with("{\n") || self.out.ends_with("\n\n");
+        if !at_start && (force || blank_between(self.text, self.pos, start)) {
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints the comments before `pos` on lines of their own
+    fn comments_before(&mut self, pos: usize, indent: usize) {
+        while let Some(comment) = self.comments.get(self.next).copied().filter(|comment| comment.info.span.start < pos) {
+            self.gap(comment.info.span.start, false);
+            self.line(indent, &comment.val.text);
+            self.pos = comment.info.span.end;
+            self.next += 1;
+        }
+    }
+
+    /// The comment at the end of the line that what's been printed ends on,
+    /// if it has one, to follow it
+    fn trailing(&mut self) -> String {
+        match self.comments.get(self.next) {
+            Some(comment) if comment.info.span.start >= self.pos && !self.text[self.pos..comment.info.span.start].contains('\n') => {
+                self.next += 1;
+                self.pos = comment.info.span.end;
+                format!(" {}", comment.val.text)
+            }
+            _ => String::new()
+        }
+    }
+
+    /// Prints what's at `span` on a line of its own, as `render` gives it,
+    /// unless it has a comment inside it
+    fn leaf(&mut self, indent: usize, span: &Span, render: impl FnOnce(&Self) -> String) {
+        self.comments_before(span.start, indent);
+        self.gap(span.start, false);
+        let inside = self.comments[self.next..].iter().take_while(|comment| comment.info.span.start < span.end).count();
+        let line = if inside > 0 {
+            self.next += inside;
+            String::from(self.text[span.start..span.end].trim())
+        }
+        else {
+            render(self)
+        };
+        self.pos = span.end;
+        let trailing = self.trailing();
+        self.line(indent, &format!("{}{}", line, trailing));
+    }
+
+    /// Ends a block whose closing brace is at `close`
+    fn close(&mut self, indent: usize, close: usize) {
+        self.comments_before(close, indent + 1);
+        self.pos = close + 1;
+        let trailing = self.trailing();
+        self.line(indent, &format!("}}{}", trailing));
+    }
+
+    /// Opens a block whose opening brace is at `open`
+    fn open(&mut self, indent: usize, header: &str, open: usize) {
+        self.pos = open + 1;
+        let trailing = self.trailing();
+        self.line(indent, &format!("{} {{{}", header, trailing));
+    }
+
+    fn docs(&mut self, doc: Option<&String>, indent: usize) {
+        for line in doc.into_iter().flat_map(|doc| doc.split('\n')) {
+            self.line(indent, format!("/// {}", line).trim_end());
+        }
+    }
+
+    fn module(&self, module: &parser::Module) -> String {
+        match &module.exports {
+            Some(exports) => {
+                let names: Vec<String> = exports.iter().map(|export| {
+                    format!("{}{}", export.val.name, if export.val.constructors { "(..)" } else { "" })
+                }).collect();
+                format!("module {} ({})", module.name, names.join(", "))
+            }
+            None => format!("module {}", module.name)
+        }
+    }
+
+    fn type_decl(&mut self, t: &TypeNode) {
+        self.docs(t.val.doc.as_ref(), 0);
+        let params = if t.val.type_params.is_empty() { String::new() } else { format!("({})", t.val.type_params.join(", ")) };
+        let header = format!("type {}{}", t.val.name, params);
+        if t.val.builtin {
+            let keyword = self.after_docs(t.info.span.start);
+            self.pos = keyword;
+            self.leaf(0, &Span { start: keyword, end: t.info.span.end }, |_| format!("builtin {}", header));
+            return;
+        }
+
+        let open = self.after_docs(t.info.span.start) + self.text[self.after_docs(t.info.span.start)..].find('{').unwrap_or(0);
+        self.open(0, &header, open);
+        for option in &t.val.options {
+            self.leaf(1, &option.info.span, |_| {
+                let args: Vec<String> = option.val.args.iter().map(type_id).collect();
+                if args.is_empty() { option.val.name.clone() } else { format!("{}({})", option.val.name, args.join(", ")) }
+            });
+        }
+        self.close(0, t.info.span.end - 1);
+    }
+
+    /// Where a declaration starts once past its doc comment
+    fn after_docs(&self, start: usize) -> usize {
+        let mut pos = start;
+        while self.text[pos..].trim_start_matches([' ', '\t']).starts_with("///") {
+            pos += self.text[pos..].find('\n').map_or(self.text.len() - pos, |i| i + 1);
+        }
+        pos
+    }
+
+    fn func(&mut self, func: &FuncNode) {
+        self.docs(func.val.doc.as_ref(), 0);
+        let body = &func.val.body;
+        let open = self.text[..body.info.span.start].rfind('{').unwrap_or(body.info.span.start);
+        let header = match parser::test_name(&func.val.name) {
+            Some(_) => func.val.name.clone(),
+            None => format!("{}({})", func.val.name, func.val.args.join(", "))
+        };
+        self.open(0, &header, open);
+        self.body(body, 1);
+        self.close(0, func.info.span.end - 1);
+    }
+
+    fn body(&mut self, body: &parser::BodyNode, indent: usize) {
+        for stmt in &body.val.stmts {
+            self.stmt(stmt, indent);
+        }
+        if let Some(expr) = &body.val.expr {
+            self.leaf(indent, &expr.info.span, |printer| printer.expr(expr, indent * INDENT, indent, true));
+        }
+    }
+
+    fn stmt(&mut self, stmt: &StmtNode, indent: usize) {
+        match &stmt.val {
+            Stmt::Assign(target, expr) => self.leaf(indent, &stmt.info.span, |printer| {
+                let target = match &target.val {
+                    Target::Var(name) => format!("{} = ", name),
+                    Target::Mutable(name) => format!("mut {} = ", name),
+                    Target::Update(name) => format!("{} := ", name)
+                };
+                format!("{}{}", target, printer.expr(expr, indent * INDENT + target.len(), indent, true))
+            }),
+            Stmt::FnCall(name, args) => self.leaf(indent, &stmt.info.span, |printer| {
+                let args: Vec<&ExprNode> = args.iter().collect();
+                printer.call(printer.tails.contains(&(stmt.info.span.start, stmt.info.span.end)), name, &args, indent * INDENT, indent, true)
+            }),
+            Stmt::Case(case) => {
+                self.comments_before(stmt.info.span.start, indent);
+                self.gap(stmt.info.span.start, false);
+                let scrutinee = &case.val.expr;
+                let header = format!("case {}", self.expr(scrutinee, indent * INDENT + 5, indent, true));
+                let open = scrutinee.info.span.end + self.text[scrutinee.info.span.end..].find('{').unwrap_or(0);
+                self.open(indent, &header, open);
+                for option in &case.val.options {
+                    self.case_option(option, indent + 1);
+                }
+                self.close(indent, case.info.span.end - 1);
+            }
+        }
+    }
+
+    fn case_option(&mut self, option: &CaseOptionNode, indent: usize) {
+        let pattern = self.pattern(&option.val.pattern);
+        match &option.val.body.val {
+            CaseBody::Expr(expr) => {
+                let span = Span { start: option.info.span.start, end: expr.info.span.end };
+                let flat = format!("{} -> {}", pattern, self.expr(expr, 0, indent, false));
+                let inside = self.comments[self.next..].iter().any(|comment| comment.info.span.start >= span.start && comment.info.span.start < span.end);
+                if inside || indent * INDENT + flat.len() <= WIDTH {
+                    self.leaf(indent, &span, |printer| format!("{} -> {}", pattern, printer.expr(expr, indent * INDENT + pattern.len() + 4, indent, true)));
+                    return;
+                }
+
+                // too wide for one line, so the arm becomes a block
+                self.comments_before(span.start, indent);
+                self.gap(span.start, false);
+                self.line(indent, &format!("{} -> {{", pattern));
+                self.leaf(indent + 1, &expr.info.span, |printer| printer.expr(expr, (indent + 1) * INDENT, indent + 1, true));
+                self.line(indent, "}");
+            }
+            CaseBody::Body(body) => {
+                self.comments_before(option.info.span.start, indent);
+                self.gap(option.info.span.start, false);
+                let open = self.text[..body.info.span.start].rfind('{').unwrap_or(body.info.span.start);
+                self.open(indent, &format!("{} ->", pattern), open);
+                self.body(body, indent + 1);
+                let close = self.text[..option.info.span.end].rfind('}').unwrap_or(option.info.span.end);
+                self.close(indent, close);
+            }
+        }
+    }
+
+    fn pattern(&self, pattern: &CasePatternNode) -> String {
+        let span = &pattern.info.span;
+        match &pattern.val {
+            // `x :: rest` is kept as it was written rather than as the
+            // constructor it stands for
+            CasePattern::ADT(name, args) if name == "Cons" && self.text[span.start..span.end].contains("::") => {
+                format!("{} :: {}", args[0], args[1])
+            }
+            CasePattern::ADT(name, args) if args.is_empty() => name.clone(),
+            CasePattern::ADT(name, args) => format!("{}({})", name, args.join(", ")),
+            CasePattern::Lit(_) => self.literal(span),
+            CasePattern::Any => String::from("_")
+        }
+    }
+
+    fn literal(&self, span: &Span) -> String {
+        self.text[span.start..span.end].chars().filter(|c| !c.is_whitespace()).collect()
+    }
+
+    /// An expression starting at column `col`, broken over lines indented
+    /// from `indent` where it's too wide, if `wrap`
+    fn expr(&self, expr: &ExprNode, col: usize, indent: usize, wrap: bool) -> String {
+        match &expr.val {
+            Expr::Lit(_) => self.literal(&expr.info.span),
+            Expr::Id(name) => name.clone(),
+            Expr::FnCall(name, args) => {
+                let args: Vec<&ExprNode> = args.iter().map(|arg| arg.as_ref()).collect();
+                self.call(self.tails.contains(&(expr.info.span.start, expr.info.span.end)), name, &args, col, indent, wrap)
+            }
+            Expr::List(elements) => {
+                let elements: Vec<&ExprNode> = elements.iter().map(|element| element.as_ref()).collect();
+                self.args("[", &elements, "]", col, indent, wrap)
+            }
+            Expr::Neg(inner) => format!("-{}", self.operand(inner, binary(&inner.val).is_some(), col + 1, indent, wrap)),
+            _ => {
+                let (left, op, right) = binary(&expr.val).expect("every other expression is binary");
+                let left = self.operand(left, needs_parens(op, left, false), col, indent, wrap);
+                let left_end = match left.rfind('\n') {
+                    Some(i) => left.len() - i - 1,
+                    None => col + left.len()
+                };
+                let right = self.operand(right, needs_parens(op, right, true), left_end + op.len() + 2, indent, wrap);
+                format!("{} {} {}", left, op, right)
+            }
+        }
+    }
+
+    fn operand(&self, expr: &ExprNode, parens: bool, col: usize, indent: usize, wrap: bool) -> String {
+        if parens {
+            format!("({})", self.expr(expr, col + 1, indent, wrap))
+        }
+        else {
+            self.expr(expr, col, indent, wrap)
+        }
+    }
+
+    fn call(&self, tail: bool, name: &str, args: &[&ExprNode], col: usize, indent: usize, wrap: bool) -> String {
+        let open = format!("{}{}(", if tail { "@tail " } else { "" }, name);
+        self.args(&open, args, ")", col, indent, wrap)
+    }
+
+    /// Arguments or elements between `open` and `close`, one to a line if
+    /// they don't fit on the line together
+    fn args(&self, open: &str, args: &[&ExprNode], close: &str, col: usize, indent: usize, wrap: bool) -> String {
+        let flat: Vec<String> = args.iter().map(|arg| self.expr(arg, 0, indent, false)).collect();
+        let flat = format!("{}{}{}", open, flat.join(", "), close);
+        if !wrap || args.is_empty() || col + flat.len() <= WIDTH {
+            return flat;
+        }
+
+        let inner = indent + 1;
+        let mut broken = format!("{}\n", open);
+        for (i, arg) in args.iter().enumerate() {
+            broken.push_str(&" ".repeat(inner * INDENT));
+            broken.push_str(&self.expr(arg, inner * INDENT, inner, true));
+            broken.push_str(if i + 1 < args.len() { ",\n" } else { "\n" });
+        }
+        broken.push_str(&" ".repeat(indent * INDENT));
+        broken.push_str(close);
+        broken
+    }
+}
@@ -0,0 +1,234 @@
+/*
+Constant folding works out, before the program runs, what it can of the
+arithmetic and comparisons whose operands are known, and propagates what it
+knows through the variables bound to it:
+
+  - a variable bound once to an atom stands for that atom, and one bound once
+    to a constructor stands for that constructor, so its fields are known
+  - an operation on Ints that are known becomes its result, unless it can
+    fail, like dividing by zero, which is left for the program to report, or
+    its result doesn't fit the 63 bits the compiled targets keep for an Int
+  - a case on a known value becomes the arm it takes
+
+Variables standing for a `mut` are bound again by each update, and possibly
+in an arm, so nothing is known about them. The lets this leaves unused are
+kept, since it's only their uses that change.
+*/
+
+use std::collections::HashMap;
+
+use crate::anf::{Atom, Block, Function, Pattern, Prim, Program, Stmt, Value, Var};
+use crate::name_analysis as na;
+use crate::registry::LangValue;
+
+// the Ints every target holds exactly, since the compiled ones tag theirs
+const MIN_INT: i64 = -(1 << 62);
+const MAX_INT: i64 = (1 << 62) - 1;
+
+#[derive(Debug, Clone)]
+enum Known {
+    Atom(Atom),
+    Construct(na::ADTValID, Vec<Atom>)
+}
+
+struct Folder<'a> {
+    prog: &'a na::Prog,
+    // whether each variable is bound only once, parameters included
+    once: Vec<bool>,
+    known: HashMap<Var, Known>
+}
+
+/// Folds the constants in every function of the program
+pub fn compile(prog: &na::Prog, program: &mut Program) {
+    for func in program.functions.iter_mut().chain(std::iter::once(&mut program.init)) {
+        function(prog, func);
+    }
+}
+
+fn function(prog: &na::Prog, func: &mut Function) {
+    let mut bindings = vec![0; func.vars.len()];
+    for param in &func.params {
+        bindings[*param as usize] += 1;
+    }
+    count_bindings(&func.body, &mut bindings);
+    let mut folder = Folder {
+        prog: prog,
+        once: bindings.iter().map(|count| *count == 1).collect(),
+        known: HashMap::new()
+    };
+    folder.block(&mut func.body);
+}
+
+fn count_bindings(body: &Block, bindings: &mut [usize]) {
+    for stmt in &body.stmts {
+        if let Stmt::Let(var, val) = stmt {
+            bindings[*var as usize] += 1;
+            count_value(val, bindings);
+        }
+    }
+    count_value(&body.result, bindings);
+}
+
+fn count_value(val: &Value, bindings: &mut [usize]) {
+    if let Value::Case(case) = val {
+        for arm in &case.arms {
+            count_bindings(&arm.body, bindings);
+        }
+        if let Some(default) = &case.default {
+            count_bindings(default, bindings);
+        }
+    }
+}
+
+impl<'a> Folder<'a> {
+    fn block(&mut self, body: &mut Block) {
+        let mut stmts = Vec::new();
+        for stmt in body.stmts.drain(..) {
+            match stmt {
+                Stmt::Let(var, val) => {
+                    let val = self.value(val, &mut stmts);
+                    self.learn(var, &val);
+                    stmts.push(Stmt::Let(var, val));
+                }
+                Stmt::SetGlobal(id, atom) => stmts.push(Stmt::SetGlobal(id, self.atom(atom)))
+            }
+        }
+        let result = std::mem::replace(&mut body.result, Value::Atom(Atom::Unit));
+        body.result = self.value(result, &mut stmts);
+        body.stmts = stmts;
+    }
+
+    /// Remembers what a variable is bound to, if it stays bound to it
+    fn learn(&mut self, var: Var, val: &Value) {
+        if !self.once[var as usize] {
+            return;
+        }
+        let known = match val {
+            Value::Atom(atom) if self.stable(atom) => Known::Atom(atom.clone()),
+            Value::Construct(id, atoms) if atoms.iter().all(|atom| self.stable(atom)) => Known::Construct(*id, atoms.clone()),
+            _ => return
+        };
+        self.known.insert(var, known);
+    }
+
+    /// Whether an atom means the same thing wherever it's used
+    fn stable(&self, atom: &Atom) -> bool {
+        match atom {
+            Atom::Var(var) => self.once[*var as usize],
+            _ => true
+        }
+    }
+
+    fn atom(&self, atom: Atom) -> Atom {
+        match &atom {
+            Atom::Var(var) => match self.known.get(var) {
+                Some(Known::Atom(known)) => known.clone(),
+                _ => atom
+            },
+            _ => atom
+        }
+    }
+
+    fn atoms(&self, atoms: Vec<Atom>) -> Vec<Atom> {
+        atoms.into_iter().map(|atom| self.atom(atom)).collect()
+    }
+
+    /// The value folded, where a case that takes a known arm leaves that
+    /// arm's statements in `stmts`
+    fn value(&mut self, val: Value, stmts: &mut Vec<Stmt>) -> Value {
+        match val {
+            Value::Atom(atom) => Value::Atom(self.atom(atom)),
+            Value::Prim(prim, atoms, info) => {
+                let atoms = self.atoms(atoms);
+                match self.prim(prim, &atoms) {
+                    Some(val) => val,
+                    None => Value::Prim(prim, atoms, info)
+                }
+            }
+            Value::Call(id, atoms) => Value::Call(id, self.atoms(atoms)),
+            Value::Apply(func, atoms) => Value::Apply(self.atom(func), self.atoms(atoms)),
+            Value::Construct(id, atoms) => Value::Construct(id, self.atoms(atoms)),
+            Value::Recur(atoms) => Value::Recur(self.atoms(atoms)),
+            Value::Field(atom, i) => {
+                let atom = self.atom(atom);
+                match &atom {
+                    Atom::Var(var) => match self.known.get(var) {
+                        Some(Known::Construct(_, fields)) => Value::Atom(fields[i].clone()),
+                        _ => Value::Field(atom, i)
+                    },
+                    _ => Value::Field(atom, i)
+                }
+            }
+            Value::Global(id) => Value::Global(id),
+            Value::Case(mut case) => {
+                case.scrutinee = self.atom(case.scrutinee);
+                let taken = match &case.scrutinee {
+                    Atom::Int(n) => Some(Pattern::Int(*n)),
+                    Atom::Var(var) => match self.known.get(var) {
+                        Some(Known::Construct(id, _)) => Some(Pattern::Constructor(*id)),
+                        _ => None
+                    },
+                    _ => None
+                };
+                if let Some(pattern) = taken {
+                    let arm = case.arms.iter().position(|arm| arm.pattern == pattern);
+                    let body = match arm {
+                        Some(i) => Some(case.arms.swap_remove(i).body),
+                        None => case.default.take()
+                    };
+                    if let Some(mut body) = body {
+                        self.block(&mut body);
+                        stmts.append(&mut body.stmts);
+                        return body.result;
+                    }
+                }
+                for arm in &mut case.arms {
+                    self.block(&mut arm.body);
+                }
+                if let Some(default) = &mut case.default {
+                    self.block(default);
+                }
+                Value::Case(case)
+            }
+        }
+    }
+
+    /// What an operation on known Ints gives, when it can be worked out
+    fn prim(&self, prim: Prim, atoms: &[Atom]) -> Option<Value> {
+        let ints = atoms.iter().map(|atom| match atom {
+            Atom::Int(n) if (MIN_INT..=MAX_INT).contains(n) => Some(*n),
+            _ => None
+        }).collect::<Option<Vec<i64>>>()?;
+        let n = match (prim, &ints[..]) {
+            (Prim::Add, [l, r]) => l + r,
+            (Prim::Sub, [l, r]) => l - r,
+            (Prim::Mul, [l, r]) => l.checked_mul(*r)?,
+            (Prim::Div, [l, r]) if *r != 0 => l / r,
+            (Prim::Mod, [l, r]) if *r != 0 => l % r,
+            (Prim::Pow, [l, r]) if (0..=u32::MAX as i64).contains(r) => l.checked_pow(*r as u32)?,
+            (Prim::BitAnd, [l, r]) => l & r,
+            (Prim::BitOr, [l, r]) => l | r,
+            (Prim::BitXor, [l, r]) => l ^ r,
+            (Prim::Shl, [l, r]) if (0..63).contains(r) => l.checked_mul(1 << r)?,
+            (Prim::Shr, [l, r]) if (0..63).contains(r) => l >> r,
+            (Prim::Neg, [n]) => -n,
+            (Prim::Lt, [l, r]) => return Some(self.bool(l < r)),
+            (Prim::Gt, [l, r]) => return Some(self.bool(l > r)),
+            (Prim::LtEq, [l, r]) => return Some(self.bool(l <= r)),
+            (Prim::GtEq, [l, r]) => return Some(self.bool(l >= r)),
+            (Prim::Eq, [l, r]) => return Some(self.bool(l == r)),
+            (Prim::NotEq, [l, r]) => return Some(self.bool(l != r)),
+            _ => return None
+        };
+        if (MIN_INT..=MAX_INT).contains(&n) {
+            Some(Value::Atom(Atom::Int(n)))
+        } else {
+            None
+        }
+    }
+
+    fn bool(&self, b: bool) -> Value {
+        let id = self.prog.registry.value_id(if b { LangValue::True } else { LangValue::False });
+        Value::Construct(id, Vec::new())
+    }
+}
@@ -0,0 +1,165 @@
+/*
+Golden tests for the compiler. Each .sp file under a directory is compiled on
+its own, with the prelude, and what the compiler made of it is compared with
+the golden file beside it, which has the same name ending in .golden instead.
+
+What's compared is a snapshot of the compiler's output: every diagnostic it
+gave, rendered as `spruce check` shows them without color, and for a program
+that checks, the types it declares and the type inferred for each of its
+functions and each name bound with `=`, in the order they're written. Names
+bound inside a function are indented beneath it.
+
+In update mode the golden files are written rather than compared, so a change
+to what the compiler says only needs the new goldens looked over. The test
+that runs the goldens updates them when SPRUCE_UPDATE_GOLDEN is set, as in
+
+    SPRUCE_UPDATE_GOLDEN=1 cargo test golden
+*/
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::interface;
+use crate::lint::Lints;
+use crate::name_analysis as na;
+use crate::source::{FileId, SourceMap};
+use crate::typecheck::{self, Environment, Stmt};
+
+/// Whether golden files are compared with or replaced by what the compiler
+/// gives now
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Mode {
+    Check,
+    Update
+}
+
+/// A program whose snapshot isn't what its golden file says
+#[derive(Debug, PartialEq)]
+pub struct Mismatch {
+    pub path: PathBuf,
+    // nothing if there's no golden file yet
+    pub expected: Option<String>,
+    pub actual: String
+}
+
+impl Mismatch {
+    /// Where the snapshot and the golden file first differ, as lines of
+    /// each, with those from the golden file marked `-` and the snapshot `+`
+    pub fn describe(&self) -> String {
+        let expected = match &self.expected {
+            Some(expected) => expected,
+            None => return format!("{} has no golden file, and gave\n{}", self.path.display(), self.actual)
+        };
+        let (old, new): (Vec<&str>, Vec<&str>) = (expected.lines().collect(), self.actual.lines().collect());
+        let same = old.iter().zip(new.iter()).take_while(|(a, b)| a == b).count();
+        let mut description = format!("{} differs from its golden file from line {}:\n", self.path.display(), same + 1);
+        for line in old.iter().skip(same) {
+            description.push_str(&format!("-{}\n", line));
+        }
+        for line in new.iter().skip(same) {
+            description.push_str(&format!("+{}\n", line));
+        }
+        description
+    }
+}
+
+/// Compiles each program under `dir` and compares its snapshot with its
+/// golden file, or writes the golden file in update mode, giving the
+/// programs that didn't match in order of path
+pub fn run_dir(dir: &Path, mode: Mode) -> io::Result<Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+    for path in programs(dir)? {
+        let text = fs::read_to_string(&path)?;
+        let name = path.strip_prefix(dir).unwrap_or(&path).to_string_lossy().into_owned();
+        let actual = snapshot(&name, &text);
+        let golden = path.with_extension("golden");
+        match mode {
+            Mode::Update => fs::write(&golden, &actual)?,
+            Mode::Check => {
+                let expected = fs::read_to_string(&golden).ok();
+                if expected.as_ref() != Some(&actual) {
+                    mismatches.push(Mismatch { path: path, expected: expected, actual: actual });
+                }
+            }
+        }
+    }
+    Ok(mismatches)
+}
+
+/// The .sp files under `dir`, at any depth, sorted
+fn programs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut found = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            found.extend(programs(&path)?);
+        }
+        else if path.extension().map_or(false, |ext| ext == "sp") {
+            found.push(path);
+        }
+    }
+    found.sort();
+    Ok(found)
+}
+
+/// What the compiler makes of the program `text`, read as the file `name`
+pub fn snapshot(name: &str, text: &str) -> String {
+    let mut sources = SourceMap::new();
+    sources.add(na::PRELUDE_FILE, na::PRELUDE);
+    let file = sources.add(name, text);
+
+    let mut lints = Lints::new();
+    let result = crate::compile_with_lints(&sources, &mut lints);
+    let errors = match &result {
+        Ok(_) => &[][..],
+        Err(errors) => &errors[..]
+    };
+    let mut output = String::new();
+    for diagnostic in errors.iter().chain(lints.warnings.iter()) {
+        output.push_str(&diagnostic.as_str(&sources));
+        output.push('\n');
+    }
+    if let Ok((prog, typed, env)) = &result {
+        output.push_str(&declarations(prog, typed, env, file));
+    }
+    output
+}
+
+/// The types the file declares, and the types of its functions and of the
+/// names it binds with `=`, in the order they're written
+fn declarations(prog: &na::Prog, typed: &typecheck::Prog, env: &Environment, file: FileId) -> String {
+    let module = match prog.modules.iter().find(|module| module.file == file) {
+        Some(module) => module,
+        None => return String::new()
+    };
+    let name = |id: na::SymbolID| prog.symbol_table.lookup_id(&id).map_or(String::new(), |sym| sym.name.clone());
+    let mut found: Vec<(usize, String)> = Vec::new();
+
+    for t in prog.types.iter().filter(|t| t.info.file == file) {
+        if let Some(adt) = prog.type_table.types.values().find(|adt| adt.name == t.val.name && adt.module == module.name) {
+            found.push((t.info.span.start, interface::write_type(&prog.type_table, adt)));
+        }
+    }
+
+    let functions: Vec<&typecheck::FuncNode> = typed.functions.iter().filter(|func| func.info.file == file).collect();
+    for func in &functions {
+        let ty = env.type_of_id(func.val.name).map_or_else(|| env.type_str(func.ty), |ty| ty.to_string());
+        found.push((func.info.span.start, format!("{} : {}\n", name(func.val.name), ty)));
+    }
+
+    for stmt in typed.assignments().into_iter().filter(|stmt| stmt.info.file == file) {
+        if let Stmt::Assign(target, expr) = &stmt.val {
+            if let na::Target::Update(_) = target.val {
+                continue;
+            }
+            let ty = env.type_of_id(target.val.id()).map_or_else(|| env.type_str(expr.ty), |ty| ty.to_string());
+            let local = functions.iter().any(|func| func.info.span.start <= stmt.info.span.start && stmt.info.span.end <= func.info.span.end);
+            let indent = if local { "    " } else { "" };
+            found.push((stmt.info.span.start, format!("{}{} : {}\n", indent, name(target.val.id()), ty)));
+        }
+    }
+
+    found.sort_by_key(|(start, _)| *start);
+    found.into_iter().map(|(_, line)| line).collect()
+}
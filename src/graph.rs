@@ -0,0 +1,153 @@
+/*
+`spruce graph` draws a program as Graphviz's dot language, from what name
+analysis makes of it, so a program needn't check to be drawn. There are two
+graphs, side by side:
+
+  - calls: an arrow from each function or definition to each one it uses,
+    whether it calls it or only names it. Definitions are boxes
+  - types: an arrow from each type to each type its constructors hold
+
+Only the program's own declarations are drawn, not the prelude's, nor tests.
+Declarations that use each other, directly or through others, are drawn
+together in a dashed box, as they're checked together, so a group that's
+larger than it should be stands out.
+
+    spruce graph main.sp | dot -Tsvg > main.svg
+*/
+
+use std::collections::HashMap;
+
+use crate::name_analysis::{self as na, TypeID};
+use crate::source::FileId;
+use crate::typecheck;
+
+/// Something drawn, with the nodes it has arrows to
+struct Node {
+    name: String,
+    shape: &'static str,
+    edges: Vec<usize>
+}
+
+/// The program's functions and definitions, and its types, in `files`, as a
+/// dot graph
+pub fn dot(prog: &na::Prog, files: &[FileId]) -> String {
+    let mut out = String::from("digraph program {\n");
+    out.push_str(&cluster("calls", "f", &calls(prog, files)));
+    out.push_str(&cluster("types", "t", &types(prog, files)));
+    out.push_str("}\n");
+    out
+}
+
+/// The name of the module declared in `file`
+fn module(prog: &na::Prog, file: FileId) -> &str {
+    prog.modules.iter().find(|module| module.file == file).map_or("", |module| module.name.as_str())
+}
+
+fn calls(prog: &na::Prog, files: &[FileId]) -> Vec<Node> {
+    let mut found: Vec<(FileId, usize, na::SymbolID, &'static str, Vec<na::SymbolID>)> = Vec::new();
+    for def in prog.definitions.iter().filter(|def| files.contains(&def.info.file)) {
+        if let na::Stmt::Assign(target, _) = &def.val {
+            let mut refs = Vec::new();
+            typecheck::stmt_refs(&def.val, &mut refs);
+            found.push((def.info.file, def.info.span.start, target.val.id(), "box", refs));
+        }
+    }
+    for func in prog.functions.iter().filter(|func| files.contains(&func.info.file) && prog.test_name(&func.val.name).is_none()) {
+        let mut refs = Vec::new();
+        typecheck::body_refs(&func.val.body.val, &mut refs);
+        found.push((func.info.file, func.info.span.start, func.val.name, "ellipse", refs));
+    }
+    found.sort_by_key(|(file, start, _, _, _)| (*file, *start));
+
+    let index: HashMap<na::SymbolID, usize> = found.iter().enumerate().map(|(i, (_, _, id, _, _))| (*id, i)).collect();
+    found.iter().map(|(file, _, id, shape, refs)| Node {
+        name: format!("{}.{}", module(prog, *file), prog.symbol_table.lookup_id(id).map_or("", |sym| sym.name.as_str())),
+        shape: shape,
+        edges: refs.iter().filter_map(|id| index.get(id).copied()).collect()
+    }).collect()
+}
+
+fn types(prog: &na::Prog, files: &[FileId]) -> Vec<Node> {
+    let mut found: Vec<(FileId, usize, &na::ADT)> = Vec::new();
+    for t in prog.types.iter().filter(|t| files.contains(&t.info.file)) {
+        let module = module(prog, t.info.file);
+        if let Some(adt) = prog.type_table.types.values().find(|adt| adt.name == t.val.name && adt.module == module) {
+            found.push((t.info.file, t.info.span.start, adt));
+        }
+    }
+    found.sort_by_key(|(file, start, _)| (*file, *start));
+
+    let index: HashMap<na::ADTID, usize> = found.iter().enumerate().map(|(i, (_, _, adt))| (adt.id, i)).collect();
+    found.iter().map(|(_, _, adt)| {
+        let mut held = Vec::new();
+        let mut constructors: Vec<&na::ADTValue> = prog.type_table.values.values().filter(|value| value.data_type == adt.id).collect();
+        constructors.sort_by_key(|value| value.id);
+        for arg in constructors.iter().flat_map(|value| value.args.iter()) {
+            held_types(arg, &mut held);
+        }
+        Node {
+            name: format!("{}.{}", adt.module, adt.name),
+            shape: "box",
+            edges: held.iter().filter_map(|id| index.get(id).copied()).collect()
+        }
+    }).collect()
+}
+
+/// Collects every type named in `ty`
+fn held_types(ty: &TypeID, held: &mut Vec<na::ADTID>) {
+    match ty {
+        TypeID::ADT(id, args) => {
+            held.push(*id);
+            for arg in args {
+                held_types(arg, held);
+            }
+        }
+        TypeID::Func(args, out) => {
+            for arg in args {
+                held_types(arg, held);
+            }
+            held_types(out, held);
+        }
+        TypeID::TParam(_) | TypeID::Prim(_) | TypeID::Unit => ()
+    }
+}
+
+/// `nodes` as a subgraph called `label`, whose nodes are named starting with
+/// `prefix`, with the groups that reach each other boxed together
+fn cluster(label: &str, prefix: &str, nodes: &[Node]) -> String {
+    let mut out = format!("    subgraph cluster_{} {{\n        label={}\n", label, quote(label));
+    let mut edges: Vec<Vec<usize>> = nodes.iter().map(|node| node.edges.clone()).collect();
+    for edges in &mut edges {
+        edges.sort();
+        edges.dedup();
+    }
+
+    let mut groups: Vec<Vec<usize>> = typecheck::strongly_connected(&edges).into_iter().filter(|group| group.len() > 1).collect();
+    groups.sort();
+    let mut grouped = vec![false; nodes.len()];
+    for node in groups.iter().flatten() {
+        grouped[*node] = true;
+    }
+
+    for (i, node) in nodes.iter().enumerate().filter(|(i, _)| !grouped[*i]) {
+        out.push_str(&format!("        {}{} [label={}, shape={}]\n", prefix, i, quote(&node.name), node.shape));
+    }
+    for (i, group) in groups.iter().enumerate() {
+        out.push_str(&format!("        subgraph cluster_{}_{} {{\n            label=\"\"\n            style=dashed\n", label, i));
+        for node in group {
+            out.push_str(&format!("            {}{} [label={}, shape={}]\n", prefix, node, quote(&nodes[*node].name), nodes[*node].shape));
+        }
+        out.push_str("        }\n");
+    }
+    for (from, targets) in edges.iter().enumerate() {
+        for to in targets {
+            out.push_str(&format!("        {}{} -> {}{}\n", prefix, from, prefix, to));
+        }
+    }
+    out.push_str("    }\n");
+    out
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,107 @@
+/*
+The interpreter and the VM keep a program's values on Rust's heap, and free
+them by counting references. That is all the collection Spruce needs: values
+are never changed once they're built, and a constructor or a composition can
+only hold values built before it, so no value can ever come to refer back to
+itself, and there are no cycles for a tracing collector to find. Everything a
+program builds is freed as soon as the last reference to it goes. A `mut` is
+a variable being bound again rather than a value changing, so it doesn't
+change this, but anything that lets a value be updated in place, or a
+closure refer to itself, would.
+
+What this module adds is accounting. Every constructor value and
+composition is an object counted while it's alive, which lets a run be given
+a limit on how many can be alive at once, past which it fails the way it
+would on running out of memory, and lets a stress run check that every
+object really is freed once the program is done with it. The counts belong
+to the thread running the program, since values never leave it.
+*/
+
+use std::cell::Cell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use crate::error::SpruceErr;
+use crate::parser::NodeInfo;
+
+thread_local! {
+    static LIVE: Cell<usize> = const { Cell::new(0) };
+    static PEAK: Cell<usize> = const { Cell::new(0) };
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static LIMIT: Cell<Option<usize>> = const { Cell::new(None) };
+    static STRESS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Something a value holds that counts as one object on the heap while it's
+/// alive
+#[derive(Debug, PartialEq)]
+pub struct Obj<T>(T);
+
+impl<T> Deref for Obj<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> Drop for Obj<T> {
+    fn drop(&mut self) {
+        LIVE.with(|live| live.set(live.get() - 1));
+    }
+}
+
+/// Counts one more object, shared by reference
+pub fn alloc<T>(val: T) -> Rc<Obj<T>> {
+    let live = LIVE.with(|live| {
+        live.set(live.get() + 1);
+        live.get()
+    });
+    PEAK.with(|peak| peak.set(peak.get().max(live)));
+    ALLOCATED.with(|allocated| allocated.set(allocated.get() + 1));
+    Rc::new(Obj(val))
+}
+
+/// Sets up the counts for a run on this thread, with at most `limit`
+/// objects alive at once if there's a limit, and with `stress`, a report at
+/// the end of the run of how the heap was used and of anything never freed
+pub fn configure(limit: Option<usize>, stress: bool) {
+    LIVE.with(|live| live.set(0));
+    PEAK.with(|peak| peak.set(0));
+    ALLOCATED.with(|allocated| allocated.set(0));
+    LIMIT.with(|cell| cell.set(limit));
+    STRESS.with(|cell| cell.set(stress));
+}
+
+/// Fails if more objects are alive than the limit allows, saying the
+/// program ran out of memory at `info`
+pub fn check(info: &NodeInfo) -> Result<(), SpruceErr> {
+    match LIMIT.with(|limit| limit.get()) {
+        Some(limit) if live() > limit => {
+            let message = format!("out of memory: the heap is limited to {} values alive at once", limit);
+            Err(SpruceErr::new(message, info.clone()))
+        }
+        _ => Ok(())
+    }
+}
+
+/// How many objects are alive
+pub fn live() -> usize {
+    LIVE.with(|live| live.get())
+}
+
+/// The report of a stress run, once the program and its values are gone,
+/// which is an error if any object outlived them. Nothing is reported
+/// outside a stress run
+pub fn report() -> Option<Result<String, String>> {
+    if !STRESS.with(|stress| stress.get()) {
+        return None;
+    }
+    let peak = PEAK.with(|peak| peak.get());
+    let allocated = ALLOCATED.with(|allocated| allocated.get());
+    let summary = format!("heap: {} values built, at most {} alive at once", allocated, peak);
+    match live() {
+        0 => Some(Ok(summary)),
+        leaked => Some(Err(format!("{}, and {} never freed", summary, leaked)))
+    }
+}
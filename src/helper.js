@@ -1,10 +0,0 @@
-// Helper functions for Spruce codegen
-
-function _to_bool(b) {
-    if (b) {
-        return [Bool.TRUE]
-    }
-    else {
-        return [Bool.FALSE]
-    }
-}
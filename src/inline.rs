@@ -0,0 +1,219 @@
+/*
+Inlining replaces calls to small functions with the functions' bodies, so the
+helpers the prelude encourages, like `not` or `withDefault`, cost nothing
+more than what they compute. A function is inlined wherever it's called when:
+
+  - its body is no bigger than the threshold, counting one for each statement
+    and one for its result, and the same for everything in its cases
+  - it can't reach itself, by calling itself or by naming itself as a value,
+    directly or not, so that inlining always ends
+
+Functions are inlined into in the order their callees are finished, so a
+small function that calls another small function is measured and inlined
+with that call already inlined. The parameters become lets of the
+arguments, which folding then propagates, and the inlined variables become
+temporaries of the caller, since a function can be inlined more than once
+into the same caller.
+
+This only reaches the compiled targets, since the interpreter and the VM run
+the typed AST rather than the IR.
+*/
+
+use std::collections::{HashMap, HashSet};
+
+use crate::anf::{Atom, Block, Function, Program, Stmt, Value, Var};
+use crate::name_analysis as na;
+
+/// The size a function can have and still be inlined, unless given with
+/// `--inline-threshold`
+pub const DEFAULT_THRESHOLD: usize = 12;
+
+/// Inlines every call to a function no bigger than `threshold` that can't
+/// reach itself
+pub fn compile(program: &mut Program, threshold: usize) {
+    let calls: HashMap<na::SymbolID, Vec<na::SymbolID>> = program.functions.iter().map(|func| {
+        let mut found = Vec::new();
+        refs(&func.body, &mut found);
+        (func.name.expect("functions are named"), found)
+    }).collect();
+
+    // callees come before their callers, apart from those that can reach
+    // themselves, which are never inlined
+    let mut order = Vec::new();
+    let mut visited = HashSet::new();
+    for func in &program.functions {
+        post_order(func.name.expect("functions are named"), &calls, &mut visited, &mut order);
+    }
+    let recursive: HashSet<na::SymbolID> = program.functions.iter().filter(|func| func.body.recurs()).filter_map(|func| func.name)
+        .chain(calls.keys().filter(|id| reaches(**id, **id, &calls)).copied()).collect();
+
+    let mut finished: HashMap<na::SymbolID, Function> = HashMap::new();
+    for id in order {
+        let index = program.functions.iter().position(|func| func.name == Some(id)).expect("the order only has functions");
+        let func = &mut program.functions[index];
+        inline_into(func, &finished);
+        if !recursive.contains(&id) && size(&func.body) <= threshold {
+            finished.insert(id, func.clone());
+        }
+    }
+    inline_into(&mut program.init, &finished);
+}
+
+fn post_order(id: na::SymbolID, calls: &HashMap<na::SymbolID, Vec<na::SymbolID>>, visited: &mut HashSet<na::SymbolID>, order: &mut Vec<na::SymbolID>) {
+    if !calls.contains_key(&id) || !visited.insert(id) {
+        return;
+    }
+    for callee in &calls[&id] {
+        post_order(*callee, calls, visited, order);
+    }
+    order.push(id);
+}
+
+/// Whether `to` can be reached from what `from` calls or names
+fn reaches(from: na::SymbolID, to: na::SymbolID, calls: &HashMap<na::SymbolID, Vec<na::SymbolID>>) -> bool {
+    let mut seen = HashSet::new();
+    let mut work = calls[&from].clone();
+    while let Some(id) = work.pop() {
+        if id == to {
+            return true;
+        }
+        if seen.insert(id) {
+            if let Some(callees) = calls.get(&id) {
+                work.extend(callees);
+            }
+        }
+    }
+    false
+}
+
+/// The functions a block calls or uses as values
+fn refs(body: &Block, found: &mut Vec<na::SymbolID>) {
+    body.each_atom(&mut |atom| if let Atom::Func(id) = atom {
+        found.push(*id);
+    });
+    let vals = body.stmts.iter().filter_map(|stmt| match stmt {
+        Stmt::Let(_, val) => Some(val),
+        Stmt::SetGlobal(_, _) => None
+    });
+    for val in vals.chain(std::iter::once(&body.result)) {
+        match val {
+            Value::Call(id, _) => found.push(*id),
+            Value::Case(case) => {
+                for arm in &case.arms {
+                    refs(&arm.body, found);
+                }
+                if let Some(default) = &case.default {
+                    refs(default, found);
+                }
+            }
+            _ => ()
+        }
+    }
+}
+
+fn size(body: &Block) -> usize {
+    let stmts: usize = body.stmts.iter().map(|stmt| match stmt {
+        Stmt::Let(_, val) => value_size(val),
+        Stmt::SetGlobal(_, _) => 1
+    }).sum();
+    stmts + value_size(&body.result)
+}
+
+fn value_size(val: &Value) -> usize {
+    match val {
+        Value::Case(case) => 1 + case.arms.iter().map(|arm| size(&arm.body)).sum::<usize>() + case.default.as_ref().map_or(0, size),
+        _ => 1
+    }
+}
+
+fn inline_into(func: &mut Function, finished: &HashMap<na::SymbolID, Function>) {
+    block(&mut func.body, &mut func.vars, finished);
+}
+
+fn block(body: &mut Block, vars: &mut Vec<Option<na::SymbolID>>, finished: &HashMap<na::SymbolID, Function>) {
+    let mut stmts = Vec::new();
+    for stmt in body.stmts.drain(..) {
+        match stmt {
+            Stmt::Let(var, val) => {
+                let val = value(val, &mut stmts, vars, finished);
+                stmts.push(Stmt::Let(var, val));
+            }
+            stmt => stmts.push(stmt)
+        }
+    }
+    let result = std::mem::replace(&mut body.result, Value::Atom(Atom::Unit));
+    body.result = value(result, &mut stmts, vars, finished);
+    body.stmts = stmts;
+}
+
+/// A value with the calls in it inlined, where an inlined body's statements
+/// go in `stmts`
+fn value(val: Value, stmts: &mut Vec<Stmt>, vars: &mut Vec<Option<na::SymbolID>>, finished: &HashMap<na::SymbolID, Function>) -> Value {
+    match val {
+        Value::Call(id, args) if finished.contains_key(&id) => {
+            let callee = &finished[&id];
+            let offset = vars.len() as Var;
+            vars.extend(callee.vars.iter().map(|_| None));
+            for (param, arg) in callee.params.iter().zip(args) {
+                stmts.push(Stmt::Let(param + offset, Value::Atom(arg)));
+            }
+            let mut body = callee.body.clone();
+            rename(&mut body, offset);
+            stmts.append(&mut body.stmts);
+            body.result
+        }
+        Value::Case(mut case) => {
+            for arm in &mut case.arms {
+                block(&mut arm.body, vars, finished);
+            }
+            if let Some(default) = &mut case.default {
+                block(default, vars, finished);
+            }
+            Value::Case(case)
+        }
+        val => val
+    }
+}
+
+/// Moves every variable of a block up by `offset`
+fn rename(body: &mut Block, offset: Var) {
+    for stmt in &mut body.stmts {
+        match stmt {
+            Stmt::Let(var, val) => {
+                *var += offset;
+                rename_value(val, offset);
+            }
+            Stmt::SetGlobal(_, atom) => rename_atom(atom, offset)
+        }
+    }
+    rename_value(&mut body.result, offset);
+}
+
+fn rename_value(val: &mut Value, offset: Var) {
+    match val {
+        Value::Atom(atom) | Value::Field(atom, _) => rename_atom(atom, offset),
+        Value::Prim(_, atoms, _) | Value::Call(_, atoms) | Value::Construct(_, atoms) | Value::Recur(atoms) => {
+            atoms.iter_mut().for_each(|atom| rename_atom(atom, offset));
+        }
+        Value::Apply(func, atoms) => {
+            rename_atom(func, offset);
+            atoms.iter_mut().for_each(|atom| rename_atom(atom, offset));
+        }
+        Value::Global(_) => (),
+        Value::Case(case) => {
+            rename_atom(&mut case.scrutinee, offset);
+            for arm in &mut case.arms {
+                rename(&mut arm.body, offset);
+            }
+            if let Some(default) = &mut case.default {
+                rename(default, offset);
+            }
+        }
+    }
+}
+
+fn rename_atom(atom: &mut Atom, offset: Var) {
+    if let Atom::Var(var) = atom {
+        *var += offset;
+    }
+}
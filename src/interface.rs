@@ -0,0 +1,110 @@
+/*
+An interface file describes a module that has already been checked, so that
+modules importing it can be checked against it without checking it again.
+It is Spruce without any bodies: the module's exports and imports, its types,
+and in place of each exported declaration, that declaration's type:
+
+    module Stack (Stack, push)
+    import Lists
+    type Stack(a) {
+        Empty
+        Push(a, Stack(a))
+    }
+    push : (a, Stack(a)) -> Stack(a)
+*/
+
+use std::path::Path;
+
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+use crate::typecheck::Environment;
+
+pub const EXTENSION: &str = "sprucei";
+
+/// Whether the file named `name` is an interface rather than source
+pub fn is_interface(name: &str) -> bool {
+    Path::new(name).extension().map_or(false, |ext| ext == EXTENSION)
+}
+
+/// Writes the interface of a module that has been checked
+pub fn emit(prog: &na::Prog, env: &Environment, module: &na::Module) -> String {
+    let mut output = format!("module {}", module.name);
+    if let Some(exports) = &module.exports {
+        let names: Vec<String> = exports.iter().map(|export| {
+            if export.val.constructors { format!("{}(..)", export.val.name) } else { export.val.name.clone() }
+        }).collect();
+        output = format!("{} ({})", output, names.join(", "));
+    }
+    output.push('\n');
+
+    for import in &module.imports {
+        output = format!("{}import {}\n", output, import.val.module);
+    }
+
+    // private types are written too, since exported declarations can still
+    // have them in their types
+    let mut types: Vec<&na::ADT> = prog.type_table.types.values().filter(|adt| adt.module == module.name).collect();
+    types.sort_by_key(|adt| adt.id);
+    for adt in types {
+        output = format!("{}{}", output, write_type(&prog.type_table, adt));
+    }
+
+    let mut decls: Vec<(&NodeInfo, na::SymbolID)> = prog.definitions.iter().filter_map(|stmt| match &stmt.val {
+        na::Stmt::Assign(tgt, _) => Some((&stmt.info, tgt.val.id())),
+        _ => None
+    }).collect();
+    decls.extend(prog.functions.iter().filter(|func| prog.test_name(&func.val.name).is_none()).map(|func| (&func.info, func.val.name)));
+    decls.retain(|(info, _)| info.file == module.file);
+    decls.sort_by_key(|(info, _)| info.span.start);
+
+    for (_, id) in decls {
+        let name = match prog.symbol_table.lookup_id(&id) {
+            Some(sym) if module.exports(&sym.name) => &sym.name,
+            _ => continue
+        };
+        if let Some(ty) = env.type_of_id(id) {
+            output = format!("{}{} : {}\n", output, name, ty);
+        }
+    }
+
+    output
+}
+
+/// A type's declaration, as it would be written in Spruce
+pub fn write_type(types: &na::TypeTableExt, adt: &na::ADT) -> String {
+    let params: Vec<String> = adt.type_params.iter().map(|id| write_type_id(types, &na::TypeID::TParam(*id))).collect();
+    let mut output = if params.is_empty() {
+        format!("type {} {{\n", adt.name)
+    }
+    else {
+        format!("type {}({}) {{\n", adt.name, params.join(", "))
+    };
+
+    let mut values: Vec<&na::ADTValue> = types.values.values().filter(|val| val.data_type == adt.id).collect();
+    values.sort_by_key(|val| val.id);
+    for val in values {
+        let args: Vec<String> = val.args.iter().map(|arg| write_type_id(types, arg)).collect();
+        if args.is_empty() {
+            output = format!("{}    {}\n", output, val.name);
+        }
+        else {
+            output = format!("{}    {}({})\n", output, val.name, args.join(", "));
+        }
+    }
+
+    format!("{}}}\n", output)
+}
+
+fn write_type_id(types: &na::TypeTableExt, ty: &na::TypeID) -> String {
+    let join = |args: &Vec<Box<na::TypeID>>| args.iter().map(|arg| write_type_id(types, arg)).collect::<Vec<String>>().join(", ");
+    match ty {
+        na::TypeID::TParam(id) => types.type_params.get(id).map_or(format!("t{}", id), |param| param.name.clone()),
+        na::TypeID::ADT(id, args) => {
+            let name = types.types.get(id).map_or(format!("adt{}", id), |adt| adt.name.clone());
+            if args.is_empty() { name } else { format!("{}({})", name, join(args)) }
+        }
+        na::TypeID::Prim(name) => name.clone(),
+        na::TypeID::Func(args, out) => format!("({}) -> {}", join(args), write_type_id(types, out)),
+        na::TypeID::Unit => String::from("()")
+    }
+}
@@ -0,0 +1,594 @@
+/*
+The JIT compiles the parts of a program it can to native code with Cranelift
+before the interpreter runs it. That's the functions that only ever deal in
+Ints and Bools: their types mention nothing else, so they are monomorphic,
+and so does every expression in their bodies. Both kinds of value are a
+64-bit integer in native code, with Bools as 0 and 1. Such a function can
+still only call functions that were compiled too, and everything else is
+left to the interpreter, which calls into native code when it reaches a
+compiled function.
+
+Native code can't return a SpruceErr, so when it fails, as when dividing by
+zero, it writes what went wrong to a buffer the JIT owns and returns at once,
+as does every compiled function that called it. Each of them notes the call
+it was making as it returns, so the error's trace has the calls made in
+native code as well as the interpreter's. The interpreter turns the failure
+into an error when the call returns.
+*/
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, MemFlags};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Switch, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module};
+
+use crate::error::SpruceErr;
+use crate::eval::{self, Natives, Runtime, Value};
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+use crate::registry::{LangType, LangValue};
+use crate::trace::Phase;
+use crate::typecheck::{self, BodyNode, CaseBody, CaseNode, Environment, Expr, ExprNode, Stmt, StmtNode, TypeRepr};
+
+/// What a compiled function takes and gives back
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Repr {
+    Int,
+    Bool
+}
+
+/// Where native code can fail, and how
+#[derive(Debug, PartialEq, Clone)]
+enum Failure {
+    DivideByZero(NodeInfo),
+    NoMatch(NodeInfo, Repr)
+}
+
+struct Compiled {
+    // takes a pointer to the arguments, so every function is called the
+    // same way from Rust
+    entry: extern "C" fn(*const i64) -> i64,
+    args: Vec<Repr>,
+    out: Repr
+}
+
+pub struct Jit {
+    // owns the memory the native code is in
+    _module: JITModule,
+    functions: HashMap<na::SymbolID, Compiled>,
+    failures: Vec<Failure>,
+    // the first is one more than the index of the failure, or 0 if nothing
+    // has failed, and the second is the value that failed to match, if any
+    failed: Box<RefCell<[i64; 2]>>,
+    // each call compiled code makes, as the function called and where
+    calls: Vec<(na::SymbolID, NodeInfo)>,
+    // the calls returned from since something failed, innermost first
+    unwound: Box<RefCell<Vec<usize>>>
+}
+
+/// Called by compiled code as it returns from a call that failed
+extern "C" fn unwind(unwound: *const RefCell<Vec<usize>>, call: i64) {
+    // the pointer is to the Jit's own list, which outlives its code
+    let unwound = unsafe { &*unwound };
+    unwound.borrow_mut().push(call as usize);
+}
+
+/// Runs a program as `eval::run_prog` does, with the functions the JIT can
+/// compile running as native code
+pub fn run_prog(prog: &na::Prog, typed: &typecheck::Prog, env: &Environment, entry: &str, runtime: Runtime) -> Result<Value, SpruceErr> {
+    let jit = compile(prog, typed, env)?;
+    let names: Vec<&str> = jit.compiled().into_iter().map(|id| eval::symbol_name(prog, id)).collect();
+    trace!(Phase::Jit, "compiled {}, and the interpreter runs the rest", names.join(", "));
+    eval::run_with_natives(prog, typed, entry, runtime, Some(&jit))
+}
+
+impl Jit {
+    /// The symbols of the functions that were compiled
+    pub fn compiled(&self) -> Vec<na::SymbolID> {
+        let mut ids: Vec<na::SymbolID> = self.functions.keys().copied().collect();
+        ids.sort();
+        ids
+    }
+}
+
+fn repr(ty: &TypeRepr) -> Option<Repr> {
+    match ty {
+        TypeRepr::Prim(name) if name == "Int" => Some(Repr::Int),
+        // the prelude's names can't be declared again, so this is its Bool
+        TypeRepr::ADT(name, args) if args.is_empty() && name == LangType::Bool.name() => Some(Repr::Bool),
+        _ => None
+    }
+}
+
+/// The functions that can be compiled, with what they take and give. A
+/// function that calls one that can't be compiled can't be either, so they
+/// are ruled out until none are left to rule out
+fn supported<'t>(prog: &na::Prog, typed: &'t typecheck::Prog, env: &Environment) -> HashMap<na::SymbolID, (&'t typecheck::FuncNode, Vec<Repr>, Repr)> {
+    let mut candidates: HashMap<na::SymbolID, (&typecheck::FuncNode, Vec<Repr>, Repr)> = typed.functions.iter().filter_map(|func| {
+        match env.type_of_id(func.val.name)? {
+            TypeRepr::Func(args, out) => {
+                let args: Option<Vec<Repr>> = args.iter().map(repr).collect();
+                Some((func.val.name, (func, args?, repr(&out)?)))
+            }
+            _ => None
+        }
+    }).collect();
+
+    loop {
+        let names: HashSet<na::SymbolID> = candidates.keys().copied().collect();
+        let before = candidates.len();
+        candidates.retain(|_, (func, _, _)| {
+            let mut check = Checker { prog: prog, env: env, callable: &names, locals: func.val.args.iter().copied().collect() };
+            check.body(&func.val.body)
+        });
+        if candidates.len() == before {
+            return candidates;
+        }
+    }
+}
+
+/// Whether everything in a body can be compiled
+struct Checker<'c> {
+    prog: &'c na::Prog,
+    env: &'c Environment,
+    callable: &'c HashSet<na::SymbolID>,
+    locals: HashSet<na::SymbolID>
+}
+
+impl<'c> Checker<'c> {
+    fn body(&mut self, body: &BodyNode) -> bool {
+        body.val.stmts.iter().all(|stmt| self.stmt(stmt)) && body.val.expr.iter().all(|expr| self.expr(expr))
+    }
+
+    fn stmt(&mut self, stmt: &StmtNode) -> bool {
+        match &stmt.val {
+            Stmt::Assign(tgt, expr) => {
+                // updating a program-level definition is left to the
+                // interpreter
+                match tgt.val {
+                    na::Target::Update(id) if !self.locals.contains(&id) => return false,
+                    _ => self.locals.insert(tgt.val.id())
+                };
+                self.expr(expr)
+            }
+            Stmt::FnCall(id, args) => self.call(*id, args.iter()),
+            Stmt::Case(case) => self.case(case)
+        }
+    }
+
+    fn case(&mut self, case: &CaseNode) -> bool {
+        self.expr(&case.val.expr) && case.val.options.iter().all(|opt| {
+            let pattern = match &opt.val.pattern.val {
+                na::CasePattern::ADT(id, args) => args.is_empty() && self.is_bool_constructor(*id),
+                na::CasePattern::Lit(_) | na::CasePattern::Any => true
+            };
+            pattern && match &opt.val.body.val {
+                CaseBody::Expr(expr) => self.expr(expr),
+                CaseBody::Body(body) => self.body(body)
+            }
+        })
+    }
+
+    fn is_bool_constructor(&self, id: na::ADTValID) -> bool {
+        id == self.prog.registry.value_id(LangValue::True) || id == self.prog.registry.value_id(LangValue::False)
+    }
+
+    fn call<'e>(&mut self, id: na::SymbolID, mut args: impl Iterator<Item=&'e ExprNode>) -> bool {
+        self.callable.contains(&id) && !self.locals.contains(&id) && args.all(|arg| self.expr(arg))
+    }
+
+    fn expr(&mut self, expr: &ExprNode) -> bool {
+        if repr(&self.env.repr(expr.ty)).is_none() {
+            return false;
+        }
+        match &expr.val {
+            Expr::Id(id) => self.locals.contains(id),
+            Expr::FnCall(id, args) => self.call(*id, args.iter().map(|arg| &**arg)),
+            Expr::ADTVal(id, args) => args.is_empty() && self.is_bool_constructor(*id),
+            Expr::Pow(_, _) | Expr::List(_) | Expr::ComposeL(_, _) | Expr::ComposeR(_, _) | Expr::Error => false,
+            _ => expr.val.children().into_iter().all(|child| self.expr(child))
+        }
+    }
+}
+
+/// Compiles every function that can be compiled
+pub fn compile(prog: &na::Prog, typed: &typecheck::Prog, env: &Environment) -> Result<Jit, SpruceErr> {
+    let ice = |message: String| SpruceErr::ice(Phase::Jit, message, eval::no_info());
+
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").map_err(|err| ice(err.to_string()))?;
+    let isa = cranelift_native::builder().map_err(|err| ice(err.to_string()))?
+        .finish(settings::Flags::new(flags)).map_err(|err| ice(err.to_string()))?;
+    let mut jit_builder = JITBuilder::with_isa(isa, default_libcall_names());
+    jit_builder.symbol("spruce_unwind", unwind as *const u8);
+    let mut module = JITModule::new(jit_builder);
+
+    let funcs = supported(prog, typed, env);
+    let mut order: Vec<na::SymbolID> = funcs.keys().copied().collect();
+    order.sort();
+
+    let word = types::I64;
+    let mut ids: HashMap<na::SymbolID, FuncId> = HashMap::new();
+    let mut entries: HashMap<na::SymbolID, FuncId> = HashMap::new();
+    for id in &order {
+        let mut sig = module.make_signature();
+        sig.params.extend(funcs[id].1.iter().map(|_| AbiParam::new(word)));
+        sig.returns.push(AbiParam::new(word));
+        let name = format!("{}_{}", eval::symbol_name(prog, *id), id);
+        ids.insert(*id, module.declare_function(&name, Linkage::Local, &sig).map_err(|err| ice(err.to_string()))?);
+
+        let mut entry_sig = module.make_signature();
+        entry_sig.params.push(AbiParam::new(module.target_config().pointer_type()));
+        entry_sig.returns.push(AbiParam::new(word));
+        entries.insert(*id, module.declare_function(&format!("{}_entry", name), Linkage::Local, &entry_sig).map_err(|err| ice(err.to_string()))?);
+    }
+
+    let mut unwind_sig = module.make_signature();
+    unwind_sig.params.push(AbiParam::new(module.target_config().pointer_type()));
+    unwind_sig.params.push(AbiParam::new(word));
+    let unwind = module.declare_function("spruce_unwind", Linkage::Import, &unwind_sig).map_err(|err| ice(err.to_string()))?;
+
+    let failed = Box::new(RefCell::new([0i64; 2]));
+    let unwound = Box::new(RefCell::new(Vec::new()));
+    let mut failures = Vec::new();
+    let mut calls = Vec::new();
+    let mut ctx = module.make_context();
+    let mut builder_ctx = FunctionBuilderContext::new();
+    for id in &order {
+        let (func, _, _) = &funcs[id];
+        trace!(Phase::Jit, "compiling {}", eval::symbol_name(prog, *id));
+        ctx.func.signature = module.declarations().get_function_decl(ids[id]).signature.clone();
+        {
+            let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+            let mut gen = FuncGen {
+                prog: prog,
+                env: env,
+                module: &mut module,
+                builder: &mut builder,
+                ids: &ids,
+                vars: HashMap::new(),
+                failures: &mut failures,
+                failed: failed.as_ptr() as i64,
+                calls: &mut calls,
+                unwind: unwind,
+                unwound: &*unwound as *const RefCell<Vec<usize>> as i64
+            };
+            gen.function(func);
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+        trace!(Phase::Jit, "{}", ctx.func.display());
+        module.define_function(ids[id], &mut ctx).map_err(|err| ice(format!("{:?}", err)))?;
+        module.clear_context(&mut ctx);
+
+        // the entry loads the arguments and calls the function
+        ctx.func.signature = module.declarations().get_function_decl(entries[id]).signature.clone();
+        {
+            let mut builder = FunctionBuilder::new(&mut ctx.func, &mut builder_ctx);
+            let block = builder.create_block();
+            builder.append_block_params_for_function_params(block);
+            builder.switch_to_block(block);
+            let ptr = builder.block_params(block)[0];
+            let args: Vec<_> = (0..func.val.args.len()).map(|i| {
+                builder.ins().load(word, MemFlags::trusted(), ptr, (i * 8) as i32)
+            }).collect();
+            let callee = module.declare_func_in_func(ids[id], builder.func);
+            let call = builder.ins().call(callee, &args);
+            let result = builder.inst_results(call)[0];
+            builder.ins().return_(&[result]);
+            builder.seal_all_blocks();
+            builder.finalize();
+        }
+        module.define_function(entries[id], &mut ctx).map_err(|err| ice(format!("{:?}", err)))?;
+        module.clear_context(&mut ctx);
+    }
+    module.finalize_definitions().map_err(|err| ice(err.to_string()))?;
+
+    let functions = order.iter().map(|id| {
+        let (_, args, out) = &funcs[id];
+        let code = module.get_finalized_function(entries[id]);
+        // the entry was declared with this signature just above
+        let entry = unsafe { std::mem::transmute::<*const u8, extern "C" fn(*const i64) -> i64>(code) };
+        (*id, Compiled { entry: entry, args: args.clone(), out: *out })
+    }).collect();
+
+    Ok(Jit {
+        _module: module,
+        functions: functions,
+        failures: failures,
+        failed: failed,
+        calls: calls,
+        unwound: unwound
+    })
+}
+
+impl Natives for Jit {
+    fn call(&self, prog: &na::Prog, id: na::SymbolID, args: &[Value]) -> Option<Result<Value, SpruceErr>> {
+        let func = self.functions.get(&id)?;
+        let true_id = prog.registry.value_id(LangValue::True);
+        let raw: Vec<i64> = args.iter().map(|arg| match arg {
+            Value::Int(n) => *n,
+            Value::ADT(id, _) => (*id == true_id) as i64,
+            _ => 0
+        }).collect();
+        debug_assert_eq!(raw.len(), func.args.len());
+
+        let result = (func.entry)(raw.as_ptr());
+        let [failure, value] = std::mem::take(&mut *self.failed.borrow_mut());
+        if failure != 0 {
+            let err = match &self.failures[failure as usize - 1] {
+                Failure::DivideByZero(info) => SpruceErr::new(String::from("division by zero"), info.clone()),
+                Failure::NoMatch(info, repr) => {
+                    let shown = eval::show(prog, &to_value(prog, *repr, value));
+                    SpruceErr::new(format!("no option matches {}", shown), info.clone())
+                }
+            };
+            // the calls made in native code, out to the one made here
+            let mut at = err.info.clone();
+            let mut trace = Vec::new();
+            for call in std::mem::take(&mut *self.unwound.borrow_mut()) {
+                let (callee, site) = &self.calls[call];
+                trace.push((String::from(eval::symbol_name(prog, *callee)), at));
+                at = site.clone();
+            }
+            trace.push((String::from(eval::symbol_name(prog, id)), at));
+            return Some(Err(err.with_trace(trace)));
+        }
+        Some(Ok(to_value(prog, func.out, result)))
+    }
+}
+
+fn to_value(prog: &na::Prog, repr: Repr, raw: i64) -> Value {
+    match repr {
+        Repr::Int => Value::Int(raw),
+        Repr::Bool => eval::bool(prog, raw != 0)
+    }
+}
+
+/// Generates the code of one function
+struct FuncGen<'g, 'b> {
+    prog: &'g na::Prog,
+    env: &'g Environment,
+    module: &'g mut JITModule,
+    builder: &'g mut FunctionBuilder<'b>,
+    ids: &'g HashMap<na::SymbolID, FuncId>,
+    vars: HashMap<na::SymbolID, Variable>,
+    failures: &'g mut Vec<Failure>,
+    // the address of the failure buffer
+    failed: i64,
+    calls: &'g mut Vec<(na::SymbolID, NodeInfo)>,
+    unwind: FuncId,
+    // the address of the list of calls unwound
+    unwound: i64
+}
+
+type CValue = cranelift_codegen::ir::Value;
+
+impl<'g, 'b> FuncGen<'g, 'b> {
+    fn function(&mut self, func: &typecheck::FuncNode) {
+        let block = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(block);
+        self.builder.switch_to_block(block);
+        let params = self.builder.block_params(block).to_vec();
+        for (arg, param) in func.val.args.iter().zip(params) {
+            let var = self.var(*arg);
+            self.builder.def_var(var, param);
+        }
+        let result = self.body(&func.val.body);
+        let result = self.or_zero(result);
+        self.builder.ins().return_(&[result]);
+    }
+
+    fn var(&mut self, id: na::SymbolID) -> Variable {
+        if let Some(var) = self.vars.get(&id) {
+            return *var;
+        }
+        let var = Variable::from_u32(self.vars.len() as u32);
+        self.builder.declare_var(var, types::I64);
+        self.vars.insert(id, var);
+        var
+    }
+
+    fn or_zero(&mut self, val: Option<CValue>) -> CValue {
+        val.unwrap_or_else(|| self.builder.ins().iconst(types::I64, 0))
+    }
+
+    fn body(&mut self, body: &BodyNode) -> Option<CValue> {
+        let mut last = None;
+        for stmt in &body.val.stmts {
+            last = self.stmt(stmt);
+        }
+        match &body.val.expr {
+            Some(expr) => Some(self.expr(expr)),
+            None => last
+        }
+    }
+
+    fn stmt(&mut self, stmt: &StmtNode) -> Option<CValue> {
+        match &stmt.val {
+            Stmt::Assign(tgt, expr) => {
+                let val = self.expr(expr);
+                let var = self.var(tgt.val.id());
+                self.builder.def_var(var, val);
+                Some(val)
+            }
+            Stmt::FnCall(id, args) => {
+                let args: Vec<CValue> = args.iter().map(|arg| self.expr(arg)).collect();
+                Some(self.call(*id, &args, &stmt.info))
+            }
+            Stmt::Case(case) => self.case(case)
+        }
+    }
+
+    /// Stops running and returns, with what went wrong in the failure buffer
+    fn fail(&mut self, failure: Failure, value: CValue) {
+        self.failures.push(failure);
+        let index = self.builder.ins().iconst(types::I64, self.failures.len() as i64);
+        let buffer = self.builder.ins().iconst(types::I64, self.failed);
+        self.builder.ins().store(MemFlags::trusted(), index, buffer, 0);
+        self.builder.ins().store(MemFlags::trusted(), value, buffer, 8);
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        self.builder.ins().return_(&[zero]);
+    }
+
+    fn call(&mut self, id: na::SymbolID, args: &[CValue], info: &NodeInfo) -> CValue {
+        let callee = self.module.declare_func_in_func(self.ids[&id], self.builder.func);
+        let call = self.builder.ins().call(callee, args);
+        let result = self.builder.inst_results(call)[0];
+        self.calls.push((id, info.clone()));
+
+        // a call that failed returns at once, and so does its caller, noting
+        // the call for the trace
+        let buffer = self.builder.ins().iconst(types::I64, self.failed);
+        let failure = self.builder.ins().load(types::I64, MemFlags::trusted(), buffer, 0);
+        let (bail, next) = (self.builder.create_block(), self.builder.create_block());
+        self.builder.ins().brif(failure, bail, &[], next, &[]);
+        self.builder.switch_to_block(bail);
+        let unwind = self.module.declare_func_in_func(self.unwind, self.builder.func);
+        let unwound = self.builder.ins().iconst(types::I64, self.unwound);
+        let index = self.builder.ins().iconst(types::I64, self.calls.len() as i64 - 1);
+        self.builder.ins().call(unwind, &[unwound, index]);
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        self.builder.ins().return_(&[zero]);
+        self.builder.switch_to_block(next);
+        result
+    }
+
+    fn case(&mut self, case: &CaseNode) -> Option<CValue> {
+        let val = self.expr(&case.val.expr);
+        let repr = repr(&self.env.repr(case.val.expr.ty)).unwrap_or(Repr::Int);
+        let merge = self.builder.create_block();
+        self.builder.append_block_param(merge, types::I64);
+
+        // numbers and Bools are both switched on as integers, and the first
+        // option for a value wins
+        let mut switch = Switch::new();
+        let mut seen = HashSet::new();
+        let mut otherwise = None;
+        let mut options: Vec<(Block, &typecheck::CaseOptionNode)> = Vec::new();
+        for opt in &case.val.options {
+            let key = match &opt.val.pattern.val {
+                na::CasePattern::Lit(lit) => Some(*lit),
+                na::CasePattern::ADT(id, _) => Some((*id == self.prog.registry.value_id(LangValue::True)) as i64),
+                na::CasePattern::Any => None
+            };
+            let block = self.builder.create_block();
+            match key {
+                Some(key) if seen.insert(key) => switch.set_entry(key as u64 as u128, block),
+                Some(_) => continue,
+                None => otherwise = Some(block)
+            }
+            options.push((block, opt));
+            if otherwise.is_some() {
+                break;
+            }
+        }
+
+        let no_match = match otherwise {
+            Some(block) => block,
+            None => self.builder.create_block()
+        };
+        switch.emit(self.builder, val, no_match);
+        if otherwise.is_none() {
+            self.builder.switch_to_block(no_match);
+            self.fail(Failure::NoMatch(case.val.expr.info.clone(), repr), val);
+        }
+
+        for (block, opt) in options {
+            self.builder.switch_to_block(block);
+            let result = match &opt.val.body.val {
+                CaseBody::Expr(expr) => Some(self.expr(expr)),
+                CaseBody::Body(body) => self.body(body)
+            };
+            let result = self.or_zero(result);
+            self.builder.ins().jump(merge, &[result]);
+        }
+
+        self.builder.switch_to_block(merge);
+        Some(self.builder.block_params(merge)[0])
+    }
+
+    fn compare(&mut self, cc: IntCC, left: &ExprNode, right: &ExprNode) -> CValue {
+        let (left, right) = (self.expr(left), self.expr(right));
+        let flag = self.builder.ins().icmp(cc, left, right);
+        self.builder.ins().uextend(types::I64, flag)
+    }
+
+    fn divide(&mut self, expr: &ExprNode, left: &ExprNode, right: &ExprNode, rem: bool) -> CValue {
+        let (left, right) = (self.expr(left), self.expr(right));
+        let (fail, next) = (self.builder.create_block(), self.builder.create_block());
+        self.builder.ins().brif(right, next, &[], fail, &[]);
+        self.builder.switch_to_block(fail);
+        self.fail(Failure::DivideByZero(expr.info.clone()), right);
+        self.builder.switch_to_block(next);
+
+        // dividing the smallest Int by -1 overflows, which would trap, so
+        // -1 is handled the way Rust's wrapping division does
+        let one = self.builder.ins().iconst(types::I64, 1);
+        let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, right, -1);
+        let divisor = self.builder.ins().select(minus_one, one, right);
+        if rem {
+            let result = self.builder.ins().srem(left, divisor);
+            let zero = self.builder.ins().iconst(types::I64, 0);
+            self.builder.ins().select(minus_one, zero, result)
+        }
+        else {
+            let result = self.builder.ins().sdiv(left, divisor);
+            let negated = self.builder.ins().ineg(left);
+            self.builder.ins().select(minus_one, negated, result)
+        }
+    }
+
+    fn expr(&mut self, expr: &ExprNode) -> CValue {
+        macro_rules! binary {
+            ($op:ident, $l:expr, $r:expr) => {{
+                let (left, right) = (self.expr($l), self.expr($r));
+                self.builder.ins().$op(left, right)
+            }};
+        }
+
+        match &expr.val {
+            Expr::Add(l, r) => binary!(iadd, l, r),
+            Expr::Subt(l, r) => binary!(isub, l, r),
+            Expr::Mult(l, r) => binary!(imul, l, r),
+            Expr::BitAnd(l, r) => binary!(band, l, r),
+            Expr::BitOr(l, r) => binary!(bor, l, r),
+            Expr::BitXor(l, r) => binary!(bxor, l, r),
+            Expr::Shl(l, r) => binary!(ishl, l, r),
+            Expr::Shr(l, r) => binary!(sshr, l, r),
+            Expr::Div(l, r) => self.divide(expr, l, r, false),
+            Expr::Mod(l, r) => self.divide(expr, l, r, true),
+            Expr::Eq(l, r) => self.compare(IntCC::Equal, l, r),
+            Expr::NotEq(l, r) => self.compare(IntCC::NotEqual, l, r),
+            Expr::Lt(l, r) => self.compare(IntCC::SignedLessThan, l, r),
+            Expr::Gt(l, r) => self.compare(IntCC::SignedGreaterThan, l, r),
+            Expr::LtEq(l, r) => self.compare(IntCC::SignedLessThanOrEqual, l, r),
+            Expr::GtEq(l, r) => self.compare(IntCC::SignedGreaterThanOrEqual, l, r),
+            Expr::Neg(inner) => {
+                let inner = self.expr(inner);
+                self.builder.ins().ineg(inner)
+            }
+            Expr::Lit(lit) => self.builder.ins().iconst(types::I64, *lit),
+            Expr::ADTVal(id, _) => {
+                let b = *id == self.prog.registry.value_id(LangValue::True);
+                self.builder.ins().iconst(types::I64, b as i64)
+            }
+            Expr::Id(id) => {
+                let var = self.var(*id);
+                self.builder.use_var(var)
+            }
+            Expr::FnCall(id, args) => {
+                let args: Vec<CValue> = args.iter().map(|arg| self.expr(arg)).collect();
+                self.call(*id, &args, &expr.info)
+            }
+            // the checker rules the rest out
+            Expr::Pow(_, _) | Expr::List(_) | Expr::ComposeL(_, _) | Expr::ComposeR(_, _) | Expr::Error => {
+                unreachable!("the JIT can't compile {:?}", expr.val)
+            }
+        }
+    }
+}
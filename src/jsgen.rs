@@ -0,0 +1,432 @@
+/*
+The js target writes a checked program out as an ES module, meant to be read
+as well as run, that imports the small runtime in jsgen_runtime.mjs. It
+doesn't need node, so it can be loaded straight into a browser too.
+
+Like the other targets it's written from the program's A-normal form, so
+every value is worked out into a variable of its own, in the order the
+interpreter has them. Variables are declared at the top of their function,
+named after the symbol they stand for if any, and temporaries start with an
+underscore, which Spruce names can't.
+
+A function calling itself in tail position is written as a loop, so that
+recursion written as a loop runs in constant stack here too, since JS engines
+don't do away with tail calls themselves.
+
+Functions are curried, so `add(a, b)` becomes `add(a)(b)`, which lets them be
+passed around and partly applied from javascript. Values of types are
+objects tagged with their constructor's name, as in
+`{ tag: 'Just', fields: [3n] }`, and each constructor is exported as a
+function that makes them. Ints are BigInts, so that they have all 64 bits
+and wrap where they do in the interpreter, and javascript calling into the
+module passes them as BigInts too. The module exports its functions and, when node
+runs it as the program, runs `main`.
+*/
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::anf::{self, Atom, Prim, Value};
+use crate::error::SpruceErr;
+use crate::eval;
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+use crate::source::SourceMap;
+
+pub const RUNTIME: &str = include_str!("jsgen_runtime.mjs");
+
+/// What the runtime is saved as, next to the module that imports it
+pub const RUNTIME_FILE: &str = "spruce-runtime.mjs";
+
+/// The builtins the runtime has JS versions of
+const JS_BUILTINS: [&str; 34] = [
+    "show", "compare", "parseInt", "parseFloat", "abs", "min", "max", "toFloat", "sqrt", "floor", "ceil", "sin", "cos", "tan",
+    "emptyDict", "dictInsert", "dictGet", "dictRemove", "dictFold", "emptySet", "setInsert", "setMember", "setUnion",
+    "setIntersection", "setFromList", "print", "printLine", "debug", "readLine", "readFile", "writeFile", "args", "randomInt", "setSeed"
+];
+
+const RESERVED: [&str; 38] = [
+    "await", "break", "case", "catch", "class", "const", "continue", "debugger", "default", "delete", "do", "else", "enum",
+    "export", "extends", "false", "finally", "for", "function", "if", "import", "in", "instanceof", "let", "new", "null",
+    "return", "static", "super", "switch", "this", "throw", "true", "try", "typeof", "var", "void", "while"
+];
+
+/// Where a value goes once it's worked out
+#[derive(PartialEq, Clone)]
+enum Dest {
+    Return,
+    Assign(String),
+    Discard
+}
+
+struct Generator<'a> {
+    prog: &'a na::Prog,
+    sources: &'a SourceMap,
+    builtins: HashMap<na::SymbolID, &'a str>,
+    constructors: HashMap<na::ADTValID, String>,
+
+    // the function being written
+    out: String,
+    indent: usize,
+    vars: Vec<Option<na::SymbolID>>,
+    uses: Vec<usize>,
+    params: Vec<anf::Var>,
+    // parameters a loop works on copies of
+    copied: Vec<anf::Var>
+}
+
+/// Writes a program out as an ES module
+pub fn generate(prog: &na::Prog, program: &anf::Program, sources: &SourceMap) -> Result<String, SpruceErr> {
+    generate_with_seed(prog, program, sources, None)
+}
+
+/// Writes a program out as an ES module whose random numbers, when it's run
+/// as the program, come from `seed` if there is one
+pub fn generate_with_seed(prog: &na::Prog, program: &anf::Program, sources: &SourceMap, seed: Option<u32>) -> Result<String, SpruceErr> {
+    let mut gen = Generator {
+        prog: prog,
+        sources: sources,
+        builtins: anf::used_builtins(prog, &program.builtins, "js", &JS_BUILTINS)?.into_iter().map(|(id, builtin, _)| (id, builtin)).collect(),
+        constructors: HashMap::new(),
+        out: String::new(),
+        indent: 0,
+        vars: Vec::new(),
+        uses: Vec::new(),
+        params: Vec::new(),
+        copied: Vec::new()
+    };
+
+    let mut out = String::from("// generated by spruce\n\n");
+    let _ = writeln!(out, "import * as _rt from './{}'\n", RUNTIME_FILE);
+
+    // constructors that share a name, from different modules, are told apart
+    // by their ids
+    let mut values: Vec<&na::ADTValue> = prog.type_table.values.values().collect();
+    values.sort_by_key(|val| val.id);
+    for val in &values {
+        let clash = values.iter().any(|other| other.id != val.id && other.name == val.name);
+        let name = if clash { format!("{}_{}", val.name, val.id) } else { val.name.clone() };
+        gen.constructors.insert(val.id, name);
+    }
+    for val in &values {
+        let name = &gen.constructors[&val.id];
+        let params: Vec<String> = (0..val.args.len()).map(|i| format!("a{}", i)).collect();
+        let curried: String = params.iter().map(|param| format!("({}) => ", param)).collect();
+        let _ = writeln!(out, "export const {} = {}_rt.value('{}', [{}])", name, curried, val.name, params.join(", "));
+    }
+    out.push('\n');
+
+    // definitions are worked out in a block of their own, so their
+    // temporaries stay out of the module's scope
+    if !program.globals.is_empty() {
+        let globals: Vec<String> = program.globals.iter().map(|id| gen.name(*id)).collect();
+        let _ = writeln!(out, "let {}", globals.join(", "));
+        gen.start(&program.init, 1);
+        gen.block(&program.init.body, Dest::Discard);
+        out.push_str("{\n");
+        out.push_str(&gen.declarations(&program.init, 1));
+        out.push_str(&gen.out);
+        out.push_str("}\n\n");
+    }
+
+    for func in &program.functions {
+        let name = gen.name(func.name.expect("functions are named"));
+        let depth = if func.params.len() > 1 { 2 } else { 1 };
+        let loops = func.body.recurs();
+        gen.start(func, if loops { depth + 1 } else { depth });
+        let params: Vec<String> = func.params.iter().map(|param| gen.own_var(*param)).collect();
+        gen.block(&func.body, Dest::Return);
+        match params.split_first() {
+            None => { let _ = writeln!(out, "export function {}() {{", name); }
+            Some((first, [])) => { let _ = writeln!(out, "export function {}({}) {{", name, first); }
+            Some((first, rest)) => {
+                let curried: Vec<String> = rest.iter().map(|param| format!("({})", param)).collect();
+                let _ = writeln!(out, "export function {}({}) {{\n    return {} => {{", name, first, curried.join(" => "));
+            }
+        }
+        out.push_str(&gen.declarations(func, depth));
+        let pad = "    ".repeat(depth);
+        if loops {
+            for param in &gen.copied {
+                let _ = writeln!(out, "{}let {} = {}", pad, gen.var(*param), gen.own_var(*param));
+            }
+            let _ = writeln!(out, "{}while (true) {{", pad);
+        }
+        out.push_str(&gen.out);
+        if loops {
+            let _ = writeln!(out, "{}}}", pad);
+        }
+        if depth > 1 {
+            out.push_str("    }\n");
+        }
+        out.push_str("}\n\n");
+    }
+
+    out.push_str("// runs when node loads this module as the program, rather than it being imported\n");
+    out.push_str("if (_rt.isMain(import.meta.url)) {\n");
+    if let Some(seed) = seed {
+        let _ = writeln!(out, "    _rt.setSeed({}n)", seed);
+    }
+    let _ = writeln!(out, "    _rt.run({})", gen.name(program.entry));
+    out.push_str("}\n");
+    Ok(out)
+}
+
+/// A JS string literal
+fn string(text: &str) -> String {
+    let mut lit = String::from("'");
+    for c in text.chars() {
+        match c {
+            '\'' | '\\' => { lit.push('\\'); lit.push(c); }
+            '\n' => lit.push_str("\\n"),
+            c => lit.push(c)
+        }
+    }
+    lit.push('\'');
+    lit
+}
+
+impl<'a> Generator<'a> {
+    /// The symbol's name, with its id appended if it shadows another symbol
+    /// or is a word JS keeps for itself. Spruce names can't contain
+    /// underscores, so the result never clashes
+    fn name(&self, id: na::SymbolID) -> String {
+        let name = eval::symbol_name(self.prog, id);
+        if self.prog.symbol_table.shadowing.contains(&id) || RESERVED.contains(&name) {
+            format!("{}_{}", name, id)
+        }
+        else {
+            String::from(name)
+        }
+    }
+
+    /// Gets ready to write `func`. A function calling itself in tail
+    /// position loops instead, setting its parameters to the new arguments.
+    /// All but the last of a curried function's parameters belong to the
+    /// closures it returns, which may be called again, so the loop works on
+    /// copies of them
+    fn start(&mut self, func: &anf::Function, indent: usize) {
+        self.out = String::new();
+        self.indent = indent;
+        self.vars = func.vars.clone();
+        self.uses = func.uses();
+        self.params = func.params.clone();
+        self.copied = match func.params.split_last() {
+            Some((_, outer)) if func.body.recurs() => outer.iter().copied().filter(|param| self.uses[*param as usize] > 0).collect(),
+            _ => Vec::new()
+        };
+    }
+
+    /// Declares the variables the function just written uses, other than
+    /// its parameters. Variables standing for symbols of the same name are
+    /// never in scope together, so they share a declaration
+    fn declarations(&self, func: &anf::Function, indent: usize) -> String {
+        let mut names: Vec<String> = Vec::new();
+        for var in 0..func.vars.len() as anf::Var {
+            let name = self.var(var);
+            if self.uses[var as usize] > 0 && !func.params.contains(&var) && !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        if names.is_empty() {
+            return String::new();
+        }
+        format!("{}let {}\n", "    ".repeat(indent), names.join(", "))
+    }
+
+    /// A parameter's copy has an underscore after its name, which Spruce
+    /// names can't have
+    fn var(&self, var: anf::Var) -> String {
+        match self.copied.contains(&var) {
+            true => format!("{}_", self.own_var(var)),
+            false => self.own_var(var)
+        }
+    }
+
+    fn own_var(&self, var: anf::Var) -> String {
+        match self.vars[var as usize] {
+            Some(id) => self.name(id),
+            None => format!("_t{}", var)
+        }
+    }
+
+    fn line(&mut self, text: &str) {
+        for _ in 0..self.indent {
+            self.out.push_str("    ");
+        }
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn location(&self, info: &NodeInfo) -> String {
+        string(&SpruceErr::new(String::new(), info.clone()).location(self.sources))
+    }
+
+    fn atom(&self, atom: &Atom) -> String {
+        match atom {
+            Atom::Var(var) => self.var(*var),
+            Atom::Int(n) => format!("{}n", n),
+            Atom::Unit => String::from("undefined"),
+            Atom::Func(id) => match self.builtins.get(id) {
+                Some(builtin) => format!("_rt.{}", builtin),
+                None => self.name(*id)
+            }
+        }
+    }
+
+    /// Applies a function to its arguments one at a time
+    fn apply(&self, func: String, args: &[Atom]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| self.atom(arg)).collect();
+        match args.is_empty() {
+            true => format!("{}()", func),
+            false => format!("{}({})", func, args.join(")("))
+        }
+    }
+
+    fn block(&mut self, block: &anf::Block, dest: Dest) {
+        for stmt in &block.stmts {
+            match stmt {
+                anf::Stmt::Let(var, val) => {
+                    let dest = match self.uses[*var as usize] {
+                        0 => Dest::Discard,
+                        _ => Dest::Assign(self.var(*var))
+                    };
+                    self.value(val, dest);
+                }
+                anf::Stmt::SetGlobal(id, atom) => {
+                    let (name, atom) = (self.name(*id), self.atom(atom));
+                    self.line(&format!("{} = {}", name, atom));
+                }
+            }
+        }
+        self.value(&block.result, dest);
+    }
+
+    fn value(&mut self, val: &Value, dest: Dest) {
+        let expr = match val {
+            Value::Case(case) => return self.case(case, dest),
+            // only what can fail or has effects is worth working out when
+            // nothing uses it
+            Value::Atom(_) | Value::Global(_) | Value::Field(_, _) | Value::Construct(_, _) if dest == Dest::Discard => return,
+            Value::Atom(Atom::Unit) if dest == Dest::Return => return self.line("return"),
+            Value::Atom(atom) => self.atom(atom),
+            Value::Global(id) => self.name(*id),
+            Value::Field(atom, i) => format!("{}.fields[{}]", self.atom(atom), i),
+            Value::Construct(id, args) => {
+                let name = self.constructors[id].clone();
+                match args.is_empty() {
+                    true => name,
+                    false => self.apply(name, args)
+                }
+            }
+            Value::Call(id, args) => self.apply(self.atom(&Atom::Func(*id)), args),
+            Value::Apply(func, args) => self.apply(self.atom(func), args),
+            Value::Recur(args) => return self.recur(args),
+            Value::Prim(prim, operands, info) => self.prim(*prim, operands, info)
+        };
+        match dest {
+            Dest::Return => self.line(&format!("return {}", expr)),
+            Dest::Assign(var) => self.line(&format!("{} = {}", var, expr)),
+            Dest::Discard => self.line(&expr)
+        }
+    }
+
+    /// Starts the loop over with new arguments. An argument that's a
+    /// parameter set before it is copied first, so that every argument has
+    /// the value it had before the loop started over
+    fn recur(&mut self, args: &[Atom]) {
+        let updates: Vec<(anf::Var, &Atom)> = self.params.clone().into_iter().zip(args).filter(|(param, arg)| {
+            self.uses[*param as usize] > 0 && **arg != Atom::Var(*param)
+        }).collect();
+        let mut values = Vec::new();
+        for (i, (_, arg)) in updates.iter().enumerate() {
+            match arg {
+                Atom::Var(var) if updates[..i].iter().any(|(param, _)| param == var) => {
+                    let copy = format!("_r{}", i);
+                    self.line(&format!("const {} = {}", copy, self.atom(arg)));
+                    values.push(copy);
+                }
+                arg => values.push(self.atom(arg))
+            }
+        }
+        for ((param, _), val) in updates.iter().zip(values) {
+            self.line(&format!("{} = {}", self.var(*param), val));
+        }
+        self.line("continue");
+    }
+
+    fn prim(&self, prim: Prim, operands: &[Atom], info: &NodeInfo) -> String {
+        let atom = |i: usize| self.atom(&operands[i]);
+        let binary = |op: &str| format!("{} {} {}", atom(0), op, atom(1));
+        let wrapped = |op: &str| format!("_rt.int({})", binary(op));
+        let checked = |op: &str| format!("_rt.{}({}, {}, {})", op, atom(0), atom(1), self.location(info));
+        match prim {
+            Prim::Add => wrapped("+"),
+            Prim::Sub => wrapped("-"),
+            Prim::Mul => wrapped("*"),
+            Prim::BitAnd => binary("&"),
+            Prim::BitOr => binary("|"),
+            Prim::BitXor => binary("^"),
+            Prim::Shl => format!("_rt.shl({}, {})", atom(0), atom(1)),
+            Prim::Shr => format!("_rt.shr({}, {})", atom(0), atom(1)),
+            Prim::Div => checked("div"),
+            Prim::Mod => checked("mod"),
+            Prim::Pow => checked("pow"),
+            Prim::Lt => format!("_rt.bool({})", binary("<")),
+            Prim::Gt => format!("_rt.bool({})", binary(">")),
+            Prim::LtEq => format!("_rt.bool({})", binary("<=")),
+            Prim::GtEq => format!("_rt.bool({})", binary(">=")),
+            Prim::Eq => format!("_rt.bool(_rt.equal({}, {}))", atom(0), atom(1)),
+            Prim::NotEq => format!("_rt.bool(!_rt.equal({}, {}))", atom(0), atom(1)),
+            // a negative literal is bracketed, since `--` is a different operator
+            Prim::Neg => match &operands[0] {
+                Atom::Int(n) if *n < 0 => format!("_rt.int(-({}n))", n),
+                operand => format!("_rt.int(-{})", self.atom(operand))
+            },
+            Prim::Compose => format!("_rt.compose({}, {})", atom(0), atom(1))
+        }
+    }
+
+    fn case(&mut self, case: &anf::Case, dest: Dest) {
+        let val = self.atom(&case.scrutinee);
+
+        // constructors are switched on by their name and numbers by their
+        // value
+        let is_adt = case.arms.iter().any(|arm| matches!(arm.pattern, anf::Pattern::Constructor(_)));
+        self.line(&format!("switch ({}) {{", if is_adt { format!("{}.tag", val) } else { val.clone() }));
+        for arm in &case.arms {
+            match &arm.pattern {
+                anf::Pattern::Constructor(id) => self.line(&format!("case {}: {{", string(&self.prog.type_table.values[id].name))),
+                anf::Pattern::Int(n) => self.line(&format!("case {}n: {{", n))
+            }
+            self.indent += 1;
+            self.option(&arm.body, dest.clone());
+        }
+        match &case.default {
+            Some(body) => {
+                self.line("default: {");
+                self.indent += 1;
+                self.option(body, dest);
+            }
+            None => {
+                let location = self.location(&case.info);
+                self.line("default:");
+                self.indent += 1;
+                self.line(&format!("_rt.noMatch({}, {})", val, location));
+                self.indent -= 1;
+            }
+        }
+        self.line("}");
+    }
+
+    /// The rest of an option, whose opening brace has been written
+    fn option(&mut self, body: &anf::Block, dest: Dest) {
+        let returns = dest == Dest::Return;
+        self.block(body, dest);
+        if !returns {
+            self.line("break");
+        }
+        self.indent -= 1;
+        self.line("}");
+    }
+}
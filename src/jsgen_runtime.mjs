@@ -0,0 +1,424 @@
+// The runtime that ES modules built by spruce build --target=js import. It
+// works in the browser as well as in node. Away from node, printing falls
+// back to the console, there is no input, and files can't be read or written.
+//
+// Values of types are objects with the constructor's name as their tag and
+// its arguments as their fields, as in { tag: 'Just', fields: [3] }, and
+// functions are curried, taking one argument at a time. Ints are BigInts,
+// kept to 64 bits so that they wrap as they do everywhere else, and Floats are
+// JS numbers.
+
+export class SpruceError extends Error {
+    constructor(message, where) {
+        super(message + '\n --> ' + where)
+        this.where = where
+    }
+}
+
+export function int(n) {
+    return BigInt.asIntN(64, n)
+}
+
+// a Float cut to an Int the way the interpreter cuts one, saturating at the
+// ends of an Int, with NaN as 0
+function toInt(x) {
+    if (Number.isNaN(x)) {
+        return 0n
+    }
+    if (x >= 2 ** 63) {
+        return 2n ** 63n - 1n
+    }
+    if (x < -(2 ** 63)) {
+        return -(2n ** 63n)
+    }
+    return BigInt(x)
+}
+
+export function value(tag, fields) {
+    return { tag: tag, fields: fields }
+}
+
+export function bool(b) {
+    return value(b ? 'True' : 'False', [])
+}
+
+function list(values) {
+    return values.reduceRight((rest, val) => value('Cons', [val, rest]), value('Nil', []))
+}
+
+// composed functions only take one argument
+export function compose(first, second) {
+    return (x) => second(first(x))
+}
+
+export function div(a, b, where) {
+    if (b == 0n) {
+        throw new SpruceError('division by zero', where)
+    }
+    return int(a / b)
+}
+
+export function mod(a, b, where) {
+    if (b == 0n) {
+        throw new SpruceError('division by zero', where)
+    }
+    return a % b
+}
+
+export function pow(a, b, where) {
+    if (b < 0n) {
+        throw new SpruceError('cannot raise an Int to the negative power ' + b, where)
+    }
+    // by squaring, wrapping as it goes, since the exact power can be huge.
+    // The interpreter stops counting the power at 2^32 - 1
+    let power = 1n
+    for (b = b < 2n ** 32n ? b : 2n ** 32n - 1n; b > 0n; b >>= 1n) {
+        if (b & 1n) {
+            power = int(power * a)
+        }
+        a = int(a * a)
+    }
+    return power
+}
+
+// shifts only look at the lowest 6 bits of how far to shift, as in the
+// interpreter
+export function shl(a, b) {
+    return int(a << (b & 63n))
+}
+
+export function shr(a, b) {
+    return a >> (b & 63n)
+}
+
+export function noMatch(val, where) {
+    throw new SpruceError('no option matches ' + show(val), where)
+}
+
+// orders any two values of the same type: numbers and text by value, and
+// values of types by constructor name and then by argument
+function order(a, b) {
+    if (a instanceof Dict) {
+        return order(value('Dict', a.entries.flat()), value('Dict', b.entries.flat()))
+    }
+    if (a instanceof SpruceSet) {
+        return order(value('Set', a.values), value('Set', b.values))
+    }
+    if (typeof a == 'object' && a !== null) {
+        if (a.tag != b.tag) {
+            return a.tag < b.tag ? -1 : 1
+        }
+        for (let i = 0; i < Math.min(a.fields.length, b.fields.length); i++) {
+            const fieldOrder = order(a.fields[i], b.fields[i])
+            if (fieldOrder != 0) {
+                return fieldOrder
+            }
+        }
+        return a.fields.length - b.fields.length
+    }
+    return a < b ? -1 : a > b ? 1 : 0
+}
+
+export function equal(a, b) {
+    return order(a, b) == 0
+}
+
+// the builtins there are JS versions of
+
+export function show(val) {
+    if (val === undefined) {
+        return '()'
+    }
+    if (typeof val == 'function') {
+        return '<function>'
+    }
+    if (val instanceof Dict) {
+        return 'Dict(' + val.entries.map(([key, v]) => show(key) + ': ' + show(v)).join(', ') + ')'
+    }
+    if (val instanceof SpruceSet) {
+        return 'Set(' + val.values.map(show).join(', ') + ')'
+    }
+    if (typeof val != 'object') {
+        return String(val)
+    }
+    if (val.fields.length == 0) {
+        return val.tag
+    }
+    return val.tag + '(' + val.fields.map(show).join(', ') + ')'
+}
+
+export const compare = (a) => (b) => {
+    const ordering = order(a, b)
+    return value(ordering < 0 ? 'LT' : ordering > 0 ? 'GT' : 'EQ', [])
+}
+
+function maybe(val) {
+    return val === undefined ? value('Nothing', []) : value('Just', [val])
+}
+
+// JS's own parseInt and parseFloat accept text with trailing garbage
+export function parseInt(text) {
+    const n = /^-?[0-9]+$/.test(text) ? BigInt(text) : undefined
+    return maybe(n === undefined || int(n) != n ? undefined : n)
+}
+
+export function parseFloat(text) {
+    return maybe(/^-?[0-9]+(\.[0-9]+)?([eE][-+]?[0-9]+)?$/.test(text) ? Number(text) : undefined)
+}
+
+export function toFloat(n) {
+    return Number(n)
+}
+
+export const abs = (n) => n < 0n ? int(-n) : n
+export const min = (a) => (b) => a < b ? a : b
+export const max = (a) => (b) => a > b ? a : b
+export const sqrt = Math.sqrt
+export const floor = (x) => toInt(Math.floor(x))
+export const ceil = (x) => toInt(Math.ceil(x))
+export const sin = Math.sin
+export const cos = Math.cos
+export const tan = Math.tan
+
+// where key is in sorted keys, or would go if it isn't there
+function search(keys, key) {
+    let low = 0
+    let high = keys.length
+    while (low < high) {
+        const mid = (low + high) >> 1
+        if (order(keys[mid], key) < 0) {
+            low = mid + 1
+        }
+        else {
+            high = mid
+        }
+    }
+    return [low, low < keys.length && order(keys[low], key) == 0]
+}
+
+// Spruce values never change, so every update copies the entries, which are
+// [key, value] pairs sorted by key
+class Dict {
+    constructor(entries) {
+        this.entries = entries
+    }
+
+    find(key) {
+        return search(this.entries.map(([k, _]) => k), key)
+    }
+}
+
+export function emptyDict() {
+    return new Dict([])
+}
+
+export const dictInsert = (dict) => (key) => (val) => {
+    const [i, found] = dict.find(key)
+    const entries = dict.entries.slice()
+    entries.splice(i, found ? 1 : 0, [key, val])
+    return new Dict(entries)
+}
+
+export const dictGet = (dict) => (key) => {
+    const [i, found] = dict.find(key)
+    return maybe(found ? dict.entries[i][1] : undefined)
+}
+
+export const dictRemove = (dict) => (key) => {
+    const [i, found] = dict.find(key)
+    if (!found) {
+        return dict
+    }
+    const entries = dict.entries.slice()
+    entries.splice(i, 1)
+    return new Dict(entries)
+}
+
+export const dictFold = (dict) => (acc) => (fn) => {
+    for (const [key, val] of dict.entries) {
+        acc = fn(acc)(key)(val)
+    }
+    return acc
+}
+
+// values are kept sorted, so that sets can be combined by merging them. Named
+// apart from JS's own Set
+class SpruceSet {
+    constructor(values) {
+        this.values = values
+    }
+}
+
+export function emptySet() {
+    return new SpruceSet([])
+}
+
+export const setInsert = (set) => (val) => {
+    const [i, found] = search(set.values, val)
+    if (found) {
+        return set
+    }
+    const values = set.values.slice()
+    values.splice(i, 0, val)
+    return new SpruceSet(values)
+}
+
+export const setMember = (set) => (val) => bool(search(set.values, val)[1])
+
+// keeps the values in a, b or both that keep says to, in order
+function merge(a, b, keep) {
+    const values = []
+    let i = 0
+    let j = 0
+    while (i < a.length || j < b.length) {
+        const ordering = i == a.length ? 1 : j == b.length ? -1 : order(a[i], b[j])
+        if (ordering < 0) {
+            if (keep.a) { values.push(a[i]) }
+            i++
+        }
+        else if (ordering > 0) {
+            if (keep.b) { values.push(b[j]) }
+            j++
+        }
+        else {
+            values.push(a[i])
+            i++
+            j++
+        }
+    }
+    return values
+}
+
+export const setUnion = (a) => (b) => new SpruceSet(merge(a.values, b.values, {a: true, b: true}))
+export const setIntersection = (a) => (b) => new SpruceSet(merge(a.values, b.values, {a: false, b: false}))
+
+export function setFromList(ls) {
+    const values = []
+    while (ls.tag == 'Cons') {
+        values.push(ls.fields[0])
+        ls = ls.fields[1]
+    }
+    values.sort(order)
+    return new SpruceSet(values.filter((val, i) => i == 0 || order(values[i - 1], val) != 0))
+}
+
+const hasProcess = typeof process != 'undefined'
+// files can only be read and written under node
+const fs = hasProcess ? await import('node:fs') : null
+const url = hasProcess ? await import('node:url') : null
+
+// whether node was given the module at moduleUrl as the program, rather than
+// something that imports it. Its path becomes a url the way node makes one,
+// with symlinks followed and anything a url can't hold escaped
+export function isMain(moduleUrl) {
+    return hasProcess && process.argv[1] !== undefined && moduleUrl == url.pathToFileURL(fs.realpathSync(process.argv[1])).href
+}
+
+export function print(text) {
+    if (hasProcess) {
+        process.stdout.write(text)
+    }
+    else {
+        console.log(text)
+    }
+}
+
+export function printLine(text) {
+    console.log(text)
+}
+
+export function debug(val) {
+    console.error('debug: ' + show(val))
+    return val
+}
+
+// stdin is read a byte at a time, so that nothing past the line is used up
+export function readLine() {
+    if (!hasProcess) {
+        return maybe(undefined)
+    }
+    const byte = new Uint8Array(1)
+    const bytes = []
+    while (true) {
+        let read
+        try {
+            read = fs.readSync(0, byte, 0, 1, null)
+        }
+        catch (e) {
+            if (e.code == 'EAGAIN') {
+                continue
+            }
+            if (e.code != 'EOF') {
+                throw e
+            }
+            read = 0
+        }
+
+        if (read == 0 && bytes.length == 0) {
+            return maybe(undefined)
+        }
+        if (read == 0 || byte[0] == 10) {
+            return maybe(new TextDecoder().decode(new Uint8Array(bytes)).replace(/\r$/, ''))
+        }
+        bytes.push(byte[0])
+    }
+}
+
+function result(action) {
+    if (!hasProcess) {
+        return value('Err', ['files can only be used under node'])
+    }
+    try {
+        return value('Ok', [action()])
+    }
+    catch (e) {
+        return value('Err', [e.message])
+    }
+}
+
+export function readFile(path) {
+    return result(() => fs.readFileSync(path, 'utf8'))
+}
+
+export const writeFile = (path) => (text) => result(() => { fs.writeFileSync(path, text) })
+
+// node's own arguments are the interpreter and the module
+export function args() {
+    return list(hasProcess ? process.argv.slice(2) : [])
+}
+
+// mulberry32, as in the interpreter
+let randomState = Date.now() >>> 0
+
+export function setSeed(seed) {
+    randomState = Number(BigInt.asUintN(32, seed))
+}
+
+function random() {
+    randomState = (randomState + 0x6D2B79F5) >>> 0
+    let t = randomState
+    t = Math.imul(t ^ (t >>> 15), t | 1)
+    t ^= t + Math.imul(t ^ (t >>> 7), t | 61)
+    return ((t ^ (t >>> 14)) >>> 0) / 4294967296
+}
+
+export const randomInt = (low) => (high) => int(low + toInt(Math.floor(random() * Number(int(high - low + 1n)))))
+
+// runs a program's main, showing its result as spruce run would
+export function run(main) {
+    try {
+        const result = main()
+        if (result !== undefined) {
+            console.log(show(result))
+        }
+    }
+    catch (e) {
+        if (!(e instanceof SpruceError)) {
+            throw e
+        }
+        console.error('error: ' + e.message)
+        if (hasProcess) {
+            process.exitCode = 1
+        }
+    }
+}
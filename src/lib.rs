@@ -0,0 +1,3936 @@
+/*
+The spruce compiler as a library. The `spruce` binary is a command line over
+it, and a host program can use it too: compile a program, then run it with
+`eval` or `vm`, or embed it with `embed::Compiler`, registering Rust functions
+it can call and passing values across with `ToValue` and `FromValue`
+*/
+
+#[macro_use]
+extern crate pest_derive;
+
+#[macro_use]
+extern crate lazy_static;
+
+#[cfg(test)]
+use std::collections::HashMap;
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use std::path::{Path, PathBuf};
+
+pub mod parser;
+pub mod error;
+pub mod error_codes;
+pub mod lint;
+#[macro_use]
+pub mod trace;
+pub mod name_analysis;
+pub mod typecheck;
+pub mod consteval;
+pub mod fmt;
+pub mod source;
+pub mod interface;
+pub mod manifest;
+mod registry;
+#[macro_use]
+pub mod embed;
+pub mod eval;
+pub mod debugger;
+pub mod profile;
+pub mod coverage;
+pub mod heap;
+pub mod vm;
+pub mod repl;
+pub mod lsp;
+pub mod references;
+pub mod outline;
+pub mod semantic;
+pub mod doc;
+pub mod testing;
+pub mod emit;
+pub mod printer;
+pub mod graph;
+pub mod watch;
+pub mod anf;
+mod decision;
+pub mod dce;
+pub mod fold;
+pub mod inline;
+pub mod mono;
+pub mod tailcall;
+pub mod native;
+pub mod cgen;
+pub mod jsgen;
+pub mod wasm;
+#[cfg(feature = "jit")]
+pub mod jit;
+#[cfg(test)]
+mod golden;
+
+
+/// Compiles files given as (text, name) pairs
+pub fn compile(files: Vec<(&str, String)>) -> Result<(name_analysis::Prog, typecheck::Prog, typecheck::Environment), Vec<error::SpruceErr>> {
+    compile_with_lints(&source::SourceMap::from_files(&files), &mut lint::Lints::new())
+}
+
+/// Compiles with lints at the levels in `lints`, which collects any warnings.
+/// Denied lints fail compilation along with the errors
+pub fn compile_with_lints(sources: &source::SourceMap, lints: &mut lint::Lints) -> Result<(name_analysis::Prog, typecheck::Prog, typecheck::Environment), Vec<error::SpruceErr>> {
+    let prog = parser::parse(sources)?;
+    trace!(trace::Phase::Parse, "{:#?}", prog);
+
+    let analyzed_prog = name_analysis::name_analysis(prog, sources, lints).map_err(|e| vec![e])?;
+    trace!(trace::Phase::Names, "{:#?}", analyzed_prog);
+
+    let (typed_prog, environment) = match typecheck::check_prog(&analyzed_prog, lints) {
+        Ok(checked) => checked,
+        Err(mut errors) => {
+            errors.extend(lints.denied.drain(..));
+            return Err(errors);
+        }
+    };
+    trace!(trace::Phase::Typecheck, "{:#?}", typed_prog);
+    trace!(trace::Phase::Typecheck, "{}", environment.as_str(&analyzed_prog, true));
+
+    if !lints.denied.is_empty() {
+        return Err(lints.denied.drain(..).collect());
+    }
+
+    consteval::check(&analyzed_prog, &typed_prog).map_err(|e| vec![e])?;
+    Ok((analyzed_prog, typed_prog, environment))
+}
+
+#[test]
+fn test_prelude() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+}
+
+#[test]
+fn test_bootstrap() {
+    use registry::{LangType, LangValue};
+
+    let files = vec![(name_analysis::PRELUDE, String::from("prelude"))];
+    let (analyzed, _, _) = compile(files).ok().expect("prelude failed to compile");
+    let list = analyzed.registry.type_id(LangType::List);
+    assert_eq!(analyzed.type_table.types[&list].name, "List");
+    let nil = analyzed.registry.value_id(LangValue::Nil);
+    assert_eq!(analyzed.type_table.values[&nil].name, "Nil");
+    assert_eq!(analyzed.type_table.values[&nil].data_type, list);
+
+    // the compiler's types have to come from the prelude
+    let prelude = "
+type Bool {
+    True
+    False
+}
+type Maybe(a) {
+    Just(a)
+    Nothing
+}
+type Result(e, a) {
+    Ok(a)
+    Err(e)
+}
+";
+    let main_prog = "
+type List(a) {
+    Cons(a, List(a))
+    Nil
+}
+";
+    let files = vec![(prelude, String::from("prelude")), (main_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("List outside the prelude was accepted");
+    assert_eq!(errors[0].message, "the prelude must declare the type 'List', which the compiler relies on");
+}
+
+#[test]
+fn test_scope() {
+    let pass_prog = "
+x = 0
+f() {
+    x
+}
+
+g() {
+    y = True
+    case y {
+        True -> y
+        False -> y
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+f(b) {
+    case b {
+        True -> {
+            x = 1
+            x
+        }
+        False -> {
+            y = x
+            y
+        }
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_mut() {
+    let pass_prog = "
+mut x = 0
+f() {
+    x := 1
+}
+
+g() {
+    mut y = True
+    case y {
+        True -> {
+            y := False
+        }
+        False -> {
+            y := True
+        }
+    }
+
+    y
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+f() {
+    x = 1
+    x := 2
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_type() {
+    let pass_prog = "
+type FooBar(a, b) {
+    Foo(a)
+    Bar(b)
+}
+
+func(fb) {
+    case fb {
+        Foo(v) -> True
+        Bar(b) -> b
+    }
+}
+
+main() {
+    fb = Foo(3)
+    res = func(fb)
+
+    fb2 = Bar(True)
+    res2 = func(fb2)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+type FooBar(a, b) {
+    Foo(a)
+    Bar(b)
+}
+
+func(fb) {
+    case fb {
+        Foo(v) -> True
+        Bar(b) -> b
+    }
+}
+
+main() {
+    fb = Foo(3)
+    res = func(fb)
+
+    fb2 = Bar(2)
+    res2 = func(fb2)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_compose() {
+    let pass_prog = "
+inc(x) {
+    x + 1
+}
+
+double(x) {
+    x * 2
+}
+
+main() {
+    f = inc >> double
+    g = inc << double
+    f(g(3))
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+inc(x) {
+    x + 1
+}
+
+main() {
+    f = inc >> not
+    f(3)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_list_literal() {
+    let pass_prog = "
+main() {
+    empty = []
+    nested = [[1], [2, 3], []]
+    map([True, False], not)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+main() {
+    ls = [1, True]
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_cons() {
+    let pass_prog = "
+length(ls) {
+    case ls {
+        x :: rest -> 1 + length(rest)
+        Nil -> 0
+    }
+}
+
+main() {
+    ls = 1 :: 2 :: [3]
+    length(0 :: ls)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+main() {
+    ls = True :: [1]
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_doc_comments() {
+    let prog = "
+/// A point on the compass
+/// that we might walk towards
+type Direction {
+    North
+    South
+}
+
+//// not a doc comment
+flip(d) {
+    case d {
+        North -> South
+        South -> North
+    }
+}
+
+/// Goes nowhere
+main() {
+    flip(North)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, _, _) = compile(files).expect("doc comments should compile");
+
+    assert_eq!(analyzed.type_doc("Direction"), Some(&String::from("A point on the compass\nthat we might walk towards")));
+
+    let flip = analyzed.functions.iter().find(|f| { analyzed.symbol_table.lookup_id(&f.val.name).unwrap().name == "flip" }).unwrap();
+    assert_eq!(analyzed.function_doc(&flip.val.name), None);
+
+    let main = analyzed.functions.iter().find(|f| { analyzed.symbol_table.lookup_id(&f.val.name).unwrap().name == "main" }).unwrap();
+    assert_eq!(analyzed.function_doc(&main.val.name), Some(&String::from("Goes nowhere")));
+}
+
+#[test]
+fn test_negation() {
+    let pass_prog = "
+sign(n) {
+    case n < 0 {
+        True -> -1
+        False -> {
+            case n {
+                0 -> 0
+                _ -> 1
+            }
+        }
+    }
+}
+
+main() {
+    x = 4
+    case sign(-x) {
+        -1 -> -(x * 2)
+        _ -> 3 - -x
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+main() {
+    -True
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+
+    let fail_prog = "
+main() {
+    case 1 {
+        -1 -> True
+        Nil -> False
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_wildcard_patterns() {
+    let prog = "
+unwrap(m) {
+    case m {
+        Just(x) -> x
+        _ -> 0
+    }
+}
+
+classify(n) {
+    case n {
+        0 -> 10
+        -1 -> 20
+        _ -> 30
+    }
+}
+
+main() {
+    a = unwrap(Just(5)) + unwrap(Nothing)
+    b = classify(0) + classify(-1) + classify(7)
+    a * 100 + b
+}
+";
+
+    // `_` matches whatever the options above it don't, constructors and
+    // numbers alike, so the case over a Maybe covers Nothing
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let sources = source::SourceMap::from_files(&files);
+    let mut lints = lint::Lints::new();
+    let (analyzed, typed, _) = compile_with_lints(&sources, &mut lints).expect("program should compile");
+    assert_eq!(lints.warnings.len(), 0);
+    let value = eval::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(eval::show(&analyzed, &value), "560");
+    let value = vm::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(eval::show(&analyzed, &value), "560");
+
+    // it binds nothing, so it can't be read as a value
+    let prog = "f(m) {\n    case m {\n        _ -> _\n    }\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("'_' is not a value");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::Syntax));
+
+    // nor stand in for the arguments of a constructor
+    let prog = "f(m) {\n    case m {\n        Just(_) -> 1\n        _ -> 0\n    }\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    assert_eq!(compile(files).is_ok(), false);
+}
+
+#[test]
+fn test_bitwise() {
+    let pass_prog = "
+main() {
+    x = 0b1100 & 0b1010 | 1 <<< 4
+    y = x ^^^ 0xFF >>> 2
+    y == 39
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), true);
+
+    let fail_prog = "
+main() {
+    True & 1
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let res = compile(files);
+    assert_eq!(res.is_ok(), false);
+}
+
+#[test]
+fn test_polymorphism() {
+    // functions and constructors can be used at a different type each time,
+    // wherever they're declared, and mutually recursive functions are
+    // checked together
+    let prog = "
+main() {
+    a = ident(1)
+    b = ident(True)
+    p = Just(a)
+    q = Just(b)
+    case q {
+        Just(z) -> not(z)
+        Nothing -> isEven(a)
+    }
+}
+
+ident(x) {
+    x
+}
+
+isEven(n) {
+    case n {
+        0 -> True
+        _ -> isOdd(n - 1)
+    }
+}
+
+isOdd(n) {
+    case n {
+        0 -> False
+        _ -> isEven(n - 1)
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    assert_eq!(compile(files).is_ok(), true);
+
+    // a function is checked before its callers, so a bad call is reported
+    // at the call
+    let prog = "main() {\n    x = later(True)\n    x\n}\nlater(y) {\n    y * 2\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files.clone()).err().expect("later takes an Int");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].line_col(&source::SourceMap::from_files(&files)).line, 2);
+    assert_eq!(errors[0].labels[0].message, "declared here");
+
+    // a mut definition has one type, however it's assigned or read
+    let prog = "
+mut g = Nothing
+set() {
+    g := Just(True)
+}
+get() {
+    case g {
+        Just(v) -> v + 1
+        Nothing -> 0
+    }
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    assert!(compile(files).is_err());
+    let prog = "mut g = Nothing\nread() {\n    g\n}\nmain() {\n    a = read() == Just(1)\n    b = read() == Just(True)\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    assert!(compile(files).is_err());
+}
+
+#[test]
+fn test_multiple_type_errors() {
+    let fail_prog = "
+f() {
+    x = 1 + True
+    y = x + 1
+    y
+}
+
+g() {
+    not(3)
+}
+
+z = [1, True]
+w = z
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("program should not typecheck");
+    assert_eq!(errors.len(), 3);
+}
+
+#[test]
+fn test_typed_prog() {
+    let prog = "
+ident(x) {
+    x
+}
+
+bigSum(a, b) {
+    c = a + b
+    case c > 2 {
+        True -> Just(c)
+        False -> Nothing
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, env) = compile(files).expect("program should typecheck");
+    let find = |name: &str| typed.functions.iter().find(|f| analyzed.symbol_table.lookup_id(&f.val.name).unwrap().name == name).unwrap();
+
+    let ident = find("ident");
+    assert_eq!(env.type_str(ident.ty), "(a) -> a");
+    assert_eq!(ident.val.body.val.expr.as_ref().map(|expr| env.type_str(expr.ty)), Some(String::from("a")));
+
+    let big_sum = find("bigSum");
+    assert_eq!(env.type_str(big_sum.ty), "(Int, Int) -> Maybe(Int)");
+    let stmts = &big_sum.val.body.val.stmts;
+    match &stmts[0].val {
+        typecheck::Stmt::Assign(_, expr) => assert_eq!(env.type_str(expr.ty), "Int"),
+        _ => panic!("first statement is not an assignment")
+    }
+    match &stmts[1].val {
+        typecheck::Stmt::Case(case) => {
+            assert_eq!(env.type_str(case.val.expr.ty), "Bool");
+            assert_eq!(env.type_str(case.ty), "Maybe(Int)");
+        }
+        _ => panic!("second statement is not a case")
+    }
+}
+
+#[test]
+fn test_environment_queries() {
+    let prog = "
+limit = 3
+
+ident(x) {
+    x
+}
+
+lengths(xs) {
+    map(xs, len)
+}
+
+len(ls) {
+    case ls {
+        Cons(first, rest) -> 1 + len(rest)
+        Nil -> 0
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, _, env) = compile(files).expect("program should typecheck");
+
+    use typecheck::TypeRepr;
+    assert_eq!(env.type_of_symbol("limit"), Some(TypeRepr::Prim(String::from("Int"))));
+    assert_eq!(env.type_of_symbol("ident"), Some(TypeRepr::Func(vec![TypeRepr::Var(0)], Box::from(TypeRepr::Var(0)))));
+    assert_eq!(env.type_of_symbol("lengths").map(|ty| ty.to_string()), Some(String::from("(List(List(a))) -> List(Int)")));
+    assert_eq!(env.type_of_symbol("map").map(|ty| ty.to_string()), Some(String::from("(List(a), (a) -> b) -> List(b)")));
+    assert_eq!(env.type_of_symbol("x"), None);
+    assert_eq!(env.type_of_symbol("missing"), None);
+
+    // locals are only found by id
+    let types: Vec<(String, String)> = env.symbol_types().map(|(id, ty)| {
+        (analyzed.symbol_table.lookup_id(&id).unwrap().name.clone(), ty.to_string())
+    }).collect();
+    assert_eq!(types.contains(&(String::from("x"), String::from("a"))), true);
+    assert_eq!(types.contains(&(String::from("rest"), String::from("List(a)"))), true);
+}
+
+#[test]
+fn test_list_library() {
+    let prog = "
+evens(ls) {
+    filter(ls, isEven)
+}
+
+isEven(n) {
+    n % 2 == 0
+}
+
+sum(ls) {
+    fold(ls, 0, add)
+}
+
+add(acc, n) {
+    acc + n
+}
+
+both = append([1, 2], [3])
+count = length([True, False])
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("map"), Some(String::from("(List(a), (a) -> b) -> List(b)")));
+    assert_eq!(type_of("filter"), Some(String::from("(List(a), (a) -> Bool) -> List(a)")));
+    assert_eq!(type_of("fold"), Some(String::from("(List(a), b, (b, a) -> b) -> b")));
+    assert_eq!(type_of("length"), Some(String::from("(List(a)) -> Int")));
+    assert_eq!(type_of("append"), Some(String::from("(List(a), List(a)) -> List(a)")));
+
+    // each use instantiates the schemes afresh
+    assert_eq!(type_of("evens"), Some(String::from("(List(Int)) -> List(Int)")));
+    assert_eq!(type_of("sum"), Some(String::from("(List(Int)) -> Int")));
+    assert_eq!(type_of("both"), Some(String::from("List(Int)")));
+    assert_eq!(type_of("count"), Some(String::from("Int")));
+}
+
+#[test]
+fn test_maybe_library() {
+    let prog = "
+half(n) {
+    case n % 2 == 0 {
+        True -> Just(n / 2)
+        False -> Nothing
+    }
+}
+
+quarter(n) {
+    andThen(half(n), half)
+}
+
+describe(n) {
+    withDefault(maybeMap(half(n), isSmall), False)
+}
+
+isSmall(n) {
+    n < 10
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("andThen"), Some(String::from("(Maybe(a), (a) -> Maybe(b)) -> Maybe(b)")));
+    assert_eq!(type_of("maybeMap"), Some(String::from("(Maybe(a), (a) -> b) -> Maybe(b)")));
+    assert_eq!(type_of("withDefault"), Some(String::from("(Maybe(a), a) -> a")));
+    assert_eq!(type_of("quarter"), Some(String::from("(Int) -> Maybe(Int)")));
+    assert_eq!(type_of("describe"), Some(String::from("(Int) -> Bool")));
+
+    // the fallback has to have the type of the value
+    let fail_prog = "
+f(m) {
+    withDefault(maybeMap(m, isSmall), 0)
+}
+
+isSmall(n) {
+    n < 10
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    assert_eq!(compile(files).is_ok(), false);
+}
+
+#[test]
+fn test_result_library() {
+    let prog = "
+checked(n) {
+    case n < 0 {
+        True -> Err(n)
+        False -> Ok(n)
+    }
+}
+
+double(n) {
+    resultMap(checked(n), twice)
+}
+
+twice(n) {
+    n * 2
+}
+
+flagged(n) {
+    mapErr(checked(n), isSmall)
+}
+
+isSmall(n) {
+    n < 10
+}
+
+both(n) {
+    resultAndThen(checked(n), checked)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("resultMap"), Some(String::from("(Result(a, b), (b) -> c) -> Result(a, c)")));
+    assert_eq!(type_of("mapErr"), Some(String::from("(Result(a, b), (a) -> c) -> Result(c, b)")));
+    assert_eq!(type_of("resultAndThen"), Some(String::from("(Result(a, b), (b) -> Result(a, c)) -> Result(a, c)")));
+    assert_eq!(type_of("double"), Some(String::from("(Int) -> Result(Int, Int)")));
+    assert_eq!(type_of("flagged"), Some(String::from("(Int) -> Result(Bool, Int)")));
+    assert_eq!(type_of("both"), Some(String::from("(Int) -> Result(Int, Int)")));
+}
+
+#[test]
+fn test_compare() {
+    let prog = "
+insert(x, ls) {
+    case ls {
+        Cons(y, rest) -> {
+            case compare(x, y) {
+                GT -> Cons(y, insert(x, rest))
+                LT -> Cons(x, ls)
+                EQ -> Cons(x, ls)
+            }
+        }
+        Nil -> [x]
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("compare"), Some(String::from("(a, a) -> Ordering")));
+    assert_eq!(type_of("insert"), Some(String::from("(a, List(a)) -> List(a)")));
+
+    // anything but a function can be compared
+    let prog = "main() {\n    a = compare(2, 10)\n    b = compare(show(12), show(3))\n    c = compare(Just(1), Nothing)\n    d = compare([1, 2], [1, 2])\n    [a, b, c, d]\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files).expect("program should typecheck");
+    let value = eval::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(eval::show(&analyzed, &value), "Cons(LT, Cons(LT, Cons(LT, Cons(EQ, Nil))))");
+    let value = vm::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(eval::show(&analyzed, &value), "Cons(LT, Cons(LT, Cons(LT, Cons(EQ, Nil))))");
+
+    let prog = "f(x) {\n    x\n}\nmain() {\n    o = compare(Just(f), Nothing)\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("functions have no order");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::IncomparableType));
+}
+
+#[test]
+fn test_show() {
+    let prog = "
+describe(m) {
+    show(maybeMap(m, double))
+}
+
+double(n) {
+    n * 2
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("show"), Some(String::from("(a) -> String")));
+    assert_eq!(type_of("describe"), Some(String::from("(Maybe(Int)) -> String")));
+
+    let fail_prog = "
+main() {
+    x = show(Just(double))
+    y = show(double)
+}
+
+double(n) {
+    n * 2
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("functions can't be shown");
+    assert_eq!(errors.len(), 2);
+    assert_eq!(errors[0].message, "cannot show a function of type (Int) -> Int");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::UnshowableType));
+
+    // only the prelude can declare builtins
+    let fail_prog = "builtin shout : (String) -> String\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("builtins belong to the prelude");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MisplacedBuiltin));
+}
+
+#[test]
+fn test_parse_builtins() {
+    let prog = "
+roundTrip(n) {
+    withDefault(parseInt(show(n)), 0)
+}
+
+asFloat(n) {
+    parseFloat(show(n))
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("parseInt"), Some(String::from("(String) -> Maybe(Int)")));
+    assert_eq!(type_of("parseFloat"), Some(String::from("(String) -> Maybe(Float)")));
+    assert_eq!(type_of("roundTrip"), Some(String::from("(a) -> Int")));
+    assert_eq!(type_of("asFloat"), Some(String::from("(a) -> Maybe(Float)")));
+
+    let fail_prog = "
+main() {
+    x = parseInt(1)
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("parseInt takes text");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MismatchedTypes));
+}
+
+#[test]
+fn test_math_builtins() {
+    let prog = "
+clamp(n, low, high) {
+    max(low, min(n, high))
+}
+
+hypot(x, y) {
+    sqrt(toFloat(x * x + y * y))
+}
+
+roundUp(n) {
+    ceil(sin(toFloat(n)))
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("abs"), Some(String::from("(Int) -> Int")));
+    assert_eq!(type_of("floor"), Some(String::from("(Float) -> Int")));
+    assert_eq!(type_of("tan"), Some(String::from("(Float) -> Float")));
+    assert_eq!(type_of("clamp"), Some(String::from("(Int, Int, Int) -> Int")));
+    assert_eq!(type_of("hypot"), Some(String::from("(Int, Int) -> Float")));
+    assert_eq!(type_of("roundUp"), Some(String::from("(Int) -> Int")));
+
+    // Ints have to be converted before they can be used as Floats
+    let fail_prog = "
+main() {
+    x = sqrt(2)
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("sqrt takes a Float");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MismatchedTypes));
+}
+
+#[test]
+fn test_dict() {
+    let prog = "
+ages() {
+    dictInsert(dictInsert(emptyDict(), 1, 30), 2, 41)
+}
+
+oldest(d) {
+    dictFold(d, 0, older)
+}
+
+older(acc, key, age) {
+    max(acc, age)
+}
+
+lookup(key) {
+    dictGet(dictRemove(ages(), 1), key)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("dictInsert"), Some(String::from("(Dict(a, b), a, b) -> Dict(a, b)")));
+    assert_eq!(type_of("dictFold"), Some(String::from("(Dict(a, b), c, (c, a, b) -> c) -> c")));
+    assert_eq!(type_of("ages"), Some(String::from("() -> Dict(Int, Int)")));
+    assert_eq!(type_of("oldest"), Some(String::from("(Dict(a, Int)) -> Int")));
+    assert_eq!(type_of("lookup"), Some(String::from("(Int) -> Maybe(Int)")));
+
+    // keys all have one type
+    let fail_prog = "
+main() {
+    d = dictInsert(dictInsert(emptyDict(), 1, 2), True, 3)
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("keys of different types");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MismatchedTypes));
+
+    // only the prelude can declare builtin types
+    let fail_prog = "builtin type Queue(a)\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("builtin types belong to the prelude");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MisplacedBuiltin));
+}
+
+#[test]
+fn test_set() {
+    let prog = "
+primes() {
+    setFromList([2, 3, 5, 7])
+}
+
+evenPrimes() {
+    setIntersection(primes(), setInsert(emptySet(), 2))
+}
+
+isPrime(n) {
+    setMember(setUnion(primes(), evenPrimes()), n)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("setFromList"), Some(String::from("(List(a)) -> Set(a)")));
+    assert_eq!(type_of("setMember"), Some(String::from("(Set(a), a) -> Bool")));
+    assert_eq!(type_of("evenPrimes"), Some(String::from("() -> Set(Int)")));
+    assert_eq!(type_of("isPrime"), Some(String::from("(Int) -> Bool")));
+
+    let fail_prog = "
+main() {
+    s = setUnion(setFromList([1]), setFromList([True]))
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("sets of different types");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MismatchedTypes));
+}
+
+#[test]
+fn test_output_builtins() {
+    let prog = "
+main() {
+    print(show(1))
+    done = printLine(show(debug(Just(2))))
+    done
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("print"), Some(String::from("(String) -> ()")));
+    assert_eq!(type_of("printLine"), Some(String::from("(String) -> ()")));
+    assert_eq!(type_of("debug"), Some(String::from("(a) -> a")));
+    assert_eq!(type_of("main"), Some(String::from("() -> ()")));
+
+    let fail_prog = "
+main() {
+    print(1)
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("print takes text");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MismatchedTypes));
+}
+
+#[test]
+fn test_input_builtins() {
+    let prog = "
+sumLines(total) {
+    case readLine() {
+        Just(line) -> sumLines(total + withDefault(parseInt(line), 0))
+        Nothing -> total
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("readLine"), Some(String::from("() -> Maybe(String)")));
+    assert_eq!(type_of("sumLines"), Some(String::from("(Int) -> Int")));
+
+    // the end of input has to be handled
+    let fail_prog = "
+main() {
+    printLine(readLine())
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files).err().expect("readLine might not have a line");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MismatchedTypes));
+}
+
+#[test]
+fn test_file_builtins() {
+    let prog = "
+copy(from, to) {
+    case readFile(from) {
+        Ok(text) -> writeFile(to, text)
+        Err(err) -> Err(err)
+    }
+}
+
+number(path) {
+    resultMap(readFile(path), parseInt)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("readFile"), Some(String::from("(String) -> Result(String, String)")));
+    assert_eq!(type_of("writeFile"), Some(String::from("(String, String) -> Result(String, ())")));
+    assert_eq!(type_of("copy"), Some(String::from("(String, String) -> Result(String, ())")));
+    assert_eq!(type_of("number"), Some(String::from("(String) -> Result(String, Maybe(Int))")));
+}
+
+#[test]
+fn test_args_builtin() {
+    let prog = "
+firstNumber() {
+    case args() {
+        Cons(arg, rest) -> parseInt(arg)
+        Nil -> Nothing
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("args"), Some(String::from("() -> List(String)")));
+    assert_eq!(type_of("firstNumber"), Some(String::from("() -> Maybe(Int)")));
+}
+
+#[test]
+fn test_random_builtins() {
+    let prog = "
+roll() {
+    randomInt(1, 6)
+}
+
+rollSeeded(seed) {
+    setSeed(seed)
+    roll()
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (_, _, env) = compile(files).expect("program should typecheck");
+
+    let type_of = |name: &str| env.type_of_symbol(name).map(|ty| ty.to_string());
+    assert_eq!(type_of("randomInt"), Some(String::from("(Int, Int) -> Int")));
+    assert_eq!(type_of("setSeed"), Some(String::from("(Int) -> ()")));
+    assert_eq!(type_of("rollSeeded"), Some(String::from("(Int) -> Int")));
+}
+
+#[test]
+fn test_eval() {
+    let prog = "
+total = fold([1, 2, 3], 0, add)
+mut calls = 0
+add(a, b) {
+    a + b
+}
+double(x) {
+    x * 2
+}
+fact(n) {
+    calls := calls + 1
+    case n {
+        0 -> 1
+        _ -> n * fact(n - 1)
+    }
+}
+main() {
+    d = dictInsert(dictInsert(emptyDict(), 2, total), 1, fact(5))
+    [Ok(map(args(), parseInt)), Err(dictGet(d, 1)), Err(Just(calls)), Err(dictGet(d, total))]
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (prog, typed, _) = compile(files).expect("program should typecheck");
+    let value = eval::run_prog(&prog, &typed, "main", eval::Runtime::new(vec![String::from("7")], None)).expect("program should run");
+    assert_eq!(eval::show(&prog, &value), "Cons(Ok(Cons(Just(7), Nil)), Cons(Err(Just(120)), Cons(Err(Just(6)), Cons(Err(Nothing), Nil))))");
+
+    // errors at runtime point at where they happened
+    let fail_prog = "
+main() {
+    x = 3
+    y = x - 3
+    x % y
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let (prog, typed, _) = compile(files).expect("program should typecheck");
+    let error = eval::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    assert_eq!(error.message, "division by zero");
+    assert_eq!(&fail_prog[error.info.span.start..error.info.span.end], "x % y");
+}
+
+#[test]
+fn test_tail_calls() {
+    let prog = "
+count(n, acc) {
+    case n {
+        0 -> acc
+        _ -> @tail count(n - 1, acc + 1)
+    }
+}
+even(n) {
+    case n {
+        0 -> True
+        _ -> odd(n - 1)
+    }
+}
+odd(n) {
+    case n {
+        0 -> False
+        _ -> even(n - 1)
+    }
+}
+toInt(b) {
+    case b {
+        True -> 1
+        False -> 0
+    }
+}
+main() {
+    [count(200000, 0), toInt(even(200001))]
+}
+";
+
+    // far deeper than the test's stack would allow if each call recursed
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files).expect("program should typecheck");
+    let value = eval::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(eval::show(&analyzed, &value), "Cons(200000, Cons(0, Nil))");
+
+    // only calls to the function itself start it over in the IR
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let dump = program.dump(&analyzed);
+    assert!(dump.contains("recur(t2, t3)"));
+    assert!(dump.contains("odd(t1)"));
+
+    let misplaced = "
+count(n) {
+    case n {
+        0 -> 0
+        _ -> 1 + @tail count(n - 1)
+    }
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (misplaced, String::from("Main"))];
+    let errors = compile(files).err().expect("the call isn't in tail position");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MisplacedTailCall));
+    assert_eq!(&misplaced[errors[0].info.span.start..errors[0].info.span.end], "@tail count(n - 1)");
+}
+
+#[test]
+fn test_heap() {
+    let prog = "
+build(n, acc) {
+    case n {
+        0 -> acc
+        _ -> build(n - 1, n :: acc)
+    }
+}
+main() {
+    xs = build(20, [])
+    length(xs)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (prog, typed, _) = compile(files).expect("program should typecheck");
+
+    // everything a program builds is freed once it's done, whichever engine
+    // runs it
+    heap::configure(None, true);
+    let value = eval::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(value, eval::Value::Int(20));
+    assert_eq!(heap::report(), Some(Ok(String::from("heap: 21 values built, at most 21 alive at once"))));
+    heap::configure(None, true);
+    vm::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert!(matches!(heap::report(), Some(Ok(_))));
+
+    // a run that needs more than the limit allows fails where it goes over
+    heap::configure(Some(10), false);
+    let error = eval::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("the heap should run out");
+    assert!(error.message.contains("out of memory"));
+    assert_eq!(heap::live(), 0);
+    heap::configure(Some(10), false);
+    assert!(vm::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).is_err());
+    heap::configure(None, false);
+    assert_eq!(heap::report(), None);
+}
+
+#[test]
+fn test_host() {
+    use embed::{Compiler, Type, Value};
+
+    let mut compiler = Compiler::new();
+    compiler.register_fn("double", Type::Func(vec![Type::Int], Box::new(Type::Int)), |_, args| match args {
+        [Value::Int(n)] => Ok(Value::Int(n * 2)),
+        _ => Err(String::from("double expects an Int"))
+    });
+    compiler.register_fn("shout", Type::Func(vec![Type::String], Box::new(Type::String)), |_, args| match args {
+        [Value::Str(text)] => Ok(Value::Str(format!("{}!", text.to_uppercase()).into())),
+        _ => Err(String::from("shout expects a String"))
+    });
+    compiler.register_fn("fetch", Type::Func(vec![Type::Int], Box::new(Type::Maybe(Box::new(Type::Var(String::from("a")))))), |_, _| {
+        Err(String::from("no network"))
+    });
+    assert!(compiler.prelude().ends_with("builtin fetch : (Int) -> Maybe(a)\n"));
+
+    // host functions are called like any other builtin, whichever engine
+    // runs the program
+    let prog = "
+main() {
+    shout(show(double(21)))
+}
+";
+    let (analyzed, typed, _) = compiler.compile(vec![(prog, String::from("Main"))]).expect("program should typecheck");
+    let value = eval::run_prog(&analyzed, &typed, "main", compiler.runtime(Vec::new(), None)).expect("program should run");
+    assert_eq!(value, Value::Str("42!".into()));
+    let value = vm::run_prog(&analyzed, &typed, "main", compiler.runtime(Vec::new(), None)).expect("program should run");
+    assert_eq!(value, Value::Str("42!".into()));
+
+    // they're checked against the type they were registered with
+    let prog = "
+main() {
+    double(show(21))
+}
+";
+    assert!(compiler.compile(vec![(prog, String::from("Main"))]).is_err());
+    assert!(Compiler::new().compile(vec![("main() {\n    double(21)\n}\n", String::from("Main"))]).is_err());
+
+    // and one that fails fails the run at its call
+    let prog = "
+main() {
+    withDefault(fetch(80), 0)
+}
+";
+    let (analyzed, typed, _) = compiler.compile(vec![(prog, String::from("Main"))]).expect("program should typecheck");
+    let error = eval::run_prog(&analyzed, &typed, "main", compiler.runtime(Vec::new(), None)).err().expect("fetch should fail");
+    assert_eq!(error.message, "no network");
+    assert_eq!(&prog[error.info.span.start..error.info.span.end], "fetch(80)");
+}
+
+#[test]
+fn test_marshal() {
+    use std::convert::TryFrom;
+    use embed::{Compiler, FromValue, ToValue, Type, Value};
+
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: i64,
+        y: i64
+    }
+    marshal_struct!(Point { x, y });
+
+    let mut compiler = Compiler::new();
+    compiler.register_fn("upTo", Type::Func(vec![Type::Int], Box::new(Type::List(Box::new(Type::Int)))), |prog, args| {
+        let n = i64::try_from(args[0].clone())?;
+        (0..n).collect::<Vec<i64>>().to_value(prog)
+    });
+    let prog = "
+type Point {
+    Point(Int, Int)
+}
+scale(p, k) {
+    case p {
+        Point(x, y) -> Point(x * k, y * k)
+    }
+}
+firstOver(xs, n) {
+    case xs {
+        Cons(x, rest) -> {
+            case x > n {
+                True -> Just(x)
+                False -> firstOver(rest, n)
+            }
+        }
+        Nil -> Nothing
+    }
+}
+total(n) {
+    fold(upTo(n), 0, add)
+}
+add(acc, x) {
+    acc + x
+}
+";
+    let (analyzed, typed, _) = compiler.compile(vec![(prog, String::from("Main"))]).expect("program should typecheck");
+
+    // Rust values go in, and come back out, as the Spruce values they stand for
+    let point = Point { x: 1, y: -2 }.to_value(&analyzed).expect("the program has Point");
+    let scaled = compiler.call(&analyzed, &typed, "scale", vec![point, Value::from(3)]).expect("scale should run");
+    assert_eq!(Point::from_value(&analyzed, &scaled), Ok(Point { x: 3, y: -6 }));
+
+    let xs = vec![1i64, 5, 9].to_value(&analyzed).expect("lists are in the prelude");
+    let found = compiler.call(&analyzed, &typed, "firstOver", vec![xs.clone(), Value::from(4)]).expect("firstOver should run");
+    assert_eq!(Option::<i64>::from_value(&analyzed, &found), Ok(Some(5)));
+    let found = compiler.call(&analyzed, &typed, "firstOver", vec![xs.clone(), Value::from(9)]).expect("firstOver should run");
+    assert_eq!(Option::<i64>::from_value(&analyzed, &found), Ok(None));
+    assert_eq!(Vec::<i64>::from_value(&analyzed, &xs), Ok(vec![1, 5, 9]));
+
+    // including through host functions
+    let sum = compiler.call(&analyzed, &typed, "total", vec![Value::from(5)]).expect("total should run");
+    assert_eq!(i64::try_from(sum), Ok(10));
+
+    // a value of another type is refused rather than misread
+    assert_eq!(bool::from_value(&analyzed, &Value::from(3)), Err(String::from("expected a Bool, not 3")));
+    assert_eq!(Point::from_value(&analyzed, &xs), Err(String::from("expected Point, not Cons(1, Cons(5, Cons(9, Nil)))")));
+    assert!(compiler.call(&analyzed, &typed, "scale", vec![Value::from(1)]).is_err());
+}
+
+#[test]
+fn test_repl() {
+    let mut session = repl::Session::new(embed::Compiler::new(), false);
+
+    // definitions join the session, and expressions are worked out with them
+    assert_eq!(session.enter("x = 20"), Ok(String::from("x : Int")));
+    assert_eq!(session.enter("double(n) {\n    n * 2\n}\n"), Ok(String::from("double : (Int) -> Int")));
+    assert_eq!(session.enter("type Shape {\n    Square(Int)\n    Dot\n}\n"), Ok(String::from("type Shape")));
+    assert_eq!(session.enter("double(x) + 2"), Ok(String::from("42 : Int")));
+    assert_eq!(session.enter("map([Dot], show)"), Ok(String::from("Cons(Dot, Nil) : List(String)")));
+    assert_eq!(session.enter("Square(x)"), Ok(String::from("Square(20) : Shape")));
+    assert_eq!(session.enter("Just(3)"), Ok(String::from("Just(3) : Maybe(Int)")));
+    assert_eq!(session.enter("case x {\n    20 -> Just(x)\n    _ -> Nothing\n}\n"), Ok(String::from("Just(20) : Maybe(Int)")));
+    assert_eq!(session.enter(""), Ok(String::new()));
+
+    // defining a name again replaces it, and what uses it sees the new one
+    assert_eq!(session.enter("x = 5"), Ok(String::from("x : Int")));
+    assert_eq!(session.enter("double(x)"), Ok(String::from("10 : Int")));
+
+    // an entry that doesn't check leaves the session as it was
+    assert!(session.enter("double(n) {\n    n + True\n}\n").is_err());
+    assert!(session.enter("1 +").err().expect("the entry doesn't parse").contains("Parse error"));
+    assert!(session.enter("x / 0").err().expect("the entry fails").contains("division by zero"));
+
+    // errors point at the entry as it was typed, after the ones before it
+    let error = session.enter("x + True").err().expect("the entry doesn't check");
+    assert!(error.contains(" --> repl:9:5\n") && error.contains("\n9 | x + True\n"), "{}", error);
+    let error = session.enter("y = x\ny + True").err().expect("the entry doesn't check");
+    assert!(error.contains(" --> repl:10:5\n") && error.contains("\n10 | y + True\n"), "{}", error);
+    let error = session.enter("double(x / 0)").err().expect("the entry fails");
+    assert!(error.contains(" --> repl:9:8\n") && !error.contains("= in:"), "{}", error);
+    let error = session.enter(":type double(").err().expect("the entry doesn't parse");
+    assert!(error.contains("\n1 | double(\n") && !error.contains("replInput") && !error.contains("replValue"), "{}", error);
+    assert_eq!(session.enter("double(4)"), Ok(String::from("8 : Int")));
+
+    // commands look at the session without running anything
+    assert!(session.enter(":type map([Square(x)], double)").is_err());
+    assert_eq!(session.enter(":type printLine(show(x))"), Ok(String::from("printLine(show(x)) : ()")));
+    assert_eq!(session.enter(":type map([x], double)"), Ok(String::from("map([x], double) : List(Int)")));
+    assert_eq!(session.enter(":type Just(x)"), Ok(String::from("Just(x) : Maybe(Int)")));
+    assert_eq!(session.enter(":type Just"), Ok(String::from("Just : (a) -> Maybe(a)")));
+    assert_eq!(session.enter(":info Shape"), Ok(String::from("type Shape {\n    Square(Int)\n    Dot\n}")));
+    assert_eq!(session.enter(":info Dot"), Ok(String::from("Dot is a constructor of Shape\ntype Shape {\n    Square(Int)\n    Dot\n}")));
+    assert_eq!(session.enter("/// Twice n\ndouble(n) {\n    n * 2\n}\n"), Ok(String::from("double : (Int) -> Int")));
+    assert_eq!(session.enter(":info double"), Ok(String::from("/// Twice n\ndouble : (Int) -> Int")));
+    assert!(session.enter(":info triple").is_err());
+    assert!(session.enter(":kind Shape").is_err());
+
+    // names are completed from the session, along with keywords and commands
+    let names = session.names();
+    assert_eq!(repl::completions(&names, "map([Sq", 7), (5, vec![String::from("Square")]));
+    assert_eq!(repl::completions(&names, "dou", 3), (0, vec![String::from("double")]));
+    assert_eq!(repl::completions(&names, "ty", 2), (0, vec![String::from("type")]));
+    assert_eq!(repl::completions(&names, ":re", 3), (0, vec![String::from(":reload")]));
+    assert_eq!(repl::completions(&names, "x + ", 4), (4, Vec::new()));
+}
+
+#[test]
+fn test_format() {
+    let messy = "import shapes
+# helpers
+
+/// Doubles n
+double(n) {
+  n*2 // twice
+}
+type Shape {
+    Sq(Int) // a square
+    Pt
+}
+x = (1+2)*3 - (4-5) - -3
+mut y = 0x1_0
+z = (f << g) >> h << (f >> g)
+main() {
+    y := y+1
+    case x {
+        n :: rest -> n
+        _ -> describeAtLength(x, someArgumentWithALongName, anotherOfThem, andAThird)
+    }
+
+
+    @tail loop(1 :: (2 :: []), (f >> g) >> h, f >> (g >> h))
+}
+";
+    let formatted = "import shapes
+
+# helpers
+
+/// Doubles n
+double(n) {
+    n * 2 // twice
+}
+
+type Shape {
+    Sq(Int) // a square
+    Pt
+}
+
+x = (1 + 2) * 3 - (4 - 5) - -3
+mut y = 0x1_0
+z = f << g >> h << (f >> g)
+
+main() {
+    y := y + 1
+    case x {
+        n :: rest -> n
+        _ -> {
+            describeAtLength(
+                x,
+                someArgumentWithALongName,
+                anotherOfThem,
+                andAThird
+            )
+        }
+    }
+
+    @tail loop(1 :: 2 :: [], f >> g >> h, f >> (g >> h))
+}
+";
+    assert_eq!(fmt::format_source(messy), formatted);
+    assert_eq!(fmt::format_source(formatted), formatted);
+    assert_eq!(fmt::format_source("double(n) {\n"), "double(n) {\n");
+
+    // the prelude comes out the same on a second pass, and means the same
+    let prelude = fmt::format_source(name_analysis::PRELUDE);
+    assert_eq!(fmt::format_source(&prelude), prelude);
+    let files = vec![(prelude.as_str(), String::from("prelude"))];
+    let (analyzed, _, _) = compile(files).ok().expect("the formatted prelude failed to compile");
+    let (original, _, _) = compile(vec![(name_analysis::PRELUDE, String::from("prelude"))]).ok().expect("prelude failed to compile");
+    assert_eq!(analyzed.functions.len(), original.functions.len());
+    assert_eq!(analyzed.signatures.len(), original.signatures.len());
+}
+
+#[test]
+fn test_repl_load() {
+    let dir = std::env::temp_dir().join(format!("spruce-test-repl-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("failed to make the test directory");
+    let path = dir.join("sizes.sp");
+    let path_name = path.to_string_lossy().into_owned();
+    fs::write(&path, "module sizes (size)\n\nsize(n) {\n    n * 10\n}\n").expect("failed to write the file");
+
+    let mut session = repl::Session::new(embed::Compiler::new(), false);
+    assert_eq!(session.enter(&format!(":load {}", path_name)), Ok(format!("loaded {}", path_name)));
+    assert_eq!(session.enter("size(4)"), Ok(String::from("40 : Int")));
+    assert_eq!(session.enter("bigger(n) {\n    size(n) + 1\n}\n"), Ok(String::from("bigger : (Int) -> Int")));
+
+    // reloading sees the file as it's been edited, and keeps the entries
+    fs::write(&path, "module sizes (size)\n\nsize(n) {\n    n * 100\n}\n").expect("failed to write the file");
+    assert_eq!(session.enter(":reload"), Ok(format!("loaded {}", path_name)));
+    assert_eq!(session.enter("bigger(4)"), Ok(String::from("401 : Int")));
+
+    // an edit the entries no longer check with leaves the file as it was
+    fs::write(&path, "module sizes (area)\n\narea(n) {\n    n * n\n}\n").expect("failed to write the file");
+    assert!(session.enter(":reload").is_err());
+    assert_eq!(session.enter("bigger(4)"), Ok(String::from("401 : Int")));
+    assert!(session.enter(":load nowhere.sp").err().expect("there's no such file").starts_with("cannot read nowhere.sp"));
+    fs::remove_dir_all(&dir).ok();
+
+    // an entry goes on while it has a bracket or a comment open
+    assert!(repl::incomplete("triple(n) {\n"));
+    assert!(repl::incomplete("/* a { comment\n"));
+    assert!(!repl::incomplete("triple(n) {\n    n * 3 // }\n}\n"));
+    assert!(!repl::incomplete("[1, 2] # [\n"));
+    assert!(!repl::incomplete(":reload\n"));
+}
+
+#[test]
+fn test_lsp() {
+    use lsp_server::{Connection, Message, Notification, Request};
+    use lsp_types::notification::{self, Notification as _};
+    use lsp_types::request::{self, Request as _};
+
+    let dir = std::env::temp_dir().join(format!("spruce-test-lsp-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("failed to make the test directory");
+    let dir = fs::canonicalize(&dir).expect("the test directory is there");
+    let shapes = dir.join("shapes.sp");
+    fs::write(&shapes, "module shapes (area)\n\n/// The area of a square with sides n long\narea(n) {\n    n * n\n}\n").expect("failed to write the file");
+
+    // main.sp is only ever open in the editor, never saved
+    let main = dir.join("main.sp");
+    let main_uri = lsp::uri_of(&main);
+
+    let (server, client) = Connection::memory();
+    let serving = std::thread::Builder::new().stack_size(1 << 30).spawn(move || lsp::serve(server)).expect("failed to start the server");
+    let notify = |method: &str, params: serde_json::Value| {
+        client.sender.send(Message::Notification(Notification::new(method.to_string(), params))).expect("the server has gone");
+    };
+    let request = |id: i32, method: &str, params: serde_json::Value| {
+        client.sender.send(Message::Request(Request::new(id.into(), method.to_string(), params))).expect("the server has gone");
+        match client.receiver.recv() {
+            Ok(Message::Response(response)) => response,
+            other => panic!("expected a response, got {:?}", other)
+        }
+    };
+    // each check publishes the diagnostics for both files
+    let published = || -> HashMap<PathBuf, Vec<lsp_types::Diagnostic>> {
+        (0..2).map(|_| match client.receiver.recv() {
+            Ok(Message::Notification(notification)) if notification.method == notification::PublishDiagnostics::METHOD => {
+                let params: lsp_types::PublishDiagnosticsParams = serde_json::from_value(notification.params).expect("diagnostics are well formed");
+                (lsp::path_of(&params.uri).expect("diagnostics are for files"), params.diagnostics)
+            }
+            other => panic!("expected diagnostics, got {:?}", other)
+        }).collect()
+    };
+
+    let initialized = request(1, request::Initialize::METHOD, serde_json::to_value(lsp_types::InitializeParams::default()).unwrap());
+    assert!(initialized.result.is_some());
+    notify(notification::Initialized::METHOD, serde_json::json!({}));
+
+    let open = serde_json::json!({ "textDocument": { "uri": main_uri.as_str(), "languageId": "spruce", "version": 1, "text": "import shapes\n\nmain() {\n    area(7 == 7)\n}\n" } });
+    notify(notification::DidOpenTextDocument::METHOD, open);
+    let diagnostics = published();
+    assert_eq!(diagnostics[&shapes].len(), 0);
+    assert_eq!(diagnostics[&main].len(), 1);
+    assert_eq!(diagnostics[&main][0].severity, Some(lsp_types::DiagnosticSeverity::ERROR));
+    assert_eq!(diagnostics[&main][0].range.start.line, 3);
+
+    // fixing the buffer clears the error without the file being saved
+    let change = serde_json::json!({ "textDocument": { "uri": main_uri.as_str(), "version": 2 }, "contentChanges": [{ "text": "import shapes\n\nmain() {\n    area(7)\n}\n" }] });
+    notify(notification::DidChangeTextDocument::METHOD, change);
+    let diagnostics = published();
+    assert_eq!(diagnostics[&main].len(), 0);
+    assert_eq!(diagnostics[&shapes].len(), 0);
+
+    // hovering shows types, and on what has a doc comment, the comment
+    let hover = |id: i32, line: u32, character: u32| -> Option<(String, Option<lsp_types::Range>)> {
+        let at = serde_json::json!({ "textDocument": { "uri": main_uri.as_str() }, "position": { "line": line, "character": character } });
+        let hover: Option<lsp_types::Hover> = serde_json::from_value(request(id, request::HoverRequest::METHOD, at).result.expect("hovering succeeds")).unwrap();
+        hover.map(|hover| match hover.contents {
+            lsp_types::HoverContents::Markup(markup) => (markup.value, hover.range),
+            other => panic!("expected markdown, got {:?}", other)
+        })
+    };
+    let (shown, range) = hover(10, 3, 5).expect("the call has a type");
+    assert_eq!(shown, "```spruce\narea : (Int) -> Int\n```\n\nThe area of a square with sides n long");
+    assert_eq!(range, Some(lsp_types::Range::new(lsp_types::Position::new(3, 4), lsp_types::Position::new(3, 8))));
+    assert_eq!(hover(11, 3, 9).map(|(shown, _)| shown), Some(String::from("```spruce\nInt\n```")));
+    assert_eq!(hover(12, 1, 0), None);
+
+    // completion offers what's in scope, closest first, and at the start of
+    // an arm, the constructors of what the case matches
+    let change = |version: i32, text: &str| {
+        let change = serde_json::json!({ "textDocument": { "uri": main_uri.as_str(), "version": version }, "contentChanges": [{ "text": text }] });
+        notify(notification::DidChangeTextDocument::METHOD, change);
+        published()
+    };
+    let complete = |id: i32, line: u32, character: u32| -> Vec<String> {
+        let at = serde_json::json!({ "textDocument": { "uri": main_uri.as_str() }, "position": { "line": line, "character": character } });
+        match serde_json::from_value(request(id, request::Completion::METHOD, at).result.expect("completion succeeds")).unwrap() {
+            lsp_types::CompletionResponse::Array(items) => items.into_iter().map(|item| item.label).collect(),
+            other => panic!("expected a list, got {:?}", other)
+        }
+    };
+    let shapes_main = "import shapes\n\ntype Shape {\n    Square(Int)\n    Circle(Int)\n}\n\nmain() {\n    side = 7\n    s = Square(side)\n    case s {\n        Square(n) -> area(n)\n\n        Circle(r) -> r\n    }\n}\n";
+    assert!(change(3, shapes_main)[&main].is_empty());
+    assert_eq!(complete(20, 12, 8), vec!["Circle", "Square"]);
+
+    // what's bound is shown with the type inferred for it
+    let hints = |id: i32| -> Vec<(u32, u32, String)> {
+        let whole = serde_json::json!({ "textDocument": { "uri": main_uri.as_str() }, "range": { "start": { "line": 0, "character": 0 }, "end": { "line": 16, "character": 0 } } });
+        let hints: Vec<lsp_types::InlayHint> = serde_json::from_value(request(id, request::InlayHintRequest::METHOD, whole).result.unwrap()).unwrap();
+        hints.into_iter().map(|hint| match hint.label {
+            lsp_types::InlayHintLabel::String(label) => (hint.position.line, hint.position.character, label),
+            other => panic!("expected a plain label, got {:?}", other)
+        }).collect()
+    };
+    assert_eq!(hints(23), vec![(7, 6, String::from(": () -> Int")), (8, 8, String::from(": Int")), (9, 5, String::from(": Shape"))]);
+
+    // an error in one function leaves the types of the others to be shown
+    let broken = "import shapes\n\nhalf(n) {\n    n / True\n}\n\nmain() {\n    side = 7\n    area(side)\n}\n";
+    assert_eq!(change(4, broken)[&main].len(), 1);
+    assert_eq!(hover(24, 8, 5).map(|(shown, _)| shown), Some(String::from("```spruce\narea : (Int) -> Int\n```\n\nThe area of a square with sides n long")));
+    assert_eq!(hover(25, 8, 10).map(|(shown, _)| shown), Some(String::from("```spruce\nside : Int\n```")));
+    assert_eq!(hover(26, 3, 8), None);
+    assert_eq!(hints(27), vec![(2, 7, String::from(": (Int) -> Int")), (6, 6, String::from(": () -> Int")), (7, 8, String::from(": Int"))]);
+
+    // what's half written doesn't check, so the names come from the last
+    // version that did, and what's in scope from the text
+    assert!(!change(5, "import shapes\n\nmain() {\n    side = 7\n    u = si\n    t = shapes.ar\n}\n")[&main].is_empty());
+    assert_eq!(complete(21, 4, 10)[..2], ["side", "sin"]);
+    assert_eq!(complete(22, 5, 17), vec!["area"]);
+
+    // names lead to where they're declared, and to everywhere they're used
+    assert!(change(6, "import shapes\n\nmain() {\n    area(7) + shapes.area(2)\n}\n")[&main].is_empty());
+    let shapes_uri = lsp::uri_of(&shapes);
+    let at = |line: u32, character: u32| serde_json::json!({ "textDocument": { "uri": main_uri.as_str() }, "position": { "line": line, "character": character } });
+    let location = |uri: &lsp_types::Uri, line: u32, start: u32, end: u32| {
+        lsp_types::Location::new(uri.clone(), lsp_types::Range::new(lsp_types::Position::new(line, start), lsp_types::Position::new(line, end)))
+    };
+    let definition: Option<lsp_types::GotoDefinitionResponse> = serde_json::from_value(request(30, request::GotoDefinition::METHOD, at(3, 21)).result.unwrap()).unwrap();
+    assert_eq!(definition, Some(lsp_types::GotoDefinitionResponse::Scalar(location(&shapes_uri, 3, 0, 4))));
+    let mut params = at(3, 6);
+    params["context"] = serde_json::json!({ "includeDeclaration": false });
+    let uses: Vec<lsp_types::Location> = serde_json::from_value(request(31, request::References::METHOD, params.clone()).result.unwrap()).unwrap();
+    assert_eq!(uses, vec![location(&main_uri, 3, 4, 8), location(&main_uri, 3, 21, 25), location(&shapes_uri, 0, 15, 19)]);
+    params["context"] = serde_json::json!({ "includeDeclaration": true });
+    let all: Vec<lsp_types::Location> = serde_json::from_value(request(32, request::References::METHOD, params).result.unwrap()).unwrap();
+    assert_eq!(all.len(), 4);
+
+    // renaming edits both files, and a rename that would collide is refused
+    let mut params = at(3, 6);
+    params["newName"] = serde_json::json!("square");
+    let edit: lsp_types::WorkspaceEdit = serde_json::from_value(request(33, request::Rename::METHOD, params.clone()).result.unwrap()).unwrap();
+    let changes = match edit.document_changes {
+        Some(lsp_types::DocumentChanges::Edits(changes)) => changes,
+        other => panic!("expected edits to documents, got {:?}", other)
+    };
+    let edited: Vec<(&lsp_types::Uri, usize)> = changes.iter().map(|change| (&change.text_document.uri, change.edits.len())).collect();
+    assert_eq!(edited, vec![(&main_uri, 2), (&shapes_uri, 2)]);
+    assert!(changes.iter().flat_map(|change| change.edits.iter()).all(|edit| match edit {
+        lsp_types::OneOf::Left(edit) => edit.new_text == "square",
+        lsp_types::OneOf::Right(_) => false
+    }));
+    params["newName"] = serde_json::json!("main");
+    let refused = request(34, request::Rename::METHOD, params);
+    assert_eq!(refused.error.map(|err| err.code), Some(lsp_server::ErrorCode::RequestFailed as i32));
+
+    // each file has an outline, and the workspace can be searched for names
+    let outline = serde_json::json!({ "textDocument": { "uri": shapes_uri.as_str() } });
+    let symbols: Option<lsp_types::DocumentSymbolResponse> = serde_json::from_value(request(35, request::DocumentSymbolRequest::METHOD, outline).result.unwrap()).unwrap();
+    let symbols = match symbols {
+        Some(lsp_types::DocumentSymbolResponse::Nested(symbols)) => symbols,
+        other => panic!("expected an outline, got {:?}", other)
+    };
+    assert_eq!(symbols.iter().map(|symbol| (symbol.name.as_str(), symbol.detail.as_deref())).collect::<Vec<(&str, Option<&str>)>>(), vec![("area", Some("(Int) -> Int"))]);
+    assert_eq!(symbols[0].selection_range, lsp_types::Range::new(lsp_types::Position::new(3, 0), lsp_types::Position::new(3, 4)));
+    assert_eq!(symbols[0].range.start, lsp_types::Position::new(2, 0));
+    let search = request(36, request::WorkspaceSymbolRequest::METHOD, serde_json::json!({ "query": "A" })).result.unwrap();
+    let names: Vec<&str> = search.as_array().expect("symbols are a list").iter().map(|symbol| symbol["name"].as_str().unwrap()).collect();
+    assert_eq!(names, vec!["main", "area"]);
+    assert_eq!(search[1]["location"]["uri"], shapes_uri.as_str());
+
+    // names are classified for highlighting, each relative to the last
+    let highlight = serde_json::json!({ "textDocument": { "uri": shapes_uri.as_str() } });
+    let tokens: Option<lsp_types::SemanticTokensResult> = serde_json::from_value(request(37, request::SemanticTokensFullRequest::METHOD, highlight).result.unwrap()).unwrap();
+    let data: Vec<[u32; 5]> = match tokens {
+        Some(lsp_types::SemanticTokensResult::Tokens(tokens)) => tokens.data.iter().map(|token| {
+            [token.delta_line, token.delta_start, token.length, token.token_type, token.token_modifiers_bitset]
+        }).collect(),
+        other => panic!("expected tokens, got {:?}", other)
+    };
+    assert_eq!(data, vec![[0, 15, 4, 0, 0], [3, 0, 4, 0, 1], [0, 5, 1, 4, 1], [1, 4, 1, 4, 0], [0, 4, 1, 4, 0]]);
+
+    // requests the server doesn't know get an error rather than no answer
+    let unknown = request(2, "spruce/unknown", serde_json::Value::Null);
+    assert_eq!(unknown.error.map(|err| err.code), Some(lsp_server::ErrorCode::MethodNotFound as i32));
+
+    assert!(request(3, request::Shutdown::METHOD, serde_json::Value::Null).error.is_none());
+    notify(notification::Exit::METHOD, serde_json::Value::Null);
+    assert_eq!(serving.join().expect("the server panicked"), Ok(()));
+    fs::remove_dir_all(&dir).ok();
+
+    // columns are counted in UTF-16 code units, so the bytes of é and 🌲
+    // aren't columns of their own
+    let mut sources = source::SourceMap::new();
+    let id = sources.add("trees.sp", "a\n\u{e9}\u{1f332}b\n");
+    let file = sources.get(id).unwrap();
+    assert_eq!(lsp::position(file, 8), lsp_types::Position::new(1, 3));
+    assert_eq!(lsp::offset(file, lsp_types::Position::new(1, 3)), 8);
+    assert_eq!(lsp::offset(file, lsp_types::Position::new(1, 40)), 9);
+    assert_eq!(lsp::offset(file, lsp_types::Position::new(9, 0)), 10);
+    assert_eq!(lsp::path_of(&lsp::uri_of(Path::new("/some dir/é.sp"))), Some(PathBuf::from("/some dir/é.sp")));
+}
+
+#[test]
+fn test_references() {
+    use references::{References, Referent};
+
+    let prog = "type Shape {
+    Square(Int)
+    Circle(Int)
+}
+
+/// Makes it bigger
+grow(shape, by) {
+    case shape {
+        Square(side) -> Square(side + by)
+        Circle(r) -> Circle(r * by)
+    }
+}
+
+total(xs) {
+    mut sum = 0
+    sum := sum + 1
+    case xs {
+        h :: t -> h + total(t)
+        _ -> sum
+    }
+}
+
+big = grow(Square(0x1f), 3)
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, _, _) = compile_with_lints(&sources, &mut lint::Lints::new()).ok().expect("the program checks");
+    let index = References::new(&analyzed, &sources);
+    let main = sources.file_id("main").unwrap();
+
+    // where each name is spelled out as a whole word
+    let spelled = |name: &str| -> Vec<usize> {
+        prog.match_indices(name).map(|(i, _)| i).filter(|i| {
+            let before = prog[..*i].chars().last().map_or(false, |c| c.is_ascii_alphanumeric());
+            let after = prog[i + name.len()..].chars().next().map_or(false, |c| c.is_ascii_alphanumeric());
+            !before && !after
+        }).collect()
+    };
+
+    // every place a name is spelled is an occurrence of the same thing, the
+    // first being its declaration
+    for name in ["Square", "Circle", "grow", "shape", "by", "side", "r", "total", "xs", "sum", "h", "t", "big"] {
+        let places = spelled(name);
+        let first = index.at(main, places[0]).unwrap_or_else(|| panic!("'{}' isn't in the index", name));
+        assert!(first.declaration, "'{}' isn't declared first", name);
+        assert_eq!(index.name(first.referent), Some(name));
+        let found: Vec<usize> = index.of(first.referent).iter().map(|occurrence| occurrence.info.span.start).collect();
+        assert_eq!(found, places, "the occurrences of '{}'", name);
+        for place in &places[1..] {
+            assert_eq!(index.at(main, *place).map(|occurrence| occurrence.referent), Some(first.referent));
+            assert_eq!(index.definition(first.referent).map(|occurrence| occurrence.info.span.start), Some(places[0]));
+        }
+    }
+
+    // nothing made up by the compiler is in the index, like the constructor
+    // of `::`, and neither is the rest of a hex literal
+    assert_eq!(index.at(main, prog.find("::").unwrap()), None);
+    assert_eq!(index.at(main, prog.find("0x1f").unwrap() + 2), None);
+    let cons = analyzed.registry.value_id(registry::LangValue::Cons);
+    assert!(index.of(Referent::Constructor(cons)).iter().all(|occurrence| occurrence.info.file != main));
+
+    // renaming edits every occurrence, unless the new name is taken, or
+    // would hide or be hidden by another name
+    let referent = |name: &str| index.at(main, spelled(name)[0]).unwrap().referent;
+    let edits = index.rename(referent("by"), "amount").expect("the rename is allowed");
+    assert_eq!(edits.iter().map(|edit| edit.info.span.start).collect::<Vec<usize>>(), spelled("by"));
+    assert!(edits.iter().all(|edit| edit.replacement == "amount"));
+    assert_eq!(index.rename(referent("Circle"), "Disc").map(|edits| edits.len()), Ok(3));
+    assert!(index.rename(referent("grow"), "total").is_err());
+    assert!(index.rename(referent("Square"), "Circle").is_err());
+    assert!(index.rename(referent("side"), "by").is_err());
+    assert!(index.rename(referent("r"), "big").is_err());
+    assert!(index.rename(referent("big"), "sum").is_err());
+    assert!(index.rename(referent("by"), "case").is_err());
+    assert!(index.rename(referent("by"), "2by").is_err());
+    let prelude = sources.file_id("prelude");
+    assert!(index.rename(Referent::Constructor(cons), "Pair").expect("the prelude's names can be renamed").iter().all(|edit| Some(edit.info.file) == prelude));
+}
+
+#[test]
+fn test_outline() {
+    use outline::Kind;
+
+    let prog = "type Tree(a) {
+    Leaf
+    Node(Tree(a), a, Tree(a))
+}
+
+/// How many values are in the tree
+size(tree) {
+    case tree {
+        Leaf -> 0
+        Node(left, value, right) -> size(left) + 1 + size(right)
+    }
+}
+
+mut seen = 0
+empty = Leaf
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let main = sources.file_id("main").unwrap();
+    let parsed = parser::parse(&sources).ok().expect("the program parses");
+    let (analyzed, _, environment) = compile_with_lints(&sources, &mut lint::Lints::new()).ok().expect("the program checks");
+
+    let summary = |symbols: &[outline::Symbol]| -> Vec<(String, Kind, String, usize)> {
+        symbols.iter().filter(|symbol| symbol.info.file == main).flat_map(|symbol| std::iter::once(symbol).chain(symbol.children.iter()))
+            .map(|symbol| (symbol.name.clone(), symbol.kind, symbol.detail.clone(), symbol.name_info.span.start))
+            .collect()
+    };
+    let at = |text: &str| prog.find(text).unwrap();
+    let expected = |size: &str, seen: &str, empty: &str| vec![
+        (String::from("Tree"), Kind::Type, String::from("type Tree(a)"), at("Tree")),
+        (String::from("Leaf"), Kind::Constructor, String::from("Tree(a)"), at("Leaf")),
+        (String::from("Node"), Kind::Constructor, String::from("(Tree(a), a, Tree(a)) -> Tree(a)"), at("Node")),
+        (String::from("size"), Kind::Function, String::from(size), at("size(tree)")),
+        (String::from("seen"), Kind::Constant, String::from(seen), at("seen")),
+        (String::from("empty"), Kind::Constant, String::from(empty), at("empty"))
+    ];
+
+    // with the types the checker inferred, and without them, what was written
+    let checked = outline::outline(&parsed, &sources, Some((&analyzed, &environment)));
+    assert_eq!(summary(&checked), expected("(Tree(a)) -> Int", "Int", "Tree(a)"));
+    assert_eq!(summary(&outline::outline(&parsed, &sources, None)), expected("(tree)", "", ""));
+
+    let json = outline::to_json(&checked.into_iter().filter(|symbol| symbol.info.file == main).collect::<Vec<outline::Symbol>>(), &sources);
+    assert_eq!(json[0]["file"], "main");
+    assert_eq!(json[0]["symbols"][0]["children"][1]["name"], "Node");
+    assert_eq!(json[0]["symbols"][1]["name"], "size");
+    assert_eq!(json[0]["symbols"][1]["range"]["start"], serde_json::json!({ "line": 6, "column": 1 }));
+    assert_eq!(json[0]["symbols"][1]["name_range"]["start"], serde_json::json!({ "line": 7, "column": 1 }));
+}
+
+#[test]
+fn test_semantic_tokens() {
+    use semantic::TokenKind;
+
+    let prog = "type Duo(a, b) {
+    Duo(a, b)
+}
+
+limit = 10
+
+clamp(x) {
+    mut y = min(x, limit)
+    y := y + 1
+    y
+}
+
+pair = Duo(clamp(1), 0xff)
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, _, _) = compile_with_lints(&sources, &mut lint::Lints::new()).ok().expect("the program checks");
+    let index = references::References::new(&analyzed, &sources);
+    let main = sources.file_id("main").unwrap();
+    let tokens: Vec<(&str, TokenKind, bool)> = semantic::tokens(&analyzed, &sources, &index).into_iter()
+        .filter(|token| token.info.file == main)
+        .map(|token| (&prog[token.info.span.start..token.info.span.end], token.kind, token.declaration))
+        .collect();
+    assert_eq!(tokens, vec![
+        ("Duo", TokenKind::Type, true),
+        ("a", TokenKind::TypeVariable, true),
+        ("b", TokenKind::TypeVariable, true),
+        ("Duo", TokenKind::Constructor, true),
+        ("a", TokenKind::TypeVariable, false),
+        ("b", TokenKind::TypeVariable, false),
+        ("limit", TokenKind::Constant, true),
+        ("clamp", TokenKind::Function, true),
+        ("x", TokenKind::Parameter, true),
+        ("y", TokenKind::Variable, true),
+        ("min", TokenKind::Function, false),
+        ("x", TokenKind::Parameter, false),
+        ("limit", TokenKind::Constant, false),
+        ("y", TokenKind::Variable, false),
+        ("y", TokenKind::Variable, false),
+        ("y", TokenKind::Variable, false),
+        ("pair", TokenKind::Constant, true),
+        ("Duo", TokenKind::Constructor, false),
+        ("clamp", TokenKind::Function, false)
+    ]);
+
+    // the prelude's signatures have types and type variables in them
+    let prelude = sources.file_id("prelude").unwrap();
+    let show = name_analysis::PRELUDE.find("builtin show : (a) -> String").unwrap();
+    let kinds: Vec<TokenKind> = semantic::tokens(&analyzed, &sources, &index).into_iter()
+        .filter(|token| token.info.file == prelude && token.info.span.start >= show && token.info.span.start < show + 29)
+        .map(|token| token.kind)
+        .collect();
+    assert_eq!(kinds, vec![TokenKind::Function, TokenKind::TypeVariable, TokenKind::Type]);
+}
+
+#[test]
+fn test_doc() {
+    let shapes = "module shapes (Shape(..), Box, area, unit)
+
+/// A shape on the plane
+///
+/// Sides are whole numbers
+type Shape {
+    Square(Int)
+    Circle(Int)
+}
+
+type Box(a) {
+    Box(a)
+}
+
+/// The area of a shape
+area(s) {
+    case s {
+        Square(n) -> n * n
+        Circle(r) -> 3 * r * r
+    }
+}
+
+unit = Square(1)
+
+hidden(x) {
+    x
+}
+";
+    let main = "import shapes
+
+/// Doubles a <shape>
+grow(s) {
+    area(s) * 2
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (shapes, String::from("shapes")), (main, String::from("main"))]);
+    let (analyzed, _, environment) = compile_with_lints(&sources, &mut lint::Lints::new()).ok().expect("the program checks");
+
+    // a page for each module and one for the index, with types linked to
+    // where they're documented, in declaration order, and nothing that isn't
+    // exported
+    let pages = doc::document(&analyzed, &environment, &sources, doc::Format::Markdown);
+    assert_eq!(pages.iter().map(|page| page.file.as_str()).collect::<Vec<&str>>(), vec!["shapes.md", "main.md", "index.md"]);
+    assert_eq!(pages[0].text, "[index](index.md)
+
+# module shapes
+
+<a id=\"Shape\"></a>
+
+## Shape
+
+type Shape
+
+A shape on the plane
+
+Sides are whole numbers
+
+- Square : (Int) -> [Shape](shapes.md#Shape)
+- Circle : (Int) -> [Shape](shapes.md#Shape)
+
+<a id=\"Box\"></a>
+
+## Box
+
+type Box(a)
+
+<a id=\"area\"></a>
+
+## area
+
+area : ([Shape](shapes.md#Shape)) -> Int
+
+The area of a shape
+
+<a id=\"unit\"></a>
+
+## unit
+
+unit : [Shape](shapes.md#Shape)
+");
+    assert!(pages[1].text.contains("grow : ([Shape](shapes.md#Shape)) -> Int\n\nDoubles a <shape>\n"));
+    assert_eq!(pages[2].text, "# Modules\n\n- [shapes](shapes.md)\n- [main](main.md)\n");
+
+    // HTML is escaped, and links the same way
+    let pages = doc::document(&analyzed, &environment, &sources, doc::Format::Html);
+    assert_eq!(pages[0].file, "shapes.html");
+    assert!(pages[0].text.contains("<h2><code>area : (<a href=\"shapes.html#Shape\">Shape</a>) -&gt; Int</code></h2>"));
+    assert!(pages[0].text.contains("<li><code>Circle : (Int) -&gt; <a href=\"shapes.html#Shape\">Shape</a></code></li>"));
+    assert!(!pages[0].text.contains("hidden"));
+    assert!(pages[1].text.contains("<p>Doubles a &lt;shape&gt;</p>"));
+}
+
+#[test]
+fn test_tests() {
+    let prog = "
+double(n) {
+    n * 2
+}
+
+/// Doubling twice is the same as times four
+test \"doubling twice\" {
+    double(double(3)) == 12
+}
+
+test \"doubling wrongly\" {
+    double(2) == 5
+}
+
+test \"dividing by zero\" {
+    zero = double(0)
+    double(1) / zero == 1
+}
+
+main() {
+    double(1)
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, typed, environment) = compile_with_lints(&sources, &mut lint::Lints::new()).ok().expect("tests check like functions");
+    assert_eq!(testing::tests(&analyzed).iter().map(|(name, _)| *name).collect::<Vec<&str>>(), vec!["doubling twice", "doubling wrongly", "dividing by zero"]);
+
+    // each test passes or fails with its header pointed at, and errors say
+    // which test they were in
+    let runs = testing::run_tests(&analyzed, &typed, &sources, &[], || eval::Runtime::new(Vec::new(), None), None);
+    assert!(runs[0].failure.is_none());
+    let failed = runs[1].failure.as_ref().expect("a test giving False fails");
+    assert_eq!(failed.message, "test \"doubling wrongly\" gave False");
+    assert_eq!(&prog[failed.info.span.start..failed.info.span.end], "test \"doubling wrongly\"");
+    let errored = runs[2].failure.as_ref().expect("a test that errors fails");
+    assert!(errored.labels.iter().any(|label| label.message == "while running test \"dividing by zero\""));
+
+    // filters pick tests by part of their name
+    let runs = testing::run_tests(&analyzed, &typed, &sources, &[String::from("doubling")], || eval::Runtime::new(Vec::new(), None), None);
+    assert_eq!(runs.iter().map(|run| run.name.as_str()).collect::<Vec<&str>>(), vec!["doubling twice", "doubling wrongly"]);
+
+    // tests are left out of interfaces, and formatted as they're written
+    let interface = interface::emit(&analyzed, &environment, &analyzed.modules[1]);
+    assert!(!interface.contains("test"));
+    let formatted = fmt::format_file(&source::SourceMap::from_files(&vec![(prog, String::from("main"))])).ok().expect("the program formats");
+    assert!(formatted.contains("/// Doubling twice is the same as times four\ntest \"doubling twice\" {\n    double(double(3)) == 12\n}\n"));
+
+    // what looks like a comment in a test's name is part of it
+    let quoted = "test \"a // b, # c\" {\n    True\n}\n";
+    assert_eq!(fmt::format_source(quoted), quoted);
+    let sources = source::SourceMap::from_files(&vec![(quoted, String::from("main"))]);
+    assert!(parser::parse(&sources).expect("the test parses").comments.is_empty());
+
+    // a test must give a Bool
+    let not_bool = "
+test \"a number\" {
+    1
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (not_bool, String::from("main"))]);
+    assert!(compile_with_lints(&sources, &mut lint::Lints::new()).is_err());
+}
+
+/// Compares what the compiler makes of each program under tests/golden with
+/// its golden file, or rewrites the golden files if SPRUCE_UPDATE_GOLDEN is set
+#[test]
+fn test_golden() {
+    let mode = if std::env::var_os("SPRUCE_UPDATE_GOLDEN").is_some() { golden::Mode::Update } else { golden::Mode::Check };
+    let mismatches = golden::run_dir(Path::new("tests/golden"), mode).expect("cannot read the golden tests");
+    let described: Vec<String> = mismatches.iter().map(|mismatch| mismatch.describe()).collect();
+    assert!(mismatches.is_empty(), "{}", described.join("\n"));
+}
+
+#[test]
+fn test_watch() {
+    let dir = std::env::temp_dir().join(format!("spruce-watch-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("cannot make a directory to watch");
+    let file = dir.join("main.sp");
+    fs::write(&file, "main() {\n    1\n}\n").expect("cannot write the file to watch");
+    let manifest = dir.join(manifest::FILE_NAME);
+
+    // nothing has changed until a file is modified, or something is made
+    // that wasn't there
+    let mut watched = watch::Watched::new(std::slice::from_ref(&file), std::slice::from_ref(&manifest));
+    assert!(!watched.changed());
+    let later = std::time::SystemTime::now() + std::time::Duration::from_secs(10);
+    fs::File::options().write(true).open(&file).and_then(|opened| opened.set_modified(later)).expect("cannot touch the watched file");
+    assert!(watched.changed());
+    assert!(!watched.changed());
+    fs::write(&manifest, "").expect("cannot write the manifest");
+    assert!(watched.changed());
+
+    fs::remove_dir_all(&dir).expect("cannot clean up");
+}
+
+#[test]
+fn test_emit() {
+    let prog = "double(n) {
+    n * 2
+}
+
+main() {
+    x = double(3)
+    case x > 4 {
+        True -> x
+        False -> 0
+    }
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main.sp"))]);
+    let files = vec![sources.file_id("main.sp").unwrap()];
+    for artifact in &emit::ALL_ARTIFACTS {
+        assert_eq!(artifact.as_str().parse::<emit::Artifact>(), Ok(*artifact));
+    }
+
+    let tokens = emit::tokens(&sources, &files).ok().expect("the program parses");
+    assert!(tokens.starts_with("main.sp:1:1 id \"double\"\nmain.sp:1:8 id \"n\"\nmain.sp:2:5 name \"n\"\nmain.sp:2:7 multiply \"*\"\n"));
+
+    let parsed = parser::parse(&sources).ok().expect("the program parses");
+    assert!(emit::ast(&parsed, &files).contains("name: \"double\""));
+
+    // every part of a function is shown with its type, and none of the
+    // prelude is
+    let (analyzed, typed, environment) = compile_with_lints(&sources, &mut lint::Lints::new()).ok().expect("the program checks");
+    assert_eq!(emit::typed_ast(&analyzed, &typed, &environment, &sources, &files), "double(n) : (Int) -> Int @ main.sp:1:1
+    * : Int @ main.sp:2:5
+        n : Int @ main.sp:2:5
+        2 : Int @ main.sp:2:9
+main() : () -> Int @ main.sp:5:1
+    let x : Int @ main.sp:6:5
+        call double : Int @ main.sp:6:9
+            3 : Int @ main.sp:6:16
+    case : Int @ main.sp:7:5
+        > : Bool @ main.sp:7:10
+            x : Int @ main.sp:7:10
+            4 : Int @ main.sp:7:14
+        True -> @ main.sp:8:9
+            x : Int @ main.sp:8:17
+        False -> @ main.sp:9:9
+            0 : Int @ main.sp:9:18
+");
+    assert_eq!(emit::named_ast(&analyzed, &files).lines().filter(|line| line.starts_with("FuncNode")).count(), 2);
+    assert!(emit::env(&analyzed, &environment).contains("double : (Int) -> Int\n"));
+    let ir = emit::ir(analyzed, typed, &environment).ok().expect("the program lowers");
+    assert!(ir.starts_with("double(v0) {\n    Mul(v0, 2)\n}\n"));
+}
+
+#[test]
+fn test_vm() {
+    let prog = "
+mut calls = 0
+count(n, acc) {
+    calls := calls + 1
+    case n {
+        0 -> acc
+        _ -> count(n - 1, acc + 1)
+    }
+}
+classify(n) {
+    case n % 3 {
+        0 -> Just(n)
+        1 -> {
+            case n > 5 {
+                True -> Just(0 - n)
+                False -> Nothing
+            }
+        }
+        _ -> Nothing
+    }
+}
+main() {
+    total = count(200000, 0)
+    mapped = map(filter([1, 2, 3, 7, 9], isEven >> not), classify)
+    fold(mapped, [Just(total), Just(calls)], prepend)
+}
+isEven(n) {
+    n % 2 == 0
+}
+prepend(ls, x) {
+    x :: ls
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (prog, typed, _) = compile(files).expect("program should typecheck");
+
+    // recursion doesn't use up the stack of the thread running the VM, which
+    // the tree-walker would need to be given a bigger one to run this
+    let value = vm::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(eval::show(&prog, &value), "Cons(Just(9), Cons(Just(-7), Cons(Just(3), Cons(Nothing, Cons(Just(200000), Cons(Just(200001), Nil))))))");
+
+    // instructions stay small enough to keep chunks compact
+    assert_eq!(std::mem::size_of::<vm::Op>(), 8);
+
+    // both engines fail the same way
+    let fail_prog = "
+main() {
+    n = 4
+    case n {
+        1 -> 1
+        2 -> 2
+    }
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let (prog, typed, _) = compile(files).expect("program should typecheck");
+    let vm_error = vm::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("no option matches");
+    let tree_error = eval::run_prog(&prog, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("no option matches");
+    assert_eq!(vm_error.message, "no option matches 4");
+    assert_eq!(vm_error.message, tree_error.message);
+    assert_eq!(vm_error.info, tree_error.info);
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn test_jit() {
+    let prog = "
+fib(n) {
+    case n < 2 {
+        True -> n
+        False -> fib(n - 1) + fib(n - 2)
+    }
+}
+isSmall(n) {
+    n <= 10
+}
+half(n) {
+    case n % 2 {
+        0 -> n / 2
+    }
+}
+divide(a, b) {
+    a / b
+}
+sum(ls) {
+    fold(ls, 0, add)
+}
+add(a, b) {
+    a + b
+}
+main() {
+    case isSmall(fib(3)) {
+        True -> [fib(20), sum([1, 2, 3]), divide(0 - 9223372036854775807 - 1, 0 - 1)]
+        False -> []
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (prog, typed, env) = compile(files).expect("program should typecheck");
+
+    // only what deals in Ints and Bools alone is compiled
+    let compiled = jit::compile(&prog, &typed, &env).expect("program should compile");
+    let mut names: Vec<&str> = compiled.compiled().into_iter().map(|id| eval::symbol_name(&prog, id)).collect();
+    names.sort();
+    assert_eq!(names, vec!["add", "divide", "fib", "half", "isSmall", "not"]);
+
+    let value = jit::run_prog(&prog, &typed, &env, "main", eval::Runtime::new(Vec::new(), None)).expect("program should run");
+    assert_eq!(eval::show(&prog, &value), "Cons(6765, Cons(6, Cons(-9223372036854775808, Nil)))");
+
+    // failures in native code are errors like the interpreter's
+    use eval::Natives;
+    let jit = compiled;
+    let id = |name: &str| typed.functions.iter().find(|func| eval::symbol_name(&prog, func.val.name) == name).expect("function exists").val.name;
+    let error = jit.call(&prog, id("divide"), &[eval::Value::Int(1), eval::Value::Int(0)]).expect("divide is compiled").err().expect("dividing by zero should fail");
+    assert_eq!(error.message, "division by zero");
+    let error = jit.call(&prog, id("half"), &[eval::Value::Int(3)]).expect("half is compiled").err().expect("3 is odd");
+    assert_eq!(error.message, "no option matches 1");
+    assert_eq!(jit.call(&prog, id("half"), &[eval::Value::Int(8)]).expect("half is compiled").ok(), Some(eval::Value::Int(4)));
+}
+
+#[test]
+fn test_anf() {
+    let prog = "
+describe(n) {
+    case n {
+        0 -> 1
+        0 -> 2
+        _ -> n * 2
+    }
+}
+size(m) {
+    case m {
+        Just(x) -> 1
+        Nothing -> 0
+    }
+}
+main() {
+    x = [1, describe(2 + 3)]
+    size(Just(x))
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files).expect("program should typecheck");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let dump = program.dump(&analyzed);
+
+    // operands are named before they're used, and lists are built from Nil up
+    assert!(dump.contains("let t0 = Add(2, 3)\n    let t1 = describe(t0)\n    let t2 = Nil()\n    let t3 = Cons(t1, t2)\n"));
+    // an option that can never be reached isn't kept
+    assert!(dump.contains("0 ->\n            1\n        _ ->"));
+    // the last constructor of a type is found by elimination, and fields
+    // that aren't used aren't loaded
+    assert!(dump.contains("case v0 {\n        Just ->\n            1\n        _ ->\n            0\n"));
+    assert!(!dump.contains("v0.0"));
+    assert_eq!(program.functions.len(), 3);
+}
+
+#[test]
+fn test_fold() {
+    let prog = "
+pick(n) {
+    width = 6 * 7
+    case width > 40 {
+        True -> n + width - 2
+        False -> n / 0
+    }
+}
+main() {
+    mut total = 1
+    total := total + 1
+    big = 1 <<< 62
+    pick(total / 0) + big
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files).expect("program should typecheck");
+    let mut program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    fold::compile(&analyzed, &mut program);
+    let dump = program.dump(&analyzed);
+
+    // known operands are worked out, and a case on a known value becomes the
+    // arm it takes
+    assert!(dump.contains("let v1 = 42
+    let t2 = True()
+    let t3 = Add(v0, 42)
+    Sub(t3, 2)
+"));
+    assert!(!dump.contains("case"));
+    // a `mut` isn't known, division by zero is left to fail when the program
+    // runs, and results the compiled targets can't hold aren't worked out
+    assert!(dump.contains("Add(v0, 1)"));
+    assert!(dump.contains("Div(v0, 0)"));
+    assert!(dump.contains("Shl(1, 62)"));
+}
+
+#[test]
+fn test_dce() {
+    let prog = "
+unused = 5
+helper(n) {
+    n * 3
+}
+other(n) {
+    n + 1
+}
+main() {
+    case 3 > 2 {
+        True -> printLine(show(other(1)))
+        False -> printLine(show(helper(unused)))
+    }
+    y = 7 * 6
+    z = y / 0
+    0
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files).expect("program should typecheck");
+    let mut program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    fold::compile(&analyzed, &mut program);
+    dce::compile(&analyzed, &mut program);
+    let dump = program.dump(&analyzed);
+
+    // a function only a folded case called, and a definition only it read,
+    // are gone
+    assert_eq!(program.functions.len(), 2);
+    assert!(!dump.contains("helper"));
+    assert!(program.globals.is_empty());
+    // bindings nothing uses are gone, unless working them out can fail
+    assert!(!dump.contains("= 42\n"));
+    assert!(dump.contains("Div(42, 0)"));
+}
+
+#[test]
+fn test_inline() {
+    let prog = "
+double(n) {
+    n * 2
+}
+isBig(n) {
+    case n > 10 {
+        True -> 1
+        False -> 0
+    }
+}
+sumTo(n, acc) {
+    case n {
+        0 -> acc
+        _ -> sumTo(n - 1, acc + isBig(double(n)))
+    }
+}
+main() {
+    printLine(show(not(True)))
+    sumTo(100, 0)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files).expect("program should typecheck");
+    let mut program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    inline::compile(&mut program, inline::DEFAULT_THRESHOLD);
+    fold::compile(&analyzed, &mut program);
+    dce::compile(&analyzed, &mut program);
+    let dump = program.dump(&analyzed);
+
+    // small functions are inlined into their callers, inside out, and then
+    // folded, while a function calling itself never is
+    assert!(dump.contains("let t3 = Mul(v0, 2)\n            let t8 = Gt(t3, 10)\n"));
+    assert!(dump.contains("let t1 = False()\n"));
+    assert!(dump.contains("sumTo(100, 0)"));
+    assert_eq!(program.functions.len(), 2);
+
+    // nothing is too small to call with a threshold of 0
+    let mut program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    inline::compile(&mut program, 0);
+    assert!(program.dump(&analyzed).contains("double(v0)"));
+}
+
+#[test]
+fn test_mono() {
+    let prog = "
+pair(a, b) {
+    [a, b]
+}
+main() {
+    printLine(show(length(pair(True, False)) + length(pair(1, 2))))
+    total(pair(3, 4))
+}
+total(xs) {
+    fold(xs, 0, add)
+}
+add(acc, x) {
+    acc + x
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (mut analyzed, mut typed, env) = compile(files).expect("program should typecheck");
+    mono::compile(&mut analyzed, &mut typed, &env, "main").expect("program should be monomorphized");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let named = |name: &str| program.functions.iter().filter(|func| eval::symbol_name(&analyzed, func.name.unwrap()) == name).count();
+
+    // a function gets a copy for each type it's used at, and only one for a
+    // type it's used at twice
+    assert_eq!(named("pair"), 2);
+    assert_eq!(named("length"), 2);
+    // one used at a single type isn't copied
+    assert_eq!(named("fold"), 1);
+    assert_eq!(named("total"), 1);
+
+    // each copy calls itself rather than the function it was copied from
+    for func in program.functions.iter().filter(|func| eval::symbol_name(&analyzed, func.name.unwrap()) == "length") {
+        let arm = match &func.body.result {
+            anf::Value::Case(case) => &case.arms[0].body,
+            _ => panic!("length should start with a case")
+        };
+        assert!(arm.stmts.iter().any(|stmt| matches!(stmt, anf::Stmt::Let(_, anf::Value::Call(id, _)) if Some(*id) == func.name)));
+    }
+}
+
+#[test]
+fn test_native() {
+    let prog = "
+type Tree(a) {
+    Leaf
+    Node(Tree(a), a, Tree(a))
+}
+insert(t, x) {
+    case t {
+        Leaf -> Node(Leaf, x, Leaf)
+        Node(l, v, r) -> {
+            case x < v {
+                True -> Node(insert(l, x), v, r)
+                False -> Node(l, v, insert(r, x))
+            }
+        }
+    }
+}
+scale = 3
+triple(n) {
+    n * scale
+}
+main() {
+    printLine(show(fold([5, 3, 8], Leaf, insert)))
+    printLine(show(map([1, 2], triple >> negate)))
+    printLine(show([sqrt(toFloat(2)), sin(toFloat(355)), cos(toFloat(0))]))
+    printLine(show(compare(floor(sqrt(toFloat(10))), ceil(tan(toFloat(1))))))
+    total = fold([7, 1 <<< 4, 2 ^ 10], 0, add)
+    printLine(show(total / 2 == 523))
+    total % 100
+}
+negate(n) {
+    0 - n
+}
+add(a, b) {
+    a + b
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files.clone()).expect("program should typecheck");
+    let sources = source::SourceMap::from_files(&files);
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let module = native::lower(&analyzed, &program, &sources).expect("program should lower");
+
+    // only what main reaches is compiled
+    let llvm = native::emit_llvm(&module);
+    assert!(llvm.contains("@sp_insert_"));
+    assert!(!llvm.contains("@sp_filter_"));
+    assert!(llvm.contains("define internal i64 @spruce_apply1(i64 %f, i64 %a0)"));
+
+    // builtins without a native version are errors at their use
+    let unsupported = "
+main() {
+    readLine()
+}
+";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (unsupported, String::from("Main"))];
+    let (analyzed_unsupported, typed_unsupported, _) = compile(files.clone()).expect("program should typecheck");
+    let program_unsupported = anf::lower(&analyzed_unsupported, &typed_unsupported, "main").expect("program should lower");
+    let error = native::lower(&analyzed_unsupported, &program_unsupported, &source::SourceMap::from_files(&files)).err().expect("readLine has no native version");
+    assert_eq!(error.message, "the native target has no builtin 'readLine'");
+    assert_eq!(error.info.file, 1);
+
+    // as are literals too wide for a tagged Int, in expressions or patterns
+    let wide_literals = [
+        ("main() {\n    show(4611686018427387904)\n}\n", "4611686018427387904"),
+        ("main() {\n    case 1 {\n        -4611686018427387905 -> 0\n        _ -> 1\n    }\n}\n", "-4611686018427387905")
+    ];
+    for (wide, literal) in wide_literals {
+        let files = vec![(prelude.as_str(), String::from("prelude")), (wide, String::from("Main"))];
+        let (analyzed_wide, typed_wide, _) = compile(files.clone()).expect("program should typecheck");
+        let program_wide = anf::lower(&analyzed_wide, &typed_wide, "main").expect("program should lower");
+        let error = native::lower(&analyzed_wide, &program_wide, &source::SourceMap::from_files(&files)).err().expect("the literal needs 64 bits");
+        assert_eq!(error.code, Some(error_codes::ErrorCode::LiteralOutOfRange));
+        assert_eq!(&wide[error.info.span.start..error.info.span.end], literal);
+    }
+    let fits = "main() {\n    show(4611686018427387903)\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fits, String::from("Main"))];
+    let (analyzed_fits, typed_fits, _) = compile(files.clone()).expect("program should typecheck");
+    let program_fits = anf::lower(&analyzed_fits, &typed_fits, "main").expect("program should lower");
+    assert!(native::lower(&analyzed_fits, &program_fits, &source::SourceMap::from_files(&files)).is_ok());
+
+    // the executable is only built where LLVM and a C compiler are installed
+    let has_tools = ["opt", "llc", "cc"].iter().all(|tool| std::process::Command::new(tool).arg("--version").output().is_ok());
+    if !has_tools {
+        return;
+    }
+    let output = std::env::temp_dir().join(format!("spruce-test-native-{}", std::process::id()));
+    native::build(&module, &output).expect("program should build");
+    let run = std::process::Command::new(&output).output().expect("executable should run");
+    fs::remove_file(&output).ok();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "Node(Node(Leaf, 3, Leaf), 5, Node(Leaf, 8, Leaf))\nCons(-3, Cons(-6, Nil))\nCons(1.4142135623730951, Cons(-0.00003014435335948845, Cons(1, Nil)))\nGT\nTrue\n47\n");
+}
+
+#[test]
+fn test_c() {
+    let prog = "
+mut calls = 0
+count(n, acc) {
+    calls := calls + 1
+    case n {
+        0 -> acc
+        _ -> count(n - 1, acc + 1)
+    }
+}
+classify(n) {
+    case n % 3 {
+        0 -> Just(n)
+        _ -> Nothing
+    }
+}
+start(n) {
+    count(n, 0)
+}
+main() {
+    printLine(show(map([3, 4, 6], classify)))
+    printLine(show((0 - 7) / 2 == 0 - 3))
+    printLine(show([sqrt(toFloat(2)), sin(toFloat(355)), cos(toFloat(0))]))
+    printLine(show(compare(floor(sqrt(toFloat(10))), ceil(tan(toFloat(1))))))
+    total = start(3000000)
+    total + calls
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files.clone()).expect("program should typecheck");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let source = cgen::generate(&analyzed, &program, &source::SourceMap::from_files(&files)).expect("program should generate");
+
+    // a function calling itself in tail position loops, and other calls in
+    // tail position go through the trampoline
+    assert!(source.contains("goto top;"));
+    assert!(source.contains("tail->code = sp_count_"));
+    assert!(!source.contains("sp_filter_"));
+
+    if std::process::Command::new("cc").arg("--version").output().is_err() {
+        return;
+    }
+    let dir = std::env::temp_dir();
+    let (c_file, output) = (dir.join(format!("spruce-test-c-{}.c", std::process::id())), dir.join(format!("spruce-test-c-{}", std::process::id())));
+    fs::write(&c_file, source).expect("cannot write the C file");
+    let built = std::process::Command::new("cc").args(["-std=c99", "-pedantic-errors", "-O1"]).arg(&c_file).arg("-o").arg(&output).arg("-lm").status().expect("cc should run");
+    assert!(built.success());
+    let run = std::process::Command::new(&output).output().expect("executable should run");
+    fs::remove_file(&c_file).ok();
+    fs::remove_file(&output).ok();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "Cons(Just(3), Cons(Nothing, Cons(Just(6), Nil)))\nTrue\nCons(1.4142135623730951, Cons(-0.00003014435335948845, Cons(1, Nil)))\nGT\n6000001\n");
+}
+
+#[test]
+fn test_js() {
+    let prog = "
+mut calls = 0
+classify(n) {
+    calls := calls + 1
+    case n % 3 {
+        0 -> Just(n)
+        _ -> Nothing
+    }
+}
+add(a, b) {
+    a + b
+}
+double(n) {
+    n * 2
+}
+main() {
+    printLine(show(map([3, 4, 6], classify)))
+    printLine(show(fold(map([1, 2], double >> double), 0, add)))
+    calls
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files.clone()).expect("program should typecheck");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let module = jsgen::generate(&analyzed, &program, &source::SourceMap::from_files(&files)).expect("program should generate");
+
+    // functions are curried, and values of types are tagged objects
+    assert!(module.contains("export function add(a) {\n    return (b) => {"));
+    assert!(module.contains("export const Just = (a0) => _rt.value('Just', [a0])"));
+    assert!(module.contains("switch (ls.tag) {"));
+    assert!(module.contains("_rt.compose(double, double)"));
+
+    if std::process::Command::new("node").arg("--version").output().is_err() {
+        return;
+    }
+    // from a directory whose path has to be escaped in a url, which the
+    // module has to see through to know it's the program
+    let dir = std::env::temp_dir().join(format!("spruce test js #{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("cannot create the module's directory");
+    fs::write(dir.join("main.mjs"), module).expect("cannot write the module");
+    fs::write(dir.join(jsgen::RUNTIME_FILE), jsgen::RUNTIME).expect("cannot write the runtime");
+    let run = std::process::Command::new("node").arg(dir.join("main.mjs")).output().expect("node should run");
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "Cons(Just(3), Cons(Nothing, Cons(Just(6), Nil)))\n12\n3\n");
+}
+
+#[test]
+fn test_js_builtins() {
+    let prog = "
+addEntry(acc, key, val) {
+    acc + key * val
+}
+main() {
+    d = dictInsert(dictInsert(emptyDict(), 2, 20), 1, 10)
+    s = setUnion(setFromList([3, 1, 3]), setInsert(emptySet(), 2))
+    printLine(show(d))
+    printLine(show(s))
+    printLine(show(dictGet(dictRemove(d, 1), 2)))
+    printLine(show(dictFold(d, 0, addEntry)))
+    printLine(show(setMember(setIntersection(s, setFromList([2])), 2)))
+    printLine(show(compare(Just(2), Just(1))))
+    printLine(show(parseFloat(show(7))))
+    printLine(show(floor(sqrt(toFloat(17))) + ceil(sin(toFloat(0)))))
+    case args() {
+        Cons(path, rest) -> {
+            written = writeFile(path, show(s))
+            printLine(show(readFile(path)))
+        }
+        Nil -> {
+            printLine(show(readLine()))
+        }
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files.clone()).expect("program should typecheck");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let module = jsgen::generate(&analyzed, &program, &source::SourceMap::from_files(&files)).expect("every builtin has a JS version");
+
+    if std::process::Command::new("node").arg("--version").output().is_err() {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("spruce-test-js-builtins-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("cannot create the module's directory");
+    fs::write(dir.join("main.mjs"), module).expect("cannot write the module");
+    fs::write(dir.join(jsgen::RUNTIME_FILE), jsgen::RUNTIME).expect("cannot write the runtime");
+    let run = std::process::Command::new("node").arg(dir.join("main.mjs")).arg(dir.join("out.txt")).output().expect("node should run");
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(
+        String::from_utf8_lossy(&run.stdout),
+        "Dict(1: 10, 2: 20)\nSet(1, 2, 3)\nJust(20)\n50\nTrue\nGT\nJust(7)\n4\nOk(Set(1, 2, 3))\n"
+    );
+}
+
+#[test]
+fn test_js_ints() {
+    let prog = "
+main() {
+    printLine(show(1 <<< 62))
+    printLine(show((1 <<< 62) | 1))
+    printLine(show(3000000000 * 3000000000))
+    printLine(show(9223372036854775807 + 1))
+    printLine(show(-9223372036854775808 / -1))
+    printLine(show(-(-9223372036854775808)))
+    printLine(show(3 ^ 50))
+    printLine(show(-5 >>> 65))
+    printLine(show(parseInt(show(9223372036854775807))))
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files.clone()).expect("program should typecheck");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let module = jsgen::generate(&analyzed, &program, &source::SourceMap::from_files(&files)).expect("program should generate");
+
+    if std::process::Command::new("node").arg("--version").output().is_err() {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("spruce-test-js-ints-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("cannot create the module's directory");
+    fs::write(dir.join("main.mjs"), module).expect("cannot write the module");
+    fs::write(dir.join(jsgen::RUNTIME_FILE), jsgen::RUNTIME).expect("cannot write the runtime");
+    let run = std::process::Command::new("node").arg(dir.join("main.mjs")).output().expect("node should run");
+    fs::remove_dir_all(&dir).ok();
+
+    // Ints are 64 bits and wrap, as they do in the interpreter
+    let expected = [
+        1i64 << 62,
+        (1i64 << 62) | 1,
+        3000000000i64.wrapping_mul(3000000000),
+        i64::MAX.wrapping_add(1),
+        i64::MIN.wrapping_div(-1),
+        i64::MIN.wrapping_neg(),
+        3i64.wrapping_pow(50),
+        (-5i64).wrapping_shr(65)
+    ];
+    let expected: String = expected.iter().map(|n| format!("{}\n", n)).collect();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), expected + "Just(9223372036854775807)\n");
+}
+
+#[test]
+fn test_js_tail_calls() {
+    let prog = "
+sumTo(n, acc) {
+    case n {
+        0 -> acc
+        _ -> sumTo(n - 1, acc + n)
+    }
+}
+swapDown(a, b) {
+    case a {
+        0 -> b
+        _ -> swapDown(b - 1, a)
+    }
+}
+main() {
+    printLine(show(swapDown(3, 5)))
+    sumTo(100000, 0)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files.clone()).expect("program should typecheck");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let module = jsgen::generate(&analyzed, &program, &source::SourceMap::from_files(&files)).expect("program should generate");
+
+    // calls to itself in tail position loop, on copies of the parameters the
+    // closures it returns hold on to
+    assert!(module.contains("let n_ = n\n        while (true) {"));
+    assert!(module.contains("const _r1 = a_\n                a_ = _t2\n                b = _r1\n                continue"));
+
+    if std::process::Command::new("node").arg("--version").output().is_err() {
+        return;
+    }
+    let dir = std::env::temp_dir().join(format!("spruce-test-js-tail-{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("cannot create the module's directory");
+    fs::write(dir.join("main.mjs"), module).expect("cannot write the module");
+    fs::write(dir.join(jsgen::RUNTIME_FILE), jsgen::RUNTIME).expect("cannot write the runtime");
+    let run = std::process::Command::new("node").arg(dir.join("main.mjs")).output().expect("node should run");
+    let reuse = "import('./main.mjs').then(m => { const f = m.sumTo(5n); console.log(String(f(0n)), String(f(1n))) })";
+    let reused = std::process::Command::new("node").arg("-e").arg(reuse).current_dir(&dir).output().expect("node should run");
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "2\n5000050000\n");
+    assert_eq!(String::from_utf8_lossy(&reused.stdout), "15 16\n");
+}
+
+#[test]
+fn test_wasm() {
+    let prog = "
+type Shape {
+    Circle(Int)
+    Square(Int, Int)
+}
+
+area(s) {
+    case s {
+        Circle(r) -> 3 * r * r
+        Square(a, b) -> a * b
+    }
+}
+
+inc(x) {
+    x + 1
+}
+
+apply(f, x) {
+    f(x)
+}
+
+main() {
+    printLine(show(map([Circle(2), Square(3, 4)], area)))
+    printLine(show([sqrt(toFloat(2)), sin(toFloat(355)), cos(toFloat(0))]))
+    printLine(show(compare(floor(sqrt(toFloat(10))), ceil(tan(toFloat(1))))))
+    debug(Square(1, 2) == Square(1, 2))
+    apply(inc >> inc, 40)
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, typed, _) = compile(files.clone()).expect("program should typecheck");
+    let program = anf::lower(&analyzed, &typed, "main").expect("program should lower");
+    let module = wasm::generate(&analyzed, &program, &source::SourceMap::from_files(&files)).expect("program should generate");
+    assert_eq!(&module[..8], b"\0asm\x01\0\0\0");
+
+    let unsupported = "
+main() {
+    readLine()
+}
+";
+    let files_unsupported = vec![(prelude.as_str(), String::from("prelude")), (unsupported, String::from("Main"))];
+    let (analyzed_unsupported, typed_unsupported, _) = compile(files_unsupported.clone()).expect("program should typecheck");
+    let program_unsupported = anf::lower(&analyzed_unsupported, &typed_unsupported, "main").expect("program should lower");
+    let error = wasm::generate(&analyzed_unsupported, &program_unsupported, &source::SourceMap::from_files(&files_unsupported)).err().expect("readLine has no wasm version");
+    assert_eq!(error.message, "the wasm target has no builtin 'readLine'");
+
+    // Ints are tagged as they are natively, so the same literals don't fit
+    let wide = "main() {\n    show(9223372036854775807)\n}\n";
+    let files_wide = vec![(prelude.as_str(), String::from("prelude")), (wide, String::from("Main"))];
+    let (analyzed_wide, typed_wide, _) = compile(files_wide.clone()).expect("program should typecheck");
+    let program_wide = anf::lower(&analyzed_wide, &typed_wide, "main").expect("program should lower");
+    let error = wasm::generate(&analyzed_wide, &program_wide, &source::SourceMap::from_files(&files_wide)).err().expect("the literal needs 64 bits");
+    assert_eq!(error.code, Some(error_codes::ErrorCode::LiteralOutOfRange));
+
+    if std::process::Command::new("node").arg("--version").output().is_err() {
+        return;
+    }
+    // from a directory whose path has to be escaped in a url, as for js
+    let dir = std::env::temp_dir().join(format!("spruce test wasm #{}", std::process::id()));
+    fs::create_dir_all(&dir).expect("cannot create the module's directory");
+    fs::write(dir.join("main.wasm"), module).expect("cannot write the module");
+    fs::write(dir.join(wasm::HOST_FILE), wasm::HOST).expect("cannot write the host");
+    let run = std::process::Command::new("node").arg(dir.join(wasm::HOST_FILE)).arg(dir.join("main.wasm")).output().expect("node should run");
+    fs::remove_dir_all(&dir).ok();
+    assert_eq!(String::from_utf8_lossy(&run.stdout), "Cons(12, Cons(12, Nil))\nCons(1.4142135623730951, Cons(-0.00003014435335948845, Cons(1, Nil)))\nGT\n42\n");
+    assert_eq!(String::from_utf8_lossy(&run.stderr), "debug: True\n");
+}
+
+#[test]
+fn test_environment_dump() {
+    let prog = "
+zero = 0
+
+ident(x) {
+    x
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let (analyzed, _, env) = compile(files).expect("program should typecheck");
+
+    // symbols are listed in order of name
+    let dump = env.as_str(&analyzed, false);
+    let names: Vec<&str> = dump.lines().map(|line| line.split(" : ").next().unwrap()).collect();
+    let mut sorted = names.clone();
+    sorted.sort();
+    assert_eq!(names, sorted);
+    assert_eq!(dump.lines().any(|line| line == "ident : (a) -> a"), true);
+    assert_eq!(dump.lines().any(|line| line == "zero : Int"), true);
+    assert_eq!(dump.lines().any(|line| line.starts_with("type ")), false);
+
+    // types and their constructors come first
+    let dump = env.as_str(&analyzed, true);
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines[0], "type Bool");
+    assert_eq!(lines.contains(&"type Maybe(a)"), true);
+    assert_eq!(lines.contains(&"Just : (a) -> Maybe(a)"), true);
+    assert_eq!(lines.contains(&"Cons : (a, List(a)) -> List(a)"), true);
+    assert_eq!(dump.ends_with(&env.as_str(&analyzed, false)), true);
+}
+
+#[test]
+fn test_type_at_position() {
+    let prog = "
+double(n) {
+    m = n * 2
+    m
+}
+
+wrap(x) {
+    y = Just(x)
+    y
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let main = source::SourceMap::from_files(&files).file_id("Main").unwrap();
+    let (_, typed, env) = compile(files).expect("program should typecheck");
+    let type_at = |text: &str, skip: usize| {
+        let offset = prog.find(text).expect(text) + skip;
+        typed.query_type_at(&env, main, offset).map(|display| display.to_string())
+    };
+
+    assert_eq!(type_at("n * 2", 0), Some(String::from("Int")));
+    assert_eq!(type_at("n * 2", 2), Some(String::from("Int")));
+    assert_eq!(type_at("m = n", 0), Some(String::from("Int")));
+    assert_eq!(type_at("double", 0), Some(String::from("(Int) -> Int")));
+    assert_eq!(type_at("Just(x)", 5), Some(String::from("a")));
+    assert_eq!(type_at("Just(x)", 0), Some(String::from("Maybe(a)")));
+    assert_eq!(type_at("wrap", 0), Some(String::from("(a) -> Maybe(a)")));
+
+    // the innermost expression is the one reported
+    let display = typed.query_type_at(&env, main, prog.find("n * 2").unwrap()).unwrap();
+    assert_eq!(display.info.span.end - display.info.span.start, 1);
+
+    assert_eq!(typed.query_type_at(&env, main, prog.find("\nwrap").unwrap()), None);
+    assert_eq!(typed.query_type_at(&env, source::NO_FILE, prog.find("n * 2").unwrap()), None);
+}
+
+#[test]
+fn test_cascading_errors() {
+    // each mistake is reported once, however its result is used afterwards
+    let fail_prog = "
+h(a) {
+    b = a + True
+    b
+}
+
+k(m) {
+    case m {
+        Just(z) -> not(z + 1)
+        Nothing -> 1 + Nil
+    }
+}
+
+main() {
+    x = h(1)
+    y = x + 1
+    w = not(x)
+    case Just(1 + True) {
+        Just(z) -> z
+        Nothing -> k(Nothing)
+    }
+}
+";
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
+    let errors = compile(files.clone()).err().expect("program should not typecheck");
+    let sources = source::SourceMap::from_files(&files);
+    let lines: Vec<usize> = errors.iter().map(|e| e.line_col(&sources).line).collect();
+    assert_eq!(lines, vec![3, 9, 10, 18]);
+}
+
+#[test]
+fn test_consteval() {
+    use error_codes::ErrorCode;
+
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let errors = |prog: &str| {
+        let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+        compile(files).err().unwrap_or_default()
+    };
+
+    // definitions that only compute values compile, however long they take
+    let prog = "
+a = 2
+b = a * 21
+n = count(100000, 0)
+count(i, acc) {
+    case i {
+        0 -> acc
+        _ -> count(i - 1, acc + 1)
+    }
+}
+main() {
+    b + n
+}
+";
+    assert!(errors(prog).is_empty());
+
+    // one with effects is refused, even from a function it calls
+    let prog = "
+greet() {
+    printLine(show(1))
+}
+x = greet()
+main() {
+    x
+}
+";
+    let found = errors(prog);
+    assert_eq!(found[0].code, Some(ErrorCode::EffectInDefinition));
+    assert_eq!(&prog[found[0].info.span.start..found[0].info.span.end], "printLine(show(1))");
+    assert_eq!(found[0].labels[0].message, "while working out 'x'");
+
+    // and one that fails does so as the program compiles
+    let prog = "
+limit = 10
+half = limit / (limit - 10)
+main() {
+    half
+}
+";
+    let found = errors(prog);
+    assert_eq!(found[0].code, Some(ErrorCode::FailingDefinition));
+    assert_eq!(found[0].message, "division by zero");
+    assert!(found[0].labels.is_empty());
+
+    // those the host gives the value of are left for the run
+    let mut compiler = embed::Compiler::new();
+    compiler.register_fn("double", embed::Type::Func(vec![embed::Type::Int], Box::new(embed::Type::Int)), |_, args| match args {
+        [embed::Value::Int(n)] => Ok(embed::Value::Int(n * 2)),
+        _ => Err(String::from("double expects an Int"))
+    });
+    let (analyzed, typed, _) = compiler.compile(vec![("x = double(2)\nmain() {\n    x\n}\n", String::from("Main"))]).expect("program should compile");
+    assert_eq!(compiler.call(&analyzed, &typed, "main", Vec::new()).ok(), Some(embed::Value::Int(4)));
+}
+
+#[test]
+fn test_error_code_examples() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    for code in error_codes::ALL_CODES.iter() {
+        assert_eq!(code.as_str().to_lowercase().parse::<error_codes::ErrorCode>(), Ok(*code));
+
+        let mut files = vec![(prelude.as_str(), String::from("prelude"))];
+        files.extend(code.example_files());
+        // some codes belong to lints, which only stop compilation when denied
+        let sources = source::SourceMap::from_files(&files);
+        let mut lints = lint::Lints::new();
+        lints.set_level(lint::Lint::NonExhaustive, lint::Level::Deny);
+        let errors = compile_with_lints(&sources, &mut lints).err().expect(code.as_str());
+        assert_eq!(errors[0].code, Some(*code));
+    }
+}
+
+#[test]
+fn test_secondary_labels() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let prog = "f(a) {\n    a + 1\n}\nmain() {\n    y = f(True)\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("call should not typecheck");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].labels.len(), 1);
+    assert_eq!(errors[0].labels[0].message, "declared here");
+    assert_eq!(errors[0].labels[0].info.span.start, 0);
+}
+
+#[test]
+fn test_readable_types() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let prog = "main() {\n    x = 1 + True\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("Bool is not an Int");
+    assert_eq!(errors[0].message, "Unification failed between Bool and Int");
+
+    let prog = "main() {\n    x = Just(1) == Nil\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("a Maybe is not a List");
+    assert_eq!(errors[0].message, "Unification failed between List(a) and Maybe(Int)");
+}
+
+#[test]
+fn test_infinite_type() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let prog = "f(x) {\n    y = Cons(x, x)\n    y\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("x can't be a list of itself");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "cannot construct infinite type: a = List(a)");
+    assert_eq!(errors[0].helps.len(), 1);
+}
+
+#[test]
+fn test_did_you_mean() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let prog = "main() {\n    x = mapp([1], not)\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("mapp is not declared");
+    assert_eq!(errors[0].helps, vec![String::from("did you mean 'map'?")]);
+
+    let prog = "f(x) {\n    case x {\n        Jsut(y) -> y\n        Nothing -> 0\n    }\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("Jsut is not a constructor");
+    assert_eq!(errors[0].helps, vec![String::from("did you mean 'Just'?")]);
+
+    // nothing is suggested when no name is close
+    let prog = "main() {\n    x = completelyUnknown\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("name is not declared");
+    assert_eq!(errors[0].helps.len(), 0);
+}
+
+#[test]
+fn test_fix_suggestions() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+
+    // a typo with a single close match can be fixed automatically
+    let prog = "main() {\n    length = 1\n    x = lenght + 1\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("lenght is not declared");
+    let fixes: Vec<&error::Suggestion> = errors[0].suggestions.iter().filter(|s| s.machine_applicable).collect();
+    assert_eq!(error::apply_suggestions(prog, fixes), "main() {\n    length = 1\n    x = length + 1\n}\n");
+
+    let prog = "main {\n    x = 1\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let errors = compile(files).err().expect("missing parens");
+    assert_eq!(error::apply_suggestions(prog, errors[0].suggestions.iter().collect()), "main() {\n    x = 1\n}\n");
+
+    // missing options are suggested with a placeholder to fill in
+    let prog = "f(ls) {\n    case ls {\n        Nil -> 0\n    }\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+    let sources = source::SourceMap::from_files(&files);
+    let mut lints = lint::Lints::new();
+    assert_eq!(compile_with_lints(&sources, &mut lints).is_ok(), true);
+    let warnings = lints.warnings;
+    assert_eq!(warnings[0].severity, error::Severity::Warning);
+    assert_eq!(warnings[0].code, Some(error_codes::ErrorCode::NonExhaustiveCase));
+    assert_eq!(warnings[0].suggestions[0].machine_applicable, false);
+    assert_eq!(
+        error::apply_suggestions(prog, warnings[0].suggestions.iter().collect()),
+        "f(ls) {\n    case ls {\n        Cons(arg1, arg2) -> todo\n        Nil -> 0\n    }\n}\n"
+    );
+
+    let mut lints = lint::Lints::new();
+    lints.set_level(lint::Lint::NonExhaustive, lint::Level::Deny);
+    let errors = compile_with_lints(&sources, &mut lints).err().expect("non-exhaustive is denied");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::NonExhaustiveCase));
+    assert_eq!(errors[0].suggestions.len(), 1);
+}
+
+#[test]
+fn test_lints() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let prog = "f(m) {\n    x = 1\n    unused = 5\n    case m {\n        Just(x) -> x\n        _ -> 0\n        Nothing -> x\n    }\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main"))];
+
+    // lints warn by default, without stopping compilation
+    let sources = source::SourceMap::from_files(&files);
+    let mut lints = lint::Lints::new();
+    assert_eq!(compile_with_lints(&sources, &mut lints).is_ok(), true);
+    let messages: Vec<&str> = lints.warnings.iter().map(|w| w.message.as_str()).collect();
+    assert_eq!(messages, vec!["'x' shadows an earlier declaration", "'unused' is never used", "this option can never be reached"]);
+    assert_eq!(lints.warnings.iter().all(|w| !w.is_error()), true);
+
+    let mut lints = lint::Lints::new();
+    lints.set_level(lint::Lint::Unused, lint::Level::Allow);
+    lints.set_level(lint::Lint::Shadowing, lint::Level::Deny);
+    let errors = compile_with_lints(&sources, &mut lints).err().expect("shadowing is denied");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].message, "'x' shadows an earlier declaration");
+    assert_eq!(lints.warnings.len(), 1);
+}
+
+#[test]
+fn test_modules() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let util = "module Util\n\ntype Pair(a, b) {\n    Pair(a, b)\n}\n\ndouble(x) {\n    x * 2\n}\n\nswap(p) {\n    case p {\n        Pair(a, b) -> Pair(b, a)\n    }\n}\n";
+    let prog = "import Util\n\ndouble(x) {\n    x + x\n}\n\nmain() {\n    p = swap(Pair(1, True))\n    double(3)\n}\n";
+
+    // modules can reuse each other's names, and are checked after their
+    // imports whatever order the files come in
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main")), (util, String::from("util.sp"))];
+    let (analyzed, _, environment) = compile(files).expect("modules should compile");
+    let order: Vec<&str> = analyzed.modules.iter().map(|module| module.name.as_str()).collect();
+    assert_eq!(order, vec!["prelude", "Util", "Main"]);
+    assert_eq!(environment.type_of_symbol("swap").map(|ty| ty.to_string()), Some(String::from("(Pair(a, b)) -> Pair(b, a)")));
+
+    // declarations are only visible where they are imported
+    let prog = "main() {\n    p = swap(Pair(1, True))\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main")), (util, String::from("util.sp"))];
+    let errors = compile(files).err().expect("Util is not imported");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::UnboundName));
+
+    let other = "module Other\n\ndouble(x) {\n    x\n}\n";
+    let prog = "import Util\nimport Other\n\nmain() {\n    double(3)\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main")), (util, String::from("util.sp")), (other, String::from("other.sp"))];
+    let errors = compile(files).err().expect("double is ambiguous");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::AmbiguousName));
+
+    let prog = "import Utils\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (prog, String::from("Main")), (util, String::from("util.sp"))];
+    let errors = compile(files).err().expect("there is no Utils");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::UnknownModule));
+    assert_eq!(errors[0].helps, vec![String::from("did you mean 'Util'?")]);
+}
+
+#[test]
+fn test_exports() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let stack = "module Stack (Stack, Entry(..), empty, push)\n\ntype Stack(a) {\n    Stack(List(a))\n}\n\ntype Entry(a) {\n    Entry(a)\n}\n\nempty() {\n    s = Stack(Nil)\n    s\n}\n\npush(s, x) {\n    case s {\n        Stack(xs) -> wrap(Cons(x, xs))\n    }\n}\n\nwrap(xs) {\n    s = Stack(xs)\n    s\n}\n";
+    let compile_main = |prog: &str| {
+        let files = vec![(prelude.as_str(), String::from("prelude")), (stack, String::from("stack.sp")), (prog, String::from("Main"))];
+        compile(files).err()
+    };
+
+    let prog = "import Stack\n\nmain() {\n    s = push(empty(), Entry(1))\n    e = Entry(2)\n    case e {\n        Entry(x) -> x\n    }\n}\n";
+    assert_eq!(compile_main(prog).is_none(), true);
+
+    // unexported functions and constructors can't be used from outside
+    let prog = "import Stack\n\nmain() {\n    s = wrap(Nil)\n}\n";
+    let errors = compile_main(prog).expect("wrap is private");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::PrivateName));
+    assert_eq!(errors[0].message, "'wrap' is private to module 'Stack'");
+
+    let prog = "import Stack\n\nsize(s) {\n    case s {\n        Stack(xs) -> 0\n    }\n}\n";
+    let errors = compile_main(prog).expect("the Stack constructor is private");
+    assert_eq!(errors[0].message, "'Stack' is private to module 'Stack'");
+
+    // a module's own declarations hide private ones of the same name
+    let prog = "import Stack\n\nwrap(x) {\n    x\n}\n\nmain() {\n    wrap(1)\n}\n";
+    assert_eq!(compile_main(prog).is_none(), true);
+
+    let bad = "module Bad (missing)\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (bad, String::from("bad.sp"))];
+    let errors = compile(files).err().expect("missing is not declared");
+    assert_eq!(errors[0].message, "'missing' is exported but not declared in module 'Bad'");
+}
+
+#[test]
+fn test_qualified_names() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let util = "module Util (Pair(..), double)\n\ntype Pair(a, b) {\n    Pair(a, b)\n}\n\ndouble(x) {\n    x * 2\n}\n\nhelper() {\n    1\n}\n";
+    let other = "module Other\n\ndouble(x) {\n    x\n}\n";
+    let compile_main = |prog: &str| {
+        let files = vec![(prelude.as_str(), String::from("prelude")), (util, String::from("util.sp")), (other, String::from("other.sp")), (prog, String::from("Main"))];
+        compile(files).err()
+    };
+
+    let prog = "import Util\nimport Other\n\nmain() {\n    p = Util.Pair(1, Other.double(2))\n    case p {\n        Util.Pair(a, b) -> Util.double(a) + b\n    }\n}\n";
+    assert_eq!(compile_main(prog).is_none(), true);
+
+    // an ambiguous name suggests each module it could come from
+    let prog = "import Util\nimport Other\n\nmain() {\n    x = double(2)\n}\n";
+    let errors = compile_main(prog).expect("double is ambiguous");
+    assert_eq!(errors[0].message, "'double' is ambiguous, as it is imported from both 'Util' and 'Other'");
+    let replacements: Vec<&str> = errors[0].suggestions.iter().map(|s| s.replacement.as_str()).collect();
+    assert_eq!(replacements, vec!["Util.double", "Other.double"]);
+    let fixed = error::apply_suggestions(prog, vec![&errors[0].suggestions[0]]);
+    assert_eq!(compile_main(&fixed).is_none(), true);
+
+    let prog = "import Util\n\nmain() {\n    x = Utl.double(2)\n}\n";
+    let errors = compile_main(prog).expect("Utl is not a module");
+    assert_eq!(errors[0].message, "module 'Utl' is not imported");
+    assert_eq!(errors[0].helps, vec![String::from("did you mean 'Util'?")]);
+
+    let prog = "import Util\n\nmain() {\n    x = Util.triple(2)\n}\n";
+    let errors = compile_main(prog).expect("Util has no triple");
+    assert_eq!(errors[0].message, "'triple' is not declared in module 'Util'");
+
+    let prog = "import Util\n\nmain() {\n    x = Util.helper()\n}\n";
+    let errors = compile_main(prog).expect("helper is private");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::PrivateName));
+}
+
+#[test]
+fn test_import_cycles() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let a = "module A\nimport B\n";
+    let b = "module B\n\nimport C\n";
+    let c = "module C\nimport A\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (a, String::from("a.sp")), (b, String::from("b.sp")), (c, String::from("c.sp"))];
+    let sources = source::SourceMap::from_files(&files);
+    let errors = compile(files.clone()).err().expect("the imports form a cycle");
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::ImportCycle));
+    assert_eq!(errors[0].message, "modules import each other in a cycle: A → B → C → A");
+
+    // every import on the way round is pointed out
+    assert_eq!(sources.name(errors[0].info.file), "a.sp");
+    let labels: Vec<(&str, usize)> = errors[0].labels.iter().map(|label| (label.message.as_str(), label.info.span.start)).collect();
+    assert_eq!(labels, vec![("'B' imports 'C' here", 10), ("'C' imports 'A' here", 9)]);
+
+    // modules that merely share an import are fine
+    let d = "module D\nimport A\nimport B\n";
+    let a = "module A\nimport B\n";
+    let b = "module B\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (d, String::from("d.sp")), (a, String::from("a.sp")), (b, String::from("b.sp"))];
+    let (analyzed, _, _) = compile(files).expect("there is no cycle");
+    let order: Vec<&str> = analyzed.modules.iter().map(|module| module.name.as_str()).collect();
+    assert_eq!(order, vec!["prelude", "B", "A", "D"]);
+}
+
+#[test]
+fn test_interfaces() {
+    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
+    let stack = "module Stack (Stack, Entry(..), empty, push, top)\n\ntype Stack(a) {\n    Stack(List(a))\n}\n\ntype Entry(a) {\n    Entry(a)\n}\n\nempty() {\n    s = Stack(Nil)\n    s\n}\n\npush(s, x) {\n    case s {\n        Stack(xs) -> wrap(Cons(x, xs))\n    }\n}\n\ntop(s) {\n    case s {\n        Stack(xs) -> head(xs)\n    }\n}\n\nhead(xs) {\n    case xs {\n        Cons(x, rest) -> Just(x)\n        Nil -> Nothing\n    }\n}\n\nwrap(xs) {\n    s = Stack(xs)\n    s\n}\n";
+    let files = vec![(prelude.as_str(), String::from("prelude")), (stack, String::from("stack.sp"))];
+    let (analyzed, _, env) = compile(files).expect("stack should typecheck");
+    let module = analyzed.modules.iter().find(|module| module.name == "Stack").unwrap();
+
+    // only exported declarations are described, but every type is
+    let written = interface::emit(&analyzed, &env, module);
+    let expected = "module Stack (Stack, Entry(..), empty, push, top)
+type Stack(a) {
+    Stack(List(a))
+}
+type Entry(a) {
+    Entry(a)
+}
+empty : () -> Stack(a)
+push : (Stack(a), a) -> Stack(a)
+top : (Stack(a)) -> Maybe(a)
+";
+    assert_eq!(written, expected);
+
+    // importers are checked against the interface in place of the module
+    let compile_main = |prog: &str| {
+        let files = vec![(prelude.as_str(), String::from("prelude")), (written.as_str(), String::from("stack.sprucei")), (prog, String::from("Main"))];
+        compile(files)
+    };
+    let prog = "import Stack\n\nmain() {\n    s = push(empty(), Entry(1))\n    top(s)\n}\n";
+    let (analyzed, _, env) = compile_main(prog).expect("main should typecheck against the interface");
+    let stack = analyzed.modules.iter().find(|module| module.name == "Stack").expect("the interface has a module");
+    assert_eq!(analyzed.signatures.iter().filter(|sig| sig.info.file == stack.file).count(), 3);
+    assert_eq!(env.type_of_symbol("main").map(|ty| ty.to_string()), Some(String::from("() -> Maybe(Entry(Int))")));
+
+    let prog = "import Stack\n\nmain() {\n    s = push(1, empty())\n}\n";
+    let errors = compile_main(prog).err().expect("the arguments are swapped");
+    assert_eq!(errors[0].code, Some(error_codes::ErrorCode::MismatchedTypes));
+
+    let prog = "import Stack\n\nmain() {\n    s = Stack(Nil)\n}\n";
+    let errors = compile_main(prog).err().expect("the Stack constructor is private");
+    assert_eq!(errors[0].message, "'Stack' is private to module 'Stack'");
+}
+
+#[test]
+fn test_graph() {
+    let prog = "type Tree(a) {
+    Leaf
+    Node(Forest(a), a)
+}
+
+type Forest(a) {
+    Forest(List(Tree(a)))
+}
+
+limit = 10
+
+isEven(n) {
+    case n {
+        0 -> True
+        _ -> isOdd(n - 1)
+    }
+}
+
+isOdd(n) {
+    case n {
+        0 -> False
+        _ -> isEven(n - 1)
+    }
+}
+
+main() {
+    print(isEven(limit))
+}
+
+test \"even\" {
+    isEven(2)
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let parsed = parser::parse(&sources).ok().expect("the program parses");
+    let analyzed = name_analysis::name_analysis(parsed, &sources, &mut lint::Lints::new()).ok().expect("the program's names resolve");
+
+    // functions that call each other are boxed together, as are types that
+    // hold each other, and neither tests nor the prelude are drawn
+    assert_eq!(graph::dot(&analyzed, &[sources.file_id("main").unwrap()]), "digraph program {
+    subgraph cluster_calls {
+        label=\"calls\"
+        f0 [label=\"main.limit\", shape=box]
+        f3 [label=\"main.main\", shape=ellipse]
+        subgraph cluster_calls_0 {
+            label=\"\"
+            style=dashed
+            f1 [label=\"main.isEven\", shape=ellipse]
+            f2 [label=\"main.isOdd\", shape=ellipse]
+        }
+        f1 -> f2
+        f2 -> f1
+        f3 -> f0
+        f3 -> f1
+    }
+    subgraph cluster_types {
+        label=\"types\"
+        subgraph cluster_types_0 {
+            label=\"\"
+            style=dashed
+            t0 [label=\"main.Tree\", shape=box]
+            t1 [label=\"main.Forest\", shape=box]
+        }
+        t0 -> t1
+        t1 -> t0
+    }
+}
+");
+}
+
+#[test]
+fn test_debugger() {
+    let prog = "fact(n) {
+    case n == 0 {
+        True -> 1
+        False -> {
+            r = fact(n - 1)
+            n * r
+        }
+    }
+}
+
+main() {
+    x = fact(3)
+    x + 1
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, typed, environment) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+    let script = "locals
+break main:12
+break fact
+continue
+continue
+bt
+delete 2
+next
+next
+print r
+finish
+locals
+quit
+";
+    let mut output = Vec::new();
+    let (result, quit) = {
+        let mut debugger = debugger::Debugger::new(&analyzed, &environment, &sources, script.as_bytes(), &mut output);
+        assert!(debugger.start());
+        let result = eval::run_observed(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None), &mut debugger);
+        (result, debugger.quit)
+    };
+    let error = result.err().expect("quitting should stop the program");
+    assert!(quit);
+    assert_eq!(error.message, "the debugger stopped the program");
+    // the debugger prompts with no newline, so each answer follows a prompt
+    assert_eq!(String::from_utf8(output).unwrap(), "(spruce) the program hasn't started yet
+(spruce) breakpoint 1 at main:12
+(spruce) breakpoint 2 at fact
+(spruce) breakpoint 1, in main at main:12:5
+   12 |     x = fact(3)
+(spruce) breakpoint 2, in fact at main:2:5
+    2 |     case n == 0 {
+(spruce) #0 fact at main:2:5
+#1 main at main:12:9
+(spruce) deleted breakpoint 2 at fact
+(spruce) in fact at main:5:13
+    5 |             r = fact(n - 1)
+(spruce) in fact at main:6:13
+    6 |             n * r
+(spruce) r : Int = 2
+(spruce) fact returned 6
+in main at main:13:5
+   13 |     x + 1
+(spruce) x : Int = 6
+(spruce) ");
+}
+
+#[test]
+fn test_profile() {
+    let prog = "fact(n) {
+    case n == 0 {
+        True -> 1
+        False -> n * fact(n - 1)
+    }
+}
+
+double(n) {
+    n * 2
+}
+
+main() {
+    x = fact(5)
+    double(x)
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, typed, _) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+    let function = |name: &str| analyzed.functions.iter().find(|func| eval::symbol_name(&analyzed, func.val.name) == name).unwrap().val.name;
+
+    let mut tree = profile::Profiler::new();
+    let value = eval::run_observed(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None), &mut tree).expect("program should run");
+    assert_eq!(eval::show(&analyzed, &value), "240");
+    let mut vm = profile::Profiler::new();
+    vm::run_profiled(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None), &mut vm).expect("program should run");
+
+    for profiler in [&mut tree, &mut vm] {
+        profiler.stop();
+        let calls: Vec<usize> = ["main", "fact", "double"].iter().map(|name| profiler.stats(function(name)).unwrap().calls).collect();
+        assert_eq!(calls, vec![1, 6, 1]);
+        let fact = profiler.stats(function("fact")).unwrap();
+        assert!(fact.own <= fact.total);
+        assert!(profiler.report(&analyzed).starts_with("function  "));
+    }
+
+    // recursion stays in one frame, and the tree-walker's call to double in
+    // tail position takes main's place
+    let stacks = |profiler: &profile::Profiler| -> Vec<String> {
+        profiler.collapsed(&analyzed).lines().map(|line| String::from(line.rsplit_once(' ').unwrap().0)).collect()
+    };
+    assert_eq!(stacks(&tree), vec!["double", "main", "main;fact"]);
+    assert_eq!(stacks(&vm), vec!["main", "main;double", "main;fact"]);
+}
+
+#[test]
+fn test_coverage() {
+    let prog = "sign(n) {
+    case n < 0 {
+        True -> {
+            m = 0 - n
+            m - m - 1
+        }
+        False -> 1
+    }
+}
+
+/// how many there are
+count(list) {
+    case list {
+        Cons(x, rest) -> x - x + 1 + count(rest)
+        Nil -> 0
+    }
+}
+
+unused(n) {
+    n
+}
+
+three = [1, 2, 3]
+
+test \"counting\" {
+    count(three) == 3
+}
+
+test \"positive\" {
+    sign(4) == 1
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main.sp"))]);
+    let (analyzed, typed, _) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+    let mut covered = coverage::Coverage::new();
+    let runs = testing::run_tests(&analyzed, &typed, &sources, &[], || eval::Runtime::new(Vec::new(), None), Some(&mut covered));
+    assert!(runs.iter().all(|run| run.failure.is_none()));
+
+    // the definition runs once for each test, and the tests aren't counted
+    let files = covered.files(&analyzed, &typed, &sources, &[sources.file_id("main.sp").unwrap()]);
+    assert_eq!(coverage::lcov(&files, &sources), "TN:
+SF:main.sp
+FN:1,sign
+FN:12,count
+FN:19,unused
+FNDA:1,sign
+FNDA:4,count
+FNDA:0,unused
+FNF:3
+FNH:2
+BRDA:2,0,0,0
+BRDA:2,0,1,1
+BRDA:13,1,0,3
+BRDA:13,1,1,1
+BRF:4
+BRH:3
+DA:2,1
+DA:3,0
+DA:4,0
+DA:5,0
+DA:7,1
+DA:13,4
+DA:14,3
+DA:15,1
+DA:20,0
+DA:23,2
+LF:10
+LH:6
+end_of_record
+");
+    assert_eq!(files[0].summary(&sources), "main.sp: 6 of 10 lines, 2 of 3 functions, 3 of 4 case options");
+    assert!(files[0].annotate(&sources).starts_with("        - | sign(n) {
+        1 |     case n < 0 {
+    ##### |         True -> {
+"));
+}
+
+#[test]
+fn test_stack_trace() {
+    let prog = "safeDiv(a, b) {
+    a / b
+}
+
+average(list, total, count) {
+    case list {
+        Cons(x, rest) -> average(rest, total + x, count + 1)
+        Nil -> safeDiv(total, count)
+    }
+}
+
+report(list) {
+    avg = average(list, 0, 0)
+    avg * 2
+}
+
+main() {
+    r = report([])
+    r
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, typed, _) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+
+    // the tree-walker's call to safeDiv in tail position takes average's
+    // place, where the VM's doesn't
+    let tree = eval::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    assert_eq!(tree.as_str(&sources), "error: division by zero
+ --> main:2:5
+  |
+2 |     a / b
+  |     ^^^^^
+  = in: safeDiv at main:2:5
+        report at main:13:11
+        main at main:18:9
+");
+    let vm = vm::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    let names: Vec<&str> = vm.trace.iter().map(|(name, _)| name.as_str()).collect();
+    assert_eq!(names, vec!["safeDiv", "average", "report", "main"]);
+    assert_eq!(vm.trace[0], tree.trace[0]);
+    assert_eq!(vm.trace[2..], tree.trace[1..]);
+
+    let prog = "down(n) {
+    case n {
+        0 -> 1 / n
+        _ -> 1 + down(n - 1)
+    }
+}
+
+main() {
+    down(30)
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, typed, _) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+    // deep enough for only the ends of the trace to be shown, which the VM
+    // goes without needing a larger stack for the test
+    let error = vm::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    assert_eq!(error.trace.len(), 32);
+    let rendered = error.as_str(&sources);
+    let trace: Vec<&str> = rendered.lines().skip_while(|line| !line.contains("= in:")).collect();
+    assert_eq!(trace.len(), 21);
+    assert_eq!(trace[0], "  = in: down at main:3:14");
+    assert_eq!(trace[10], "        ... 12 more calls");
+    assert_eq!(trace[20], "        main at main:9:5");
+
+    // recursion deeper than the tree-walker's stack allows fails with the
+    // calls it went through, on the stack a run is given
+    let prog = prog.replace("1 / n", "0").replace("down(30)", "down(10000000)");
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog.as_str(), String::from("main"))]);
+    let (analyzed, typed, _) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+    let error = std::thread::scope(|scope| {
+        std::thread::Builder::new().stack_size(1 << 30).spawn_scoped(scope, || {
+            eval::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("the recursion should overflow")
+        }).expect("failed to start the run").join().expect("the run panicked")
+    });
+    assert!(error.message.starts_with("stack overflow"), "{}", error.message);
+    assert_eq!(error.trace.len(), error.message.split(' ').nth(2).and_then(|calls| calls.parse::<usize>().ok()).expect("the message gives the depth"));
+    assert!(error.trace.iter().all(|(name, _)| name == "down"));
+}
+
+#[cfg(feature = "jit")]
+#[test]
+fn test_jit_stack_trace() {
+    let prog = "safeDiv(a, b) {
+    a / b
+}
+
+scale(n, d) {
+    safeDiv(n * 2, d) + 1
+}
+
+report(list) {
+    s = scale(length(list), 0)
+    s * 2
+}
+
+main() {
+    r = report([])
+    r
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, typed, env) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+    let compiled = jit::compile(&analyzed, &typed, &env).expect("program should compile");
+    let names: Vec<&str> = compiled.compiled().into_iter().map(|id| eval::symbol_name(&analyzed, id)).collect();
+    assert!(names.contains(&"safeDiv") && names.contains(&"scale"));
+    assert!(!names.contains(&"report") && !names.contains(&"main"));
+
+    // the calls made in native code are in the trace along with the
+    // interpreter's
+    let error = jit::run_prog(&analyzed, &typed, &env, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    assert_eq!(error.as_str(&sources), "error: division by zero
+ --> main:2:5
+  |
+2 |     a / b
+  |     ^^^^^
+  = in: safeDiv at main:2:5
+        scale at main:6:5
+        report at main:10:9
+        main at main:15:9
+");
+    let tree = eval::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    assert_eq!(error.trace, tree.trace);
+
+    let prog = "down(n) {
+    case n {
+        0 -> 1 / n
+        _ -> 1 + down(n - 1)
+    }
+}
+
+main() {
+    down(30)
+}
+";
+    let sources = source::SourceMap::from_files(&vec![(name_analysis::PRELUDE, String::from("prelude")), (prog, String::from("main"))]);
+    let (analyzed, typed, env) = compile_with_lints(&sources, &mut lint::Lints::new()).expect("program should typecheck");
+    // main is compiled as well, so the whole trace is native code's
+    let error = jit::run_prog(&analyzed, &typed, &env, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    let vm = vm::run_prog(&analyzed, &typed, "main", eval::Runtime::new(Vec::new(), None)).err().expect("dividing by zero should fail");
+    assert_eq!(error.trace.len(), 32);
+    assert_eq!(error.trace, vm.trace);
+}
@@ -0,0 +1,126 @@
+/*
+Lints are findings that don't stop a program from compiling, but probably
+point at a mistake. Each has a level that can be changed from the command
+line: allowed lints are dropped, warnings are reported alongside the compiled
+output, and denied lints are reported as errors.
+*/
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::error::{Diagnostic, Severity};
+use crate::parser::NodeInfo;
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Lint {
+    // variables and arguments that are never read
+    Unused,
+    // declarations hiding another of the same name in an outer scope
+    Shadowing,
+    // case options that can never be reached
+    Unreachable,
+    // cases over constructors missing some of them
+    NonExhaustive
+}
+
+pub const ALL_LINTS: [Lint; 4] = [Lint::Unused, Lint::Shadowing, Lint::Unreachable, Lint::NonExhaustive];
+
+impl Lint {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Lint::Unused => "unused",
+            Lint::Shadowing => "shadowing",
+            Lint::Unreachable => "unreachable",
+            Lint::NonExhaustive => "non-exhaustive"
+        }
+    }
+}
+
+impl FromStr for Lint {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        ALL_LINTS.iter().find(|lint| lint.as_str() == name).copied().ok_or(())
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Level {
+    Allow,
+    Warn,
+    Deny
+}
+
+impl Level {
+    /// Level for a command line flag, as in `-W unused`
+    pub fn from_flag(flag: &str) -> Option<Level> {
+        match flag {
+            "-A" => Some(Level::Allow),
+            "-W" => Some(Level::Warn),
+            "-D" => Some(Level::Deny),
+            _ => None
+        }
+    }
+}
+
+/// Something a pass noticed that may be worth a lint, before its level has
+/// been taken into account
+pub type Finding = (Lint, String, NodeInfo);
+
+/// Collects lint findings from every pass, sorting them into warnings and
+/// errors by their configured level
+#[derive(Debug)]
+pub struct Lints {
+    levels: HashMap<Lint, Level>,
+    pub warnings: Vec<Diagnostic>,
+    pub denied: Vec<Diagnostic>
+}
+
+impl Default for Lints {
+    fn default() -> Self {
+        Lints::new()
+    }
+}
+
+impl Lints {
+    /// Every lint starts out as a warning
+    pub fn new() -> Self {
+        Lints {
+            levels: ALL_LINTS.iter().map(|lint| (*lint, Level::Warn)).collect(),
+            warnings: Vec::new(),
+            denied: Vec::new()
+        }
+    }
+
+    pub fn set_level(&mut self, lint: Lint, level: Level) {
+        self.levels.insert(lint, level);
+    }
+
+    pub fn level(&self, lint: Lint) -> Level {
+        *self.levels.get(&lint).expect("lint without a level")
+    }
+
+    pub fn report(&mut self, finding: Finding) {
+        let (lint, message, info) = finding;
+        self.report_diagnostic(lint, Diagnostic::new(message, info));
+    }
+
+    /// Reports a finding that carries more than a message, such as a code or
+    /// suggestions, which are kept whatever its level
+    pub fn report_diagnostic(&mut self, lint: Lint, diagnostic: Diagnostic) {
+        match self.level(lint) {
+            Level::Allow => (),
+            Level::Warn => self.warnings.push(Diagnostic { severity: Severity::Warning, ..diagnostic }),
+            Level::Deny => {
+                let err = diagnostic.with_help(format!("'-D {}' makes this an error", lint.as_str()));
+                self.denied.push(err);
+            }
+        }
+    }
+
+    pub fn report_all(&mut self, findings: Vec<Finding>) {
+        for finding in findings {
+            self.report(finding);
+        }
+    }
+}
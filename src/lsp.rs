@@ -0,0 +1,1117 @@
+/*
+`spruce lsp` is a language server. Editors start it and talk to it over stdin
+and stdout with the Language Server Protocol, and it tells them what the
+compiler thinks of the program being edited.
+
+Whenever a document is opened, changed or closed, the program it's part of is
+checked again and the diagnostics for each of its files are sent back, an
+empty list for the files with none so the editor clears any it showed before.
+A document is part of the package whose spruce.toml is in its directory or the
+nearest one above it, or if it isn't in one, of a program made of the .sp files
+in its directory. The documents the editor has open stand in for the files on
+disk, whether they've been saved or not.
+
+Positions in the protocol are a line and a column counted in UTF-16 code
+units, starting from 0, where the compiler deals in byte offsets, so spans
+are converted on the way out and positions on the way in.
+*/
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{self, Notification as _};
+use lsp_types::request::{self, Request as _};
+use lsp_types::{CompletionItem, CompletionItemKind, DiagnosticRelatedInformation, Documentation, DiagnosticSeverity, Hover, HoverContents, Location, MarkupContent, MarkupKind, NumberOrString, Position, Range, Uri};
+
+use crate::error::{Severity, SpruceErr};
+use crate::lint::Lints;
+use crate::manifest;
+use crate::name_analysis as na;
+use crate::outline;
+use crate::parser::{self, NodeInfo};
+use crate::references::References;
+use crate::semantic::{self, TokenKind};
+use crate::source::{FileId, SourceFile, SourceMap};
+use crate::eval;
+use crate::registry::LangType;
+use crate::typecheck::{self, Expr, ExprNode, Node, Stmt, StmtNode, TypeRepr};
+
+/// The documents open in the editor, by path, with the text the editor has
+/// for them
+#[derive(Default, Clone)]
+pub struct Workspace {
+    open: HashMap<PathBuf, String>
+}
+
+/// A program checked from the workspace
+pub struct Analysis {
+    pub sources: SourceMap,
+    // where each file other than the prelude is
+    pub paths: HashMap<FileId, PathBuf>,
+    pub result: Result<(na::Prog, typecheck::Prog, typecheck::Environment), Vec<SpruceErr>>,
+    // what of a program with errors checks, if its names could be resolved
+    pub recovered: Option<(na::Prog, typecheck::Prog, typecheck::Environment)>,
+    pub warnings: Vec<SpruceErr>,
+    // the names of the program, if they could be resolved
+    pub references: Option<References>,
+    // what each of those names is
+    pub tokens: Vec<semantic::Token>,
+    // what each file of the program declares, if it parses
+    pub outline: Vec<outline::Symbol>
+}
+
+impl Workspace {
+    pub fn open(&mut self, path: &Path, text: String) {
+        self.open.insert(normalize(path), text);
+    }
+
+    pub fn close(&mut self, path: &Path) {
+        self.open.remove(&normalize(path));
+    }
+
+    /// The paths of the documents open in the editor
+    pub fn documents(&self) -> Vec<PathBuf> {
+        let mut documents: Vec<PathBuf> = self.open.keys().cloned().collect();
+        documents.sort();
+        documents
+    }
+
+    /// The text of a file, as the editor has it if it's open
+    pub fn text(&self, path: &Path) -> Option<String> {
+        match self.open.get(path) {
+            Some(text) => Some(text.clone()),
+            None => fs::read_to_string(path).ok()
+        }
+    }
+
+    /// Every file of the program `path` is part of, `path` among them
+    pub fn program(&self, path: &Path) -> Vec<PathBuf> {
+        let path = normalize(path);
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+
+        // the nearest package above the file, if the file is one of its
+        // sources, or those of a package it depends on
+        if let Some(package) = dir.ancestors().find(|dir| dir.join(manifest::FILE_NAME).is_file()) {
+            if let Ok(plan) = manifest::plan(package) {
+                let files: Vec<PathBuf> = plan.packages.iter().flat_map(|package| package.files.iter()).map(|file| normalize(file)).collect();
+                if files.contains(&path) {
+                    return files;
+                }
+            }
+        }
+
+        // the file's directory, including files open in it that were never
+        // saved
+        let mut files: Vec<PathBuf> = fs::read_dir(dir).into_iter().flatten().filter_map(|entry| entry.ok()).map(|entry| normalize(&entry.path())).collect();
+        files.extend(self.open.keys().filter(|open| open.parent() == Some(dir)).cloned());
+        files.push(path.clone());
+        files.retain(|file| file.extension().map_or(false, |ext| ext == "sp"));
+        files.sort();
+        files.dedup();
+        files
+    }
+
+    /// Checks the program `path` is part of, along with the prelude
+    pub fn analyze(&self, path: &Path) -> Analysis {
+        let mut sources = SourceMap::new();
+        sources.add(na::PRELUDE_FILE, na::PRELUDE);
+        let mut paths = HashMap::new();
+        for file in self.program(path) {
+            if let Some(text) = self.text(&file) {
+                paths.insert(sources.add(&file.to_string_lossy(), &text), file);
+            }
+        }
+
+        let mut lints = Lints::new();
+        let result = crate::compile_with_lints(&sources, &mut lints);
+
+        // a program with type errors still has its names resolved, and the
+        // types of what checks
+        let names = |prog: &na::Prog| {
+            let references = References::new(prog, &sources);
+            let tokens = semantic::tokens(prog, &sources, &references);
+            (references, tokens)
+        };
+        let recovered = match &result {
+            Ok(_) => None,
+            Err(_) => parser::parse(&sources).ok()
+                .and_then(|prog| na::name_analysis(prog, &sources, &mut Lints::new()).ok())
+                .map(|prog| {
+                    let (typed, env, _) = typecheck::check_partial(&prog, &mut Lints::new());
+                    (prog, typed, env)
+                })
+        };
+        let (references, tokens) = match (&result, &recovered) {
+            (Ok((prog, _, _)), _) | (Err(_), Some((prog, _, _))) => Some(names(prog)),
+            (Err(_), None) => None
+        }.unzip();
+
+        // the outline is of what parses, with types if it checks
+        let (parsed, _) = parser::parse_partial(&sources);
+        let checked = result.as_ref().ok().map(|(prog, _, env)| (prog, env));
+        let outline = outline::outline(&parsed, &sources, checked).into_iter().filter(|symbol| paths.contains_key(&symbol.info.file)).collect();
+        Analysis {
+            sources: sources,
+            paths: paths,
+            result: result,
+            recovered: recovered,
+            warnings: lints.warnings,
+            references: references,
+            tokens: tokens.unwrap_or_default(),
+            outline: outline
+        }
+    }
+}
+
+impl Analysis {
+    /// The program as checked, or what of it checks if it has errors
+    fn checked(&self) -> Option<&(na::Prog, typecheck::Prog, typecheck::Environment)> {
+        self.result.as_ref().ok().or(self.recovered.as_ref())
+    }
+
+    /// The diagnostics for each file of the program, in the form editors
+    /// take them
+    pub fn diagnostics(&self) -> Vec<(PathBuf, Vec<lsp_types::Diagnostic>)> {
+        let errors = match &self.result {
+            Ok(_) => &[][..],
+            Err(errors) => &errors[..]
+        };
+        let mut by_file: HashMap<FileId, Vec<lsp_types::Diagnostic>> = self.paths.keys().map(|id| (*id, Vec::new())).collect();
+        for diagnostic in errors.iter().chain(self.warnings.iter()) {
+            if let Some(found) = by_file.get_mut(&diagnostic.info.file) {
+                found.push(self.to_lsp(diagnostic));
+            }
+        }
+
+        let mut diagnostics: Vec<(PathBuf, Vec<lsp_types::Diagnostic>)> = by_file.into_iter().map(|(id, found)| (self.paths[&id].clone(), found)).collect();
+        diagnostics.sort_by(|a, b| a.0.cmp(&b.0));
+        diagnostics
+    }
+
+    /// Where `info` is, if it's in one of the workspace's files
+    pub fn location(&self, info: &NodeInfo) -> Option<Location> {
+        let path = self.paths.get(&info.file)?;
+        let file = self.sources.get(info.file)?;
+        Some(Location::new(uri_of(path), range(file, info.span.start, info.span.end)))
+    }
+
+    /// The file and byte offset of an editor's position in the document at
+    /// `path`
+    pub fn locate(&self, path: &Path, position: Position) -> Option<(FileId, usize)> {
+        let path = normalize(path);
+        let (id, _) = self.paths.iter().find(|(_, file)| **file == path)?;
+        Some((*id, offset(self.sources.get(*id)?, position)))
+    }
+
+    /// Where what the name at `position` in the document at `path` stands
+    /// for is declared
+    pub fn definition(&self, path: &Path, position: Position) -> Option<Location> {
+        let (file, offset) = self.locate(path, position)?;
+        let references = self.references.as_ref()?;
+        let name = references.at(file, offset)?;
+        self.location(&references.definition(name.referent)?.info)
+    }
+
+    /// Everywhere what the name at `position` in the document at `path`
+    /// stands for is named, with or without where it's declared
+    pub fn references(&self, path: &Path, position: Position, declaration: bool) -> Vec<Location> {
+        let found = self.locate(path, position).and_then(|(file, offset)| {
+            let references = self.references.as_ref()?;
+            Some(references.of(references.at(file, offset)?.referent))
+        });
+        found.unwrap_or_default().into_iter()
+            .filter(|occurrence| declaration || !occurrence.declaration)
+            .filter_map(|occurrence| self.location(&occurrence.info))
+            .collect()
+    }
+
+    /// The outline of the document at `path`, each type with its
+    /// constructors inside it
+    pub fn document_symbols(&self, path: &Path) -> Vec<lsp_types::DocumentSymbol> {
+        let path = normalize(path);
+        self.outline.iter()
+            .filter(|symbol| self.paths.get(&symbol.info.file) == Some(&path))
+            .filter_map(|symbol| self.document_symbol(symbol))
+            .collect()
+    }
+
+    fn document_symbol(&self, symbol: &outline::Symbol) -> Option<lsp_types::DocumentSymbol> {
+        let range = self.location(&symbol.info)?.range;
+        let selection = self.location(&symbol.name_info)?.range;
+        let children: Vec<lsp_types::DocumentSymbol> = symbol.children.iter().filter_map(|child| self.document_symbol(child)).collect();
+        // made from JSON, since the struct has a deprecated field that can't
+        // be left out of it
+        let found = serde_json::json!({
+            "name": symbol.name,
+            "detail": symbol.detail,
+            "kind": symbol_kind(symbol.kind),
+            "range": range,
+            "selectionRange": selection,
+            "children": children
+        });
+        serde_json::from_value(found).ok()
+    }
+
+    /// The declarations of every file of the program whose names have
+    /// `query` in them, ignoring case, with where each is
+    pub fn workspace_symbols(&self, query: &str) -> Vec<lsp_types::WorkspaceSymbol> {
+        let query = query.to_lowercase();
+        let mut found = Vec::new();
+        for symbol in &self.outline {
+            for (symbol, container) in std::iter::once((symbol, None)).chain(symbol.children.iter().map(|child| (child, Some(symbol.name.clone())))) {
+                if !symbol.name.to_lowercase().contains(&query) {
+                    continue;
+                }
+                if let Some(location) = self.location(&symbol.name_info) {
+                    found.push(lsp_types::WorkspaceSymbol {
+                        name: symbol.name.clone(),
+                        kind: symbol_kind(symbol.kind),
+                        tags: None,
+                        container_name: container,
+                        location: lsp_types::OneOf::Left(location),
+                        data: None
+                    });
+                }
+            }
+        }
+        found
+    }
+
+    /// What each name in the document at `path` is, in the form editors
+    /// take it: for each name, how many lines it's down from the last and
+    /// how far along, which is from the last if it's on the same line, how
+    /// long it is, and its kind and modifiers as indices into the legend
+    pub fn semantic_tokens(&self, path: &Path) -> lsp_types::SemanticTokens {
+        let mut data = Vec::new();
+        let file = self.locate(path, Position::new(0, 0)).and_then(|(id, _)| Some((id, self.sources.get(id)?)));
+        if let Some((id, file)) = file {
+            let mut last = Position::new(0, 0);
+            for token in self.tokens.iter().filter(|token| token.info.file == id) {
+                let start = position(file, token.info.span.start);
+                let end = position(file, token.info.span.end);
+                let delta_start = if start.line == last.line { start.character - last.character } else { start.character };
+                let mut modifiers = 0;
+                if token.declaration {
+                    modifiers |= 1;
+                }
+                if token.kind == TokenKind::Constant {
+                    modifiers |= 2;
+                }
+                data.push(lsp_types::SemanticToken {
+                    delta_line: start.line - last.line,
+                    delta_start: delta_start,
+                    length: end.character - start.character,
+                    token_type: token_type(token.kind),
+                    token_modifiers_bitset: modifiers
+                });
+                last = start;
+            }
+        }
+        lsp_types::SemanticTokens { result_id: None, data: data }
+    }
+
+    /// The types inferred for what's bound in `range` of the document at
+    /// `path`, shown after each name bound with `=` and each function's
+    /// parameters, since nothing in the language is annotated with its type.
+    /// In a program with type errors, what checks still has its types shown
+    pub fn inlay_hints(&self, path: &Path, range: Range) -> Vec<lsp_types::InlayHint> {
+        let (id, file, (prog, typed, env)) = match (self.locate(path, range.start), self.checked()) {
+            (Some((id, _)), Some(result)) => match self.sources.get(id) {
+                Some(file) => (id, file, result),
+                None => return Vec::new()
+            },
+            _ => return Vec::new()
+        };
+        let (start, end) = (offset(file, range.start), offset(file, range.end));
+        let mut hints: Vec<(usize, String)> = Vec::new();
+
+        // after a function's parameters, which are in its header, past any
+        // doc comment. Tests have none, and always give a Bool
+        for func in prog.functions.iter().filter(|func| func.info.file == id && prog.test_name(&func.val.name).is_none()) {
+            let header = outline::after_docs(&file.text, &func.info.span);
+            let close = file.text[header..func.info.span.end].find(')').map(|close| header + close + 1);
+            if let (Some(close), Some(ty)) = (close, env.type_of_id(func.val.name).filter(|ty| !ty.has_error())) {
+                hints.push((close, ty.to_string()));
+            }
+        }
+
+        // after a name bound with `=`, but not one updated with `:=`
+        for stmt in typed.assignments().into_iter().filter(|stmt| stmt.info.file == id) {
+            if let Stmt::Assign(target, expr) = &stmt.val {
+                if let na::Target::Update(_) = target.val {
+                    continue;
+                }
+                let ty = env.type_of_id(target.val.id()).unwrap_or_else(|| env.repr(expr.ty));
+                if !ty.has_error() {
+                    hints.push((target.info.span.end, ty.to_string()));
+                }
+            }
+        }
+
+        hints.sort();
+        hints.into_iter().filter(|(at, _)| start <= *at && *at <= end).map(|(at, ty)| lsp_types::InlayHint {
+            position: position(file, at),
+            label: lsp_types::InlayHintLabel::String(format!(": {}", ty)),
+            kind: Some(lsp_types::InlayHintKind::TYPE),
+            text_edits: None,
+            tooltip: None,
+            padding_left: None,
+            padding_right: Some(true),
+            data: None
+        }).collect()
+    }
+
+    /// The edits to each file that rename what the name at `position` in the
+    /// document at `path` stands for to `name`, unless the rename is refused
+    pub fn rename(&self, path: &Path, position: Position, name: &str) -> Result<lsp_types::WorkspaceEdit, String> {
+        let references = self.references.as_ref().ok_or_else(|| String::from("the program's names couldn't be resolved"))?;
+        let referent = self.locate(path, position)
+            .and_then(|(file, offset)| references.at(file, offset))
+            .map(|occurrence| occurrence.referent)
+            .ok_or_else(|| String::from("there's no name here to rename"))?;
+
+        // the edits to each file, in the order of the files
+        let mut changes: Vec<lsp_types::TextDocumentEdit> = Vec::new();
+        for edit in references.rename(referent, name)? {
+            // a name that's also used outside the workspace, as one the
+            // prelude declares is, can't be renamed everywhere
+            let location = self.location(&edit.info)
+                .ok_or_else(|| format!("can't rename '{}', since it's named outside the workspace", references.name(referent).unwrap_or("")))?;
+            let text_edit = lsp_types::OneOf::Left(lsp_types::TextEdit::new(location.range, edit.replacement));
+            match changes.iter_mut().find(|change| change.text_document.uri == location.uri) {
+                Some(change) => change.edits.push(text_edit),
+                None => changes.push(lsp_types::TextDocumentEdit {
+                    text_document: lsp_types::OptionalVersionedTextDocumentIdentifier { uri: location.uri, version: None },
+                    edits: vec![text_edit]
+                })
+            }
+        }
+        Ok(lsp_types::WorkspaceEdit {
+            document_changes: Some(lsp_types::DocumentChanges::Edits(changes)),
+            ..Default::default()
+        })
+    }
+
+    /// What the editor shows when hovering at `position` in the document at
+    /// `path`: the type of the innermost expression there, and when it names
+    /// a function or a constructor, its doc comment. In a program with type
+    /// errors, what checks still has its types shown
+    pub fn hover(&self, path: &Path, position: Position) -> Option<Hover> {
+        let (file, offset) = self.locate(path, position)?;
+        let (prog, typed, env) = self.checked()?;
+        let node = *typed.nodes_at(file, offset).last()?;
+        if env.repr(node.ty()).has_error() {
+            return None;
+        }
+
+        // on the name of a call, it's the function being called that's shown,
+        // rather than what it returns
+        let call = match node {
+            Node::Stmt(StmtNode { val: Stmt::FnCall(id, _), info, .. }) | Node::Expr(ExprNode { val: Expr::FnCall(id, _), info, .. }) => Some((*id, info)),
+            _ => None
+        };
+        let callee = call.and_then(|(id, info)| {
+            let name = eval::symbol_name(prog, id);
+            let mut info = info.clone();
+            info.span.end = info.span.start + name.len();
+            if offset < info.span.end {
+                let ty = env.type_of_id(id).filter(|ty| !ty.has_error())?;
+                Some((Some(name.to_string()), ty.to_string(), prog.function_doc(&id), Some(info)))
+            }
+            else {
+                None
+            }
+        });
+
+        let (name, ty, doc, info) = match (callee, node) {
+            (Some(callee), _) => callee,
+            (None, Node::Func(func)) => {
+                let name = eval::symbol_name(prog, func.val.name).to_string();
+                (Some(name), env.type_str(func.ty), prog.function_doc(&func.val.name), None)
+            }
+            (None, Node::Expr(ExprNode { val: Expr::Id(id), ty, info })) => {
+                (Some(eval::symbol_name(prog, *id).to_string()), env.type_str(*ty), prog.function_doc(id), Some(info.clone()))
+            }
+            (None, Node::Expr(ExprNode { val: Expr::ADTVal(val, _), ty, info })) => {
+                let value = &prog.type_table.values[val];
+                let adt = &prog.type_table.types[&value.data_type];
+                (Some(value.name.clone()), env.type_str(*ty), prog.type_doc(&adt.name), Some(info.clone()))
+            }
+            (None, node) => (None, env.type_str(node.ty()), None, Some(node.info().clone()))
+        };
+
+        let mut contents = match name {
+            Some(name) => format!("```spruce\n{} : {}\n```", name, ty),
+            None => format!("```spruce\n{}\n```", ty)
+        };
+        if let Some(doc) = doc {
+            contents.push_str(&format!("\n\n{}", doc));
+        }
+        Some(Hover {
+            contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: contents }),
+            range: info.and_then(|info| self.location(&info)).map(|location| location.range)
+        })
+    }
+
+    /// What the name being written at `offset` in `text` could be, where
+    /// `text` is the document at `path` as the editor has it. The program
+    /// gives what the modules declare and their types, and the text what's
+    /// in scope at the cursor, so an analysis of an older version of the
+    /// document still gives most of them
+    pub fn completions(&self, path: &Path, text: &str, offset: usize) -> Vec<CompletionItem> {
+        let (prog, typed, env) = match &self.result {
+            Ok(checked) => checked,
+            Err(_) => return Vec::new()
+        };
+        let path = normalize(path);
+        let file = self.paths.iter().find(|(_, file)| **file == path).map(|(id, _)| *id);
+        let current = prog.modules.iter().find(|module| Some(module.file) == file);
+        let prelude = self.sources.file_id(na::PRELUDE_FILE);
+        let scan = Scan::new(text, offset);
+
+        // how close a declaration of `module` is, if it can be seen from the
+        // cursor at all
+        let proximity = |module: &na::Module, exported: bool| -> Option<Proximity> {
+            match (&scan.qualifier, current) {
+                (Some(qualifier), _) if *qualifier == module.name && exported => Some(Proximity::Import),
+                (Some(_), _) => None,
+                (None, Some(current)) if current.name == module.name => Some(Proximity::Module),
+                (None, _) if Some(module.file) == prelude && exported => Some(Proximity::Prelude),
+                (None, Some(current)) if current.imports.iter().any(|import| import.val.module == module.name) && exported => Some(Proximity::Import),
+                (None, Some(_)) => None,
+                (None, None) => Some(Proximity::Import)
+            }
+        };
+        let module_of = |file: FileId| prog.modules.iter().find(|module| module.file == file);
+
+        let mut found: Vec<(Proximity, CompletionItem)> = Vec::new();
+        let mut add = |proximity: Proximity, name: &str, kind: CompletionItemKind, detail: Option<String>, doc: Option<&String>| {
+            found.push((proximity, CompletionItem {
+                label: name.to_string(),
+                kind: Some(kind),
+                detail: detail,
+                documentation: doc.map(|doc| Documentation::MarkupContent(MarkupContent { kind: MarkupKind::Markdown, value: doc.clone() })),
+                sort_text: Some(proximity.sort_text(name)),
+                ..Default::default()
+            }));
+        };
+
+        // at the start of an arm, the constructors of what's being matched.
+        // Its type is that of the constructors the other arms match, or what
+        // the program says it is if it was checked as the editor has it
+        if let Some(arms) = &scan.arm {
+            let list = prog.registry.type_id(LangType::List);
+            let matched = arms.iter().find_map(|name| match name.as_str() {
+                "::" => Some(list),
+                name => prog.type_table.values.values().find(|val| val.name == name).map(|val| val.data_type)
+            });
+            let scrutinee = match (matched, file) {
+                (Some(adt), _) => Some(prog.type_table.types[&adt].name.clone()),
+                (None, Some(file)) => typed.functions.iter().find_map(|func| case_at(&func.val.body, file, offset)).and_then(|case| {
+                    match env.repr(case.val.expr.ty) {
+                        TypeRepr::ADT(name, _) => Some(name),
+                        _ => None
+                    }
+                }),
+                (None, None) => None
+            };
+            for val in prog.type_table.values.values() {
+                let adt = &prog.type_table.types[&val.data_type];
+                if scrutinee.as_ref().map_or(false, |name| *name != adt.name) {
+                    continue;
+                }
+                let seen = prog.modules.iter().find(|module| module.name == val.module).and_then(|module| proximity(module, module.exports_constructors(&adt.name)));
+                if let Some(seen) = seen {
+                    add(seen, &val.name, CompletionItemKind::CONSTRUCTOR, env.type_of_value(val.id).map(|ty| ty.to_string()), prog.type_doc(&adt.name));
+                }
+            }
+        }
+        else {
+            if scan.qualifier.is_none() {
+                let depth = scan.scopes.len();
+                for (i, scope) in scan.scopes.iter().enumerate() {
+                    for name in scope.iter().rev() {
+                        add(Proximity::Local(depth - i), name, CompletionItemKind::VARIABLE, None, None);
+                    }
+                }
+                for module in prog.modules.iter().filter(|module| current.map_or(false, |current| current.imports.iter().any(|import| import.val.module == module.name))) {
+                    add(Proximity::Import, &module.name, CompletionItemKind::MODULE, None, None);
+                }
+            }
+
+            let functions = prog.functions.iter().map(|func| (func.val.name, func.info.file, func.val.doc.as_ref()));
+            let definitions = prog.definitions.iter().filter_map(|stmt| match &stmt.val {
+                na::Stmt::Assign(target, _) => Some((target.val.id(), stmt.info.file, None)),
+                _ => None
+            });
+            let signatures = prog.signatures.iter().map(|sig| (sig.val.name, sig.info.file, None));
+            for (id, file, doc) in functions.chain(definitions).chain(signatures) {
+                let name = eval::symbol_name(prog, id);
+                if let Some(seen) = module_of(file).and_then(|module| proximity(module, module.exports(name))) {
+                    let ty = env.type_of_id(id);
+                    let kind = match ty {
+                        Some(TypeRepr::Func(_, _)) => CompletionItemKind::FUNCTION,
+                        _ => CompletionItemKind::VARIABLE
+                    };
+                    add(seen, name, kind, ty.map(|ty| ty.to_string()), doc);
+                }
+            }
+            for val in prog.type_table.values.values() {
+                let adt = &prog.type_table.types[&val.data_type];
+                let seen = prog.modules.iter().find(|module| module.name == val.module).and_then(|module| proximity(module, module.exports_constructors(&adt.name)));
+                if let Some(seen) = seen {
+                    add(seen, &val.name, CompletionItemKind::CONSTRUCTOR, env.type_of_value(val.id).map(|ty| ty.to_string()), prog.type_doc(&adt.name));
+                }
+            }
+        }
+
+        // the closest of the names that start with what's been written, once
+        // each, since the closest hides the rest
+        found.retain(|(_, item)| item.label.starts_with(&scan.prefix));
+        found.sort_by(|a, b| (a.0, &a.1.label).cmp(&(b.0, &b.1.label)));
+        let mut seen = std::collections::HashSet::new();
+        found.into_iter().filter(|(_, item)| seen.insert(item.label.clone())).map(|(_, item)| item).collect()
+    }
+
+    fn to_lsp(&self, diagnostic: &SpruceErr) -> lsp_types::Diagnostic {
+        let file = self.sources.get(diagnostic.info.file).expect("diagnostics are in the program's files");
+
+        // helps are shown beneath the message, as they are on the command line
+        let mut message = diagnostic.message.clone();
+        for help in &diagnostic.helps {
+            message.push_str(&format!("\nhelp: {}", help));
+        }
+
+        let related: Vec<DiagnosticRelatedInformation> = diagnostic.labels.iter().filter_map(|label| {
+            self.location(&label.info).map(|location| DiagnosticRelatedInformation { location: location, message: label.message.clone() })
+        }).collect();
+
+        lsp_types::Diagnostic {
+            range: range(file, diagnostic.info.span.start, diagnostic.info.span.end),
+            severity: Some(match diagnostic.severity {
+                Severity::Error => DiagnosticSeverity::ERROR,
+                Severity::Warning => DiagnosticSeverity::WARNING,
+                Severity::Note => DiagnosticSeverity::INFORMATION
+            }),
+            code: diagnostic.code.map(|code| NumberOrString::String(code.as_str().to_string())),
+            source: Some(String::from("spruce")),
+            message: message,
+            related_information: if related.is_empty() { None } else { Some(related) },
+            ..Default::default()
+        }
+    }
+}
+
+/// How close a name is to where it's being completed. Variables in scope at
+/// the cursor come first, innermost first, then the module's own
+/// declarations, then those of its imports and last the prelude's
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+enum Proximity {
+    Local(usize),
+    Module,
+    Import,
+    Prelude
+}
+
+impl Proximity {
+    /// Sorts names by how close they are, then by name
+    fn sort_text(&self, name: &str) -> String {
+        match self {
+            Proximity::Local(depth) => format!("0{:03}{}", depth, name),
+            Proximity::Module => format!("1{}", name),
+            Proximity::Import => format!("2{}", name),
+            Proximity::Prelude => format!("3{}", name)
+        }
+    }
+}
+
+/// What the text of a function says about the cursor, read a line at a time
+/// so that it works on code in the middle of being written, which won't
+/// parse
+#[derive(Debug, Default)]
+struct Scan {
+    // the part of the name before the cursor
+    prefix: String,
+    // the module the name is qualified by, as in `List.ma`
+    qualifier: Option<String>,
+    // the variables of each scope around the cursor, innermost last
+    scopes: Vec<Vec<String>>,
+    // at the start of a case arm, the constructors the case's other arms
+    // match, with `::` standing for a list's
+    arm: Option<Vec<String>>
+}
+
+impl Scan {
+    fn new(text: &str, offset: usize) -> Self {
+        let offset = offset.min(text.len());
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let before = &text[line_start..offset];
+        let word = before.rfind(|c: char| !c.is_ascii_alphanumeric()).map_or(0, |i| i + 1);
+        let leading = &before[..word];
+        let qualifier = leading.strip_suffix('.').map(|rest| {
+            let start = rest.rfind(|c: char| !c.is_ascii_alphanumeric()).map_or(0, |i| i + 1);
+            rest[start..].to_string()
+        }).filter(|qualifier| !qualifier.is_empty());
+
+        // the function the cursor is in starts on the nearest line above it
+        // that isn't indented
+        let lines: Vec<&str> = text[..line_start].lines().collect();
+        let start = lines.iter().rposition(|line| line.starts_with(|c: char| c.is_ascii_alphabetic())).unwrap_or(lines.len());
+
+        // each frame is a scope, along with the arms so far when it's a case
+        let mut frames: Vec<(Vec<String>, Option<Vec<String>>)> = vec![(Vec::new(), None)];
+        for line in &lines[start.min(lines.len())..] {
+            let line = line.split("//").next().unwrap_or("").trim();
+            if line.starts_with('}') {
+                frames.pop();
+                if frames.is_empty() {
+                    frames.push((Vec::new(), None));
+                }
+                continue;
+            }
+            match line.split_once("->") {
+                Some((pattern, rest)) => {
+                    let (constructor, binders) = pattern_names(pattern);
+                    if let (Some(arms), Some(constructor)) = (&mut frames.last_mut().unwrap().1, constructor) {
+                        arms.push(constructor);
+                    }
+                    if rest.trim() == "{" {
+                        frames.push((binders, None));
+                    }
+                }
+                None if line.ends_with('{') && line.starts_with("case ") => frames.push((Vec::new(), Some(Vec::new()))),
+                // a function's parameters are in scope in its body
+                None if line.ends_with('{') => {
+                    let params = line.split_once('(').and_then(|(_, rest)| rest.split_once(')')).map_or("", |(params, _)| params);
+                    frames.push((names(params), None));
+                }
+                None => {
+                    if let Some((target, value)) = line.split_once('=') {
+                        let target = target.trim().strip_prefix("mut ").unwrap_or(target.trim()).trim();
+                        if !value.starts_with('=') && is_name(target) {
+                            frames.last_mut().unwrap().0.push(target.to_string());
+                        }
+                    }
+                }
+            }
+        }
+
+        // an arm's variables are in scope after its arrow, on its own line
+        if let Some((pattern, _)) = leading.split_once("->") {
+            frames.push((pattern_names(pattern).1, None));
+        }
+        let arm = match frames.last() {
+            Some((_, Some(arms))) if leading.trim().is_empty() => Some(arms.clone()),
+            _ => None
+        };
+        Scan {
+            prefix: before[word..].to_string(),
+            qualifier: qualifier,
+            scopes: frames.into_iter().map(|(scope, _)| scope).collect(),
+            arm: arm
+        }
+    }
+}
+
+fn is_name(text: &str) -> bool {
+    text.starts_with(|c: char| c.is_ascii_alphabetic()) && text.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+/// The names in a list of them, such as a function's parameters
+fn names(list: &str) -> Vec<String> {
+    list.split(',').map(str::trim).filter(|name| is_name(name)).map(String::from).collect()
+}
+
+/// The constructor a pattern matches, if it's one, and the variables it binds
+fn pattern_names(pattern: &str) -> (Option<String>, Vec<String>) {
+    let pattern = pattern.trim();
+    if let Some((head, tail)) = pattern.split_once("::") {
+        return (Some(String::from("::")), names(&format!("{},{}", head, tail)));
+    }
+    match pattern.split_once('(') {
+        Some((constructor, fields)) => (Some(constructor.trim().to_string()), names(fields.trim_end_matches(')'))),
+        None if pattern.starts_with(|c: char| c.is_ascii_uppercase()) => (Some(pattern.to_string()), Vec::new()),
+        None => (None, Vec::new())
+    }
+}
+
+/// The innermost case around `offset` in `file` where the cursor isn't in
+/// any of its arms
+fn case_at(body: &typecheck::BodyNode, file: FileId, offset: usize) -> Option<&typecheck::CaseNode> {
+    let within = |info: &NodeInfo| info.file == file && info.span.start <= offset && offset <= info.span.end;
+    body.val.stmts.iter().filter(|stmt| within(&stmt.info)).find_map(|stmt| match &stmt.val {
+        Stmt::Case(case) => match case.val.options.iter().find(|opt| within(&opt.info)) {
+            Some(opt) => match &opt.val.body.val {
+                typecheck::CaseBody::Body(body) => case_at(body, file, offset),
+                typecheck::CaseBody::Expr(_) => None
+            },
+            None => Some(case)
+        },
+        _ => None
+    })
+}
+
+/// The editor's position of the byte at `offset` in `file`
+pub fn position(file: &SourceFile, offset: usize) -> Position {
+    let offset = offset.min(file.text.len());
+    let line = file.lines.line_of(offset);
+    let (start, _) = file.lines.line_range(&file.text, line);
+    let character: usize = file.text[start..offset].chars().map(char::len_utf16).sum();
+    Position::new(line as u32, character as u32)
+}
+
+/// The editor's range of the bytes from `start` to `end` in `file`
+pub fn range(file: &SourceFile, start: usize, end: usize) -> Range {
+    Range::new(position(file, start), position(file, end))
+}
+
+/// The byte offset in `file` of an editor's position. Positions past the end
+/// of a line are taken to be its end, and past the last line the file's end
+pub fn offset(file: &SourceFile, position: Position) -> usize {
+    let line = position.line as usize;
+    if line >= file.lines.line_count() {
+        return file.text.len();
+    }
+
+    let (start, end) = file.lines.line_range(&file.text, line);
+    let mut units = 0;
+    for (i, c) in file.text[start..end].char_indices() {
+        if units >= position.character as usize {
+            return start + i;
+        }
+        units += c.len_utf16();
+    }
+    end
+}
+
+/// The file URI of a path, which should be absolute
+pub fn uri_of(path: &Path) -> Uri {
+    let mut uri = String::from("file://");
+    for byte in path.to_string_lossy().bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'/' | b'-' | b'.' | b'_' | b'~' => uri.push(byte as char),
+            _ => uri.push_str(&format!("%{:02X}", byte))
+        }
+    }
+    uri.parse().expect("paths make valid file URIs")
+}
+
+/// The path of a file URI
+pub fn path_of(uri: &Uri) -> Option<PathBuf> {
+    let encoded = uri.as_str().strip_prefix("file://")?.as_bytes();
+    let mut bytes = Vec::new();
+    let mut i = 0;
+    while i < encoded.len() {
+        let escaped = encoded.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match (encoded[i], escaped) {
+            (b'%', Some(byte)) => {
+                bytes.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                bytes.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8(bytes).ok().map(PathBuf::from)
+}
+
+/// The same path for a file however it's reached, so that the editor's
+/// documents and the files found on disk can be matched up
+fn normalize(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The kinds of names in semantic tokens, in the order `token_type`
+/// numbers them
+const TOKEN_TYPES: [lsp_types::SemanticTokenType; 6] = [
+    lsp_types::SemanticTokenType::FUNCTION,
+    lsp_types::SemanticTokenType::ENUM_MEMBER,
+    lsp_types::SemanticTokenType::TYPE,
+    lsp_types::SemanticTokenType::TYPE_PARAMETER,
+    lsp_types::SemanticTokenType::PARAMETER,
+    lsp_types::SemanticTokenType::VARIABLE
+];
+
+/// The modifiers of names in semantic tokens, each a bit in the order given.
+/// A constant is a variable that's read only
+const TOKEN_MODIFIERS: [lsp_types::SemanticTokenModifier; 2] = [
+    lsp_types::SemanticTokenModifier::DECLARATION,
+    lsp_types::SemanticTokenModifier::READONLY
+];
+
+fn token_type(kind: TokenKind) -> u32 {
+    match kind {
+        TokenKind::Function => 0,
+        TokenKind::Constructor => 1,
+        TokenKind::Type => 2,
+        TokenKind::TypeVariable => 3,
+        TokenKind::Parameter => 4,
+        TokenKind::Variable | TokenKind::Constant => 5
+    }
+}
+
+fn symbol_kind(kind: outline::Kind) -> lsp_types::SymbolKind {
+    match kind {
+        outline::Kind::Function => lsp_types::SymbolKind::FUNCTION,
+        outline::Kind::Constant => lsp_types::SymbolKind::CONSTANT,
+        outline::Kind::Type => lsp_types::SymbolKind::ENUM,
+        outline::Kind::Constructor => lsp_types::SymbolKind::ENUM_MEMBER
+    }
+}
+
+/// Serves the editor on the other end of `connection` until it exits
+pub fn serve(connection: Connection) -> Result<(), String> {
+    let capabilities = lsp_types::ServerCapabilities {
+        text_document_sync: Some(lsp_types::TextDocumentSyncCapability::Kind(lsp_types::TextDocumentSyncKind::FULL)),
+        hover_provider: Some(lsp_types::HoverProviderCapability::Simple(true)),
+        definition_provider: Some(lsp_types::OneOf::Left(true)),
+        references_provider: Some(lsp_types::OneOf::Left(true)),
+        rename_provider: Some(lsp_types::OneOf::Left(true)),
+        document_symbol_provider: Some(lsp_types::OneOf::Left(true)),
+        workspace_symbol_provider: Some(lsp_types::OneOf::Left(true)),
+        inlay_hint_provider: Some(lsp_types::OneOf::Left(true)),
+        semantic_tokens_provider: Some(lsp_types::SemanticTokensServerCapabilities::SemanticTokensOptions(lsp_types::SemanticTokensOptions {
+            legend: lsp_types::SemanticTokensLegend { token_types: TOKEN_TYPES.to_vec(), token_modifiers: TOKEN_MODIFIERS.to_vec() },
+            full: Some(lsp_types::SemanticTokensFullOptions::Bool(true)),
+            ..Default::default()
+        })),
+        completion_provider: Some(lsp_types::CompletionOptions {
+            trigger_characters: Some(vec![String::from(".")]),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let capabilities = serde_json::to_value(capabilities).map_err(|err| err.to_string())?;
+    connection.initialize(capabilities).map_err(|err| err.to_string())?;
+
+    let mut server = Server {
+        connection: connection,
+        workspace: Workspace::default(),
+        analyses: HashMap::new(),
+        checked: HashMap::new()
+    };
+    server.run()
+}
+
+struct Server {
+    connection: Connection,
+    workspace: Workspace,
+    // the last check of each document's program, until a document changes
+    analyses: HashMap<PathBuf, Rc<Analysis>>,
+    // the last check of each document's program that had no errors, which
+    // outlasts changes to the document
+    checked: HashMap<PathBuf, Rc<Analysis>>
+}
+
+impl Server {
+    fn run(&mut self) -> Result<(), String> {
+        let receiver = self.connection.receiver.clone();
+        for message in receiver {
+            match message {
+                Message::Request(request) => {
+                    if self.connection.handle_shutdown(&request).map_err(|err| err.to_string())? {
+                        return Ok(());
+                    }
+                    let response = self.request(request);
+                    self.send(Message::Response(response))?;
+                }
+                Message::Notification(notification) => self.notify(notification)?,
+                Message::Response(_) => ()
+            }
+        }
+        Ok(())
+    }
+
+    fn request(&mut self, request: Request) -> Response {
+        let result = match request.method.as_str() {
+            request::HoverRequest::METHOD => params(request.params).and_then(|params: lsp_types::HoverParams| {
+                let at = params.text_document_position_params;
+                let hover = self.analysis(&at.text_document.uri).and_then(|(analysis, path)| analysis.hover(&path, at.position));
+                serde_json::to_value(hover).map_err(|err| err.to_string())
+            }),
+            request::GotoDefinition::METHOD => params(request.params).and_then(|params: lsp_types::GotoDefinitionParams| {
+                let at = params.text_document_position_params;
+                let definition = self.analysis(&at.text_document.uri).and_then(|(analysis, path)| analysis.definition(&path, at.position));
+                serde_json::to_value(definition.map(lsp_types::GotoDefinitionResponse::Scalar)).map_err(|err| err.to_string())
+            }),
+            request::References::METHOD => params(request.params).and_then(|params: lsp_types::ReferenceParams| {
+                let at = params.text_document_position;
+                let declaration = params.context.include_declaration;
+                let found = self.analysis(&at.text_document.uri).map(|(analysis, path)| analysis.references(&path, at.position, declaration));
+                serde_json::to_value(found).map_err(|err| err.to_string())
+            }),
+            request::Completion::METHOD => params(request.params).and_then(|params: lsp_types::CompletionParams| {
+                let at = params.text_document_position;
+                let items = path_of(&at.text_document.uri).map_or_else(Vec::new, |path| self.complete(&path, at.position));
+                serde_json::to_value(lsp_types::CompletionResponse::Array(items)).map_err(|err| err.to_string())
+            }),
+            request::DocumentSymbolRequest::METHOD => params(request.params).and_then(|params: lsp_types::DocumentSymbolParams| {
+                let symbols = self.analysis(&params.text_document.uri).map(|(analysis, path)| analysis.document_symbols(&path));
+                serde_json::to_value(symbols.map(lsp_types::DocumentSymbolResponse::Nested)).map_err(|err| err.to_string())
+            }),
+            request::WorkspaceSymbolRequest::METHOD => params(request.params).and_then(|params: lsp_types::WorkspaceSymbolParams| {
+                let symbols = self.workspace_symbols(&params.query);
+                serde_json::to_value(lsp_types::WorkspaceSymbolResponse::Nested(symbols)).map_err(|err| err.to_string())
+            }),
+            request::SemanticTokensFullRequest::METHOD => params(request.params).and_then(|params: lsp_types::SemanticTokensParams| {
+                let tokens = self.analysis(&params.text_document.uri).map(|(analysis, path)| analysis.semantic_tokens(&path));
+                serde_json::to_value(tokens.map(lsp_types::SemanticTokensResult::Tokens)).map_err(|err| err.to_string())
+            }),
+            request::InlayHintRequest::METHOD => params(request.params).and_then(|params: lsp_types::InlayHintParams| {
+                let hints = self.analysis(&params.text_document.uri).map(|(analysis, path)| analysis.inlay_hints(&path, params.range));
+                serde_json::to_value(hints).map_err(|err| err.to_string())
+            }),
+            request::Rename::METHOD => {
+                let params: lsp_types::RenameParams = match params(request.params) {
+                    Ok(params) => params,
+                    Err(message) => return Response::new_err(request.id, lsp_server::ErrorCode::InvalidParams as i32, message)
+                };
+                let at = params.text_document_position;
+                let edit = match self.analysis(&at.text_document.uri) {
+                    Some((analysis, path)) => analysis.rename(&path, at.position, &params.new_name),
+                    None => Err(String::from("the document isn't open"))
+                };
+                // a rename that's refused is a request that failed, not one
+                // that was malformed
+                match edit {
+                    Ok(edit) => serde_json::to_value(edit).map_err(|err| err.to_string()),
+                    Err(message) => return Response::new_err(request.id, lsp_server::ErrorCode::RequestFailed as i32, message)
+                }
+            }
+            method => {
+                let message = format!("spruce doesn't handle {}", method);
+                return Response::new_err(request.id, lsp_server::ErrorCode::MethodNotFound as i32, message);
+            }
+        };
+        match result {
+            Ok(result) => Response::new_ok(request.id, result),
+            Err(message) => Response::new_err(request.id, lsp_server::ErrorCode::InvalidParams as i32, message)
+        }
+    }
+
+    fn notify(&mut self, notification: Notification) -> Result<(), String> {
+        match notification.method.as_str() {
+            notification::DidOpenTextDocument::METHOD => {
+                let params: lsp_types::DidOpenTextDocumentParams = params(notification.params)?;
+                if let Some(path) = path_of(&params.text_document.uri) {
+                    self.workspace.open(&path, params.text_document.text);
+                    self.publish(&path)?;
+                }
+            }
+            // the whole of the document is sent each time it changes
+            notification::DidChangeTextDocument::METHOD => {
+                let params: lsp_types::DidChangeTextDocumentParams = params(notification.params)?;
+                if let (Some(path), Some(change)) = (path_of(&params.text_document.uri), params.content_changes.into_iter().last()) {
+                    self.workspace.open(&path, change.text);
+                    self.publish(&path)?;
+                }
+            }
+            notification::DidCloseTextDocument::METHOD => {
+                let params: lsp_types::DidCloseTextDocumentParams = params(notification.params)?;
+                if let Some(path) = path_of(&params.text_document.uri) {
+                    self.workspace.close(&path);
+                    self.publish(&path)?;
+                }
+            }
+            _ => ()
+        }
+        Ok(())
+    }
+
+    /// The program the document at `uri` is part of, checked as the editor
+    /// has it, along with the document's path
+    fn analysis(&mut self, uri: &Uri) -> Option<(Rc<Analysis>, PathBuf)> {
+        let path = normalize(&path_of(uri)?);
+        if !self.analyses.contains_key(&path) {
+            self.remember(&path, self.workspace.analyze(&path));
+        }
+        Some((self.analyses[&path].clone(), path))
+    }
+
+    /// The declarations matching `query` in the programs of every open
+    /// document, each once though programs may share files
+    fn workspace_symbols(&mut self, query: &str) -> Vec<lsp_types::WorkspaceSymbol> {
+        let mut found: Vec<lsp_types::WorkspaceSymbol> = Vec::new();
+        for path in self.workspace.documents() {
+            if !self.analyses.contains_key(&path) {
+                self.remember(&path, self.workspace.analyze(&path));
+            }
+            for symbol in self.analyses[&path].workspace_symbols(query) {
+                if !found.iter().any(|other| other.location == symbol.location) {
+                    found.push(symbol);
+                }
+            }
+        }
+        found
+    }
+
+    fn remember(&mut self, path: &Path, analysis: Analysis) {
+        let analysis = Rc::new(analysis);
+        if analysis.result.is_ok() {
+            self.checked.insert(path.to_path_buf(), analysis.clone());
+        }
+        self.analyses.insert(path.to_path_buf(), analysis);
+    }
+
+    /// What the name being written at `position` in the document at `path`
+    /// could be. It rarely parses while it's being written, so the program
+    /// is checked without the line the cursor is on, and failing that the
+    /// last check of it without errors is used
+    fn complete(&mut self, path: &Path, position: Position) -> Vec<CompletionItem> {
+        let path = normalize(path);
+        let text = match self.workspace.text(&path) {
+            Some(text) => text,
+            None => return Vec::new()
+        };
+        let mut document = SourceMap::new();
+        let id = document.add(&path.to_string_lossy(), &text);
+        let offset = offset(document.get(id).expect("the document was just added"), position);
+
+        let mut repaired = self.workspace.clone();
+        repaired.open(&path, blank_line(&text, offset));
+        let analysis = repaired.analyze(&path);
+        if analysis.result.is_ok() {
+            return analysis.completions(&path, &text, offset);
+        }
+        match self.analysis(&uri_of(&path)) {
+            Some((analysis, _)) if analysis.result.is_ok() => analysis.completions(&path, &text, offset),
+            _ => self.checked.get(&path).map_or_else(Vec::new, |checked| checked.completions(&path, &text, offset))
+        }
+    }
+
+    /// Checks the program `path` is part of, and sends the diagnostics for
+    /// each of its files. Every other check is out of date once a document
+    /// changes
+    fn publish(&mut self, path: &Path) -> Result<(), String> {
+        self.analyses.clear();
+        let analysis = self.workspace.analyze(path);
+        for (path, diagnostics) in analysis.diagnostics() {
+            let params = lsp_types::PublishDiagnosticsParams::new(uri_of(&path), diagnostics, None);
+            let notification = Notification::new(notification::PublishDiagnostics::METHOD.to_string(), params);
+            self.send(Message::Notification(notification))?;
+        }
+        self.remember(&normalize(path), analysis);
+        Ok(())
+    }
+
+    fn send(&self, message: Message) -> Result<(), String> {
+        self.connection.sender.send(message).map_err(|err| err.to_string())
+    }
+}
+
+/// `text` with the line `offset` is on blanked out, keeping the offsets of
+/// everything else as they were
+fn blank_line(text: &str, offset: usize) -> String {
+    let start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+    format!("{}{}{}", &text[..start], " ".repeat(end - start), &text[end..])
+}
+
+fn params<P: serde::de::DeserializeOwned>(params: serde_json::Value) -> Result<P, String> {
+    serde_json::from_value(params).map_err(|err| err.to_string())
+}
@@ -1,197 +1,920 @@
-#[macro_use]
-extern crate pest_derive;
-
-#[macro_use]
-extern crate lazy_static;
-
 use std::fs;
 use std::collections::HashMap;
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
 
-mod parser;
-mod error;
-mod name_analysis;
-mod typecheck;
-mod codegen;
+use spruce::{compile_with_lints, trace};
+use spruce::{parser, error, error_codes, name_analysis, typecheck, fmt, source, interface, manifest, embed, eval, debugger, profile, coverage, heap, vm, repl, lsp, outline, doc, testing, emit, graph, watch, anf, dce, fold, inline, mono, tailcall, native, cgen, jsgen, wasm};
+#[cfg(feature = "jit")]
+use spruce::jit;
+
+mod cli;
 
 /// Compilation takes place in four phases: Parsing, Name Analysis, Type
-/// Checking, and Code Generation. Parsing and Name Analysis both emit their
-/// own IR, Type Checking simply emits a mapping from symbols to types, and
-/// Code Generation writes the compiled javascript to a file
+/// Checking, and Code Generation. The first three each emit their own IR,
+/// with Type Checking also emitting a mapping from symbols to types, and
+/// Code Generation writes the compiled program out. Each subcommand runs as
+/// much of this as it needs
 fn main() {
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let unparsed_file = fs::read_to_string("samples/lists.sp").expect("cannot read file");
-    let files = vec![(prelude.as_str(), String::from("prelude")), (unparsed_file.as_str(), String::from("main"))];
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (command, rest) = match args.split_first() {
+        Some((command, rest)) => (command.as_str(), rest),
+        None => cli::usage_error(&cli::usage())
+    };
+    match command {
+        "check" => check(rest),
+        "run" => run(rest),
+        "build" => build(rest),
+        "repl" => repl(rest),
+        "explain" => explain(rest),
+        "fmt" => format(rest),
+        "lsp" => language_server(rest),
+        "doc" => document(rest),
+        "graph" => graph(rest),
+        "test" => test_program(rest),
+        "help" | "--help" | "-h" => print!("{}", cli::usage()),
+        _ => cli::usage_error(&format!("unknown command '{}'\n\n{}", command, cli::usage()))
+    }
+}
+
+/// Explains an error code, as in `spruce explain E0001`
+fn explain(args: &[String]) {
+    match args.first().and_then(|code| code.parse::<error_codes::ErrorCode>().ok()) {
+        Some(code) if args.len() == 1 => print!("{}", code.explain()),
+        _ => cli::usage_error(&format!("usage: spruce explain <code>, where code is one of E0001 to {}", error_codes::ALL_CODES.last().unwrap().as_str()))
+    }
+}
+
+/// Checks a program without running it, as in `spruce check [--fix]
+/// [--emit-interface] [--emit-js] [--emit-symbols] [--watch] [--seed=<n>] [files...]`. In a package,
+/// every source file of it and its dependencies is checked, along with any
+/// files given, which otherwise are the program, each a module the others
+/// can import. `--fix` writes the fixes that don't need a person to look at
+/// them back to their files, `--emit-interface` writes an interface next to
+/// each source file, for compiling against later without checking it again,
+/// `--emit-js` writes the program to out.mjs as `spruce build --target=js`
+/// would, without optimizing it, along with the runtime it imports, and
+/// `--emit-symbols` writes an outline of what each file declares to
+/// symbols.json. `--emit=<artifact>`, which can be given more than once,
+/// writes what a phase of the compiler made of the program to stdout, one of
+/// tokens, ast, named-ast, typed-ast, ir or env. `--watch` checks the
+/// program again whenever its files change, instead of doing any of those
+fn check(args: &[String]) {
+    let usage = "usage: spruce check [--fix] [--emit-interface] [--emit-js] [--emit-symbols] [--emit=tokens|ast|named-ast|typed-ast|ir|env] [--watch] [--seed=<n>] [--color=always|never|auto] [--verbose <phase>] [-A/-W/-D <lint>] [files...]";
+    let mut common = cli::Common::default();
+    let mut fix = false;
+    let mut emit_interfaces = false;
+    let mut emit_js = false;
+    let mut emit_symbols = false;
+    let mut watch = false;
+    let mut artifacts = Vec::new();
+    let mut seed = None;
+    let mut module_paths = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match common.parse(arg, &mut rest) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(message) => cli::usage_error(&message)
+        }
+        match arg.as_str() {
+            "--fix" => fix = true,
+            "--emit-interface" => emit_interfaces = true,
+            "--emit-js" => emit_js = true,
+            "--emit-symbols" => emit_symbols = true,
+            "--watch" => watch = true,
+            arg if arg.starts_with("--emit=") => {
+                let name = &arg["--emit=".len()..];
+                artifacts.push(name.parse::<emit::Artifact>().unwrap_or_else(|_| cli::usage_error(&format!("unknown artifact '{}'\n{}", name, usage))));
+            }
+            // fixes the program's random numbers, for runs that can be repeated
+            arg if arg.starts_with("--seed=") => seed = Some(cli::number("--seed", &arg["--seed=".len()..])),
+            arg if arg.ends_with(".sp") || interface::is_interface(arg) => module_paths.push(arg.to_string()),
+            _ => cli::usage_error(&format!("unrecognized argument '{}'\n{}", arg, usage))
+        }
+    }
+    if watch {
+        if fix || emit_interfaces || emit_js || emit_symbols || !artifacts.is_empty() {
+            cli::usage_error(&format!("--watch only reports what's wrong, so it can't be given with --fix or --emit flags\n{}", usage));
+        }
+        if module_paths.is_empty() && !Path::new(manifest::FILE_NAME).is_file() {
+            cli::usage_error(&format!("there's no {} here, so give the files to check\n{}", manifest::FILE_NAME, usage));
+        }
+        watch_program(&module_paths, &mut common);
+    }
+    let use_color = common.use_color();
+    let (sources, paths) = read_program(&module_paths, "check", usage);
+    let mut files: Vec<source::FileId> = paths.keys().copied().collect();
+    files.sort();
+
+    // what only needs the program to parse is written out even if it
+    // doesn't check, for looking into why
+    if artifacts.iter().any(|artifact| artifact.parsed()) {
+        let parsed = parser::parse(&sources);
+        for artifact in artifacts.iter().filter(|artifact| artifact.parsed()) {
+            match (artifact, &parsed) {
+                (emit::Artifact::Tokens, _) => match emit::tokens(&sources, &files) {
+                    Ok(tokens) => print!("{}", tokens),
+                    Err(e) => eprintln!("{}", e.render(&sources, use_color))
+                },
+                (_, Ok(prog)) => print!("{}", emit::ast(prog, &files)),
+                (_, Err(_)) => ()
+            }
+        }
+    }
 
-    let (analyzed_prog, environment) = match compile(files.clone()){
+    let result = compile_with_lints(&sources, &mut common.lints);
+    for warning in &common.lints.warnings {
+        eprintln!("{}", warning.render(&sources, use_color));
+    }
+
+    let (analyzed_prog, typed_prog, environment) = match result {
         Ok(r) => r,
-        Err(e) => {
-            println!("{}", e.as_str(&files));
-            return;
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("{}", e.render(&sources, use_color));
+            }
+
+            // with --fix, edits that don't need a person to look at them are
+            // written back to their files
+            if fix {
+                for (id, source) in sources.files() {
+                    let fixes: Vec<&error::Suggestion> = errors.iter().flat_map(|e| e.suggestions.iter()).filter(|suggestion| {
+                        suggestion.machine_applicable && suggestion.info.file == id
+                    }).collect();
+
+                    if let (false, Some(path)) = (fixes.is_empty(), paths.get(&id)) {
+                        let fixed = error::apply_suggestions(&source.text, fixes);
+                        fs::write(path, fixed).expect("failed to write fixes");
+                        println!("applied fixes to {}", path.display());
+                    }
+                }
+            }
+            cli::fail();
         }
     };
 
-    let mut out_file = fs::File::create("out.js").expect("failed to create file");
-    codegen::gen_prog(&mut out_file, &analyzed_prog, &environment);
-}
+    if emit_interfaces {
+        for module in analyzed_prog.modules.iter().filter(|module| !module.interface) {
+            if let Some(path) = paths.get(&module.file) {
+                let path = path.with_extension(interface::EXTENSION);
+                fs::write(&path, interface::emit(&analyzed_prog, &environment, module)).expect("failed to write interface");
+            }
+        }
+    }
 
-pub fn compile(files: Vec<(&str, String)>) -> Result<(name_analysis::Prog, typecheck::Environment), error::SpruceErr> {
-    let prog = parser::parse(files.clone())?;
-    println!("{:#?}", prog);
+    if emit_js {
+        let generated = anf::lower(&analyzed_prog, &typed_prog, "main").and_then(|program| jsgen::generate_with_seed(&analyzed_prog, &program, &sources, seed));
+        match generated {
+            Ok(module) => {
+                fs::write("out.mjs", module).expect("failed to write out.mjs");
+                fs::write(jsgen::RUNTIME_FILE, jsgen::RUNTIME).expect("failed to write the js runtime");
+            }
+            Err(e) => {
+                eprintln!("{}", e.render(&sources, use_color));
+                cli::fail();
+            }
+        }
+    }
 
-    let analyzed_prog = name_analysis::name_analysis(prog)?;
-    println!("{:#?}", analyzed_prog);
+    // the prelude isn't part of the outline, as it's no file of the program's
+    if emit_symbols {
+        let parsed = parser::parse(&sources).ok().expect("the program parsed when it was checked");
+        let symbols: Vec<outline::Symbol> = outline::outline(&parsed, &sources, Some((&analyzed_prog, &environment))).into_iter()
+            .filter(|symbol| paths.contains_key(&symbol.info.file))
+            .collect();
+        let json = serde_json::to_string_pretty(&outline::to_json(&symbols, &sources)).expect("the outline is JSON");
+        fs::write("symbols.json", json + "\n").expect("failed to write symbols.json");
+    }
 
-    let environment = typecheck::check_prog(&analyzed_prog)?;
-    println!("{}", environment.as_str(&analyzed_prog));
+    for artifact in &artifacts {
+        match artifact {
+            emit::Artifact::Tokens | emit::Artifact::Ast => (),
+            emit::Artifact::NamedAst => print!("{}", emit::named_ast(&analyzed_prog, &files)),
+            emit::Artifact::TypedAst => print!("{}", emit::typed_ast(&analyzed_prog, &typed_prog, &environment, &sources, &files)),
+            emit::Artifact::Env => print!("{}", emit::env(&analyzed_prog, &environment)),
+            emit::Artifact::Ir => ()
+        }
+    }
 
-    Ok((analyzed_prog, environment))
+    // the IR is made by changing the program, so it comes last
+    if artifacts.contains(&emit::Artifact::Ir) {
+        match emit::ir(analyzed_prog, typed_prog, &environment) {
+            Ok(ir) => print!("{}", ir),
+            Err(e) => {
+                eprintln!("{}", e.render(&sources, use_color));
+                cli::fail();
+            }
+        }
+    }
 }
 
-#[test]
-fn test_prelude() {
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let files = vec![(prelude.as_str(), String::from("prelude"))];
-    let res = compile(files);
-    assert_eq!(res.is_ok(), true);
+/// The prelude and the files of a program, with where each file other than
+/// the prelude is on disk. In a package, they're every source file of it and
+/// its dependencies, along with `module_paths`, which otherwise are the
+/// program. What's read is for the command `verb`, whose usage is `usage`
+fn read_program(module_paths: &[String], verb: &str, usage: &str) -> (source::SourceMap, HashMap<source::FileId, PathBuf>) {
+    if module_paths.is_empty() && !Path::new(manifest::FILE_NAME).is_file() {
+        cli::usage_error(&format!("there's no {} here, so give the files to {}\n{}", manifest::FILE_NAME, verb, usage));
+    }
+    load_program(module_paths).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        cli::fail();
+    })
 }
 
-#[test]
-fn test_scope() {
-    let pass_prog = "
-x = 0
-f() {
-    x
+/// Reads a program as `read_program` does, giving what went wrong rather
+/// than ending the command if a file can't be read
+fn load_program(module_paths: &[String]) -> Result<(source::SourceMap, HashMap<source::FileId, PathBuf>), String> {
+    let mut sources = source::SourceMap::new();
+    sources.add(name_analysis::PRELUDE_FILE, name_analysis::PRELUDE);
+    let mut paths: HashMap<source::FileId, PathBuf> = HashMap::new();
+
+    if Path::new(manifest::FILE_NAME).is_file() {
+        let files = manifest::plan(Path::new(".")).and_then(|plan| plan.read_files()).map_err(|err| err.to_string())?;
+        for (text, path) in files {
+            let name = path.strip_prefix(".").unwrap_or(&path).to_string_lossy().into_owned();
+            paths.insert(sources.add(&name, &text), path);
+        }
+    }
+    for path in module_paths {
+        let text = fs::read_to_string(path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+        paths.insert(sources.add(path, &text), PathBuf::from(path));
+    }
+    Ok((sources, paths))
 }
 
-g() {
-    y = True
-    case y {
-        True -> y
-        False -> y
+/// Checks the program again whenever one of its files changes, clearing the
+/// screen before showing what was found, until the command is stopped
+fn watch_program(module_paths: &[String], common: &mut cli::Common) -> ! {
+    let use_color = common.use_color();
+    let manifest = PathBuf::from(manifest::FILE_NAME);
+    loop {
+        // the files are looked at before they're checked, so a change made
+        // while checking is caught. One that can't be read is reported like
+        // any other problem, as it's likely being saved or moved
+        let mut watched = match load_program(module_paths) {
+            Ok((sources, paths)) => {
+                let files: Vec<PathBuf> = paths.values().cloned().collect();
+                let watched = watch::Watched::new(&files, std::slice::from_ref(&manifest));
+                common.lints.warnings.clear();
+                common.lints.denied.clear();
+                let errors = compile_with_lints(&sources, &mut common.lints).err().unwrap_or_default();
+
+                if std::io::stdout().is_terminal() {
+                    print!("\x1b[2J\x1b[H");
+                }
+                for diagnostic in errors.iter().chain(common.lints.warnings.iter()) {
+                    println!("{}", diagnostic.render(&sources, use_color));
+                }
+                let count = |n: usize, what: &str| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" });
+                println!("{} and {}, watching {} for changes", count(errors.len(), "error"), count(common.lints.warnings.len(), "warning"), count(files.len(), "file"));
+                watched
+            }
+            Err(err) => {
+                println!("{}", err);
+                let files: Vec<PathBuf> = module_paths.iter().map(PathBuf::from).collect();
+                watch::Watched::new(&files, std::slice::from_ref(&manifest))
+            }
+        };
+        std::io::stdout().flush().expect("failed to write to stdout");
+        watched.wait();
     }
 }
-";
 
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
-    let res = compile(files);
-    assert_eq!(res.is_ok(), true);
-
-    let fail_prog = "
-f(b) {
-    case b {
-        True -> {
-            x = 1
-            x
+/// Writes documentation for a program, as in `spruce doc
+/// [--format=html|markdown] [--out=<dir>] [files...]`: a page for each of its
+/// modules and an index of them, in the directory `--out`, or doc if it isn't
+/// given. The program is read the way `spruce check` reads it
+fn document(args: &[String]) {
+    let usage = "usage: spruce doc [--format=html|markdown] [--out=<dir>] [--color=always|never|auto] [--verbose <phase>] [-A/-W/-D <lint>] [files...]";
+    let mut common = cli::Common::default();
+    let mut format = doc::Format::Html;
+    let mut out = PathBuf::from("doc");
+    let mut module_paths = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match common.parse(arg, &mut rest) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(message) => cli::usage_error(&message)
         }
-        False -> {
-            y = x
-            y
+        match arg.as_str() {
+            arg if arg.starts_with("--format=") => {
+                format = arg["--format=".len()..].parse::<doc::Format>().unwrap_or_else(|_| cli::usage_error(&format!("unknown format '{}'\n{}", &arg["--format=".len()..], usage)));
+            }
+            arg if arg.starts_with("--out=") => out = PathBuf::from(&arg["--out=".len()..]),
+            arg if arg.ends_with(".sp") || interface::is_interface(arg) => module_paths.push(arg.to_string()),
+            _ => cli::usage_error(&format!("unrecognized argument '{}'\n{}", arg, usage))
         }
     }
-}
-";
+    let use_color = common.use_color();
+    let (sources, _) = read_program(&module_paths, "document", usage);
 
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
-    let res = compile(files);
-    assert_eq!(res.is_ok(), false);
-}
+    let result = compile_with_lints(&sources, &mut common.lints);
+    for warning in &common.lints.warnings {
+        eprintln!("{}", warning.render(&sources, use_color));
+    }
+    let (analyzed_prog, _, environment) = result.unwrap_or_else(|errors| {
+        for e in &errors {
+            eprintln!("{}", e.render(&sources, use_color));
+        }
+        cli::fail();
+    });
 
-#[test]
-fn test_mut() {
-    let pass_prog = "
-mut x = 0
-f() {
-    x := 1
+    let written = fs::create_dir_all(&out).and_then(|_| {
+        for page in doc::document(&analyzed_prog, &environment, &sources, format) {
+            fs::write(out.join(&page.file), page.text)?;
+        }
+        Ok(())
+    });
+    if let Err(err) = written {
+        eprintln!("cannot write to {}: {}", out.display(), err);
+        cli::fail();
+    }
+    println!("wrote documentation to {}", out.display());
 }
 
-g() {
-    mut y = True
-    case y {
-        True -> {
-            y := False
+/// Runs a program's tests, as in `spruce test [--seed=<n>] [filters...]
+/// [files...]`, where only the tests whose names contain one of the filters
+/// are run if any are given. The program is read the way `spruce check`
+/// reads it, and the command fails if any test does
+fn test_program(args: &[String]) {
+    let usage = "usage: spruce test [--seed=<n>] [--coverage[=<lcov file>]] [--color=always|never|auto] [--verbose <phase>] [-A/-W/-D <lint>] [filters...] [files...]";
+    let mut common = cli::Common::default();
+    let mut seed = None;
+    let mut filters = Vec::new();
+    let mut module_paths = Vec::new();
+    let mut coverage = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match common.parse(arg, &mut rest) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(message) => cli::usage_error(&message)
         }
-        False -> {
-            y := True
+        match arg.as_str() {
+            arg if arg.starts_with("--seed=") => seed = Some(cli::number("--seed", &arg["--seed=".len()..])),
+            "--coverage" => coverage = Some(None),
+            arg if arg.starts_with("--coverage=") => coverage = Some(Some(&arg["--coverage=".len()..])),
+            arg if arg.starts_with("--") => cli::usage_error(&format!("unrecognized argument '{}'\n{}", arg, usage)),
+            arg if arg.ends_with(".sp") || interface::is_interface(arg) => module_paths.push(arg.to_string()),
+            filter => filters.push(filter.to_string())
         }
     }
+    let use_color = common.use_color();
+    let (sources, paths) = read_program(&module_paths, "test", usage);
 
-    y
+    let result = compile_with_lints(&sources, &mut common.lints);
+    for warning in &common.lints.warnings {
+        eprintln!("{}", warning.render(&sources, use_color));
+    }
+    let (analyzed_prog, typed_prog, _) = result.unwrap_or_else(|errors| {
+        for e in &errors {
+            eprintln!("{}", e.render(&sources, use_color));
+        }
+        cli::fail();
+    });
+
+    // tests recurse as deeply as programs do, so they get the same stack
+    // as `spruce run`
+    let mut covered = coverage::Coverage::new();
+    let runs = std::thread::scope(|scope| {
+        std::thread::Builder::new().stack_size(1 << 30).spawn_scoped(scope, || {
+            let observer: Option<&mut dyn eval::Observer> = if coverage.is_some() { Some(&mut covered) } else { None };
+            testing::run_tests(&analyzed_prog, &typed_prog, &sources, &filters, || eval::Runtime::new(Vec::new(), seed), observer)
+        }).expect("failed to start the interpreter").join().expect("the interpreter panicked")
+    });
+
+    for run in &runs {
+        let status = if run.failure.is_none() { "ok" } else { "FAILED" };
+        println!("test \"{}\" ... {}", run.name, status);
+    }
+    let failures: Vec<&error::SpruceErr> = runs.iter().filter_map(|run| run.failure.as_ref()).collect();
+    for failure in &failures {
+        eprintln!("{}", failure.render(&sources, use_color));
+    }
+    println!("{} passed, {} failed", runs.len() - failures.len(), failures.len());
+
+    // the files are shown annotated unless the coverage is written for
+    // another tool to show
+    if let Some(lcov) = coverage {
+        let mut files: Vec<source::FileId> = paths.keys().copied().collect();
+        files.sort();
+        let files = covered.files(&analyzed_prog, &typed_prog, &sources, &files);
+        match lcov {
+            Some(lcov) => fs::write(lcov, coverage::lcov(&files, &sources)).unwrap_or_else(|err| {
+                eprintln!("cannot write {}: {}", lcov, err);
+                cli::fail();
+            }),
+            None => for file in &files {
+                println!("\n{}:\n{}", sources.name(file.file), file.annotate(&sources));
+            }
+        }
+        for file in &files {
+            println!("{}", file.summary(&sources));
+        }
+    }
+    if !failures.is_empty() {
+        cli::fail();
+    }
 }
-";
-
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
-    let res = compile(files);
-    assert_eq!(res.is_ok(), true);
 
-    let fail_prog = "
-f() {
-    x = 1
-    x := 2
+/// Draws a program's calls and types, as in `spruce graph [--format=dot]
+/// [files...]`, writing Graphviz's dot language to stdout. Only names need
+/// resolving, so a program with type errors can still be drawn. The program
+/// is read the way `spruce check` reads it
+fn graph(args: &[String]) {
+    let usage = "usage: spruce graph [--format=dot] [--color=always|never|auto] [--verbose <phase>] [-A/-W/-D <lint>] [files...]";
+    let mut common = cli::Common::default();
+    let mut module_paths = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match common.parse(arg, &mut rest) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(message) => cli::usage_error(&message)
+        }
+        match arg.as_str() {
+            // dot is the only format so far
+            "--format=dot" => (),
+            arg if arg.starts_with("--format=") => cli::usage_error(&format!("unknown format '{}'\n{}", &arg["--format=".len()..], usage)),
+            arg if arg.ends_with(".sp") || interface::is_interface(arg) => module_paths.push(arg.to_string()),
+            _ => cli::usage_error(&format!("unrecognized argument '{}'\n{}", arg, usage))
+        }
+    }
+    let use_color = common.use_color();
+    let (sources, paths) = read_program(&module_paths, "draw", usage);
+    let mut files: Vec<source::FileId> = paths.keys().copied().collect();
+    files.sort();
+
+    let analyzed = parser::parse(&sources)
+        .and_then(|prog| name_analysis::name_analysis(prog, &sources, &mut common.lints).map_err(|e| vec![e]));
+    for warning in &common.lints.warnings {
+        eprintln!("{}", warning.render(&sources, use_color));
+    }
+    match analyzed {
+        Ok(prog) => print!("{}", graph::dot(&prog, &files)),
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("{}", e.render(&sources, use_color));
+            }
+            cli::fail();
+        }
+    }
 }
-";
 
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
-    let res = compile(files);
-    assert_eq!(res.is_ok(), false);
-}
+/// Formats source files in place, as in `spruce fmt [--check] [files...]`,
+/// or the package's own files when none are given. `--check` changes
+/// nothing, and fails if any file isn't formatted
+fn format(args: &[String]) {
+    let usage = "usage: spruce fmt [--check] [--color=always|never|auto] [files...]";
+    let mut common = cli::Common::default();
+    let mut check = false;
+    let mut paths = Vec::new();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match common.parse(arg, &mut rest) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(message) => cli::usage_error(&message)
+        }
+        match arg.as_str() {
+            "--check" => check = true,
+            path if path.ends_with(".sp") => paths.push(PathBuf::from(path)),
+            _ => cli::usage_error(&format!("unrecognized argument '{}'\n{}", arg, usage))
+        }
+    }
+    if paths.is_empty() {
+        if !Path::new(manifest::FILE_NAME).is_file() {
+            cli::usage_error(&format!("there's no {} here, so give the files to format\n{}", manifest::FILE_NAME, usage));
+        }
+        let plan = manifest::plan(Path::new(".")).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            cli::fail();
+        });
+        paths = plan.packages.last().map_or(Vec::new(), |package| package.files.clone());
+    }
 
-#[test]
-fn test_type() {
-    let pass_prog = "
-type FooBar(a, b) {
-    Foo(a)
-    Bar(b)
+    let mut failed = false;
+    for path in &paths {
+        let text = fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("cannot read {}: {}", path.display(), err);
+            cli::fail();
+        });
+        let mut sources = source::SourceMap::new();
+        sources.add(&path.to_string_lossy(), &text);
+        let formatted = match fmt::format_file(&sources) {
+            Ok(formatted) => formatted,
+            Err(errors) => {
+                for e in &errors {
+                    eprintln!("{}", e.render(&sources, common.use_color()));
+                }
+                failed = true;
+                continue;
+            }
+        };
+        if formatted == text {
+            continue;
+        }
+        if check {
+            println!("{} isn't formatted", path.display());
+            failed = true;
+        }
+        else {
+            fs::write(path, formatted).unwrap_or_else(|err| {
+                eprintln!("cannot write {}: {}", path.display(), err);
+                cli::fail();
+            });
+            println!("formatted {}", path.display());
+        }
+    }
+    if failed {
+        cli::fail();
+    }
 }
 
-func(fb) {
-    case fb {
-        Foo(v) -> True
-        Bar(b) -> b
+/// Runs a program instead of compiling it, as in `spruce run [--seed=<n>]
+/// [--engine=tree|vm|jit] file.sp [--] [args...]`, where the program gets the
+/// arguments after its file, less a `--` that separates them from it. The
+/// tree-walker runs it unless another engine is asked for, and the JIT is
+/// only there when built with the jit feature.
+/// `--heap-limit=<n>` fails the run once more than n values are alive at
+/// once, and `--heap-stress` reports how the heap was used and fails if
+/// anything outlives the program
+fn run(args: &[String]) {
+    let usage = "usage: spruce run [--seed=<n>] [--engine=tree|vm|jit] [--heap-limit=<n>] [--heap-stress] [--debug] [--break=<function>|<file>:<line>]... [--profile[=<stacks file>]] [--color=always|never|auto] [--verbose <phase>] [-A/-W/-D <lint>] <file.sp> [--] [args...]";
+    let mut common = cli::Common::default();
+    let mut seed = None;
+    let mut engine = "tree";
+    let mut heap_limit = None;
+    let mut heap_stress = false;
+    let mut debug = false;
+    let mut breaks = Vec::new();
+    let mut profile = None;
+    let mut rest = args.iter();
+    let path = loop {
+        let arg = match rest.next() {
+            Some(arg) => arg,
+            None => cli::usage_error(usage)
+        };
+        match common.parse(arg, &mut rest) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(message) => cli::usage_error(&message)
+        }
+        match arg.as_str() {
+            arg if arg.starts_with("--seed=") => seed = Some(cli::number("--seed", &arg["--seed=".len()..])),
+            arg if arg.starts_with("--heap-limit=") => heap_limit = Some(cli::number("--heap-limit", &arg["--heap-limit=".len()..])),
+            "--heap-stress" => heap_stress = true,
+            "--debug" => debug = true,
+            arg if arg.starts_with("--break=") => breaks.push(&arg["--break=".len()..]),
+            "--profile" => profile = Some(None),
+            arg if arg.starts_with("--profile=") => profile = Some(Some(&arg["--profile=".len()..])),
+            arg if arg.starts_with("--engine=") => match &arg["--engine=".len()..] {
+                name @ ("tree" | "vm" | "jit") => engine = name,
+                name => cli::usage_error(&format!("--engine expects tree, vm or jit, not '{}'", name))
+            },
+            path if path.ends_with(".sp") => break path,
+            _ => cli::usage_error(usage)
+        }
+    };
+    let mut rest = rest.peekable();
+    rest.next_if(|arg| *arg == "--");
+    let prog_args: Vec<String> = rest.cloned().collect();
+    if cfg!(not(feature = "jit")) && engine == "jit" {
+        cli::usage_error("--engine=jit needs spruce to be built with the jit feature, as in cargo build --features jit");
+    }
+    if debug && engine != "tree" {
+        cli::usage_error("--debug needs the tree engine");
+    }
+    if !debug && !breaks.is_empty() {
+        cli::usage_error("--break needs --debug");
+    }
+    if profile.is_some() && (debug || engine == "jit") {
+        cli::usage_error("--profile needs the tree or vm engine, without --debug");
+    }
+    let use_color = common.use_color();
+    let (sources, analyzed_prog, typed_prog, environment) = compile_file(path, &mut common);
+
+    // deep recursion is how Spruce loops, so the interpreter gets a stack
+    // to match
+    let mut quit = false;
+    let mut profiler = profile::Profiler::new();
+    let (outcome, heap_report) = std::thread::scope(|scope| {
+        std::thread::Builder::new().stack_size(1 << 30).spawn_scoped(scope, || {
+            heap::configure(heap_limit, heap_stress);
+            let runtime = eval::Runtime::new(prog_args, seed);
+            let result = match engine {
+                "vm" if profile.is_some() => vm::run_profiled(&analyzed_prog, &typed_prog, "main", runtime, &mut profiler),
+                "vm" => vm::run_prog(&analyzed_prog, &typed_prog, "main", runtime),
+                #[cfg(feature = "jit")]
+                "jit" => jit::run_prog(&analyzed_prog, &typed_prog, &environment, "main", runtime),
+                // the debugger talks on stderr, leaving stdout to the program
+                _ if debug => {
+                    let stdin = std::io::stdin();
+                    let mut debugger = debugger::Debugger::new(&analyzed_prog, &environment, &sources, stdin.lock(), std::io::stderr());
+                    for spec in &breaks {
+                        if let Err(message) = debugger.add_breakpoint(spec) {
+                            cli::usage_error(&message);
+                        }
+                    }
+                    let result = match debugger.start() {
+                        true => eval::run_observed(&analyzed_prog, &typed_prog, "main", runtime, &mut debugger),
+                        false => Err(error::SpruceErr::new(String::from("the debugger stopped the program"), eval::no_info()))
+                    };
+                    quit = debugger.quit;
+                    result
+                }
+                _ if profile.is_some() => eval::run_observed(&analyzed_prog, &typed_prog, "main", runtime, &mut profiler),
+                _ => eval::run_prog(&analyzed_prog, &typed_prog, "main", runtime)
+            };
+            profiler.stop();
+            let outcome = result.map(|value| match value {
+                eval::Value::Unit => None,
+                value => Some(eval::show(&analyzed_prog, &value))
+            });
+            (outcome, heap::report())
+        }).expect("failed to start the interpreter").join().expect("the interpreter panicked")
+    });
+
+    match &outcome {
+        Ok(Some(output)) => println!("{}", output),
+        Ok(None) => (),
+        // quitting the debugger isn't an error in the program
+        Err(_) if quit => (),
+        Err(e) => eprintln!("{}", e.render(&sources, use_color))
+    }
+    if let Some(stacks) = profile {
+        eprint!("{}", profiler.report(&analyzed_prog));
+        if let Some(stacks) = stacks {
+            fs::write(stacks, profiler.collapsed(&analyzed_prog)).unwrap_or_else(|err| {
+                eprintln!("cannot write {}: {}", stacks, err);
+                cli::fail();
+            });
+        }
+    }
+    match heap_report {
+        Some(Ok(summary)) => eprintln!("{}", summary),
+        Some(Err(leak)) => {
+            eprintln!("{}", leak);
+            cli::fail();
+        }
+        None => ()
+    }
+    if outcome.is_err() {
+        cli::fail();
     }
 }
 
-main() {
-    fb = Foo(3)
-    res = func(fb)
+/// Builds a program for a target other than spruce's own javascript, as in
+/// `spruce build --target=native|c|js|wasm file.sp [-o <output>]`, where
+/// native gives an executable, c gives C source to build it with, js gives an
+/// ES module, with the runtime it imports written next to it, and wasm gives
+/// a WebAssembly module, with a host to run it written next to it. The output
+/// is named after the file unless given. `--opt-level=0` leaves out the
+/// optimizations the compiled targets get, inlining small functions, folding
+/// constants and removing dead code, which `--opt-level=1`, the default, runs.
+/// `--inline-threshold=<n>` sets how big a function can be and be inlined
+fn build(args: &[String]) {
+    let usage = || -> ! { cli::usage_error("usage: spruce build --target=native|c|js|wasm [--opt-level=0|1] [--inline-threshold=<n>] [--color=always|never|auto] [--verbose <phase>] [-A/-W/-D <lint>] <file.sp> [-o <output>]") };
+    let mut common = cli::Common::default();
+    let mut target = None;
+    let mut opt_level = 1;
+    let mut inline_threshold = inline::DEFAULT_THRESHOLD;
+    let mut output = None;
+    let mut path = None;
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match common.parse(arg, &mut rest) {
+            Ok(true) => continue,
+            Ok(false) => (),
+            Err(message) => cli::usage_error(&message)
+        }
+        match arg.as_str() {
+            "-o" => output = Some(rest.next().cloned().unwrap_or_else(|| usage())),
+            arg if arg.starts_with("--target=") => target = Some(&arg["--target=".len()..]),
+            arg if arg.starts_with("--opt-level=") => match &arg["--opt-level=".len()..] {
+                "0" => opt_level = 0,
+                "1" => opt_level = 1,
+                level => cli::usage_error(&format!("--opt-level expects 0 or 1, not '{}'", level))
+            },
+            arg if arg.starts_with("--inline-threshold=") => inline_threshold = cli::number("--inline-threshold", &arg["--inline-threshold=".len()..]),
+            arg if arg.ends_with(".sp") => path = Some(arg),
+            _ => usage()
+        }
+    }
+    let (target, path) = match (target, path) {
+        (Some(target @ ("native" | "c" | "js" | "wasm")), Some(path)) => (target, path),
+        (Some(target), _) if !["native", "c", "js", "wasm"].contains(&target) => {
+            cli::usage_error(&format!("--target expects native, c, js or wasm, not '{}'", target))
+        }
+        _ => usage()
+    };
+    let output = output.unwrap_or_else(|| match target {
+        "c" => format!("{}.c", path.trim_end_matches(".sp")),
+        "js" => format!("{}.mjs", path.trim_end_matches(".sp")),
+        "wasm" => format!("{}.wasm", path.trim_end_matches(".sp")),
+        _ => path.trim_end_matches(".sp").to_string()
+    });
+    let use_color = common.use_color();
+    let (sources, mut analyzed_prog, mut typed_prog, environment) = compile_file(path, &mut common);
+
+    let write = |path: &Path, contents: &[u8]| fs::write(path, contents).unwrap_or_else(|err| {
+        eprintln!("cannot write {}: {}", path.display(), err);
+        cli::fail();
+    });
+    // js values carry their types with them, so that target needs no copies
+    // of polymorphic functions, and its functions keep their own names
+    if target != "js" {
+        if let Err(e) = mono::compile(&mut analyzed_prog, &mut typed_prog, &environment, "main") {
+            eprintln!("{}", e.render(&sources, use_color));
+            cli::fail();
+        }
+    }
+    let mut program = match anf::lower(&analyzed_prog, &typed_prog, "main") {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("{}", e.render(&sources, use_color));
+            cli::fail();
+        }
+    };
+    if opt_level >= 1 {
+        inline::compile(&mut program, inline_threshold);
+        tailcall::compile(&mut program);
+        fold::compile(&analyzed_prog, &mut program);
+        dce::compile(&analyzed_prog, &mut program);
+    }
+    trace!(trace::Phase::Codegen, "lowered {} functions to A-normal form:\n{}", program.functions.len(), program.dump(&analyzed_prog));
+    if target == "wasm" {
+        match wasm::generate(&analyzed_prog, &program, &sources) {
+            Ok(module) => {
+                write(Path::new(&output), &module);
+                write(&Path::new(&output).with_file_name(wasm::HOST_FILE), wasm::HOST.as_bytes());
+            }
+            Err(e) => {
+                eprintln!("{}", e.render(&sources, use_color));
+                cli::fail();
+            }
+        }
+        return;
+    }
+    if target == "js" {
+        match jsgen::generate(&analyzed_prog, &program, &sources) {
+            Ok(source) => {
+                write(Path::new(&output), source.as_bytes());
+                write(&Path::new(&output).with_file_name(jsgen::RUNTIME_FILE), jsgen::RUNTIME.as_bytes());
+            }
+            Err(e) => {
+                eprintln!("{}", e.render(&sources, use_color));
+                cli::fail();
+            }
+        }
+        return;
+    }
+    if target == "c" {
+        match cgen::generate(&analyzed_prog, &program, &sources) {
+            Ok(source) => write(Path::new(&output), source.as_bytes()),
+            Err(e) => {
+                eprintln!("{}", e.render(&sources, use_color));
+                cli::fail();
+            }
+        }
+        return;
+    }
 
-    fb2 = Bar(True)
-    res2 = func(fb2)
+    let module = match native::lower(&analyzed_prog, &program, &sources) {
+        Ok(module) => module,
+        Err(e) => {
+            eprintln!("{}", e.render(&sources, use_color));
+            cli::fail();
+        }
+    };
+    trace!(trace::Phase::Codegen, "lowered {} functions for the native target", module.functions.len());
+    if let Err(message) = native::build(&module, Path::new(&output)) {
+        eprintln!("{}", message);
+        cli::fail();
+    }
 }
-";
 
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let files = vec![(prelude.as_str(), String::from("prelude")), (pass_prog, String::from("Main"))];
-    let res = compile(files);
-    assert_eq!(res.is_ok(), true);
+/// Reads definitions and expressions, as in `spruce repl`, showing the type
+/// of each definition and the value of each expression along with its type.
+/// The session ends at `:quit` or the end of input
+fn repl(args: &[String]) {
+    use rustyline::error::ReadlineError;
+
+    let mut common = cli::Common::default();
+    let mut rest = args.iter();
+    while let Some(arg) = rest.next() {
+        match common.parse(arg, &mut rest) {
+            Ok(true) => (),
+            Ok(false) => cli::usage_error("usage: spruce repl [--color=always|never|auto] [--verbose <phase>]"),
+            Err(message) => cli::usage_error(&message)
+        }
+    }
+    let use_color = common.use_color();
+
+    // the history is kept across sessions, in the home directory
+    let history = std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".spruce_history"));
+
+    // expressions are run with the interpreter, which gets the stack it
+    // would have in a run
+    std::thread::Builder::new().stack_size(1 << 30).spawn(move || {
+        let mut session = repl::Session::new(embed::Compiler::new(), use_color);
+        let mut editor: rustyline::Editor<repl::Helper, rustyline::history::DefaultHistory> = rustyline::Editor::new().unwrap_or_else(|err| {
+            eprintln!("cannot start the line editor: {}", err);
+            cli::fail();
+        });
+        editor.set_helper(Some(repl::Helper::new(session.names())));
+        if let Some(path) = &history {
+            editor.load_history(path).ok();
+        }
+
+        'session: loop {
+            // an entry with a bracket left open goes on over the lines after
+            // it, until it's closed or a blank line ends it
+            let mut entry = String::new();
+            loop {
+                match editor.readline(if entry.is_empty() { "> " } else { "| " }) {
+                    // interrupting drops the entry being written
+                    Err(ReadlineError::Interrupted) => continue 'session,
+                    Err(_) if entry.is_empty() => break 'session,
+                    Err(_) => break,
+                    Ok(line) if !entry.is_empty() && line.trim().is_empty() => break,
+                    Ok(line) => {
+                        entry.push_str(&line);
+                        entry.push('\n');
+                    }
+                }
+                if !repl::incomplete(&entry) {
+                    break;
+                }
+            }
+            if !entry.trim().is_empty() {
+                editor.add_history_entry(entry.trim_end()).ok();
+            }
+            if entry.trim() == ":quit" {
+                break;
+            }
+            match session.enter(&entry) {
+                Ok(shown) if shown.is_empty() => (),
+                Ok(shown) => println!("{}", shown),
+                Err(errors) => eprintln!("{}", errors)
+            }
+            if let Some(helper) = editor.helper_mut() {
+                helper.names = session.names();
+            }
+        }
 
-    let fail_prog = "
-type FooBar(a, b) {
-    Foo(a)
-    Bar(b)
+        if let Some(path) = &history {
+            editor.save_history(path).ok();
+        }
+    }).expect("failed to start the session").join().expect("the session panicked");
 }
 
-func(fb) {
-    case fb {
-        Foo(v) -> True
-        Bar(b) -> b
+/// Serves an editor over stdin and stdout, as in `spruce lsp`. Editors that
+/// pass `--stdio` to say so are understood
+fn language_server(args: &[String]) {
+    if args.iter().any(|arg| arg != "--stdio") {
+        cli::usage_error("usage: spruce lsp [--stdio]");
     }
-}
 
-main() {
-    fb = Foo(3)
-    res = func(fb)
+    // programs are checked with the stack a compile would have
+    let served = std::thread::Builder::new().stack_size(1 << 30).spawn(|| {
+        let (connection, io_threads) = lsp_server::Connection::stdio();
+        lsp::serve(connection)?;
+        io_threads.join().map_err(|err| err.to_string())
+    }).expect("failed to start the server").join().expect("the server panicked");
 
-    fb2 = Bar(2)
-    res2 = func(fb2)
+    if let Err(message) = served {
+        eprintln!("{}", message);
+        cli::fail();
+    }
 }
-";
 
-    let prelude = fs::read_to_string("src/prelude.sp").expect("cannot read prelude");
-    let files = vec![(prelude.as_str(), String::from("prelude")), (fail_prog, String::from("Main"))];
-    let res = compile(files);
-    assert_eq!(res.is_ok(), false);
+/// Compiles the file at `path` along with the prelude, and along with the
+/// package's files when run in one so that it can import their modules, with
+/// the lints and colors of `common`. Any warnings are printed, and it exits
+/// if the program has errors. Files are read, and named, as `load_program`
+/// reads them
+fn compile_file(path: &str, common: &mut cli::Common) -> (source::SourceMap, name_analysis::Prog, typecheck::Prog, typecheck::Environment) {
+    let (sources, _) = load_program(&[path.to_string()]).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        cli::fail();
+    });
+
+    let use_color = common.use_color();
+    let result = compile_with_lints(&sources, &mut common.lints);
+    for warning in &common.lints.warnings {
+        eprintln!("{}", warning.render(&sources, use_color));
+    }
+    match result {
+        Ok((analyzed_prog, typed_prog, environment)) => (sources, analyzed_prog, typed_prog, environment),
+        Err(errors) => {
+            for e in &errors {
+                eprintln!("{}", e.render(&sources, use_color));
+            }
+            cli::fail();
+        }
+    }
 }
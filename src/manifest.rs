@@ -0,0 +1,387 @@
+/*
+A package is a directory with a `spruce.toml` manifest, which names the
+package, says where its source files are, and lists the packages it depends
+on:
+
+    [package]
+    name = "app"
+    sources = ["src"]
+
+    [dependencies]
+    lists = { path = "../lists" }
+    json = { git = "https://example.com/json.git", rev = "v1.0" }
+
+Building a package builds its dependencies first, so the modules of every
+package it depends on can be imported. Git dependencies are cloned into the
+`.spruce` directory of the package being built, into a directory of their
+own for each url and rev, so changing either fetches the dependency again.
+*/
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub const FILE_NAME: &str = "spruce.toml";
+
+// where git dependencies are checked out, relative to the package being built
+const GIT_DIR: &str = ".spruce/git";
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Source {
+    Path(PathBuf),
+    Git { url: String, rev: Option<String> }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Dependency {
+    pub name: String,
+    pub source: Source
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Manifest {
+    pub name: String,
+    // directories searched for source files, relative to the manifest
+    pub roots: Vec<PathBuf>,
+    pub dependencies: Vec<Dependency>
+}
+
+/// A manifest that couldn't be read, or a package that couldn't be found
+#[derive(Debug, PartialEq, Clone)]
+pub struct ManifestError {
+    pub path: PathBuf,
+    pub message: String
+}
+
+impl ManifestError {
+    fn new(path: &Path, message: String) -> Self {
+        ManifestError { path: path.to_path_buf(), message: message }
+    }
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "error: {}\n --> {}", self.message, self.path.display())
+    }
+}
+
+impl std::error::Error for ManifestError {}
+
+impl Manifest {
+    /// Reads the manifest in the package directory `dir`
+    pub fn load(dir: &Path) -> Result<Manifest, ManifestError> {
+        let path = dir.join(FILE_NAME);
+        match fs::read_to_string(&path) {
+            Ok(text) => Manifest::parse(&text, &path),
+            Err(err) => Err(ManifestError::new(&path, format!("cannot read manifest: {}", err)))
+        }
+    }
+
+    /// Parses the text of a manifest, where `path` is only used for errors
+    pub fn parse(text: &str, path: &Path) -> Result<Manifest, ManifestError> {
+        let error = |message: String| ManifestError::new(path, message);
+        let value: toml::Value = text.parse().map_err(|err| error(format!("invalid manifest: {}", err)))?;
+
+        let package = value.get("package").and_then(toml::Value::as_table).ok_or_else(|| error(String::from("missing [package] table")))?;
+        let name = match package.get("name").and_then(toml::Value::as_str) {
+            Some(name) => String::from(name),
+            None => return Err(error(String::from("the package needs a name, as in `name = \"app\"`")))
+        };
+
+        let roots = match package.get("sources") {
+            Some(sources) => {
+                let roots = sources.as_array().map(|roots| roots.iter().map(toml::Value::as_str).collect::<Option<Vec<&str>>>());
+                match roots {
+                    Some(Some(roots)) => roots.into_iter().map(PathBuf::from).collect(),
+                    _ => return Err(error(String::from("`sources` should be a list of directories")))
+                }
+            }
+            None => vec![PathBuf::from("src")]
+        };
+
+        let mut dependencies = Vec::new();
+        if let Some(deps) = value.get("dependencies") {
+            let deps = deps.as_table().ok_or_else(|| error(String::from("[dependencies] should be a table")))?;
+            for (dep_name, dep) in deps {
+                let source = match (dep.get("path").and_then(toml::Value::as_str), dep.get("git").and_then(toml::Value::as_str)) {
+                    (Some(dep_path), None) => Source::Path(PathBuf::from(dep_path)),
+                    (None, Some(url)) => Source::Git {
+                        url: String::from(url),
+                        rev: dep.get("rev").and_then(toml::Value::as_str).map(String::from)
+                    },
+                    _ => return Err(error(format!("dependency '{}' needs either a `path` or a `git` url", dep_name)))
+                };
+                dependencies.push(Dependency { name: dep_name.clone(), source: source });
+            }
+        }
+
+        Ok(Manifest { name: name, roots: roots, dependencies: dependencies })
+    }
+}
+
+/// A package ready to be compiled, with the source files found in its roots
+#[derive(Debug, PartialEq, Clone)]
+pub struct Package {
+    pub name: String,
+    pub dir: PathBuf,
+    pub files: Vec<PathBuf>
+}
+
+/// The packages to compile, each coming after the packages it depends on,
+/// with the package the plan was made for last
+#[derive(Debug, PartialEq)]
+pub struct BuildPlan {
+    pub packages: Vec<Package>
+}
+
+impl BuildPlan {
+    /// The text of every source file in the plan, along with its path
+    pub fn read_files(&self) -> Result<Vec<(String, PathBuf)>, ManifestError> {
+        let paths = self.packages.iter().flat_map(|package| package.files.iter());
+        paths.map(|path| match fs::read_to_string(path) {
+            Ok(text) => Ok((text, path.clone())),
+            Err(err) => Err(ManifestError::new(path, format!("cannot read file: {}", err)))
+        }).collect()
+    }
+}
+
+/// Works out which packages the package in `dir` needs, fetching any git
+/// dependencies that haven't been already
+pub fn plan(dir: &Path) -> Result<BuildPlan, ManifestError> {
+    let mut planner = Planner {
+        git_dir: dir.join(GIT_DIR),
+        packages: Vec::new(),
+        dirs: HashMap::new()
+    };
+    planner.visit(dir, &mut Vec::new())?;
+
+    Ok(BuildPlan { packages: planner.packages })
+}
+
+struct Planner {
+    git_dir: PathBuf,
+    packages: Vec<Package>,
+    // the directory each planned package was found in, by name
+    dirs: HashMap<String, PathBuf>
+}
+
+impl Planner {
+    /// Plans the package in `dir` after its dependencies. `path` holds the
+    /// packages depending on it that are still being planned, to catch
+    /// cycles
+    fn visit(&mut self, dir: &Path, path: &mut Vec<String>) -> Result<(), ManifestError> {
+        let manifest = Manifest::load(dir)?;
+        let manifest_path = dir.join(FILE_NAME);
+
+        if let Some(start) = path.iter().position(|name| *name == manifest.name) {
+            let mut cycle = path[start..].to_vec();
+            cycle.push(manifest.name.clone());
+            return Err(ManifestError::new(&manifest_path, format!("packages depend on each other in a cycle: {}", cycle.join(" → "))));
+        }
+
+        let canonical = fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+        match self.dirs.get(&manifest.name) {
+            Some(planned) if *planned == canonical => return Ok(()),
+            Some(planned) => {
+                return Err(ManifestError::new(&manifest_path, format!("there are two packages named '{}', the other is in {}", manifest.name, planned.display())));
+            }
+            None => ()
+        }
+
+        path.push(manifest.name.clone());
+        for dep in &manifest.dependencies {
+            let dep_dir = match &dep.source {
+                Source::Path(dep_path) => dir.join(dep_path),
+                Source::Git { url, rev } => {
+                    let checkout = checkout_dir(&self.git_dir, &dep.name, url, rev);
+                    fetch(url, rev, &checkout).map_err(|message| ManifestError::new(&manifest_path, message))?;
+                    checkout
+                }
+            };
+            if !dep_dir.join(FILE_NAME).is_file() {
+                return Err(ManifestError::new(&manifest_path, format!("dependency '{}' has no {} in {}", dep.name, FILE_NAME, dep_dir.display())));
+            }
+
+            let dep_name = Manifest::load(&dep_dir)?.name;
+            if dep_name != dep.name {
+                return Err(ManifestError::new(&manifest_path, format!("dependency '{}' is the package '{}'", dep.name, dep_name)));
+            }
+            self.visit(&dep_dir, path)?;
+        }
+        path.pop();
+
+        let mut files = Vec::new();
+        for root in &manifest.roots {
+            let root_dir = dir.join(root);
+            if !root_dir.is_dir() {
+                return Err(ManifestError::new(&manifest_path, format!("source directory '{}' does not exist", root.display())));
+            }
+            find_sources(&root_dir, &mut files).map_err(|err| ManifestError::new(&root_dir, format!("cannot read directory: {}", err)))?;
+        }
+
+        self.dirs.insert(manifest.name.clone(), canonical);
+        self.packages.push(Package { name: manifest.name, dir: dir.to_path_buf(), files: files });
+        Ok(())
+    }
+}
+
+/// Collects the source files under `dir`, sorted so builds don't depend on
+/// the order the file system lists them in
+fn find_sources(dir: &Path, files: &mut Vec<PathBuf>) -> std::io::Result<()> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(dir)?.map(|entry| entry.map(|entry| entry.path())).collect::<std::io::Result<_>>()?;
+    entries.sort();
+
+    for entry in entries {
+        if entry.is_dir() {
+            find_sources(&entry, files)?;
+        }
+        else if entry.extension().map_or(false, |ext| ext == "sp") {
+            files.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Where the git dependency `name` is checked out at `rev` of `url`
+fn checkout_dir(git_dir: &Path, name: &str, url: &str, rev: &Option<String>) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    url.hash(&mut hasher);
+    rev.hash(&mut hasher);
+    git_dir.join(format!("{}-{:016x}", name, hasher.finish()))
+}
+
+/// Clones a git dependency into `dest`, unless it already has been. It's
+/// cloned beside `dest` and only moved there once it's checked out at `rev`,
+/// so a fetch that fails leaves nothing to be taken for one that worked
+fn fetch(url: &str, rev: &Option<String>, dest: &Path) -> Result<(), String> {
+    if dest.exists() {
+        return Ok(());
+    }
+
+    let run = |command: &str, args: &[&str]| {
+        match Command::new("git").args(args).output() {
+            Ok(output) if output.status.success() => Ok(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            Ok(output) => Err(format!("git {} failed: {}", command, String::from_utf8_lossy(&output.stderr).trim())),
+            Err(err) => Err(format!("cannot run git: {}", err))
+        }
+    };
+
+    let name = dest.file_name().map_or(String::new(), |name| name.to_string_lossy().into_owned());
+    let partial = dest.with_file_name(format!("{}.partial", name));
+    if partial.exists() {
+        fs::remove_dir_all(&partial).map_err(|err| format!("cannot remove {}: {}", partial.display(), err))?;
+    }
+    let partial_str = partial.to_string_lossy();
+
+    let fetched = run("clone", &["clone", "--quiet", "--", url, &partial_str]).and_then(|_| match rev {
+        // the rev is made a commit first, so it can't be taken for an option
+        Some(rev) => {
+            let spec = format!("{}^{{commit}}", rev);
+            let commit = run("rev-parse", &["-C", &partial_str, "rev-parse", "--verify", "--quiet", "--end-of-options", &spec])
+                .map_err(|_| format!("'{}' is not a revision of {}", rev, url))?;
+            run("checkout", &["-C", &partial_str, "checkout", "--quiet", "--detach", &commit]).map(|_| ())
+        }
+        None => Ok(())
+    });
+    match fetched {
+        Ok(()) => fs::rename(&partial, dest).map_err(|err| format!("cannot move {} to {}: {}", partial.display(), dest.display(), err)),
+        Err(message) => {
+            fs::remove_dir_all(&partial).ok();
+            Err(message)
+        }
+    }
+}
+
+#[test]
+fn parse_manifest() {
+    let path = Path::new(FILE_NAME);
+    let text = "[package]\nname = \"app\"\n\n[dependencies]\nlists = { path = \"../lists\" }\njson = { git = \"https://example.com/json.git\", rev = \"v1\" }\n";
+    let manifest = Manifest::parse(text, path).expect("manifest should parse");
+    assert_eq!(manifest.name, "app");
+    assert_eq!(manifest.roots, vec![PathBuf::from("src")]);
+
+    let mut deps = manifest.dependencies.clone();
+    deps.sort_by(|a, b| a.name.cmp(&b.name));
+    assert_eq!(deps, vec![
+        Dependency { name: String::from("json"), source: Source::Git { url: String::from("https://example.com/json.git"), rev: Some(String::from("v1")) } },
+        Dependency { name: String::from("lists"), source: Source::Path(PathBuf::from("../lists")) }
+    ]);
+
+    let err = Manifest::parse("[package]\nsources = [\"lib\"]\n", path).err().expect("the name is missing");
+    assert_eq!(err.message, "the package needs a name, as in `name = \"app\"`");
+
+    let err = Manifest::parse("[package]\nname = \"app\"\n[dependencies]\nlists = {}\n", path).err().expect("lists has no source");
+    assert_eq!(err.message, "dependency 'lists' needs either a `path` or a `git` url");
+}
+
+#[test]
+fn plan_packages() {
+    let base = std::env::temp_dir().join(format!("spruce-plan-{}", std::process::id()));
+    let write = |file: &str, text: &str| {
+        let path = base.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    };
+    write("app/spruce.toml", "[package]\nname = \"app\"\n[dependencies]\nlists = { path = \"../lists\" }\nutil = { path = \"../util\" }\n");
+    write("app/src/main.sp", "");
+    write("lists/spruce.toml", "[package]\nname = \"lists\"\nsources = [\"lib\"]\n[dependencies]\nutil = { path = \"../util\" }\n");
+    write("lists/lib/list.sp", "");
+    write("lists/lib/more/sort.sp", "");
+    write("util/spruce.toml", "[package]\nname = \"util\"\n");
+    write("util/src/util.sp", "");
+
+    // shared dependencies are planned once, before anything using them
+    let build = plan(&base.join("app")).expect("packages should plan");
+    let names: Vec<&str> = build.packages.iter().map(|package| package.name.as_str()).collect();
+    assert_eq!(names, vec!["util", "lists", "app"]);
+    assert_eq!(build.packages[1].files, vec![base.join("app/../lists/lib/list.sp"), base.join("app/../lists/lib/more/sort.sp")]);
+
+    write("util/spruce.toml", "[package]\nname = \"util\"\n[dependencies]\napp = { path = \"../app\" }\n");
+    let err = plan(&base.join("app")).err().expect("the packages form a cycle");
+    assert_eq!(err.message, "packages depend on each other in a cycle: app → lists → util → app");
+
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn fetch_git() {
+    let base = std::env::temp_dir().join(format!("spruce-fetch-{}", std::process::id()));
+    let repo = base.join("json");
+    fs::create_dir_all(&repo).unwrap();
+    let git = |args: &[&str]| {
+        let status = Command::new("git").arg("-C").arg(&repo).args(["-c", "user.name=spruce", "-c", "user.email=spruce@example.com"]).args(args).output().unwrap().status;
+        assert!(status.success(), "git {:?} failed", args);
+    };
+    git(&["init", "--quiet"]);
+    for version in ["v1", "v2"] {
+        fs::write(repo.join(FILE_NAME), format!("[package]\nname = \"json\"\n# {}\n", version)).unwrap();
+        git(&["add", "."]);
+        git(&["commit", "--quiet", "-m", version]);
+        git(&["tag", version]);
+    }
+
+    // each rev is checked out on its own, so changing it fetches it again
+    let url = repo.to_string_lossy().into_owned();
+    let fetched = |rev: &str| -> Result<String, String> {
+        let rev = Some(String::from(rev));
+        let dest = checkout_dir(&base.join(GIT_DIR), "json", &url, &rev);
+        fetch(&url, &rev, &dest)?;
+        Ok(fs::read_to_string(dest.join(FILE_NAME)).unwrap())
+    };
+    assert!(fetched("v1").unwrap().ends_with("# v1\n"));
+    assert!(fetched("v2").unwrap().ends_with("# v2\n"));
+    assert!(fetched("v1").unwrap().ends_with("# v1\n"));
+
+    // a rev that isn't there leaves nothing behind, even one like an option
+    assert_eq!(fetched("v3"), Err(format!("'v3' is not a revision of {}", url)));
+    assert!(fetched("--orphan=x").is_err());
+    let left: Vec<String> = fs::read_dir(base.join(GIT_DIR)).unwrap().map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned()).collect();
+    assert_eq!(left.len(), 2);
+    assert!(left.iter().all(|name| !name.ends_with(".partial")));
+
+    fs::remove_dir_all(&base).unwrap();
+}
@@ -0,0 +1,165 @@
+/*
+Monomorphization gives each polymorphic function a copy of its own for every
+type it's used at, so that each function the compiled targets write works on
+values of types known when it's compiled. That's what lets a target keep an
+Int or a Bool unboxed wherever its type says it is one, rather than having to
+handle every value the same way in case it's something else.
+
+It works on the typed AST, before it's lowered, starting from `main` and the
+program's definitions:
+
+  - each use of a function, called or as a value, has a type, and matching
+    that type against the function's scheme gives what each of its
+    quantified variables stands for there
+  - the first instantiation of a function keeps the function's own symbol,
+    and every other one gets a copy of the function under a new symbol with
+    the same name, so a function only ever used at one type isn't copied
+  - a copy's body is walked with its variables standing for its types, so
+    the functions it uses are instantiated at types with them filled in
+
+Recursive calls within a group of definitions are at the group's own types,
+since Spruce has no polymorphic recursion, so this always ends. Functions
+that aren't reached are left as they are, for lowering to leave out.
+*/
+
+use std::collections::HashMap;
+
+use crate::error::SpruceErr;
+use crate::eval;
+use crate::name_analysis as na;
+use crate::trace::Phase;
+use crate::typecheck::{self, BodyNode, CaseBody, Environment, Expr, ExprNode, FuncNode, StmtNode, TVarID, TypeRepr};
+
+type Subst = HashMap<TVarID, TypeRepr>;
+
+struct Mono<'a> {
+    prog: &'a mut na::Prog,
+    env: &'a Environment,
+    // the functions as they were checked, which every copy starts from
+    originals: HashMap<na::SymbolID, FuncNode>,
+    // the function standing for each function at each instantiation of its
+    // variables
+    instances: HashMap<(na::SymbolID, Vec<TypeRepr>), na::SymbolID>,
+    // copies of each function, in the order they were made
+    copies: HashMap<na::SymbolID, Vec<na::SymbolID>>,
+    // instances still to walk, with the function each is an instance of
+    work: Vec<(na::SymbolID, na::SymbolID, Subst)>
+}
+
+/// Gives every polymorphic function reachable from `entry` a copy per
+/// instantiation, pointing each use at the copy for its types
+pub fn compile(prog: &mut na::Prog, typed: &mut typecheck::Prog, env: &Environment, entry: &str) -> Result<(), SpruceErr> {
+    let main = eval::entry_point(prog, typed, entry)?.val.name;
+    let mut mono = Mono {
+        prog: prog,
+        env: env,
+        originals: typed.functions.iter().map(|func| (func.val.name, func.clone())).collect(),
+        instances: HashMap::new(),
+        copies: HashMap::new(),
+        work: Vec::new()
+    };
+    mono.instances.insert((main, Vec::new()), main);
+    mono.work.push((main, main, Subst::new()));
+    for def in &mut typed.definitions {
+        mono.stmt(def, &Subst::new());
+    }
+
+    let mut walked = HashMap::new();
+    while let Some((id, original, subst)) = mono.work.pop() {
+        let mut func = mono.originals[&original].clone();
+        func.val.name = id;
+        mono.body(&mut func.val.body, &subst);
+        walked.insert(id, func);
+    }
+
+    let mut functions = Vec::new();
+    for func in typed.functions.drain(..) {
+        let id = func.val.name;
+        functions.push(walked.remove(&id).unwrap_or(func));
+        for copy in mono.copies.get(&id).into_iter().flatten() {
+            functions.push(walked.remove(copy).expect("every copy is walked"));
+        }
+    }
+    typed.functions = functions;
+    Ok(())
+}
+
+impl<'a> Mono<'a> {
+    /// The function to use for `id` at the type `at`, which is `id` itself
+    /// for anything other than a function
+    fn instance(&mut self, id: na::SymbolID, at: TypeRepr) -> na::SymbolID {
+        if !self.originals.contains_key(&id) {
+            return id;
+        }
+        let types = self.env.instantiation(id, &at);
+        let key = (id, types.iter().map(|(_, ty)| ty.clone()).collect());
+        if let Some(instance) = self.instances.get(&key) {
+            return *instance;
+        }
+
+        let instance = if self.instances.keys().any(|(original, _)| *original == id) {
+            let copy = self.prog.symbol_table.copy_symbol(id);
+            self.copies.entry(id).or_default().push(copy);
+            copy
+        }
+        else {
+            id
+        };
+        if !types.is_empty() {
+            trace!(Phase::Codegen, "instantiated '{}' at {}", eval::symbol_name(self.prog, id), at);
+        }
+        self.instances.insert(key, instance);
+        self.work.push((instance, id, types.into_iter().collect()));
+        instance
+    }
+
+    fn body(&mut self, body: &mut BodyNode, subst: &Subst) {
+        for stmt in &mut body.val.stmts {
+            self.stmt(stmt, subst);
+        }
+        if let Some(expr) = &mut body.val.expr {
+            self.expr(expr, subst);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &mut StmtNode, subst: &Subst) {
+        match &mut stmt.val {
+            typecheck::Stmt::Assign(_, expr) => self.expr(expr, subst),
+            typecheck::Stmt::FnCall(id, args) => {
+                let arg_types = args.iter().map(|arg| self.env.instance_type(arg.ty, subst)).collect();
+                let at = TypeRepr::Func(arg_types, Box::from(self.env.instance_type(stmt.ty, subst)));
+                *id = self.instance(*id, at);
+                for arg in args {
+                    self.expr(arg, subst);
+                }
+            }
+            typecheck::Stmt::Case(case) => {
+                self.expr(&mut case.val.expr, subst);
+                for opt in &mut case.val.options {
+                    match &mut opt.val.body.val {
+                        CaseBody::Expr(expr) => self.expr(expr, subst),
+                        CaseBody::Body(body) => self.body(body, subst)
+                    }
+                }
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &mut ExprNode, subst: &Subst) {
+        match &mut expr.val {
+            Expr::FnCall(id, args) => {
+                let arg_types = args.iter().map(|arg| self.env.instance_type(arg.ty, subst)).collect();
+                let at = TypeRepr::Func(arg_types, Box::from(self.env.instance_type(expr.ty, subst)));
+                *id = self.instance(*id, at);
+            }
+            Expr::Id(id) => {
+                let at = self.env.instance_type(expr.ty, subst);
+                *id = self.instance(*id, at);
+            }
+            _ => ()
+        }
+        for child in expr.val.children_mut() {
+            self.expr(child, subst);
+        }
+    }
+}
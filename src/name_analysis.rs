@@ -10,24 +10,128 @@ use std::collections::HashMap;
 use std::collections::HashSet;
 use std::iter::FromIterator;
 
-use crate::error::{SpruceErr};
+use crate::error::{SpruceErr, Suggestion};
+use crate::error_codes::ErrorCode;
+use crate::lint::{Lint, Lints, Finding};
 
 use crate::parser;
-use crate::parser::{NodeInfo};
+use crate::parser::{NodeInfo, Span};
+use crate::registry::{LangValue, Registry, LANG_FNS, LANG_TYPES, LANG_VALUES};
+use crate::source::{FileId, SourceMap, NO_FILE};
 
 
 fn double_decl(name: &String, info: NodeInfo) -> SpruceErr {
-    SpruceErr{
-        message: String::from(format!("'{}' declared twice", name)),
-        info: info
+    SpruceErr::new(
+        String::from(format!("'{}' declared twice", name)),
+        info
+    ).with_code(ErrorCode::DuplicateName)
+}
+
+fn undeclared(name: &String, info: NodeInfo, candidates: Vec<&String>) -> SpruceErr {
+    let name_start = Some(info.span.start);
+    suggest(SpruceErr::new(
+        String::from(format!("'{}' used but not declared", name)),
+        info
+    ).with_code(ErrorCode::UnboundName), name, name_start, candidates)
+}
+
+/// An unqualified name that several imports export, which offers each of
+/// their qualified forms as a replacement
+fn ambiguous(name: &String, modules: &Vec<String>, info: NodeInfo) -> SpruceErr {
+    let quoted: Vec<String> = modules.iter().map(|module| format!("'{}'", module)).collect();
+    let (last, rest) = quoted.split_last().expect("ambiguous name from no modules");
+    let mut err = SpruceErr::new(
+        format!("'{}' is ambiguous, as it is imported from both {} and {}", name, rest.join(", "), last),
+        info.clone()
+    ).with_code(ErrorCode::AmbiguousName).with_help(String::from("qualify it with the module it should come from"));
+
+    for module in modules {
+        err = err.with_suggestion(Suggestion {
+            message: format!("use '{}.{}'", module, name),
+            info: NodeInfo {
+                span: Span { start: info.span.start, end: info.span.start + name.len() },
+                file: info.file
+            },
+            replacement: format!("{}.{}", module, name),
+            machine_applicable: false
+        });
+    }
+    err
+}
+
+fn private(name: &String, module: &String, info: NodeInfo) -> SpruceErr {
+    SpruceErr::new(
+        format!("'{}' is private to module '{}'", name, module),
+        info
+    ).with_code(ErrorCode::PrivateName).with_help(format!("add it to the export list of '{}' to use it here", module))
+}
+
+/// Adds a "did you mean" to an error about `name` with up to three of the
+/// closest candidates, if any are close enough to plausibly be typos. When
+/// the name's position is known, each candidate is also offered as an edit
+fn suggest(err: SpruceErr, name: &String, name_start: Option<usize>, candidates: Vec<&String>) -> SpruceErr {
+    let max_distance = std::cmp::max(1, name.chars().count() / 3);
+    let mut close: Vec<(usize, &String)> = candidates.into_iter().filter_map(|candidate| {
+        let distance = edit_distance(name, candidate);
+        if distance <= max_distance { Some((distance, candidate)) } else { None }
+    }).collect();
+    close.sort();
+    close.dedup();
+
+    close.truncate(3);
+
+    let mut err = err;
+    let file = err.info.file;
+    if let Some(start) = name_start {
+        for (_, candidate) in &close {
+            err = err.with_suggestion(Suggestion {
+                message: format!("replace with '{}'", candidate),
+                info: NodeInfo {
+                    span: Span { start: start, end: start + name.len() },
+                    file: file
+                },
+                replacement: (*candidate).clone(),
+                machine_applicable: close.len() == 1
+            });
+        }
+    }
+
+    let names: Vec<String> = close.iter().map(|(_, candidate)| format!("'{}'", candidate)).collect();
+    match names.len() {
+        0 => err,
+        1 => err.with_help(format!("did you mean {}?", names[0])),
+        _ => err.with_help(format!("did you mean one of {}?", names.join(", ")))
     }
 }
 
-fn undeclared(name: &String, info: NodeInfo) -> SpruceErr {
-    SpruceErr{
-        message: String::from(format!("'{}' used but not declared", name)),
-        info: info
+/// Edit distance between two names, counted in characters, where swapping
+/// two adjacent characters counts as a single edit
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // dist[i][j] is the distance between the first i chars of a and the
+    // first j chars of b
+    let mut dist = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        dist[i][0] = i;
+    }
+    for j in 0..=b.len() {
+        dist[0][j] = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            dist[i][j] = (dist[i - 1][j] + 1).min(dist[i][j - 1] + 1).min(dist[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                dist[i][j] = dist[i][j].min(dist[i - 2][j - 2] + 1);
+            }
+        }
     }
+
+    dist[a.len()][b.len()]
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -41,13 +145,42 @@ pub enum Expr {
     FnCall(SymbolID, Vec<Box<ExprNode>>),
     Id(SymbolID),
     ADTVal(ADTValID, Vec<Box<ExprNode>>),
-    Lit(f64),
+    Lit(i64),
+    Neg(Box<ExprNode>),
+    List(Vec<Box<ExprNode>>),
     Eq(Box<ExprNode>, Box<ExprNode>),
     NotEq(Box<ExprNode>, Box<ExprNode>),
     LtEq(Box<ExprNode>, Box<ExprNode>),
     GtEq(Box<ExprNode>, Box<ExprNode>),
     Lt(Box<ExprNode>, Box<ExprNode>),
     Gt(Box<ExprNode>, Box<ExprNode>),
+    ComposeR(Box<ExprNode>, Box<ExprNode>),
+    ComposeL(Box<ExprNode>, Box<ExprNode>),
+    BitAnd(Box<ExprNode>, Box<ExprNode>),
+    BitOr(Box<ExprNode>, Box<ExprNode>),
+    BitXor(Box<ExprNode>, Box<ExprNode>),
+    Shl(Box<ExprNode>, Box<ExprNode>),
+    Shr(Box<ExprNode>, Box<ExprNode>),
+}
+
+impl Expr {
+    /// The expressions directly inside this one
+    pub fn children(&self) -> Vec<&ExprNode> {
+        match self {
+            Expr::Id(_) | Expr::Lit(_) => Vec::new(),
+            Expr::Neg(inner) => vec![inner],
+            Expr::FnCall(_, args) | Expr::ADTVal(_, args) | Expr::List(args) => {
+                args.iter().map(|arg| &**arg).collect()
+            }
+            Expr::Add(left, right) | Expr::Subt(left, right) | Expr::Mult(left, right) |
+            Expr::Div(left, right) | Expr::Pow(left, right) | Expr::Mod(left, right) |
+            Expr::BitAnd(left, right) | Expr::BitOr(left, right) | Expr::BitXor(left, right) |
+            Expr::Shl(left, right) | Expr::Shr(left, right) |
+            Expr::Eq(left, right) | Expr::NotEq(left, right) | Expr::LtEq(left, right) |
+            Expr::GtEq(left, right) | Expr::Lt(left, right) | Expr::Gt(left, right) |
+            Expr::ComposeR(left, right) | Expr::ComposeL(left, right) => vec![left, right]
+        }
+    }
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -81,13 +214,14 @@ pub struct CaseNode {
     pub info: NodeInfo
 }
 
-#[derive(Debug, PartialEq)]
-pub struct CasePattern {
-    pub base: ADTValID,
-    pub args: Vec<SymbolID> 
+#[derive(Debug, PartialEq, Clone)]
+pub enum CasePattern {
+    ADT(ADTValID, Vec<SymbolID>),
+    Lit(i64),
+    Any
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct CasePatternNode {
     pub val: CasePattern,
     pub info: NodeInfo
@@ -142,7 +276,7 @@ pub struct StmtNode {
     pub info: NodeInfo
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Target {
     Var(SymbolID),
     Mutable(SymbolID),
@@ -158,7 +292,7 @@ impl Target {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct TargetNode {
     pub val: Target,
     pub info: NodeInfo
@@ -167,7 +301,9 @@ pub struct TargetNode {
 #[derive(Debug, PartialEq)]
 pub struct Type {
     pub name: String,
-    pub options: Vec<TypeOptionNode>
+    pub options: Vec<TypeOptionNode>,
+    pub doc: Option<String>,
+    pub builtin: bool
 }
 
 #[derive(Debug, PartialEq)]
@@ -188,11 +324,27 @@ pub struct TypeOptionNode {
     pub info: NodeInfo
 }
 
+/// The type of a declaration from an interface file. Type variables are
+/// type parameters of the signature, listed in `params`
+#[derive(Debug, PartialEq)]
+pub struct Signature {
+    pub name: SymbolID,
+    pub ty: TypeID,
+    pub params: Vec<TParamID>
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SignatureNode {
+    pub val: Signature,
+    pub info: NodeInfo
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Func {
     pub name: SymbolID,
     pub args: Vec<SymbolID>,
-    pub body: BodyNode
+    pub body: BodyNode,
+    pub doc: Option<String>
 }
 
 #[derive(Debug, PartialEq)]
@@ -201,15 +353,35 @@ pub struct FuncNode {
     pub info: NodeInfo
 }
 
-// exists to record types of adts and values we need in codegen
+/// A file's module, and the modules it imports
 #[derive(Debug, PartialEq, Clone)]
-pub struct InternalTypes {
-    pub bool_id: ADTID,
-    pub maybe_id: ADTID,
+pub struct Module {
+    pub name: String,
+    pub file: FileId,
+    pub exports: Option<Vec<parser::ExportNode>>,
+    pub imports: Vec<parser::ImportNode>,
+    // read from an interface file, so there is nothing of it to check or
+    // generate code for
+    pub interface: bool
+}
+
+impl Module {
+    /// Whether other modules can see the declaration `name`. A module
+    /// without an export list shows them everything
+    pub fn exports(&self, name: &str) -> bool {
+        match &self.exports {
+            Some(exports) => exports.iter().any(|export| export.val.name == name),
+            None => true
+        }
+    }
 
-    pub list_id: ADTID,
-    pub cons_id: ADTValID,
-    pub nil_id: ADTValID
+    /// Whether other modules can use the constructors of the type `name`
+    pub fn exports_constructors(&self, name: &str) -> bool {
+        match &self.exports {
+            Some(exports) => exports.iter().any(|export| export.val.name == name && export.val.constructors),
+            None => true
+        }
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -217,58 +389,329 @@ pub struct Prog {
     pub functions: Vec<FuncNode>,
     pub definitions: Vec<StmtNode>,
     pub types: Vec<TypeNode>,
+    pub signatures: Vec<SignatureNode>,
+    // ordered so that each module comes after the modules it imports
+    pub modules: Vec<Module>,
     pub symbol_table: SymbolTable,
     pub type_table: TypeTableExt,
-    pub internal_types: InternalTypes
+    pub registry: Registry
 }
 
+impl Prog {
+    /// The doc comment on the function declared as `id`, if it has one
+    pub fn function_doc(&self, id: &SymbolID) -> Option<&String> {
+        self.functions.iter()
+            .find(|func| { func.val.name == *id })
+            .and_then(|func| { func.val.doc.as_ref() })
+    }
 
-pub fn name_analysis(prog: parser::Prog) -> Result<Prog, SpruceErr> {
-    let (types, type_table) = analyze_types(&prog)?;
-    let (mut sym_table, fn_ids, targets) = collect_decls(&prog)?;
+    /// The name of the test the function declared as `id` is, if it's one
+    pub fn test_name(&self, id: &SymbolID) -> Option<&str> {
+        self.symbol_table.lookup_id(id).and_then(|sym| parser::test_name(&sym.name))
+    }
 
-    let mut defs = Vec::new();
-    for (def, target) in prog.definitions.iter().zip(targets.into_iter()) {
-        defs.push(check_global(&mut sym_table, &type_table, def, target)?);
+    /// The doc comment on the ADT named `name`, if it has one
+    pub fn type_doc(&self, name: &str) -> Option<&String> {
+        self.types.iter()
+            .find(|t| { t.val.name == name })
+            .and_then(|t| { t.val.doc.as_ref() })
     }
+}
+
+/// Every module can see the prelude's declarations without importing it
+pub const PRELUDE_FILE: &str = "prelude";
+
+/// The prelude is built into the compiler, and compiled before every program
+pub const PRELUDE: &str = include_str!("prelude.sp");
+
+pub fn name_analysis(prog: parser::Prog, sources: &SourceMap, lints: &mut Lints) -> Result<Prog, SpruceErr> {
+    let modules = module_graph(&prog.modules)?;
+    let prelude_file = sources.file_id(PRELUDE_FILE);
+    let prelude = modules.iter().find(|module| Some(module.file) == prelude_file).map(|module| module.name.clone());
+
+    let (types, mut type_table) = analyze_types(&prog, &modules, &prelude)?;
+    type_table.registry = bootstrap(&type_table, &modules, &prelude)?;
+    let (mut sym_table, fn_ids, targets, sig_ids) = collect_decls(&prog, &modules)?;
+    check_exports(&sym_table, &type_table, &modules)?;
+    check_tail_calls(&prog)?;
 
+    // each module is checked with only its own declarations, the prelude's
+    // and those of its imports in scope
+    let mut defs = Vec::new();
     let mut funcs = Vec::new();
-    for (func, id) in prog.functions.iter().zip(fn_ids.into_iter()) {
-        funcs.push(check_function(&mut sym_table, &type_table, func, id)?);
+    let mut signatures = Vec::new();
+    for module in &modules {
+        sym_table.enter_module(module, &prelude, &modules);
+        type_table.set_scope(module, &prelude, &modules);
+
+        for (def, target) in prog.definitions.iter().zip(targets.iter()) {
+            if def.info.file == module.file {
+                defs.push(check_global(&mut sym_table, &type_table, def, target.clone())?);
+            }
+        }
+
+        for (func, id) in prog.functions.iter().zip(fn_ids.iter()) {
+            if func.info.file == module.file {
+                funcs.push(check_function(&mut sym_table, &type_table, func, *id)?);
+            }
+        }
+
+        for (sig, id) in prog.signatures.iter().zip(sig_ids.iter()) {
+            if sig.info.file == module.file {
+                if !module.interface && Some(&module.name) != prelude.as_ref() {
+                    return Err(SpruceErr::new(
+                        format!("'{}' is declared as a builtin outside of the prelude", sig.val.name),
+                        sig.info.clone()
+                    ).with_code(ErrorCode::MisplacedBuiltin));
+                }
+                if Some(&module.name) == prelude.as_ref() {
+                    if let Some(item) = LANG_FNS.iter().find(|item| item.name() == sig.val.name) {
+                        type_table.registry.add_fn(*item, *id);
+                    }
+                }
+                signatures.push(check_signature(&mut type_table, sig, *id)?);
+            }
+        }
+
+        sym_table.leave_module(module);
     }
-    
-    sym_table.pop_layer();
-
-    let internal_types = InternalTypes {
-        bool_id: type_table.get_type(&String::from("Bool")).expect("Could not find Bool id").id,
-        maybe_id: type_table.get_type(&String::from("Maybe")).expect("Could not find Maybe id").id,
-        list_id: type_table.get_type(&String::from("List")).expect("Could not find List id").id,
-        cons_id: type_table.get_value(&String::from("Cons")).expect("Could not find Cons id").id,
-        nil_id: type_table.get_value(&String::from("Nil")).expect("Could not find Nil id").id,
-    };
 
+    for module in &modules {
+        sym_table.layers.push(sym_table.modules.remove(&module.name).unwrap_or_default());
+        sym_table.pop_layer();
+    }
+    // the prelude ships with the compiler, so its findings aren't the user's to fix
+    lints.report_all(sym_table.lint_findings.drain(..).filter(|(_, _, info)| Some(info.file) != prelude_file).collect());
+    for (lint, diagnostic) in sym_table.lint_diagnostics.drain(..) {
+        if Some(diagnostic.info.file) != prelude_file {
+            lints.report_diagnostic(lint, diagnostic);
+        }
+    }
+
+    let registry = type_table.registry.clone();
     let out_prog = Prog {
         functions: funcs, 
         definitions: defs,
         types: types,
+        signatures: signatures,
+        modules: modules,
         symbol_table: sym_table,
         type_table: type_table.to_ext(),
-        internal_types: internal_types
+        registry: registry
     };
     Ok(out_prog)
 }
 
+/// Finds the prelude's declarations the compiler relies on. Without a prelude
+/// they may be declared by any module
+fn bootstrap(types: &TypeTable, modules: &Vec<Module>, prelude: &Option<String>) -> Result<Registry, SpruceErr> {
+    let declared_by_prelude = |adt: &ADT| prelude.as_ref().map_or(true, |name| &adt.module == name);
+    let missing = |kind: &str, name: &str, code: ErrorCode| {
+        let file = modules.iter().find(|module| Some(&module.name) == prelude.as_ref()).map_or(NO_FILE, |module| module.file);
+        SpruceErr::new(
+            format!("the prelude must declare the {} '{}', which the compiler relies on", kind, name),
+            NodeInfo { span: Span { start: 0, end: 0 }, file: file }
+        ).with_code(code)
+    };
+
+    let mut registry = Registry::new();
+    for item in LANG_TYPES.iter() {
+        match types.types.get(item.name()) {
+            Some(adt) if declared_by_prelude(adt) => registry.add_type(*item, adt.id),
+            _ => return Err(missing("type", item.name(), ErrorCode::UnknownType))
+        }
+    }
+    for item in LANG_VALUES.iter() {
+        let adt = types.values.get(item.name()).and_then(|val| {
+            types.types.values().find(|adt| adt.id == val.data_type).map(|adt| (val.id, adt))
+        });
+        match adt {
+            Some((id, adt)) if declared_by_prelude(adt) => registry.add_value(*item, id),
+            _ => return Err(missing("constructor", item.name(), ErrorCode::UnboundName))
+        }
+    }
+    Ok(registry)
+}
+
+/// The module declared by `file`
+fn module_of(modules: &Vec<Module>, file: FileId) -> &Module {
+    modules.iter().find(|module| module.file == file).expect("file without a module")
+}
+
+/// Checks that every import names a module of the program, and orders the
+/// modules so that each comes after the modules it imports. Otherwise the
+/// order of the files is kept, so the prelude goes first
+fn module_graph(modules: &Vec<parser::ModuleNode>) -> Result<Vec<Module>, SpruceErr> {
+    let mut index_of: HashMap<&String, usize> = HashMap::new();
+    for (i, module) in modules.iter().enumerate() {
+        if index_of.insert(&module.val.name, i).is_some() {
+            return Err(double_decl(&module.val.name, module.info.clone()));
+        }
+    }
+
+    let mut edges = Vec::new();
+    for module in modules {
+        let mut imports = Vec::new();
+        for import in &module.val.imports {
+            match index_of.get(&import.val.module) {
+                Some(i) => imports.push(*i),
+                None => {
+                    let name = &import.val.module;
+                    let name_start = import.info.span.end - name.len();
+                    return Err(suggest(SpruceErr::new(
+                        format!("module '{}' not found", name),
+                        import.info.clone()
+                    ).with_code(ErrorCode::UnknownModule), name, Some(name_start), index_of.keys().cloned().collect()));
+                }
+            }
+        }
+        edges.push(imports);
+    }
+
+    let mut order = Vec::new();
+    for i in 0..modules.len() {
+        visit_module(modules, &edges, i, &mut Vec::new(), &mut order)?;
+    }
+
+    Ok(order.into_iter().map(|i| {
+        let module = &modules[i];
+        Module {
+            name: module.val.name.clone(),
+            file: module.info.file,
+            exports: module.val.exports.clone(),
+            imports: module.val.imports.clone(),
+            interface: module.val.interface
+        }
+    }).collect())
+}
+
+/// Places a module in `order` once everything it imports has been. `path`
+/// holds the imports followed to get here, and the search fails if one of
+/// them leads back to a module already on it
+fn visit_module<'a>(modules: &'a Vec<parser::ModuleNode>, edges: &Vec<Vec<usize>>, i: usize, path: &mut Vec<(usize, &'a parser::ImportNode)>, order: &mut Vec<usize>) -> Result<(), SpruceErr> {
+    if order.contains(&i) {
+        return Ok(());
+    }
+
+    for (import, next) in modules[i].val.imports.iter().zip(edges[i].iter()) {
+        path.push((i, import));
+        if let Some(start) = path.iter().position(|(from, _)| from == next) {
+            return Err(import_cycle(modules, &path[start..]));
+        }
+
+        visit_module(modules, edges, *next, path, order)?;
+        path.pop();
+    }
+
+    order.push(i);
+    Ok(())
+}
+
+/// Reports a cycle of imports, given as each module on it along with its
+/// import of the next. The error points at the first import, with a label
+/// on each of the others
+fn import_cycle(modules: &Vec<parser::ModuleNode>, cycle: &[(usize, &parser::ImportNode)]) -> SpruceErr {
+    let mut names: Vec<&str> = cycle.iter().map(|(i, _)| modules[*i].val.name.as_str()).collect();
+    names.push(names[0]);
+
+    let (_, first) = cycle[0];
+    if cycle.len() == 1 {
+        return SpruceErr::new(
+            format!("module '{}' imports itself", names[0]),
+            first.info.clone()
+        ).with_code(ErrorCode::ImportCycle).with_help(String::from("a module can always see its own declarations, so the import isn't needed"));
+    }
+
+    let mut err = SpruceErr::new(
+        format!("modules import each other in a cycle: {}", names.join(" → ")),
+        first.info.clone()
+    ).with_code(ErrorCode::ImportCycle);
+    for (i, import) in &cycle[1..] {
+        err = err.with_label(format!("'{}' imports '{}' here", modules[*i].val.name, import.val.module), import.info.clone());
+    }
+    err.with_help(String::from("move what the modules share into a module of its own, which they can each import"))
+}
+
+/// Makes sure every call marked `@tail` is in tail position
+fn check_tail_calls(prog: &parser::Prog) -> Result<(), SpruceErr> {
+    let mut in_tail = Vec::new();
+    for func in &prog.functions {
+        tail_calls(&func.val.body, &mut in_tail);
+    }
+    match prog.tail_calls.iter().find(|info| !in_tail.contains(info)) {
+        Some(info) => Err(SpruceErr::new(
+            String::from("this call is marked '@tail', but its result isn't what the function gives back"),
+            info.clone()
+        ).with_code(ErrorCode::MisplacedTailCall).with_help(String::from("move the call to the end of the function, or remove '@tail'"))),
+        None => Ok(())
+    }
+}
+
+/// Where the calls are whose result is the value of `body`, when `body` is
+/// in tail position
+fn tail_calls(body: &parser::BodyNode, found: &mut Vec<NodeInfo>) {
+    match (&body.val.expr, body.val.stmts.last()) {
+        (Some(expr), _) => tail_call(expr, found),
+        (None, Some(stmt)) => match &stmt.val {
+            parser::Stmt::FnCall(_, _) => found.push(stmt.info.clone()),
+            parser::Stmt::Case(case) => {
+                for opt in &case.val.options {
+                    match &opt.val.body.val {
+                        parser::CaseBody::Expr(expr) => tail_call(expr, found),
+                        parser::CaseBody::Body(body) => tail_calls(body, found)
+                    }
+                }
+            }
+            parser::Stmt::Assign(_, _) => ()
+        },
+        (None, None) => ()
+    }
+}
+
+fn tail_call(expr: &parser::ExprNode, found: &mut Vec<NodeInfo>) {
+    if let parser::Expr::FnCall(_, _) = expr.val {
+        found.push(expr.info.clone());
+    }
+}
+
+/// Makes sure everything a module exports is declared in it, and that only
+/// types export their constructors
+fn check_exports(table: &SymbolTable, types: &TypeTable, modules: &Vec<Module>) -> Result<(), SpruceErr> {
+    for module in modules {
+        for export in module.exports.iter().flatten() {
+            let name = &export.val.name;
+            let is_type = types.types.get(name).map_or(false, |adt| adt.module == module.name);
+            let is_symbol = table.modules.get(&module.name).map_or(false, |layer| layer.contains_key(name));
+
+            if !is_type && !is_symbol {
+                return Err(SpruceErr::new(
+                    format!("'{}' is exported but not declared in module '{}'", name, module.name),
+                    export.info.clone()
+                ).with_code(ErrorCode::UnboundName));
+            }
+            if export.val.constructors && !is_type {
+                return Err(SpruceErr::new(
+                    format!("'{}' is not a type, so has no constructors to export", name),
+                    export.info.clone()
+                ).with_code(ErrorCode::NotAConstructor));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub type SymbolID = u32;
 pub type CaseID = u32;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum SymbolType {
     Const,
     Mutable,
     Function
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Symbol {
     pub id: SymbolID,
     pub name: String,
@@ -282,12 +725,45 @@ pub struct SymbolTable {
     next_id: SymbolID,
     next_case_id: CaseID,
     layers: Vec<SymbolLayer>,
-    pub store: HashMap<SymbolID, Symbol>
+    // the top-level declarations of each module outside of the one being
+    // checked
+    modules: HashMap<String, SymbolLayer>,
+    // names that imported modules declare but don't export, and which
+    // module each belongs to
+    hidden: HashMap<String, String>,
+    // names exported by several imports, and the modules exporting them
+    ambiguous: HashMap<String, Vec<String>>,
+    // what can be reached through each module name, as in `List.map`
+    qualifiers: HashMap<String, SymbolLayer>,
+    pub store: HashMap<SymbolID, Symbol>,
+
+    // where each symbol was declared, and which have been read, for lints
+    decl_info: HashMap<SymbolID, NodeInfo>,
+    used: HashSet<SymbolID>,
+    // symbols hiding another of the same name in an outer scope
+    pub shadowing: HashSet<SymbolID>,
+    lint_findings: Vec<Finding>,
+    // findings with a code and suggestions of their own
+    lint_diagnostics: Vec<(Lint, SpruceErr)>
 }
 
 impl SymbolTable {
     fn new() -> Self {
-        SymbolTable { next_id: 0, next_case_id: 0, layers: vec![], store: HashMap::new() }
+        SymbolTable {
+            next_id: 0,
+            next_case_id: 0,
+            layers: vec![],
+            modules: HashMap::new(),
+            hidden: HashMap::new(),
+            ambiguous: HashMap::new(),
+            qualifiers: HashMap::new(),
+            store: HashMap::new(),
+            decl_info: HashMap::new(),
+            used: HashSet::new(),
+            shadowing: HashSet::new(),
+            lint_findings: Vec::new(),
+            lint_diagnostics: Vec::new()
+        }
     }
 
     fn push_layer(&mut self) {
@@ -295,8 +771,18 @@ impl SymbolTable {
         self.layers.push(new_layer);
     }
 
+    /// Leaves a scope, noting any of its variables that were never read
     fn pop_layer(&mut self) {
         self.layers.pop().map(|mut layer| {
+            let mut unused: Vec<&Symbol> = layer.values().filter(|sym| {
+                sym.sym_type != SymbolType::Function && !self.used.contains(&sym.id)
+            }).collect();
+            unused.sort_by_key(|sym| sym.id);
+            for sym in unused {
+                let info = self.decl_info.get(&sym.id).expect("symbol without a declaration").clone();
+                self.lint_findings.push((Lint::Unused, format!("'{}' is never used", sym.name), info));
+            }
+
             for (_, v) in layer.drain() {
                 self.store.insert(v.id, v);
             }
@@ -304,12 +790,18 @@ impl SymbolTable {
     }
 
     /// Returns SymbolID if insert was successful
-    fn attempt_insert(&mut self, name: &String, sym_type: SymbolType) -> Option<SymbolID> {
+    fn attempt_insert(&mut self, name: &String, sym_type: SymbolType, info: &NodeInfo) -> Option<SymbolID> {
         if self.conflicts(name) {
             return None;
         }
 
         let id = self.next_id;
+        if self.lookup(name).is_some() {
+            self.shadowing.insert(id);
+            self.lint_findings.push((Lint::Shadowing, format!("'{}' shadows an earlier declaration", name), info.clone()));
+        }
+        self.decl_info.insert(id, info.clone());
+
         let ret = self.layers.last_mut().and_then(|layer| {
             let symbol = Symbol { id: id, name: name.clone(), sym_type: sym_type };
             layer.insert(name.clone(), symbol);
@@ -320,6 +812,90 @@ impl SymbolTable {
         ret
     }
 
+    /// Declares a top-level name in a module, returning None if the module
+    /// already has one by that name. Names declared by more than one module
+    /// are marked as shadowing so they get distinct names in codegen
+    fn declare(&mut self, module: &String, name: &String, sym_type: SymbolType, info: &NodeInfo) -> Option<SymbolID> {
+        let elsewhere = self.modules.values().any(|layer| layer.contains_key(name));
+
+        self.layers = vec![self.modules.remove(module).unwrap_or_default()];
+        let id = self.attempt_insert(name, sym_type, info);
+        if let Some(layer) = self.layers.pop() {
+            self.modules.insert(module.clone(), layer);
+        }
+
+        if let (Some(id), true) = (id, elsewhere) {
+            self.shadowing.insert(id);
+        }
+        id
+    }
+
+    /// Scopes the table to a module: its own declarations, behind what its
+    /// imports export, behind the prelude's declarations. Names exported by
+    /// more than one import are left out, and have to be qualified
+    fn enter_module(&mut self, module: &Module, prelude: &Option<String>, modules: &Vec<Module>) {
+        let mut prelude_layer = match prelude {
+            Some(name) if *name != module.name => self.modules.get(name).cloned().unwrap_or_default(),
+            _ => HashMap::new()
+        };
+
+        let mut imported: SymbolLayer = HashMap::new();
+        let mut imported_from: HashMap<String, &String> = HashMap::new();
+        self.hidden.clear();
+        self.ambiguous.clear();
+        self.qualifiers.clear();
+        for import in &module.imports {
+            let (layer, exporter) = match (self.modules.get(&import.val.module), modules.iter().find(|m| m.name == import.val.module)) {
+                (Some(layer), Some(exporter)) => (layer, exporter),
+                _ => continue
+            };
+
+            let mut exported = HashMap::new();
+            for (name, sym) in layer {
+                if !exporter.exports(name) {
+                    self.hidden.insert(name.clone(), exporter.name.clone());
+                    continue;
+                }
+                exported.insert(name.clone(), sym.clone());
+
+                match imported_from.get(name) {
+                    Some(other) if **other != import.val.module => {
+                        let sources = self.ambiguous.entry(name.clone()).or_insert_with(|| vec![(*other).clone()]);
+                        if !sources.contains(&import.val.module) {
+                            sources.push(import.val.module.clone());
+                        }
+                    }
+                    _ => {
+                        imported_from.insert(name.clone(), &import.val.module);
+                        imported.insert(name.clone(), sym.clone());
+                    }
+                }
+            }
+            self.qualifiers.insert(import.val.module.clone(), exported);
+        }
+
+        for name in self.ambiguous.keys() {
+            imported.remove(name);
+            prelude_layer.remove(name);
+        }
+
+        let own = self.modules.remove(&module.name).unwrap_or_default();
+        if let Some(name) = prelude {
+            let layer = if *name == module.name { own.clone() } else { self.modules.get(name).cloned().unwrap_or_default() };
+            self.qualifiers.insert(name.clone(), layer);
+        }
+        self.qualifiers.insert(module.name.clone(), own.clone());
+        self.layers = vec![prelude_layer, imported, own];
+    }
+
+    /// Puts a module's declarations back once it has been checked
+    fn leave_module(&mut self, module: &Module) {
+        if let Some(own) = self.layers.pop() {
+            self.modules.insert(module.name.clone(), own);
+        }
+        self.layers.clear();
+    }
+
     fn lookup(&self, name: &String) -> Option<&Symbol> {
         for layer in self.layers.iter().rev() {
             match layer.get(name) {
@@ -333,19 +909,40 @@ impl SymbolTable {
         None
     }
 
+    /// Every name in scope, for suggesting alternatives to a misspelling
+    fn visible_names(&self) -> Vec<&String> {
+        self.layers.iter().flat_map(|layer| layer.keys()).collect()
+    }
+
+    /// Names of mutable variables in scope
+    fn mutable_names(&self) -> Vec<&String> {
+        self.layers.iter().flat_map(|layer| layer.values()).filter(|sym| sym.sym_type == SymbolType::Mutable).map(|sym| &sym.name).collect()
+    }
+
     pub fn lookup_id(&self, id: &SymbolID) -> Option<&Symbol> {
         self.store.get(id)
     }
 
-    // TODO: allow variable shadowing
+    /// Declares another symbol like `id`, for a copy of what it names
+    pub fn copy_symbol(&mut self, id: SymbolID) -> SymbolID {
+        let copy = self.next_id;
+        self.next_id += 1;
+        let symbol = Symbol { id: copy, ..self.store[&id].clone() };
+        self.store.insert(copy, symbol);
+        copy
+    }
+
+    /// Names may shadow those in outer scopes, but not be declared twice in
+    /// the same one
     fn conflicts(&self, name: &String) -> bool {
-        for layer in self.layers.iter().rev() {
-            if layer.get(name).is_some() {
-                return true;
-            }
+        match self.layers.last() {
+            Some(layer) => layer.contains_key(name),
+            None => false
         }
+    }
 
-        false
+    fn mark_used(&mut self, id: SymbolID) {
+        self.used.insert(id);
     }
 
     fn new_case_id(&mut self) -> CaseID {
@@ -354,44 +951,43 @@ impl SymbolTable {
     }
 }
 
-/// collects top-level name declarations
-fn collect_decls(prog: &parser::Prog) -> Result<(SymbolTable, Vec<SymbolID>, Vec<TargetNode>), SpruceErr> {
+/// collects top-level name declarations, into the module of the file each
+/// is declared in
+fn collect_decls(prog: &parser::Prog, modules: &Vec<Module>) -> Result<(SymbolTable, Vec<SymbolID>, Vec<TargetNode>, Vec<SymbolID>), SpruceErr> {
     let mut table = SymbolTable::new();
-    table.push_layer();
 
     let mut fn_ids = Vec::new();
     for func in &prog.functions {
-        if table.conflicts(&func.val.name) {
-            return Err(double_decl(&func.val.name, func.info.clone()));
+        let module = module_of(modules, func.info.file);
+        match table.declare(&module.name, &func.val.name, SymbolType::Function, &func.info) {
+            Some(id) => fn_ids.push(id),
+            None => return Err(double_decl(&func.val.name, func.info.clone()))
         }
-        let id = table.attempt_insert(&func.val.name, SymbolType::Function).expect("unreachable");
-        fn_ids.push(id);
     }
 
     let mut tgts = Vec::new();
     for var in &prog.definitions {
+        let module = module_of(modules, var.info.file);
         let tgt_val = match &var.val {
             parser::Stmt::Assign(tgt, _) => {
                 match &tgt.val {
                     parser::Target::Var(name) => {
-                        if table.conflicts(name) {
-                            return Err(double_decl(name, tgt.info.clone()));
+                        match table.declare(&module.name, name, SymbolType::Const, &tgt.info) {
+                            Some(id) => Target::Var(id),
+                            None => return Err(double_decl(name, tgt.info.clone()))
                         }
-                        let id = table.attempt_insert(name, SymbolType::Const).expect("unreachable");
-                        Target::Var(id)
                     }
                     parser::Target::Mutable(name) => {
-                        if table.conflicts(name) {
-                            return Err(double_decl(name, tgt.info.clone()));
+                        match table.declare(&module.name, name, SymbolType::Mutable, &tgt.info) {
+                            Some(id) => Target::Mutable(id),
+                            None => return Err(double_decl(name, tgt.info.clone()))
                         }
-                        let id = table.attempt_insert(name, SymbolType::Mutable).expect("unreachable");
-                        Target::Mutable(id)
                     }
                     parser::Target::Update(_) => {
-                        return Err(SpruceErr {
-                            message: String::from(format!("Updates not allowed in program level-statements")),
-                            info: var.info.clone()
-                        });
+                        return Err(SpruceErr::new(
+                            String::from(format!("Updates not allowed in program level-statements")),
+                            var.info.clone()
+                        ).with_code(ErrorCode::InvalidUpdate));
                     }
                 }
             }
@@ -403,8 +999,19 @@ fn collect_decls(prog: &parser::Prog) -> Result<(SymbolTable, Vec<SymbolID>, Vec
             info: var.info.clone()
         });
     }
-    
-    Ok((table, fn_ids, tgts))
+
+    // what an interface declares could be a function or a definition, but
+    // either way it isn't this program's to lint. Builtins are functions
+    let mut sig_ids = Vec::new();
+    for sig in &prog.signatures {
+        let module = module_of(modules, sig.info.file);
+        match table.declare(&module.name, &sig.val.name, SymbolType::Function, &sig.info) {
+            Some(id) => sig_ids.push(id),
+            None => return Err(double_decl(&sig.val.name, sig.info.clone()))
+        }
+    }
+
+    Ok((table, fn_ids, tgts, sig_ids))
 }
 
 fn check_global(table: &mut SymbolTable, types: &TypeTable, stmt: &parser::StmtNode, tgt: TargetNode) -> Result<StmtNode, SpruceErr> {
@@ -426,12 +1033,11 @@ fn check_function(table: &mut SymbolTable, types: &TypeTable, func: &parser::Fun
 
     let mut arg_symbols = Vec::new();
     for arg in &func.val.args {
-        match table.attempt_insert(&arg, SymbolType::Const) {
+        match table.attempt_insert(&arg, SymbolType::Const, &func.info) {
             Some(id) => {
                 arg_symbols.push(id);
             }
             None => {
-                // TODO: implement variable shadowing with arguments
                 return Err(double_decl(arg, func.info.clone()));
             }
         }
@@ -442,7 +1048,7 @@ fn check_function(table: &mut SymbolTable, types: &TypeTable, func: &parser::Fun
     table.pop_layer();
 
     Ok(FuncNode {
-        val: Func {name: id, args: arg_symbols, body: body},
+        val: Func {name: id, args: arg_symbols, body: body, doc: func.val.doc.clone()},
         info: func.info.clone()
     })
 }
@@ -467,8 +1073,10 @@ fn check_body(table: &mut SymbolTable, types: &TypeTable, body: &parser::BodyNod
 fn check_stmt(table: &mut SymbolTable, types: &TypeTable, stmt: &parser::StmtNode) -> Result<StmtNode, SpruceErr> {
     let stmt_val = match &stmt.val {
         parser::Stmt::Assign(tgt, expr) => {
-            let new_tgt = check_target(table, tgt)?;
+            // the value is checked first, so it can refer to a variable the
+            // target shadows
             let new_expr = check_expr(table, types, expr)?;
+            let new_tgt = check_target(table, tgt)?;
 
             Stmt::Assign(new_tgt, new_expr)
         }
@@ -481,6 +1089,9 @@ fn check_stmt(table: &mut SymbolTable, types: &TypeTable, stmt: &parser::StmtNod
             for opt in &case.val.options {
                 options.push(check_case_option(table, types, opt)?);
             }
+            if let Some(finding) = check_exhaustive(types, &options, &case.info) {
+                table.lint_diagnostics.push((Lint::NonExhaustive, finding));
+            }
 
             Stmt::Case(CaseNode {
                 val: Case {id: id, expr: expr, options: options},
@@ -488,18 +1099,21 @@ fn check_stmt(table: &mut SymbolTable, types: &TypeTable, stmt: &parser::StmtNod
             })
         }
         parser::Stmt::FnCall(name, args) => {
-            match table.lookup(&name) {
-                Some(sym) => {
+            match resolve(table, types, name, &stmt.info)? {
+                Resolved::Symbol(id) => {
+                    table.mark_used(id);
+
                     let mut checked_args = Vec::new();
                     for arg in args {
                         let checked = check_expr(table, types, &arg)?;
                         checked_args.push(checked);
                     }
 
-                    Stmt::FnCall(sym.id, checked_args)
+                    Stmt::FnCall(id, checked_args)
                 }
-                None => {
-                    return Err(undeclared(name, stmt.info.clone()));
+                // constructors are only called for their value
+                Resolved::Value(_) => {
+                    return Err(unbound(table, types, name, stmt.info.clone(), table.visible_names()));
                 }
             }
         }
@@ -511,6 +1125,63 @@ fn check_stmt(table: &mut SymbolTable, types: &TypeTable, stmt: &parser::StmtNod
     })
 }
 
+/// Finds the constructors a case over them has no option for, when it has no
+/// wildcard either. A value that reaches such a case stops the program when
+/// it runs, so this is a lint rather than an error. Cases mixing constructors
+/// of different types are left for the typechecker to report
+fn check_exhaustive(types: &TypeTable, options: &Vec<CaseOptionNode>, info: &NodeInfo) -> Option<SpruceErr> {
+    let mut covered = HashSet::new();
+    for opt in options {
+        match &opt.val.pattern.val {
+            CasePattern::ADT(id, _) => {
+                covered.insert(*id);
+            }
+            CasePattern::Lit(_) | CasePattern::Any => return None
+        }
+    }
+
+    let data_types: HashSet<ADTID> = types.values.values().filter(|val| covered.contains(&val.id)).map(|val| val.data_type).collect();
+    if data_types.len() != 1 {
+        return None;
+    }
+    let data_type = data_types.into_iter().next().unwrap();
+
+    let mut missing: Vec<&ADTValue> = types.values.values().filter(|val| {
+        val.data_type == data_type && !covered.contains(&val.id)
+    }).collect();
+    if missing.is_empty() {
+        return None;
+    }
+    missing.sort_by_key(|val| val.id);
+
+    let names: Vec<&str> = missing.iter().map(|val| val.name.as_str()).collect();
+    let arms: Vec<String> = missing.iter().map(|val| {
+        let args: Vec<String> = (1..=val.args.len()).map(|i| format!("arg{}", i)).collect();
+        if args.is_empty() {
+            format!("{} -> todo\n", val.name)
+        }
+        else {
+            format!("{}({}) -> todo\n", val.name, args.join(", "))
+        }
+    }).collect();
+
+    // new arms go above the first one, so the inserted text can take its
+    // indentation
+    let first_option = &options[0].info;
+    Some(SpruceErr::new(
+        format!("case does not cover {}", names.join(", ")),
+        info.clone()
+    ).with_code(ErrorCode::NonExhaustiveCase).with_help(String::from("add an option for each, or a '_' option")).with_suggestion(Suggestion {
+        message: String::from("add the missing options"),
+        info: NodeInfo {
+            span: Span { start: first_option.span.start, end: first_option.span.start },
+            file: first_option.file
+        },
+        replacement: arms.concat(),
+        machine_applicable: false
+    }))
+}
+
 fn check_case_option(table: &mut SymbolTable, types: &TypeTable, opt: &parser::CaseOptionNode) -> Result<CaseOptionNode, SpruceErr> {
     table.push_layer();
     let pattern = check_case_pattern(table, types, &opt.val.pattern)?;
@@ -531,26 +1202,53 @@ fn check_case_option(table: &mut SymbolTable, types: &TypeTable, opt: &parser::C
 }
 
 fn check_case_pattern(table: &mut SymbolTable, types: &TypeTable, pattern: &parser::CasePatternNode) -> Result<CasePatternNode, SpruceErr> {
-    let id = match types.get_value(&pattern.val.base) {
-        Some(val) => {
-            val.id
+    let (base, args) = match &pattern.val {
+        parser::CasePattern::ADT(base, args) => (base, args),
+        parser::CasePattern::Lit(val) => {
+            return Ok(CasePatternNode {
+                val: CasePattern::Lit(*val),
+                info: pattern.info.clone()
+            });
         }
-        None => {
-            return Err(SpruceErr {
-                message: String::from(format!("'{}' is not an ADT value", pattern.val.base)),
+        parser::CasePattern::Any => {
+            return Ok(CasePatternNode {
+                val: CasePattern::Any,
                 info: pattern.info.clone()
             });
         }
     };
 
+    let not_constructor = || {
+        suggest(SpruceErr::new(
+            String::from(format!("'{}' is not an ADT value", base)),
+            pattern.info.clone()
+        ).with_code(ErrorCode::NotAConstructor), base, Some(pattern.info.span.start), types.value_names())
+    };
+
+    let id = match types.get_value(base) {
+        Some(val) => {
+            val.id
+        }
+        None if base.contains('.') => {
+            match resolve(table, types, base, &pattern.info)? {
+                Resolved::Value(id) => id,
+                Resolved::Symbol(_) => return Err(not_constructor())
+            }
+        }
+        None if types.private_value_owner(base).is_some() => {
+            let module = types.private_value_owner(base).unwrap();
+            return Err(private(base, module, pattern.info.clone()));
+        }
+        None => return Err(not_constructor())
+    };
+
     let mut arg_symbols = Vec::new();
-    for arg in &pattern.val.args {
-        match table.attempt_insert(&arg, SymbolType::Const) {
+    for arg in args {
+        match table.attempt_insert(&arg, SymbolType::Const, &pattern.info) {
             Some(id) => {
                 arg_symbols.push(id);
             }
             None => {
-                // TODO: implement variable shadowing with arguments
                 return Err(double_decl(arg, pattern.info.clone()));
             }
         }
@@ -558,7 +1256,7 @@ fn check_case_pattern(table: &mut SymbolTable, types: &TypeTable, pattern: &pars
 
 
     Ok(CasePatternNode {
-        val: CasePattern {base: id, args: arg_symbols},
+        val: CasePattern::ADT(id, arg_symbols),
         info: pattern.info.clone()
     })
 }
@@ -566,7 +1264,7 @@ fn check_case_pattern(table: &mut SymbolTable, types: &TypeTable, pattern: &pars
 fn check_target(table: &mut SymbolTable, tgt: &parser::TargetNode) -> Result<TargetNode, SpruceErr> {
     let tgt_val = match &tgt.val {
         parser::Target::Var(name) => {
-            let id_result = table.attempt_insert(name, SymbolType::Const);
+            let id_result = table.attempt_insert(name, SymbolType::Const, &tgt.info);
             match id_result {
                 Some(id) => Ok(Target::Var(id)),
                 None => {
@@ -575,7 +1273,7 @@ fn check_target(table: &mut SymbolTable, tgt: &parser::TargetNode) -> Result<Tar
             }
         }
         parser::Target::Mutable(name) => {
-            let id_result = table.attempt_insert(&name, SymbolType::Mutable);
+            let id_result = table.attempt_insert(&name, SymbolType::Mutable, &tgt.info);
             match id_result {
                 Some(id) => Ok(Target::Mutable(id)),
                 None => Err(double_decl(name, tgt.info.clone()))
@@ -588,16 +1286,16 @@ fn check_target(table: &mut SymbolTable, tgt: &parser::TargetNode) -> Result<Tar
                 Some(sym) => {
                     match sym.sym_type {
                         SymbolType::Mutable => Ok(Target::Update(sym.id)),
-                        _ => Err(SpruceErr {
-                            message: String::from(format!("attempt to update non-mutable '{}'", name)),
-                            info: tgt.info.clone()
-                        })
+                        _ => Err(SpruceErr::new(
+                            String::from(format!("attempt to update non-mutable '{}'", name)),
+                            tgt.info.clone()
+                        ).with_code(ErrorCode::InvalidUpdate))
                     }
                 }
-                None => Err(SpruceErr {
-                    message: String::from(format!("'{}' not declared before attempting update", name)),
-                    info: tgt.info.clone()
-                })
+                None => Err(suggest(SpruceErr::new(
+                    String::from(format!("'{}' not declared before attempting update", name)),
+                    tgt.info.clone()
+                ).with_code(ErrorCode::UnboundName), name, Some(tgt.info.span.start), table.mutable_names()))
             }
         }
     }?;
@@ -608,18 +1306,107 @@ fn check_target(table: &mut SymbolTable, tgt: &parser::TargetNode) -> Result<Tar
     })
 }
 
-fn check_expr(table: &SymbolTable, types: &TypeTable, expr: &parser::ExprNode) -> Result<ExprNode, SpruceErr> {
+/// The error for a name that isn't in scope, which explains when the name
+/// is declared but hidden by an imported module's export list, or when it
+/// needs qualifying to say which import it means
+fn unbound(table: &SymbolTable, types: &TypeTable, name: &String, info: NodeInfo, candidates: Vec<&String>) -> SpruceErr {
+    if let Some(modules) = table.ambiguous.get(name) {
+        return ambiguous(name, modules, info);
+    }
+
+    let owner = table.hidden.get(name).or_else(|| types.private_value_owner(name));
+    match owner {
+        Some(module) if table.lookup(name).is_none() => private(name, module, info),
+        _ => undeclared(name, info, candidates)
+    }
+}
+
+/// What a name in an expression refers to
+enum Resolved {
+    Symbol(SymbolID),
+    Value(ADTValID)
+}
+
+/// Resolves a name used as a value to a symbol or a constructor. A name
+/// qualified as `Module.name` is looked up among what that module shows
+/// this one, and nowhere else
+fn resolve(table: &SymbolTable, types: &TypeTable, name: &String, info: &NodeInfo) -> Result<Resolved, SpruceErr> {
+    let (module, base) = match name.split_once('.') {
+        Some((module, base)) => (String::from(module), String::from(base)),
+        None => {
+            return match (table.lookup(name), types.get_value(name)) {
+                (Some(sym), _) => Ok(Resolved::Symbol(sym.id)),
+                (_, Some(val)) => Ok(Resolved::Value(val.id)),
+                (None, None) => Err(unbound(table, types, name, info.clone(), values_in_scope(table, types)))
+            };
+        }
+    };
+
+    let layer = match table.qualifiers.get(&module) {
+        Some(layer) => layer,
+        None => {
+            let err = SpruceErr::new(
+                format!("module '{}' is not imported", module),
+                info.clone()
+            ).with_code(ErrorCode::UnknownModule);
+            return Err(suggest(err, &module, Some(info.span.start), table.qualifiers.keys().collect()));
+        }
+    };
+
+    let value = types.get_value(&base).filter(|val| val.module == module);
+    match (layer.get(&base), value) {
+        (Some(sym), _) => Ok(Resolved::Symbol(sym.id)),
+        (_, Some(val)) => Ok(Resolved::Value(val.id)),
+        (None, None) if table.hidden.get(&base) == Some(&module) || types.private_value_owner(&base) == Some(&module) => {
+            Err(private(&base, &module, info.clone()))
+        }
+        (None, None) => {
+            let mut candidates: Vec<&String> = layer.keys().collect();
+            candidates.extend(types.values.values().filter(|val| val.module == module && types.visible_values.contains(&val.id)).map(|val| &val.name));
+
+            let err = SpruceErr::new(
+                format!("'{}' is not declared in module '{}'", base, module),
+                info.clone()
+            ).with_code(ErrorCode::UnboundName);
+            Err(suggest(err, &base, Some(info.span.start + module.len() + 1), candidates))
+        }
+    }
+}
+
+/// Everything an identifier in an expression could refer to
+fn values_in_scope<'a>(table: &'a SymbolTable, types: &'a TypeTable) -> Vec<&'a String> {
+    let mut names = table.visible_names();
+    names.extend(types.value_names());
+    names
+}
+
+fn check_expr(table: &mut SymbolTable, types: &TypeTable, expr: &parser::ExprNode) -> Result<ExprNode, SpruceErr> {
     let expr_val = match &expr.val {
         parser::Expr::Id(name) => {
-            match (table.lookup(&name), types.get_value(&name)) {
-                (Some(sym), _) => Ok(Expr::Id(sym.id)),
-                (_, Some(val)) => Ok(Expr::ADTVal(val.id, vec![])),
-                (None, None) => Err(undeclared(name, expr.info.clone()))
+            match resolve(table, types, name, &expr.info)? {
+                Resolved::Symbol(id) => {
+                    table.mark_used(id);
+                    Ok(Expr::Id(id))
+                }
+                Resolved::Value(id) => Ok(Expr::ADTVal(id, vec![]))
             }
         }
 
         parser::Expr::Lit(val) => Ok(Expr::Lit(*val)),
 
+        parser::Expr::Neg(inner) => {
+            let checked = check_expr(table, types, &*inner)?;
+            Ok(Expr::Neg(Box::from(checked)))
+        }
+
+        parser::Expr::List(elements) => {
+            let mut checked_elements = Vec::new();
+            for elem in elements {
+                checked_elements.push(Box::from(check_expr(table, types, &*elem)?));
+            }
+            Ok(Expr::List(checked_elements))
+        }
+
         parser::Expr::Add(l, r) => {
             let left = check_expr(table, types, &*l)?;
             let right = check_expr(table, types, &*r)?;
@@ -680,32 +1467,72 @@ fn check_expr(table: &SymbolTable, types: &TypeTable, expr: &parser::ExprNode) -
             let right = check_expr(table, types, &*r)?;
             Ok(Expr::Gt(Box::from(left), Box::from(right)))
         }
+        parser::Expr::BitAnd(l, r) => {
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::BitAnd(Box::from(left), Box::from(right)))
+        }
+        parser::Expr::BitOr(l, r) => {
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::BitOr(Box::from(left), Box::from(right)))
+        }
+        parser::Expr::BitXor(l, r) => {
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::BitXor(Box::from(left), Box::from(right)))
+        }
+        parser::Expr::Shl(l, r) => {
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::Shl(Box::from(left), Box::from(right)))
+        }
+        parser::Expr::Shr(l, r) => {
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::Shr(Box::from(left), Box::from(right)))
+        }
+        // `x :: rest` is sugar for the prelude's `Cons(x, rest)`
+        parser::Expr::Cons(l, r) => {
+            let cons = types.registry.value_id(LangValue::Cons);
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::ADTVal(cons, vec![Box::from(left), Box::from(right)]))
+        }
+        parser::Expr::ComposeR(l, r) => {
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::ComposeR(Box::from(left), Box::from(right)))
+        }
+        parser::Expr::ComposeL(l, r) => {
+            let left = check_expr(table, types, &*l)?;
+            let right = check_expr(table, types, &*r)?;
+            Ok(Expr::ComposeL(Box::from(left), Box::from(right)))
+        }
 
         parser::Expr::FnCall(fn_name, args) => {
-            match (table.lookup(&fn_name), types.get_value(&fn_name)) {
+            match resolve(table, types, fn_name, &expr.info)? {
+
+                Resolved::Symbol(id) => {
+                    table.mark_used(id);
 
-                (Some(sym), _) => {
                     let mut checked_args = Vec::new();
                     for arg in args {
                         let checked = check_expr(table, types, &*arg)?;
                         checked_args.push(Box::from(checked));
                     }
 
-                    Ok(Expr::FnCall(sym.id, checked_args))
+                    Ok(Expr::FnCall(id, checked_args))
                 }
 
-                (_, Some(value)) => {
+                Resolved::Value(id) => {
                     let mut checked_args = Vec::new();
                     for arg in args {
                         let checked = check_expr(table, types, &*arg)?;
                         checked_args.push(Box::from(checked));
                     }
 
-                    Ok(Expr::ADTVal(value.id, checked_args))
-                }
-
-                (None, None) => {
-                    return Err(undeclared(fn_name, expr.info.clone()));
+                    Ok(Expr::ADTVal(id, checked_args))
                 }
             }
         }
@@ -734,6 +1561,9 @@ pub enum TypeID {
     TParam(TParamID),
     ADT(ADTID, Vec<Box<TypeID>>),
     Prim(String),
+    // only written in interface files
+    Func(Vec<Box<TypeID>>, Box<TypeID>),
+    Unit
 }
 
 #[derive(Debug, PartialEq)]
@@ -741,14 +1571,16 @@ pub struct ADTValue {
     pub id: ADTValID,
     pub name: String,
     pub args: Vec<TypeID>,
-    pub data_type: ADTID
+    pub data_type: ADTID,
+    pub module: String
 }
 
 #[derive(Debug, PartialEq)]
 pub struct ADT {
     pub id: ADTID,
     pub type_params: Vec<TParamID>,
-    pub name: String
+    pub name: String,
+    pub module: String
 }
 
 #[derive(Debug, PartialEq)]
@@ -759,7 +1591,14 @@ struct TypeTable {
     primitives: HashSet<String>,
     types: HashMap<String, ADT>,
     values: HashMap<String, ADTValue>,
-    type_params: HashMap<TParamID, TParam>
+    type_params: HashMap<TParamID, TParam>,
+    // the types and constructors that can currently be named, and the
+    // modules they might otherwise have been imported from. Type names are
+    // unique across the program, visible or not
+    visible_types: HashSet<ADTID>,
+    visible_values: HashSet<ADTValID>,
+    imported: HashSet<String>,
+    registry: Registry
 }
 
 /// Version of type table that is exported. Note that values are indexed by
@@ -768,13 +1607,14 @@ struct TypeTable {
 pub struct TypeTableExt {
     pub types: HashMap<ADTID, ADT>,
     pub values: HashMap<ADTValID, ADTValue>,
+    pub type_params: HashMap<TParamID, TParam>,
     pub primitives: HashSet<String>
 }
 
 impl TypeTable {
     fn new() -> Self {
         // TODO: figure out the proper way to do this in rust
-        let primitives = vec![String::from("Int"), String::from("Float"), String::from("Char")];
+        let primitives = vec![String::from("Int"), String::from("Float"), String::from("Char"), String::from("String")];
 
         TypeTable {
             next_type_id: 0,
@@ -783,12 +1623,38 @@ impl TypeTable {
             primitives: HashSet::from_iter(primitives),
             types: HashMap::default(),
             values: HashMap::default(),
-            type_params: HashMap::default()
+            type_params: HashMap::default(),
+            visible_types: HashSet::new(),
+            visible_values: HashSet::new(),
+            imported: HashSet::new(),
+            registry: Registry::new()
         }
     }
 
-    fn add_type(&mut self, name: &String, params: Vec<TParamID>) {
-        let new_adt = ADT {name: name.clone(), id: self.next_type_id, type_params: params};
+    /// Restricts the types and constructors that can be named to those of
+    /// `module` and the prelude, along with whatever its imports export
+    fn set_scope(&mut self, module: &Module, prelude: &Option<String>, modules: &Vec<Module>) {
+        self.imported = module.imports.iter().map(|import| import.val.module.clone()).collect();
+
+        let visible = |owner: &String, exported: &dyn Fn(&Module) -> bool| {
+            *owner == module.name || Some(owner) == prelude.as_ref() || (self.imported.contains(owner) && modules.iter().any(|m| m.name == *owner && exported(m)))
+        };
+
+        let visible_types: HashSet<ADTID> = self.types.values().filter(|adt| {
+            visible(&adt.module, &|m| m.exports(&adt.name))
+        }).map(|adt| adt.id).collect();
+
+        let type_names: HashMap<ADTID, &String> = self.types.values().map(|adt| (adt.id, &adt.name)).collect();
+        let visible_values: HashSet<ADTValID> = self.values.values().filter(|val| {
+            visible(&val.module, &|m| m.exports_constructors(type_names[&val.data_type]))
+        }).map(|val| val.id).collect();
+
+        self.visible_types = visible_types;
+        self.visible_values = visible_values;
+    }
+
+    fn add_type(&mut self, name: &String, params: Vec<TParamID>, module: &String) {
+        let new_adt = ADT {name: name.clone(), id: self.next_type_id, type_params: params, module: module.clone()};
         self.next_type_id += 1;
         self.types.insert(name.clone(), new_adt);
     }
@@ -797,7 +1663,7 @@ impl TypeTable {
         let mut r = self.types.get_mut(data_type);
         match r {
             Some(adt) => {
-                let new_val = ADTValue {name: name.clone(), args: (*args).clone(), data_type: adt.id, id: self.next_val_id};
+                let new_val = ADTValue {name: name.clone(), args: (*args).clone(), data_type: adt.id, id: self.next_val_id, module: adt.module.clone()};
                 self.next_val_id += 1;
                 self.values.insert(new_val.name.clone(), new_val);
             }
@@ -814,11 +1680,27 @@ impl TypeTable {
     }
 
     pub fn get_type(&self, name: &String) -> Option<&ADT> {
-        self.types.get(name)
+        self.types.get(name).filter(|adt| self.visible_types.contains(&adt.id))
     }
 
     pub fn get_value(&self, name: &String) -> Option<&ADTValue> {
-        self.values.get(name)
+        self.values.get(name).filter(|val| self.visible_values.contains(&val.id))
+    }
+
+    /// The imported module a constructor belongs to, if it exists but that
+    /// module doesn't export it
+    fn private_value_owner(&self, name: &String) -> Option<&String> {
+        self.values.get(name).filter(|val| {
+            !self.visible_values.contains(&val.id) && self.imported.contains(&val.module)
+        }).map(|val| &val.module)
+    }
+
+    /// The imported module a type belongs to, if it exists but that module
+    /// doesn't export it
+    fn private_type_owner(&self, name: &String) -> Option<&String> {
+        self.types.get(name).filter(|adt| {
+            !self.visible_types.contains(&adt.id) && self.imported.contains(&adt.module)
+        }).map(|adt| &adt.module)
     }
 
     pub fn get_tparam(&self, id: &TParamID) -> Option<&TParam> {
@@ -829,6 +1711,15 @@ impl TypeTable {
         self.types.contains_key(name) || self.primitives.contains(name)
     }
     
+    fn value_names(&self) -> Vec<&String> {
+        self.values.values().filter(|val| self.visible_values.contains(&val.id)).map(|val| &val.name).collect()
+    }
+
+    fn type_names(&self) -> Vec<&String> {
+        let types = self.types.values().filter(|adt| self.visible_types.contains(&adt.id)).map(|adt| &adt.name);
+        types.chain(self.primitives.iter()).collect()
+    }
+
     fn has_value(&self, name: &String) -> bool {
         self.values.contains_key(name)
     }
@@ -837,6 +1728,7 @@ impl TypeTable {
         TypeTableExt {
             types: self.types.into_iter().map(|(k, v)| {(v.id, v)}).collect(),
             values: self.values.into_iter().map(|(k, v)| {(v.id, v)}).collect(),
+            type_params: self.type_params,
             primitives: self.primitives
         }
     }
@@ -844,7 +1736,7 @@ impl TypeTable {
 
 /// Makes two passes, first over types and second over their values this is
 /// because values might contain other ADTs
-fn analyze_types(prog: & parser::Prog) -> Result<(Vec<TypeNode>, TypeTable), SpruceErr>{
+fn analyze_types(prog: & parser::Prog, modules: &Vec<Module>, prelude: &Option<String>) -> Result<(Vec<TypeNode>, TypeTable), SpruceErr>{
     let mut type_table = TypeTable::new();
 
     for t in &prog.types {
@@ -853,10 +1745,17 @@ fn analyze_types(prog: & parser::Prog) -> Result<(Vec<TypeNode>, TypeTable), Spr
         }
 
         if !t.val.name.chars().next().unwrap().is_uppercase() {
-            return Err(SpruceErr {
-                message: String::from(format!("{} is an invalid type name: types must be uppercase", t.val.name)),
-                info: t.info.clone()
-            });
+            return Err(SpruceErr::new(
+                String::from(format!("{} is an invalid type name: types must be uppercase", t.val.name)),
+                t.info.clone()
+            ).with_code(ErrorCode::LowercaseType));
+        }
+
+        if t.val.builtin && Some(&module_of(modules, t.info.file).name) != prelude.as_ref() {
+            return Err(SpruceErr::new(
+                format!("'{}' is declared as a builtin outside of the prelude", t.val.name),
+                t.info.clone()
+            ).with_code(ErrorCode::MisplacedBuiltin));
         }
 
         let mut params = Vec::new();
@@ -864,11 +1763,12 @@ fn analyze_types(prog: & parser::Prog) -> Result<(Vec<TypeNode>, TypeTable), Spr
             let id = type_table.add_tparam(&param);
             params.push(id);
         }
-        type_table.add_type(&t.val.name, params);
+        type_table.add_type(&t.val.name, params, &module_of(modules, t.info.file).name);
     }
 
     for t in &prog.types {
-        let type_symbol = type_table.get_type(&t.val.name).expect("unreachable");
+        type_table.set_scope(module_of(modules, t.info.file), prelude, modules);
+        let type_symbol = type_table.types.get(&t.val.name).expect("unreachable");
         let params: HashMap<String, TParamID> = type_symbol.type_params.iter().map(|id| {
             let tparam = type_table.get_tparam(id).expect("unreachable");
             (tparam.name.clone(), tparam.id)
@@ -899,7 +1799,9 @@ fn analyze_types(prog: & parser::Prog) -> Result<(Vec<TypeNode>, TypeTable), Spr
                         val: TypeOption {name: o.val.name.clone(), args: o.val.args.iter().map(|id| {id.name.clone()}).collect() },
                         info: o.info.clone()
                     }
-                }).collect()
+                }).collect(),
+                doc: t.val.doc.clone(),
+                builtin: t.val.builtin
             },
             info: t.info.clone()
         }
@@ -914,7 +1816,7 @@ fn check_type_identifier(ident: &parser::TypeIdentifier, params: &HashMap<String
         args.push(Box::from(check_type_identifier(&**arg, params, type_table, &info)?));
     }
 
-    match (params.get(&ident.name), type_table.types.get(&ident.name), type_table.primitives.get(&ident.name)) {
+    match (params.get(&ident.name), type_table.get_type(&ident.name), type_table.primitives.get(&ident.name)) {
         (Some(tparam_id), _, _) => {
             Ok(TypeID::TParam(*tparam_id))
         }
@@ -924,11 +1826,67 @@ fn check_type_identifier(ident: &parser::TypeIdentifier, params: &HashMap<String
         (_, _, Some(s)) => {
             Ok(TypeID::Prim(s.clone()))
         }
+        _ if type_table.private_type_owner(&ident.name).is_some() => {
+            let module = type_table.private_type_owner(&ident.name).unwrap();
+            Err(private(&ident.name, module, info.clone()))
+        }
         _ => {
-            return Err(SpruceErr {
-                message: String::from(format!("type does not exist: {}", ident.name)),
-                info: info.clone()
-            });
+            let mut candidates = type_table.type_names();
+            candidates.extend(params.keys());
+            return Err(suggest(SpruceErr::new(
+                String::from(format!("type does not exist: {}", ident.name)),
+                info.clone()
+            ).with_code(ErrorCode::UnknownType), &ident.name, None, candidates));
+        }
+    }
+}
+
+/// Resolves the names in an interface's signature, where any lowercase name
+/// is a type variable
+fn check_signature(type_table: &mut TypeTable, sig: &parser::SignatureNode, id: SymbolID) -> Result<SignatureNode, SpruceErr> {
+    let mut params = HashMap::new();
+    let ty = check_sig_type(&sig.val.ty, &mut params, type_table, &sig.info)?;
+    let mut params: Vec<TParamID> = params.into_iter().map(|(_, id)| id).collect();
+    params.sort();
+
+    Ok(SignatureNode {
+        val: Signature { name: id, ty: ty, params: params },
+        info: sig.info.clone()
+    })
+}
+
+fn check_sig_type(ty: &parser::SigType, params: &mut HashMap<String, TParamID>, type_table: &mut TypeTable, info: &NodeInfo) -> Result<TypeID, SpruceErr> {
+    match ty {
+        parser::SigType::Unit => Ok(TypeID::Unit),
+        parser::SigType::Func(args, out) => {
+            let mut arg_ids = Vec::new();
+            for arg in args {
+                arg_ids.push(Box::from(check_sig_type(arg, params, type_table, info)?));
+            }
+            Ok(TypeID::Func(arg_ids, Box::from(check_sig_type(out, params, type_table, info)?)))
+        }
+        parser::SigType::Named(name, args) if args.is_empty() && name.chars().next().map_or(false, char::is_lowercase) => {
+            let id = match params.get(name) {
+                Some(id) => *id,
+                None => {
+                    let id = type_table.add_tparam(name);
+                    params.insert(name.clone(), id);
+                    id
+                }
+            };
+            Ok(TypeID::TParam(id))
+        }
+        parser::SigType::Named(name, args) => {
+            let mut arg_ids = Vec::new();
+            for arg in args {
+                arg_ids.push(Box::from(check_sig_type(arg, params, type_table, info)?));
+            }
+
+            let ident = parser::TypeIdentifier { name: name.clone(), args: Vec::new() };
+            match check_type_identifier(&ident, &HashMap::new(), type_table, info)? {
+                TypeID::ADT(id, _) => Ok(TypeID::ADT(id, arg_ids)),
+                other => Ok(other)
+            }
         }
     }
 }
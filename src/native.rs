@@ -0,0 +1,753 @@
+/*
+The native target builds a standalone executable from a checked program. The
+program's A-normal form is lowered to a smaller IR first: functions of basic
+blocks, whose instructions work on 64-bit words in numbered registers, with
+each variable in a stack slot of its own. From there it's written out as
+LLVM IR, which LLVM's `opt` and `llc` optimize and compile to an object file,
+and the C compiler links with the runtime in runtime.c.
+
+Values are represented the way the runtime describes: Ints are tagged by
+being stored as 2n + 1, so they are 63 bits wide in native code, and
+everything else is a pointer to an object the runtime allocates. Arithmetic
+wraps at 63 bits rather than 64, and a literal that needs the 64th is an error
+here. Only the
+functions the program can reach are compiled, and only some of the builtins
+have native versions, so using one that doesn't is an error here rather than
+when the program runs.
+*/
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use crate::anf;
+use crate::error::SpruceErr;
+use crate::eval;
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+use crate::registry::LangValue;
+use crate::source::SourceMap;
+
+pub const RUNTIME: &str = include_str!("runtime.c");
+
+/// The builtins the runtime has native versions of
+const NATIVE_BUILTINS: [&str; 16] = ["show", "compare", "print", "printLine", "debug", "abs", "min", "max", "toFloat", "sqrt", "floor", "ceil", "sin", "cos", "tan", "args"];
+
+// the kinds of object the runtime allocates
+const ADT_KIND: i64 = 0;
+
+// where an object's fields start, after its kind, tag and field count
+const FIELDS: u32 = 3;
+
+pub type Reg = u32;
+pub type BlockId = u32;
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operand {
+    Reg(Reg),
+    Const(i64),
+    // the address of a function, with the number of arguments it takes
+    Func(String, usize),
+    // the address of a string constant
+    Str(u32)
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum CmpOp {
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Inst {
+    Bin(Reg, BinOp, Operand, Operand),
+    // gives 1 if the comparison holds and 0 if not
+    Cmp(Reg, CmpOp, Operand, Operand),
+    Call(Reg, String, Vec<Operand>),
+    // a word of an object, by its index
+    Load(Reg, Operand, u32),
+    Store(Operand, u32, Operand),
+    LoadGlobal(Reg, u32),
+    StoreGlobal(u32, Operand),
+    LoadSlot(Reg, u32),
+    StoreSlot(u32, Operand)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Term {
+    Return(Operand),
+    Jump(BlockId),
+    // to the first block if the operand isn't 0
+    Branch(Operand, BlockId, BlockId),
+    Switch(Operand, Vec<(i64, BlockId)>, BlockId),
+    // stops the program with a message, both of which are string constants
+    Fail(u32, u32),
+    // stops the program because a case had no option for the value
+    NoMatch(Operand, u32),
+    Unreachable
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Block {
+    pub insts: Vec<Inst>,
+    pub term: Term
+}
+
+/// The first `params` registers hold the arguments
+#[derive(Debug, PartialEq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub params: usize,
+    pub slots: u32,
+    pub blocks: Vec<Block>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Module {
+    pub functions: Vec<Function>,
+    pub globals: u32,
+    pub strings: Vec<String>,
+    // constructor names, indexed by id
+    pub constructors: Vec<String>,
+    // the numbers of arguments functions are called with as values
+    pub applies: BTreeSet<usize>,
+    // the builtins that are used, and the number of arguments they take
+    pub builtins: Vec<(String, usize)>,
+    // the ids of the constructors the runtime makes itself
+    pub runtime_ids: Vec<(&'static str, na::ADTValID)>,
+    pub entry: String
+}
+
+struct Lowering<'a> {
+    sources: &'a SourceMap,
+    // what each function is called, and takes
+    functions: HashMap<na::SymbolID, (String, usize)>,
+    builtins: HashMap<na::SymbolID, (String, usize)>,
+    globals: HashMap<na::SymbolID, u32>,
+    strings: Vec<String>,
+    applies: BTreeSet<usize>,
+
+    // the function being lowered, whose variables each have the slot of
+    // the same number
+    blocks: Vec<Block>,
+    current: BlockId,
+    next_reg: Reg,
+    next_slot: u32,
+    params: Vec<anf::Var>,
+    // where the function starts over from
+    top: BlockId
+}
+
+/// Lowers a program to the native target's IR
+pub fn lower(prog: &na::Prog, program: &anf::Program, sources: &SourceMap) -> Result<Module, SpruceErr> {
+    let mut lowering = Lowering {
+        sources: sources,
+        functions: program.functions.iter().filter_map(|func| func.name.map(|name| (name, (mangle(prog, name), func.params.len())))).collect(),
+        builtins: HashMap::new(),
+        globals: program.globals.iter().enumerate().map(|(i, id)| (*id, i as u32)).collect(),
+        strings: Vec::new(),
+        applies: BTreeSet::new(),
+        blocks: Vec::new(),
+        current: 0,
+        next_reg: 0,
+        next_slot: 0,
+        params: Vec::new(),
+        top: 0
+    };
+    anf::tagged_ints(program, "native")?;
+    for (id, builtin, arity) in anf::used_builtins(prog, &program.builtins, "native", &NATIVE_BUILTINS)? {
+        lowering.builtins.insert(id, (format!("spruce_builtin_{}", builtin), arity));
+    }
+
+    let mut functions = Vec::new();
+    for func in &program.functions {
+        let name = lowering.functions[&func.name.expect("functions are named")].0.clone();
+        functions.push(lowering.function(&name, func));
+    }
+    functions.push(lowering.function("spruce_init", &program.init));
+
+    let mut builtins: Vec<(String, usize)> = lowering.builtins.values().cloned().collect();
+    builtins.sort();
+    Ok(Module {
+        functions: functions,
+        globals: lowering.globals.len() as u32,
+        strings: lowering.strings,
+        constructors: constructor_names(prog),
+        applies: lowering.applies,
+        builtins: builtins,
+        runtime_ids: vec![
+            ("spruce_true_id", prog.registry.value_id(LangValue::True)),
+            ("spruce_false_id", prog.registry.value_id(LangValue::False)),
+            ("spruce_cons_id", prog.registry.value_id(LangValue::Cons)),
+            ("spruce_nil_id", prog.registry.value_id(LangValue::Nil)),
+            ("spruce_lt_id", prog.registry.value_id(LangValue::LT)),
+            ("spruce_eq_id", prog.registry.value_id(LangValue::EQ)),
+            ("spruce_gt_id", prog.registry.value_id(LangValue::GT))
+        ],
+        entry: lowering.functions[&program.entry].0.clone()
+    })
+}
+
+/// A name for a function that is unique and fit for C and LLVM
+pub fn mangle(prog: &na::Prog, id: na::SymbolID) -> String {
+    let clean: String = eval::symbol_name(prog, id).chars().map(|c| if c.is_ascii_alphanumeric() { c } else { '_' }).collect();
+    format!("sp_{}_{}", clean, id)
+}
+
+/// Constructor names, indexed by id
+pub fn constructor_names(prog: &na::Prog) -> Vec<String> {
+    let mut names = Vec::new();
+    for val in prog.type_table.values.values() {
+        if names.len() <= val.id as usize {
+            names.resize(val.id as usize + 1, String::new());
+        }
+        names[val.id as usize] = val.name.clone();
+    }
+    names
+}
+
+impl<'a> Lowering<'a> {
+    fn function(&mut self, name: &str, func: &anf::Function) -> Function {
+        self.blocks = vec![Block { insts: Vec::new(), term: Term::Unreachable }];
+        self.current = 0;
+        self.next_reg = func.params.len() as Reg;
+        self.next_slot = func.vars.len() as u32;
+        for (i, param) in func.params.iter().enumerate() {
+            self.emit(Inst::StoreSlot(*param, Operand::Reg(i as Reg)));
+        }
+        self.params = func.params.clone();
+        if func.body.recurs() {
+            self.top = self.block();
+            self.terminate(Term::Jump(self.top));
+            self.current = self.top;
+        }
+
+        let result = self.block_value(&func.body);
+        self.terminate(Term::Return(result));
+        Function {
+            name: String::from(name),
+            params: func.params.len(),
+            slots: self.next_slot,
+            blocks: std::mem::take(&mut self.blocks)
+        }
+    }
+
+    fn fresh_slot(&mut self) -> u32 {
+        self.next_slot += 1;
+        self.next_slot - 1
+    }
+
+    fn reg(&mut self) -> Reg {
+        self.next_reg += 1;
+        self.next_reg - 1
+    }
+
+    fn emit(&mut self, inst: Inst) {
+        self.blocks[self.current as usize].insts.push(inst);
+    }
+
+    fn terminate(&mut self, term: Term) {
+        self.blocks[self.current as usize].term = term;
+    }
+
+    fn block(&mut self) -> BlockId {
+        self.blocks.push(Block { insts: Vec::new(), term: Term::Unreachable });
+        (self.blocks.len() - 1) as BlockId
+    }
+
+    fn string(&mut self, text: String) -> u32 {
+        match self.strings.iter().position(|s| *s == text) {
+            Some(i) => i as u32,
+            None => {
+                self.strings.push(text);
+                (self.strings.len() - 1) as u32
+            }
+        }
+    }
+
+    fn location(&mut self, info: &NodeInfo) -> u32 {
+        let location = SpruceErr::new(String::new(), info.clone()).location(self.sources);
+        self.string(location)
+    }
+
+    fn bin(&mut self, op: BinOp, left: Operand, right: Operand) -> Operand {
+        let reg = self.reg();
+        self.emit(Inst::Bin(reg, op, left, right));
+        Operand::Reg(reg)
+    }
+
+    fn call(&mut self, func: &str, args: Vec<Operand>) -> Operand {
+        let reg = self.reg();
+        self.emit(Inst::Call(reg, String::from(func), args));
+        Operand::Reg(reg)
+    }
+
+    fn untag(&mut self, val: Operand) -> Operand {
+        self.bin(BinOp::Shr, val, Operand::Const(1))
+    }
+
+    fn tag(&mut self, val: Operand) -> Operand {
+        let shifted = self.bin(BinOp::Shl, val, Operand::Const(1));
+        self.bin(BinOp::Or, shifted, Operand::Const(1))
+    }
+
+    fn bool(&mut self, flag: Operand) -> Operand {
+        self.call("spruce_bool", vec![flag])
+    }
+
+    fn alloc(&mut self, id: na::ADTValID, fields: Vec<Operand>) -> Operand {
+        let obj = self.call("spruce_alloc", vec![Operand::Const(ADT_KIND), Operand::Const(id as i64), Operand::Const(fields.len() as i64)]);
+        for (i, field) in fields.into_iter().enumerate() {
+            self.emit(Inst::Store(obj.clone(), FIELDS + i as u32, field));
+        }
+        obj
+    }
+
+    fn block_value(&mut self, block: &anf::Block) -> Operand {
+        for stmt in &block.stmts {
+            match stmt {
+                anf::Stmt::Let(var, val) => {
+                    let val = self.value(val);
+                    self.emit(Inst::StoreSlot(*var, val));
+                }
+                anf::Stmt::SetGlobal(id, atom) => {
+                    let val = self.atom(atom);
+                    self.emit(Inst::StoreGlobal(self.globals[id], val));
+                }
+            }
+        }
+        self.value(&block.result)
+    }
+
+    fn case(&mut self, case: &anf::Case) -> Operand {
+        let val = self.atom(&case.scrutinee);
+        let result = self.fresh_slot();
+        let merge = self.block();
+
+        // constructors are switched on by their id and numbers by their
+        // tagged value
+        let is_adt = case.arms.iter().any(|arm| matches!(arm.pattern, anf::Pattern::Constructor(_)));
+        let key = if is_adt {
+            let reg = self.reg();
+            self.emit(Inst::Load(reg, val.clone(), 1));
+            Operand::Reg(reg)
+        }
+        else {
+            val.clone()
+        };
+
+        let mut entries: Vec<(i64, BlockId)> = Vec::new();
+        let mut bodies = Vec::new();
+        for arm in &case.arms {
+            let block = self.block();
+            match &arm.pattern {
+                anf::Pattern::Constructor(id) => entries.push((*id as i64, block)),
+                anf::Pattern::Int(n) => entries.push((tagged(*n), block))
+            }
+            bodies.push((block, &arm.body));
+        }
+        let default = self.block();
+        match &case.default {
+            Some(_) => (),
+            None => {
+                let location = self.location(&case.info);
+                self.blocks[default as usize].term = Term::NoMatch(val.clone(), location);
+            }
+        }
+        self.terminate(Term::Switch(key, entries, default));
+
+        for (block, body) in bodies {
+            self.current = block;
+            let value = self.block_value(body);
+            self.emit(Inst::StoreSlot(result, value));
+            self.terminate(Term::Jump(merge));
+        }
+        if let Some(body) = &case.default {
+            self.current = default;
+            let value = self.block_value(body);
+            self.emit(Inst::StoreSlot(result, value));
+            self.terminate(Term::Jump(merge));
+        }
+
+        self.current = merge;
+        let reg = self.reg();
+        self.emit(Inst::LoadSlot(reg, result));
+        Operand::Reg(reg)
+    }
+
+    fn atom(&mut self, atom: &anf::Atom) -> Operand {
+        match atom {
+            anf::Atom::Var(var) => {
+                let reg = self.reg();
+                self.emit(Inst::LoadSlot(reg, *var));
+                Operand::Reg(reg)
+            }
+            anf::Atom::Int(n) => Operand::Const(tagged(*n)),
+            anf::Atom::Unit => Operand::Const(0),
+            anf::Atom::Func(id) => {
+                let (name, arity) = self.functions.get(id).or_else(|| self.builtins.get(id)).cloned().expect("lowering only reaches what was checked to be callable");
+                self.call("spruce_func", vec![Operand::Func(name, arity)])
+            }
+        }
+    }
+
+    fn atoms(&mut self, atoms: &[anf::Atom]) -> Vec<Operand> {
+        atoms.iter().map(|atom| self.atom(atom)).collect()
+    }
+
+    fn arith(&mut self, op: BinOp, left: Operand, right: Operand) -> Operand {
+        let (left, right) = (self.untag(left), self.untag(right));
+        let right = match op {
+            // shifts wrap around, as in the interpreter
+            BinOp::Shl | BinOp::Shr => self.bin(BinOp::And, right, Operand::Const(63)),
+            _ => right
+        };
+        let result = self.bin(op, left, right);
+        self.tag(result)
+    }
+
+    fn divide(&mut self, info: &NodeInfo, op: BinOp, left: Operand, right: Operand) -> Operand {
+        let reg = self.reg();
+        self.emit(Inst::Cmp(reg, CmpOp::Ne, right.clone(), Operand::Const(tagged(0))));
+        let (fail, next) = (self.block(), self.block());
+        self.terminate(Term::Branch(Operand::Reg(reg), next, fail));
+        let (message, location) = (self.string(String::from("division by zero")), self.location(info));
+        self.blocks[fail as usize].term = Term::Fail(message, location);
+        self.current = next;
+
+        let (left, right) = (self.untag(left), self.untag(right));
+        let result = self.bin(op, left, right);
+        self.tag(result)
+    }
+
+    fn compare(&mut self, op: CmpOp, left: Operand, right: Operand) -> Operand {
+        // tagging keeps the order of Ints, so they are compared as they are
+        let reg = self.reg();
+        self.emit(Inst::Cmp(reg, op, left, right));
+        self.bool(Operand::Reg(reg))
+    }
+
+    fn prim(&mut self, prim: anf::Prim, operands: &[anf::Atom], info: &NodeInfo) -> Operand {
+        let mut operands = self.atoms(operands).into_iter();
+        let left = operands.next().expect("operations have an operand");
+        if prim == anf::Prim::Neg {
+            let inner = self.untag(left);
+            let negated = self.bin(BinOp::Sub, Operand::Const(0), inner);
+            return self.tag(negated);
+        }
+        let right = operands.next().expect("operations other than negation have two operands");
+        match prim {
+            anf::Prim::Add => self.arith(BinOp::Add, left, right),
+            anf::Prim::Sub => self.arith(BinOp::Sub, left, right),
+            anf::Prim::Mul => self.arith(BinOp::Mul, left, right),
+            anf::Prim::BitAnd => self.arith(BinOp::And, left, right),
+            anf::Prim::BitOr => self.arith(BinOp::Or, left, right),
+            anf::Prim::BitXor => self.arith(BinOp::Xor, left, right),
+            anf::Prim::Shl => self.arith(BinOp::Shl, left, right),
+            anf::Prim::Shr => self.arith(BinOp::Shr, left, right),
+            anf::Prim::Div => self.divide(info, BinOp::Div, left, right),
+            anf::Prim::Mod => self.divide(info, BinOp::Rem, left, right),
+            anf::Prim::Pow => {
+                let location = self.location(info);
+                self.call("spruce_pow", vec![left, right, Operand::Str(location)])
+            }
+            anf::Prim::Lt => self.compare(CmpOp::Lt, left, right),
+            anf::Prim::Gt => self.compare(CmpOp::Gt, left, right),
+            anf::Prim::LtEq => self.compare(CmpOp::Le, left, right),
+            anf::Prim::GtEq => self.compare(CmpOp::Ge, left, right),
+            anf::Prim::Eq => {
+                let equal = self.call("spruce_equal", vec![left, right]);
+                self.bool(equal)
+            }
+            anf::Prim::NotEq => {
+                let equal = self.call("spruce_equal", vec![left, right]);
+                let flag = self.bin(BinOp::Xor, equal, Operand::Const(1));
+                self.bool(flag)
+            }
+            anf::Prim::Compose => self.call("spruce_compose", vec![left, right]),
+            anf::Prim::Neg => unreachable!("negation was handled above")
+        }
+    }
+
+    fn value(&mut self, val: &anf::Value) -> Operand {
+        match val {
+            anf::Value::Atom(atom) => self.atom(atom),
+            anf::Value::Prim(prim, operands, info) => self.prim(*prim, operands, info),
+            anf::Value::Global(id) => {
+                let reg = self.reg();
+                self.emit(Inst::LoadGlobal(reg, self.globals[id]));
+                Operand::Reg(reg)
+            }
+            anf::Value::Call(id, args) => {
+                let args = self.atoms(args);
+                let (name, _) = self.functions.get(id).or_else(|| self.builtins.get(id)).cloned().expect("lowering only reaches what was checked to be callable");
+                self.call(&name, args)
+            }
+            anf::Value::Apply(func, args) => {
+                let mut all = vec![self.atom(func)];
+                all.extend(self.atoms(args));
+                // compositions pass their result on to their second function
+                self.applies.insert(args.len());
+                self.applies.insert(1);
+                self.call(&format!("spruce_apply{}", args.len()), all)
+            }
+            anf::Value::Construct(id, args) => {
+                let args = self.atoms(args);
+                self.alloc(*id, args)
+            }
+            anf::Value::Field(val, i) => {
+                let val = self.atom(val);
+                let reg = self.reg();
+                self.emit(Inst::Load(reg, val, FIELDS + *i as u32));
+                Operand::Reg(reg)
+            }
+            anf::Value::Case(case) => self.case(case),
+            anf::Value::Recur(args) => {
+                let args = self.atoms(args);
+                for (param, arg) in self.params.clone().into_iter().zip(args) {
+                    self.emit(Inst::StoreSlot(param, arg));
+                }
+                self.terminate(Term::Jump(self.top));
+                // what follows is never reached, but still needs a block
+                self.current = self.block();
+                Operand::Const(0)
+            }
+        }
+    }
+}
+
+/// An Int as native code stores it
+fn tagged(n: i64) -> i64 {
+    n.wrapping_shl(1) | 1
+}
+
+/// Writes a module out as LLVM IR
+pub fn emit_llvm(module: &Module) -> String {
+    let mut out = String::from("; generated by spruce\n\n");
+
+    let names: Vec<String> = module.constructors.iter().enumerate().map(|(i, name)| {
+        let _ = writeln!(out, "@.name.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"", i, name.len() + 1, escape(name));
+        format!("i8* getelementptr inbounds ([{} x i8], [{} x i8]* @.name.{}, i64 0, i64 0)", name.len() + 1, name.len() + 1, i)
+    }).collect();
+    let _ = writeln!(out, "@spruce_names = constant [{} x i8*] [{}]", names.len(), names.join(", "));
+    for (name, id) in &module.runtime_ids {
+        let _ = writeln!(out, "@{} = constant i64 {}", name, id);
+    }
+    for (i, text) in module.strings.iter().enumerate() {
+        let _ = writeln!(out, "@.str.{} = private unnamed_addr constant [{} x i8] c\"{}\\00\"", i, text.len() + 1, escape(text));
+    }
+    for i in 0..module.globals {
+        let _ = writeln!(out, "@g.{} = internal global i64 0", i);
+    }
+    out.push('\n');
+
+    out.push_str("declare i64 @spruce_alloc(i64, i64, i64)\n");
+    out.push_str("declare i64 @spruce_func(i64)\n");
+    out.push_str("declare i64 @spruce_compose(i64, i64)\n");
+    out.push_str("declare i64 @spruce_bool(i64)\n");
+    out.push_str("declare i64 @spruce_equal(i64, i64)\n");
+    out.push_str("declare i64 @spruce_pow(i64, i64, i8*)\n");
+    out.push_str("declare void @spruce_fail(i8*, i8*) noreturn\n");
+    out.push_str("declare void @spruce_no_match(i64, i8*) noreturn\n");
+    for (name, arity) in &module.builtins {
+        let _ = writeln!(out, "declare i64 @{}({})", name, vec!["i64"; *arity].join(", "));
+    }
+    out.push('\n');
+
+    for func in &module.functions {
+        emit_function(&mut out, module, func);
+    }
+
+    let _ = writeln!(out, "define i64 @spruce_main() {{\n  %r = call i64 @{}()\n  ret i64 %r\n}}\n", module.entry);
+    for arity in &module.applies {
+        emit_apply(&mut out, *arity);
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.bytes().map(|b| match b {
+        b'"' | b'\\' => format!("\\{:02X}", b),
+        b if b.is_ascii_graphic() || b == b' ' => (b as char).to_string(),
+        b => format!("\\{:02X}", b)
+    }).collect()
+}
+
+fn emit_function(out: &mut String, module: &Module, func: &Function) {
+    let params: Vec<String> = (0..func.params).map(|i| format!("i64 %r{}", i)).collect();
+    let linkage = if func.name == "spruce_init" { "" } else { "internal " };
+    let _ = writeln!(out, "define {}i64 @{}({}) {{", linkage, func.name, params.join(", "));
+
+    let mut temp = 0;
+    for (i, block) in func.blocks.iter().enumerate() {
+        let _ = writeln!(out, "b{}:", i);
+        if i == 0 {
+            for slot in 0..func.slots {
+                let _ = writeln!(out, "  %s{} = alloca i64", slot);
+            }
+        }
+
+        let mut word = |out: &mut String, obj: &Operand, index: u32| {
+            temp += 2;
+            let _ = writeln!(out, "  %t{} = inttoptr i64 {} to i64*", temp - 2, operand(module, obj));
+            let _ = writeln!(out, "  %t{} = getelementptr i64, i64* %t{}, i64 {}", temp - 1, temp - 2, index);
+            format!("%t{}", temp - 1)
+        };
+        for inst in &block.insts {
+            match inst {
+                Inst::Bin(reg, op, l, r) => {
+                    let op = match op {
+                        BinOp::Add => "add",
+                        BinOp::Sub => "sub",
+                        BinOp::Mul => "mul",
+                        BinOp::Div => "sdiv",
+                        BinOp::Rem => "srem",
+                        BinOp::And => "and",
+                        BinOp::Or => "or",
+                        BinOp::Xor => "xor",
+                        BinOp::Shl => "shl",
+                        BinOp::Shr => "ashr"
+                    };
+                    let _ = writeln!(out, "  %r{} = {} i64 {}, {}", reg, op, operand(module, l), operand(module, r));
+                }
+                Inst::Cmp(reg, op, l, r) => {
+                    let op = match op {
+                        CmpOp::Ne => "ne",
+                        CmpOp::Lt => "slt",
+                        CmpOp::Gt => "sgt",
+                        CmpOp::Le => "sle",
+                        CmpOp::Ge => "sge"
+                    };
+                    let _ = writeln!(out, "  %c{} = icmp {} i64 {}, {}", reg, op, operand(module, l), operand(module, r));
+                    let _ = writeln!(out, "  %r{} = zext i1 %c{} to i64", reg, reg);
+                }
+                Inst::Call(reg, name, args) => {
+                    let args: Vec<String> = args.iter().map(|arg| typed_operand(module, arg)).collect();
+                    let _ = writeln!(out, "  %r{} = call i64 @{}({})", reg, name, args.join(", "));
+                }
+                Inst::Load(reg, obj, index) => {
+                    let ptr = word(out, obj, *index);
+                    let _ = writeln!(out, "  %r{} = load i64, i64* {}", reg, ptr);
+                }
+                Inst::Store(obj, index, val) => {
+                    let ptr = word(out, obj, *index);
+                    let _ = writeln!(out, "  store i64 {}, i64* {}", operand(module, val), ptr);
+                }
+                Inst::LoadGlobal(reg, global) => { let _ = writeln!(out, "  %r{} = load i64, i64* @g.{}", reg, global); }
+                Inst::StoreGlobal(global, val) => { let _ = writeln!(out, "  store i64 {}, i64* @g.{}", operand(module, val), global); }
+                Inst::LoadSlot(reg, slot) => { let _ = writeln!(out, "  %r{} = load i64, i64* %s{}", reg, slot); }
+                Inst::StoreSlot(slot, val) => { let _ = writeln!(out, "  store i64 {}, i64* %s{}", operand(module, val), slot); }
+            }
+        }
+
+        match &block.term {
+            Term::Return(val) => { let _ = writeln!(out, "  ret i64 {}", operand(module, val)); }
+            Term::Jump(target) => { let _ = writeln!(out, "  br label %b{}", target); }
+            Term::Branch(cond, yes, no) => {
+                let _ = writeln!(out, "  %t{} = icmp ne i64 {}, 0", temp, operand(module, cond));
+                let _ = writeln!(out, "  br i1 %t{}, label %b{}, label %b{}", temp, yes, no);
+                temp += 1;
+            }
+            Term::Switch(key, entries, default) => {
+                let entries: Vec<String> = entries.iter().map(|(value, target)| format!("i64 {}, label %b{}", value, target)).collect();
+                let _ = writeln!(out, "  switch i64 {}, label %b{} [{}]", operand(module, key), default, entries.join(" "));
+            }
+            Term::Fail(message, location) => {
+                let _ = writeln!(out, "  call void @spruce_fail({}, {})", typed_operand(module, &Operand::Str(*message)), typed_operand(module, &Operand::Str(*location)));
+                out.push_str("  unreachable\n");
+            }
+            Term::NoMatch(val, location) => {
+                let _ = writeln!(out, "  call void @spruce_no_match(i64 {}, {})", operand(module, val), typed_operand(module, &Operand::Str(*location)));
+                out.push_str("  unreachable\n");
+            }
+            Term::Unreachable => out.push_str("  unreachable\n")
+        }
+    }
+    out.push_str("}\n\n");
+}
+
+fn operand(module: &Module, op: &Operand) -> String {
+    match op {
+        Operand::Reg(reg) => format!("%r{}", reg),
+        Operand::Const(n) => n.to_string(),
+        Operand::Func(name, arity) => format!("ptrtoint (i64 ({})* @{} to i64)", vec!["i64"; *arity].join(", "), name),
+        Operand::Str(i) => {
+            let len = module.strings[*i as usize].len() + 1;
+            format!("getelementptr inbounds ([{} x i8], [{} x i8]* @.str.{}, i64 0, i64 0)", len, len, i)
+        }
+    }
+}
+
+fn typed_operand(module: &Module, op: &Operand) -> String {
+    match op {
+        Operand::Str(_) => format!("i8* {}", operand(module, op)),
+        _ => format!("i64 {}", operand(module, op))
+    }
+}
+
+/// Calls a function value with `arity` arguments: a function is called
+/// directly, and a composition calls its first function and then its second
+fn emit_apply(out: &mut String, arity: usize) {
+    let params: Vec<String> = (0..arity).map(|i| format!("i64 %a{}", i)).collect();
+    let types = vec!["i64"; arity].join(", ");
+    let _ = writeln!(out, "define internal i64 @spruce_apply{}(i64 %f, {}) {{", arity, params.join(", "));
+    out.push_str("  %obj = inttoptr i64 %f to i64*\n");
+    out.push_str("  %kind = load i64, i64* %obj\n");
+    out.push_str("  %first.ptr = getelementptr i64, i64* %obj, i64 3\n");
+    out.push_str("  %first = load i64, i64* %first.ptr\n");
+    out.push_str("  %is.func = icmp eq i64 %kind, 1\n");
+    out.push_str("  br i1 %is.func, label %func, label %compose\n");
+    out.push_str("func:\n");
+    let _ = writeln!(out, "  %code = inttoptr i64 %first to i64 ({})*", types);
+    let _ = writeln!(out, "  %direct = call i64 %code({})", params.join(", "));
+    out.push_str("  ret i64 %direct\n");
+    out.push_str("compose:\n");
+    out.push_str("  %second.ptr = getelementptr i64, i64* %obj, i64 4\n");
+    out.push_str("  %second = load i64, i64* %second.ptr\n");
+    let _ = writeln!(out, "  %inner = call i64 @spruce_apply{}(i64 %first, {})", arity, params.join(", "));
+    out.push_str("  %outer = call i64 @spruce_apply1(i64 %second, i64 %inner)\n");
+    out.push_str("  ret i64 %outer\n}\n\n");
+}
+
+/// Builds an executable at `output`, with the intermediate files in a
+/// directory of their own
+pub fn build(module: &Module, output: &Path) -> Result<(), String> {
+    let dir = std::env::temp_dir().join(format!("spruce-build-{}", std::process::id()));
+    fs::create_dir_all(&dir).map_err(|err| format!("cannot create {}: {}", dir.display(), err))?;
+    let (ir, optimized, object, runtime) = (dir.join("prog.ll"), dir.join("prog.bc"), dir.join("prog.o"), dir.join("runtime.c"));
+    fs::write(&ir, emit_llvm(module)).map_err(|err| format!("cannot write {}: {}", ir.display(), err))?;
+    fs::write(&runtime, RUNTIME).map_err(|err| format!("cannot write {}: {}", runtime.display(), err))?;
+
+    let steps: Vec<(&str, Vec<&std::ffi::OsStr>)> = vec![
+        ("opt", vec!["-O2".as_ref(), ir.as_os_str(), "-o".as_ref(), optimized.as_os_str()]),
+        ("llc", vec!["-O2".as_ref(), "-filetype=obj".as_ref(), "-relocation-model=pic".as_ref(), optimized.as_os_str(), "-o".as_ref(), object.as_os_str()]),
+        ("cc", vec!["-O2".as_ref(), object.as_os_str(), runtime.as_os_str(), "-o".as_ref(), output.as_os_str(), "-lm".as_ref()])
+    ];
+    for (tool, args) in steps {
+        let result = Command::new(tool).args(&args).output().map_err(|err| format!("cannot run {}: {}", tool, err))?;
+        if !result.status.success() {
+            return Err(format!("{} failed:\n{}", tool, String::from_utf8_lossy(&result.stderr)));
+        }
+    }
+    fs::remove_dir_all(&dir).ok();
+    Ok(())
+}
@@ -0,0 +1,170 @@
+/*
+An outline of what a program declares at the top level of each of its files:
+its functions, its types with their constructors, and its constants. Editors
+show it as a file's outline and search it for a name across the workspace,
+and `spruce check --emit-symbols` writes it out as JSON for those that don't
+speak the language server protocol.
+
+The outline is made from the parsed program, so a file that parses has one
+even while it doesn't check. Where the program does check, functions and
+constants are shown with the types inferred for them; otherwise a function is
+shown with its parameters, and a constant with nothing.
+*/
+
+use serde_json::json;
+
+use crate::fmt;
+use crate::name_analysis as na;
+use crate::parser::{self, NodeInfo, Span, Stmt, Target};
+use crate::source::SourceMap;
+use crate::typecheck::Environment;
+
+/// What kind of declaration a symbol is
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Kind {
+    Function,
+    Constant,
+    Type,
+    Constructor
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Function => "function",
+            Kind::Constant => "constant",
+            Kind::Type => "type",
+            Kind::Constructor => "constructor"
+        }
+    }
+}
+
+/// A declaration in the outline
+#[derive(Debug, PartialEq, Clone)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: Kind,
+    // its type or parameters, shown next to its name
+    pub detail: String,
+    // all of the declaration, doc comment and all
+    pub info: NodeInfo,
+    // just its name
+    pub name_info: NodeInfo,
+    // the constructors of a type
+    pub children: Vec<Symbol>
+}
+
+/// The top-level declarations of every file of `prog`, in order of file and
+/// then of where they are, with the types `checked` has for them if it's given
+pub fn outline(prog: &parser::Prog, sources: &SourceMap, checked: Option<(&na::Prog, &Environment)>) -> Vec<Symbol> {
+    let mut symbols = Vec::new();
+
+    for func in &prog.functions {
+        let ty = checked.and_then(|(analyzed, env)| {
+            let found = analyzed.functions.iter().find(|other| other.info == func.info)?;
+            env.type_of_id(found.val.name)
+        });
+        let detail = match ty {
+            Some(ty) => ty.to_string(),
+            None => format!("({})", func.val.args.join(", "))
+        };
+        symbols.push(symbol(sources, &func.val.name, Kind::Function, detail, &func.info));
+    }
+
+    for def in &prog.definitions {
+        let name = match &def.val {
+            Stmt::Assign(parser::TargetNode { val: Target::Var(name), .. }, _) | Stmt::Assign(parser::TargetNode { val: Target::Mutable(name), .. }, _) => name,
+            _ => continue
+        };
+        let ty = checked.and_then(|(analyzed, env)| {
+            let found = analyzed.definitions.iter().find(|other| other.info == def.info)?;
+            match &found.val {
+                na::Stmt::Assign(target, _) => env.type_of_id(target.val.id()),
+                _ => None
+            }
+        });
+        let detail = ty.map(|ty| ty.to_string()).unwrap_or_default();
+        symbols.push(symbol(sources, name, Kind::Constant, detail, &def.info));
+    }
+
+    for t in &prog.types {
+        let built = if t.val.type_params.is_empty() { t.val.name.clone() } else { format!("{}({})", t.val.name, t.val.type_params.join(", ")) };
+        let mut found = symbol(sources, &t.val.name, Kind::Type, format!("type {}", built), &t.info);
+        found.children = t.val.options.iter().map(|option| {
+            let args: Vec<String> = option.val.args.iter().map(fmt::type_id).collect();
+            let detail = if args.is_empty() { built.clone() } else { format!("({}) -> {}", args.join(", "), built) };
+            symbol(sources, &option.val.name, Kind::Constructor, detail, &option.info)
+        }).collect();
+        symbols.push(found);
+    }
+
+    symbols.sort_by_key(|symbol| (symbol.info.file, symbol.info.span.start));
+    symbols
+}
+
+/// The outline as JSON, a list of the files with what each declares, where
+/// lines and columns start from 1 and columns count characters
+pub fn to_json(symbols: &[Symbol], sources: &SourceMap) -> serde_json::Value {
+    let mut files: Vec<serde_json::Value> = Vec::new();
+    for (id, file) in sources.files() {
+        let declared: Vec<serde_json::Value> = symbols.iter().filter(|symbol| symbol.info.file == id).map(|symbol| symbol_json(symbol, sources)).collect();
+        if !declared.is_empty() {
+            files.push(json!({ "file": file.name, "symbols": declared }));
+        }
+    }
+    serde_json::Value::Array(files)
+}
+
+fn symbol_json(symbol: &Symbol, sources: &SourceMap) -> serde_json::Value {
+    let place = |info: &NodeInfo| {
+        let file = sources.get(info.file).expect("symbols are in the source");
+        let (start, end) = file.lines.span_to_line_col(&file.text, &info.span);
+        json!({ "start": { "line": start.line, "column": start.col }, "end": { "line": end.line, "column": end.col } })
+    };
+    json!({
+        "name": symbol.name,
+        "kind": symbol.kind.as_str(),
+        "detail": symbol.detail,
+        "range": place(&symbol.info),
+        "name_range": place(&symbol.name_info),
+        "children": symbol.children.iter().map(|child| symbol_json(child, sources)).collect::<Vec<serde_json::Value>>()
+    })
+}
+
+fn symbol(sources: &SourceMap, name: &str, kind: Kind, detail: String, info: &NodeInfo) -> Symbol {
+    let text = sources.get(info.file).map_or("", |file| file.text.as_str());
+    Symbol {
+        name: name.to_string(),
+        kind: kind,
+        detail: detail,
+        info: info.clone(),
+        name_info: NodeInfo { span: name_span(text, &info.span, name), file: info.file },
+        children: Vec::new()
+    }
+}
+
+/// Where `name` is first spelled as a whole word in `span` of `text`, past
+/// any doc comment, or all of `span` if it isn't
+fn name_span(text: &str, span: &Span, name: &str) -> Span {
+    let mut from = after_docs(text, span);
+    while let Some(found) = text[from..span.end].find(name) {
+        let at = from + found;
+        let end = at + name.len();
+        let before = text[..at].chars().last().map_or(false, |c| c.is_ascii_alphanumeric());
+        let after = text[end..].chars().next().map_or(false, |c| c.is_ascii_alphanumeric());
+        if !before && !after {
+            return Span { start: at, end: end };
+        }
+        from = end;
+    }
+    span.clone()
+}
+
+/// Where the declaration `span` covers starts once past its doc comment
+pub fn after_docs(text: &str, span: &Span) -> usize {
+    let mut start = span.start;
+    while text[start..span.end].trim_start().starts_with("///") {
+        start = text[start..span.end].find('\n').map_or(span.end, |end| start + end + 1);
+    }
+    start
+}
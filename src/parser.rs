@@ -1,13 +1,33 @@
 
 extern crate pest;
 
+use std::collections::HashMap;
+use std::path::Path;
+
 use pest::{Parser};
 use pest::iterators::{Pairs, Pair};
 use pest::prec_climber::{PrecClimber, Operator, Assoc};
 use pest::error::InputLocation;
 
-use crate::error::SpruceErr;
+use crate::error::{SpruceErr, Suggestion};
+use crate::error_codes::ErrorCode;
+use crate::interface;
+use crate::source::{FileId, SourceMap};
+
+
+/// Words the grammar reserves, which can't be names
+pub const KEYWORDS: [&str; 6] = ["builtin", "case", "import", "module", "mut", "type"];
 
+/// A test is a function named `test "name"`, which no function written as
+/// one can be, since names are only letters and digits
+pub fn test_function(name: &str) -> String {
+    format!("test \"{}\"", name)
+}
+
+/// The name of the test that `function` is, if it's one
+pub fn test_name(function: &str) -> Option<&str> {
+    function.strip_prefix("test \"")?.strip_suffix('"')
+}
 
 #[derive(Parser)]
 #[grammar = "spruce.pest"]
@@ -19,8 +39,14 @@ lazy_static! {
         use Assoc::*;
 
         PrecClimber::new(vec![
+            Operator::new(compose_r, Left) | Operator::new(compose_l, Right),
             Operator::new(eq, Left) | Operator::new(not_eq, Left),
             Operator::new(lt_eq, Left) | Operator::new(gt_eq, Left) | Operator::new(lt, Left) | Operator::new(gt, Left),
+            Operator::new(cons, Right),
+            Operator::new(bit_or, Left),
+            Operator::new(bit_xor, Left),
+            Operator::new(bit_and, Left),
+            Operator::new(shift_l, Left) | Operator::new(shift_r, Left),
             Operator::new(modulus, Left),
             Operator::new(add, Left) | Operator::new(subtract, Left),
             Operator::new(multiply, Left) | Operator::new(divide, Left),
@@ -32,7 +58,7 @@ lazy_static! {
 #[derive(Debug, PartialEq, Clone)]
 pub struct NodeInfo {
     pub span: Span,
-    pub file: String
+    pub file: FileId
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -57,13 +83,24 @@ pub enum Expr {
     Mod(Box<ExprNode>, Box<ExprNode>),
     FnCall(String, Vec<Box<ExprNode>>),
     Id(String),
-    Lit(f64),
+    Lit(i64),
+    Neg(Box<ExprNode>),
+    List(Vec<Box<ExprNode>>),
     Eq(Box<ExprNode>, Box<ExprNode>),
     NotEq(Box<ExprNode>, Box<ExprNode>),
     LtEq(Box<ExprNode>, Box<ExprNode>),
     GtEq(Box<ExprNode>, Box<ExprNode>),
     Lt(Box<ExprNode>, Box<ExprNode>),
     Gt(Box<ExprNode>, Box<ExprNode>),
+    // f >> g applies f first, f << g applies g first
+    ComposeR(Box<ExprNode>, Box<ExprNode>),
+    ComposeL(Box<ExprNode>, Box<ExprNode>),
+    Cons(Box<ExprNode>, Box<ExprNode>),
+    BitAnd(Box<ExprNode>, Box<ExprNode>),
+    BitOr(Box<ExprNode>, Box<ExprNode>),
+    BitXor(Box<ExprNode>, Box<ExprNode>),
+    Shl(Box<ExprNode>, Box<ExprNode>),
+    Shr(Box<ExprNode>, Box<ExprNode>),
 }
 
 #[derive(Debug, PartialEq)]
@@ -97,9 +134,11 @@ pub struct CaseNode {
 }
 
 #[derive(Debug, PartialEq)]
-pub struct CasePattern {
-    pub base: String,
-    pub args: Vec<String> 
+pub enum CasePattern {
+    // an ADT value and the names its arguments are bound to
+    ADT(String, Vec<String>),
+    Lit(i64),
+    Any
 }
 
 #[derive(Debug, PartialEq)]
@@ -177,7 +216,10 @@ pub struct TargetNode {
 pub struct Type {
     pub name: String,
     pub type_params: Vec<String>,
-    pub options: Vec<TypeOptionNode>
+    pub options: Vec<TypeOptionNode>,
+    pub doc: Option<String>,
+    // implemented by the runtime, so without constructors
+    pub builtin: bool
 }
 
 #[derive(Debug, PartialEq)]
@@ -208,7 +250,8 @@ pub struct TypeOptionNode {
 pub struct Func {
     pub name: String,
     pub args: Vec<String>,
-    pub body: BodyNode
+    pub body: BodyNode,
+    pub doc: Option<String>
 }
 
 #[derive(Debug, PartialEq)]
@@ -217,41 +260,217 @@ pub struct FuncNode {
     pub info: NodeInfo
 }
 
+/// A type as written in an interface file
+#[derive(Debug, PartialEq, Clone)]
+pub enum SigType {
+    // a type variable, a primitive or an ADT with its arguments
+    Named(String, Vec<SigType>),
+    Func(Vec<SigType>, Box<SigType>),
+    Unit
+}
+
+/// The type of a declaration in an interface file, or of a builtin, which
+/// stands in for the declaration itself
+#[derive(Debug, PartialEq, Clone)]
+pub struct Signature {
+    pub name: String,
+    pub ty: SigType
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct SignatureNode {
+    pub val: Signature,
+    pub info: NodeInfo
+}
+
+/// Comments are discarded by the grammar, but kept alongside the AST so that
+/// tools which print source back out can reattach them
+#[derive(Debug, PartialEq)]
+pub struct Comment {
+    // the full comment text, including delimiters
+    pub text: String
+}
+
+#[derive(Debug, PartialEq)]
+pub struct CommentNode {
+    pub val: Comment,
+    pub info: NodeInfo
+}
+
+/// The module a file belongs to, which is named after the file, less any
+/// directory and extension, unless it opens with a `module` declaration
+#[derive(Debug, PartialEq, Clone)]
+pub struct Module {
+    pub name: String,
+    // None if the module exports everything
+    pub exports: Option<Vec<ExportNode>>,
+    pub imports: Vec<ImportNode>,
+    // read from an interface file rather than source
+    pub interface: bool
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ModuleNode {
+    pub val: Module,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Export {
+    pub name: String,
+    pub constructors: bool
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExportNode {
+    pub val: Export,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Import {
+    pub module: String
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ImportNode {
+    pub val: Import,
+    pub info: NodeInfo
+}
+
 #[derive(Debug, PartialEq)]
 pub struct Prog {
     pub functions: Vec<FuncNode>,
     pub definitions: Vec<StmtNode>,
-    pub types: Vec<TypeNode>
+    pub types: Vec<TypeNode>,
+    pub signatures: Vec<SignatureNode>,
+    pub modules: Vec<ModuleNode>,
+    pub comments: Vec<CommentNode>,
+    // where each call marked `@tail` is
+    pub tail_calls: Vec<NodeInfo>
 }
 
-fn to_expr(expr: Pair<Rule>, file_name: &String) -> ExprNode {
-    PREC_CLIMBER.climb(
-        expr.into_inner(),
-        |pair: Pair<Rule>| match pair.as_rule() {
-            Rule::id => ExprNode {
-                val: Expr::Id(String::from(pair.as_str())),
-                info: NodeInfo {span: Span::from(pair.as_span()), file: file_name.clone()}
-            },
-            Rule::num => ExprNode {
-                val: Expr::Lit(pair.as_str().parse::<f64>().unwrap()),
-                info: NodeInfo {span: Span::from(pair.as_span()), file: file_name.clone()}
-            },
-            Rule::expr => to_expr(pair, file_name),
-            Rule::fn_call => {
-                let pair_span = pair.as_span();
+/// Converts any of the literal forms the grammar accepts into its value, or
+/// says why it can't be an Int. Whole numbers are read exactly, and a decimal
+/// with a fraction or an exponent isn't an Int at all
+fn to_num(text: &str) -> Result<i64, (ErrorCode, String)> {
+    let digits = text.replace("_", "");
+    let (sign, unsigned) = match digits.strip_prefix("-") {
+        Some(rest) => ("-", rest),
+        None => ("", digits.as_str())
+    };
+    let out_of_range = || {
+        let message = format!("{} doesn't fit in an Int, which goes from {} to {}", text, i64::MIN, i64::MAX);
+        (ErrorCode::LiteralOutOfRange, message)
+    };
 
-                let mut children = pair.into_inner();
-                let id = String::from(children.next().unwrap().as_str());
-                let args = children.into_iter().map(|arg| { Box::from(to_expr(arg, file_name)) }).collect();
+    let radix = match unsigned.get(..2) {
+        Some("0x") => 16,
+        Some("0b") => 2,
+        _ => 10
+    };
+    if radix != 10 {
+        return i64::from_str_radix(&format!("{}{}", sign, &unsigned[2..]), radix).map_err(|_| out_of_range());
+    }
+    if unsigned.contains(['.', 'e', 'E']) {
+        return Err((ErrorCode::FractionalLiteral, format!("{} isn't an Int, and there are no Float literals", text)));
+    }
+    digits.parse::<i64>().map_err(|_| out_of_range())
+}
 
-                ExprNode {
-                    val: Expr::FnCall(id, args),
-                    info: NodeInfo {span: Span::from(pair_span), file: file_name.clone()}
+/// The literals of a file that aren't Ints, or don't fit in one. A literal negated
+/// directly is read along with its sign, so the smallest Int can be written
+fn literal_errors(pairs: &Pairs<Rule>, file: FileId) -> Vec<SpruceErr> {
+    let mut negated = HashMap::new();
+    let mut errors = Vec::new();
+    for pair in pairs.clone().flatten() {
+        let (text, span) = match pair.as_rule() {
+            Rule::neg => {
+                if let Some(inner) = pair.clone().into_inner().next().filter(|inner| inner.as_rule() == Rule::num) {
+                    negated.insert(inner.as_span().start(), Span::from(pair.as_span()));
                 }
+                continue;
             }
-            _ => unreachable!(),
+            Rule::num => match negated.get(&pair.as_span().start()) {
+                Some(span) => (format!("-{}", pair.as_str()), span.clone()),
+                None => (String::from(pair.as_str()), Span::from(pair.as_span()))
+            },
+            Rule::lit_pattern => (String::from(pair.as_str()), Span::from(pair.as_span())),
+            _ => continue
+        };
+        if let Err((code, message)) = to_num(&text) {
+            errors.push(SpruceErr::new(message, NodeInfo { span: span, file: file }).with_code(code));
+        }
+    }
+    errors
+}
+
+fn to_term(pair: Pair<Rule>, file: FileId) -> ExprNode {
+    match pair.as_rule() {
+        Rule::name => ExprNode {
+            val: Expr::Id(String::from(pair.as_str())),
+            info: NodeInfo {span: Span::from(pair.as_span()), file: file}
         },
+        // literals that aren't Ints have already been reported
+        Rule::num => ExprNode {
+            val: Expr::Lit(to_num(pair.as_str()).unwrap_or(0)),
+            info: NodeInfo {span: Span::from(pair.as_span()), file: file}
+        },
+        Rule::expr => to_expr(pair, file),
+        Rule::neg => {
+            let pair_span = pair.as_span();
+            let inner = pair.into_inner().next().unwrap();
+
+            // negative literals are folded straight into the literal, and
+            // read with their sign
+            let val = match inner.as_rule() {
+                Rule::num => Expr::Lit(to_num(&format!("-{}", inner.as_str())).unwrap_or(0)),
+                _ => {
+                    let inner = to_term(inner, file);
+                    match inner.val {
+                        Expr::Lit(l) if l != i64::MIN => Expr::Lit(-l),
+                        _ => Expr::Neg(Box::from(inner))
+                    }
+                }
+            };
+
+            ExprNode {
+                val: val,
+                info: NodeInfo {span: Span::from(pair_span), file: file}
+            }
+        }
+        Rule::fn_call => {
+            let pair_span = pair.as_span();
+
+            let mut children = pair.into_inner().skip_while(|child| child.as_rule() == Rule::tail_mark);
+            let id = String::from(children.next().unwrap().as_str());
+            let args = children.map(|arg| { Box::from(to_expr(arg, file)) }).collect();
+
+            ExprNode {
+                val: Expr::FnCall(id, args),
+                info: NodeInfo {span: Span::from(pair_span), file: file}
+            }
+        }
+        Rule::list => {
+            let pair_span = pair.as_span();
+            let elements = pair.into_inner().map(|elem| { Box::from(to_expr(elem, file)) }).collect();
+
+            ExprNode {
+                val: Expr::List(elements),
+                info: NodeInfo {span: Span::from(pair_span), file: file}
+            }
+        }
+        _ => unreachable!(),
+    }
+}
+
+fn to_expr(expr: Pair<Rule>, file: FileId) -> ExprNode {
+    PREC_CLIMBER.climb(
+        expr.into_inner(),
+        |pair: Pair<Rule>| to_term(pair, file),
         |lhs: ExprNode, op: Pair<Rule>, rhs: ExprNode| {
+            // binary expressions cover both operands, not just the operator
+            let span = Span {start: lhs.info.span.start, end: rhs.info.span.end};
             let expr = match op.as_rule() {
                 Rule::add      => Expr::Add(Box::from(lhs), Box::from(rhs)),
                 Rule::subtract => Expr::Subt(Box::from(lhs), Box::from(rhs)),
@@ -265,18 +484,26 @@ fn to_expr(expr: Pair<Rule>, file_name: &String) -> ExprNode {
                 Rule::gt_eq    => Expr::GtEq(Box::from(lhs), Box::from(rhs)),
                 Rule::lt       => Expr::Lt(Box::from(lhs), Box::from(rhs)),
                 Rule::gt       => Expr::Gt(Box::from(lhs), Box::from(rhs)),
+                Rule::compose_r => Expr::ComposeR(Box::from(lhs), Box::from(rhs)),
+                Rule::compose_l => Expr::ComposeL(Box::from(lhs), Box::from(rhs)),
+                Rule::cons     => Expr::Cons(Box::from(lhs), Box::from(rhs)),
+                Rule::bit_and  => Expr::BitAnd(Box::from(lhs), Box::from(rhs)),
+                Rule::bit_or   => Expr::BitOr(Box::from(lhs), Box::from(rhs)),
+                Rule::bit_xor  => Expr::BitXor(Box::from(lhs), Box::from(rhs)),
+                Rule::shift_l  => Expr::Shl(Box::from(lhs), Box::from(rhs)),
+                Rule::shift_r  => Expr::Shr(Box::from(lhs), Box::from(rhs)),
                 _ => unreachable!(),
             };
 
             ExprNode {
                 val: expr,
-                info: NodeInfo{span: Span::from(op.as_span()), file: file_name.clone()}
+                info: NodeInfo{span: span, file: file}
             }
         },
     )
 }
 
-fn to_body(body: Pair<Rule>, file_name: &String) -> BodyNode {
+fn to_body(body: Pair<Rule>, file: FileId) -> BodyNode {
     let body_span = body.as_span();
 
     let mut stmts = Vec::new();
@@ -284,10 +511,10 @@ fn to_body(body: Pair<Rule>, file_name: &String) -> BodyNode {
     for element in body.into_inner() {
         match element.as_rule() {
             Rule::assign | Rule::fn_call | Rule::case => {
-                stmts.push(to_stmt(element, file_name));
+                stmts.push(to_stmt(element, file));
             }
             Rule::expr => {
-                expr = Option::Some(to_expr(element, file_name));
+                expr = Option::Some(to_expr(element, file));
             }
             _ => {
                 println!("rule {:?} encountered", element.as_rule());
@@ -303,33 +530,45 @@ fn to_body(body: Pair<Rule>, file_name: &String) -> BodyNode {
 
     BodyNode {
         val: body_struct,
-        info: NodeInfo {span: Span::from(body_span), file: file_name.clone()}
+        info: NodeInfo {span: Span::from(body_span), file: file}
     }
 }
 
-fn to_case_option(option: Pair<Rule>, file_name: &String) -> CaseOptionNode {
+fn to_case_option(option: Pair<Rule>, file: FileId) -> CaseOptionNode {
     let option_span = option.as_span();
 
     let mut children = option.into_inner();
     let mut pattern_children = children.next().unwrap().into_inner();
     let pattern_token = pattern_children.next().unwrap();
-    let pattern_base = String::from(pattern_token.as_str());
-    let pattern_args = pattern_children.into_iter().map(|arg| { String::from(arg.as_str()) }).collect();
+    let pattern_span = pattern_token.as_span();
+    let pattern_val = match pattern_token.as_rule() {
+        // `x :: rest` is sugar for `Cons(x, rest)`
+        Rule::cons_pattern => {
+            let args = pattern_token.into_inner().map(|arg| { String::from(arg.as_str()) }).collect();
+            CasePattern::ADT(String::from("Cons"), args)
+        }
+        Rule::any_pattern => CasePattern::Any,
+        Rule::lit_pattern => CasePattern::Lit(to_num(pattern_token.as_str()).unwrap_or(0)),
+        _ => {
+            let args = pattern_children.map(|arg| { String::from(arg.as_str()) }).collect();
+            CasePattern::ADT(String::from(pattern_token.as_str()), args)
+        }
+    };
     let pattern = CasePatternNode {
-        val: CasePattern { base: pattern_base, args: pattern_args },
+        val: pattern_val,
         // TODO: make span both base and args
-        info: NodeInfo {span: Span::from(pattern_token.as_span()), file: file_name.clone()}
+        info: NodeInfo {span: Span::from(pattern_span), file: file}
     };
 
     let body_token = children.next().unwrap();
     let body_span = body_token.as_span();
     let body = CaseBodyNode {
         val: match body_token.as_rule() {
-            Rule::expr => CaseBody::Expr(to_expr(body_token, file_name)),
-            Rule::body => CaseBody::Body(to_body(body_token, file_name)),
+            Rule::expr => CaseBody::Expr(to_expr(body_token, file)),
+            Rule::body => CaseBody::Body(to_body(body_token, file)),
             _ => unreachable!()
         },
-        info: NodeInfo {span: Span::from(body_span), file: file_name.clone()}
+        info: NodeInfo {span: Span::from(body_span), file: file}
     };
 
     let case_option = CaseOption {
@@ -339,19 +578,19 @@ fn to_case_option(option: Pair<Rule>, file_name: &String) -> CaseOptionNode {
 
     CaseOptionNode {
         val: case_option,
-        info: NodeInfo {span: Span::from(option_span), file: file_name.clone()}
+        info: NodeInfo {span: Span::from(option_span), file: file}
     }
 }
 
-fn to_case(case: Pair<Rule>, file_name: &String) -> CaseNode {
+fn to_case(case: Pair<Rule>, file: FileId) -> CaseNode {
     let case_span = case.as_span();
 
     let mut children = case.into_inner();
-    let expr = to_expr(children.next().unwrap(), file_name);
+    let expr = to_expr(children.next().unwrap(), file);
 
     let mut options = Vec::new();
     for option in children {
-        options.push(to_case_option(option, file_name));
+        options.push(to_case_option(option, file));
     }
 
     let case_struct = Case {
@@ -361,11 +600,11 @@ fn to_case(case: Pair<Rule>, file_name: &String) -> CaseNode {
 
     CaseNode {
         val: case_struct,
-        info: NodeInfo {span: Span::from(case_span), file: file_name.clone()}
+        info: NodeInfo {span: Span::from(case_span), file: file}
     }
 }
 
-fn to_stmt(stmt: Pair<Rule>, file_name: &String) -> StmtNode {
+fn to_stmt(stmt: Pair<Rule>, file: FileId) -> StmtNode {
     let stmt_span = stmt.as_span();
 
     let stmt_val = match stmt.as_rule() {
@@ -381,24 +620,24 @@ fn to_stmt(stmt: Pair<Rule>, file_name: &String) -> StmtNode {
             };
             let target = TargetNode {
                 val: target_val,
-                info: NodeInfo {span: Span::from(tgt_span), file: file_name.clone()}
+                info: NodeInfo {span: Span::from(tgt_span), file: file}
             };
-            let expr = to_expr(children.next().unwrap(), file_name);
+            let expr = to_expr(children.next().unwrap(), file);
             Stmt::Assign(target, expr)
         }
         Rule::fn_call => {
-            let mut children = stmt.into_inner();
+            let mut children = stmt.into_inner().skip_while(|child| child.as_rule() == Rule::tail_mark);
             let id = String::from(children.next().unwrap().as_str());
 
             let mut args = Vec::new();
             for arg in children {
-                args.push(to_expr(arg, file_name));
+                args.push(to_expr(arg, file));
             }
 
             Stmt::FnCall(id, args)
         }
         Rule::case => {
-            Stmt::Case(to_case(stmt, file_name))
+            Stmt::Case(to_case(stmt, file))
         }
         _ => {
             println!("rule {:?} encountered", stmt.as_rule());
@@ -408,37 +647,62 @@ fn to_stmt(stmt: Pair<Rule>, file_name: &String) -> StmtNode {
 
     StmtNode {
         val: stmt_val,
-        info: NodeInfo {span: Span::from(stmt_span), file: file_name.clone()}
+        info: NodeInfo {span: Span::from(stmt_span), file: file}
     }
 }
 
-fn to_func(mut p: Pair<Rule>, file_name: &String) -> FuncNode {
+/// Joins the lines of a doc comment, dropping the slashes and the single
+/// space conventionally following them
+fn to_doc(docs: Pair<Rule>) -> Option<String> {
+    let lines: Vec<&str> = docs.into_inner().map(|line| {
+        let text = &line.as_str()[3..];
+        text.strip_prefix(" ").unwrap_or(text)
+    }).collect();
+
+    if lines.is_empty() {
+        None
+    }
+    else {
+        Some(lines.join("\n"))
+    }
+}
+
+fn to_func(mut p: Pair<Rule>, file: FileId) -> FuncNode {
     let func_span = p.as_span();
     let mut func = p.into_inner();
 
-    let id = String::from(func.next().unwrap().as_str());
+    let doc = to_doc(func.next().unwrap());
+    let name = func.next().unwrap();
 
-    let args = func.next().unwrap();
+    // a test takes no arguments
     let mut arg_vec = Vec::new();
-    for arg in args.into_inner() {
-        arg_vec.push(String::from(arg.as_str()));
+    let id = if name.as_rule() == Rule::test_name {
+        let quoted = name.as_str();
+        test_function(&quoted[1..quoted.len() - 1])
     }
+    else {
+        for arg in func.next().unwrap().into_inner() {
+            arg_vec.push(String::from(arg.as_str()));
+        }
+        String::from(name.as_str())
+    };
 
-    let body = to_body(func.next().unwrap(), file_name);
+    let body = to_body(func.next().unwrap(), file);
 
     let func = Func {
         name: id,
         args: arg_vec,
-        body: body
+        body: body,
+        doc: doc
     };
 
     FuncNode {
         val: func,
-        info: NodeInfo {span: Span::from(func_span), file: file_name.clone()}
+        info: NodeInfo {span: Span::from(func_span), file: file}
     }
 }
 
-fn to_type_option(option: Pair<Rule>, file_name: &String) -> TypeOptionNode {
+fn to_type_option(option: Pair<Rule>, file: FileId) -> TypeOptionNode {
     let option_span = option.as_span();
 
     let mut children = option.into_inner();
@@ -456,7 +720,7 @@ fn to_type_option(option: Pair<Rule>, file_name: &String) -> TypeOptionNode {
 
     TypeOptionNode {
         val: type_option_val,
-        info: NodeInfo {span: Span::from(option_span), file: file_name.clone() }
+        info: NodeInfo {span: Span::from(option_span), file: file }
     }
 }
 
@@ -477,10 +741,12 @@ fn to_type_identifier(ident: Pair<Rule>) -> TypeIdentifier {
     }
 }
 
-fn to_type(mut t: Pair<Rule>, file_name: &String) -> TypeNode {
+fn to_type(mut t: Pair<Rule>, file: FileId) -> TypeNode {
     let type_span = t.as_span();
+    let builtin = t.as_rule() == Rule::builtin_type;
     let mut children = t.into_inner();
 
+    let doc = to_doc(children.next().unwrap());
     let name = String::from(children.next().unwrap().as_str());
 
     let mut params = Vec::new();
@@ -491,37 +757,136 @@ fn to_type(mut t: Pair<Rule>, file_name: &String) -> TypeNode {
 
     let mut options = Vec::new();
     for option in children {
-        options.push(to_type_option(option, file_name));
+        options.push(to_type_option(option, file));
     }
 
     let type_val = Type {
         name: name,
         type_params: params,
-        options: options
+        options: options,
+        doc: doc,
+        builtin: builtin
     };
 
     TypeNode {
         val: type_val,
-        info: NodeInfo {span: Span::from(type_span), file: file_name.clone() }
+        info: NodeInfo {span: Span::from(type_span), file: file }
+    }
+}
+
+fn to_sig_type(ty: Pair<Rule>) -> SigType {
+    match ty.as_rule() {
+        Rule::fn_type => {
+            let mut types: Vec<SigType> = ty.into_inner().map(to_sig_type).collect();
+            let out = types.pop().unwrap();
+            SigType::Func(types, Box::from(out))
+        }
+        Rule::unit_type => SigType::Unit,
+        Rule::named_type => {
+            let mut children = ty.into_inner();
+            let name = String::from(children.next().unwrap().as_str());
+            SigType::Named(name, children.map(to_sig_type).collect())
+        }
+        _ => unreachable!()
+    }
+}
+
+fn to_signature(sig: Pair<Rule>, file: FileId) -> SignatureNode {
+    let span = Span::from(sig.as_span());
+    let mut children = sig.into_inner();
+    let name = String::from(children.next().unwrap().as_str());
+
+    SignatureNode {
+        val: Signature { name: name, ty: to_sig_type(children.next().unwrap()) },
+        info: NodeInfo { span: span, file: file }
     }
 }
 
-fn to_ast(files: Vec<(Pairs<Rule>, String)>) -> Prog {
+fn to_comments(text: &str, file: FileId) -> Vec<CommentNode> {
+    let scanned = ExprParser::parse(Rule::comments, text).expect("comment scan matches any input");
+
+    scanned.flatten().filter(|pair| { pair.as_rule() == Rule::comment }).map(|pair| {
+        CommentNode {
+            val: Comment { text: String::from(pair.as_str()) },
+            info: NodeInfo {span: Span::from(pair.as_span()), file: file}
+        }
+    }).collect()
+}
+
+fn to_exports(exports: Pair<Rule>, file: FileId) -> Vec<ExportNode> {
+    exports.into_inner().map(|export| {
+        let span = Span::from(export.as_span());
+        let mut inner = export.into_inner();
+        let name = String::from(inner.next().unwrap().as_str());
+
+        ExportNode {
+            val: Export { name: name, constructors: inner.next().is_some() },
+            info: NodeInfo { span: span, file: file }
+        }
+    }).collect()
+}
+
+fn to_ast(files: Vec<(Pairs<Rule>, FileId)>, comments: Vec<CommentNode>, sources: &SourceMap) -> Prog {
     let mut stmts = Vec::new();
     let mut functions = Vec::new();
     let mut types = Vec::new();
+    let mut signatures = Vec::new();
+    let mut modules: Vec<ModuleNode> = Vec::new();
+    let mut tail_calls = Vec::new();
+
+    for (pairs, file) in files {
+        let marked = pairs.clone().flatten().filter(|pair| {
+            pair.as_rule() == Rule::fn_call && pair.clone().into_inner().next().map_or(false, |first| first.as_rule() == Rule::tail_mark)
+        });
+        tail_calls.extend(marked.map(|pair| NodeInfo { span: Span::from(pair.as_span()), file: file }));
+
+        // a file that failed to parse arrives in several pieces, which all
+        // share one module
+        let module = match modules.iter().position(|m| { m.info.file == file }) {
+            Some(i) => i,
+            None => {
+                let name = sources.name(file);
+                let stem = Path::new(name).file_stem().and_then(|stem| stem.to_str()).unwrap_or(name);
+                modules.push(ModuleNode {
+                    val: Module { name: String::from(stem), exports: None, imports: Vec::new(), interface: interface::is_interface(name) },
+                    info: NodeInfo { span: Span { start: 0, end: 0 }, file: file }
+                });
+                modules.len() - 1
+            }
+        };
 
-    for (file, name) in files {
-        for element in file {
+        for element in pairs {
             match element.as_rule() {
-                Rule::function_decl => {
-                    functions.push( to_func(element, &name) );
+                Rule::module_decl => {
+                    let span = Span::from(element.as_span());
+                    let mut decl = element.into_inner();
+                    modules[module].val.name = String::from(decl.next().unwrap().as_str());
+                    modules[module].val.exports = decl.next().map(|exports| to_exports(exports, file));
+                    modules[module].info.span = span;
+                }
+                Rule::import_decl => {
+                    let span = Span::from(element.as_span());
+                    let import_name = element.into_inner().next().unwrap().as_str();
+                    modules[module].val.imports.push(ImportNode {
+                        val: Import { module: String::from(import_name) },
+                        info: NodeInfo { span: span, file: file }
+                    });
+                }
+                Rule::function_decl | Rule::test_decl => {
+                    functions.push( to_func(element, file) );
                 }
                 Rule::assign => {
-                    stmts.push( to_stmt(element, &name) );
+                    stmts.push( to_stmt(element, file) );
                 }
-                Rule::type_decl => {
-                    types.push( to_type(element, &name) );
+                Rule::type_decl | Rule::builtin_type => {
+                    types.push( to_type(element, file) );
+                }
+                Rule::signature => {
+                    signatures.push( to_signature(element, file) );
+                }
+                Rule::builtin_decl => {
+                    let sig = element.into_inner().find(|pair| pair.as_rule() == Rule::signature).unwrap();
+                    signatures.push( to_signature(sig, file) );
                 }
                 Rule::EOI => (),
                 _ => unreachable!()
@@ -532,43 +897,257 @@ fn to_ast(files: Vec<(Pairs<Rule>, String)>) -> Prog {
     Prog {
         functions: functions,
         definitions: stmts,
-        types: types
+        types: types,
+        signatures: signatures,
+        modules: modules,
+        comments: comments,
+        tail_calls: tail_calls
+    }
+}
+
+fn to_parse_err(e: pest::error::Error<Rule>, text: &str, file: FileId) -> SpruceErr {
+    let span = match e.location {
+        InputLocation::Pos(pos) => Span {start: pos, end: pos},
+        InputLocation::Span((start, end)) => Span {start: start, end: end}
+    };
+
+    let err = SpruceErr::new(
+        String::from("Parse error"),
+        NodeInfo {
+            span: span.clone(),
+            file: file
+        }
+    ).with_code(ErrorCode::Syntax);
+
+    match missing_parens(text, span.start) {
+        Some(name_end) => {
+            err.with_help(String::from("function declarations need an argument list, even an empty one")).with_suggestion(Suggestion {
+                message: String::from("add '()'"),
+                info: NodeInfo {
+                    span: Span { start: name_end, end: name_end },
+                    file: file
+                },
+                replacement: String::from("()"),
+                machine_applicable: true
+            })
+        }
+        None => err
     }
 }
 
-pub fn parse(unparsed: Vec<(&str, String)>) -> Result<Prog, SpruceErr> {
+/// Spots a function declared without parentheses, as in `main {`, when
+/// parsing fails at the brace. Returns where the name ends
+fn missing_parens(file: &str, pos: usize) -> Option<usize> {
+    if !file[pos..].starts_with('{') {
+        return None;
+    }
+
+    let line_start = file[..pos].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let name = file[line_start..pos].trim_end();
+    let is_name = name.chars().next().map_or(false, |c| c.is_ascii_alphabetic()) && name.chars().all(|c| c.is_ascii_alphanumeric());
+    if is_name {
+        Some(line_start + name.len())
+    }
+    else {
+        None
+    }
+}
+
+/// Offsets at which a top-level declaration may begin, used to resynchronize
+/// after a syntax error. That's any line starting in the first column, other
+/// than closing brackets and declarations whose doc comment precedes them
+fn sync_points(file: &str) -> Vec<usize> {
+    let mut points = vec![0];
+    let mut offset = 0;
+    let mut after_doc = false;
+    for line in file.split('\n') {
+        let starts_decl = match line.chars().next() {
+            Some(c) => !c.is_whitespace() && !['}', ')', ']'].contains(&c),
+            None => false
+        };
+        if starts_decl && !after_doc && offset != 0 {
+            points.push(offset);
+        }
+
+        after_doc = line.starts_with("///");
+        offset += line.len() + 1;
+    }
+
+    points
+}
+
+/// Cuts the file off at `end` and blanks everything before `start`, keeping
+/// newlines and byte lengths so spans still line up with the original file
+fn mask_chunk(file: &str, start: usize, end: usize) -> String {
+    let mut masked = String::with_capacity(end);
+    for (i, c) in file[..end].char_indices() {
+        if i < start && c != '\n' {
+            masked.push_str(&" ".repeat(c.len_utf8()));
+        }
+        else {
+            masked.push(c);
+        }
+    }
+
+    masked
+}
+
+/// Parses as much as possible, returning the AST of every declaration that
+/// parsed along with an error for each one that didn't. Files that fail to
+/// parse are split into their top-level declarations, which are retried one
+/// at a time
+pub fn parse_partial(sources: &SourceMap) -> (Prog, Vec<SpruceErr>) {
+    let mut chunks = Vec::new();
     let mut parse_results = Vec::new();
-    for (file, name) in unparsed {
-        let parsed = ExprParser::parse(Rule::file, &file);
-        match parsed {
+    let mut comments = Vec::new();
+    let mut errors = Vec::new();
+    for (id, source) in sources.files() {
+        let text = source.text.as_str();
+        comments.extend(to_comments(text, id));
+
+        // interfaces are written by the compiler, so aren't worth recovering
+        if interface::is_interface(&source.name) {
+            match ExprParser::parse(Rule::interface, text) {
+                Ok(pairs) => parse_results.push((pairs, id)),
+                Err(e) => errors.push(to_parse_err(e, text, id))
+            }
+            continue;
+        }
+
+        match ExprParser::parse(Rule::file, text) {
             Ok(pairs) => {
-                parse_results.push((pairs, name));
+                parse_results.push((pairs, id));
+            }
+            Err(_) => {
+                let points = sync_points(text);
+                for (i, start) in points.iter().enumerate() {
+                    let end = points.get(i + 1).cloned().unwrap_or(text.len());
+                    chunks.push((mask_chunk(text, *start, end), id));
+                }
+            }
+        }
+    }
+
+    for (chunk, id) in &chunks {
+        match ExprParser::parse(Rule::file, chunk) {
+            Ok(pairs) => {
+                parse_results.push((pairs, *id));
             }
             Err(e) => {
-                let err = match e.location {
-                    InputLocation::Pos(pos) => {
-                        SpruceErr {
-                            message: String::from("Parse error"),
-                            info: NodeInfo {
-                                span: Span {start: pos, end: pos},
-                                file: name.clone()
-                            }
-                        }
-                    }
-                    InputLocation::Span((start, end)) => {
-                        SpruceErr {
-                            message: String::from("Parse error"),
-                            info: NodeInfo {
-                                span: Span {start: start, end: end},
-                                file: name.clone()
-                            }
-                        }
-                    }
-                };
-                return Err(err);
+                errors.push(to_parse_err(e, chunk, *id));
             }
         }
     }
 
-    Ok(to_ast(parse_results))
+    for (pairs, id) in &parse_results {
+        errors.extend(literal_errors(pairs, *id));
+    }
+    (to_ast(parse_results, comments, sources), errors)
+}
+
+/// A token of a file as the grammar sees it, which is one of the innermost
+/// rules it matched, such as a name or a number. Keywords and punctuation are
+/// matched as part of the rules around them, so aren't tokens of their own
+#[derive(Debug, PartialEq)]
+pub struct Token {
+    pub rule: String,
+    pub info: NodeInfo
+}
+
+/// The tokens of the file `id`, in order, without recovering from errors
+pub fn tokens(sources: &SourceMap, id: FileId) -> Result<Vec<Token>, SpruceErr> {
+    let source = match sources.get(id) {
+        Some(source) => source,
+        None => return Ok(Vec::new())
+    };
+    let rule = if interface::is_interface(&source.name) { Rule::interface } else { Rule::file };
+    let pairs = ExprParser::parse(rule, &source.text).map_err(|e| to_parse_err(e, &source.text, id))?;
+    Ok(pairs.flatten()
+        .filter(|pair| pair.as_str() != "" && pair.clone().into_inner().next().is_none())
+        .map(|pair| Token { rule: format!("{:?}", pair.as_rule()), info: NodeInfo { span: Span::from(pair.as_span()), file: id } })
+        .collect())
+}
+
+pub fn parse(sources: &SourceMap) -> Result<Prog, Vec<SpruceErr>> {
+    let (prog, errors) = parse_partial(sources);
+    if errors.is_empty() {
+        Ok(prog)
+    }
+    else {
+        Err(errors)
+    }
+}
+
+#[test]
+fn comment_spans() {
+    let src = "# one\nf() { /* two /* nested */ */\n    1 // three\n}\n";
+    let prog = parse(&SourceMap::from_files(&vec![(src, String::from("main"))])).expect("comments should parse");
+
+    let texts: Vec<&str> = prog.comments.iter().map(|c| { c.val.text.as_str() }).collect();
+    assert_eq!(texts, vec!["# one", "/* two /* nested */ */", "// three"]);
+
+    let nested = &prog.comments[1].info.span;
+    assert_eq!(&src[nested.start..nested.end], "/* two /* nested */ */");
+}
+
+#[test]
+fn literal_forms() {
+    assert_eq!(to_num("0xFF"), Ok(255));
+    assert_eq!(to_num("0b1010"), Ok(10));
+    assert_eq!(to_num("1_000_000"), Ok(1000000));
+    assert_eq!(to_num("0xdead_beef"), Ok(3735928559));
+    assert_eq!(to_num("-0b1"), Ok(-1));
+
+    // whole numbers are exact all the way to the ends of an Int
+    assert_eq!(to_num("9007199254740993"), Ok(9007199254740993));
+    assert_eq!(to_num("-9223372036854775808"), Ok(i64::MIN));
+    assert_eq!(to_num("0x7FFF_FFFF_FFFF_FFFF"), Ok(i64::MAX));
+    assert!(to_num("0xFFFF_FFFF_FFFF_FFFF").is_err());
+    assert!(to_num("9223372036854775808").is_err());
+
+    // and a fraction or an exponent is refused rather than cut off
+    assert_eq!(to_num("2.7").map_err(|(code, _)| code), Err(ErrorCode::FractionalLiteral));
+    assert_eq!(to_num("1e3").map_err(|(code, _)| code), Err(ErrorCode::FractionalLiteral));
+
+    let src = "x = -9223372036854775808\ny = 9223372036854775808\nf(n) {\n    case n {\n        0b1_0000000000000000000000000000000000000000000000000000000000000000 -> 1\n        _ -> 0\n    }\n}\n";
+    let (prog, errors) = parse_partial(&SourceMap::from_files(&vec![(src, String::from("main"))]));
+    match &prog.definitions[0].val {
+        Stmt::Assign(_, expr) => assert_eq!(expr.val, Expr::Lit(i64::MIN)),
+        stmt => panic!("expected an assignment, not {:?}", stmt)
+    }
+    let spans: Vec<&str> = errors.iter().map(|e| &src[e.info.span.start..e.info.span.end]).collect();
+    assert_eq!(spans, vec!["9223372036854775808", "0b1_0000000000000000000000000000000000000000000000000000000000000000"]);
+    assert!(errors.iter().all(|e| e.code == Some(ErrorCode::LiteralOutOfRange)));
+
+    let src = "x = 0x_1\n";
+    assert_eq!(parse(&SourceMap::from_files(&vec![(src, String::from("main"))])).is_err(), true);
+}
+
+#[test]
+fn parse_recovery() {
+    let src = "
+f() {
+    1 +
+}
+
+/// documented
+g() {
+    2
+}
+
+x = = 3
+h() {
+    case 1 {
+}
+";
+    let (prog, errors) = parse_partial(&SourceMap::from_files(&vec![(src, String::from("main"))]));
+    assert_eq!(errors.len(), 3);
+    assert_eq!(prog.functions.len(), 1);
+    assert_eq!(prog.functions[0].val.name, "g");
+    assert_eq!(prog.functions[0].val.doc, Some(String::from("documented")));
+
+    // errors point into the original file rather than into a chunk
+    let line_of = |offset: usize| { src[..offset].matches('\n').count() + 1 };
+    let lines: Vec<usize> = errors.iter().map(|e| { line_of(e.info.span.start) }).collect();
+    assert_eq!(lines, vec![3, 11, 14]);
 }
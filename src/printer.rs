@@ -0,0 +1,488 @@
+/*
+The printer writes a parsed file back out as source that parses to the same
+AST, other than where each part is. It's what tools that rewrite a program
+build on: they change the AST and print it, rather than editing text.
+
+Unlike the formatter, the printer doesn't look at the source, so it only has
+what the AST keeps. Comments aren't printed, numbers are written as the
+values they stand for, and patterns like `x :: rest` are written as the
+constructor they stand for. Parentheses are only added where the operators'
+precedence needs them, and where a call ending a body would otherwise be
+read as a statement.
+*/
+
+use std::collections::HashSet;
+
+use crate::fmt::{binary, needs_parens, sig_type, type_id};
+use crate::parser::{self, BodyNode, CaseBody, CaseNode, CasePattern, Expr, ExprNode, FuncNode, ModuleNode, Prog, SignatureNode, Stmt, StmtNode, Target, TypeNode};
+use crate::source::FileId;
+
+const INDENT: &str = "    ";
+
+/// A top level declaration, in the order they're printed in
+enum Item<'a> {
+    Import(&'a parser::ImportNode),
+    Type(&'a TypeNode),
+    Signature(&'a SignatureNode),
+    Definition(&'a StmtNode),
+    Function(&'a FuncNode)
+}
+
+impl<'a> Item<'a> {
+    fn start(&self) -> usize {
+        match self {
+            Item::Import(import) => import.info.span.start,
+            Item::Type(t) => t.info.span.start,
+            Item::Signature(sig) => sig.info.span.start,
+            Item::Definition(def) => def.info.span.start,
+            Item::Function(func) => func.info.span.start
+        }
+    }
+
+    /// Whether the declaration is written over several lines, and so gets a
+    /// blank line either side
+    fn block(&self) -> bool {
+        match self {
+            Item::Type(t) => !t.val.builtin,
+            Item::Function(_) => true,
+            _ => false
+        }
+    }
+}
+
+/// The declarations in `prog` from `file` as source, in the order they were
+/// written
+pub fn print(prog: &Prog, file: FileId) -> String {
+    let module = prog.modules.iter().find(|module| module.info.file == file);
+    let mut items: Vec<Item> = Vec::new();
+    items.extend(module.into_iter().flat_map(|module| module.val.imports.iter()).map(Item::Import));
+    items.extend(prog.types.iter().filter(|t| t.info.file == file).map(Item::Type));
+    items.extend(prog.signatures.iter().filter(|sig| sig.info.file == file).map(Item::Signature));
+    items.extend(prog.definitions.iter().filter(|def| def.info.file == file).map(Item::Definition));
+    items.extend(prog.functions.iter().filter(|func| func.info.file == file).map(Item::Function));
+    items.sort_by_key(|item| item.start());
+
+    let tails = prog.tail_calls.iter().filter(|info| info.file == file).map(|info| (info.span.start, info.span.end)).collect();
+    let mut printer = Printer { tails: tails, out: String::new() };
+
+    // an interface file names its module and gives the types of its
+    // declarations, where a source file gives the types of its builtins
+    let interface = module.map_or(false, |module| module.val.interface);
+    if let Some(module) = module.filter(|module| module.info.span.end > 0) {
+        printer.out.push_str(&format!("{}\n", module_decl(module)));
+    }
+    let mut last_block = true;
+    for item in &items {
+        if !printer.out.is_empty() && (last_block || item.block()) {
+            printer.out.push('\n');
+        }
+        last_block = item.block();
+        match item {
+            Item::Import(import) => printer.line(0, &format!("import {}", import.val.module)),
+            Item::Type(t) => printer.type_decl(t),
+            Item::Signature(sig) => {
+                let keyword = if interface { "" } else { "builtin " };
+                printer.line(0, &format!("{}{} : {}", keyword, sig.val.name, sig_type(&sig.val.ty)));
+            }
+            Item::Definition(def) => printer.stmt(def, 0),
+            Item::Function(func) => printer.func(func)
+        }
+    }
+    printer.out
+}
+
+fn module_decl(module: &ModuleNode) -> String {
+    match &module.val.exports {
+        Some(exports) => {
+            let names: Vec<String> = exports.iter().map(|export| {
+                format!("{}{}", export.val.name, if export.val.constructors { "(..)" } else { "" })
+            }).collect();
+            format!("module {} ({})", module.val.name, names.join(", "))
+        }
+        None => format!("module {}", module.val.name)
+    }
+}
+
+struct Printer {
+    // the spans of the calls marked `@tail`
+    tails: HashSet<(usize, usize)>,
+    out: String
+}
+
+impl Printer {
+    fn line(&mut self, indent: usize, line: &str) {
+        self.out.push_str(&INDENT.repeat(indent));
+        self.out.push_str(line);
+        self.out.push('\n');
+    }
+
+    fn docs(&mut self, doc: Option<&String>) {
+        for line in doc.into_iter().flat_map(|doc| doc.split('\n')) {
+            self.line(0, &format!("/// {}", line));
+        }
+    }
+
+    fn type_decl(&mut self, t: &TypeNode) {
+        self.docs(t.val.doc.as_ref());
+        let params = if t.val.type_params.is_empty() { String::new() } else { format!("({})", t.val.type_params.join(", ")) };
+        if t.val.builtin {
+            self.line(0, &format!("builtin type {}{}", t.val.name, params));
+            return;
+        }
+
+        self.line(0, &format!("type {}{} {{", t.val.name, params));
+        // a type needs a line between its braces even without constructors
+        if t.val.options.is_empty() {
+            self.out.push('\n');
+        }
+        for option in &t.val.options {
+            let args: Vec<String> = option.val.args.iter().map(type_id).collect();
+            let option = if args.is_empty() { option.val.name.clone() } else { format!("{}({})", option.val.name, args.join(", ")) };
+            self.line(1, &option);
+        }
+        self.line(0, "}");
+    }
+
+    fn func(&mut self, func: &FuncNode) {
+        self.docs(func.val.doc.as_ref());
+        let header = match parser::test_name(&func.val.name) {
+            Some(_) => func.val.name.clone(),
+            None => format!("{}({})", func.val.name, func.val.args.join(", "))
+        };
+        self.line(0, &format!("{} {{", header));
+        self.body(&func.val.body, 1);
+        self.line(0, "}");
+    }
+
+    fn body(&mut self, body: &BodyNode, indent: usize) {
+        for stmt in &body.val.stmts {
+            self.stmt(stmt, indent);
+        }
+        if let Some(expr) = &body.val.expr {
+            // a call on a line of its own is a statement, so one that gives
+            // the body's value is kept apart from them by parentheses
+            let line = match &expr.val {
+                Expr::FnCall(..) => format!("({})", self.expr(expr)),
+                _ => self.expr(expr)
+            };
+            self.line(indent, &line);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &StmtNode, indent: usize) {
+        match &stmt.val {
+            Stmt::Assign(target, expr) => {
+                let target = match &target.val {
+                    Target::Var(name) => format!("{} = ", name),
+                    Target::Mutable(name) => format!("mut {} = ", name),
+                    Target::Update(name) => format!("{} := ", name)
+                };
+                let line = format!("{}{}", target, self.expr(expr));
+                self.line(indent, &line);
+            }
+            Stmt::FnCall(name, args) => {
+                let args: Vec<&ExprNode> = args.iter().collect();
+                let line = self.call(self.tails.contains(&(stmt.info.span.start, stmt.info.span.end)), name, &args);
+                self.line(indent, &line);
+            }
+            Stmt::Case(case) => self.case(case, indent)
+        }
+    }
+
+    fn case(&mut self, case: &CaseNode, indent: usize) {
+        let header = format!("case {} {{", self.expr(&case.val.expr));
+        self.line(indent, &header);
+        for option in &case.val.options {
+            let pattern = match &option.val.pattern.val {
+                CasePattern::ADT(name, args) if args.is_empty() => name.clone(),
+                CasePattern::ADT(name, args) => format!("{}({})", name, args.join(", ")),
+                CasePattern::Lit(n) => n.to_string(),
+                CasePattern::Any => String::from("_")
+            };
+            match &option.val.body.val {
+                CaseBody::Expr(expr) => {
+                    let line = format!("{} -> {}", pattern, self.expr(expr));
+                    self.line(indent + 1, &line);
+                }
+                CaseBody::Body(body) => {
+                    self.line(indent + 1, &format!("{} -> {{", pattern));
+                    self.body(body, indent + 2);
+                    self.line(indent + 1, "}");
+                }
+            }
+        }
+        self.line(indent, "}");
+    }
+
+    fn expr(&self, expr: &ExprNode) -> String {
+        match &expr.val {
+            // a negative literal is written as the negation the parser
+            // folds back into it
+            Expr::Lit(n) => n.to_string(),
+            Expr::Id(name) => name.clone(),
+            Expr::FnCall(name, args) => {
+                let args: Vec<&ExprNode> = args.iter().map(|arg| arg.as_ref()).collect();
+                self.call(self.tails.contains(&(expr.info.span.start, expr.info.span.end)), name, &args)
+            }
+            Expr::List(elements) => {
+                let elements: Vec<String> = elements.iter().map(|element| self.expr(element)).collect();
+                format!("[{}]", elements.join(", "))
+            }
+            Expr::Neg(inner) => format!("-{}", self.operand(inner, binary(&inner.val).is_some())),
+            _ => {
+                let (left, op, right) = binary(&expr.val).expect("every other expression is binary");
+                format!("{} {} {}", self.operand(left, needs_parens(op, left, false)), op, self.operand(right, needs_parens(op, right, true)))
+            }
+        }
+    }
+
+    fn operand(&self, expr: &ExprNode, parens: bool) -> String {
+        if parens { format!("({})", self.expr(expr)) } else { self.expr(expr) }
+    }
+
+    fn call(&self, tail: bool, name: &str, args: &[&ExprNode]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| self.expr(arg)).collect();
+        format!("{}{}({})", if tail { "@tail " } else { "" }, name, args.join(", "))
+    }
+}
+
+/// What the parser made of the one file `text`, as `name`, other than where
+/// each part is and the comments, which the printer can't keep
+#[cfg(test)]
+fn shape(name: &str, text: &str) -> Result<String, Vec<crate::error::SpruceErr>> {
+    let mut sources = crate::source::SourceMap::new();
+    sources.add(name, text);
+    let prog = parser::parse(&sources)?;
+    // calls are told apart by name, since where they are changes
+    let tails: Vec<&str> = prog.tail_calls.iter().map(|info| {
+        let call = text[info.span.start..info.span.end].trim_start_matches("@tail").trim_start();
+        &call[..call.find('(').unwrap_or(call.len())]
+    }).collect();
+    let debug = format!("{:?}", (&prog.functions, &prog.definitions, &prog.types, &prog.signatures, &prog.modules, tails));
+
+    let mut shape = String::new();
+    let mut rest = debug.as_str();
+    while let Some(start) = rest.find("span: Span { ") {
+        shape.push_str(&rest[..start]);
+        rest = &rest[start..];
+        rest = &rest[rest.find(" }").unwrap() + 2..];
+    }
+    shape.push_str(rest);
+    Ok(shape)
+}
+
+/// Prints the file `text`, and checks what it prints parses to what it did,
+/// and prints the same again
+#[cfg(test)]
+fn assert_round_trip(name: &str, text: &str) {
+    let mut sources = crate::source::SourceMap::new();
+    let file = sources.add(name, text);
+    let prog = parser::parse(&sources).unwrap_or_else(|errors| panic!("{} doesn't parse: {}", name, errors[0].as_str(&sources)));
+    let printed = print(&prog, file);
+    let reparsed = shape(name, &printed).unwrap_or_else(|errors| panic!("{} printed as\n{}\nwhich doesn't parse: {}", name, printed, errors[0].message));
+    let expected = shape(name, text).ok().unwrap();
+    let same = expected.chars().zip(reparsed.chars()).take_while(|(a, b)| a == b).count();
+    assert!(reparsed == expected, "{} printed as\n{}\nwhich parses differently, from\n{}\nto\n{}", name, printed, expected.chars().skip(same.saturating_sub(100)).take(300).collect::<String>(), reparsed.chars().skip(same.saturating_sub(100)).take(300).collect::<String>());
+
+    let mut sources = crate::source::SourceMap::new();
+    let file = sources.add(name, &printed);
+    let prog = parser::parse(&sources).ok().unwrap();
+    assert_eq!(print(&prog, file), printed);
+}
+
+#[test]
+fn print_programs() {
+    assert_round_trip(crate::name_analysis::PRELUDE_FILE, crate::name_analysis::PRELUDE);
+    for dir in &["samples", "benches", "tests/golden/errors", "tests/golden/inference"] {
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            let name = path.to_string_lossy().into_owned();
+            let text = std::fs::read_to_string(&path).unwrap();
+            // the golden tests include a program that doesn't parse
+            if path.extension().map_or(false, |ext| ext == "sp") && shape(&name, &text).is_ok() {
+                assert_round_trip(&name, &text);
+            }
+        }
+    }
+
+    let interface = "module Stack (Stack, push)\nimport List\n\ntype Stack(a) {\n    Stack(List(a))\n}\n\npush : (a, Stack(a)) -> Stack(a)\nempty : () -> Stack(Int)\n";
+    assert_round_trip(&format!("stack.{}", crate::interface::EXTENSION), interface);
+
+    let prog = "module main (Tree(..), depth)
+import List
+
+/// a tree
+///
+///   with an indented line
+type Tree(a) {
+    Leaf
+    Node(Tree(a), a, Tree(a))
+}
+
+type Empty {
+
+}
+
+builtin type Handle(a)
+builtin open : (String, (Int) -> ()) -> Handle(Int)
+
+depth(t) {
+    case t {
+        Leaf -> 0
+        Node(l, v, r) -> {
+            mut d = max(depth(l), depth(r))
+            d := d + 1
+            (max(d, 0))
+        }
+    }
+}
+
+sum(xs, acc) {
+    case xs {
+        x :: rest -> @tail sum(rest, acc + x)
+        -1 -> -acc ^ 2
+        0x10 -> -(acc - 1)
+        _ -> acc
+    }
+}
+
+test \"depth of a leaf\" {
+    print(-2 ^ -x, a - (b - c), (a :: b) :: c, a :: b :: c, f >> g << h)
+    (a == b) == (c != d)
+}
+
+x = [1, 1_000, 0b11, -0xf]
+";
+    assert_round_trip("main.sp", prog);
+}
+
+/// Programs made up from a seed, which aren't meant to check, only parse
+#[cfg(test)]
+struct Generator {
+    state: u64
+}
+
+#[cfg(test)]
+impl Generator {
+    fn below(&mut self, n: usize) -> usize {
+        // xorshift, which is plenty random for picking what to write next
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state % n as u64) as usize
+    }
+
+    fn pick<'a>(&mut self, options: &[&'a str]) -> &'a str {
+        options[self.below(options.len())]
+    }
+
+    fn list(&mut self, most: usize, mut each: impl FnMut(&mut Self) -> String) -> String {
+        let n = self.below(most + 1);
+        (0..n).map(|_| each(self)).collect::<Vec<String>>().join(", ")
+    }
+
+    fn expr(&mut self, depth: usize) -> String {
+        if depth == 0 || self.below(3) == 0 {
+            return self.term(depth);
+        }
+        let op = self.pick(&[">>>", "<<<", "^^^", "&", "|", ">>", "<<", "::", "+", "-", "*", "/", "^", "%", "==", "!=", "<=", ">=", "<", ">"]);
+        format!("{} {} {}", self.expr(depth - 1), op, self.expr(depth - 1))
+    }
+
+    fn term(&mut self, depth: usize) -> String {
+        let choice = if depth == 0 { self.below(2) } else { self.below(6) };
+        match choice {
+            0 => String::from(self.pick(&["a", "b", "xs", "n", "List.map", "True"])),
+            1 => String::from(self.pick(&["0", "7", "42", "1_000", "0x1F", "0b101", "-0b1", "0xdead_beef"])),
+            2 => format!("-{}", self.term(depth - 1)),
+            3 => {
+                let tail = if self.below(4) == 0 { "@tail " } else { "" };
+                let name = self.pick(&["f", "g", "List.length"]);
+                format!("{}{}({})", tail, name, self.list(3, |gen| gen.expr(depth - 1)))
+            }
+            4 => format!("[{}]", self.list(3, |gen| gen.expr(depth - 1))),
+            _ => format!("({})", self.expr(depth - 1))
+        }
+    }
+
+    fn body(&mut self, indent: usize, depth: usize) -> String {
+        let pad = INDENT.repeat(indent);
+        let mut body = String::new();
+        for _ in 0..self.below(4) {
+            body.push_str(&pad);
+            let stmt = match self.below(if depth == 0 { 4 } else { 5 }) {
+                0 => format!("x = {}", self.expr(depth + 1)),
+                1 => format!("mut y = {}", self.expr(depth + 1)),
+                2 => format!("y := {}", self.expr(depth + 1)),
+                3 => format!("{}f({})", if self.below(3) == 0 { "@tail " } else { "" }, self.list(2, |gen| gen.expr(depth + 1))),
+                _ => self.case(indent, depth - 1)
+            };
+            body.push_str(&stmt);
+            body.push('\n');
+        }
+        match self.below(if depth == 0 { 2 } else { 3 }) {
+            0 => (),
+            1 => body.push_str(&format!("{}{}\n", pad, self.expr(depth + 1))),
+            _ => body.push_str(&format!("{}{}\n", pad, self.case(indent, depth - 1)))
+        }
+        body
+    }
+
+    fn case(&mut self, indent: usize, depth: usize) -> String {
+        let pad = INDENT.repeat(indent);
+        // `case (x)` reads as a call to `case`, which the parser doesn't go
+        // back on where a statement follows
+        let mut scrutinee = self.expr(depth + 1);
+        while scrutinee.starts_with('(') {
+            scrutinee = self.expr(depth + 1);
+        }
+        let mut case = format!("case {} {{\n", scrutinee);
+        for _ in 0..self.below(3) + 1 {
+            let pattern = self.pick(&["_", "Nil", "Cons(h, t)", "h :: t", "Just(v)", "3", "-2", "0x10", "0b1"]);
+            if self.below(2) == 0 {
+                case.push_str(&format!("{}{}{} -> {}\n", pad, INDENT, pattern, self.expr(depth + 1)));
+            }
+            else {
+                case.push_str(&format!("{}{}{} -> {{\n{}{}{}}}\n", pad, INDENT, pattern, self.body(indent + 2, depth), pad, INDENT));
+            }
+        }
+        case.push_str(&format!("{}}}", pad));
+        case
+    }
+
+    fn docs(&mut self) -> String {
+        self.pick(&["", "/// documented\n", "/// two\n///\n///   lines\n"]).to_string()
+    }
+
+    fn prog(&mut self) -> String {
+        let mut prog = String::from(self.pick(&["", "module m\n", "module m (Tree(..), f)\n"]));
+        for _ in 0..self.below(8) + 1 {
+            let item = match self.below(8) {
+                0 => format!("import {}\n", self.pick(&["List", "Maybe"])),
+                1 => format!("{}type Tree(a) {{\n    Leaf\n    Node(Tree(a), a, List(Tree(a)))\n}}\n", self.docs()),
+                2 => format!("{}type Empty {{\n\n}}\n", self.docs()),
+                3 => format!("{}builtin type Handle(a)\n{}builtin {}\n", self.docs(), self.docs(), self.pick(&["h : Int", "g : () -> ()", "f : (Int, List(a)) -> (a) -> Bool"])),
+                4 => format!("z = {}\n", self.expr(3)),
+                5 => format!("{}test \"gives {}\" {{\n{}}}\n", self.docs(), self.below(100), self.body(1, 2)),
+                _ => format!("{}f(a, b) {{\n{}}}\n", self.docs(), self.body(1, 2))
+            };
+            prog.push_str(&item);
+            if self.below(2) == 0 {
+                prog.push('\n');
+            }
+        }
+        prog
+    }
+}
+
+#[test]
+fn print_generated_programs() {
+    for seed in 1..500u64 {
+        let mut gen = Generator { state: seed.wrapping_mul(0x9E37_79B9_7F4A_7C15) };
+        let prog = gen.prog();
+        if let Err(errors) = shape("main.sp", &prog) {
+            panic!("seed {} made a program that doesn't parse: {}\n{}", seed, errors[0].message, prog);
+        }
+        assert_round_trip("main.sp", &prog);
+    }
+}
@@ -0,0 +1,195 @@
+/*
+The profiler behind `spruce run --profile`, which times each call of a
+program's functions as the tree-walker or the VM runs it. It reports, for
+each function, how many times it was called, the time spent in it and the
+calls it made (its total), and the time spent in it alone (its own):
+
+    function      calls       total         own
+    fact              4    0.021 ms    0.019 ms
+    main              1    0.030 ms    0.009 ms
+
+A recursive function's total only counts its outermost calls, so time isn't
+counted twice. `--profile=<file>` also writes the stacks the run was in as
+collapsed stacks, one per line with the nanoseconds spent there, which
+flamegraph.pl and inferno draw:
+
+    main;fact 1042
+
+A call of a function that's already in the stack counts as being where it
+already is, so a loop is as deep as the functions it goes through, and not
+as deep as it recursed.
+
+The tree-walker runs a call in tail position in place of the call it's in,
+so it's in place of it in the stacks as well, where the VM calls it from
+the call it's in.
+*/
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::error::SpruceErr;
+use crate::eval::{self, Frame, Observer, Value};
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+
+/// What the profiler found out about one function
+#[derive(Debug, Default, Clone)]
+pub struct Stats {
+    pub calls: usize,
+    pub total: Duration,
+    pub own: Duration,
+    // the calls of it being made, so only the outermost counts to its total
+    active: usize
+}
+
+/// One of the stacks the run has been in, as a function called from the
+/// stack before it. No function is in a stack twice
+struct Stack {
+    id: na::SymbolID,
+    parent: Option<usize>,
+    own: Duration
+}
+
+/// A call being made
+struct Call {
+    id: na::SymbolID,
+    stack: usize,
+    started: Instant,
+    // the time spent in the calls it made
+    inner: Duration
+}
+
+pub struct Profiler {
+    started: Instant,
+    elapsed: Option<Duration>,
+    calls: Vec<Call>,
+    functions: HashMap<na::SymbolID, Stats>,
+    stacks: Vec<Stack>,
+    children: HashMap<(Option<usize>, na::SymbolID), usize>
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            started: Instant::now(),
+            elapsed: None,
+            calls: Vec::new(),
+            functions: HashMap::new(),
+            stacks: Vec::new(),
+            children: HashMap::new()
+        }
+    }
+
+    /// Starts timing a call of `id`, from the call being made, if any
+    pub fn start_call(&mut self, id: na::SymbolID) {
+        let parent = self.calls.last().map(|call| call.stack);
+        let mut recursive = parent;
+        while let Some(i) = recursive.filter(|i| self.stacks[*i].id != id) {
+            recursive = self.stacks[i].parent;
+        }
+        let stacks = &mut self.stacks;
+        let stack = match recursive {
+            Some(stack) => stack,
+            None => *self.children.entry((parent, id)).or_insert_with(|| {
+                stacks.push(Stack { id: id, parent: parent, own: Duration::ZERO });
+                stacks.len() - 1
+            })
+        };
+        let stats = self.functions.entry(id).or_default();
+        stats.calls += 1;
+        stats.active += 1;
+        self.calls.push(Call { id: id, stack: stack, started: Instant::now(), inner: Duration::ZERO });
+    }
+
+    /// Stops timing the innermost call being made
+    pub fn end_call(&mut self) {
+        let call = match self.calls.pop() {
+            Some(call) => call,
+            None => return
+        };
+        let elapsed = call.started.elapsed();
+        let own = elapsed.saturating_sub(call.inner);
+        self.stacks[call.stack].own += own;
+        let stats = self.functions.entry(call.id).or_default();
+        stats.own += own;
+        stats.active -= 1;
+        if stats.active == 0 {
+            stats.total += elapsed;
+        }
+        if let Some(caller) = self.calls.last_mut() {
+            caller.inner += elapsed;
+        }
+    }
+
+    /// Ends the run, and any calls it was making, as when it fails
+    pub fn stop(&mut self) {
+        while !self.calls.is_empty() {
+            self.end_call();
+        }
+        self.elapsed.get_or_insert(self.started.elapsed());
+    }
+
+    /// What was found out about `id`, if it was called
+    #[cfg(test)]
+    pub fn stats(&self, id: na::SymbolID) -> Option<&Stats> {
+        self.functions.get(&id)
+    }
+
+    /// A table of the functions that were called, the slowest first
+    pub fn report(&self, prog: &na::Prog) -> String {
+        let mut functions: Vec<(&str, &Stats)> = self.functions.iter().map(|(id, stats)| (eval::symbol_name(prog, *id), stats)).collect();
+        functions.sort_by(|(a, a_stats), (b, b_stats)| b_stats.total.cmp(&a_stats.total).then(b_stats.own.cmp(&a_stats.own)).then(a.cmp(b)));
+
+        let width = functions.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max("function".len());
+        let mut out = format!("{:<width$}  {:>9}  {:>12}  {:>12}\n", "function", "calls", "total", "own", width = width);
+        for (name, stats) in functions {
+            out.push_str(&format!("{:<width$}  {:>9}  {:>12}  {:>12}\n", name, stats.calls, millis(stats.total), millis(stats.own), width = width));
+        }
+        if let Some(elapsed) = self.elapsed {
+            out.push_str(&format!("the run took {}\n", millis(elapsed)));
+        }
+        out
+    }
+
+    /// Each stack the run was in, as the functions in it from the outermost
+    /// separated by `;`, and the nanoseconds spent in it
+    pub fn collapsed(&self, prog: &na::Prog) -> String {
+        let mut lines: Vec<String> = self.stacks.iter().map(|stack| {
+            let mut names = vec![eval::symbol_name(prog, stack.id)];
+            let mut parent = stack.parent;
+            while let Some(i) = parent {
+                names.push(eval::symbol_name(prog, self.stacks[i].id));
+                parent = self.stacks[i].parent;
+            }
+            names.reverse();
+            format!("{} {}", names.join(";"), stack.own.as_nanos())
+        }).collect();
+        lines.sort();
+        lines.iter().map(|line| format!("{}\n", line)).collect()
+    }
+}
+
+fn millis(time: Duration) -> String {
+    format!("{:.3} ms", time.as_secs_f64() * 1000.0)
+}
+
+impl Observer for Profiler {
+    fn enter(&mut self, _: &na::Prog, id: na::SymbolID, _: &NodeInfo) -> Result<(), SpruceErr> {
+        self.start_call(id);
+        Ok(())
+    }
+
+    fn leave(&mut self, _: &na::Prog, _: na::SymbolID, _: Option<&Value>) {
+        self.end_call();
+    }
+
+    fn reach(&mut self, _: &na::Prog, _: &Frame, _: &NodeInfo) -> Result<(), SpruceErr> {
+        Ok(())
+    }
+}
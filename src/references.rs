@@ -0,0 +1,469 @@
+/*
+An index of every name in a program that stands for a symbol or a
+constructor, by where it is, for tools that go from a name to where it's
+declared or to everywhere else it's used. It's built from the name analysis
+IR, which knows what each name stands for, but not always exactly where the
+name is: a call covers its arguments along with its name, and a function
+covers its doc comment, parameters and body. So the spans of names are found
+in the source text within the spans of the nodes, by looking for a word
+spelled the way the name is.
+
+Names the compiler makes up, such as the constructors a list literal or `::`
+is built with, aren't in the source, so they aren't in the index.
+*/
+
+use std::collections::HashMap;
+
+use crate::name_analysis::{self as na, ADTValID, SymbolID};
+use crate::parser::{self, NodeInfo, Span};
+use crate::source::{FileId, SourceMap};
+
+/// What a name in the source stands for
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum Referent {
+    Symbol(SymbolID),
+    Constructor(ADTValID)
+}
+
+/// A name in the source, and what it stands for
+#[derive(Debug, PartialEq, Clone)]
+pub struct Occurrence {
+    pub referent: Referent,
+    pub info: NodeInfo,
+    // whether this is where what it stands for is declared
+    pub declaration: bool
+}
+
+/// Every name in a program that stands for a symbol or a constructor
+#[derive(Debug, PartialEq)]
+pub struct References {
+    // in order of file, then of where they start
+    occurrences: Vec<Occurrence>,
+    names: HashMap<Referent, String>,
+    // what each module declares at the top level, for telling whether a
+    // name is taken
+    modules: Vec<na::Module>,
+    declarations: Vec<Declaration>,
+    // each function, whose variables are the names declared within it
+    functions: Vec<(SymbolID, NodeInfo)>,
+    prelude: Option<FileId>
+}
+
+/// A symbol or constructor declared at the top level of a module
+#[derive(Debug, PartialEq)]
+struct Declaration {
+    referent: Referent,
+    file: FileId,
+    // the type a constructor builds, which decides whether it's exported
+    data_type: Option<String>
+}
+
+/// A change to the source, replacing what's under `info` with `replacement`
+#[derive(Debug, PartialEq, Clone)]
+pub struct Edit {
+    pub info: NodeInfo,
+    pub replacement: String
+}
+
+impl References {
+    pub fn new(prog: &na::Prog, sources: &SourceMap) -> Self {
+        let mut index = Indexer {
+            prog: prog,
+            sources: sources,
+            occurrences: Vec::new(),
+            names: HashMap::new()
+        };
+        index.prog();
+
+        let mut declarations: Vec<Declaration> = top_level(prog).into_iter().map(|(id, file)| {
+            index.name(Referent::Symbol(id));
+            Declaration { referent: Referent::Symbol(id), file: file, data_type: None }
+        }).collect();
+        for val in prog.type_table.values.values() {
+            let module = prog.modules.iter().find(|module| module.name == val.module);
+            if let Some(module) = module {
+                index.name(Referent::Constructor(val.id));
+                let data_type = prog.type_table.types.get(&val.data_type).map(|adt| adt.name.clone());
+                declarations.push(Declaration { referent: Referent::Constructor(val.id), file: module.file, data_type: data_type });
+            }
+        }
+
+        let mut occurrences = index.occurrences;
+        occurrences.sort_by_key(|occurrence| (occurrence.info.file, occurrence.info.span.start));
+        occurrences.dedup();
+        References {
+            occurrences: occurrences,
+            names: index.names,
+            modules: prog.modules.clone(),
+            declarations: declarations,
+            functions: prog.functions.iter().map(|func| (func.val.name, func.info.clone())).collect(),
+            prelude: sources.file_id(na::PRELUDE_FILE)
+        }
+    }
+
+    /// The name at `offset` bytes into `file`, counting the offset just past
+    /// its end as on it, as a cursor there is
+    pub fn at(&self, file: FileId, offset: usize) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| {
+            occurrence.info.file == file && occurrence.info.span.start <= offset && offset <= occurrence.info.span.end
+        })
+    }
+
+    /// Where `referent` is declared, if it's in the source
+    pub fn definition(&self, referent: Referent) -> Option<&Occurrence> {
+        self.occurrences.iter().find(|occurrence| occurrence.referent == referent && occurrence.declaration)
+    }
+
+    /// Every occurrence of `referent`, its declaration among them
+    pub fn of(&self, referent: Referent) -> Vec<&Occurrence> {
+        self.occurrences.iter().filter(|occurrence| occurrence.referent == referent).collect()
+    }
+
+    /// The name `referent` is spelled with
+    pub fn name(&self, referent: Referent) -> Option<&str> {
+        self.names.get(&referent).map(String::as_str)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Occurrence> {
+        self.occurrences.iter()
+    }
+
+    /// The edits that rename `referent` to `name` everywhere it's named. A
+    /// rename is refused where the new name is taken, or where it would
+    /// hide something else of that name or be hidden by it, since either
+    /// would change what some name stands for
+    pub fn rename(&self, referent: Referent, name: &str) -> Result<Vec<Edit>, String> {
+        let old = self.name(referent).unwrap_or("");
+        let refuse = |reason: String| Err(format!("can't rename '{}' to '{}', since {}", old, name, reason));
+        if !name.starts_with(|c: char| c.is_ascii_alphabetic()) || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return refuse(String::from("a name is a letter followed by letters and digits"));
+        }
+        if parser::KEYWORDS.contains(&name) {
+            return refuse(format!("'{}' is a keyword", name));
+        }
+        if self.definition(referent).is_none() {
+            return refuse(String::from("it isn't declared in the source"));
+        }
+
+        match self.declarations.iter().find(|decl| decl.referent == referent) {
+            // what's declared at the top level is seen by its own module, and
+            // if it's exported, by those importing it
+            Some(decl) => {
+                let owner = self.modules.iter().find(|module| module.file == decl.file);
+                for module in self.modules.iter().filter(|module| owner.map_or(false, |owner| self.sees(module, owner, decl, old))) {
+                    let taken = self.declarations.iter().find(|other| {
+                        other.referent != referent && self.same_kind(other.referent, referent) && self.name(other.referent) == Some(name) && self.visible(module, other)
+                    });
+                    if taken.is_some() {
+                        return refuse(format!("module {} already has a '{}'", module.name, name));
+                    }
+                    if let Referent::Symbol(_) = referent {
+                        if let Some(func) = self.functions.iter().find(|(_, info)| info.file == module.file && self.declares(info, name)) {
+                            return refuse(format!("'{}' has a variable named '{}', which would hide it", self.name(Referent::Symbol(func.0)).unwrap_or(""), name));
+                        }
+                    }
+                }
+            }
+            // a variable is seen by the rest of its function, where it would
+            // hide a top-level name, or be hidden by another variable
+            None => {
+                let declared = self.definition(referent).map(|occurrence| occurrence.info.clone());
+                let func = declared.and_then(|declared| self.functions.iter().find(|(_, info)| {
+                    info.file == declared.file && info.span.start <= declared.span.start && declared.span.end <= info.span.end
+                }));
+                if let Some((func, info)) = func {
+                    if self.declares(info, name) {
+                        return refuse(format!("'{}' already has a variable named '{}'", self.name(Referent::Symbol(*func)).unwrap_or(""), name));
+                    }
+                    let module = self.modules.iter().find(|module| module.file == info.file);
+                    let hidden = module.and_then(|module| self.declarations.iter().find(|decl| {
+                        matches!(decl.referent, Referent::Symbol(_)) && self.name(decl.referent) == Some(name) && self.visible(module, decl)
+                    }));
+                    if hidden.is_some() {
+                        return refuse(format!("it would hide the top-level '{}'", name));
+                    }
+                }
+            }
+        }
+
+        Ok(self.of(referent).into_iter().map(|occurrence| Edit { info: occurrence.info.clone(), replacement: name.to_string() }).collect())
+    }
+
+    /// Whether `module` can see `decl`, declared in `owner` as `name`
+    fn sees(&self, module: &na::Module, owner: &na::Module, decl: &Declaration, name: &str) -> bool {
+        let exported = match &decl.data_type {
+            Some(data_type) => owner.exports_constructors(data_type),
+            None => owner.exports(name)
+        };
+        module.file == owner.file || (exported && (Some(owner.file) == self.prelude || module.imports.iter().any(|import| import.val.module == owner.name)))
+    }
+
+    /// Whether `module` can see `decl` by its name alone
+    fn visible(&self, module: &na::Module, decl: &Declaration) -> bool {
+        let owner = self.modules.iter().find(|owner| owner.file == decl.file);
+        owner.map_or(false, |owner| self.sees(module, owner, decl, self.name(decl.referent).unwrap_or("")))
+    }
+
+    fn same_kind(&self, a: Referent, b: Referent) -> bool {
+        matches!((a, b), (Referent::Symbol(_), Referent::Symbol(_)) | (Referent::Constructor(_), Referent::Constructor(_)))
+    }
+
+    /// Whether a variable named `name` is declared within `info`
+    fn declares(&self, info: &NodeInfo, name: &str) -> bool {
+        self.occurrences.iter().any(|occurrence| {
+            occurrence.declaration && occurrence.info.file == info.file &&
+            info.span.start <= occurrence.info.span.start && occurrence.info.span.end <= info.span.end &&
+            self.name(occurrence.referent) == Some(name) && !self.declarations.iter().any(|decl| decl.referent == occurrence.referent)
+        })
+    }
+}
+
+/// The symbols declared at the top level of each module, with the file each
+/// is in
+fn top_level(prog: &na::Prog) -> Vec<(SymbolID, FileId)> {
+    prog.functions.iter().map(|func| (func.val.name, func.info.file))
+        .chain(prog.signatures.iter().map(|sig| (sig.val.name, sig.info.file)))
+        .chain(prog.definitions.iter().filter_map(|stmt| match &stmt.val {
+            na::Stmt::Assign(target, _) => Some((target.val.id(), stmt.info.file)),
+            _ => None
+        }))
+        .collect()
+}
+
+/// The words in `span` of `text`, each a name or a keyword
+pub fn words(text: &str, span: &Span) -> Vec<Span> {
+    let mut words = Vec::new();
+    let mut start = None;
+    let mut in_word = false;
+    for (i, c) in text[span.start..span.end.min(text.len())].char_indices() {
+        // a word starts with a letter, so the rest of a number isn't one
+        match (start, c.is_ascii_alphanumeric()) {
+            (None, true) if c.is_ascii_alphabetic() && !in_word => start = Some(i),
+            (Some(begun), false) => {
+                words.push(Span { start: span.start + begun, end: span.start + i });
+                start = None;
+            }
+            _ => ()
+        }
+        in_word = c.is_ascii_alphanumeric() || c == '_';
+    }
+    if let Some(begun) = start {
+        words.push(Span { start: span.start + begun, end: span.end.min(text.len()) });
+    }
+    words
+}
+
+struct Indexer<'a> {
+    prog: &'a na::Prog,
+    sources: &'a SourceMap,
+    occurrences: Vec<Occurrence>,
+    names: HashMap<Referent, String>
+}
+
+impl<'a> Indexer<'a> {
+    fn prog(&mut self) {
+        let prog = self.prog;
+        for func in &prog.functions {
+            self.function(func);
+        }
+        for stmt in &prog.definitions {
+            self.stmt(stmt);
+        }
+        for sig in &prog.signatures {
+            self.first(Referent::Symbol(sig.val.name), &sig.info, true);
+        }
+        for ty in &prog.types {
+            let module = self.module(ty.info.file);
+            for option in &ty.val.options {
+                let val = module.and_then(|module| {
+                    prog.type_table.values.values().find(|val| val.name == option.val.name && val.module == module.name)
+                });
+                if let Some(val) = val {
+                    self.first(Referent::Constructor(val.id), &option.info, true);
+                }
+            }
+        }
+
+        // the names a module exports are the module's own declarations
+        for module in &prog.modules {
+            for export in module.exports.iter().flatten() {
+                let id = top_level(prog).into_iter().find(|(id, file)| *file == module.file && self.symbol_name(*id) == export.val.name);
+                if let Some((id, _)) = id {
+                    self.first(Referent::Symbol(id), &export.info, false);
+                }
+            }
+        }
+    }
+
+    fn module(&self, file: FileId) -> Option<&'a na::Module> {
+        self.prog.modules.iter().find(|module| module.file == file)
+    }
+
+    fn symbol_name(&self, id: SymbolID) -> &'a str {
+        self.prog.symbol_table.lookup_id(&id).map_or("", |sym| sym.name.as_str())
+    }
+
+    fn name(&mut self, referent: Referent) -> String {
+        let name = match referent {
+            Referent::Symbol(id) => self.symbol_name(id).to_string(),
+            Referent::Constructor(id) => self.prog.type_table.values.get(&id).map_or(String::new(), |val| val.name.clone())
+        };
+        self.names.insert(referent, name.clone());
+        name
+    }
+
+    fn words(&self, file: FileId, span: &Span) -> Vec<Span> {
+        self.sources.get(file).map_or_else(Vec::new, |source| words(&source.text, span))
+    }
+
+    fn spelled(&self, file: FileId, word: &Span, name: &str) -> bool {
+        self.sources.get(file).map_or(false, |source| source.text.get(word.start..word.end) == Some(name))
+    }
+
+    fn add(&mut self, referent: Referent, file: FileId, span: Span, declaration: bool) {
+        self.occurrences.push(Occurrence {
+            referent: referent,
+            info: NodeInfo { span: span, file: file },
+            declaration: declaration
+        });
+    }
+
+    /// Indexes the first word in `info` spelled as `referent` is
+    fn first(&mut self, referent: Referent, info: &NodeInfo, declaration: bool) {
+        let name = self.name(referent);
+        let words = self.words(info.file, &info.span);
+        if let Some(word) = words.into_iter().find(|word| self.spelled(info.file, word, &name)) {
+            self.add(referent, info.file, word, declaration);
+        }
+    }
+
+    /// Indexes the last word before `end` spelled as `referent` is, which is
+    /// where a qualified name's own name is
+    fn last_before(&mut self, referent: Referent, info: &NodeInfo, end: usize) {
+        let name = self.name(referent);
+        let span = Span { start: info.span.start, end: end.min(info.span.end) };
+        let words = self.words(info.file, &span);
+        if let Some(word) = words.into_iter().rev().find(|word| self.spelled(info.file, word, &name)) {
+            self.add(referent, info.file, word, false);
+        }
+    }
+
+    /// Where the first of `args` starts, which a call's name comes before
+    fn args_start(info: &NodeInfo, args: &[&na::ExprNode]) -> usize {
+        args.first().map_or(info.span.end, |arg| arg.info.span.start)
+    }
+
+    fn function(&mut self, func: &na::FuncNode) {
+        // the name and parameters are on the first line after any docs
+        let source = match self.sources.get(func.info.file) {
+            Some(source) => source,
+            None => return
+        };
+        let text = &source.text[func.info.span.start..func.info.span.end.min(source.text.len())];
+        let mut header = func.info.span.start;
+        for line in text.split('\n') {
+            if !line.trim_start().starts_with("///") {
+                header += line.len() - line.trim_start().len();
+                let end = header + line.find('{').unwrap_or(line.len()) - (line.len() - line.trim_start().len());
+                let words = self.words(func.info.file, &Span { start: header, end: end });
+                let referents = std::iter::once(func.val.name).chain(func.val.args.iter().copied()).map(Referent::Symbol);
+                for (referent, word) in referents.zip(words) {
+                    let name = self.name(referent);
+                    if self.spelled(func.info.file, &word, &name) {
+                        self.add(referent, func.info.file, word, true);
+                    }
+                }
+                break;
+            }
+            header += line.len() + 1;
+        }
+        self.body(&func.val.body);
+    }
+
+    fn body(&mut self, body: &na::BodyNode) {
+        for stmt in &body.val.stmts {
+            self.stmt(stmt);
+        }
+        if let Some(expr) = &body.val.expr {
+            self.expr(expr);
+        }
+    }
+
+    fn stmt(&mut self, stmt: &na::StmtNode) {
+        match &stmt.val {
+            na::Stmt::Assign(target, expr) => {
+                // `mut x` is declared by its last word, and `x :` updated by
+                // its first
+                let referent = Referent::Symbol(target.val.id());
+                match target.val {
+                    na::Target::Update(_) => self.first(referent, &target.info, false),
+                    _ => {
+                        let name = self.name(referent);
+                        let words = self.words(target.info.file, &target.info.span);
+                        if let Some(word) = words.into_iter().rev().find(|word| self.spelled(target.info.file, word, &name)) {
+                            self.add(referent, target.info.file, word, true);
+                        }
+                    }
+                }
+                self.expr(expr);
+            }
+            na::Stmt::FnCall(id, args) => {
+                let args: Vec<&na::ExprNode> = args.iter().collect();
+                self.last_before(Referent::Symbol(*id), &stmt.info, Self::args_start(&stmt.info, &args));
+                for arg in args {
+                    self.expr(arg);
+                }
+            }
+            na::Stmt::Case(case) => self.case(case)
+        }
+    }
+
+    fn case(&mut self, case: &na::CaseNode) {
+        self.expr(&case.val.expr);
+        for option in &case.val.options {
+            let pattern = &option.val.pattern;
+            if let na::CasePattern::ADT(val, ids) = &pattern.val {
+                // a pattern covers its constructor, so its variables are
+                // found after it, up to the arm's arrow. The constructor is
+                // the word before them, unless the pattern is `h :: t`
+                let file = pattern.info.file;
+                let arrow = self.sources.get(file).and_then(|source| source.text[pattern.info.span.start..].find("->"));
+                let end = arrow.map_or(pattern.info.span.end, |arrow| pattern.info.span.start + arrow).min(option.info.span.end);
+                let words = self.words(file, &Span { start: pattern.info.span.start, end: end });
+                if words.len() >= ids.len() {
+                    let (before, binders) = words.split_at(words.len() - ids.len());
+                    let constructor = Referent::Constructor(*val);
+                    let name = self.name(constructor);
+                    if let Some(word) = before.last().filter(|word| self.spelled(file, word, &name)) {
+                        self.add(constructor, file, word.clone(), false);
+                    }
+                    for (id, word) in ids.iter().zip(binders) {
+                        self.add(Referent::Symbol(*id), file, word.clone(), true);
+                    }
+                }
+            }
+            match &option.val.body.val {
+                na::CaseBody::Expr(expr) => self.expr(expr),
+                na::CaseBody::Body(body) => self.body(body)
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &na::ExprNode) {
+        match &expr.val {
+            na::Expr::Id(id) => self.last_before(Referent::Symbol(*id), &expr.info, expr.info.span.end),
+            na::Expr::FnCall(id, args) => {
+                let args: Vec<&na::ExprNode> = args.iter().map(|arg| &**arg).collect();
+                self.last_before(Referent::Symbol(*id), &expr.info, Self::args_start(&expr.info, &args));
+            }
+            na::Expr::ADTVal(val, args) => {
+                let args: Vec<&na::ExprNode> = args.iter().map(|arg| &**arg).collect();
+                self.last_before(Referent::Constructor(*val), &expr.info, Self::args_start(&expr.info, &args));
+            }
+            _ => ()
+        }
+        for child in expr.val.children() {
+            self.expr(child);
+        }
+    }
+}
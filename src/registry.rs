@@ -0,0 +1,142 @@
+/*
+A few of the prelude's types and constructors have meaning to the compiler:
+comparisons produce Bools, list literals and `::` build Lists, Results are
+what a `?` operator would unwrap and pass errors along from, and the
+interpreter's builtins give back Maybes and Orderings. The prelude
+declares them in Spruce like any other type, and once it has been analyzed the
+registry records the ids they were given, under the names the compiler knows
+them by.
+
+Some of the prelude's builtins have rules of their own too, such as `show`
+and `compare` refusing functions. Unlike the types, the compiler can do without them.
+*/
+
+use std::collections::HashMap;
+
+use crate::name_analysis::{ADTID, ADTValID, SymbolID};
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum LangType {
+    Bool,
+    Maybe,
+    Result,
+    List,
+    Ordering
+}
+
+pub const LANG_TYPES: [LangType; 5] = [LangType::Bool, LangType::Maybe, LangType::Result, LangType::List, LangType::Ordering];
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum LangValue {
+    True,
+    False,
+    Just,
+    Nothing,
+    Ok,
+    Err,
+    Cons,
+    Nil,
+    LT,
+    EQ,
+    GT
+}
+
+pub const LANG_VALUES: [LangValue; 11] = [
+    LangValue::True, LangValue::False, LangValue::Just, LangValue::Nothing,
+    LangValue::Ok, LangValue::Err, LangValue::Cons, LangValue::Nil,
+    LangValue::LT, LangValue::EQ, LangValue::GT
+];
+
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
+pub enum LangFn {
+    Show,
+    Compare
+}
+
+pub const LANG_FNS: [LangFn; 2] = [LangFn::Show, LangFn::Compare];
+
+impl LangType {
+    /// What the prelude calls the type
+    pub fn name(&self) -> &'static str {
+        match self {
+            LangType::Bool => "Bool",
+            LangType::Maybe => "Maybe",
+            LangType::Result => "Result",
+            LangType::List => "List",
+            LangType::Ordering => "Ordering"
+        }
+    }
+}
+
+impl LangValue {
+    /// What the prelude calls the constructor
+    pub fn name(&self) -> &'static str {
+        match self {
+            LangValue::True => "True",
+            LangValue::False => "False",
+            LangValue::Just => "Just",
+            LangValue::Nothing => "Nothing",
+            LangValue::Ok => "Ok",
+            LangValue::Err => "Err",
+            LangValue::Cons => "Cons",
+            LangValue::Nil => "Nil",
+            LangValue::LT => "LT",
+            LangValue::EQ => "EQ",
+            LangValue::GT => "GT"
+        }
+    }
+}
+
+impl LangFn {
+    /// What the prelude calls the builtin
+    pub fn name(&self) -> &'static str {
+        match self {
+            LangFn::Show => "show",
+            LangFn::Compare => "compare"
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Registry {
+    types: HashMap<LangType, ADTID>,
+    values: HashMap<LangValue, ADTValID>,
+    fns: HashMap<LangFn, SymbolID>
+}
+
+impl Registry {
+    pub fn new() -> Self {
+        Registry {
+            types: HashMap::new(),
+            values: HashMap::new(),
+            fns: HashMap::new()
+        }
+    }
+
+    pub fn add_type(&mut self, item: LangType, id: ADTID) {
+        self.types.insert(item, id);
+    }
+
+    pub fn add_value(&mut self, item: LangValue, id: ADTValID) {
+        self.values.insert(item, id);
+    }
+
+    pub fn add_fn(&mut self, item: LangFn, id: SymbolID) {
+        self.fns.insert(item, id);
+    }
+
+    /// Name analysis makes sure the prelude declares every item, so one that
+    /// is missing is a bug in the compiler
+    pub fn type_id(&self, item: LangType) -> ADTID {
+        *self.types.get(&item).unwrap_or_else(|| panic!("the registry has no type {}", item.name()))
+    }
+
+    pub fn value_id(&self, item: LangValue) -> ADTValID {
+        *self.values.get(&item).unwrap_or_else(|| panic!("the registry has no constructor {}", item.name()))
+    }
+
+    /// None if the prelude doesn't declare the builtin
+    pub fn fn_id(&self, item: LangFn) -> Option<SymbolID> {
+        self.fns.get(&item).copied()
+    }
+}
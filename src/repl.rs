@@ -0,0 +1,475 @@
+/*
+The REPL keeps a session of definitions entered one at a time, and works out
+expressions against them. Each entry is either:
+
+  - definitions, anything that parses as a file would: functions, types and
+    program-level definitions, which join the session once they check along
+    with everything before them. Defining a name again replaces the entry
+    that defined it, so a function can be fixed and tried again
+  - an expression, which is checked as the value of a variable in a
+    function of its own, run with the interpreter and shown along with its
+    type, but doesn't join the session. Entries that aren't one expression,
+    such as a few statements, are the body of that function instead. Its
+    errors point at the entry as it was typed, with the function left out
+  - a command, starting with a colon: `:type` shows an expression's type
+    without running it, and `:info` shows what a name is, the declaration
+    of a type, the type a constructor belongs to, or the type of a function
+    or definition, with its doc comment. `:load` reads files into the
+    session, and `:reload` reads them again after they've been edited
+
+The session is checked as a whole with each entry, in a file of its own
+after the prelude, so an entry sees everything entered before it just as a
+module sees its own declarations, and the symbol table and Environment an
+entry is checked with are those of the whole session. Loaded files are
+modules of their own that the session imports, so it sees what they export,
+and reloading them keeps every entry, which is checked again with the files
+as they are now.
+
+An entry can run over several lines: while it has a bracket or a block
+comment left open, the lines after it are read as more of it. Lines are
+read with an editor that keeps a history across sessions, and completes the
+word before the cursor from the session's names, the keywords and the
+commands, or a path after `:load`.
+*/
+
+use std::fs;
+
+use pest::Parser;
+use rustyline::completion::{Completer, FilenameCompleter};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::Context;
+
+use crate::embed::Compiler;
+use crate::error::SpruceErr;
+use crate::eval::{self, Value};
+use crate::interface;
+use crate::lint::Lints;
+use crate::name_analysis::{self as na, PRELUDE_FILE};
+use crate::parser::{self, NodeInfo};
+use crate::source::{FileId, SourceMap};
+use crate::typecheck::{self, Environment, TypeRepr};
+
+/// What the session's file is called in diagnostics
+pub const SESSION_FILE: &str = "repl";
+
+/// The function an expression is the body of. Names can only be letters and
+/// digits, so it's one a session is unlikely to define
+const INPUT_FN: &str = "replInput";
+
+/// The variable an expression is given to, since an expression standing as
+/// a statement is read as a call, and so couldn't be a constructor
+const INPUT_VAR: &str = "replValue";
+
+const COMMANDS: [&str; 5] = [":info", ":load", ":quit", ":reload", ":type"];
+
+/// A checked program and what checking it gave
+type Checked = (na::Prog, typecheck::Prog, Environment);
+
+pub struct Session {
+    compiler: Compiler,
+    // the entries that make up the session, with the names each defines
+    entries: Vec<(String, Vec<String>)>,
+    // the files loaded into the session, with the module each is and what
+    // each held when it was read
+    loaded: Vec<Loaded>,
+    use_color: bool
+}
+
+/// A file loaded into the session
+struct Loaded {
+    path: String,
+    module: String,
+    text: String
+}
+
+/// Whether an entry has a bracket or a block comment still open, so the line
+/// after it is more of it
+pub fn incomplete(input: &str) -> bool {
+    let mut depth: i64 = 0;
+    let mut comments = 0;
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('/', Some('*')) => {
+                chars.next();
+                comments += 1;
+            }
+            ('*', Some('/')) if comments > 0 => {
+                chars.next();
+                comments -= 1;
+            }
+            _ if comments > 0 => (),
+            ('/', Some('/')) | ('#', _) => {
+                chars.find(|c| *c == '\n');
+            }
+            ('(', _) | ('[', _) | ('{', _) => depth += 1,
+            (')', _) | (']', _) | ('}', _) => depth -= 1,
+            _ => ()
+        }
+    }
+    depth > 0 || comments > 0
+}
+
+/// Where the word before `pos` starts, and what it could be completed to
+/// from `names`. A command is completed at the start of the line
+pub fn completions(names: &[String], line: &str, pos: usize) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before.rfind(|c: char| !c.is_ascii_alphanumeric()).map_or(0, |i| i + 1);
+    if before.starts_with(':') && !before.contains(' ') {
+        return (0, COMMANDS.iter().filter(|command| command.starts_with(before)).map(|command| command.to_string()).collect());
+    }
+    if start == pos {
+        return (pos, Vec::new());
+    }
+    let word = &before[start..];
+    let mut found: Vec<String> = names.iter().map(String::as_str).chain(parser::KEYWORDS.iter().copied())
+        .filter(|name| name.starts_with(word))
+        .map(String::from)
+        .collect();
+    found.sort();
+    found.dedup();
+    (start, found)
+}
+
+/// What the line editor is given to complete with
+pub struct Helper {
+    /// The names the session has, as of the last entry
+    pub names: Vec<String>,
+    files: FilenameCompleter
+}
+
+impl Helper {
+    pub fn new(names: Vec<String>) -> Self {
+        Helper {
+            names: names,
+            files: FilenameCompleter::new()
+        }
+    }
+}
+
+impl Completer for Helper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        if line.starts_with(":load ") {
+            let (start, paths) = self.files.complete(line, pos, ctx)?;
+            return Ok((start, paths.into_iter().map(|path| path.replacement).collect()));
+        }
+        Ok(completions(&self.names, line, pos))
+    }
+}
+
+impl Hinter for Helper {
+    type Hint = String;
+}
+
+impl Highlighter for Helper {}
+
+impl Validator for Helper {}
+
+impl rustyline::Helper for Helper {}
+
+/// The names an entry defines, if it parses as definitions, or how far into
+/// it parsing got and the errors it found
+fn definitions(input: &str) -> Result<Vec<String>, (usize, Vec<SpruceErr>)> {
+    let prog = parser::parse(&entry_sources(input)).map_err(|errors| (errors.iter().map(|e| e.info.span.start).max().unwrap_or(0), errors))?;
+    let mut names: Vec<String> = prog.functions.iter().map(|func| func.val.name.clone()).collect();
+    names.extend(prog.definitions.iter().filter_map(|def| match &def.val {
+        parser::Stmt::Assign(tgt, _) => match &tgt.val {
+            parser::Target::Var(name) | parser::Target::Mutable(name) => Some(name.clone()),
+            parser::Target::Update(_) => None
+        },
+        _ => None
+    }));
+    for t in &prog.types {
+        names.push(t.val.name.clone());
+        names.extend(t.val.options.iter().map(|opt| opt.val.name.clone()));
+    }
+    Ok(names)
+}
+
+/// Whether an entry is one expression, rather than statements or a case
+fn is_expr(input: &str) -> bool {
+    let input = input.trim();
+    let expr = parser::ExprParser::parse(parser::Rule::expr, input).ok().and_then(|mut pairs| pairs.next());
+    expr.map_or(false, |expr| expr.as_span().end() == input.len())
+}
+
+/// An entry that isn't definitions, as the function the session runs it in
+struct Wrapped {
+    // the entry as it was typed
+    input: String,
+    // the function, and where in it the entry starts
+    text: String,
+    start: usize
+}
+
+impl Wrapped {
+    /// An expression, given to a variable the function gives back
+    fn expr(input: &str) -> Self {
+        let prefix = format!("{}() {{\n    {} = ", INPUT_FN, INPUT_VAR);
+        Wrapped {
+            input: format!("{}\n", input.trim_end()),
+            text: format!("{}{}\n    {}\n}}\n", prefix, input.trim_end(), INPUT_VAR),
+            start: prefix.len()
+        }
+    }
+
+    /// Statements, as the body of the function
+    fn body(input: &str) -> Self {
+        let prefix = format!("{}() {{\n    ", INPUT_FN);
+        Wrapped {
+            input: format!("{}\n", input.trim_end()),
+            text: format!("{}{}\n}}\n", prefix, input.trim_end()),
+            start: prefix.len()
+        }
+    }
+
+    /// An error in the function, coming after `before` bytes of `file`, as
+    /// it would be had the entry been typed there as it is, without the
+    /// function around it. What points at the function points at the entry
+    fn unwrap(&self, mut err: SpruceErr, file: FileId, before: usize) -> SpruceErr {
+        let (start, end) = (before + self.start, before + self.start + self.input.trim_end().len());
+        let place = |info: &mut NodeInfo| {
+            if info.file == file && info.span.start >= before {
+                info.span.start = info.span.start.clamp(start, end) - self.start;
+                info.span.end = info.span.end.clamp(start, end) - self.start;
+            }
+        };
+        place(&mut err.info);
+        err.labels.iter_mut().for_each(|label| place(&mut label.info));
+        err.suggestions.iter_mut().for_each(|suggestion| place(&mut suggestion.info));
+        err.trace.retain(|(function, _)| function != INPUT_FN);
+        err.trace.iter_mut().for_each(|(_, info)| place(info));
+        err
+    }
+}
+
+/// The type of the value an entry's function gives back
+fn entry_type(env: &Environment) -> String {
+    match env.type_of_symbol(INPUT_FN) {
+        Some(TypeRepr::Func(_, ret)) => ret.to_string(),
+        _ => String::new()
+    }
+}
+
+/// An entry on its own, as it's parsed to see what it is
+fn entry_sources(input: &str) -> SourceMap {
+    let mut sources = SourceMap::new();
+    sources.add(SESSION_FILE, input);
+    sources
+}
+
+impl Session {
+    /// A session whose programs can call the host functions `compiler` has
+    pub fn new(compiler: Compiler, use_color: bool) -> Self {
+        Session {
+            compiler: compiler,
+            entries: Vec::new(),
+            loaded: Vec::new(),
+            use_color: use_color
+        }
+    }
+
+    /// The session's source, with `extra` after its entries
+    fn source(&self, entries: &[(String, Vec<String>)], extra: &str) -> String {
+        let mut source: String = self.loaded.iter().map(|file| format!("import {}\n", file.module)).collect();
+        source.extend(entries.iter().map(|(text, _)| text.as_str()));
+        source.push_str(extra);
+        source
+    }
+
+    fn sources(&self, source: &str) -> SourceMap {
+        let mut sources = SourceMap::new();
+        sources.add(PRELUDE_FILE, &self.compiler.prelude());
+        for file in &self.loaded {
+            sources.add(&file.path, &file.text);
+        }
+        sources.add(SESSION_FILE, source);
+        sources
+    }
+
+    fn check(&self, sources: &SourceMap) -> Result<Checked, String> {
+        crate::compile_with_lints(sources, &mut Lints::new()).map_err(|errors| self.render(sources, &errors))
+    }
+
+    fn render(&self, sources: &SourceMap, errors: &[SpruceErr]) -> String {
+        errors.iter().map(|e| e.render(sources, self.use_color)).collect::<Vec<String>>().join("\n")
+    }
+
+    /// The types, constructors, functions and definitions the session can
+    /// use, for completing names
+    pub fn names(&self) -> Vec<String> {
+        let (prog, _, env) = match self.check(&self.sources(&self.source(&self.entries, ""))) {
+            Ok(checked) => checked,
+            Err(_) => return Vec::new()
+        };
+        let types = &prog.type_table;
+        let mut names: Vec<String> = env.global_names().cloned().collect();
+        names.extend(types.types.values().map(|adt| adt.name.clone()));
+        names.extend(types.values.values().map(|val| val.name.clone()));
+        names
+    }
+
+    /// Handles an entry, giving what to show for it, or the errors it caused
+    pub fn enter(&mut self, input: &str) -> Result<String, String> {
+        let input = if input.ends_with('\n') { String::from(input) } else { format!("{}\n", input) };
+        if input.trim().is_empty() {
+            return Ok(String::new());
+        }
+        if let Some(command) = input.trim().strip_prefix(':') {
+            return self.command(command);
+        }
+        match definitions(&input) {
+            Ok(names) => self.define(input, names),
+            Err(_) if is_expr(&input) => self.evaluate(&Wrapped::expr(&input)),
+            Err((def_reached, def_errors)) => {
+                let wrapped = Wrapped::body(&input);
+                match definitions(&wrapped.text) {
+                    Ok(_) => self.evaluate(&wrapped),
+                    // whichever reading got further is the one meant
+                    Err((expr_reached, expr_errors)) if expr_reached.saturating_sub(wrapped.start) > def_reached => {
+                        Err(self.render_parse(&wrapped, expr_errors))
+                    }
+                    Err(_) => Err(self.render(&entry_sources(&input), &def_errors))
+                }
+            }
+        }
+    }
+
+    fn define(&mut self, input: String, names: Vec<String>) -> Result<String, String> {
+        let mut entries: Vec<(String, Vec<String>)> = self.entries.iter().filter(|(_, defined)| {
+            !defined.iter().any(|name| names.contains(name))
+        }).cloned().collect();
+        entries.push((input, names.clone()));
+        let sources = self.sources(&self.source(&entries, ""));
+        let checked = self.check(&sources)?;
+
+        let (prog, _, env) = &checked;
+        let shown: Vec<String> = names.iter().filter_map(|name| {
+            if prog.type_table.types.values().any(|adt| adt.name == *name) {
+                return Some(format!("type {}", name));
+            }
+            env.type_of_symbol(name).map(|ty| format!("{} : {}", name, ty))
+        }).collect();
+        self.entries = entries;
+        Ok(shown.join("\n"))
+    }
+
+    /// Renders errors in an entry's function, parsed on its own, against
+    /// the entry as it was typed
+    fn render_parse(&self, wrapped: &Wrapped, errors: Vec<SpruceErr>) -> String {
+        let errors: Vec<SpruceErr> = errors.into_iter().map(|e| wrapped.unwrap(e, 0, 0)).collect();
+        self.render(&entry_sources(&wrapped.input), &errors)
+    }
+
+    /// Renders errors in an entry's function, coming after the session's
+    /// entries, against the session with the entry after it as it was typed
+    fn render_entry(&self, wrapped: &Wrapped, errors: Vec<SpruceErr>) -> String {
+        let session = self.source(&self.entries, "");
+        let sources = self.sources(&format!("{}{}", session, wrapped.input));
+        let file = sources.file_id(SESSION_FILE).expect("the session has a file");
+        let errors: Vec<SpruceErr> = errors.into_iter().map(|e| wrapped.unwrap(e, file, session.len())).collect();
+        self.render(&sources, &errors)
+    }
+
+    /// The session with an entry wrapped as a function after it, checked,
+    /// along with the entry's type
+    fn check_entry(&self, wrapped: &Wrapped) -> Result<Checked, String> {
+        let sources = self.sources(&self.source(&self.entries, &wrapped.text));
+        crate::compile_with_lints(&sources, &mut Lints::new()).map_err(|errors| self.render_entry(wrapped, errors))
+    }
+
+    fn evaluate(&self, wrapped: &Wrapped) -> Result<String, String> {
+        let (prog, typed, env) = self.check_entry(wrapped)?;
+        let ty = entry_type(&env);
+        let value = eval::call_fn(&prog, &typed, INPUT_FN, Vec::new(), self.compiler.runtime(Vec::new(), None))
+            .map_err(|e| self.render_entry(wrapped, vec![e]))?;
+        Ok(match value {
+            Value::Unit => String::new(),
+            value => format!("{} : {}", eval::show(&prog, &value), ty)
+        })
+    }
+
+    fn command(&mut self, command: &str) -> Result<String, String> {
+        let (name, arg) = command.split_once(' ').map_or((command, ""), |(name, arg)| (name, arg.trim()));
+        match (name, arg) {
+            ("type", expr) if !expr.is_empty() => {
+                // a constructor only stands on its own when it has no fields,
+                // so one that has them is shown as the function it is
+                if let Some(ty) = self.constructor_type(expr)? {
+                    return Ok(format!("{} : {}", expr, ty));
+                }
+                let wrapped = match is_expr(expr) {
+                    true => Wrapped::expr(expr),
+                    false => Wrapped::body(expr)
+                };
+                if let Err((_, errors)) = definitions(&wrapped.text) {
+                    return Err(self.render_parse(&wrapped, errors));
+                }
+                let (_, _, env) = self.check_entry(&wrapped)?;
+                Ok(format!("{} : {}", expr, entry_type(&env)))
+            }
+            ("info", name) if !name.is_empty() => self.info(name),
+            ("load", paths) if !paths.is_empty() => self.load(paths.split_whitespace().map(String::from).collect()),
+            ("reload", "") if !self.loaded.is_empty() => self.load(self.loaded.iter().map(|file| file.path.clone()).collect()),
+            ("reload", "") => Err(String::from("there are no files to reload, so load some with :load <path>")),
+            _ => Err(String::from("expected :type <expression>, :info <name>, :load <path>, :reload or :quit"))
+        }
+    }
+
+    /// Reads the files at `paths` in place of those loaded, as long as the
+    /// session still checks with them
+    fn load(&mut self, paths: Vec<String>) -> Result<String, String> {
+        let mut loaded = Vec::new();
+        for path in paths {
+            let text = fs::read_to_string(&path).map_err(|err| format!("cannot read {}: {}", path, err))?;
+            let mut file = SourceMap::new();
+            file.add(&path, &text);
+            let prog = parser::parse(&file).map_err(|errors| self.render(&file, &errors))?;
+            let module = prog.modules.first().map_or(String::new(), |module| module.val.name.clone());
+            loaded.push(Loaded { path: path, module: module, text: text });
+        }
+
+        let previous = std::mem::replace(&mut self.loaded, loaded);
+        let sources = self.sources(&self.source(&self.entries, ""));
+        if let Err(errors) = self.check(&sources) {
+            self.loaded = previous;
+            return Err(errors);
+        }
+        Ok(self.loaded.iter().map(|file| format!("loaded {}", file.path)).collect::<Vec<String>>().join("\n"))
+    }
+
+    /// The type of the constructor named `name`, if the session has one
+    fn constructor_type(&self, name: &str) -> Result<Option<TypeRepr>, String> {
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Ok(None);
+        }
+        let (prog, _, env) = self.check(&self.sources(&self.source(&self.entries, "")))?;
+        let val = prog.type_table.values.values().find(|val| val.name == name);
+        Ok(val.and_then(|val| env.type_of_value(val.id)))
+    }
+
+    /// What the name is, as the session sees it
+    fn info(&self, name: &str) -> Result<String, String> {
+        let (prog, _, env) = self.check(&self.sources(&self.source(&self.entries, "")))?;
+        let types = &prog.type_table;
+        let docs = |doc: Option<&String>| doc.map_or(String::new(), |doc| doc.lines().map(|line| format!("/// {}\n", line)).collect());
+        if let Some(adt) = types.types.values().find(|adt| adt.name == name) {
+            return Ok(format!("{}{}", docs(prog.type_doc(name)), interface::write_type(types, adt).trim_end()));
+        }
+        if let Some(val) = types.values.values().find(|val| val.name == name) {
+            let adt = &types.types[&val.data_type];
+            return Ok(format!("{} is a constructor of {}\n{}", name, adt.name, interface::write_type(types, adt).trim_end()));
+        }
+        match env.type_of_symbol(name) {
+            Some(ty) => {
+                let func = prog.functions.iter().find(|func| eval::symbol_name(&prog, func.val.name) == name);
+                Ok(format!("{}{} : {}", docs(func.and_then(|func| prog.function_doc(&func.val.name))), name, ty))
+            }
+            None => Err(format!("'{}' isn't defined", name))
+        }
+    }
+}
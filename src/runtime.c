@@ -0,0 +1,386 @@
+/*
+The runtime linked into native Spruce programs. Every value is a 64-bit word:
+an Int n is stored as 2n + 1, so it's always odd, and anything else is a
+pointer to an object, which is always even. Unit is 0. Objects are a kind, a
+tag, a field count and then the fields, each of which is another word:
+
+    ADT      the constructor's id, and its arguments
+    FUNC     0, and the address of the function
+    COMPOSE  0, and the first and second functions
+    STRING   0, and a pointer to the characters
+    FLOAT    0, and the bits of the double
+
+Objects are bump allocated from large blocks and never freed, since programs
+are short lived.
+
+The generated code provides the names of constructors, the ids of the
+constructors the runtime makes itself, `spruce_init`, which evaluates the
+program's definitions, and `spruce_main`.
+*/
+
+#include <inttypes.h>
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+enum { ADT, FUNC, COMPOSE, STRING, FLOAT };
+
+typedef int64_t value;
+
+extern const char *spruce_names[];
+extern const int64_t spruce_true_id, spruce_false_id, spruce_cons_id, spruce_nil_id;
+extern const int64_t spruce_lt_id, spruce_eq_id, spruce_gt_id;
+extern value spruce_init(void);
+extern value spruce_main(void);
+
+static int spruce_argc;
+static char **spruce_argv;
+
+#define BLOCK_WORDS (1 << 20)
+static int64_t *next_word, *block_end;
+
+static int64_t *words(int64_t n) {
+    if (next_word == NULL || next_word + n > block_end) {
+        int64_t size = n > BLOCK_WORDS ? n : BLOCK_WORDS;
+        next_word = malloc(size * sizeof(int64_t));
+        if (next_word == NULL) {
+            fprintf(stderr, "error: out of memory\n");
+            exit(1);
+        }
+        block_end = next_word + size;
+    }
+    int64_t *start = next_word;
+    next_word += n;
+    return start;
+}
+
+static int64_t *object(value v) {
+    return (int64_t *)(intptr_t)v;
+}
+
+value spruce_alloc(int64_t kind, int64_t tag, int64_t fields) {
+    int64_t *obj = words(3 + fields);
+    obj[0] = kind;
+    obj[1] = tag;
+    obj[2] = fields;
+    return (value)(intptr_t)obj;
+}
+
+value spruce_func(value address) {
+    value func = spruce_alloc(FUNC, 0, 1);
+    object(func)[3] = address;
+    return func;
+}
+
+value spruce_compose(value first, value second) {
+    value func = spruce_alloc(COMPOSE, 0, 2);
+    object(func)[3] = first;
+    object(func)[4] = second;
+    return func;
+}
+
+value spruce_bool(int64_t b) {
+    return spruce_alloc(ADT, b ? spruce_true_id : spruce_false_id, 0);
+}
+
+static value string(char *text) {
+    value str = spruce_alloc(STRING, 0, 1);
+    object(str)[3] = (value)(intptr_t)text;
+    return str;
+}
+
+static const char *chars(value str) {
+    return (const char *)(intptr_t)object(str)[3];
+}
+
+static value boxed(double x) {
+    value f = spruce_alloc(FLOAT, 0, 1);
+    memcpy(&object(f)[3], &x, sizeof x);
+    return f;
+}
+
+static double unboxed(value f) {
+    double x;
+    memcpy(&x, &object(f)[3], sizeof x);
+    return x;
+}
+
+/* A Float as the interpreter writes one: with the fewest digits that read
+   back as the same number, written out in full rather than with an exponent */
+static void show_float(FILE *out, double x) {
+    if (isnan(x)) {
+        fputs("NaN", out);
+        return;
+    }
+    if (isinf(x)) {
+        fputs(x > 0 ? "Infinity" : "-Infinity", out);
+        return;
+    }
+    if (x == 0) {
+        fputs(signbit(x) ? "-0" : "0", out);
+        return;
+    }
+
+    char digits[32];
+    int precision = 1;
+    for (; precision < 17; precision++) {
+        snprintf(digits, sizeof digits, "%.*e", precision - 1, x);
+        if (strtod(digits, NULL) == x) {
+            break;
+        }
+    }
+    snprintf(digits, sizeof digits, "%.*e", precision - 1, x);
+    char *at = digits;
+    if (*at == '-') {
+        fputc('-', out);
+        at++;
+    }
+    /* what's left is d.ddde+XX, which becomes the digits alone */
+    char *e = strchr(at, 'e');
+    int exponent = atoi(e + 1);
+    *e = '\0';
+    if (at[1] == '.') {
+        memmove(at + 1, at + 2, strlen(at + 2) + 1);
+    }
+    int count = (int)strlen(at);
+    if (exponent < 0) {
+        fputs("0.", out);
+        for (int i = exponent; i < -1; i++) {
+            fputc('0', out);
+        }
+        fputs(at, out);
+    }
+    else if (exponent + 1 >= count) {
+        fputs(at, out);
+        for (int i = count; i <= exponent; i++) {
+            fputc('0', out);
+        }
+    }
+    else {
+        fprintf(out, "%.*s.%s", exponent + 1, at, at + exponent + 1);
+    }
+}
+
+/* Floats are ordered by their bits, with those of negative ones but the sign
+   flipped, which puts -0 before 0 and NaN at the ends as the interpreter does */
+static int64_t float_order(double x) {
+    int64_t bits;
+    memcpy(&bits, &x, sizeof bits);
+    return bits < 0 ? bits ^ INT64_MAX : bits;
+}
+
+/* A Float cut to an Int, saturating at the ends of the range tagged Ints
+   have, with NaN as 0 */
+static value to_int(double x) {
+    int64_t n;
+    if (isnan(x)) {
+        n = 0;
+    }
+    else if (x >= 4611686018427387904.0) {
+        n = INT64_MAX >> 1;
+    }
+    else if (x < -4611686018427387904.0) {
+        n = INT64_MIN >> 1;
+    }
+    else {
+        n = (int64_t)x;
+    }
+    return (value)(((uint64_t)n << 1) | 1);
+}
+
+/* A value as it would be written in Spruce, as the show builtin gives it */
+static void show(FILE *out, value v) {
+    if (v == 0) {
+        fputs("()", out);
+        return;
+    }
+    if (v & 1) {
+        fprintf(out, "%" PRId64, v >> 1);
+        return;
+    }
+
+    int64_t *obj = object(v);
+    switch (obj[0]) {
+    case ADT:
+        fputs(spruce_names[obj[1]], out);
+        if (obj[2] > 0) {
+            fputc('(', out);
+            for (int64_t i = 0; i < obj[2]; i++) {
+                if (i > 0) {
+                    fputs(", ", out);
+                }
+                show(out, obj[3 + i]);
+            }
+            fputc(')', out);
+        }
+        break;
+    case STRING:
+        fputs(chars(v), out);
+        break;
+    case FLOAT:
+        show_float(out, unboxed(v));
+        break;
+    default:
+        fputs("<function>", out);
+    }
+}
+
+/* Orders values as the interpreter does: numbers by value, and values of
+   types by constructor name and then by argument */
+static int compare(value a, value b) {
+    if ((a & 1) && (b & 1)) {
+        return (a > b) - (a < b);
+    }
+    if (a == 0 || b == 0 || (a & 1) || (b & 1)) {
+        return (a > b) - (a < b);
+    }
+
+    int64_t *x = object(a), *y = object(b);
+    if (x[0] == STRING && y[0] == STRING) {
+        return strcmp(chars(a), chars(b));
+    }
+    if (x[0] == FLOAT && y[0] == FLOAT) {
+        int64_t i = float_order(unboxed(a)), j = float_order(unboxed(b));
+        return (i > j) - (i < j);
+    }
+    if (x[0] != ADT || y[0] != ADT) {
+        return (a > b) - (a < b);
+    }
+
+    int order = strcmp(spruce_names[x[1]], spruce_names[y[1]]);
+    for (int64_t i = 0; order == 0 && i < x[2] && i < y[2]; i++) {
+        order = compare(x[3 + i], y[3 + i]);
+    }
+    return order != 0 ? order : (x[2] > y[2]) - (x[2] < y[2]);
+}
+
+int64_t spruce_equal(value a, value b) {
+    return compare(a, b) == 0;
+}
+
+void spruce_fail(const char *message, const char *where) {
+    fflush(stdout);
+    fprintf(stderr, "error: %s\n --> %s\n", message, where);
+    exit(1);
+}
+
+void spruce_no_match(value v, const char *where) {
+    fflush(stdout);
+    fputs("error: no option matches ", stderr);
+    show(stderr, v);
+    fprintf(stderr, "\n --> %s\n", where);
+    exit(1);
+}
+
+value spruce_pow(value base, value exp, const char *where) {
+    int64_t b = base >> 1, e = exp >> 1;
+    if (e < 0) {
+        char message[64];
+        snprintf(message, sizeof message, "cannot raise an Int to the negative power %" PRId64, e);
+        spruce_fail(message, where);
+    }
+    uint64_t result = 1;
+    for (; e > 0; e--) {
+        result *= (uint64_t)b;
+    }
+    return (value)((result << 1) | 1);
+}
+
+/* the builtins there are native versions of */
+
+value spruce_builtin_show(value v) {
+    char *text;
+    size_t size;
+    FILE *out = open_memstream(&text, &size);
+    show(out, v);
+    fclose(out);
+    return string(text);
+}
+
+value spruce_builtin_compare(value a, value b) {
+    int order = compare(a, b);
+    return spruce_alloc(ADT, order < 0 ? spruce_lt_id : order > 0 ? spruce_gt_id : spruce_eq_id, 0);
+}
+
+value spruce_builtin_print(value str) {
+    fputs(chars(str), stdout);
+    fflush(stdout);
+    return 0;
+}
+
+value spruce_builtin_printLine(value str) {
+    puts(chars(str));
+    return 0;
+}
+
+value spruce_builtin_debug(value v) {
+    fputs("debug: ", stderr);
+    show(stderr, v);
+    fputc('\n', stderr);
+    return v;
+}
+
+value spruce_builtin_abs(value n) {
+    return n < 0 ? 2 - n : n;
+}
+
+value spruce_builtin_min(value a, value b) {
+    return a < b ? a : b;
+}
+
+value spruce_builtin_max(value a, value b) {
+    return a > b ? a : b;
+}
+
+value spruce_builtin_toFloat(value n) {
+    return boxed((double)(n >> 1));
+}
+
+value spruce_builtin_sqrt(value x) {
+    return boxed(sqrt(unboxed(x)));
+}
+
+value spruce_builtin_floor(value x) {
+    return to_int(floor(unboxed(x)));
+}
+
+value spruce_builtin_ceil(value x) {
+    return to_int(ceil(unboxed(x)));
+}
+
+value spruce_builtin_sin(value x) {
+    return boxed(sin(unboxed(x)));
+}
+
+value spruce_builtin_cos(value x) {
+    return boxed(cos(unboxed(x)));
+}
+
+value spruce_builtin_tan(value x) {
+    return boxed(tan(unboxed(x)));
+}
+
+value spruce_builtin_args(void) {
+    value list = spruce_alloc(ADT, spruce_nil_id, 0);
+    for (int i = spruce_argc - 1; i >= 1; i--) {
+        value cons = spruce_alloc(ADT, spruce_cons_id, 2);
+        object(cons)[3] = string(spruce_argv[i]);
+        object(cons)[4] = list;
+        list = cons;
+    }
+    return list;
+}
+
+int main(int argc, char **argv) {
+    spruce_argc = argc;
+    spruce_argv = argv;
+    spruce_init();
+    value result = spruce_main();
+    if (result != 0) {
+        show(stdout, result);
+        putchar('\n');
+    }
+    return 0;
+}
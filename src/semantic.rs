@@ -0,0 +1,114 @@
+/*
+What each name in a program is, for editors to highlight by rather than by
+what a name looks like: a function, a constructor, a type or a type variable,
+a parameter, a variable or a constant. Names that stand for symbols and
+constructors come from the reference index, which knows what each one stands
+for, and those that stand for types from the type declarations and
+signatures, where every word after the declared name is a type or a type
+variable.
+*/
+
+use std::collections::HashSet;
+
+use crate::name_analysis::{self as na, SymbolID};
+use crate::outline;
+use crate::parser::{NodeInfo, Span};
+use crate::references::{self, References, Referent};
+use crate::source::SourceMap;
+
+/// What a name is
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TokenKind {
+    Function,
+    Constructor,
+    Type,
+    TypeVariable,
+    Parameter,
+    Variable,
+    Constant
+}
+
+/// A name in the source, and what it is
+#[derive(Debug, PartialEq, Clone)]
+pub struct Token {
+    pub info: NodeInfo,
+    pub kind: TokenKind,
+    // whether this is where the name is declared
+    pub declaration: bool
+}
+
+/// Every name in `prog` that's a symbol, a constructor or a type, in order of
+/// file and then of where they start
+pub fn tokens(prog: &na::Prog, sources: &SourceMap, references: &References) -> Vec<Token> {
+    let functions: HashSet<SymbolID> = prog.functions.iter().map(|func| func.val.name)
+        .chain(prog.signatures.iter().filter(|sig| matches!(sig.val.ty, na::TypeID::Func(_, _))).map(|sig| sig.val.name))
+        .collect();
+    let parameters: HashSet<SymbolID> = prog.functions.iter().flat_map(|func| func.val.args.iter().copied()).collect();
+    let constants: HashSet<SymbolID> = prog.definitions.iter().filter_map(|stmt| match &stmt.val {
+        na::Stmt::Assign(target, _) => Some(target.val.id()),
+        _ => None
+    }).chain(prog.signatures.iter().map(|sig| sig.val.name)).collect();
+
+    let mut tokens: Vec<Token> = references.iter().map(|occurrence| {
+        let kind = match occurrence.referent {
+            Referent::Constructor(_) => TokenKind::Constructor,
+            Referent::Symbol(id) if functions.contains(&id) => TokenKind::Function,
+            Referent::Symbol(id) if parameters.contains(&id) => TokenKind::Parameter,
+            Referent::Symbol(id) if constants.contains(&id) => TokenKind::Constant,
+            Referent::Symbol(_) => TokenKind::Variable
+        };
+        Token { info: occurrence.info.clone(), kind: kind, declaration: occurrence.declaration }
+    }).collect();
+
+    let known: HashSet<&str> = prog.type_table.types.values().map(|adt| adt.name.as_str())
+        .chain(prog.type_table.primitives.iter().map(String::as_str))
+        .collect();
+    for t in &prog.types {
+        let text = match sources.get(t.info.file) {
+            Some(source) => &source.text,
+            None => continue
+        };
+
+        // the header is the name and then the type parameters, after
+        // `builtin` and `type`
+        let start = outline::after_docs(text, &t.info.span);
+        let end = text[start..t.info.span.end].find('{').map_or(t.info.span.end, |open| start + open);
+        let header: Vec<Span> = references::words(text, &Span { start: start, end: end }).into_iter()
+            .filter(|word| !matches!(&text[word.start..word.end], "builtin" | "type"))
+            .collect();
+        let params: Vec<&str> = header.iter().skip(1).map(|word| &text[word.start..word.end]).collect();
+        for (i, word) in header.iter().enumerate() {
+            let kind = if i == 0 { TokenKind::Type } else { TokenKind::TypeVariable };
+            tokens.push(Token { info: NodeInfo { span: word.clone(), file: t.info.file }, kind: kind, declaration: true });
+        }
+
+        // each constructor's fields, after its name
+        for option in &t.val.options {
+            for word in references::words(text, &option.info.span).into_iter().skip(1) {
+                let kind = if params.contains(&&text[word.start..word.end]) { TokenKind::TypeVariable } else { TokenKind::Type };
+                tokens.push(Token { info: NodeInfo { span: word, file: t.info.file }, kind: kind, declaration: false });
+            }
+        }
+    }
+
+    // a signature's type, after its name and the colon
+    for sig in &prog.signatures {
+        let text = match sources.get(sig.info.file) {
+            Some(source) => &source.text,
+            None => continue
+        };
+        let colon = match text[sig.info.span.start..sig.info.span.end].find(':') {
+            Some(colon) => sig.info.span.start + colon,
+            None => continue
+        };
+        for word in references::words(text, &Span { start: colon, end: sig.info.span.end }) {
+            let kind = if known.contains(&text[word.start..word.end]) { TokenKind::Type } else { TokenKind::TypeVariable };
+            tokens.push(Token { info: NodeInfo { span: word, file: sig.info.file }, kind: kind, declaration: false });
+        }
+    }
+
+    tokens.sort_by_key(|token| (token.info.file, token.info.span.start));
+    tokens.dedup();
+    tokens
+}
+
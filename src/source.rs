@@ -0,0 +1,242 @@
+/*
+Spans throughout the compiler are byte offsets into a file, which is named by
+the FileId it was given when added to the SourceMap. This module keeps the
+files, and converts spans into the line and column numbers people (and
+editors) expect.
+*/
+
+use crate::parser::{NodeInfo, Span};
+
+/// Identifies a file in a SourceMap
+pub type FileId = u32;
+
+/// Stands for a position that isn't in any file, such as a declaration the
+/// compiler made up
+pub const NO_FILE: FileId = FileId::MAX;
+
+/// A line and column in a source file, both starting from 1. Columns count
+/// characters rather than bytes
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LineCol {
+    pub line: usize,
+    pub col: usize
+}
+
+/// Records where each line of a file starts, so offsets can be turned into
+/// line numbers with a binary search instead of rescanning the file
+#[derive(Debug, PartialEq, Clone)]
+pub struct LineIndex {
+    line_starts: Vec<usize>
+}
+
+impl LineIndex {
+    pub fn new(text: &str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, c) in text.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+
+        LineIndex { line_starts: line_starts }
+    }
+
+    /// Zero-based index of the line containing `offset`
+    pub fn line_of(&self, offset: usize) -> usize {
+        match self.line_starts.binary_search(&offset) {
+            Ok(line) => line,
+            Err(next_line) => next_line - 1
+        }
+    }
+
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+
+    /// Byte range of a zero-based line, excluding its newline
+    pub fn line_range(&self, text: &str, line: usize) -> (usize, usize) {
+        let start = self.line_starts[line];
+        let end = match self.line_starts.get(line + 1) {
+            Some(next_start) => next_start - 1,
+            None => text.len()
+        };
+
+        (start, end)
+    }
+
+    pub fn line_col(&self, text: &str, offset: usize) -> LineCol {
+        let line = self.line_of(offset);
+        let start = self.line_starts[line];
+        let col = text[start..offset].chars().count() + 1;
+
+        LineCol { line: line + 1, col: col }
+    }
+
+    /// Start and end positions of a span
+    pub fn span_to_line_col(&self, text: &str, span: &Span) -> (LineCol, LineCol) {
+        (self.line_col(text, span.start), self.line_col(text, span.end))
+    }
+}
+
+/// A file in a SourceMap. Its offsets are shifted by `start` to place them
+/// in the range shared by every file in the map
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub text: String,
+    pub start: usize,
+    pub lines: LineIndex
+}
+
+/// Where a span is, in terms of the file's name and its lines
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResolvedSpan {
+    pub file: String,
+    pub start: LineCol,
+    pub end: LineCol
+}
+
+/// Every file of a program. Files are interned by name, and each is laid out
+/// after the last in a single global range of offsets, so that a global
+/// offset identifies a position in any of them
+#[derive(Debug, PartialEq, Clone)]
+pub struct SourceMap {
+    files: Vec<SourceFile>
+}
+
+impl Default for SourceMap {
+    fn default() -> Self {
+        SourceMap::new()
+    }
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: Vec::new() }
+    }
+
+    /// A map of files given as (text, name) pairs, with ids in that order
+    pub fn from_files(files: &Vec<(&str, String)>) -> Self {
+        let mut map = SourceMap::new();
+        for (text, name) in files {
+            map.add(name, text);
+        }
+        map
+    }
+
+    /// Adds a file, or returns the id it already has if one by that name
+    /// was added before
+    pub fn add(&mut self, name: &str, text: &str) -> FileId {
+        if let Some(id) = self.file_id(name) {
+            return id;
+        }
+
+        // one past the end of the last file, so end of file offsets never
+        // land on the start of the next
+        let start = self.files.last().map_or(0, |file| file.start + file.text.len() + 1);
+        self.files.push(SourceFile {
+            name: String::from(name),
+            text: String::from(text),
+            start: start,
+            lines: LineIndex::new(text)
+        });
+        (self.files.len() - 1) as FileId
+    }
+
+    pub fn file_id(&self, name: &str) -> Option<FileId> {
+        self.files.iter().position(|file| file.name == name).map(|i| i as FileId)
+    }
+
+    pub fn get(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id as usize)
+    }
+
+    /// The file's name, or a placeholder for ids from another map
+    pub fn name(&self, id: FileId) -> &str {
+        self.get(id).map_or("<unknown>", |file| file.name.as_str())
+    }
+
+    pub fn files(&self) -> impl Iterator<Item=(FileId, &SourceFile)> {
+        self.files.iter().enumerate().map(|(i, file)| (i as FileId, file))
+    }
+
+    /// The global offset of a position in a file
+    pub fn global(&self, id: FileId, offset: usize) -> Option<usize> {
+        self.get(id).map(|file| file.start + offset)
+    }
+
+    /// The file a global offset falls in, and the offset within it
+    pub fn locate(&self, global: usize) -> Option<(FileId, usize)> {
+        let i = match self.files.binary_search_by_key(&global, |file| file.start) {
+            Ok(i) => i,
+            Err(0) => return None,
+            Err(next) => next - 1
+        };
+
+        let file = &self.files[i];
+        if global - file.start <= file.text.len() {
+            Some((i as FileId, global - file.start))
+        }
+        else {
+            None
+        }
+    }
+
+    /// Line and column of an offset in a file. Line 0 stands for a file
+    /// that isn't in the map
+    pub fn line_col(&self, id: FileId, offset: usize) -> LineCol {
+        match self.get(id) {
+            Some(file) => file.lines.line_col(&file.text, offset),
+            None => LineCol { line: 0, col: 0 }
+        }
+    }
+
+    pub fn resolve(&self, info: &NodeInfo) -> ResolvedSpan {
+        ResolvedSpan {
+            file: String::from(self.name(info.file)),
+            start: self.line_col(info.file, info.span.start),
+            end: self.line_col(info.file, info.span.end)
+        }
+    }
+}
+
+
+#[test]
+fn line_cols() {
+    let text = "ab\nc\u{e9}d\n\nlast";
+    let index = LineIndex::new(text);
+
+    assert_eq!(index.line_col(text, 0), LineCol { line: 1, col: 1 });
+    assert_eq!(index.line_col(text, 2), LineCol { line: 1, col: 3 });
+    assert_eq!(index.line_col(text, 3), LineCol { line: 2, col: 1 });
+    // the e-acute is two bytes but one column
+    assert_eq!(index.line_col(text, 6), LineCol { line: 2, col: 3 });
+    assert_eq!(index.line_col(text, 8), LineCol { line: 3, col: 1 });
+    assert_eq!(index.line_col(text, text.len()), LineCol { line: 4, col: 5 });
+
+    assert_eq!(index.line_range(text, 1), (3, 7));
+    assert_eq!(index.line_range(text, 3), (9, 13));
+}
+
+#[test]
+fn source_map() {
+    let mut map = SourceMap::new();
+    let prelude = map.add("prelude", "one() {\n    1\n}\n");
+    let main = map.add("main", "x = one()\n");
+    assert_eq!(map.add("prelude", "ignored"), prelude);
+    assert_eq!(map.file_id("main"), Some(main));
+    assert_eq!(map.name(main), "main");
+
+    // offsets from every file share one range, without overlapping
+    let global = map.global(main, 4).unwrap();
+    assert_eq!(global, 21);
+    assert_eq!(map.locate(global), Some((main, 4)));
+    assert_eq!(map.locate(map.global(prelude, 16).unwrap()), Some((prelude, 16)));
+    assert_eq!(map.locate(100), None);
+
+    let info = NodeInfo { span: Span { start: 12, end: 13 }, file: prelude };
+    let resolved = map.resolve(&info);
+    assert_eq!(resolved.file, "prelude");
+    assert_eq!(resolved.start, LineCol { line: 2, col: 5 });
+    assert_eq!(map.resolve(&NodeInfo { span: Span { start: 0, end: 0 }, file: 7 }).file, "<unknown>");
+}
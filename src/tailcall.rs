@@ -0,0 +1,36 @@
+/*
+A function that calls itself in tail position is looping, so those calls are
+turned into `Recur` in the IR, which each backend compiles to a jump back to
+the start of the function with its parameters set to the new arguments. That
+way recursion written as a loop runs in constant stack on every target, not
+only where the target happens to optimize tail calls. Calls in tail position
+to other functions are left alone; the C target still hands those to its
+trampoline.
+*/
+
+use crate::anf::{Block, Program, Value};
+use crate::name_analysis as na;
+
+/// Turns every function's calls to itself in tail position into `Recur`
+pub fn compile(program: &mut Program) {
+    for func in &mut program.functions {
+        let name = func.name.expect("functions are named");
+        block(name, &mut func.body);
+    }
+}
+
+/// Only a block's result is in tail position, and only if the block is
+fn block(name: na::SymbolID, body: &mut Block) {
+    match &mut body.result {
+        Value::Call(id, args) if *id == name => body.result = Value::Recur(std::mem::take(args)),
+        Value::Case(case) => {
+            for arm in &mut case.arms {
+                block(name, &mut arm.body);
+            }
+            if let Some(default) = &mut case.default {
+                block(name, default);
+            }
+        }
+        _ => ()
+    }
+}
@@ -0,0 +1,70 @@
+/*
+`spruce test` runs a program's tests, which are declared as
+
+    test "adding to an empty list" {
+        length(Cons(1, Nil)) == 1
+    }
+
+A test is checked like a function that takes no arguments and gives a Bool,
+and passes if it gives True. It's run by the interpreter, after the program's
+definitions, each test starting again from them, so one test can't change what
+another sees. A test that gives False fails, as does one that stops with an
+error, which is reported with the error.
+*/
+
+use crate::error::SpruceErr;
+use crate::eval::{self, Observer, Runtime};
+use crate::name_analysis as na;
+use crate::outline;
+use crate::parser::{self, NodeInfo, Span};
+use crate::source::SourceMap;
+use crate::typecheck;
+
+/// A test that was run
+#[derive(Debug)]
+pub struct Run {
+    pub name: String,
+    // the test's header, past any doc comment and up to its body
+    pub info: NodeInfo,
+    // what went wrong, pointing at the test, if it didn't pass
+    pub failure: Option<SpruceErr>
+}
+
+/// The names of the tests in `prog`, with where each is declared, in order of
+/// file and then of where they are
+pub fn tests(prog: &na::Prog) -> Vec<(&str, &NodeInfo)> {
+    let mut tests: Vec<(&str, &NodeInfo)> = prog.functions.iter()
+        .filter_map(|func| Some((prog.test_name(&func.val.name)?, &func.info)))
+        .collect();
+    tests.sort_by_key(|(_, info)| (info.file, info.span.start));
+    tests
+}
+
+/// Runs the tests whose names contain any of `filters`, or all of them when
+/// there are none, each with a runtime from `runtime`, and telling
+/// `observer`, if there is one, what they do
+pub fn run_tests(prog: &na::Prog, typed: &typecheck::Prog, sources: &SourceMap, filters: &[String], runtime: impl Fn() -> Runtime, mut observer: Option<&mut dyn Observer>) -> Vec<Run> {
+    tests(prog).into_iter()
+        .filter(|(name, _)| filters.is_empty() || filters.iter().any(|filter| name.contains(filter.as_str())))
+        .map(|(name, info)| {
+            let info = header(sources, info);
+            let failure = match eval::call_observed(prog, typed, &parser::test_function(name), Vec::new(), runtime(), observer.as_mut().map(|observer| &mut **observer as &mut dyn Observer)) {
+                Ok(value) if eval::compare(prog, &value, &eval::bool(prog, true)).is_eq() => None,
+                Ok(_) => Some(SpruceErr::new(format!("test \"{}\" gave False", name), info.clone())),
+                Err(err) => Some(err.with_label(format!("while running test \"{}\"", name), info.clone()))
+            };
+            Run { name: name.to_string(), info: info, failure: failure }
+        })
+        .collect()
+}
+
+/// Where the test `info` covers is declared, without its doc comment or body
+fn header(sources: &SourceMap, info: &NodeInfo) -> NodeInfo {
+    let text = match sources.get(info.file) {
+        Some(file) => &file.text,
+        None => return info.clone()
+    };
+    let start = outline::after_docs(text, &info.span);
+    let end = text[start..info.span.end].find('{').map_or(info.span.end, |open| start + open);
+    NodeInfo { span: Span { start: start, end: text[start..end].trim_end().len() + start }, file: info.file }
+}
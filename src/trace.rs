@@ -0,0 +1,70 @@
+/*
+Tracing shows what the compiler is doing inside a phase, which is mostly
+useful when working on the compiler itself. It is off unless a phase is turned
+on with `--verbose <phase>`, and everything goes to stderr so it never mixes
+with a program's diagnostics.
+*/
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Phase {
+    Parse,
+    Names,
+    Typecheck,
+    Codegen,
+    Eval,
+    Vm,
+    Jit
+}
+
+pub const ALL_PHASES: [Phase; 7] = [Phase::Parse, Phase::Names, Phase::Typecheck, Phase::Codegen, Phase::Eval, Phase::Vm, Phase::Jit];
+
+// one bit per phase
+static ENABLED: AtomicU8 = AtomicU8::new(0);
+
+impl Phase {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Phase::Parse => "parse",
+            Phase::Names => "names",
+            Phase::Typecheck => "typecheck",
+            Phase::Codegen => "codegen",
+            Phase::Eval => "eval",
+            Phase::Vm => "vm",
+            Phase::Jit => "jit"
+        }
+    }
+
+    fn bit(&self) -> u8 {
+        1 << (*self as u8)
+    }
+}
+
+impl FromStr for Phase {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        ALL_PHASES.iter().find(|phase| phase.as_str() == name).copied().ok_or(())
+    }
+}
+
+pub fn enable(phase: Phase) {
+    ENABLED.fetch_or(phase.bit(), Ordering::Relaxed);
+}
+
+pub fn enabled(phase: Phase) -> bool {
+    ENABLED.load(Ordering::Relaxed) & phase.bit() != 0
+}
+
+/// Writes a line to stderr when tracing is on for a phase. The arguments are
+/// only formatted if it is
+#[macro_export]
+macro_rules! trace {
+    ($phase:expr, $($arg:tt)*) => {
+        if $crate::trace::enabled($phase) {
+            eprintln!("[{}] {}", $phase.as_str(), format!($($arg)*));
+        }
+    };
+}
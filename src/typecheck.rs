@@ -1,734 +1,2161 @@
 use std::collections::HashMap;
 use std::collections::HashSet;
-use std::iter::FromIterator;
+use std::fmt;
 
-use crate::error::SpruceErr;
+use crate::error::{SpruceErr, Label};
+use crate::error_codes::ErrorCode;
+use crate::lint::{Lint, Lints, Finding};
 use crate::name_analysis as na;
 use crate::parser;
 use crate::parser::{NodeInfo, Span};
+use crate::registry::{LangFn, LangType, Registry};
+use crate::source::{FileId, NO_FILE};
+use crate::trace::Phase;
 
-type TVarID = u32;
+pub type TVarID = u32;
 
-/// a type of a symbol, expression, etc
-#[derive(Clone, Debug, PartialEq)]
-enum Type {
+/// A handle to a type stored in a TypeArena
+pub type TypeId = u32;
+
+/// a type of a symbol, expression, etc. The types it's made of are referred
+/// to by their ids
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Type {
     Unit,
     Prim(String),
     TVar(TVarID),
     // the ADT, followed by type params
-    ADT(na::ADTID, Vec<Box<Type>>),
-    Func(Vec<Box<Type>>, Box<Type>)
+    ADT(na::ADTID, Vec<TypeId>),
+    Func(Vec<TypeId>, TypeId),
+    // stands in for the type of anything that failed to typecheck. It unifies
+    // with every type, so one mistake is only reported once
+    Error
 }
 
-impl Type {
-    /// Pretty-printing for types, which converts type variable ids into more
-    /// readable lowercase characters
-    fn as_str(&self, prog: &na::Prog) -> String {
-        let mut tvar_names: HashMap<TVarID, String> = HashMap::new();
-        let mut next_name = 0u8;
-        self.as_str_inner(prog, &mut tvar_names, &mut next_name)
+// every arena starts with these, so they have the same id everywhere
+pub const UNIT_TYPE: TypeId = 0;
+pub const ERROR_TYPE: TypeId = 1;
+pub const INT_TYPE: TypeId = 2;
+
+/// Every type built while checking a program is stored once here. Building a
+/// type that already exists gives back the id it was stored under, so two
+/// types are the same exactly when their ids are, and sharing a type is just
+/// copying its id
+#[derive(Debug)]
+pub struct TypeArena {
+    types: Vec<Type>,
+    ids: HashMap<Type, TypeId>
+}
+
+impl TypeArena {
+    fn new() -> Self {
+        let mut arena = TypeArena {
+            types: Vec::new(),
+            ids: HashMap::new()
+        };
+        arena.intern(Type::Unit);
+        arena.intern(Type::Error);
+        arena.intern(Type::Prim(String::from("Int")));
+        arena
     }
 
-    fn as_str_inner(&self, prog: &na::Prog, tvar_names: &mut HashMap<TVarID, String>, next_name: &mut u8) -> String {
-        match self {
-            Type::TVar(id) => {
-                match tvar_names.get(id) {
-                    Some(repr) => repr.clone(),
-                    None => {
-                        let tvar_repr = ((*next_name + 97) as char).to_string();
-                        *next_name += 1;
-                        tvar_names.insert(*id, tvar_repr.clone());
-                        tvar_repr
-                    }
+    fn intern(&mut self, ty: Type) -> TypeId {
+        if let Some(id) = self.ids.get(&ty) {
+            return *id;
+        }
+
+        let id = self.types.len() as TypeId;
+        self.types.push(ty.clone());
+        self.ids.insert(ty, id);
+        id
+    }
+
+    pub fn get(&self, id: TypeId) -> &Type {
+        &self.types[id as usize]
+    }
+
+    fn tvar(&mut self, id: TVarID) -> TypeId {
+        self.intern(Type::TVar(id))
+    }
+
+    fn adt(&mut self, id: na::ADTID, args: Vec<TypeId>) -> TypeId {
+        self.intern(Type::ADT(id, args))
+    }
+
+    fn func(&mut self, args: Vec<TypeId>, out: TypeId) -> TypeId {
+        self.intern(Type::Func(args, out))
+    }
+
+    /// Writes out a type, looking through the type variables that have been
+    /// bound. ADTs and unbound type variables are named by the callbacks
+    fn write(&self, id: TypeId, tvars: &TVarTable, adt_name: &dyn Fn(&na::ADTID) -> String, tvar_name: &mut dyn FnMut(TVarID) -> String) -> String {
+        match self.get(id) {
+            Type::TVar(var) => {
+                let root = tvars.root(*var);
+                match tvars.binding[root as usize] {
+                    Some(bound) => self.write(bound, tvars, adt_name, tvar_name),
+                    None => tvar_name(root)
                 }
             }
             Type::Unit => String::from("()"),
+            Type::Error => String::from("?"),
             Type::Prim(name) => name.clone(),
-            Type::ADT(id, args) => {
-                let name = prog.type_table.types.get(id).expect("dangling type id").name.clone();
+            Type::ADT(adt, args) => {
+                let name = adt_name(adt);
                 if args.len() == 0 {
-                    name.clone()
+                    name
                 }
                 else {
-                    let mut output = format!("{}(", name);
-                    args.first().as_ref().map(|arg| {
-                        output = format!("{}{}", output, arg.as_str_inner(prog, tvar_names, next_name));
-                    });
-                    for arg in args.iter().skip(1) {
-                        output = format!("{}, {}", output, arg.as_str_inner(prog, tvar_names, next_name));
-                    };
-                    format!("{})", output)
+                    let args: Vec<String> = args.iter().map(|arg| self.write(*arg, tvars, adt_name, tvar_name)).collect();
+                    format!("{}({})", name, args.join(", "))
+                }
+            }
+            Type::Func(args, out) => {
+                let args: Vec<String> = args.iter().map(|arg| self.write(*arg, tvars, adt_name, tvar_name)).collect();
+                format!("({}) -> {}", args.join(", "), self.write(*out, tvars, adt_name, tvar_name))
+            }
+        }
+    }
+
+    /// debug version of write that prints type variable and ADT ids
+    fn write_debug(&self, id: TypeId, tvars: &TVarTable) -> String {
+        self.write(id, tvars, &|adt| format!("adt{}", adt), &mut |var| format!("t{}", var))
+    }
+}
+
+/// A type in a form that doesn't depend on the checker, for tools built on
+/// the compiler. Type variables are numbered in the order they first appear
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum TypeRepr {
+    Unit,
+    Prim(String),
+    Var(u32),
+    // the name of the ADT, followed by type params
+    ADT(String, Vec<TypeRepr>),
+    Func(Vec<TypeRepr>, Box<TypeRepr>),
+    // only in the types of programs that failed to check
+    Error
+}
+
+impl TypeRepr {
+    /// Whether part of the type is what failed to check
+    pub fn has_error(&self) -> bool {
+        match self {
+            TypeRepr::Error => true,
+            TypeRepr::ADT(_, args) => args.iter().any(TypeRepr::has_error),
+            TypeRepr::Func(args, out) => args.iter().any(TypeRepr::has_error) || out.has_error(),
+            TypeRepr::Unit | TypeRepr::Prim(_) | TypeRepr::Var(_) => false
+        }
+    }
+}
+
+/// Written the same way as types in messages, with type variables lettered
+impl fmt::Display for TypeRepr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |types: &Vec<TypeRepr>| types.iter().map(|ty| ty.to_string()).collect::<Vec<String>>().join(", ");
+        match self {
+            TypeRepr::Unit => write!(f, "()"),
+            TypeRepr::Error => write!(f, "?"),
+            TypeRepr::Prim(name) => write!(f, "{}", name),
+            TypeRepr::Var(n) => write!(f, "{}", (*n as u8 + 97) as char),
+            TypeRepr::ADT(name, args) if args.is_empty() => write!(f, "{}", name),
+            TypeRepr::ADT(name, args) => write!(f, "{}({})", name, join(args)),
+            TypeRepr::Func(args, out) => write!(f, "({}) -> {}", join(args), out)
+        }
+    }
+}
+
+/// Why two types failed to unify. When a nested type fails, such as an
+/// argument of a function type, this describes the innermost failure
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeError {
+    Mismatch(TypeId, TypeId),
+    // both are function types
+    ArityMismatch(TypeId, TypeId),
+    // binding the type variable to the type would make an infinite type
+    OccursCheck(TypeId, TypeId)
+}
+
+impl TypeError {
+    fn message(&self, env: &Environment) -> String {
+        match self {
+            TypeError::Mismatch(left, right) => {
+                let names = env.describe(vec![*left, *right]);
+                format!("Unification failed between {} and {}", names[0], names[1])
+            }
+            TypeError::OccursCheck(var, ty) => {
+                let names = env.describe(vec![*var, *ty]);
+                format!("cannot construct infinite type: {} = {}", names[0], names[1])
+            }
+            TypeError::ArityMismatch(left, right) => {
+                let names = env.describe(vec![*left, *right]);
+                format!("function types take different numbers of arguments: {} and {}", names[0], names[1])
+            }
+        }
+    }
+
+    fn code(&self) -> ErrorCode {
+        match self {
+            TypeError::Mismatch(_, _) => ErrorCode::MismatchedTypes,
+            TypeError::ArityMismatch(_, _) => ErrorCode::ArityMismatch,
+            TypeError::OccursCheck(_, _) => ErrorCode::InfiniteType
+        }
+    }
+
+    fn to_diagnostic(&self, env: &Environment, info: &NodeInfo) -> SpruceErr {
+        let mut err = SpruceErr::new(self.message(env), info.clone()).with_code(self.code());
+        if let TypeError::OccursCheck(_, _) = self {
+            err = err.with_help(String::from("a value is used here as part of itself, which often means a recursive definition or arguments in the wrong order"));
+        }
+        err.type_error = Some(self.clone());
+        err
+    }
+}
+
+/// A type that may be polymorphic. Every use of something with a scheme gets
+/// fresh type variables in place of the quantified ones, so a function like
+/// `map` can be used at a different type each time
+#[derive(Clone, Debug, PartialEq)]
+pub struct Scheme {
+    pub vars: Vec<TVarID>,
+    pub ty: TypeId
+}
+
+impl Scheme {
+    /// A type with nothing quantified, as for arguments and local variables
+    fn mono(ty: TypeId) -> Self {
+        Scheme { vars: Vec::new(), ty: ty }
+    }
+}
+
+/// Environment tracks the types of symbols in our program. Types of ADTs and
+/// their values are also tracked here.
+#[derive(Debug)]
+pub struct Environment {
+    types: TypeArena,
+    tvars: TVarTable,
+
+    // top-level definitions are checked a group of mutually recursive ones at
+    // a time, a level deeper than everything checked before them. Type
+    // variables start at the level they're created at and drop to the level
+    // of any variable bound to a type containing them. Once a group is done,
+    // variables still deeper than the current level can't be reached from
+    // outside it, so they are generalized
+    level: u32,
+
+    sym_type: HashMap<na::SymbolID, Scheme>,
+
+    adt_type: HashMap<na::ADTID, TypeId>,
+    val_type: HashMap<na::ADTValID, Scheme>,
+    // names of ADTs, for messages
+    adt_names: HashMap<na::ADTID, String>,
+    // top-level functions and definitions, whose names are unique
+    globals: HashMap<String, na::SymbolID>,
+
+    // prelude adts are used internally, so we need to record their type ids
+    registry: Registry,
+
+    // where each symbol's type was first pinned down, so mismatches with it
+    // can point back there
+    origins: HashMap<na::SymbolID, Label>,
+
+    // the type of every argument to `show`, which can't be a function. These
+    // are only checked once the whole program has been, since that's when
+    // they are as pinned down as they will get. Arguments of a polymorphic
+    // function can still be functions, until there are classes to say so
+    shown: Vec<(TypeId, NodeInfo)>,
+    // the same for the arguments of `compare`
+    compared: Vec<(TypeId, NodeInfo)>,
+
+    // errors are collected here so that checking can carry on past them
+    errors: Vec<SpruceErr>,
+    lint_findings: Vec<Finding>
+}
+
+impl Environment {
+    fn new(registry: Registry) -> Self {
+        Environment {
+            types: TypeArena::new(),
+            tvars: TVarTable::new(),
+            level: 0,
+            sym_type: HashMap::new(),
+            val_type: HashMap::new(),
+            adt_type: HashMap::new(),
+            adt_names: HashMap::new(),
+            globals: HashMap::new(),
+            registry: registry,
+            origins: HashMap::new(),
+            shown: Vec::new(),
+            compared: Vec::new(),
+            errors: Vec::new(),
+            lint_findings: Vec::new()
+        }
+    }
+
+    fn report(&mut self, err: SpruceErr) {
+        self.errors.push(err);
+    }
+
+    fn set_origin(&mut self, id: na::SymbolID, message: &str, info: &NodeInfo) {
+        self.origins.insert(id, Label { message: String::from(message), info: info.clone() });
+    }
+
+    /// Points an error about a symbol's type at where that type came from
+    fn explain_origin(&self, err: SpruceErr, id: &na::SymbolID) -> SpruceErr {
+        match self.origins.get(id) {
+            Some(origin) => err.with_label(origin.message.clone(), origin.info.clone()),
+            None => err
+        }
+    }
+
+    fn new_tvar(&mut self) -> TypeId {
+        let var = self.tvars.new_var(self.level);
+        self.types.tvar(var)
+    }
+
+    fn enter_level(&mut self) {
+        self.level += 1;
+    }
+
+    fn exit_level(&mut self) {
+        self.level -= 1;
+    }
+
+    /// Quantifies the type variables in `ty` that belong only to the group
+    /// that was just checked
+    fn generalize(&mut self, ty: TypeId) -> Scheme {
+        let ty = self.resolve(ty);
+        let mut vars: Vec<TVarID> = tvars(&self.types, ty).into_iter().filter(|id| self.tvars.level(*id) > self.level).collect();
+        vars.sort();
+        Scheme { vars: vars, ty: ty }
+    }
+
+    /// Leaves the type variables in `ty` unquantified, and drops them to the
+    /// current level so that nothing checked later generalizes them either.
+    /// A `mut` definition keeps one type for every assignment to it, as a
+    /// variable at one type could otherwise be assigned a value of another
+    fn restrict(&mut self, ty: TypeId) -> Scheme {
+        let ty = self.resolve(ty);
+        self.tvars.lower_levels(&self.types, ty, self.level);
+        Scheme { vars: Vec::new(), ty: ty }
+    }
+
+    /// A copy of a scheme's type with fresh type variables for the quantified
+    /// ones
+    fn instantiate(&mut self, scheme: &Scheme) -> TypeId {
+        let fresh: TSubst = scheme.vars.iter().map(|id| (*id, self.new_tvar())).collect();
+        apply(&mut self.types, &fresh, scheme.ty)
+    }
+
+    /// The type of a symbol at one of its uses
+    fn get_sym_type(&mut self, id: &na::SymbolID) -> Option<TypeId> {
+        let scheme = self.sym_type.get(id)?.clone();
+        Some(self.instantiate(&scheme))
+    }
+
+    fn insert_sym_type(&mut self, id: na::SymbolID, ty: TypeId) {
+        self.sym_type.insert(id, Scheme::mono(ty));
+    }
+
+    /// A type with everything its variables have been unified with filled in
+    fn resolve(&mut self, ty: TypeId) -> TypeId {
+        self.tvars.resolve(&mut self.types, ty)
+    }
+
+    fn get_adt_type(&self, id: &na::ADTID, info: &NodeInfo) -> Result<TypeId, SpruceErr> {
+        match self.adt_type.get(id) {
+            Some(ty) => Ok(*ty),
+            None => Err(SpruceErr::ice(Phase::Typecheck, format!("type {} was never given a type", id), info.clone()))
+        }
+    }
+
+    /// The types of a fresh instance of a constructor's arguments, and the
+    /// type it builds
+    fn get_constructor_type(&mut self, id: &na::ADTValID, info: &NodeInfo) -> Result<(Vec<TypeId>, TypeId), SpruceErr> {
+        let scheme = match self.val_type.get(id) {
+            Some(scheme) => scheme.clone(),
+            None => return Err(SpruceErr::ice(Phase::Typecheck, format!("constructor {} has no type", id), info.clone()))
+        };
+
+        let ty = self.instantiate(&scheme);
+        match self.types.get(ty) {
+            Type::Func(args, out) => Ok((args.clone(), *out)),
+            _ => Err(SpruceErr::ice(Phase::Typecheck, format!("constructor {} has no function type", id), info.clone()))
+        }
+    }
+
+    fn adt_name(&self, id: &na::ADTID) -> String {
+        match self.adt_names.get(id) {
+            Some(name) => name.clone(),
+            None => format!("adt{}", id)
+        }
+    }
+
+    /// Names types for messages, with ADTs written out and type variables
+    /// lettered consistently across all of them
+    fn describe(&self, types: Vec<TypeId>) -> Vec<String> {
+        let adt_name = |id: &na::ADTID| self.adt_name(id);
+
+        let mut tvar_names: HashMap<TVarID, String> = HashMap::new();
+        let mut tvar_name = |id: TVarID| {
+            let next_name = ((tvar_names.len() as u8 + 97) as char).to_string();
+            tvar_names.entry(id).or_insert(next_name).clone()
+        };
+        types.iter().map(|ty| self.types.write(*ty, &self.tvars, &adt_name, &mut tvar_name)).collect()
+    }
+
+    /// The type of a symbol, with the variables of a polymorphic one numbered
+    /// from 0
+    pub fn type_of_id(&self, id: na::SymbolID) -> Option<TypeRepr> {
+        let scheme = self.sym_type.get(&id)?;
+        Some(self.repr(scheme.ty))
+    }
+
+    /// The type of a constructor, as a function from its fields
+    pub fn type_of_value(&self, id: na::ADTValID) -> Option<TypeRepr> {
+        let scheme = self.val_type.get(&id)?;
+        Some(self.repr(scheme.ty))
+    }
+
+    /// The type of a top-level function or definition
+    pub fn type_of_symbol(&self, name: &str) -> Option<TypeRepr> {
+        self.type_of_id(*self.globals.get(name)?)
+    }
+
+    /// The names of every top-level function and definition
+    pub fn global_names(&self) -> impl Iterator<Item = &String> {
+        self.globals.keys()
+    }
+
+    /// Every symbol with a type, in order of id
+    pub fn symbol_types(&self) -> impl Iterator<Item = (na::SymbolID, TypeRepr)> + '_ {
+        let mut ids: Vec<na::SymbolID> = self.sym_type.keys().copied().collect();
+        ids.sort();
+        ids.into_iter().filter_map(move |id| self.type_of_id(id).map(|ty| (id, ty)))
+    }
+
+    /// A type as tools outside the checker see it, with everything its
+    /// variables have been unified with filled in
+    pub fn repr(&self, ty: TypeId) -> TypeRepr {
+        self.repr_inner(ty, &mut HashMap::new())
+    }
+
+    fn repr_inner(&self, ty: TypeId, vars: &mut HashMap<TVarID, u32>) -> TypeRepr {
+        match self.types.get(ty) {
+            Type::TVar(var) => {
+                let root = self.tvars.root(*var);
+                match self.tvars.binding[root as usize] {
+                    Some(bound) => self.repr_inner(bound, vars),
+                    None => {
+                        let next = vars.len() as u32;
+                        TypeRepr::Var(*vars.entry(root).or_insert(next))
+                    }
+                }
+            }
+            Type::Unit => TypeRepr::Unit,
+            Type::Error => TypeRepr::Error,
+            Type::Prim(name) => TypeRepr::Prim(name.clone()),
+            Type::ADT(id, args) => {
+                let args = args.iter().map(|arg| self.repr_inner(*arg, vars)).collect();
+                TypeRepr::ADT(self.adt_name(id), args)
+            }
+            Type::Func(args, out) => {
+                let args = args.iter().map(|arg| self.repr_inner(*arg, vars)).collect();
+                TypeRepr::Func(args, Box::from(self.repr_inner(*out, vars)))
+            }
+        }
+    }
+
+    /// The type `ty` has where the variables of a polymorphic function stand
+    /// for the types `subst` gives them. A variable nothing pins down is
+    /// taken to be (), since no value of it is ever looked at
+    pub fn instance_type(&self, ty: TypeId, subst: &HashMap<TVarID, TypeRepr>) -> TypeRepr {
+        match self.types.get(ty) {
+            Type::TVar(var) => {
+                let root = self.tvars.root(*var);
+                match self.tvars.binding[root as usize] {
+                    Some(bound) => self.instance_type(bound, subst),
+                    None => subst.get(&root).cloned().unwrap_or(TypeRepr::Unit)
+                }
+            }
+            Type::Unit => TypeRepr::Unit,
+            Type::Error => TypeRepr::Error,
+            Type::Prim(name) => TypeRepr::Prim(name.clone()),
+            Type::ADT(id, args) => TypeRepr::ADT(self.adt_name(id), args.iter().map(|arg| self.instance_type(*arg, subst)).collect()),
+            Type::Func(args, out) => {
+                let args = args.iter().map(|arg| self.instance_type(*arg, subst)).collect();
+                TypeRepr::Func(args, Box::from(self.instance_type(*out, subst)))
+            }
+        }
+    }
+
+    /// What each variable a polymorphic symbol quantifies stands for when
+    /// it's used at the type `at`, in order, which is nothing for a symbol
+    /// that isn't polymorphic
+    pub fn instantiation(&self, id: na::SymbolID, at: &TypeRepr) -> Vec<(TVarID, TypeRepr)> {
+        let scheme = match self.sym_type.get(&id) {
+            Some(scheme) => scheme,
+            None => return Vec::new()
+        };
+        let mut found = HashMap::new();
+        self.match_type(scheme.ty, at, &mut found);
+        scheme.vars.iter().map(|var| {
+            let root = self.tvars.root(*var);
+            (root, found.get(&root).cloned().unwrap_or(TypeRepr::Unit))
+        }).collect()
+    }
+
+    fn match_type(&self, ty: TypeId, at: &TypeRepr, found: &mut HashMap<TVarID, TypeRepr>) {
+        match (self.types.get(ty), at) {
+            (Type::TVar(var), _) => {
+                let root = self.tvars.root(*var);
+                match self.tvars.binding[root as usize] {
+                    Some(bound) => self.match_type(bound, at, found),
+                    None => { found.entry(root).or_insert_with(|| at.clone()); }
+                }
+            }
+            (Type::ADT(_, args), TypeRepr::ADT(_, at_args)) => {
+                for (arg, at_arg) in args.iter().zip(at_args) {
+                    self.match_type(*arg, at_arg, found);
+                }
+            }
+            (Type::Func(args, out), TypeRepr::Func(at_args, at_out)) => {
+                for (arg, at_arg) in args.iter().zip(at_args) {
+                    self.match_type(*arg, at_arg, found);
+                }
+                self.match_type(*out, at_out, found);
+            }
+            _ => ()
+        }
+    }
+
+    /// Writes out a type the way it appears in messages
+    pub fn type_str(&self, ty: TypeId) -> String {
+        self.describe(vec![ty]).remove(0)
+    }
+
+    /// Writes out a type with the ids of its type variables, for tracing
+    fn describe_debug(&self, ty: TypeId) -> String {
+        self.types.write_debug(ty, &self.tvars)
+    }
+
+    /// Lists the type of every symbol, sorted by name so the output is the
+    /// same from run to run. With `with_types`, ADTs and the types of their
+    /// constructors are listed first
+    pub fn as_str(&self, prog: &na::Prog, with_types: bool) -> String {
+        let mut output = String::from("");
+        if with_types {
+            let mut adts: Vec<(&String, TypeId)> = self.adt_type.iter().filter_map(|(id, ty)| {
+                self.adt_names.get(id).map(|name| (name, *ty))
+            }).collect();
+            adts.sort();
+            for (_, ty) in adts {
+                output = format!("{}type {}\n", output, self.type_str(ty));
+            }
+
+            let mut constructors: Vec<(&String, TypeId)> = self.val_type.iter().filter_map(|(id, scheme)| {
+                prog.type_table.values.get(id).map(|val| (&val.name, scheme.ty))
+            }).collect();
+            constructors.sort();
+            for (name, ty) in constructors {
+                output = format!("{}{} : {}\n", output, name, self.type_str(ty));
+            }
+        }
+
+        let mut symbols: Vec<(&String, na::SymbolID, TypeId)> = self.sym_type.iter().filter_map(|(id, scheme)| {
+            prog.symbol_table.store.get(id).map(|sym| (&sym.name, *id, scheme.ty))
+        }).collect();
+        symbols.sort();
+        for (name, _, ty) in symbols {
+            output = format!("{}{} : {}\n", output, name, self.type_str(ty));
+        }
+
+        output
+    }
+}
+
+/// Type variables and what they've been unified with, kept as a union-find.
+/// Unifying two variables joins their classes, and unifying a variable with
+/// anything else binds its class to that type. Finding a variable's class
+/// compresses the path to its root, so long chains of variables unified with
+/// each other stay cheap to follow
+#[derive(Debug)]
+struct TVarTable {
+    parent: Vec<TVarID>,
+    rank: Vec<u8>,
+    // the binding and level of a class are kept at its root
+    binding: Vec<Option<TypeId>>,
+    level: Vec<u32>,
+
+    // changes since the last snapshot, so a failed unification can be undone
+    // rather than leave half its bindings behind
+    undo_log: Vec<Undo>
+}
+
+#[derive(Debug)]
+enum Undo {
+    Parent(TVarID, TVarID),
+    Rank(TVarID, u8),
+    Binding(TVarID, Option<TypeId>),
+    Level(TVarID, u32)
+}
+
+impl TVarTable {
+    fn new() -> Self {
+        TVarTable {
+            parent: Vec::new(),
+            rank: Vec::new(),
+            binding: Vec::new(),
+            level: Vec::new(),
+            undo_log: Vec::new()
+        }
+    }
+
+    fn new_var(&mut self, level: u32) -> TVarID {
+        let id = self.parent.len() as TVarID;
+        self.parent.push(id);
+        self.rank.push(0);
+        self.binding.push(None);
+        self.level.push(level);
+        id
+    }
+
+    /// The root of a variable's class, compressing the path to it
+    fn find(&mut self, id: TVarID) -> TVarID {
+        let parent = self.parent[id as usize];
+        if parent == id {
+            return id;
+        }
+
+        let root = self.find(parent);
+        if root != parent {
+            self.undo_log.push(Undo::Parent(id, parent));
+            self.parent[id as usize] = root;
+        }
+        root
+    }
+
+    /// The root of a variable's class, for when the table can't be changed
+    fn root(&self, id: TVarID) -> TVarID {
+        let mut id = id;
+        while self.parent[id as usize] != id {
+            id = self.parent[id as usize];
+        }
+        id
+    }
+
+    fn level(&self, id: TVarID) -> u32 {
+        self.level[self.root(id) as usize]
+    }
+
+    fn set_level(&mut self, root: TVarID, level: u32) {
+        self.undo_log.push(Undo::Level(root, self.level[root as usize]));
+        self.level[root as usize] = level;
+    }
+
+    /// Joins the classes of two unbound variables
+    fn union(&mut self, left: TVarID, right: TVarID) {
+        let (left, right) = (self.find(left), self.find(right));
+        if left == right {
+            return;
+        }
+
+        let (root, child) = if self.rank[left as usize] < self.rank[right as usize] { (right, left) } else { (left, right) };
+        if self.rank[left as usize] == self.rank[right as usize] {
+            self.undo_log.push(Undo::Rank(root, self.rank[root as usize]));
+            self.rank[root as usize] += 1;
+        }
+        self.undo_log.push(Undo::Parent(child, child));
+        self.parent[child as usize] = root;
+
+        let level = self.level[left as usize].min(self.level[right as usize]);
+        self.set_level(root, level);
+    }
+
+    /// Binds an unbound variable's class to a type. The variables in the type
+    /// are now reachable wherever the class is, so they can be generalized
+    /// no sooner than it can
+    fn bind(&mut self, types: &TypeArena, id: TVarID, ty: TypeId) {
+        let root = self.find(id);
+        let level = self.level[root as usize];
+        self.lower_levels(types, ty, level);
+
+        self.undo_log.push(Undo::Binding(root, self.binding[root as usize]));
+        self.binding[root as usize] = Some(ty);
+    }
+
+    fn lower_levels(&mut self, types: &TypeArena, ty: TypeId, level: u32) {
+        let ty = self.shallow(types, ty);
+        match types.get(ty) {
+            Type::TVar(id) => {
+                let root = self.find(*id);
+                if self.level[root as usize] > level {
+                    self.set_level(root, level);
+                }
+            }
+            Type::ADT(_, args) => {
+                for arg in args {
+                    self.lower_levels(types, *arg, level);
+                }
+            }
+            Type::Func(args, out) => {
+                for arg in args {
+                    self.lower_levels(types, *arg, level);
+                }
+                self.lower_levels(types, *out, level);
+            }
+            Type::Unit | Type::Prim(_) | Type::Error => ()
+        }
+    }
+
+    /// Looks through bound variables at the top of a type, stopping at
+    /// anything that isn't a bound variable
+    fn shallow(&mut self, types: &TypeArena, ty: TypeId) -> TypeId {
+        let mut ty = ty;
+        while let Type::TVar(id) = types.get(ty) {
+            let root = self.find(*id);
+            match self.binding[root as usize] {
+                Some(bound) => ty = bound,
+                None => break
+            }
+        }
+        ty
+    }
+
+    /// A type with every bound variable replaced by what it's bound to, and
+    /// every unbound one by the root of its class
+    fn resolve(&self, types: &mut TypeArena, ty: TypeId) -> TypeId {
+        match types.get(ty).clone() {
+            Type::TVar(id) => {
+                let root = self.root(id);
+                match self.binding[root as usize] {
+                    Some(bound) => self.resolve(types, bound),
+                    None => types.tvar(root)
+                }
+            }
+            Type::Unit | Type::Prim(_) | Type::Error => ty,
+            Type::ADT(id, args) => {
+                let args = args.iter().map(|arg| self.resolve(types, *arg)).collect();
+                types.adt(id, args)
+            }
+            Type::Func(args, out) => {
+                let args = args.iter().map(|arg| self.resolve(types, *arg)).collect();
+                let out = self.resolve(types, out);
+                types.func(args, out)
+            }
+        }
+    }
+
+    fn occurs(&self, types: &TypeArena, id: TVarID, ty: TypeId) -> bool {
+        match types.get(ty) {
+            Type::TVar(var) => {
+                let root = self.root(*var);
+                root == self.root(id) || match self.binding[root as usize] {
+                    Some(bound) => self.occurs(types, id, bound),
+                    None => false
+                }
+            }
+            Type::ADT(_, args) => args.iter().any(|arg| self.occurs(types, id, *arg)),
+            Type::Func(args, out) => args.iter().any(|arg| self.occurs(types, id, *arg)) || self.occurs(types, id, *out),
+            Type::Unit | Type::Prim(_) | Type::Error => false
+        }
+    }
+
+    /// Forgets the changes made so far, keeping them
+    fn snapshot(&mut self) {
+        self.undo_log.clear();
+    }
+
+    /// Undoes every change since the last snapshot
+    fn rollback(&mut self) {
+        while let Some(undo) = self.undo_log.pop() {
+            match undo {
+                Undo::Parent(id, parent) => self.parent[id as usize] = parent,
+                Undo::Rank(id, rank) => self.rank[id as usize] = rank,
+                Undo::Binding(id, binding) => self.binding[id as usize] = binding,
+                Undo::Level(id, level) => self.level[id as usize] = level
+            }
+        }
+    }
+}
+
+/// T(ype)Subst maps type variables to types, as when instantiating a scheme
+/// with fresh variables
+type TSubst = HashMap<TVarID, TypeId>;
+
+macro_rules! bool_adt {
+    ($e:ident) => {
+        $e.types.adt($e.registry.type_id(LangType::Bool), vec![])
+    };
+}
+
+// The typed program mirrors the name analysis IR, with the resolved type of
+// every expression and statement filled in. Types are ids in the
+// environment's arena. Only programs without errors get one, so it never
+// contains the error type
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expr {
+    Add(Box<ExprNode>, Box<ExprNode>),
+    Mult(Box<ExprNode>, Box<ExprNode>),
+    Subt(Box<ExprNode>, Box<ExprNode>),
+    Div(Box<ExprNode>, Box<ExprNode>),
+    Pow(Box<ExprNode>, Box<ExprNode>),
+    Mod(Box<ExprNode>, Box<ExprNode>),
+    FnCall(na::SymbolID, Vec<Box<ExprNode>>),
+    Id(na::SymbolID),
+    ADTVal(na::ADTValID, Vec<Box<ExprNode>>),
+    Lit(i64),
+    Neg(Box<ExprNode>),
+    List(Vec<Box<ExprNode>>),
+    Eq(Box<ExprNode>, Box<ExprNode>),
+    NotEq(Box<ExprNode>, Box<ExprNode>),
+    LtEq(Box<ExprNode>, Box<ExprNode>),
+    GtEq(Box<ExprNode>, Box<ExprNode>),
+    Lt(Box<ExprNode>, Box<ExprNode>),
+    Gt(Box<ExprNode>, Box<ExprNode>),
+    ComposeR(Box<ExprNode>, Box<ExprNode>),
+    ComposeL(Box<ExprNode>, Box<ExprNode>),
+    BitAnd(Box<ExprNode>, Box<ExprNode>),
+    BitOr(Box<ExprNode>, Box<ExprNode>),
+    BitXor(Box<ExprNode>, Box<ExprNode>),
+    Shl(Box<ExprNode>, Box<ExprNode>),
+    Shr(Box<ExprNode>, Box<ExprNode>),
+    // stands in for an expression that failed to typecheck while the rest
+    // of the program is checked
+    Error
+}
+
+impl Expr {
+    /// The expressions directly inside this one
+    pub fn children(&self) -> Vec<&ExprNode> {
+        match self {
+            Expr::Id(_) | Expr::Lit(_) | Expr::Error => Vec::new(),
+            Expr::Neg(inner) => vec![inner],
+            Expr::FnCall(_, args) | Expr::ADTVal(_, args) | Expr::List(args) => {
+                args.iter().map(|arg| &**arg).collect()
+            }
+            Expr::Add(left, right) | Expr::Subt(left, right) | Expr::Mult(left, right) |
+            Expr::Div(left, right) | Expr::Pow(left, right) | Expr::Mod(left, right) |
+            Expr::BitAnd(left, right) | Expr::BitOr(left, right) | Expr::BitXor(left, right) |
+            Expr::Shl(left, right) | Expr::Shr(left, right) |
+            Expr::Eq(left, right) | Expr::NotEq(left, right) | Expr::LtEq(left, right) |
+            Expr::GtEq(left, right) | Expr::Lt(left, right) | Expr::Gt(left, right) |
+            Expr::ComposeR(left, right) | Expr::ComposeL(left, right) => vec![left, right]
+        }
+    }
+
+    pub fn children_mut(&mut self) -> Vec<&mut ExprNode> {
+        match self {
+            Expr::Id(_) | Expr::Lit(_) | Expr::Error => Vec::new(),
+            Expr::Neg(inner) => vec![inner],
+            Expr::FnCall(_, args) | Expr::ADTVal(_, args) | Expr::List(args) => {
+                args.iter_mut().map(|arg| &mut **arg).collect()
+            }
+            Expr::Add(left, right) | Expr::Subt(left, right) | Expr::Mult(left, right) |
+            Expr::Div(left, right) | Expr::Pow(left, right) | Expr::Mod(left, right) |
+            Expr::BitAnd(left, right) | Expr::BitOr(left, right) | Expr::BitXor(left, right) |
+            Expr::Shl(left, right) | Expr::Shr(left, right) |
+            Expr::Eq(left, right) | Expr::NotEq(left, right) | Expr::LtEq(left, right) |
+            Expr::GtEq(left, right) | Expr::Lt(left, right) | Expr::Gt(left, right) |
+            Expr::ComposeR(left, right) | Expr::ComposeL(left, right) => vec![left, right]
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct ExprNode {
+    pub val: Expr,
+    pub ty: TypeId,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Body {
+    pub stmts: Vec<StmtNode>,
+    pub expr: Option<ExprNode>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct BodyNode {
+    pub val: Body,
+    pub ty: TypeId,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Case {
+    pub id: na::CaseID,
+    pub expr: ExprNode,
+    pub options: Vec<CaseOptionNode>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaseNode {
+    pub val: Case,
+    pub ty: TypeId,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaseOption {
+    pub pattern: na::CasePatternNode,
+    pub body: CaseBodyNode
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaseOptionNode {
+    pub val: CaseOption,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum CaseBody {
+    Expr(ExprNode),
+    Body(BodyNode)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct CaseBodyNode {
+    pub val: CaseBody,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum Stmt {
+    Assign(na::TargetNode, ExprNode),
+    FnCall(na::SymbolID, Vec<ExprNode>),
+    Case(CaseNode)
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct StmtNode {
+    pub val: Stmt,
+    pub ty: TypeId,
+    pub info: NodeInfo
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Func {
+    pub name: na::SymbolID,
+    pub args: Vec<na::SymbolID>,
+    pub body: BodyNode
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FuncNode {
+    pub val: Func,
+    pub ty: TypeId,
+    pub info: NodeInfo
+}
+
+/// Functions and definitions are in the same order as in the name analysis
+/// IR, which still holds the symbol and type tables
+#[derive(Debug, PartialEq)]
+pub struct Prog {
+    pub functions: Vec<FuncNode>,
+    pub definitions: Vec<StmtNode>
+}
+
+/// The type of something in the source, and the part of the source it
+/// covers, as shown when hovering over it in an editor
+#[derive(Debug, PartialEq, Clone)]
+pub struct TypeDisplay {
+    pub ty: TypeRepr,
+    pub info: NodeInfo
+}
+
+impl fmt::Display for TypeDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.ty)
+    }
+}
+
+/// A function, statement or expression of the typed program
+#[derive(Debug, Clone, Copy)]
+pub enum Node<'a> {
+    Func(&'a FuncNode),
+    Stmt(&'a StmtNode),
+    Expr(&'a ExprNode)
+}
+
+impl<'a> Node<'a> {
+    pub fn ty(&self) -> TypeId {
+        match self {
+            Node::Func(func) => func.ty,
+            Node::Stmt(stmt) => stmt.ty,
+            Node::Expr(expr) => expr.ty
+        }
+    }
+
+    pub fn info(&self) -> &'a NodeInfo {
+        match self {
+            Node::Func(func) => &func.info,
+            Node::Stmt(stmt) => &stmt.info,
+            Node::Expr(expr) => &expr.info
+        }
+    }
+}
+
+impl Prog {
+    /// The type of the innermost expression at `offset` bytes into `file`.
+    /// Outside of any expression, such as on the name a statement assigns to
+    /// or in a function's declaration, it's the type of the statement or
+    /// function instead
+    pub fn query_type_at(&self, env: &Environment, file: FileId, offset: usize) -> Option<TypeDisplay> {
+        self.nodes_at(file, offset).last().map(|node| TypeDisplay { ty: env.repr(node.ty()), info: node.info().clone() })
+    }
+
+    /// The nodes covering `offset` bytes into `file`, from the outermost in
+    pub fn nodes_at(&self, file: FileId, offset: usize) -> Vec<Node<'_>> {
+        let mut found = Vec::new();
+        for func in &self.functions {
+            if covers(&func.info, file, offset) {
+                found.push(Node::Func(func));
+                find_in_body(&func.val.body, file, offset, &mut found);
+            }
+        }
+        for stmt in &self.definitions {
+            find_in_stmt(stmt, file, offset, &mut found);
+        }
+        found
+    }
+
+    /// Every statement binding a name, at the top level or in a body, in
+    /// the order they're written within each function
+    pub fn assignments(&self) -> Vec<&StmtNode> {
+        let mut found = Vec::new();
+        for stmt in &self.definitions {
+            assignments_in_stmt(stmt, &mut found);
+        }
+        for func in &self.functions {
+            assignments_in_body(&func.val.body, &mut found);
+        }
+        found
+    }
+}
+
+fn assignments_in_body<'a>(body: &'a BodyNode, found: &mut Vec<&'a StmtNode>) {
+    for stmt in &body.val.stmts {
+        assignments_in_stmt(stmt, found);
+    }
+}
+
+fn assignments_in_stmt<'a>(stmt: &'a StmtNode, found: &mut Vec<&'a StmtNode>) {
+    match &stmt.val {
+        Stmt::Assign(_, _) => found.push(stmt),
+        Stmt::FnCall(_, _) => (),
+        Stmt::Case(case) => {
+            for opt in &case.val.options {
+                if let CaseBody::Body(body) = &opt.val.body.val {
+                    assignments_in_body(body, found);
+                }
+            }
+        }
+    }
+}
+
+fn covers(info: &NodeInfo, file: FileId, offset: usize) -> bool {
+    info.file == file && info.span.start <= offset && offset < info.span.end
+}
+
+// nodes are visited outside in, so the last one found is the innermost
+
+fn find_in_body<'a>(body: &'a BodyNode, file: FileId, offset: usize, found: &mut Vec<Node<'a>>) {
+    for stmt in &body.val.stmts {
+        find_in_stmt(stmt, file, offset, found);
+    }
+    if let Some(expr) = &body.val.expr {
+        find_in_expr(expr, file, offset, found);
+    }
+}
+
+fn find_in_stmt<'a>(stmt: &'a StmtNode, file: FileId, offset: usize, found: &mut Vec<Node<'a>>) {
+    if !covers(&stmt.info, file, offset) {
+        return;
+    }
+
+    found.push(Node::Stmt(stmt));
+    match &stmt.val {
+        Stmt::Assign(_, expr) => find_in_expr(expr, file, offset, found),
+        Stmt::FnCall(_, args) => {
+            for arg in args {
+                find_in_expr(arg, file, offset, found);
+            }
+        }
+        Stmt::Case(case) => {
+            find_in_expr(&case.val.expr, file, offset, found);
+            for opt in &case.val.options {
+                match &opt.val.body.val {
+                    CaseBody::Expr(expr) => find_in_expr(expr, file, offset, found),
+                    CaseBody::Body(body) => find_in_body(body, file, offset, found)
+                }
+            }
+        }
+    }
+}
+
+fn find_in_expr<'a>(expr: &'a ExprNode, file: FileId, offset: usize, found: &mut Vec<Node<'a>>) {
+    if !covers(&expr.info, file, offset) {
+        return;
+    }
+
+    found.push(Node::Expr(expr));
+    for child in expr.val.children() {
+        find_in_expr(child, file, offset, found);
+    }
+}
+
+/// Checks the whole program, carrying on past errors in individual
+/// statements so that every independent mistake is reported at once
+pub fn check_prog(prog: &na::Prog, lints: &mut Lints) -> Result<(Prog, Environment), Vec<SpruceErr>> {
+    match check_partial(prog, lints) {
+        (typed, env, errors) if errors.is_empty() => Ok((typed, env)),
+        (_, _, errors) => Err(errors)
+    }
+}
+
+/// Checks the program as `check_prog` does, giving what checked even if
+/// some of it didn't: the functions and definitions without errors, typed,
+/// along with the errors in the rest
+pub fn check_partial(prog: &na::Prog, lints: &mut Lints) -> (Prog, Environment, Vec<SpruceErr>) {
+    let mut env = Environment::new(prog.registry.clone());
+
+    // constructors are generalized over the type parameters of their ADT
+    env.enter_level();
+    let mut tparams: HashMap<na::TParamID, TypeId> = HashMap::new();
+    for (_, ty) in &prog.type_table.types {
+        let adt_params = ty.type_params.iter();
+        let tvars = adt_params.map(|id| {
+            let tvar = env.new_tvar();
+            tparams.insert(*id, tvar);
+            tvar
+        }).collect();
+
+        let adt_type = env.types.adt(ty.id, tvars);
+        env.adt_type.insert(ty.id, adt_type);
+        env.adt_names.insert(ty.id, ty.name.clone());
+    }
+
+    for (_, val) in &prog.type_table.values {
+        let info = constructor_info(prog, &val.name);
+        let constructor_type = val.args.iter().map(|arg| {
+            create_ident_type(&mut env.types, arg, &tparams, &info)
+        }).collect::<Result<Vec<TypeId>, SpruceErr>>().and_then(|args| {
+            let out = env.get_adt_type(&val.data_type, &info)?;
+            Ok(env.types.func(args, out))
+        });
+
+        match constructor_type {
+            Ok(ty) => {
+                env.exit_level();
+                let scheme = env.generalize(ty);
+                env.enter_level();
+                env.val_type.insert(val.id, scheme);
+            }
+            Err(err) => env.report(err)
+        }
+    }
+    env.exit_level();
+
+    // what interfaces declare was checked when they were written, so each
+    // signature is taken at its word
+    for sig in &prog.signatures {
+        env.enter_level();
+        let tparams: HashMap<na::TParamID, TypeId> = sig.val.params.iter().map(|id| (*id, env.new_tvar())).collect();
+        let sig_type = create_ident_type(&mut env.types, &sig.val.ty, &tparams, &sig.info);
+        env.exit_level();
+
+        match sig_type {
+            Ok(ty) => {
+                let scheme = env.generalize(ty);
+                env.sym_type.insert(sig.val.name, scheme);
+                if let Some(sym) = prog.symbol_table.lookup_id(&sig.val.name) {
+                    env.globals.insert(sym.name.clone(), sig.val.name);
                 }
             }
-            Type::Func(args, out) => {
-                let mut output = String::from("(");
-                args.first().as_ref().map(|arg| {
-                    output = format!("{}{}", output, arg.as_str_inner(prog, tvar_names, next_name));
-                });
-                for arg in args.iter().skip(1) {
-                    output = format!("{}, {}", output, arg.as_str_inner(prog, tvar_names, next_name));
-                };
+            Err(err) => env.report(err)
+        }
+    }
 
-                format!("{}) -> {}", output, out.as_str_inner(prog, tvar_names, next_name))
+    // definitions are checked in order of dependency, so that each is
+    // generalized before anything else uses it. Mutually recursive ones are
+    // checked together, and can only use each other at a single type.
+    // Modules come in dependency order already, so each is checked in turn
+    let mut typed_defs: HashMap<na::SymbolID, StmtNode> = HashMap::new();
+    let mut typed_funcs: HashMap<na::SymbolID, FuncNode> = HashMap::new();
+    let groups = prog.modules.iter().flat_map(|module| binding_groups(prog, module));
+    for group in groups {
+        env.enter_level();
+        for binding in &group {
+            if let Binding::Func(func) = binding {
+                let fn_tvar = env.new_tvar();
+                env.insert_sym_type(func.val.name, fn_tvar);
             }
         }
-    }
 
-    /// debug version of as_str that prints type ids
-    fn as_str_debug(&self) -> String {
-        match self {
-            Type::TVar(id) => format!("t{}", id),
-            Type::Unit => String::from("()"),
-            Type::Prim(name) => name.clone(),
-            Type::ADT(id, args) => {
-                let name = format!("adt{}", id);
-                if args.len() == 0 {
-                    name.clone()
+        for binding in &group {
+            match binding {
+                Binding::Def(stmt) => {
+                    let typed = check_definition(&mut env, stmt);
+                    if let (Some(id), Some(typed)) = (binding.id(), typed) {
+                        typed_defs.insert(id, typed);
+                    }
                 }
-                else {
-                    let mut output = format!("{}(", name);
-                    args.first().as_ref().map(|arg| {
-                        output = format!("{}{}", output, arg.as_str_debug());
-                    });
-                    for arg in args.iter().skip(1) {
-                        output = format!("{}, {}", output, arg.as_str_debug());
-                    };
-                    format!("{})", output)
+                Binding::Func(func) => {
+                    match check_func(&mut env, func, prog.test_name(&func.val.name).is_some()) {
+                        Ok(typed) => {
+                            typed_funcs.insert(func.val.name, typed);
+                        }
+                        Err(err) => {
+                            env.report(err);
+                            env.insert_sym_type(func.val.name, ERROR_TYPE);
+                        }
+                    }
                 }
             }
-            Type::Func(args, out) => {
-                let mut output = String::from("(");
-                args.first().as_ref().map(|arg| {
-                    output = format!("{}{}", output, arg.as_str_debug());
-                });
-                for arg in args.iter().skip(1) {
-                    output = format!("{}, {}", output, arg.as_str_debug());
-                };
+        }
+        env.exit_level();
 
-                format!("{}) -> {}", output, out.as_str_debug())
+        for binding in &group {
+            if let Some(id) = binding.id() {
+                if let Some(sym) = prog.symbol_table.lookup_id(&id) {
+                    env.globals.insert(sym.name.clone(), id);
+                }
+                if let Some(scheme) = env.sym_type.get(&id) {
+                    let scheme = if binding.is_mutable() {
+                        env.restrict(scheme.ty)
+                    } else {
+                        env.generalize(scheme.ty)
+                    };
+                    env.sym_type.insert(id, scheme);
+                }
             }
         }
     }
-}
+    lints.report_all(env.lint_findings.drain(..).collect());
 
-/// Environment tracks the types of symbols in our program. Types of ADTs and
-/// their values are also tracked here.
-#[derive(Debug)]
-pub struct Environment {
-    next_type_var: TVarID,
+    for (ty, info) in std::mem::take(&mut env.shown) {
+        if let Some(func) = find_func(&mut env, ty) {
+            let err = SpruceErr::new(format!("cannot show a function of type {}", env.type_str(func)), info)
+                .with_code(ErrorCode::UnshowableType);
+            env.report(err);
+        }
+    }
+    for (ty, info) in std::mem::take(&mut env.compared) {
+        if let Some(func) = find_func(&mut env, ty) {
+            let err = SpruceErr::new(format!("cannot compare a function of type {}", env.type_str(func)), info)
+                .with_code(ErrorCode::IncomparableType);
+            env.report(err);
+        }
+    }
 
-    // once we finish refining a type, it is 'flushed' into the complete
-    // table, where sub applications will not affect it
-    active_sym_type: HashMap<na::SymbolID, Type>,
-    complete_sym_type: HashMap<na::SymbolID, Type>,
+    let mut typed = Prog {
+        functions: prog.functions.iter().filter_map(|func| typed_funcs.remove(&func.val.name)).collect(),
+        definitions: prog.definitions.iter().filter_map(|stmt| {
+            Binding::Def(stmt).id().and_then(|id| typed_defs.remove(&id))
+        }).collect()
+    };
+    resolve_prog(&mut env, &mut typed);
+    let errors = std::mem::take(&mut env.errors);
+    (typed, env, errors)
+}
 
-    adt_type: HashMap<na::ADTID, Type>,
-    val_type: HashMap<na::ADTValID, Type>,
+/// Fills in the final type of every node in a typed program, once nothing
+/// more can be learned about them
+fn resolve_prog(env: &mut Environment, prog: &mut Prog) {
+    for func in &mut prog.functions {
+        func.ty = env.resolve(func.ty);
+        resolve_body(env, &mut func.val.body);
+    }
+    for stmt in &mut prog.definitions {
+        resolve_stmt(env, stmt);
+    }
+}
 
-    // prelude adts are used internally, so we need to record their type ids
-    internal_types: na::InternalTypes
+fn resolve_body(env: &mut Environment, body: &mut BodyNode) {
+    body.ty = env.resolve(body.ty);
+    for stmt in &mut body.val.stmts {
+        resolve_stmt(env, stmt);
+    }
+    if let Some(expr) = &mut body.val.expr {
+        resolve_expr(env, expr);
+    }
 }
 
-impl Environment {
-    fn new(internal_types: na::InternalTypes) -> Self {
-        Environment {
-            next_type_var: 0,
-            complete_sym_type: HashMap::new(), 
-            active_sym_type: HashMap::new(), 
-            val_type: HashMap::new(), 
-            adt_type: HashMap::new(),
-            internal_types: internal_types
+fn resolve_stmt(env: &mut Environment, stmt: &mut StmtNode) {
+    stmt.ty = env.resolve(stmt.ty);
+    match &mut stmt.val {
+        Stmt::Assign(_, expr) => resolve_expr(env, expr),
+        Stmt::FnCall(_, args) => {
+            for arg in args {
+                resolve_expr(env, arg);
+            }
+        }
+        Stmt::Case(case) => {
+            case.ty = env.resolve(case.ty);
+            resolve_expr(env, &mut case.val.expr);
+            for opt in &mut case.val.options {
+                match &mut opt.val.body.val {
+                    CaseBody::Expr(expr) => resolve_expr(env, expr),
+                    CaseBody::Body(body) => resolve_body(env, body)
+                }
+            }
         }
     }
+}
 
-    fn new_tvar(&mut self) -> Type {
-        self.next_type_var += 1;
-        Type::TVar(self.next_type_var - 1)
+fn resolve_expr(env: &mut Environment, expr: &mut ExprNode) {
+    expr.ty = env.resolve(expr.ty);
+    for child in expr.val.children_mut() {
+        resolve_expr(env, child);
     }
+}
 
-    fn get_sym_type(&self, id: &na::SymbolID) -> Option<&Type> {
-        match self.active_sym_type.get(id) {
-            Some(ty) => Some(ty),
-            None => {
-                self.complete_sym_type.get(id)
+fn check_definition(env: &mut Environment, stmt: &na::StmtNode) -> Option<StmtNode> {
+    match &stmt.val {
+        na::Stmt::Assign(tgt, expr) => {
+            let stmt_tvar = env.new_tvar();
+            match typecheck(env, &expr, stmt_tvar) {
+                Ok(typed) => {
+                    env.insert_sym_type(tgt.val.id(), stmt_tvar);
+                    Some(StmtNode { val: Stmt::Assign(tgt.clone(), typed), ty: stmt_tvar, info: stmt.info.clone() })
+                }
+                Err(err) => {
+                    env.report(err);
+                    env.insert_sym_type(tgt.val.id(), ERROR_TYPE);
+                    None
+                }
             }
         }
+        _ => {
+            env.report(SpruceErr::ice(Phase::Typecheck, String::from("program-level statement is not an assignment"), stmt.info.clone()));
+            None
+        }
     }
+}
 
-    fn insert_sym_type(&mut self, id: na::SymbolID, ty: Type) {
-        self.active_sym_type.insert(id, ty);
+/// Something declared at the top level of a program
+enum Binding<'a> {
+    Def(&'a na::StmtNode),
+    Func(&'a na::FuncNode)
+}
+
+impl<'a> Binding<'a> {
+    fn id(&self) -> Option<na::SymbolID> {
+        match self {
+            Binding::Def(stmt) => {
+                match &stmt.val {
+                    na::Stmt::Assign(tgt, _) => Some(tgt.val.id()),
+                    _ => None
+                }
+            }
+            Binding::Func(func) => Some(func.val.name)
+        }
     }
 
-    /// 'flushes' symbols from active to complete, effectively preventing
-    /// further refinement of their types. This is generally called after we
-    /// leave a function, and have a complete idea of what the function's type
-    /// is
-    fn flush_active_symbols(&mut self) {
-        self.complete_sym_type.extend(self.active_sym_type.drain());
+    fn is_mutable(&self) -> bool {
+        match self {
+            Binding::Def(stmt) => {
+                match &stmt.val {
+                    na::Stmt::Assign(tgt, _) => matches!(tgt.val, na::Target::Mutable(_)),
+                    _ => false
+                }
+            }
+            Binding::Func(_) => false
+        }
     }
+}
 
-    /// applies a set of substitutions to active symbols in the environment,
-    /// refining type variables to more specific types
-    fn apply_subs(&mut self, subs: &TSubst) {
-        self.active_sym_type = self.active_sym_type.iter().map(|(k, ty)| {
-            (*k, apply(subs, (*ty).clone()))
-        }).collect();
+/// Splits a module's top-level definitions into groups that refer to each
+/// other, ordered so that every group comes after the groups it uses. Within
+/// that, definitions are kept in program order
+fn binding_groups<'a>(prog: &'a na::Prog, module: &na::Module) -> Vec<Vec<Binding<'a>>> {
+    let defs = prog.definitions.iter().filter(|stmt| stmt.info.file == module.file);
+    let mut bindings: Vec<Binding> = defs.map(Binding::Def).collect();
+    bindings.extend(prog.functions.iter().filter(|func| func.info.file == module.file).map(Binding::Func));
+
+    let mut index_of: HashMap<na::SymbolID, usize> = HashMap::new();
+    for (i, binding) in bindings.iter().enumerate() {
+        if let Some(id) = binding.id() {
+            index_of.insert(id, i);
+        }
     }
 
-    pub fn as_str(&self, prog: &na::Prog) -> String {
-        let mut output = String::from("");
-        for (id, ty) in &self.complete_sym_type {
-            let name = prog.symbol_table.store.get(id).expect("dangling symbol id").name.clone();
-            output = format!("{}{} : {}\n", output, name, ty.as_str(prog));
+    let edges: Vec<Vec<usize>> = bindings.iter().map(|binding| {
+        let mut refs = Vec::new();
+        match binding {
+            Binding::Def(stmt) => stmt_refs(&stmt.val, &mut refs),
+            Binding::Func(func) => body_refs(&func.val.body.val, &mut refs)
         }
+        refs.iter().filter_map(|id| index_of.get(id).copied()).collect()
+    }).collect();
 
-        output
+    let mut slots: Vec<Option<Binding>> = bindings.into_iter().map(Some).collect();
+    strongly_connected(&edges).into_iter().map(|group| {
+        group.into_iter().filter_map(|node| slots[node].take()).collect()
+    }).collect()
+}
+
+/// Splits the nodes of a graph, given as the nodes each one has an edge to,
+/// into groups that can each reach every node in them, ordered so that every
+/// group comes after the groups it reaches. Each group is sorted
+pub fn strongly_connected(edges: &Vec<Vec<usize>>) -> Vec<Vec<usize>> {
+    let mut search = GroupSearch {
+        edges: edges,
+        index: vec![None; edges.len()],
+        lowlink: vec![0; edges.len()],
+        on_stack: vec![false; edges.len()],
+        stack: Vec::new(),
+        next_index: 0,
+        groups: Vec::new()
+    };
+    for node in 0..edges.len() {
+        if search.index[node].is_none() {
+            search.visit(node);
+        }
+    }
+    for group in &mut search.groups {
+        group.sort();
     }
+    search.groups
 }
 
-/// T(ype)Subst represents a set of substitutions generated by unification
-/// and typecheck
-type TSubst = HashMap<TVarID, Type>;
+/// Tarjan's strongly connected components algorithm. A group is finished
+/// only after every group reachable from it, so dependencies come first
+struct GroupSearch<'a> {
+    edges: &'a Vec<Vec<usize>>,
+    index: Vec<Option<usize>>,
+    lowlink: Vec<usize>,
+    on_stack: Vec<bool>,
+    stack: Vec<usize>,
+    next_index: usize,
+    groups: Vec<Vec<usize>>
+}
 
-pub fn check_prog(prog: &na::Prog) -> Result<Environment, SpruceErr> {
-    let mut env = Environment::new(prog.internal_types.clone());
+impl<'a> GroupSearch<'a> {
+    fn visit(&mut self, node: usize) {
+        self.index[node] = Some(self.next_index);
+        self.lowlink[node] = self.next_index;
+        self.next_index += 1;
+        self.stack.push(node);
+        self.on_stack[node] = true;
 
-    let mut tparams: HashMap<na::TParamID, Type> = HashMap::new();
-    let mut adts: HashMap<na::ADTID, Type> = HashMap::new();
-    for (_, ty) in &prog.type_table.types {
-        let adt_params = ty.type_params.iter();
-        let tvars = adt_params.map(|id| {
-            let tvar = env.new_tvar();
-            tparams.insert(*id, tvar.clone());
-            Box::from(tvar)
-        }).collect();
+        for next in self.edges[node].iter() {
+            match self.index[*next] {
+                None => {
+                    self.visit(*next);
+                    self.lowlink[node] = self.lowlink[node].min(self.lowlink[*next]);
+                }
+                Some(next_index) if self.on_stack[*next] => {
+                    self.lowlink[node] = self.lowlink[node].min(next_index);
+                }
+                Some(_) => ()
+            }
+        }
 
-        env.adt_type.insert(ty.id, Type::ADT(ty.id, tvars));
+        if Some(self.lowlink[node]) == self.index[node] {
+            let mut group = Vec::new();
+            while let Some(member) = self.stack.pop() {
+                self.on_stack[member] = false;
+                group.push(member);
+                if member == node {
+                    break;
+                }
+            }
+            self.groups.push(group);
+        }
     }
+}
 
-    for (_, val) in &prog.type_table.values {
-        let args = val.args.iter().map(|arg| {
-            let arg_type = create_ident_type(arg, &mut env, &tparams);
-            Box::from(arg_type)
-        }).collect();
-        let out = env.adt_type.get(&val.data_type).expect("dangling adt id");
-
-        env.val_type.insert(val.id, Type::Func(args, Box::from(out.clone())));
+/// Collects every symbol referred to in a body
+pub fn body_refs(body: &na::Body, refs: &mut Vec<na::SymbolID>) {
+    for stmt in &body.stmts {
+        stmt_refs(&stmt.val, refs);
     }
-    env.flush_active_symbols();
+    if let Some(expr) = &body.expr {
+        expr_refs(&expr.val, refs);
+    }
+}
 
-    for stmt in &prog.definitions {
-        match &stmt.val {
-            na::Stmt::Assign(tgt, expr) => {
-                let stmt_tvar = env.new_tvar();
-                let subs = typecheck(&mut env, &expr, &stmt_tvar)?;
-                let stmt_type = apply(&subs, stmt_tvar);
-                env.insert_sym_type(tgt.val.id(), stmt_type);
+/// Collects every symbol referred to in a statement
+pub fn stmt_refs(stmt: &na::Stmt, refs: &mut Vec<na::SymbolID>) {
+    match stmt {
+        na::Stmt::Assign(_, expr) => expr_refs(&expr.val, refs),
+        na::Stmt::FnCall(id, args) => {
+            refs.push(*id);
+            for arg in args {
+                expr_refs(&arg.val, refs);
+            }
+        }
+        na::Stmt::Case(case) => {
+            expr_refs(&case.val.expr.val, refs);
+            for opt in &case.val.options {
+                match &opt.val.body.val {
+                    na::CaseBody::Expr(expr) => expr_refs(&expr.val, refs),
+                    na::CaseBody::Body(body) => body_refs(&body.val, refs)
+                }
             }
-            _ => unreachable!()
         }
     }
+}
+
+/// A function type within `ty`, if it has one
+fn find_func(env: &mut Environment, ty: TypeId) -> Option<TypeId> {
+    let resolved = env.resolve(ty);
+    match env.types.get(resolved).clone() {
+        Type::Func(_, _) => Some(resolved),
+        Type::ADT(_, args) => args.into_iter().find_map(|arg| find_func(env, arg)),
+        _ => None
+    }
+}
 
-    for func in &prog.functions {
-        check_func(&mut env, func)?;
+fn expr_refs(expr: &na::Expr, refs: &mut Vec<na::SymbolID>) {
+    match expr {
+        na::Expr::Id(id) => refs.push(*id),
+        na::Expr::FnCall(id, args) => {
+            refs.push(*id);
+            for arg in args {
+                expr_refs(&arg.val, refs);
+            }
+        }
+        na::Expr::ADTVal(_, args) | na::Expr::List(args) => {
+            for arg in args {
+                expr_refs(&arg.val, refs);
+            }
+        }
+        na::Expr::Lit(_) => (),
+        na::Expr::Neg(inner) => expr_refs(&inner.val, refs),
+        na::Expr::Add(left, right) | na::Expr::Subt(left, right) | na::Expr::Mult(left, right) |
+        na::Expr::Div(left, right) | na::Expr::Pow(left, right) | na::Expr::Mod(left, right) |
+        na::Expr::BitAnd(left, right) | na::Expr::BitOr(left, right) | na::Expr::BitXor(left, right) |
+        na::Expr::Shl(left, right) | na::Expr::Shr(left, right) |
+        na::Expr::Eq(left, right) | na::Expr::NotEq(left, right) | na::Expr::LtEq(left, right) |
+        na::Expr::GtEq(left, right) | na::Expr::Lt(left, right) | na::Expr::Gt(left, right) |
+        na::Expr::ComposeR(left, right) | na::Expr::ComposeL(left, right) => {
+            expr_refs(&left.val, refs);
+            expr_refs(&right.val, refs);
+        }
     }
+}
 
-    Ok(env)
+/// Where a constructor is declared, for internal errors about it. A position
+/// in no file is used if it can't be found, which is still reported
+fn constructor_info(prog: &na::Prog, name: &String) -> NodeInfo {
+    let options = prog.types.iter().flat_map(|ty| ty.val.options.iter());
+    match options.filter(|opt| &opt.val.name == name).next() {
+        Some(opt) => opt.info.clone(),
+        None => NodeInfo { span: Span { start: 0, end: 0 }, file: NO_FILE }
+    }
 }
 
-fn create_ident_type(ident: &na::TypeID, env: &Environment,  tparams: &HashMap<na::TParamID, Type>) -> Type {
+/// Type of a constructor argument or signature, with type parameters
+/// replaced by the type variables standing in for them
+fn create_ident_type(types: &mut TypeArena, ident: &na::TypeID, tparams: &HashMap<na::TParamID, TypeId>, info: &NodeInfo) -> Result<TypeId, SpruceErr> {
     match ident {
         na::TypeID::TParam(id) => {
-            tparams.get(id).expect("dangling tparam id").clone()
+            match tparams.get(id) {
+                Some(tvar) => Ok(*tvar),
+                None => Err(SpruceErr::ice(Phase::Typecheck, format!("type parameter {} does not belong to any type", id), info.clone()))
+            }
         }
         na::TypeID::ADT(id, args) => {
-            let arg_types: Vec<Box<Type>> = args.iter().map(|arg| {
-                Box::from(create_ident_type(arg, env, tparams))
-            }).collect();
-            //env.adt_type.get(id).expect("dangling type id").clone()
-            Type::ADT(*id, arg_types)
+            let arg_types = args.iter().map(|arg| {
+                create_ident_type(types, arg, tparams, info)
+            }).collect::<Result<Vec<TypeId>, SpruceErr>>()?;
+            Ok(types.adt(*id, arg_types))
         }
         na::TypeID::Prim(s) => {
-            Type::Prim(s.clone())
+            Ok(types.intern(Type::Prim(s.clone())))
         }
+        na::TypeID::Func(args, out) => {
+            let arg_types = args.iter().map(|arg| {
+                create_ident_type(types, arg, tparams, info)
+            }).collect::<Result<Vec<TypeId>, SpruceErr>>()?;
+            let out_type = create_ident_type(types, out, tparams, info)?;
+            Ok(types.func(arg_types, out_type))
+        }
+        na::TypeID::Unit => Ok(UNIT_TYPE)
     }
 }
 
-fn check_func(env: &mut Environment, func: &na::FuncNode) -> Result<bool, SpruceErr> {
+/// Checks a function, or a test if `test` is set, which must give a Bool
+fn check_func(env: &mut Environment, func: &na::FuncNode, test: bool) -> Result<FuncNode, SpruceErr> {
     let mut arg_types = Vec::new();
     for arg in &func.val.args {
         let arg_tvar = env.new_tvar();
-        env.insert_sym_type(*arg, arg_tvar.clone());
-        arg_types.push(Box::from(arg_tvar));
+        env.insert_sym_type(*arg, arg_tvar);
+        arg_types.push(arg_tvar);
     }
-    let ret_tvar = env.new_tvar();
-    let fn_type = Type::Func(arg_types, Box::from(ret_tvar.clone()));
-    let body_subs = check_body(env, &func.val.body, &ret_tvar)?;
-
-    let refined_fn_type = apply(&body_subs, fn_type);
-    env.apply_subs(&body_subs);
+    let ret_tvar = if test { bool_adt!(env) } else { env.new_tvar() };
+    let fn_type = env.types.func(arg_types, ret_tvar);
+    let body = check_body(env, &func.val.body, ret_tvar)?;
 
-    // it's possible that the function id is already assigned a type from an
-    // earlier typecheck if it appeared in a function call
+    // the function was given a type before its group was checked, which
+    // recursive calls may have refined
     match env.get_sym_type(&func.val.name) {
         Some(env_fn_type) => {
-            match unify(env_fn_type, &refined_fn_type, &func.info) {
-                Ok(subs) => {
-                    env.apply_subs(&subs);
-                }
+            match unify(env, env_fn_type, fn_type, &func.info) {
+                Ok(()) => (),
                 Err(type_err) => {
-                    return Err(SpruceErr {
-                        message: String::from("Function definiton incompatible with earlier function call"),
-                        info: type_err.info.clone()
-                    })
+                    let mut err = SpruceErr::new(
+                        String::from("Function definiton incompatible with earlier function call"),
+                        type_err.info.clone()
+                    ).with_code(ErrorCode::MismatchedTypes);
+                    err.type_error = type_err.type_error;
+                    return Err(env.explain_origin(err, &func.val.name))
                 }
             };
         }
         None => {
-            env.insert_sym_type(func.val.name, refined_fn_type);
+            env.insert_sym_type(func.val.name, fn_type);
         }
     };
-
-    env.flush_active_symbols();
-
-    Ok(true)
+    env.set_origin(func.val.name, "declared here", &func.info);
+
+    Ok(FuncNode {
+        val: Func {
+            name: func.val.name,
+            args: func.val.args.clone(),
+            body: body
+        },
+        ty: fn_type,
+        info: func.info.clone()
+    })
 }
 
 
-fn check_case(env: &mut Environment, case: &na::CaseNode, ty: &Type) -> Result<TSubst, SpruceErr> {
-    let mut subs = HashMap::new();
-
+fn check_case(env: &mut Environment, case: &na::CaseNode, ty: TypeId) -> Result<CaseNode, SpruceErr> {
+    // a bad expression doesn't stop the options from being checked, since
+    // the patterns still say what type is being matched
     let expr_tvar = env.new_tvar();
-    let expr_subs = typecheck(env, &case.val.expr, &expr_tvar).expect("failed typecheck");
-    let expr_type = apply(&expr_subs, expr_tvar);
-    env.apply_subs(&expr_subs);
-    subs.extend(expr_subs);
+    let typed_expr = match typecheck(env, &case.val.expr, expr_tvar) {
+        Ok(typed) => typed,
+        Err(err) => {
+            env.report(err);
+            error_node(&case.val.expr)
+        }
+    };
+    let expr_type = typed_expr.ty;
 
 
-    // start by analyzing patterns
-    let mut pattern_type_id = None;
+    // options after a wildcard, or repeating an earlier pattern, never run
+    let mut seen: Vec<&na::CasePattern> = Vec::new();
     for opt in &case.val.options {
-        let opt_pat_type_id = match env.val_type.get(&opt.val.pattern.val.base).expect("dangling type id") {
-            Type::Func(args, out) => {
-                match &**out {
-                    Type::ADT(id, _) => id,
-                    _ => unreachable!()
-                }
+        let pattern = &opt.val.pattern.val;
+        let covered = seen.iter().any(|earlier| {
+            match (earlier, pattern) {
+                (na::CasePattern::Any, _) => true,
+                (na::CasePattern::ADT(id1, _), na::CasePattern::ADT(id2, _)) => id1 == id2,
+                (na::CasePattern::Lit(lit1), na::CasePattern::Lit(lit2)) => lit1 == lit2,
+                _ => false
             }
-            _ => unreachable!()
+        });
+        if covered {
+            env.lint_findings.push((Lint::Unreachable, String::from("this option can never be reached"), opt.val.pattern.info.clone()));
+        }
+        seen.push(pattern);
+    }
+
+    // start by analyzing patterns. All of them need to agree on the type
+    // being matched: an ADT, or Int when matching on literals
+    let mut pattern_type: Option<TypeId> = None;
+    for opt in &case.val.options {
+        let opt_pat_type = match &opt.val.pattern.val {
+            na::CasePattern::ADT(base, _) => env.get_constructor_type(base, &opt.val.pattern.info)?.1,
+            na::CasePattern::Lit(_) => INT_TYPE,
+            na::CasePattern::Any => continue
         };
-        match pattern_type_id {
+        match pattern_type {
             None => {
-                pattern_type_id = Some(*opt_pat_type_id);
+                pattern_type = Some(opt_pat_type);
             }
-            Some(pat_type_id) => {
-                if pat_type_id != *opt_pat_type_id {
-                    return Err(SpruceErr {
-                        message: format!("case statement has patterns of both types {} and {}", pat_type_id, opt_pat_type_id),
-                        info: opt.val.pattern.info.clone()
-                    })
+            Some(pat_type) => {
+                let same_type = match (env.types.get(pat_type), env.types.get(opt_pat_type)) {
+                    (Type::ADT(id1, _), Type::ADT(id2, _)) => id1 == id2,
+                    _ => pat_type == opt_pat_type
+                };
+                if !same_type {
+                    return Err(SpruceErr::new(
+                        {
+                            let names = env.describe(vec![pat_type, opt_pat_type]);
+                            format!("case statement has patterns of both types {} and {}", names[0], names[1])
+                        },
+                        opt.val.pattern.info.clone()
+                    ).with_code(ErrorCode::MixedPatterns))
                 }
             }
         }
     }
 
-    let adt_type = env.adt_type.get(&pattern_type_id.expect("unreachable")).expect("dangling adt id").clone();
-    let (adt_id, adt_args) = match &adt_type {
-        Type::ADT(id, args) => (id, args),
-        _ => unreachable!()
+    // a case made only of wildcards can match on anything
+    let matched_type = match pattern_type {
+        Some(pat_type) => pat_type,
+        None => env.new_tvar()
     };
 
-    // we can't let the tvars of the acutal ADT "leak" to the arms. For reasons
-    // that are not entirely clear to me we don't need to do this when
-    // applying an adt constructor, only here
-    let adt_tvar_subs = refresh_tvars(env, &adt_type);
-
-    let pattern_subs = unify(&apply(&adt_tvar_subs, adt_type), &expr_type, &case.info)?;
-    env.apply_subs(&pattern_subs);
-    subs.extend(pattern_subs);
+    unify(env, matched_type, expr_type, &case.info)?;
 
     let mut is_unit = false;
     let mut has_expr = false;
+    let mut typed_options = Vec::new();
     for opt in &case.val.options {
-        let pattern_arg_types = match env.val_type.get(&opt.val.pattern.val.base).expect("dangling type id") {
-            Type::Func(args, _) => args.clone(),
-            _ => unreachable!()
-        };
-
-        for (arg, pat_arg_type) in opt.val.pattern.val.args.iter().zip(pattern_arg_types) {
-            let arg_type: Type = apply(&adt_tvar_subs, *pat_arg_type);
-            env.insert_sym_type(*arg, arg_type);
+        // each pattern has its own instance of the constructor, tied to the
+        // matched type so arguments get the types of what they match
+        if let na::CasePattern::ADT(base, args) = &opt.val.pattern.val {
+            let (pattern_arg_types, pattern_out) = env.get_constructor_type(base, &opt.val.pattern.info)?;
+            unify(env, pattern_out, matched_type, &opt.val.pattern.info)?;
+
+            for (arg, pat_arg_type) in args.iter().zip(pattern_arg_types) {
+                env.insert_sym_type(*arg, pat_arg_type);
+            }
         }
 
 
-        match &opt.val.body.val {
+        let typed_body = match &opt.val.body.val {
             na::CaseBody::Body(body) => {
                 let opt_tvar = env.new_tvar();
-                let opt_subs = check_body(env, &body, &opt_tvar)?;
-                let opt_type = apply(&opt_subs, opt_tvar);
-                env.apply_subs(&opt_subs);
-                subs.extend(opt_subs);
-
-                match unify(&apply(&subs, (*ty).clone()), &opt_type, &body.info) {
-                    Ok(uni_subs) => {
-                        env.apply_subs(&uni_subs);
-                        subs.extend(uni_subs);
-                    }
-                    Err(_) => {
-                        is_unit = true;
-                    }
+                let typed = check_body(env, &body, opt_tvar)?;
+
+                if unify(env, ty, opt_tvar, &body.info).is_err() {
+                    is_unit = true;
                 }
+                CaseBody::Body(typed)
             }
             na::CaseBody::Expr(expr) => {
                 has_expr = true;
 
-                let opt_subs = typecheck(env, &expr, &apply(&subs, (*ty).clone()))?;
-                env.apply_subs(&opt_subs);
-                subs.extend(opt_subs);
+                // the other options still decide the type of the case
+                match typecheck(env, &expr, ty) {
+                    Ok(typed) => CaseBody::Expr(typed),
+                    Err(err) => {
+                        env.report(err);
+                        CaseBody::Expr(error_node(expr))
+                    }
+                }
             }
-        }
+        };
+
+        typed_options.push(CaseOptionNode {
+            val: CaseOption {
+                pattern: opt.val.pattern.clone(),
+                body: CaseBodyNode { val: typed_body, info: opt.val.body.info.clone() }
+            },
+            info: opt.info.clone()
+        });
     }
 
     if is_unit {
         if has_expr {
-            return Err(SpruceErr {
-                message: String::from("Case with expr must have type"),
-                info: case.info.clone()
-            });
+            return Err(SpruceErr::new(
+                String::from("Case with expr must have type"),
+                case.info.clone()
+            ).with_code(ErrorCode::UntypedCase));
         }
         else {
-            let unit_subs = unify(ty, &Type::Unit, &case.info).expect("unreachable");
-            subs.extend(unit_subs);
+            unify(env, ty, UNIT_TYPE, &case.info)?;
         }
     }
 
-    Ok(subs)
+    Ok(CaseNode {
+        val: Case {
+            id: case.val.id,
+            expr: typed_expr,
+            options: typed_options
+        },
+        ty: ty,
+        info: case.info.clone()
+    })
 }
 
-fn check_body(env: &mut Environment, body: &na::BodyNode, ty: &Type) -> Result<TSubst, SpruceErr> {
-    let mut stmt_types = Vec::new();
-    let mut subs = HashMap::new();
-    for stmt in &body.val.stmts {
-        match &stmt.val {
-            na::Stmt::Assign(tgt, expr) => {
-                match &tgt.val {
-                    na::Target::Update(id) => {
-                        let sym_type = env.get_sym_type(id).expect("Dangling symbol id").clone();
-                        let stmt_subs = typecheck(env, expr, &sym_type)?;
-                        env.apply_subs(&stmt_subs);
-
-                        let var_type = apply(&stmt_subs, sym_type);
-                        stmt_types.push(var_type);
-                        subs.extend(stmt_subs);
-                    }
-                    _ => {
-                        let new_tvar = env.new_tvar();
-                        let stmt_subs = typecheck(env, expr, &new_tvar)?;
-                        let var_type = apply(&stmt_subs, new_tvar);
-                        
-                        env.insert_sym_type(tgt.val.id(), var_type.clone());
-                        env.apply_subs(&stmt_subs);
-
-                        stmt_types.push(var_type);
-                        subs.extend(stmt_subs);
-                    }
+/// Typechecks a single statement in a body. Its type is that of the value it
+/// produces
+fn check_stmt(env: &mut Environment, stmt: &na::StmtNode) -> Result<StmtNode, SpruceErr> {
+    let (val, ty) = match &stmt.val {
+        na::Stmt::Assign(tgt, expr) => {
+            match &tgt.val {
+                na::Target::Update(id) => {
+                    let sym_type = match env.get_sym_type(id) {
+                        Some(sym_type) => sym_type,
+                        None => {
+                            return Err(SpruceErr::ice(Phase::Typecheck, format!("updated symbol {} has no type", id), tgt.info.clone()))
+                        }
+                    };
+                    let typed = typecheck(env, expr, sym_type)?;
+                    (Stmt::Assign(tgt.clone(), typed), sym_type)
                 }
-            }
-            na::Stmt::Case(case) => {
-                let new_tvar = env.new_tvar();
-                let case_subs = check_case(env, case, &new_tvar)?;
-                let var_type = apply(&case_subs, new_tvar);
+                _ => {
+                    let new_tvar = env.new_tvar();
+                    let typed = typecheck(env, expr, new_tvar)?;
 
-                env.apply_subs(&case_subs);
-
-                stmt_types.push(var_type);
-                subs.extend(case_subs);
+                    env.insert_sym_type(tgt.val.id(), new_tvar);
+                    env.set_origin(tgt.val.id(), "assigned here", &stmt.info);
+                    (Stmt::Assign(tgt.clone(), typed), new_tvar)
+                }
             }
-            // it's annoying that fn call doesn't carry a single expr; we
-            // might want to make this change soon
-            na::Stmt::FnCall(id, args) => {
-                let cloned_args = args.iter().map(|arg| { Box::from(arg.clone()) }).collect();
-                let fn_expr = na::ExprNode {
-                    val: na::Expr::FnCall(id.clone(), cloned_args),
-                    info: stmt.info.clone()
-                };
-
-                let new_tvar = env.new_tvar();
-                let fn_subs = typecheck(env, &fn_expr, &new_tvar)?;
-                let fn_type = apply(&fn_subs, new_tvar);
+        }
+        na::Stmt::Case(case) => {
+            let new_tvar = env.new_tvar();
+            let typed = check_case(env, case, new_tvar)?;
+            (Stmt::Case(typed), new_tvar)
+        }
+        na::Stmt::FnCall(id, args) => {
+            let new_tvar = env.new_tvar();
+            let typed_args = check_call(env, id, args.iter().collect(), new_tvar, &stmt.info)?;
+            (Stmt::FnCall(*id, typed_args), new_tvar)
+        }
+    };
 
-                env.apply_subs(&fn_subs);
+    Ok(StmtNode { val: val, ty: ty, info: stmt.info.clone() })
+}
 
-                stmt_types.push(fn_type);
-                subs.extend(fn_subs);
+fn check_body(env: &mut Environment, body: &na::BodyNode, ty: TypeId) -> Result<BodyNode, SpruceErr> {
+    let mut stmts = Vec::new();
+    let mut last_stmt_type = None;
+    for stmt in &body.val.stmts {
+        match check_stmt(env, stmt) {
+            Ok(typed) => {
+                last_stmt_type = Some(typed.ty);
+                stmts.push(typed);
+            }
+            Err(err) => {
+                env.report(err);
+
+                // whatever the statement declared is poisoned, so later uses
+                // of it don't report errors of their own
+                if let na::Stmt::Assign(tgt, _) = &stmt.val {
+                    match tgt.val {
+                        na::Target::Update(_) => (),
+                        _ => env.insert_sym_type(tgt.val.id(), ERROR_TYPE)
+                    }
+                }
+                last_stmt_type = Some(ERROR_TYPE);
             }
         }
     }
 
-    match &body.val.expr {
+    let expr = match &body.val.expr {
         Some(expr) => {
-            let expr_subs = typecheck(env, &expr, ty)?;
-            env.apply_subs(&expr_subs);
-            subs.extend(expr_subs);
+            match typecheck(env, &expr, ty) {
+                Ok(typed) => Some(typed),
+                Err(err) => {
+                    env.report(err);
+                    Some(error_node(expr))
+                }
+            }
         }
         None => {
-            let last_stmt_type = stmt_types.last().expect("unreachable");
-            let stmt_subs = unify(last_stmt_type, ty, &body.info).expect("unreachable");
-
-            env.apply_subs(&stmt_subs);
-            subs.extend(stmt_subs);
+            let last_stmt_type = match last_stmt_type {
+                Some(stmt_type) => stmt_type,
+                None => {
+                    return Err(SpruceErr::ice(Phase::Typecheck, String::from("body has neither statements nor an expression"), body.info.clone()))
+                }
+            };
+            unify(env, last_stmt_type, ty, &body.info)?;
+            None
         }
     };
 
-    Ok(subs)
+    Ok(BodyNode {
+        val: Body {
+            stmts: stmts,
+            expr: expr
+        },
+        ty: ty,
+        info: body.info.clone()
+    })
 }
 
-macro_rules! int_prim {
-    () => {
-        Type::Prim(String::from("Int"))
-    };
-}
+/// Checks a call of `id` whose result has type `ty`, giving back the typed
+/// arguments
+fn check_call(env: &mut Environment, id: &na::SymbolID, args: Vec<&na::ExprNode>, ty: TypeId, info: &NodeInfo) -> Result<Vec<ExprNode>, SpruceErr> {
+    let mut typed_args = Vec::new();
+    for arg in args {
+        let arg_tvar = env.new_tvar();
+        typed_args.push(typecheck(env, arg, arg_tvar)?);
+    }
 
-macro_rules! bool_adt {
-    ($e:ident) => {
-        Type::ADT($e.internal_types.bool_id, vec![])
+    let arg_types = typed_args.iter().map(|arg| arg.ty).collect();
+    let fn_type = env.types.func(arg_types, ty);
+
+    let fn_sym_type = match env.get_sym_type(&id) {
+        Some(sym) => sym,
+        None => {
+            let fn_tvar = env.new_tvar();
+            env.insert_sym_type(*id, fn_tvar);
+            env.set_origin(*id, "type first inferred from this call", info);
+            fn_tvar
+        }
     };
+    unify(env, fn_sym_type, fn_type, info).map_err(|err| env.explain_origin(err, id))?;
+    Ok(typed_args)
 }
 
-// TODO: add apply_env everywhere
-fn typecheck(env: &mut Environment, expr: &na::ExprNode, ty: &Type) -> Result<TSubst, SpruceErr> {
-    println!("Typecheck {:?} and {:?}", expr.val, ty);
-    let res = match &expr.val {
-        na::Expr::Lit(_) => unify(ty, &int_prim!(), &expr.info),
+fn typecheck(env: &mut Environment, expr: &na::ExprNode, ty: TypeId) -> Result<ExprNode, SpruceErr> {
+    trace!(Phase::Typecheck, "typecheck {:?} against {}", expr.val, env.describe_debug(ty));
+    let val = match &expr.val {
+        na::Expr::Lit(lit) => {
+            unify(env, ty, INT_TYPE, &expr.info)?;
+            Expr::Lit(*lit)
+        }
+        na::Expr::Neg(inner) => {
+            unify(env, ty, INT_TYPE, &expr.info)?;
+            Expr::Neg(Box::from(typecheck(env, &*inner, INT_TYPE)?))
+        }
+        // every element must share the list's type parameter
+        na::Expr::List(elements) => {
+            let elem_tvar = env.new_tvar();
+            let list_type = env.types.adt(env.registry.type_id(LangType::List), vec![elem_tvar]);
+            unify(env, ty, list_type, &expr.info)?;
+
+            let mut typed_elements = Vec::new();
+            for elem in elements {
+                typed_elements.push(Box::from(typecheck(env, &*elem, elem_tvar)?));
+            }
+            Expr::List(typed_elements)
+        }
         na::Expr::Add(left, right) | na::Expr::Subt(left, right) | na::Expr::Mult(left, right) |
-        na::Expr::Div(left, right) | na::Expr::Pow(left, right) | na::Expr::Mod(left, right) => {
-            let mut subs = unify(ty, &int_prim!(), &expr.info)?;
-
-            let subs1 = typecheck(env, &*left, &int_prim!())?;
-            let subs2 = typecheck(env, &*right, &int_prim!())?;
-            subs.extend(subs1);
-            subs.extend(subs2);
-            Ok(subs)
+        na::Expr::Div(left, right) | na::Expr::Pow(left, right) | na::Expr::Mod(left, right) |
+        na::Expr::BitAnd(left, right) | na::Expr::BitOr(left, right) | na::Expr::BitXor(left, right) |
+        na::Expr::Shl(left, right) | na::Expr::Shr(left, right) => {
+            unify(env, ty, INT_TYPE, &expr.info)?;
+
+            let typed_left = typecheck(env, &*left, INT_TYPE).map_err(|err| expected_by(err, left, expr))?;
+            let typed_right = typecheck(env, &*right, INT_TYPE).map_err(|err| expected_by(err, right, expr))?;
+            binary(expr, typed_left, typed_right)?
         }
         na::Expr::Eq(left, right) | na::Expr::NotEq(left, right) => {
-            let mut subs = unify(ty, &bool_adt!(env), &expr.info)?;
+            let bool_type = bool_adt!(env);
+            unify(env, ty, bool_type, &expr.info)?;
 
             let new_tvar = env.new_tvar();
-            let subs1 = typecheck(env, &*left, &new_tvar)?;
-
-            let updated_tvar = apply(&subs1, new_tvar);
-            let subs2 = typecheck(env, &*right, &updated_tvar)?;
-
-            subs.extend(subs1);
-            subs.extend(subs2);
-            Ok(subs)
+            let typed_left = typecheck(env, &*left, new_tvar)?;
+            let typed_right = typecheck(env, &*right, new_tvar)?;
+            binary(expr, typed_left, typed_right)?
         }
         na::Expr::LtEq(left, right) | na::Expr::GtEq(left, right) | na::Expr::Lt(left, right) |
         na::Expr::Gt(left, right) => {
-            let mut subs = unify(ty, &bool_adt!(env), &expr.info)?;
+            let bool_type = bool_adt!(env);
+            unify(env, ty, bool_type, &expr.info)?;
 
-            let subs1 = typecheck(env, &*left, &int_prim!())?;
-            let subs2 = typecheck(env, &*right, &int_prim!())?;
-            subs.extend(subs1);
-            subs.extend(subs2);
-            Ok(subs)
+            let typed_left = typecheck(env, &*left, INT_TYPE).map_err(|err| expected_by(err, left, expr))?;
+            let typed_right = typecheck(env, &*right, INT_TYPE).map_err(|err| expected_by(err, right, expr))?;
+            binary(expr, typed_left, typed_right)?
+        }
+        // (a -> b) -> (b -> c) -> (a -> c), where `first` is applied before
+        // `second` regardless of which way the operator points
+        na::Expr::ComposeR(first, second) | na::Expr::ComposeL(second, first) => {
+            let a = env.new_tvar();
+            let b = env.new_tvar();
+            let c = env.new_tvar();
+            let out_type = env.types.func(vec![a], c);
+            unify(env, ty, out_type, &expr.info)?;
+
+            let first_type = env.types.func(vec![a], b);
+            let typed_first = typecheck(env, &*first, first_type)?;
+
+            let second_type = env.types.func(vec![b], c);
+            let typed_second = typecheck(env, &*second, second_type)?;
+            match &expr.val {
+                na::Expr::ComposeR(_, _) => Expr::ComposeR(Box::from(typed_first), Box::from(typed_second)),
+                _ => Expr::ComposeL(Box::from(typed_second), Box::from(typed_first))
+            }
         }
 
         na::Expr::Id(id) => {
             match env.get_sym_type(&id) {
                 Some(sym_type) => {
-                    unify(ty, sym_type, &expr.info)
+                    unify(env, ty, sym_type, &expr.info).map_err(|err| env.explain_origin(err, id))?;
                 }
                 // if we encounter an id without an id, make a tvar and keep
                 // going. we'll verify the type later when we check whatever
                 // the id refers to
                 None => {
                     let id_tvar = env.new_tvar();
-                    env.insert_sym_type(*id, id_tvar.clone());
-                    unify(ty, &id_tvar, &expr.info)
+                    env.insert_sym_type(*id, id_tvar);
+                    env.set_origin(*id, "type first inferred from this use", &expr.info);
+                    unify(env, ty, id_tvar, &expr.info)?;
                 }
             }
+            Expr::Id(*id)
         }
 
         na::Expr::FnCall(id, args) => {
-            let mut subs = HashMap::new();
-            let mut arg_types = Vec::new();
-            for arg in args {
-                let arg_tvar = env.new_tvar();
-                let arg_subs = typecheck(env, &*arg, &arg_tvar)?;
-                arg_types.push(Box::from(apply(&arg_subs, arg_tvar)));
-                subs.extend(arg_subs);
+            let typed_args = check_call(env, id, args.iter().map(|arg| &**arg).collect(), ty, &expr.info)?;
+            if Some(*id) == env.registry.fn_id(LangFn::Show) {
+                env.shown.extend(typed_args.iter().map(|arg| (arg.ty, arg.info.clone())));
             }
-
-            let out_tvar = env.new_tvar();
-            let out_subs = unify(&ty, &out_tvar, &expr.info)?;
-            let out_type = apply(&out_subs, out_tvar);
-            subs.extend(out_subs);
-
-            let fn_type = Type::Func(arg_types, Box::from(out_type));
-
-            let fn_sym_type = match env.get_sym_type(&id) {
-                Some(sym) => sym.clone(),
-                None => {
-                    let fn_tvar = env.new_tvar();
-                    env.insert_sym_type(*id, fn_tvar.clone());
-                    fn_tvar
-                }
-            };
-            let fn_subs = unify(&fn_sym_type, &fn_type, &expr.info)?;
-            subs.extend(fn_subs);
-
-            Ok(subs)
+            if Some(*id) == env.registry.fn_id(LangFn::Compare) {
+                env.compared.extend(typed_args.iter().map(|arg| (arg.ty, arg.info.clone())));
+            }
+            Expr::FnCall(*id, typed_args.into_iter().map(Box::from).collect())
         }
 
         na::Expr::ADTVal(id, args) => {
-            let mut subs = HashMap::new();
-            let mut arg_types = Vec::new();
+            let mut typed_args = Vec::new();
             for arg in args {
                 let arg_tvar = env.new_tvar();
-                let arg_subs = typecheck(env, &*arg, &arg_tvar)?;
-                arg_types.push(Box::from(apply(&arg_subs, arg_tvar)));
-                subs.extend(arg_subs);
+                typed_args.push(typecheck(env, &*arg, arg_tvar)?);
             }
 
-            let out_tvar = env.new_tvar();
-            let out_subs = unify(&ty, &out_tvar, &expr.info)?;
-            let out_type = apply(&out_subs, out_tvar);
-            subs.extend(out_subs);
-
-            let fn_type = Type::Func(arg_types, Box::from(out_type));
+            let arg_types = typed_args.iter().map(|arg| arg.ty).collect();
+            let fn_type = env.types.func(arg_types, ty);
 
-            let fn_sym_type = env.val_type.get(&id).expect("dangling val id");
-            let fn_subs = unify(&fn_sym_type, &fn_type, &expr.info)?;
-            subs.extend(fn_subs);
-
-            Ok(subs)
+            let (val_args, val_out) = env.get_constructor_type(id, &expr.info)?;
+            let val_type = env.types.func(val_args, val_out);
+            unify(env, val_type, fn_type, &expr.info)?;
+            Expr::ADTVal(*id, typed_args.into_iter().map(Box::from).collect())
         }
-    }?;
+    };
 
-    println!("subs: {:?}\ntype: {:?}\n", res, apply(&res, ty.clone()));
+    trace!(Phase::Typecheck, "type: {}", env.describe_debug(ty));
 
-    Ok(res)
+    Ok(ExprNode { val: val, ty: ty, info: expr.info.clone() })
 }
 
-fn refresh_tvars(env: &mut Environment, ty: &Type) -> TSubst {
-    let old_tvars = tvars(ty);
-    let mut replacements = HashMap::new();
-    for tvar in old_tvars {
-        replacements.insert(tvar, env.new_tvar());
+/// The typed version of a binary operator, given its typed operands
+fn binary(op: &na::ExprNode, left: ExprNode, right: ExprNode) -> Result<Expr, SpruceErr> {
+    let (left, right) = (Box::from(left), Box::from(right));
+    match op.val {
+        na::Expr::Add(_, _) => Ok(Expr::Add(left, right)),
+        na::Expr::Subt(_, _) => Ok(Expr::Subt(left, right)),
+        na::Expr::Mult(_, _) => Ok(Expr::Mult(left, right)),
+        na::Expr::Div(_, _) => Ok(Expr::Div(left, right)),
+        na::Expr::Pow(_, _) => Ok(Expr::Pow(left, right)),
+        na::Expr::Mod(_, _) => Ok(Expr::Mod(left, right)),
+        na::Expr::BitAnd(_, _) => Ok(Expr::BitAnd(left, right)),
+        na::Expr::BitOr(_, _) => Ok(Expr::BitOr(left, right)),
+        na::Expr::BitXor(_, _) => Ok(Expr::BitXor(left, right)),
+        na::Expr::Shl(_, _) => Ok(Expr::Shl(left, right)),
+        na::Expr::Shr(_, _) => Ok(Expr::Shr(left, right)),
+        na::Expr::Eq(_, _) => Ok(Expr::Eq(left, right)),
+        na::Expr::NotEq(_, _) => Ok(Expr::NotEq(left, right)),
+        na::Expr::LtEq(_, _) => Ok(Expr::LtEq(left, right)),
+        na::Expr::GtEq(_, _) => Ok(Expr::GtEq(left, right)),
+        na::Expr::Lt(_, _) => Ok(Expr::Lt(left, right)),
+        na::Expr::Gt(_, _) => Ok(Expr::Gt(left, right)),
+        _ => Err(SpruceErr::ice(Phase::Typecheck, String::from("expression is not a binary operator"), op.info.clone()))
     }
+}
+
+/// Stands in for an expression that failed to check and was reported
+fn error_node(expr: &na::ExprNode) -> ExprNode {
+    ExprNode { val: Expr::Error, ty: ERROR_TYPE, info: expr.info.clone() }
+}
 
-    replacements
+/// Labels a type error in an operand of an Int operator with the operator,
+/// unless the error came from deeper inside the operand
+fn expected_by(err: SpruceErr, operand: &na::ExprNode, op: &na::ExprNode) -> SpruceErr {
+    if err.info == operand.info {
+        err.with_label(String::from("Int expected by this operator"), op.info.clone())
+    }
+    else {
+        err
+    }
 }
 
-fn apply(subs: &TSubst, ty: Type) -> Type {
-    match &ty {
+fn apply(types: &mut TypeArena, subs: &TSubst, ty: TypeId) -> TypeId {
+    match types.get(ty).clone() {
         Type::TVar(id) => {
-            match &subs.get(id) {
-                Some(sub_ty) => apply(subs, (**sub_ty).clone()),
+            match subs.get(&id) {
+                Some(sub_ty) => apply(types, subs, *sub_ty),
                 None => ty
             }
         }
-        Type::Unit => ty,
+        Type::Unit | Type::Error => ty,
         Type::Prim(_) => ty,
         Type::ADT(id, params) => {
-            let new_params = params.iter().map(|p| { Box::from(apply(subs, (**p).clone())) }).collect();
+            let new_params = params.iter().map(|p| apply(types, subs, *p)).collect();
 
-            Type::ADT(*id, new_params)
+            types.adt(id, new_params)
         }
         Type::Func(args, out) => {
-            let new_args = args.iter().map(|arg| { Box::from(apply(subs, (**arg).clone())) }).collect();
+            let new_args = args.iter().map(|arg| apply(types, subs, *arg)).collect();
+            let new_out = apply(types, subs, out);
 
-            Type::Func(new_args, Box::from(apply(subs, (**out).clone())))
+            types.func(new_args, new_out)
         }
     }
 }
 
-fn unify(left: &Type, right: &Type, info: &NodeInfo) -> Result<TSubst, SpruceErr> {
-    //println!("unification on: {} and {}", left.as_str_debug(), right.as_str_debug());
-    match (left, right) {
-        (Type::TVar(id1), Type::TVar(id2)) => {
-            if id1 == id2 {
-                Some(HashMap::new())
-            }
-            else {
-                Some(HashMap::from_iter(vec![(*id1, right.clone())]))
-            }
+/// Unifies two types, reporting a failure as an error at `info` that names
+/// types as they appear in the program. A failed unification leaves the
+/// environment as it was
+fn unify(env: &mut Environment, left: TypeId, right: TypeId, info: &NodeInfo) -> Result<(), SpruceErr> {
+    env.tvars.snapshot();
+    match unify_types(&mut env.tvars, &mut env.types, left, right) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            env.tvars.rollback();
+            Err(err.to_diagnostic(env, info))
         }
+    }
+}
 
-        (Type::TVar(id), _) => {
-            if tvars(right).contains(id) {
-                None
-            }
-            else {
-                Some(HashMap::from_iter(vec![(*id, right.clone())]))
-            }
-        }
+fn unify_types(table: &mut TVarTable, types: &mut TypeArena, left: TypeId, right: TypeId) -> Result<(), TypeError> {
+    let left = table.shallow(types, left);
+    let right = table.shallow(types, right);
+    trace!(Phase::Typecheck, "unify {} and {}", types.write_debug(left, table), types.write_debug(right, table));
 
-        (_, Type::TVar(id)) => {
-            if tvars(left).contains(id) {
-                None
-            }
-            else {
-                Some(HashMap::from_iter(vec![(*id, left.clone())]))
-            }
-        }
+    // types are interned, so identical types are already known to unify
+    if left == right {
+        return Ok(());
+    }
 
-        (Type::Prim(p1), Type::Prim(p2)) => {
-            if p1 == p2 {
-                Some(HashMap::new())
-            }
-            else {
-                None
-            }
+    match (types.get(left).clone(), types.get(right).clone()) {
+        (Type::TVar(id1), Type::TVar(id2)) => {
+            table.union(id1, id2);
+            Ok(())
         }
 
-        (Type::ADT(ty1, tparams1), Type::ADT(ty2, tparams2)) => {
-            if ty1 == ty2 {
-                let mut subs = HashMap::new();
-                for (tparam1, tparam2) in tparams1.iter().zip(tparams2) {
-                    let arg_subs = unify(&apply(&subs, *tparam1.clone()), &apply(&subs, *tparam2.clone()), info)?;
-                    subs.extend(arg_subs);
-                }
+        (Type::TVar(id), _) => bind_tvar(table, types, id, left, right),
+        (_, Type::TVar(id)) => bind_tvar(table, types, id, right, left),
 
-                Some(subs)
-            }
-            else {
-                None
+        // an error has already been reported for whatever has this type
+        (Type::Error, _) | (_, Type::Error) => Ok(()),
+
+        (Type::ADT(ty1, tparams1), Type::ADT(ty2, tparams2)) if ty1 == ty2 => {
+            for (tparam1, tparam2) in tparams1.iter().zip(tparams2) {
+                unify_types(table, types, *tparam1, tparam2)?;
             }
+            Ok(())
         }
+
         (Type::Func(args1, out1), Type::Func(args2, out2)) => {
             if args1.len() != args2.len() {
-                None
+                return Err(TypeError::ArityMismatch(table.resolve(types, left), table.resolve(types, right)));
             }
-            else {
-                let mut subs = HashMap::new();
-                for (arg1, arg2) in args1.iter().zip(args2) {
-                    let arg_subs = unify(&apply(&subs, *arg1.clone()), &apply(&subs, *arg2.clone()), info)?;
-                    subs.extend(arg_subs);
-                }
-
-                let out_subs = unify(&apply(&subs, *out1.clone()), &apply(&subs, *out2.clone()), info)?;
-                subs.extend(out_subs);
 
-                Some(subs)
+            for (arg1, arg2) in args1.iter().zip(args2) {
+                unify_types(table, types, *arg1, arg2)?;
             }
-
+            unify_types(table, types, out1, out2)
         }
 
-        _ => None
-    }.ok_or(SpruceErr {message: format!("Unification failed between {} and {}", left.as_str_debug(), right.as_str_debug()), info: info.clone()})
+        _ => Err(TypeError::Mismatch(table.resolve(types, left), table.resolve(types, right)))
+    }
+}
+
+/// Binds the unbound type variable `id`, whose type is `var`, to `ty`
+fn bind_tvar(table: &mut TVarTable, types: &mut TypeArena, id: TVarID, var: TypeId, ty: TypeId) -> Result<(), TypeError> {
+    if table.occurs(types, id, ty) {
+        Err(TypeError::OccursCheck(var, table.resolve(types, ty)))
+    }
+    else {
+        table.bind(types, id, ty);
+        Ok(())
+    }
 }
 
-fn tvars(ty: &Type) -> HashSet<TVarID> {
-    match ty {
-        Type::TVar(id) => HashSet::from_iter(vec![*id]),
-        Type::Unit => HashSet::new(),
+fn tvars(types: &TypeArena, ty: TypeId) -> HashSet<TVarID> {
+    match types.get(ty) {
+        Type::TVar(id) => vec![*id].into_iter().collect(),
+        Type::Unit | Type::Error => HashSet::new(),
         Type::Prim(_) => HashSet::new(),
         Type::ADT(_, tparams) => {
             let mut vars = HashSet::new();
             for p in tparams {
-                let param_vars = tvars(p);
+                let param_vars = tvars(types, *p);
                 vars.extend(param_vars);
             }
             vars
@@ -736,10 +2163,10 @@ fn tvars(ty: &Type) -> HashSet<TVarID> {
         Type::Func(args, out) => {
             let mut vars = HashSet::new();
             for arg in args {
-                let arg_vars = tvars(arg);
+                let arg_vars = tvars(types, *arg);
                 vars.extend(arg_vars);
             }
-            vars.extend(tvars(&out));
+            vars.extend(tvars(types, *out));
             vars
         }
     }
@@ -748,66 +2175,197 @@ fn tvars(ty: &Type) -> HashSet<TVarID> {
 
 #[test]
 fn func_tvars() {
-    let ty = Type::Func(vec![Box::from(Type::TVar(0))], Box::from(Type::TVar(1)));
-    let res = tvars(&ty);
+    let mut types = TypeArena::new();
+    let (a, b) = (types.tvar(0), types.tvar(1));
+    let ty = types.func(vec![a], b);
+    let res = tvars(&types, ty);
     assert_eq!(res.contains(&0), true);
     assert_eq!(res.contains(&1), true);
 }
 
+#[test]
+fn intern_types() {
+    let mut types = TypeArena::new();
+    assert_eq!(types.intern(Type::Prim(String::from("Int"))), INT_TYPE);
+    assert_eq!(types.intern(Type::Unit), UNIT_TYPE);
+
+    // building the same type twice gives the same id
+    let a = types.tvar(0);
+    let list_a = types.adt(2, vec![a]);
+    let fn_type = types.func(vec![list_a, INT_TYPE], a);
+    let a_again = types.tvar(0);
+    let list_again = types.adt(2, vec![a_again]);
+    assert_eq!(list_again, list_a);
+    assert_eq!(types.func(vec![list_again, INT_TYPE], a), fn_type);
+    assert_ne!(types.func(vec![INT_TYPE, list_a], a), fn_type);
+    assert_eq!(types.get(fn_type), &Type::Func(vec![list_a, INT_TYPE], a));
+}
+
 #[test]
 fn unify_prim() {
-    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: String::from("")};
+    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: NO_FILE};
+    let test_it = Registry::new();
+    let mut env = Environment::new(test_it);
 
-    let res = unify(&int_prim!(), &int_prim!(), &test_info);
+    let res = unify(&mut env, INT_TYPE, INT_TYPE, &test_info);
     assert_eq!(res.is_ok(), true);
 
-    let res = unify(&int_prim!(), &Type::Prim(String::from("Float")), &test_info);
+    let float = env.types.intern(Type::Prim(String::from("Float")));
+    let res = unify(&mut env, INT_TYPE, float, &test_info);
     assert_eq!(res.is_ok(), false);
+    assert_eq!(res.unwrap_err().type_error, Some(TypeError::Mismatch(INT_TYPE, float)));
 }
 
 #[test]
 fn unify_fn() {
-    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: String::from("")};
+    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: NO_FILE};
+    let test_it = Registry::new();
+    let mut env = Environment::new(test_it);
+    let bool_type = env.types.adt(0, vec![]);
+    let int_to_int = env.types.func(vec![INT_TYPE], INT_TYPE);
 
-    let res = unify(
-        &Type::Func(vec![Box::from(Type::TVar(0))], Box::from(Type::TVar(0))),
-        &Type::Func(vec![Box::from(int_prim!())], Box::from(int_prim!())),
-        &test_info
-    );
+    let a = env.new_tvar();
+    let a_to_a = env.types.func(vec![a], a);
+    let res = unify(&mut env, a_to_a, int_to_int, &test_info);
     assert_eq!(res.is_ok(), true);
-    assert_eq!(*res.expect("").get(&0).expect(""), int_prim!());
+    assert_eq!(env.resolve(a), INT_TYPE);
 
-    let res = unify(
-        &Type::Func(vec![Box::from(Type::TVar(0))], Box::from(Type::TVar(0))),
-        &Type::Func(vec![Box::from(int_prim!())], Box::from(Type::ADT(0, vec![]))),
-        &test_info
-    );
+    let b = env.new_tvar();
+    let b_to_b = env.types.func(vec![b], b);
+    let int_to_bool = env.types.func(vec![INT_TYPE], bool_type);
+    let res = unify(&mut env, b_to_b, int_to_bool, &test_info);
     assert_eq!(res.is_ok(), false);
+    // nothing is left bound by a failed unification
+    assert_eq!(env.resolve(b), b);
+
+    // failures are reported for the innermost types that disagree
+    let bool_to_int = env.types.func(vec![bool_type], INT_TYPE);
+    let res = unify_types(&mut env.tvars, &mut env.types, int_to_int, bool_to_int);
+    assert_eq!(res, Err(TypeError::Mismatch(INT_TYPE, bool_type)));
+
+    let two_args = env.types.func(vec![INT_TYPE, INT_TYPE], INT_TYPE);
+    assert_eq!(unify_types(&mut env.tvars, &mut env.types, int_to_int, two_args), Err(TypeError::ArityMismatch(int_to_int, two_args)));
+
+    let c = env.new_tvar();
+    let list_of_self = env.types.adt(2, vec![c]);
+    assert_eq!(unify_types(&mut env.tvars, &mut env.types, c, list_of_self), Err(TypeError::OccursCheck(c, list_of_self)));
+}
+
+// bindings are made in place, so a variable bound twice keeps what both
+// unifications learned, and types already bound to a variable see whatever
+// it's bound to later
+#[test]
+fn unify_refines_bindings() {
+    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: NO_FILE};
+    let test_it = Registry::new();
+    let mut env = Environment::new(test_it);
+
+    let (a, b, c) = (env.new_tvar(), env.new_tvar(), env.new_tvar());
+    let list_b = env.types.adt(2, vec![b]);
+    let list_int = env.types.adt(2, vec![INT_TYPE]);
+    unify(&mut env, a, list_b, &test_info).expect("");
+    unify(&mut env, a, list_int, &test_info).expect("");
+    assert_eq!(env.resolve(b), INT_TYPE);
+
+    let list_c = env.types.adt(2, vec![c]);
+    let d = env.new_tvar();
+    unify(&mut env, d, list_c, &test_info).expect("");
+    unify(&mut env, c, a, &test_info).expect("");
+    let list_list_int = env.types.adt(2, vec![list_int]);
+    assert_eq!(env.resolve(d), list_list_int);
+}
+
+#[test]
+fn union_find() {
+    let mut types = TypeArena::new();
+    let mut table = TVarTable::new();
+    let vars: Vec<TVarID> = (0..4).map(|_| table.new_var(1)).collect();
+    let outer = table.new_var(0);
+
+    // a chain of unified variables all end up in one class
+    for pair in vars.windows(2) {
+        table.union(pair[0], pair[1]);
+    }
+    let root = table.find(vars[0]);
+    assert_eq!(vars.iter().all(|var| table.find(*var) == root), true);
+    assert_eq!(vars.iter().all(|var| table.parent[*var as usize] == root), true);
+
+    // binding the class to a type containing a variable lowers the variable
+    // to the class's level, and joining with an outer variable lowers both
+    let inner = table.new_var(2);
+    let inner_type = types.tvar(inner);
+    let list_inner = types.adt(2, vec![inner_type]);
+    let (var_type, root_type) = (types.tvar(vars[1]), types.tvar(root));
+    table.snapshot();
+    table.bind(&types, vars[3], list_inner);
+    assert_eq!(table.level(inner), 1);
+    table.union(vars[0], outer);
+    assert_eq!(table.level(vars[2]), 0);
+    assert_eq!(table.resolve(&mut types, var_type), list_inner);
+
+    // everything since the snapshot can be undone
+    table.rollback();
+    assert_eq!(table.level(inner), 2);
+    assert_eq!(table.level(vars[2]), 1);
+    assert_eq!(table.resolve(&mut types, var_type), root_type);
+    assert_ne!(table.root(outer), root);
 }
 
 
 // verify that typecheck(Just(0), Maybe(Bool)) fails
 #[test]
 fn typecheck_adt() {
-    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: String::from("")};
-    let test_it = na::InternalTypes {bool_id: 0, maybe_id: 1, list_id: 2, cons_id: 0, nil_id: 1};
+    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: NO_FILE};
+    let test_it = Registry::new();
 
     let mut env = Environment::new(test_it);
-    env.val_type.insert(0, Type::Func(vec![Box::from(Type::TVar(0))], Box::from(Type::ADT(1, vec![Box::from(Type::TVar(0))]))));
+    let a = env.new_tvar();
+    let maybe_a = env.types.adt(1, vec![a]);
+    let just_type = env.types.func(vec![a], maybe_a);
+    env.val_type.insert(0, Scheme { vars: vec![0], ty: just_type });
     let expr = na::ExprNode {
         val: na::Expr::ADTVal(0, vec![
             Box::from(na::ExprNode {
-                val: na::Expr::Lit(0.0),
+                val: na::Expr::Lit(0),
                 info: test_info.clone()
             })
         ]),
         info: test_info.clone()
     };
 
-    let res = typecheck(
-        &mut env,
-        &expr,
-        &Type::ADT(1, vec![Box::from(Type::ADT(0, vec![]))]),
-    );
+    let bool_type = env.types.adt(0, vec![]);
+    let maybe_bool = env.types.adt(1, vec![bool_type]);
+    let res = typecheck(&mut env, &expr, maybe_bool);
     assert_eq!(res.is_err(), true);
 }
+
+#[test]
+fn generalize_levels() {
+    let test_info = NodeInfo {span: Span {start: 0, end: 0}, file: NO_FILE};
+    let test_it = Registry::new();
+    let mut env = Environment::new(test_it);
+
+    let outer = env.new_tvar();
+    env.enter_level();
+    let inner = env.new_tvar();
+    let bound = env.new_tvar();
+    // bound is now reachable from outer, so it can't be generalized
+    let list_bound = env.types.adt(2, vec![bound]);
+    unify(&mut env, outer, list_bound, &test_info).expect("");
+    env.exit_level();
+
+    let ty = env.types.func(vec![inner, outer], inner);
+    let scheme = env.generalize(ty);
+    assert_eq!(scheme.vars, vec![1]);
+
+    // each instance gets its own variables for the quantified ones only
+    let instance = env.instantiate(&scheme);
+    match env.types.get(instance).clone() {
+        Type::Func(args, out) => {
+            assert_eq!(args[0], out);
+            assert_ne!(args[0], inner);
+            assert_eq!(args[1], list_bound);
+        }
+        _ => panic!("instance is not a function")
+    }
+}
@@ -0,0 +1,710 @@
+/*
+The VM is a second way to run a checked program, which compiles it to
+bytecode first instead of walking the typed AST. Each function becomes a chunk
+of instructions for a stack machine, with the values of its arguments and
+variables in numbered slots at the bottom of its frame, and the constants the
+instructions load kept in one pool for the whole program. Calls between
+Spruce functions push a frame rather than recursing in Rust, so the depth of a
+program's recursion is only limited by memory.
+
+A case compiles to one dispatch instruction and a jump table, which goes
+straight to the option for the constructor or number it finds rather than
+trying each pattern in turn. Jumps that land on other jumps are threaded
+through to where they end up once a chunk is compiled.
+
+Values, and the builtins, are shared with the tree-walking interpreter, so a
+program gives the same results whichever engine runs it.
+*/
+
+use std::collections::HashMap;
+
+use crate::error::SpruceErr;
+use crate::eval::{self, Machine, Runtime, Value};
+use crate::heap;
+use crate::name_analysis as na;
+use crate::parser::NodeInfo;
+use crate::profile::Profiler;
+use crate::registry::LangValue;
+use crate::trace::Phase;
+use crate::typecheck::{self, BodyNode, CaseBody, CaseNode, Expr, ExprNode, Stmt, StmtNode};
+
+/// Instructions have operands of at most five bytes, so each fits in eight
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Op {
+    // pushes a value from the constant pool
+    Const(u32),
+    Load(u16),
+    Store(u16),
+    LoadGlobal(u32),
+    StoreGlobal(u32),
+    Dup,
+    Pop,
+    Add,
+    Subt,
+    Mult,
+    Div,
+    Mod,
+    Pow,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
+    Neg,
+    Eq,
+    NotEq,
+    Lt,
+    Gt,
+    LtEq,
+    GtEq,
+    // pops the second function and then the first
+    Compose,
+    // pops the arguments of a constructor and pushes its value
+    MakeADT(na::ADTValID, u8),
+    // pops elements and pushes a list of them
+    MakeList(u32),
+    // replaces the value of a constructor with its arguments
+    Unpack(u8),
+    Call(u32, u8),
+    CallBuiltin(u32, u8),
+    // calls the function on top of the stack, with the arguments under it
+    CallValue(u8),
+    // jumps by the value on top of the stack, using a jump table, and leaves
+    // it there
+    Switch(u32),
+    Jump(u32),
+    Return,
+    // something that only an interface declares, or that has no value yet
+    Missing(na::SymbolID)
+}
+
+/// Where a case goes for each constructor or number, and for anything else
+#[derive(Debug, PartialEq, Clone)]
+pub struct JumpTable {
+    constructors: HashMap<na::ADTValID, u32>,
+    numbers: HashMap<i64, u32>,
+    default: Option<u32>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Chunk {
+    name: String,
+    arity: u8,
+    locals: u16,
+    code: Vec<Op>,
+    // the source of each instruction, for errors while running it
+    infos: Vec<NodeInfo>
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Bytecode<'a> {
+    chunks: Vec<Chunk>,
+    // evaluates the program's definitions, and is the last chunk
+    init: u32,
+    constants: Vec<Value>,
+    tables: Vec<JumpTable>,
+    builtins: Vec<&'a str>,
+    // the symbol each global is, for errors
+    globals: Vec<na::SymbolID>,
+    // the function each chunk is, other than init
+    ids: Vec<na::SymbolID>,
+    functions: HashMap<na::SymbolID, u32>,
+    builtin_ids: HashMap<na::SymbolID, u32>
+}
+
+struct Compiler<'a> {
+    code: Bytecode<'a>,
+    ints: HashMap<i64, u32>,
+    funcs: HashMap<na::SymbolID, u32>,
+    global_ids: HashMap<na::SymbolID, u32>,
+    chunk: Chunk,
+    locals: HashMap<na::SymbolID, u16>
+}
+
+/// Compiles a checked program to bytecode
+pub fn compile<'a>(prog: &'a na::Prog, typed: &typecheck::Prog) -> Result<Bytecode<'a>, SpruceErr> {
+    let builtins = eval::builtins(prog);
+    let mut builtin_names: Vec<(&na::SymbolID, &&str)> = builtins.iter().collect();
+    builtin_names.sort();
+
+    let mut globals = Vec::new();
+    for def in &typed.definitions {
+        if let Stmt::Assign(tgt, _) = &def.val {
+            globals.push(tgt.val.id());
+        }
+    }
+
+    let mut compiler = Compiler {
+        code: Bytecode {
+            chunks: Vec::new(),
+            init: typed.functions.len() as u32,
+            constants: Vec::new(),
+            tables: Vec::new(),
+            builtins: builtin_names.iter().map(|(_, name)| **name).collect(),
+            globals: globals.clone(),
+            ids: typed.functions.iter().map(|func| func.val.name).collect(),
+            functions: typed.functions.iter().enumerate().map(|(i, func)| (func.val.name, i as u32)).collect(),
+            builtin_ids: builtin_names.iter().enumerate().map(|(i, (id, _))| (**id, i as u32)).collect()
+        },
+        ints: HashMap::new(),
+        funcs: HashMap::new(),
+        global_ids: globals.iter().enumerate().map(|(i, id)| (*id, i as u32)).collect(),
+        chunk: Chunk { name: String::new(), arity: 0, locals: 0, code: Vec::new(), infos: Vec::new() },
+        locals: HashMap::new()
+    };
+
+    for func in &typed.functions {
+        compiler.start_chunk(eval::symbol_name(prog, func.val.name), &func.val.args);
+        compiler.compile_body(&func.val.body)?;
+        compiler.emit(Op::Return, &func.info);
+        compiler.finish_chunk();
+    }
+
+    compiler.start_chunk("<init>", &[]);
+    for def in &typed.definitions {
+        compiler.compile_stmt(def)?;
+        compiler.emit(Op::Pop, &def.info);
+    }
+    let unit = compiler.constant(Value::Unit);
+    let info = typed.definitions.last().map_or(eval::no_info(), |def| def.info.clone());
+    compiler.emit(Op::Const(unit), &info);
+    compiler.emit(Op::Return, &info);
+    compiler.finish_chunk();
+
+    trace!(Phase::Vm, "{:#?}", compiler.code);
+    Ok(compiler.code)
+}
+
+impl<'a> Compiler<'a> {
+    fn start_chunk(&mut self, name: &str, args: &[na::SymbolID]) {
+        self.chunk = Chunk { name: String::from(name), arity: args.len() as u8, locals: 0, code: Vec::new(), infos: Vec::new() };
+        self.locals = HashMap::new();
+        for arg in args {
+            self.local(*arg);
+        }
+    }
+
+    fn finish_chunk(&mut self) {
+        let mut chunk = std::mem::replace(&mut self.chunk, Chunk { name: String::new(), arity: 0, locals: 0, code: Vec::new(), infos: Vec::new() });
+        thread_jumps(&mut chunk, &mut self.code.tables);
+        self.code.chunks.push(chunk);
+    }
+
+    /// The slot of a variable, which is given one the first time it's seen
+    fn local(&mut self, id: na::SymbolID) -> u16 {
+        let next = self.locals.len() as u16;
+        let slot = *self.locals.entry(id).or_insert(next);
+        self.chunk.locals = self.chunk.locals.max(slot + 1);
+        slot
+    }
+
+    fn emit(&mut self, op: Op, info: &NodeInfo) -> usize {
+        self.chunk.code.push(op);
+        self.chunk.infos.push(info.clone());
+        self.chunk.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.chunk.code.len() as u32
+    }
+
+    fn constant(&mut self, val: Value) -> u32 {
+        // ints and functions are used over and over, so they are only
+        // pooled once
+        let next = self.code.constants.len() as u32;
+        let index = match &val {
+            Value::Int(n) => *self.ints.entry(*n).or_insert(next),
+            Value::Func(id) => *self.funcs.entry(*id).or_insert(next),
+            _ => next
+        };
+        if index == next {
+            self.code.constants.push(val);
+        }
+        index
+    }
+
+    fn compile_body(&mut self, body: &BodyNode) -> Result<(), SpruceErr> {
+        for (i, stmt) in body.val.stmts.iter().enumerate() {
+            if i > 0 {
+                self.emit(Op::Pop, &stmt.info);
+            }
+            self.compile_stmt(stmt)?;
+        }
+        match (&body.val.expr, body.val.stmts.is_empty()) {
+            (Some(expr), empty) => {
+                if !empty {
+                    self.emit(Op::Pop, &expr.info);
+                }
+                self.compile_expr(expr)
+            }
+            (None, false) => Ok(()),
+            (None, true) => {
+                let unit = self.constant(Value::Unit);
+                self.emit(Op::Const(unit), &body.info);
+                Ok(())
+            }
+        }
+    }
+
+    /// Every statement leaves its value on the stack
+    fn compile_stmt(&mut self, stmt: &StmtNode) -> Result<(), SpruceErr> {
+        match &stmt.val {
+            Stmt::Assign(tgt, expr) => {
+                self.compile_expr(expr)?;
+                self.emit(Op::Dup, &stmt.info);
+                let id = tgt.val.id();
+                // updates can reach program-level `mut` definitions, and
+                // definitions themselves are globals
+                match self.global_ids.get(&id) {
+                    Some(global) if !self.locals.contains_key(&id) => self.emit(Op::StoreGlobal(*global), &stmt.info),
+                    _ => {
+                        let slot = self.local(id);
+                        self.emit(Op::Store(slot), &stmt.info)
+                    }
+                };
+                Ok(())
+            }
+            Stmt::FnCall(id, args) => {
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.compile_call(*id, args.len(), &stmt.info);
+                Ok(())
+            }
+            Stmt::Case(case) => self.compile_case(case)
+        }
+    }
+
+    fn compile_case(&mut self, case: &CaseNode) -> Result<(), SpruceErr> {
+        self.compile_expr(&case.val.expr)?;
+        let table = self.code.tables.len() as u32;
+        self.code.tables.push(JumpTable { constructors: HashMap::new(), numbers: HashMap::new(), default: None });
+        self.emit(Op::Switch(table), &case.val.expr.info);
+
+        let mut exits = Vec::new();
+        for opt in &case.val.options {
+            let target = self.here();
+            let entry = &mut self.code.tables[table as usize];
+            // once a pattern matches anything, the options after it can't
+            // be reached, and otherwise the first option for a value wins
+            if entry.default.is_some() {
+                break;
+            }
+            match &opt.val.pattern.val {
+                na::CasePattern::ADT(id, _) => { entry.constructors.entry(*id).or_insert(target); }
+                na::CasePattern::Lit(lit) => { entry.numbers.entry(*lit).or_insert(target); }
+                na::CasePattern::Any => entry.default = Some(target)
+            }
+
+            match &opt.val.pattern.val {
+                na::CasePattern::ADT(_, names) => {
+                    self.emit(Op::Unpack(names.len() as u8), &opt.info);
+                    for name in names.iter().rev() {
+                        let slot = self.local(*name);
+                        self.emit(Op::Store(slot), &opt.info);
+                    }
+                }
+                _ => { self.emit(Op::Pop, &opt.info); }
+            }
+
+            match &opt.val.body.val {
+                CaseBody::Expr(expr) => self.compile_expr(expr)?,
+                CaseBody::Body(body) => self.compile_body(body)?
+            }
+            exits.push(self.emit(Op::Jump(0), &opt.info));
+        }
+
+        let end = self.here();
+        for exit in exits {
+            self.chunk.code[exit] = Op::Jump(end);
+        }
+        Ok(())
+    }
+
+    /// Calls what `id` names, with its arguments already on the stack
+    fn compile_call(&mut self, id: na::SymbolID, argc: usize, info: &NodeInfo) {
+        let argc = argc as u8;
+        if self.locals.contains_key(&id) || self.global_ids.contains_key(&id) {
+            self.compile_load(id, info);
+            self.emit(Op::CallValue(argc), info);
+        }
+        else if let Some(func) = self.code.functions.get(&id).copied() {
+            self.emit(Op::Call(func, argc), info);
+        }
+        else if let Some(builtin) = self.code.builtin_ids.get(&id).copied() {
+            self.emit(Op::CallBuiltin(builtin, argc), info);
+        }
+        else {
+            self.emit(Op::Missing(id), info);
+        }
+    }
+
+    fn compile_load(&mut self, id: na::SymbolID, info: &NodeInfo) {
+        if let Some(slot) = self.locals.get(&id).copied() {
+            self.emit(Op::Load(slot), info);
+        }
+        else if let Some(global) = self.global_ids.get(&id).copied() {
+            self.emit(Op::LoadGlobal(global), info);
+        }
+        else if self.code.functions.contains_key(&id) || self.code.builtin_ids.contains_key(&id) {
+            let func = self.constant(Value::Func(id));
+            self.emit(Op::Const(func), info);
+        }
+        else {
+            self.emit(Op::Missing(id), info);
+        }
+    }
+
+    fn compile_expr(&mut self, expr: &ExprNode) -> Result<(), SpruceErr> {
+        let info = &expr.info;
+        let op = match &expr.val {
+            Expr::Add(_, _) => Op::Add,
+            Expr::Subt(_, _) => Op::Subt,
+            Expr::Mult(_, _) => Op::Mult,
+            Expr::Div(_, _) => Op::Div,
+            Expr::Mod(_, _) => Op::Mod,
+            Expr::Pow(_, _) => Op::Pow,
+            Expr::BitAnd(_, _) => Op::BitAnd,
+            Expr::BitOr(_, _) => Op::BitOr,
+            Expr::BitXor(_, _) => Op::BitXor,
+            Expr::Shl(_, _) => Op::Shl,
+            Expr::Shr(_, _) => Op::Shr,
+            Expr::Eq(_, _) => Op::Eq,
+            Expr::NotEq(_, _) => Op::NotEq,
+            Expr::Lt(_, _) => Op::Lt,
+            Expr::Gt(_, _) => Op::Gt,
+            Expr::LtEq(_, _) => Op::LtEq,
+            Expr::GtEq(_, _) => Op::GtEq,
+            Expr::Neg(_) => Op::Neg,
+            Expr::ComposeR(first, second) | Expr::ComposeL(second, first) => {
+                self.compile_expr(first)?;
+                self.compile_expr(second)?;
+                self.emit(Op::Compose, info);
+                return Ok(());
+            }
+            Expr::Lit(lit) => {
+                let lit = self.constant(Value::Int(*lit));
+                self.emit(Op::Const(lit), info);
+                return Ok(());
+            }
+            Expr::List(elements) => Op::MakeList(elements.len() as u32),
+            Expr::Id(id) => {
+                self.compile_load(*id, info);
+                return Ok(());
+            }
+            Expr::FnCall(id, args) => {
+                for arg in args {
+                    self.compile_expr(arg)?;
+                }
+                self.compile_call(*id, args.len(), info);
+                return Ok(());
+            }
+            Expr::ADTVal(id, args) => Op::MakeADT(*id, args.len() as u8),
+            Expr::Error => return Err(SpruceErr::ice(Phase::Vm, String::from("compiled an expression that failed to typecheck"), info.clone()))
+        };
+
+        // the rest push their operands in order, then combine them
+        for child in expr.val.children() {
+            self.compile_expr(child)?;
+        }
+        self.emit(op, info);
+        Ok(())
+    }
+}
+
+/// Points jumps, and the targets of jump tables, at where they end up rather
+/// than at other jumps
+fn thread_jumps(chunk: &mut Chunk, tables: &mut [JumpTable]) {
+    let code = chunk.code.clone();
+    let follow = |mut target: u32| {
+        // a chain of jumps can't loop, since they only go forward
+        while let Some(Op::Jump(next)) = code.get(target as usize) {
+            target = *next;
+        }
+        target
+    };
+
+    for op in chunk.code.iter_mut() {
+        match op {
+            Op::Jump(target) => match code.get(follow(*target) as usize) {
+                Some(Op::Return) => *op = Op::Return,
+                _ => *target = follow(*target)
+            },
+            Op::Switch(table) => {
+                let table = &mut tables[*table as usize];
+                for target in table.constructors.values_mut().chain(table.numbers.values_mut()).chain(table.default.iter_mut()) {
+                    *target = follow(*target);
+                }
+            }
+            _ => ()
+        }
+    }
+}
+
+struct Frame {
+    chunk: u32,
+    pc: usize,
+    // where the frame's slots start on the stack
+    base: usize
+}
+
+struct VM<'a> {
+    prog: &'a na::Prog,
+    code: &'a Bytecode<'a>,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    globals: Vec<Option<Value>>,
+    runtime: Runtime,
+    true_val: Value,
+    false_val: Value,
+    profiler: Option<&'a mut Profiler>
+}
+
+/// Runs the function named `entry` on the VM, as `eval::run_prog` does with
+/// the tree-walker
+pub fn run_prog(prog: &na::Prog, typed: &typecheck::Prog, entry: &str, runtime: Runtime) -> Result<Value, SpruceErr> {
+    run_with_profiler(prog, typed, entry, runtime, None)
+}
+
+/// Runs the program as `run_prog` does, timing its calls with `profiler`
+pub fn run_profiled(prog: &na::Prog, typed: &typecheck::Prog, entry: &str, runtime: Runtime, profiler: &mut Profiler) -> Result<Value, SpruceErr> {
+    run_with_profiler(prog, typed, entry, runtime, Some(profiler))
+}
+
+fn run_with_profiler(prog: &na::Prog, typed: &typecheck::Prog, entry: &str, runtime: Runtime, profiler: Option<&mut Profiler>) -> Result<Value, SpruceErr> {
+    let code = compile(prog, typed)?;
+    let func = eval::entry_point(prog, typed, entry)?;
+    let mut vm = VM {
+        prog: prog,
+        code: &code,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        globals: vec![None; code.globals.len()],
+        runtime: runtime,
+        true_val: eval::constructor(prog, LangValue::True, Vec::new()),
+        false_val: eval::constructor(prog, LangValue::False, Vec::new()),
+        profiler: profiler
+    };
+
+    vm.push_frame(code.init, 0);
+    vm.run(0).map_err(|err| vm.trace(err))?;
+    vm.push_frame(code.functions[&func.val.name], 0);
+    vm.run(0).map_err(|err| vm.trace(err))
+}
+
+impl<'a> Machine<'a> for VM<'a> {
+    fn prog(&self) -> &'a na::Prog {
+        self.prog
+    }
+
+    fn runtime(&mut self) -> &mut Runtime {
+        &mut self.runtime
+    }
+
+    fn apply(&mut self, func: &Value, args: Vec<Value>, info: &NodeInfo) -> Result<Value, SpruceErr> {
+        match func {
+            Value::Func(id) => {
+                if let Some(chunk) = self.code.functions.get(id).copied() {
+                    let depth = self.frames.len();
+                    let argc = args.len();
+                    self.stack.extend(args);
+                    self.push_frame(chunk, argc);
+                    self.run(depth)
+                }
+                else if let Some(builtin) = self.code.builtin_ids.get(id).copied() {
+                    let name = self.code.builtins[builtin as usize];
+                    eval::call_builtin(self, name, args, info)
+                }
+                else {
+                    Err(eval::missing(self.prog, *id, info))
+                }
+            }
+            Value::Composed(funcs) => {
+                let result = self.apply(&funcs.0, args, info)?;
+                self.apply(&funcs.1, vec![result], info)
+            }
+            _ => Err(SpruceErr::ice(Phase::Vm, format!("called {}, which isn't a function", eval::show(self.prog, func)), info.clone()))
+        }
+    }
+}
+
+impl<'a> VM<'a> {
+    /// Gives `err` the calls the VM was making when it happened, which an
+    /// error leaves in place, as the tree-walker would
+    fn trace(&self, err: SpruceErr) -> SpruceErr {
+        if !err.trace.is_empty() || !err.is_error() {
+            return err;
+        }
+        let trace = self.frames.iter().rev().filter(|frame| frame.chunk != self.code.init).enumerate().map(|(i, frame)| {
+            let chunk = &self.code.chunks[frame.chunk as usize];
+            // each frame but the innermost has gone past the call it's making
+            let at = if i == 0 { err.info.clone() } else { chunk.infos[frame.pc - 1].clone() };
+            (chunk.name.clone(), at)
+        }).collect();
+        err.with_trace(trace)
+    }
+
+    /// Starts a call to a chunk whose arguments are on top of the stack
+    fn push_frame(&mut self, chunk: u32, argc: usize) {
+        let base = self.stack.len() - argc;
+        trace!(Phase::Vm, "calling {}", self.code.chunks[chunk as usize].name);
+        self.stack.resize(base + self.code.chunks[chunk as usize].locals as usize, Value::Unit);
+        self.frames.push(Frame { chunk: chunk, pc: 0, base: base });
+        if let (Some(profiler), Some(id)) = (self.profiler.as_mut(), self.code.ids.get(chunk as usize)) {
+            profiler.start_call(*id);
+        }
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("the stack is never empty when popped")
+    }
+
+    fn pop_args(&mut self, argc: u8) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - argc as usize)
+    }
+
+    fn pop_int(&mut self, info: &NodeInfo) -> Result<i64, SpruceErr> {
+        match self.pop() {
+            Value::Int(n) => Ok(n),
+            val => Err(SpruceErr::ice(Phase::Vm, format!("expected an Int, found {}", eval::show(self.prog, &val)), info.clone()))
+        }
+    }
+
+    fn bool(&self, b: bool) -> Value {
+        if b { self.true_val.clone() } else { self.false_val.clone() }
+    }
+
+    /// Runs until the frame at `depth` returns, giving its value
+    fn run(&mut self, depth: usize) -> Result<Value, SpruceErr> {
+        let code = self.code;
+        loop {
+            let frame = self.frames.last_mut().expect("the VM always has a frame while running");
+            let chunk = &code.chunks[frame.chunk as usize];
+            let op = chunk.code[frame.pc];
+            let info = &chunk.infos[frame.pc];
+            let base = frame.base;
+            frame.pc += 1;
+
+            match op {
+                Op::Const(i) => self.stack.push(code.constants[i as usize].clone()),
+                Op::Load(slot) => {
+                    let val = self.stack[base + slot as usize].clone();
+                    self.stack.push(val);
+                }
+                Op::Store(slot) => self.stack[base + slot as usize] = self.pop(),
+                Op::LoadGlobal(i) => match &self.globals[i as usize] {
+                    Some(val) => self.stack.push(val.clone()),
+                    None => return Err(eval::missing(self.prog, code.globals[i as usize], info))
+                },
+                Op::StoreGlobal(i) => self.globals[i as usize] = Some(self.pop()),
+                Op::Dup => {
+                    let val = self.stack.last().expect("the stack is never empty when duplicated").clone();
+                    self.stack.push(val);
+                }
+                Op::Pop => { self.pop(); }
+                Op::Add | Op::Subt | Op::Mult | Op::Div | Op::Mod | Op::Pow | Op::BitAnd | Op::BitOr | Op::BitXor |
+                Op::Shl | Op::Shr | Op::Lt | Op::Gt | Op::LtEq | Op::GtEq => {
+                    let right = self.pop_int(info)?;
+                    let left = self.pop_int(info)?;
+                    let val = match op {
+                        Op::Add => Value::Int(left.wrapping_add(right)),
+                        Op::Subt => Value::Int(left.wrapping_sub(right)),
+                        Op::Mult => Value::Int(left.wrapping_mul(right)),
+                        Op::Div | Op::Mod if right == 0 => return Err(SpruceErr::new(String::from("division by zero"), info.clone())),
+                        Op::Div => Value::Int(left.wrapping_div(right)),
+                        Op::Mod => Value::Int(left.wrapping_rem(right)),
+                        Op::Pow if right < 0 => {
+                            return Err(SpruceErr::new(format!("cannot raise an Int to the negative power {}", right), info.clone()));
+                        }
+                        Op::Pow => Value::Int(left.wrapping_pow(right.min(u32::MAX as i64) as u32)),
+                        Op::BitAnd => Value::Int(left & right),
+                        Op::BitOr => Value::Int(left | right),
+                        Op::BitXor => Value::Int(left ^ right),
+                        Op::Shl => Value::Int(left.wrapping_shl(right as u32)),
+                        Op::Shr => Value::Int(left.wrapping_shr(right as u32)),
+                        Op::Lt => self.bool(left < right),
+                        Op::Gt => self.bool(left > right),
+                        Op::LtEq => self.bool(left <= right),
+                        _ => self.bool(left >= right)
+                    };
+                    self.stack.push(val);
+                }
+                Op::Neg => {
+                    let n = self.pop_int(info)?;
+                    self.stack.push(Value::Int(n.wrapping_neg()));
+                }
+                Op::Eq | Op::NotEq => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let equal = eval::compare(self.prog, &left, &right) == std::cmp::Ordering::Equal;
+                    let val = self.bool(equal == (op == Op::Eq));
+                    self.stack.push(val);
+                }
+                Op::Compose => {
+                    let second = self.pop();
+                    let first = self.pop();
+                    self.stack.push(Value::Composed(heap::alloc((first, second))));
+                    heap::check(info)?;
+                }
+                Op::MakeADT(id, argc) => {
+                    let args = self.pop_args(argc);
+                    self.stack.push(Value::ADT(id, heap::alloc(args)));
+                    heap::check(info)?;
+                }
+                Op::MakeList(count) => {
+                    let elements = self.stack.split_off(self.stack.len() - count as usize);
+                    self.stack.push(eval::list(self.prog, elements));
+                    heap::check(info)?;
+                }
+                Op::Unpack(argc) => match self.pop() {
+                    Value::ADT(_, args) if args.len() == argc as usize => self.stack.extend(args.iter().cloned()),
+                    val => return Err(SpruceErr::ice(Phase::Vm, format!("cannot unpack {}", eval::show(self.prog, &val)), info.clone()))
+                },
+                Op::Call(chunk, argc) => self.push_frame(chunk, argc as usize),
+                Op::CallBuiltin(builtin, argc) => {
+                    let args = self.pop_args(argc);
+                    let val = eval::call_builtin(self, code.builtins[builtin as usize], args, info)?;
+                    self.stack.push(val);
+                }
+                Op::CallValue(argc) => match self.pop() {
+                    // calls to Spruce functions stay in this loop
+                    Value::Func(id) if code.functions.contains_key(&id) => self.push_frame(code.functions[&id], argc as usize),
+                    func => {
+                        let args = self.pop_args(argc);
+                        let val = self.apply(&func, args, info)?;
+                        self.stack.push(val);
+                    }
+                },
+                Op::Switch(table) => {
+                    let table = &code.tables[table as usize];
+                    let target = match self.stack.last() {
+                        Some(Value::ADT(id, _)) => table.constructors.get(id),
+                        Some(Value::Int(n)) => table.numbers.get(n),
+                        _ => None
+                    };
+                    match target.or(table.default.as_ref()) {
+                        Some(target) => self.frames.last_mut().expect("the VM always has a frame while running").pc = *target as usize,
+                        None => {
+                            let val = self.pop();
+                            return Err(SpruceErr::new(format!("no option matches {}", eval::show(self.prog, &val)), info.clone()));
+                        }
+                    }
+                }
+                Op::Jump(target) => self.frames.last_mut().expect("the VM always has a frame while running").pc = target as usize,
+                Op::Return => {
+                    let val = self.pop();
+                    let frame = self.frames.pop().expect("the VM always has a frame while running");
+                    self.stack.truncate(frame.base);
+                    if let Some(profiler) = self.profiler.as_mut().filter(|_| frame.chunk != code.init) {
+                        profiler.end_call();
+                    }
+                    if self.frames.len() == depth {
+                        return Ok(val);
+                    }
+                    self.stack.push(val);
+                }
+                Op::Missing(id) => return Err(eval::missing(self.prog, id, info))
+            }
+        }
+    }
+}
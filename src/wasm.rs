@@ -0,0 +1,1013 @@
+/*
+The wasm target encodes a checked program as a WebAssembly module directly,
+without going through another compiler. Values are 64-bit words laid out as
+the native target lays them out, so Ints are 63 bits wide here too, with the
+same wrapping and the same limit on literals: an Int n is 2n + 1, Unit is 0,
+and anything else is the address of an object in linear memory, made of its
+kind, its tag, its field count and then its fields. Strings keep their length where the field count would be, followed
+by their bytes, and Floats keep their bits as their one field.
+
+The module carries its own runtime, a bump allocator that grows memory as it
+needs to along with show, comparison and the builtins there are wasm versions
+of, so the only things it needs from its host are what it can't do inside the
+sandbox: writing bytes out, through `spruce.write(fd, ptr, len)`, stopping,
+through `spruce.exit(code)`, the trigonometry wasm has no instructions for,
+and writing a Float out with as few digits as it takes, through
+`spruce.showFloat(x, ptr)`. It exports its memory and `_start`, which
+evaluates the definitions, runs main and writes out its result. The host in
+wasm_host.mjs provides all of these, in node or a browser.
+*/
+
+use std::collections::HashMap;
+
+use crate::anf::{self, Atom, Prim, Value};
+use crate::error::SpruceErr;
+use crate::name_analysis as na;
+use crate::native;
+use crate::parser::NodeInfo;
+use crate::registry::LangValue;
+use crate::source::SourceMap;
+
+pub const HOST: &str = include_str!("wasm_host.mjs");
+
+/// What the host is saved as, next to the module
+pub const HOST_FILE: &str = "spruce-wasm-host.mjs";
+
+// the kinds of object
+const ADT: i64 = 0;
+const FUNC: i64 = 1;
+const COMPOSE: i64 = 2;
+const STR: i64 = 3;
+const FLOAT: i64 = 4;
+
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+const F64: u8 = 0x7c;
+const EMPTY: u8 = 0x40;
+
+// where constructor names are found, as an address and a length each
+const NAMES: u32 = 8;
+
+// the host's functions come first, then the runtime's, in this order
+const WRITE: u32 = 0;
+const EXIT: u32 = 1;
+const HOST_SIN: u32 = 2;
+const HOST_COS: u32 = 3;
+const HOST_TAN: u32 = 4;
+const SHOW_FLOAT: u32 = 5;
+const RESERVE: u32 = 6;
+const ALLOC: u32 = 7;
+const BOOL: u32 = 8;
+const MAKE_FUNC: u32 = 9;
+const MAKE_COMPOSE: u32 = 10;
+const MAKE_FLOAT: u32 = 11;
+const PUT: u32 = 12;
+const PUT_INT: u32 = 13;
+const SHOW_INTO: u32 = 14;
+const SHOW: u32 = 15;
+const BYTES_COMPARE: u32 = 16;
+const COMPARE: u32 = 17;
+const FAIL: u32 = 18;
+const POW: u32 = 19;
+const PRINT: u32 = 20;
+const PRINT_LINE: u32 = 21;
+const DEBUG: u32 = 22;
+const ABS: u32 = 23;
+const MIN: u32 = 24;
+const MAX: u32 = 25;
+const COMPARE_BUILTIN: u32 = 26;
+const TO_FLOAT: u32 = 27;
+const SQRT: u32 = 28;
+const FLOOR: u32 = 29;
+const CEIL: u32 = 30;
+const SIN: u32 = 31;
+const COS: u32 = 32;
+const TAN: u32 = 33;
+const FIRST_APPLY: u32 = 34;
+
+// the most entries a case's branch table is given before it tests the arms in
+// turn instead
+const MAX_TABLE: u32 = 64;
+
+/// An Int as it's stored
+fn tagged(n: i64) -> i64 {
+    n.wrapping_shl(1) | 1
+}
+
+/// The builtins the runtime has wasm versions of
+const WASM_BUILTINS: [(&str, u32); 15] = [
+    ("show", SHOW), ("compare", COMPARE_BUILTIN), ("print", PRINT), ("printLine", PRINT_LINE), ("debug", DEBUG), ("abs", ABS), ("min", MIN), ("max", MAX),
+    ("toFloat", TO_FLOAT), ("sqrt", SQRT), ("floor", FLOOR), ("ceil", CEIL), ("sin", SIN), ("cos", COS), ("tan", TAN)
+];
+
+fn uleb(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0) {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn name(out: &mut Vec<u8>, text: &str) {
+    uleb(out, text.len() as u64);
+    out.extend_from_slice(text.as_bytes());
+}
+
+fn section(out: &mut Vec<u8>, id: u8, items: Vec<Vec<u8>>) {
+    let mut body = Vec::new();
+    uleb(&mut body, items.len() as u64);
+    for item in items {
+        body.extend(item);
+    }
+    out.push(id);
+    uleb(out, body.len() as u64);
+    out.extend(body);
+}
+
+/// A function's body as it's written, one instruction at a time
+struct Code {
+    params: u32,
+    locals: Vec<u8>,
+    bytes: Vec<u8>,
+    // how many blocks the next instruction is inside
+    depth: u32
+}
+
+impl Code {
+    fn new(params: u32) -> Self {
+        Code { params: params, locals: Vec::new(), bytes: Vec::new(), depth: 0 }
+    }
+
+    fn local(&mut self, ty: u8) -> u32 {
+        self.locals.push(ty);
+        self.params + self.locals.len() as u32 - 1
+    }
+
+    fn op(&mut self, op: u8) -> &mut Self {
+        self.bytes.push(op);
+        self
+    }
+
+    fn with(&mut self, op: u8, index: u32) -> &mut Self {
+        self.bytes.push(op);
+        uleb(&mut self.bytes, index as u64);
+        self
+    }
+
+    fn get(&mut self, local: u32) -> &mut Self { self.with(0x20, local) }
+    fn set(&mut self, local: u32) -> &mut Self { self.with(0x21, local) }
+    fn tee(&mut self, local: u32) -> &mut Self { self.with(0x22, local) }
+    fn global_get(&mut self, global: u32) -> &mut Self { self.with(0x23, global) }
+    fn global_set(&mut self, global: u32) -> &mut Self { self.with(0x24, global) }
+    fn call(&mut self, func: u32) -> &mut Self { self.with(0x10, func) }
+    fn br(&mut self, depth: u32) -> &mut Self { self.with(0x0c, depth) }
+    fn br_if(&mut self, depth: u32) -> &mut Self { self.with(0x0d, depth) }
+
+    fn br_table(&mut self, depths: &[u32], default: u32) -> &mut Self {
+        self.bytes.push(0x0e);
+        uleb(&mut self.bytes, depths.len() as u64);
+        for depth in depths {
+            uleb(&mut self.bytes, *depth as u64);
+        }
+        uleb(&mut self.bytes, default as u64);
+        self
+    }
+    fn block(&mut self) -> &mut Self { self.open(0x02, EMPTY) }
+    fn looped(&mut self) -> &mut Self { self.open(0x03, EMPTY) }
+    fn if_(&mut self) -> &mut Self { self.open(0x04, EMPTY) }
+
+    fn open(&mut self, op: u8, ty: u8) -> &mut Self {
+        self.depth += 1;
+        self.op(op).op(ty)
+    }
+
+    fn end(&mut self) -> &mut Self {
+        self.depth -= 1;
+        self.op(0x0b)
+    }
+    fn ret(&mut self) -> &mut Self { self.op(0x0f) }
+    fn unreachable(&mut self) -> &mut Self { self.op(0x00) }
+    fn drop_(&mut self) -> &mut Self { self.op(0x1a) }
+    fn select(&mut self) -> &mut Self { self.op(0x1b) }
+
+    fn i32(&mut self, n: i32) -> &mut Self {
+        self.bytes.push(0x41);
+        sleb(&mut self.bytes, n as i64);
+        self
+    }
+
+    fn i64(&mut self, n: i64) -> &mut Self {
+        self.bytes.push(0x42);
+        sleb(&mut self.bytes, n);
+        self
+    }
+
+    fn memory(&mut self, op: u8, align: u32, offset: u32) -> &mut Self {
+        self.bytes.push(op);
+        uleb(&mut self.bytes, align as u64);
+        uleb(&mut self.bytes, offset as u64);
+        self
+    }
+
+    /// Loads the word at `offset` of the object whose address, as an i64,
+    /// is on the stack
+    fn word(&mut self, offset: u32) -> &mut Self { self.op(0xa7).memory(0x29, 3, offset) }
+    fn load(&mut self, offset: u32) -> &mut Self { self.memory(0x29, 3, offset) }
+    fn store(&mut self, offset: u32) -> &mut Self { self.memory(0x37, 3, offset) }
+    fn load32(&mut self, offset: u32) -> &mut Self { self.memory(0x28, 2, offset) }
+    fn load8(&mut self, offset: u32) -> &mut Self { self.memory(0x2d, 0, offset) }
+    fn load_float(&mut self, offset: u32) -> &mut Self { self.memory(0x2b, 3, offset) }
+    fn store_float(&mut self, offset: u32) -> &mut Self { self.memory(0x39, 3, offset) }
+    fn store8(&mut self, offset: u32) -> &mut Self { self.memory(0x3a, 0, offset) }
+
+    fn wrap(&mut self) -> &mut Self { self.op(0xa7) }
+    fn extend(&mut self) -> &mut Self { self.op(0xad) }
+
+    /// Lines the heap up to a word, as objects start on one
+    fn align(&mut self) -> &mut Self {
+        self.global_get(0).i32(7).op(0x6a).i32(-8).op(0x71).global_set(0)
+    }
+
+    /// Compares the two i64s on the stack as -1, 0 or 1, given them in
+    /// locals as well
+    fn order(&mut self, a: u32, b: u32) -> &mut Self {
+        self.get(a).get(b).op(0x55).get(a).get(b).op(0x53).op(0x6b)
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        uleb(&mut body, self.locals.len() as u64);
+        for ty in &self.locals {
+            body.push(1);
+            body.push(*ty);
+        }
+        body.extend(&self.bytes);
+        body.push(0x0b);
+        let mut out = Vec::new();
+        uleb(&mut out, body.len() as u64);
+        out.extend(body);
+        out
+    }
+}
+
+struct Generator<'a> {
+    sources: &'a SourceMap,
+    types: Vec<(Vec<u8>, Vec<u8>)>,
+    funcs: Vec<(u32, Code)>,
+    data: Vec<u8>,
+    strings: HashMap<String, (u32, u32)>,
+    functions: HashMap<na::SymbolID, u32>,
+    builtins: HashMap<na::SymbolID, u32>,
+    table: Vec<u32>,
+    globals: HashMap<na::SymbolID, u32>,
+    max_arity: usize,
+
+    // the function being written
+    code: Code,
+    params: Vec<anf::Var>,
+    // the depth of the loop the function starts over from
+    top: u32
+}
+
+/// Encodes a program as a WebAssembly module
+pub fn generate(prog: &na::Prog, program: &anf::Program, sources: &SourceMap) -> Result<Vec<u8>, SpruceErr> {
+    let max_arity = program.functions.iter().map(|func| func.params.len()).chain(std::iter::once(2)).max().unwrap_or(2);
+    anf::tagged_ints(program, "wasm")?;
+    let builtins = anf::used_builtins(prog, &program.builtins, "wasm", &WASM_BUILTINS.map(|(name, _)| name))?;
+    let first_user = FIRST_APPLY + max_arity as u32 + 3;
+    let mut gen = Generator {
+        sources: sources,
+        types: Vec::new(),
+        funcs: Vec::new(),
+        data: vec![0; NAMES as usize],
+        strings: HashMap::new(),
+        functions: program.functions.iter().enumerate().map(|(i, func)| (func.name.expect("functions are named"), first_user + i as u32)).collect(),
+        builtins: builtins.iter().map(|(id, builtin, _)| (*id, WASM_BUILTINS.iter().find(|(name, _)| name == builtin).expect("builtin was checked").1)).collect(),
+        table: Vec::new(),
+        globals: program.globals.iter().enumerate().map(|(i, id)| (*id, i as u32 + 1)).collect(),
+        max_arity: max_arity,
+        code: Code::new(0),
+        params: Vec::new(),
+        top: 0
+    };
+
+    // every function that can be called as a value has a place in the table
+    gen.table = gen.functions.values().chain(gen.builtins.values()).cloned().collect();
+    gen.table.sort();
+    gen.table.dedup();
+
+    let names = native::constructor_names(prog);
+    gen.data.resize(NAMES as usize + names.len() * 8, 0);
+    for (i, constructor) in names.iter().enumerate() {
+        let (addr, len) = gen.string(constructor);
+        let at = NAMES as usize + i * 8;
+        gen.data[at..at + 4].copy_from_slice(&addr.to_le_bytes());
+        gen.data[at + 4..at + 8].copy_from_slice(&len.to_le_bytes());
+    }
+
+    gen.runtime(prog);
+    for arity in 0..=max_arity {
+        gen.apply(arity);
+    }
+
+    gen.function(&program.init);
+    gen.code.drop_();
+    let init = std::mem::replace(&mut gen.code, Code::new(0));
+    let ty = gen.ty(&[], &[]);
+    gen.funcs.push((ty, init));
+    let init_index = first_user - 2;
+
+    let start = gen.start_function(init_index, gen.functions[&program.entry]);
+    let ty = gen.ty(&[], &[]);
+    gen.funcs.push((ty, start));
+
+    for func in &program.functions {
+        gen.function(func);
+        let code = std::mem::replace(&mut gen.code, Code::new(0));
+        let ty = gen.ty(&vec![I64; func.params.len()], &[I64]);
+        gen.funcs.push((ty, code));
+    }
+
+    Ok(gen.encode(program.globals.len()))
+}
+
+impl<'a> Generator<'a> {
+    fn ty(&mut self, params: &[u8], results: &[u8]) -> u32 {
+        let ty = (params.to_vec(), results.to_vec());
+        match self.types.iter().position(|other| *other == ty) {
+            Some(i) => i as u32,
+            None => {
+                self.types.push(ty);
+                (self.types.len() - 1) as u32
+            }
+        }
+    }
+
+    /// Where a constant string is in memory, and its length
+    fn string(&mut self, text: &str) -> (u32, u32) {
+        if let Some(found) = self.strings.get(text) {
+            return *found;
+        }
+        let found = (self.data.len() as u32, text.len() as u32);
+        self.data.extend_from_slice(text.as_bytes());
+        self.strings.insert(String::from(text), found);
+        found
+    }
+
+    fn put(&mut self, code: &mut Code, text: &str) {
+        let (addr, len) = self.string(text);
+        code.i32(addr as i32).i32(len as i32).call(PUT);
+    }
+
+    fn write(&mut self, code: &mut Code, fd: i32, text: &str) {
+        let (addr, len) = self.string(text);
+        code.i32(fd).i32(addr as i32).i32(len as i32).call(WRITE);
+    }
+
+    fn add(&mut self, params: &[u8], results: &[u8], code: Code) {
+        let ty = self.ty(params, results);
+        self.funcs.push((ty, code));
+    }
+
+    /// The runtime's functions, in the order of their indices
+    fn runtime(&mut self, prog: &na::Prog) {
+        let (true_id, false_id) = (prog.registry.value_id(LangValue::True) as i64, prog.registry.value_id(LangValue::False) as i64);
+        let [lt_id, eq_id, gt_id] = [LangValue::LT, LangValue::EQ, LangValue::GT].map(|value| prog.registry.value_id(value) as i64);
+
+        // reserve(bytes) -> address, growing memory until it fits
+        let mut c = Code::new(1);
+        let addr = c.local(I32);
+        c.global_get(0).set(addr);
+        c.global_get(0).get(0).op(0x6a).global_set(0);
+        c.block().looped();
+        c.global_get(0).op(0x3f).op(0x00).i32(16).op(0x74).op(0x4d).br_if(1);
+        c.i32(1).op(0x40).op(0x00).i32(-1).op(0x46).if_().unreachable().end();
+        c.br(0).end().end();
+        c.get(addr);
+        self.add(&[I32], &[I32], c);
+
+        // alloc(kind, tag, count) -> object
+        let mut c = Code::new(3);
+        let p = c.local(I32);
+        c.align();
+        c.get(2).wrap().i32(3).op(0x6a).i32(3).op(0x74).call(RESERVE).set(p);
+        c.get(p).get(0).store(0);
+        c.get(p).get(1).store(8);
+        c.get(p).get(2).store(16);
+        c.get(p).extend();
+        self.add(&[I64, I64, I64], &[I64], c);
+
+        // bool(flag) -> True or False
+        let mut c = Code::new(1);
+        c.i64(ADT).i64(true_id).i64(false_id).get(0).select().i64(0).call(ALLOC);
+        self.add(&[I32], &[I64], c);
+
+        // func(index) -> a function value
+        let mut c = Code::new(1);
+        let p = c.local(I64);
+        c.i64(FUNC).i64(0).i64(1).call(ALLOC).tee(p).wrap().get(0).store(24).get(p);
+        self.add(&[I64], &[I64], c);
+
+        // compose(first, second) -> a function value
+        let mut c = Code::new(2);
+        let p = c.local(I64);
+        c.i64(COMPOSE).i64(0).i64(2).call(ALLOC).tee(p).wrap().get(0).store(24);
+        c.get(p).wrap().get(1).store(32).get(p);
+        self.add(&[I64, I64], &[I64], c);
+
+        // float(x) -> a Float value
+        let mut c = Code::new(1);
+        let p = c.local(I64);
+        c.i64(FLOAT).i64(0).i64(1).call(ALLOC).tee(p).wrap().get(0).store_float(24).get(p);
+        self.add(&[F64], &[I64], c);
+
+        // put(address, length) adds bytes to the end of the heap
+        let mut c = Code::new(2);
+        c.get(1).call(RESERVE).get(0).get(1).op(0xfc).with(0x0a, 0).op(0x00);
+        self.add(&[I32, I32], &[], c);
+
+        // put_int(n) adds n, as it would be shown
+        let mut c = Code::new(1);
+        let (m, digits, at, i) = (c.local(I64), c.local(I32), c.local(I32), c.local(I32));
+        c.get(0).i64(0).op(0x53).if_();
+        self.put(&mut c, "-");
+        c.i64(0).get(0).op(0x7d).set(0).end();
+        c.get(0).set(m).i32(1).set(digits);
+        c.block().looped();
+        c.get(m).i64(10).op(0x80).tee(m).op(0x50).br_if(1);
+        c.get(digits).i32(1).op(0x6a).set(digits).br(0);
+        c.end().end();
+        c.get(digits).call(RESERVE).set(at).get(digits).set(i);
+        c.looped();
+        c.get(i).i32(1).op(0x6b).set(i);
+        c.get(at).get(i).op(0x6a).get(0).i64(10).op(0x82).i64(48).op(0x7c).wrap().store8(0);
+        c.get(0).i64(10).op(0x80).set(0);
+        c.get(i).br_if(0).end();
+        self.add(&[I64], &[], c);
+
+        // show_into(value) adds the value, as it would be shown
+        let mut c = Code::new(1);
+        let (obj, entry, i, count, at) = (c.local(I32), c.local(I32), c.local(I32), c.local(I32), c.local(I32));
+        c.get(0).op(0x50).if_();
+        self.put(&mut c, "()");
+        c.ret().end();
+        c.get(0).i64(1).op(0x83).wrap().if_().get(0).i64(1).op(0x87).call(PUT_INT).ret().end();
+        c.get(0).wrap().set(obj);
+        c.get(obj).load(0).i64(STR).op(0x51).if_().get(obj).i32(24).op(0x6a).get(obj).load(8).wrap().call(PUT).ret().end();
+        // the host writes a Float into room for the longest one, and the heap
+        // is given back what it didn't use
+        c.get(obj).load(0).i64(FLOAT).op(0x51).if_();
+        c.i32(400).call(RESERVE).tee(at).get(obj).load_float(24).get(at).call(SHOW_FLOAT).op(0x6a).global_set(0).ret();
+        c.end();
+        c.get(obj).load(0).i64(ADT).op(0x52).if_();
+        self.put(&mut c, "<function>");
+        c.ret().end();
+        c.get(obj).load(8).wrap().i32(3).op(0x74).i32(NAMES as i32).op(0x6a).set(entry);
+        c.get(entry).load32(0).get(entry).load32(4).call(PUT);
+        c.get(obj).load(16).wrap().tee(count).op(0x45).if_().ret().end();
+        self.put(&mut c, "(");
+        c.i32(0).set(i);
+        c.looped();
+        c.get(i).if_();
+        self.put(&mut c, ", ");
+        c.end();
+        c.get(obj).get(i).i32(3).op(0x74).op(0x6a).load(24).call(SHOW_INTO);
+        c.get(i).i32(1).op(0x6a).tee(i).get(count).op(0x48).br_if(0).end();
+        self.put(&mut c, ")");
+        self.add(&[I64], &[], c);
+
+        // show(value) -> a string of the value as it would be shown
+        let mut c = Code::new(1);
+        let start = c.local(I32);
+        c.align().i32(24).call(RESERVE).set(start);
+        c.get(0).call(SHOW_INTO);
+        c.get(start).i64(STR).store(0);
+        c.get(start).global_get(0).get(start).op(0x6b).i32(24).op(0x6b).extend().store(8);
+        c.get(start).i64(0).store(16);
+        c.get(start).extend();
+        self.add(&[I64], &[I64], c);
+
+        // bytes_compare(a, a_len, b, b_len) -> -1, 0 or 1
+        let mut c = Code::new(4);
+        let (i, x, y) = (c.local(I32), c.local(I32), c.local(I32));
+        c.block().looped();
+        c.get(i).get(1).op(0x4f).get(i).get(3).op(0x4f).op(0x72).br_if(1);
+        c.get(0).get(i).op(0x6a).load8(0).set(x);
+        c.get(2).get(i).op(0x6a).load8(0).set(y);
+        c.get(x).get(y).op(0x47).if_().get(x).get(y).op(0x4b).get(x).get(y).op(0x49).op(0x6b).ret().end();
+        c.get(i).i32(1).op(0x6a).set(i).br(0);
+        c.end().end();
+        c.get(1).get(3).op(0x4b).get(1).get(3).op(0x49).op(0x6b);
+        self.add(&[I32, I32, I32, I32], &[I32], c);
+
+        // compare(a, b) -> -1, 0 or 1, ordering values as the interpreter
+        // does
+        let mut c = Code::new(2);
+        let (x, y, ex, ey, i, n, order) = (c.local(I32), c.local(I32), c.local(I32), c.local(I32), c.local(I32), c.local(I32), c.local(I32));
+        let (kx, ky) = (c.local(I64), c.local(I64));
+        c.get(0).get(1).op(0x84).i64(1).op(0x83).wrap().get(0).op(0x50).op(0x72).get(1).op(0x50).op(0x72).if_().order(0, 1).ret().end();
+        c.get(0).wrap().set(x).get(1).wrap().set(y);
+        c.get(x).load(0).get(y).load(0).op(0x52).if_().order(0, 1).ret().end();
+        c.get(x).load(0).i64(STR).op(0x51).if_();
+        c.get(x).i32(24).op(0x6a).get(x).load(8).wrap().get(y).i32(24).op(0x6a).get(y).load(8).wrap().call(BYTES_COMPARE).ret();
+        c.end();
+        // Floats by their bits, with those of negative ones but the sign
+        // flipped, which puts -0 before 0 and NaN at the ends
+        c.get(x).load(0).i64(FLOAT).op(0x51).if_();
+        for (obj, key) in [(x, kx), (y, ky)] {
+            c.get(obj).load(24).tee(key).i64(i64::MAX).op(0x85).get(key).get(key).i64(0).op(0x53).select().set(key);
+        }
+        c.order(kx, ky).ret().end();
+        c.get(x).load(0).i64(ADT).op(0x52).if_().order(0, 1).ret().end();
+        c.get(x).load(8).wrap().i32(3).op(0x74).i32(NAMES as i32).op(0x6a).set(ex);
+        c.get(y).load(8).wrap().i32(3).op(0x74).i32(NAMES as i32).op(0x6a).set(ey);
+        c.get(ex).load32(0).get(ex).load32(4).get(ey).load32(0).get(ey).load32(4).call(BYTES_COMPARE).tee(order).if_().get(order).ret().end();
+        c.get(x).load(16).get(y).load(16).get(x).load(16).get(y).load(16).op(0x53).select().wrap().set(n);
+        c.block().looped();
+        c.get(i).get(n).op(0x4f).br_if(1);
+        c.get(x).get(i).i32(3).op(0x74).op(0x6a).load(24);
+        c.get(y).get(i).i32(3).op(0x74).op(0x6a).load(24);
+        c.call(COMPARE).tee(order).if_().get(order).ret().end();
+        c.get(i).i32(1).op(0x6a).set(i).br(0);
+        c.end().end();
+        c.get(x).load(16).get(y).load(16).op(0x55).get(x).load(16).get(y).load(16).op(0x53).op(0x6b);
+        self.add(&[I64, I64], &[I32], c);
+
+        // fail(message, length, value, show it, location, length) writes an
+        // error out and stops
+        let mut c = Code::new(6);
+        let s = c.local(I32);
+        self.write(&mut c, 2, "error: ");
+        c.i32(2).get(0).get(1).call(WRITE);
+        c.get(3).if_().get(2).call(SHOW).wrap().set(s).i32(2).get(s).i32(24).op(0x6a).get(s).load(8).wrap().call(WRITE).end();
+        self.write(&mut c, 2, "\n --> ");
+        c.i32(2).get(4).get(5).call(WRITE);
+        self.write(&mut c, 2, "\n");
+        c.i32(1).call(EXIT).unreachable();
+        self.add(&[I32, I32, I64, I32, I32, I32], &[], c);
+
+        // pow(base, exponent, location, length) -> base to the exponent
+        let mut c = Code::new(4);
+        let (b, e, r) = (c.local(I64), c.local(I64), c.local(I64));
+        c.get(1).i64(0).op(0x53).if_();
+        let (addr, len) = self.string("cannot raise an Int to the negative power ");
+        c.i32(addr as i32).i32(len as i32).get(1).i32(1).get(2).get(3).call(FAIL);
+        c.end();
+        c.get(0).i64(1).op(0x87).set(b).get(1).i64(1).op(0x87).set(e).i64(1).set(r);
+        c.block().looped();
+        c.get(e).op(0x50).br_if(1);
+        c.get(e).i64(1).op(0x83).op(0x50).op(0x45).if_().get(r).get(b).op(0x7e).set(r).end();
+        c.get(b).get(b).op(0x7e).set(b);
+        c.get(e).i64(1).op(0x87).set(e).br(0);
+        c.end().end();
+        c.get(r).i64(1).op(0x86).i64(1).op(0x84);
+        self.add(&[I64, I64, I32, I32], &[I64], c);
+
+        // print(string) and printLine(string)
+        for line in [false, true] {
+            let mut c = Code::new(1);
+            c.i32(1).get(0).wrap().i32(24).op(0x6a).get(0).word(8).wrap().call(WRITE);
+            if line {
+                self.write(&mut c, 1, "\n");
+            }
+            c.i64(0);
+            self.add(&[I64], &[I64], c);
+        }
+
+        // debug(value) -> value, writing it out first
+        let mut c = Code::new(1);
+        let s = c.local(I64);
+        self.write(&mut c, 2, "debug: ");
+        c.get(0).call(SHOW).set(s).i32(2).get(s).wrap().i32(24).op(0x6a).get(s).word(8).wrap().call(WRITE);
+        self.write(&mut c, 2, "\n");
+        c.get(0);
+        self.add(&[I64], &[I64], c);
+
+        // abs, min and max, on tagged Ints
+        let mut c = Code::new(1);
+        c.i64(2).get(0).op(0x7d).get(0).get(0).i64(0).op(0x53).select();
+        self.add(&[I64], &[I64], c);
+        for op in [0x53, 0x55] {
+            let mut c = Code::new(2);
+            c.get(0).get(1).get(0).get(1).op(op).select();
+            self.add(&[I64, I64], &[I64], c);
+        }
+
+        // compare(a, b) -> LT, EQ or GT
+        let mut c = Code::new(2);
+        let order = c.local(I32);
+        c.get(0).get(1).call(COMPARE).set(order);
+        c.i64(ADT).i64(lt_id).i64(gt_id).i64(eq_id).get(order).i32(0).op(0x4a).select().get(order).i32(0).op(0x48).select().i64(0).call(ALLOC);
+        self.add(&[I64, I64], &[I64], c);
+
+        // toFloat(n) -> n as a Float
+        let mut c = Code::new(1);
+        c.get(0).i64(1).op(0x87).op(0xb9).call(MAKE_FLOAT);
+        self.add(&[I64], &[I64], c);
+
+        // sqrt(x)
+        let mut c = Code::new(1);
+        c.get(0).wrap().load_float(24).op(0x9f).call(MAKE_FLOAT);
+        self.add(&[I64], &[I64], c);
+
+        // floor(x) and ceil(x), which saturate at the ends of an Int and take
+        // NaN to 0 as the interpreter does, within the 63 bits Ints have here
+        for op in [0x9c, 0x9b] {
+            let mut c = Code::new(1);
+            let n = c.local(I64);
+            c.get(0).wrap().load_float(24).op(op).op(0xfc).op(0x06).set(n);
+            c.i64(anf::TAGGED_MAX).get(n).get(n).i64(anf::TAGGED_MAX).op(0x55).select().set(n);
+            c.i64(anf::TAGGED_MIN).get(n).get(n).i64(anf::TAGGED_MIN).op(0x53).select();
+            c.i64(1).op(0x86).i64(1).op(0x84);
+            self.add(&[I64], &[I64], c);
+        }
+
+        // sin(x), cos(x) and tan(x), which the host works out
+        for host in [HOST_SIN, HOST_COS, HOST_TAN] {
+            let mut c = Code::new(1);
+            c.get(0).wrap().load_float(24).call(host).call(MAKE_FLOAT);
+            self.add(&[I64], &[I64], c);
+        }
+    }
+
+    /// apply_n(function, args...) calls a function value with n arguments.
+    /// A composition calls its first function and then its second
+    fn apply(&mut self, arity: usize) {
+        let mut c = Code::new(arity as u32 + 1);
+        let obj = c.local(I32);
+        c.get(0).wrap().set(obj);
+        c.get(obj).load(0).i64(COMPOSE).op(0x51).if_();
+        c.get(obj).load(32).get(obj).load(24);
+        for i in 0..arity {
+            c.get(i as u32 + 1);
+        }
+        c.call(FIRST_APPLY + arity as u32).call(FIRST_APPLY + 1).ret().end();
+        for i in 0..arity {
+            c.get(i as u32 + 1);
+        }
+        let ty = self.ty(&vec![I64; arity], &[I64]);
+        c.get(obj).load(24).wrap().with(0x11, ty).op(0x00);
+        self.add(&vec![I64; arity + 1], &[I64], c);
+    }
+
+    /// _start evaluates the definitions, runs main and writes its result
+    /// out unless it's Unit
+    fn start_function(&mut self, init: u32, main: u32) -> Code {
+        let mut c = Code::new(0);
+        let (r, s) = (c.local(I64), c.local(I64));
+        c.call(init).call(main).tee(r).i64(0).op(0x52).if_();
+        c.get(r).call(SHOW).set(s).i32(1).get(s).wrap().i32(24).op(0x6a).get(s).word(8).wrap().call(WRITE);
+        self.write(&mut c, 1, "\n");
+        c.end();
+        c
+    }
+
+    /// Starts writing a function, whose variables are the locals of the
+    /// same number, and writes its body. A function that starts itself over
+    /// has its body in a loop
+    fn function(&mut self, func: &anf::Function) {
+        self.code = Code::new(func.params.len() as u32);
+        for _ in func.params.len()..func.vars.len() {
+            self.code.local(I64);
+        }
+        self.params = func.params.clone();
+        if func.body.recurs() {
+            self.code.open(0x03, I64);
+            self.top = self.code.depth;
+            self.block(&func.body);
+            self.code.end();
+        }
+        else {
+            self.block(&func.body);
+        }
+    }
+
+    fn location(&mut self, info: &NodeInfo) -> (u32, u32) {
+        let location = SpruceErr::new(String::new(), info.clone()).location(self.sources);
+        self.string(&location)
+    }
+
+    /// Writes a block, leaving its value on the stack
+    fn block(&mut self, block: &anf::Block) {
+        for stmt in &block.stmts {
+            match stmt {
+                anf::Stmt::Let(var, val) => {
+                    self.value(val);
+                    self.code.set(*var);
+                }
+                anf::Stmt::SetGlobal(id, atom) => {
+                    self.atom(atom);
+                    let global = self.globals[id];
+                    self.code.global_set(global);
+                }
+            }
+        }
+        self.value(&block.result);
+    }
+
+    /// Writes a case as a block per arm inside one for the default, which
+    /// the test at their centre branches out of to the arm it picks
+    fn case(&mut self, case: &anf::Case) {
+        let result = self.code.local(I64);
+        let arms = case.arms.len() as u32;
+        self.code.block().block();
+        for _ in 0..arms {
+            self.code.block();
+        }
+        self.test(case);
+        for (i, arm) in case.arms.iter().enumerate() {
+            self.code.end();
+            self.block(&arm.body);
+            self.code.set(result).br(arms - i as u32);
+        }
+        self.code.end();
+        match &case.default {
+            Some(body) => {
+                self.block(body);
+                self.code.set(result);
+            }
+            None => {
+                let (message, length) = self.string("no option matches ");
+                let (location, location_length) = self.location(&case.info);
+                self.code.i32(message as i32).i32(length as i32);
+                self.atom(&case.scrutinee);
+                self.code.i32(1).i32(location as i32).i32(location_length as i32).call(FAIL);
+            }
+        }
+        self.code.end().get(result);
+    }
+
+    /// Branches to the depth of the arm a case's scrutinee matches, or past
+    /// them all to the default. Constructors close enough together are
+    /// looked up in a table by their id, and anything else is tested in turn
+    fn test(&mut self, case: &anf::Case) {
+        let arms = case.arms.len() as u32;
+        let ids: Vec<u32> = case.arms.iter().filter_map(|arm| match arm.pattern {
+            anf::Pattern::Constructor(id) => Some(id),
+            anf::Pattern::Int(_) => None
+        }).collect();
+        let (low, high) = (ids.iter().min().copied().unwrap_or(0), ids.iter().max().copied().unwrap_or(0));
+        if !ids.is_empty() && high - low < MAX_TABLE {
+            let mut depths = vec![arms; (high - low + 1) as usize];
+            for (i, id) in ids.iter().enumerate() {
+                depths[(id - low) as usize] = i as u32;
+            }
+            self.atom(&case.scrutinee);
+            self.code.word(8).wrap().i32(low as i32).op(0x6b).br_table(&depths, arms);
+            return;
+        }
+
+        // constructors by their id and numbers by their tagged value
+        let key = self.code.local(I64);
+        self.atom(&case.scrutinee);
+        if !ids.is_empty() {
+            self.code.word(8);
+        }
+        self.code.set(key);
+        for (i, arm) in case.arms.iter().enumerate() {
+            let expected = match arm.pattern {
+                anf::Pattern::Constructor(id) => id as i64,
+                anf::Pattern::Int(n) => tagged(n)
+            };
+            self.code.get(key).i64(expected).op(0x51).br_if(i as u32);
+        }
+        self.code.br(arms);
+    }
+
+    /// Pushes an atom's value
+    fn atom(&mut self, atom: &Atom) {
+        match atom {
+            Atom::Var(var) => { self.code.get(*var); }
+            Atom::Int(n) => { self.code.i64(tagged(*n)); }
+            Atom::Unit => { self.code.i64(0); }
+            Atom::Func(id) => {
+                let func = self.functions.get(id).or_else(|| self.builtins.get(id)).expect("generation only reaches what was checked to be callable");
+                let slot = self.table.iter().position(|f| f == func).expect("every function has a place in the table");
+                self.code.i64(slot as i64).call(MAKE_FUNC);
+            }
+        }
+    }
+
+    fn untag(&mut self) {
+        self.code.i64(1).op(0x87);
+    }
+
+    fn tag(&mut self) {
+        self.code.i64(1).op(0x86).i64(1).op(0x84);
+    }
+
+    /// Operates on the untagged values of two Ints and tags the result
+    fn arith(&mut self, op: u8, l: &Atom, r: &Atom) {
+        self.atom(l);
+        self.untag();
+        self.atom(r);
+        self.untag();
+        if op == 0x86 || op == 0x87 {
+            self.code.i64(63).op(0x83);
+        }
+        self.code.op(op);
+        self.tag();
+    }
+
+    fn divide(&mut self, op: u8, info: &NodeInfo, l: &Atom, r: &Atom) {
+        self.atom(r);
+        self.code.i64(tagged(0)).op(0x51).if_();
+        let (message, length) = self.string("division by zero");
+        let (location, location_length) = self.location(info);
+        self.code.i32(message as i32).i32(length as i32).i64(0).i32(0).i32(location as i32).i32(location_length as i32).call(FAIL);
+        self.code.end();
+        self.arith(op, l, r);
+    }
+
+    fn prim(&mut self, prim: Prim, operands: &[Atom], info: &NodeInfo) {
+        let (l, r) = (&operands[0], operands.get(1).unwrap_or(&Atom::Unit));
+        match prim {
+            Prim::Add => self.arith(0x7c, l, r),
+            Prim::Sub => self.arith(0x7d, l, r),
+            Prim::Mul => self.arith(0x7e, l, r),
+            Prim::BitAnd => self.arith(0x83, l, r),
+            Prim::BitOr => self.arith(0x84, l, r),
+            Prim::BitXor => self.arith(0x85, l, r),
+            Prim::Shl => self.arith(0x86, l, r),
+            Prim::Shr => self.arith(0x87, l, r),
+            Prim::Div => self.divide(0x7f, info, l, r),
+            Prim::Mod => self.divide(0x81, info, l, r),
+            Prim::Pow => {
+                self.atom(l);
+                self.atom(r);
+                let (location, length) = self.location(info);
+                self.code.i32(location as i32).i32(length as i32).call(POW);
+            }
+            // tagging keeps the order of Ints, so they are compared as they
+            // are
+            Prim::Lt | Prim::Gt | Prim::LtEq | Prim::GtEq => {
+                self.atom(l);
+                self.atom(r);
+                let op = match prim {
+                    Prim::Lt => 0x53,
+                    Prim::Gt => 0x55,
+                    Prim::LtEq => 0x57,
+                    _ => 0x59
+                };
+                self.code.op(op).call(BOOL);
+            }
+            Prim::Eq | Prim::NotEq => {
+                self.atom(l);
+                self.atom(r);
+                self.code.call(COMPARE);
+                match prim {
+                    Prim::Eq => self.code.op(0x45),
+                    _ => self.code.i32(0).op(0x47)
+                };
+                self.code.call(BOOL);
+            }
+            Prim::Neg => {
+                self.code.i64(2);
+                self.atom(l);
+                self.code.op(0x7d);
+            }
+            Prim::Compose => {
+                self.atom(l);
+                self.atom(r);
+                self.code.call(MAKE_COMPOSE);
+            }
+        }
+    }
+
+    /// Pushes a value
+    fn value(&mut self, val: &Value) {
+        match val {
+            Value::Atom(atom) => self.atom(atom),
+            Value::Prim(prim, operands, info) => self.prim(*prim, operands, info),
+            Value::Global(id) => {
+                let global = self.globals[id];
+                self.code.global_get(global);
+            }
+            Value::Call(id, args) => {
+                args.iter().for_each(|arg| self.atom(arg));
+                let func = *self.functions.get(id).or_else(|| self.builtins.get(id)).expect("generation only reaches what was checked to be callable");
+                self.code.call(func);
+            }
+            Value::Apply(func, args) => {
+                self.atom(func);
+                args.iter().for_each(|arg| self.atom(arg));
+                self.code.call(FIRST_APPLY + args.len() as u32);
+            }
+            Value::Construct(id, args) => {
+                let obj = self.code.local(I64);
+                self.code.i64(ADT).i64(*id as i64).i64(args.len() as i64).call(ALLOC).set(obj);
+                for (i, arg) in args.iter().enumerate() {
+                    self.code.get(obj).wrap();
+                    self.atom(arg);
+                    self.code.store(24 + 8 * i as u32);
+                }
+                self.code.get(obj);
+            }
+            Value::Field(atom, i) => {
+                self.atom(atom);
+                self.code.word(24 + 8 * *i as u32);
+            }
+            Value::Case(case) => self.case(case),
+            Value::Recur(args) => {
+                args.iter().for_each(|arg| self.atom(arg));
+                for param in self.params.iter().rev() {
+                    self.code.set(*param);
+                }
+                let depth = self.code.depth - self.top;
+                self.code.br(depth);
+            }
+        }
+    }
+
+    fn encode(&mut self, definitions: usize) -> Vec<u8> {
+        let write = self.ty(&[I32, I32, I32], &[]);
+        let exit = self.ty(&[I32], &[]);
+        let trig = self.ty(&[F64], &[F64]);
+        let show_float = self.ty(&[F64, I32], &[I32]);
+        let mut out = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+
+        section(&mut out, 1, self.types.iter().map(|(params, results)| {
+            let mut ty = vec![0x60];
+            uleb(&mut ty, params.len() as u64);
+            ty.extend(params);
+            uleb(&mut ty, results.len() as u64);
+            ty.extend(results);
+            ty
+        }).collect());
+
+        section(&mut out, 2, [("write", write), ("exit", exit), ("sin", trig), ("cos", trig), ("tan", trig), ("showFloat", show_float)].iter().map(|(field, ty)| {
+            let mut import = Vec::new();
+            name(&mut import, "spruce");
+            name(&mut import, field);
+            import.push(0x00);
+            uleb(&mut import, *ty as u64);
+            import
+        }).collect());
+
+        section(&mut out, 3, self.funcs.iter().map(|(ty, _)| {
+            let mut func = Vec::new();
+            uleb(&mut func, *ty as u64);
+            func
+        }).collect());
+
+        let mut table = vec![0x70, 0x00];
+        uleb(&mut table, self.table.len() as u64);
+        section(&mut out, 4, vec![table]);
+
+        // the heap starts after the constants, with a page to grow into
+        let heap = (self.data.len() as u32 + 7) & !7;
+        let mut memory = vec![0x00];
+        uleb(&mut memory, (heap as u64 >> 16) + 1);
+        section(&mut out, 5, vec![memory]);
+
+        let mut globals = Vec::new();
+        let mut global = vec![I32, 0x01, 0x41];
+        sleb(&mut global, heap as i64);
+        global.push(0x0b);
+        globals.push(global);
+        for _ in 0..definitions {
+            globals.push(vec![I64, 0x01, 0x42, 0x00, 0x0b]);
+        }
+        section(&mut out, 6, globals);
+
+        let start = FIRST_APPLY + self.max_arity as u32 + 2;
+        section(&mut out, 7, vec![("memory", 0x02, 0), ("_start", 0x00, start)].into_iter().map(|(field, kind, index)| {
+            let mut export = Vec::new();
+            name(&mut export, field);
+            export.push(kind);
+            uleb(&mut export, index as u64);
+            export
+        }).collect());
+
+        let mut elements = vec![0x00, 0x41, 0x00, 0x0b];
+        uleb(&mut elements, self.table.len() as u64);
+        for func in &self.table {
+            uleb(&mut elements, *func as u64);
+        }
+        section(&mut out, 9, vec![elements]);
+
+        section(&mut out, 10, self.funcs.iter().map(|(_, code)| code.encode()).collect());
+
+        let mut data = vec![0x00, 0x41, 0x00, 0x0b];
+        uleb(&mut data, self.data.len() as u64);
+        data.extend(&self.data);
+        section(&mut out, 11, vec![data]);
+        out
+    }
+}
@@ -0,0 +1,96 @@
+// The host for modules built by spruce build --target=wasm. A module imports
+// only what it can't do in its sandbox, which is writing bytes out, stopping,
+// and the parts of Floats wasm has no instructions for:
+//
+//     spruce.write(fd, ptr, len)  writes len bytes of its memory from ptr,
+//                                 to stdout for 1 and stderr for 2
+//     spruce.exit(code)           stops the program with an exit code
+//     spruce.sin(x), cos, tan     the trigonometric functions
+//     spruce.showFloat(x, ptr)    writes x into its memory at ptr as show
+//                                 gives it, giving the number of bytes
+//
+// In node, run a module with `node spruce-wasm-host.mjs main.wasm`. In a
+// browser, or to write somewhere else, import run and give it the module's
+// bytes and a write function of your own.
+
+class SpruceExit extends Error {
+    constructor(code) {
+        super('spruce program exited with ' + code)
+        this.code = code
+    }
+}
+
+const hasProcess = typeof process != 'undefined'
+const decoder = new TextDecoder()
+const encoder = new TextEncoder()
+
+function defaultWrite(fd, bytes) {
+    if (hasProcess) {
+        (fd == 2 ? process.stderr : process.stdout).write(bytes)
+    }
+    else {
+        (fd == 2 ? console.error : console.log)(decoder.decode(bytes))
+    }
+}
+
+// a Float as the interpreter writes one, which is as JS does but with the
+// digits written out in full rather than with an exponent
+function showFloat(x) {
+    if (Object.is(x, -0)) {
+        return '-0'
+    }
+    const text = String(Math.abs(x))
+    const e = text.indexOf('e')
+    if (!Number.isFinite(x) || e < 0) {
+        return String(x)
+    }
+    const sign = x < 0 ? '-' : ''
+    const digits = text.slice(0, e).replace('.', '')
+    const exponent = Number(text.slice(e + 1))
+    if (exponent < 0) {
+        return sign + '0.' + '0'.repeat(-exponent - 1) + digits
+    }
+    return sign + digits + '0'.repeat(exponent + 1 - digits.length)
+}
+
+export async function instantiate(bytes, write = defaultWrite) {
+    let memory
+    const { instance } = await WebAssembly.instantiate(bytes, {
+        spruce: {
+            // copied, since the memory may grow before the bytes are used
+            write: (fd, ptr, len) => write(fd, new Uint8Array(memory.buffer, ptr, len).slice()),
+            exit: (code) => { throw new SpruceExit(code) },
+            sin: Math.sin,
+            cos: Math.cos,
+            tan: Math.tan,
+            showFloat: (x, ptr) => encoder.encodeInto(showFloat(x), new Uint8Array(memory.buffer, ptr)).written
+        }
+    })
+    memory = instance.exports.memory
+    return instance
+}
+
+// runs a program's main, giving the exit code it stops with
+export async function run(bytes, write = defaultWrite) {
+    const instance = await instantiate(bytes, write)
+    try {
+        instance.exports._start()
+        return 0
+    }
+    catch (e) {
+        if (e instanceof SpruceExit) {
+            return e.code
+        }
+        throw e
+    }
+}
+
+// runs when node is given this file as the program, rather than something
+// that imports it. Its path becomes a url the way node makes one, with
+// symlinks followed and anything a url can't hold escaped
+const { pathToFileURL } = hasProcess ? await import('node:url') : {}
+const { realpathSync } = hasProcess ? await import('node:fs') : {}
+if (hasProcess && process.argv[1] !== undefined && import.meta.url == pathToFileURL(realpathSync(process.argv[1])).href) {
+    const { readFile } = await import('node:fs/promises')
+    process.exitCode = await run(await readFile(process.argv[2]))
+}
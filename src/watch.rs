@@ -0,0 +1,63 @@
+/*
+Watching a program's files for `spruce check --watch`. There's no portable way
+to be told a file changed without a dependency for each platform, so files are
+polled instead: a program is a handful of files, and looking at when each was
+last modified a few times a second costs next to nothing.
+
+The directories the files are in are watched along with them, since adding or
+removing a file modifies its directory, and either can change what the program
+is, as can its manifest.
+*/
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+/// How long to wait between looking at the files again
+const POLL: Duration = Duration::from_millis(250);
+
+/// Files and directories, with when each was last modified as of when they
+/// were last looked at
+pub struct Watched {
+    stamps: Vec<(PathBuf, Option<SystemTime>)>
+}
+
+impl Watched {
+    /// Watches `files`, the directories they're in, and `extra`, which
+    /// needn't exist yet
+    pub fn new(files: &[PathBuf], extra: &[PathBuf]) -> Self {
+        let mut paths: BTreeSet<PathBuf> = BTreeSet::new();
+        for file in files {
+            paths.insert(file.clone());
+            let dir = file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or_else(|| Path::new("."));
+            paths.insert(dir.to_path_buf());
+        }
+        paths.extend(extra.iter().cloned());
+        Watched { stamps: paths.into_iter().map(|path| (path.clone(), modified(&path))).collect() }
+    }
+
+    /// Whether anything watched was modified, created or removed since it
+    /// was last looked at, looking at them all again
+    pub fn changed(&mut self) -> bool {
+        let mut changed = false;
+        for (path, stamp) in self.stamps.iter_mut() {
+            let now = modified(path);
+            changed |= now != *stamp;
+            *stamp = now;
+        }
+        changed
+    }
+
+    /// Waits until something watched changes
+    pub fn wait(&mut self) {
+        while !self.changed() {
+            thread::sleep(POLL);
+        }
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
@@ -0,0 +1,63 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// A fresh directory holding `files`, given as (path, text) pairs
+fn package(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("spruce-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (path, text) in files {
+        let path = dir.join(path);
+        fs::create_dir_all(path.parent().unwrap()).expect("cannot make the package");
+        fs::write(path, text).expect("cannot write the package");
+    }
+    dir
+}
+
+fn spruce(dir: &PathBuf, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_spruce")).args(args).current_dir(dir).output().expect("cannot run spruce")
+}
+
+#[test]
+fn test_run_package() {
+    // a file run or built in a package can import the package's modules
+    let dir = package("run", &[
+        ("spruce.toml", "[package]\nname = \"app\"\nsources = [\"src\"]\n"),
+        ("src/main.sp", "module Main\nimport Util\nmain() {\n    printLine(show(twice(21)))\n}\n"),
+        ("src/util.sp", "module Util\ntwice(n) {\n    n * 2\n}\n")
+    ]);
+    for args in [&["run", "src/main.sp"][..], &["run", "--engine=vm", "src/main.sp"], &["run", "--profile", "src/main.sp"]] {
+        let out = spruce(&dir, args);
+        assert!(out.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&out.stderr));
+        assert_eq!(String::from_utf8_lossy(&out.stdout), "42\n");
+    }
+    let out = spruce(&dir, &["build", "--target=c", "src/main.sp"]);
+    assert!(out.status.success(), "build failed: {}", String::from_utf8_lossy(&out.stderr));
+    assert!(dir.join("src/main.c").is_file());
+    fs::remove_dir_all(&dir).expect("cannot remove the package");
+}
+
+#[test]
+fn test_emit_js() {
+    // the module is written wherever spruce is run, with the runtime it needs
+    let dir = package("emit-js", &[("main.sp", "main() {\n    printLine(show(randomInt(1, 100)))\n    7 ^^^ 2\n}\n")]);
+    let out = spruce(&dir, &["check", "--emit-js", "--seed=5", "main.sp"]);
+    assert!(out.status.success(), "check failed: {}", String::from_utf8_lossy(&out.stderr));
+    let seeded = spruce(&dir, &["run", "--seed=5", "main.sp"]);
+    if let Ok(run) = Command::new("node").arg("out.mjs").current_dir(&dir).output() {
+        assert_eq!(String::from_utf8_lossy(&run.stdout), String::from_utf8_lossy(&seeded.stdout));
+    }
+    fs::remove_dir_all(&dir).expect("cannot remove the package");
+}
+
+#[test]
+fn test_run_args() {
+    // a leading -- only separates the program's arguments from spruce's own
+    let dir = package("run-args", &[("main.sp", "main() {\n    printLine(show(args()))\n}\n")]);
+    for (args, shown) in [(&["run", "main.sp", "--", "--verbose", "foo"][..], "Cons(--verbose, Cons(foo, Nil))\n"), (&["run", "main.sp", "--", "--"], "Cons(--, Nil)\n"), (&["run", "main.sp", "foo"], "Cons(foo, Nil)\n")] {
+        let out = spruce(&dir, args);
+        assert!(out.status.success(), "{:?} failed: {}", args, String::from_utf8_lossy(&out.stderr));
+        assert_eq!(String::from_utf8_lossy(&out.stdout), shown);
+    }
+    fs::remove_dir_all(&dir).expect("cannot remove the package");
+}
@@ -0,0 +1,35 @@
+use spruce::embed::{Compiler, FromValue, ToValue, Type, Value};
+use spruce::marshal_struct;
+
+#[derive(Debug, PartialEq)]
+struct Point {
+    x: i64,
+    y: i64
+}
+marshal_struct!(Point { x, y });
+
+#[test]
+fn test_host_program() {
+    let mut compiler = Compiler::new();
+    compiler.register_fn("offset", Type::Func(vec![], Box::new(Type::Int)), |_, _| Ok(Value::from(10)));
+    let prog = "
+type Point {
+    Point(Int, Int)
+}
+shift(p) {
+    case p {
+        Point(x, y) -> Point(x + offset(), y)
+    }
+}
+";
+    let (analyzed, typed, _) = compiler.compile(vec![(prog, String::from("Main"))]).expect("program should typecheck");
+
+    // a host program passes its own values in, and gets them back out
+    let point = Point { x: 1, y: 2 }.to_value(&analyzed).expect("the program has Point");
+    let shifted = compiler.call(&analyzed, &typed, "shift", vec![point]).expect("shift should run");
+    assert_eq!(Point::from_value(&analyzed, &shifted), Ok(Point { x: 11, y: 2 }));
+
+    // and can check and format programs without running them
+    assert!(spruce::compile(vec![("main() {\n    1 + True\n}\n", String::from("Main"))]).is_err());
+    assert_eq!(spruce::fmt::format_source("main(){\n1+2\n}\n"), "main() {\n    1 + 2\n}\n");
+}